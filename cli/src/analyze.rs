@@ -0,0 +1,97 @@
+//! `connect4-cli analyze`: inspect a position without writing a test for it.
+use std::fmt::Write;
+
+use connect4::{
+    analyze, parse_history,
+    render::{self, RenderOptions},
+    Analysis, GameState, GameStatus, MoveRequest,
+};
+
+use crate::{name, score_label};
+
+pub fn run(history: String, depth: u8, color: bool) -> anyhow::Result<()> {
+    let moves = parse_history(&history)?;
+    let state = GameState::from_history(&moves)?;
+    let analysis = analyze(MoveRequest {
+        position: history,
+        level: depth,
+        first_player: None,
+    })?;
+    let options = RenderOptions {
+        last_move: moves.last().map(|mv| mv.column),
+        ..RenderOptions::default()
+    };
+    print!(
+        "{}\n{}",
+        render::text(&state, &options, color),
+        report(&analysis)
+    );
+    Ok(())
+}
+
+/// Status, column scores and the expected line, with one-based columns.
+fn report(analysis: &Analysis) -> String {
+    let mut out = String::new();
+    // Writing to a `String` cannot fail.
+    let _ = match (analysis.status, analysis.winner) {
+        (GameStatus::Won, Some(winner)) => writeln!(out, "{} has won.", name(winner)),
+        (GameStatus::Draw, _) => writeln!(out, "Draw: the board is full."),
+        _ => writeln!(out, "{} to move.", name(analysis.to_move)),
+    };
+    if analysis.scores.is_empty() {
+        return out;
+    }
+    let _ = writeln!(
+        out,
+        "Scores at depth {} for {}:",
+        analysis.depth,
+        name(analysis.to_move)
+    );
+    for scored in &analysis.scores {
+        let best = if analysis.best() == Some(scored.column) {
+            "  best"
+        } else {
+            ""
+        };
+        let _ = writeln!(
+            out,
+            "  {}  {:>7}{best}",
+            scored.column + 1,
+            score_label(scored.score)
+        );
+    }
+    let line: Vec<String> = analysis
+        .line
+        .iter()
+        .map(|column| (column + 1).to_string())
+        .collect();
+    let _ = writeln!(out, "Line: {}", line.join(" "));
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_scores_and_the_line() {
+        let analysis = analyze(MoveRequest {
+            position: "R0B1R0B1R0B1".to_string(),
+            level: 3,
+            first_player: None,
+        })
+        .unwrap();
+        let text = report(&analysis);
+        assert!(text.starts_with("Red to move.\nScores at depth 3 for Red:\n"));
+        assert!(text.contains("\n  1      win  best\n"));
+        assert!(text.ends_with("Line: 1\n"));
+
+        let over = analyze(MoveRequest {
+            position: "R0B1R0B1R0B1R0".to_string(),
+            level: 3,
+            first_player: None,
+        })
+        .unwrap();
+        assert_eq!(report(&over), "Red has won.\n");
+    }
+}
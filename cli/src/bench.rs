@@ -0,0 +1,99 @@
+//! `connect4-cli bench` and `perft`: engine speed and move-generation checks
+//! that run the same on any machine.
+use std::time::{Duration, Instant};
+
+use connect4::{
+    best_move_with_progress, parse_history,
+    perft::{divide, perft},
+    GameState, MoveRequest,
+};
+
+/// Opening, middlegame and endgame positions, fixed so that runs compare.
+const SUITE: [&str; 6] = [
+    "",
+    "R3B3",
+    "R3B3R3B3R2B4",
+    "R3B2R3B3R3B4R2B3R4B4",
+    "R3B3R2B4R4B2R1B4R3B3R2B2R4B4",
+    "R3B2R3B3R3B4R2B3R4B4R4B2R2B2R1B1R5B5",
+];
+
+fn rate(nodes: u64, elapsed: Duration) -> u64 {
+    (nodes as f64 / elapsed.as_secs_f64().max(1e-9)) as u64
+}
+
+pub fn bench(depth: u8) -> anyhow::Result<()> {
+    let mut total_nodes = 0;
+    let mut total_time = Duration::ZERO;
+    println!(
+        "{:<40} {:>5} {:>12} {:>9} {:>12}",
+        "position", "depth", "nodes", "ms", "nodes/s"
+    );
+    for position in SUITE {
+        let mut nodes = 0;
+        let mut reached = 0;
+        let started = Instant::now();
+        best_move_with_progress(
+            MoveRequest {
+                position: position.to_string(),
+                level: depth,
+                first_player: None,
+            },
+            |info| (nodes, reached) = (info.nodes, info.depth),
+        )?;
+        let elapsed = started.elapsed();
+        total_nodes += nodes;
+        total_time += elapsed;
+        let shown = if position.is_empty() {
+            "(empty)"
+        } else {
+            position
+        };
+        println!(
+            "{shown:<40} {reached:>5} {nodes:>12} {:>9} {:>12}",
+            elapsed.as_millis(),
+            rate(nodes, elapsed)
+        );
+    }
+    println!(
+        "Total: {total_nodes} nodes in {:.2}s, {} nodes/s",
+        total_time.as_secs_f64(),
+        rate(total_nodes, total_time)
+    );
+    Ok(())
+}
+
+pub fn run_perft(depth: usize, position: &str) -> anyhow::Result<()> {
+    let state = GameState::from_history(&parse_history(position)?)?;
+    let started = Instant::now();
+    let counts = divide(&state, depth);
+    let total = if depth == 0 {
+        perft(&state, 0)
+    } else {
+        counts.iter().map(|(_, count)| count).sum()
+    };
+    let elapsed = started.elapsed();
+    for (column, count) in counts {
+        println!("{}: {count}", column + 1);
+    }
+    println!(
+        "perft {depth}: {total} in {:.2}s, {} positions/s",
+        elapsed.as_secs_f64(),
+        rate(total, elapsed)
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use connect4::GameStatus;
+
+    #[test]
+    fn suite_positions_are_games_in_progress() {
+        for position in SUITE {
+            let state = GameState::from_history(&parse_history(position).unwrap()).unwrap();
+            assert_eq!(state.status(), GameStatus::InProgress, "{position}");
+        }
+    }
+}
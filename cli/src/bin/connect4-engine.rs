@@ -0,0 +1,334 @@
+//! The engine over a line protocol on stdin/stdout, modelled on UCI, for
+//! GUIs and tournament managers that drive engines as subprocesses.
+//!
+//! ```text
+//! > uci
+//! < id name connect4-engine 0.1.0
+//! < uciok
+//! > position R3B3
+//! > go depth 8
+//! < info depth 1 score 6 nodes 7 time 0 pv 3
+//! < ...
+//! < bestmove 2
+//! ```
+//!
+//! Commands:
+//! - `uci` identifies the engine; `isready` answers `readyok`.
+//! - `position [history]` sets the position from a move history such as
+//!   `R3B3`; without one, or with `startpos`, it is the empty board.
+//!   `newgame` (or `ucinewgame`) also resets it and clears the
+//!   transposition table, which otherwise carries over between searches.
+//! - `setoption name Hash value MB` resizes the transposition table to
+//!   1-4096 MiB (default 1), clearing it.
+//! - `go depth N` searches to depth 1-15; `go movetime T` searches for `T`
+//!   milliseconds and `go infinite` until `stop`. Plain `go` is depth 10.
+//! - `go nodes N` searches until `N` nodes with a fresh default table, so
+//!   the move, depths, scores and node counts are the same on any machine;
+//!   only `time` varies.
+//! - `stop` ends the search early and `quit` leaves.
+//!
+//! Columns are zero-based, as in move histories. After each completed depth
+//! the engine sends `info` with the score from the side to move's view
+//! (±1000000 is a forced win or loss), cumulative nodes, elapsed
+//! milliseconds and the expected line; `bestmove` follows the last one.
+//! Problems are reported as `info string error: ...` and otherwise ignored.
+use std::{
+    io::{self, BufRead, Write},
+    sync::{Arc, Mutex},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use connect4::{
+    parse_history, search, tt::TranspositionTable, Algorithm, CancelToken, GameState, Player,
+    SearchLimits, SearchOptions,
+};
+
+/// Depth of a plain `go`.
+const DEFAULT_DEPTH: u8 = 10;
+/// Transposition table size in MiB, until `setoption name Hash`.
+const DEFAULT_HASH_MB: usize = 1;
+const MAX_HASH_MB: usize = 4096;
+
+#[derive(Debug, PartialEq, Eq)]
+enum Go {
+    Depth(u8),
+    MoveTime(Duration),
+    Nodes(u64),
+    Infinite,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Command {
+    Uci,
+    IsReady,
+    NewGame,
+    SetHash(usize),
+    Position(String),
+    Go(Go),
+    Stop,
+    Quit,
+}
+
+fn parse(line: &str) -> Result<Option<Command>, String> {
+    let mut words = line.split_whitespace();
+    let Some(command) = words.next() else {
+        return Ok(None);
+    };
+    let rest: Vec<&str> = words.collect();
+    let command = match (command, rest.as_slice()) {
+        ("uci", []) => Command::Uci,
+        ("isready", []) => Command::IsReady,
+        ("newgame" | "ucinewgame", []) => Command::NewGame,
+        ("setoption", ["name", "Hash", "value", mb]) => match mb.parse() {
+            Ok(mb @ 1..=MAX_HASH_MB) => Command::SetHash(mb),
+            _ => return Err(format!("Hash must be 1-{MAX_HASH_MB} MiB, not {mb}")),
+        },
+        ("position", [] | ["startpos"]) => Command::Position(String::new()),
+        ("position", [history]) => Command::Position(history.to_string()),
+        ("go", []) => Command::Go(Go::Depth(DEFAULT_DEPTH)),
+        ("go", ["infinite"]) => Command::Go(Go::Infinite),
+        ("go", ["depth", depth]) => match depth.parse() {
+            Ok(depth @ 1..=15) => Command::Go(Go::Depth(depth)),
+            _ => return Err(format!("depth must be 1-15, not {depth}")),
+        },
+        ("go", ["nodes", nodes]) => match nodes.parse() {
+            Ok(nodes @ 1..) => Command::Go(Go::Nodes(nodes)),
+            _ => return Err(format!("nodes must be a positive count, not {nodes}")),
+        },
+        ("go", ["movetime", ms]) => match ms.parse() {
+            Ok(ms) => Command::Go(Go::MoveTime(Duration::from_millis(ms))),
+            _ => return Err(format!("movetime must be milliseconds, not {ms}")),
+        },
+        ("stop", []) => Command::Stop,
+        ("quit", []) => Command::Quit,
+        _ => return Err(format!("unknown command: {}", line.trim())),
+    };
+    Ok(Some(command))
+}
+
+type Output = Arc<Mutex<dyn Write + Send>>;
+
+fn send(out: &Output, line: &str) {
+    let mut out = out.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    // A closed stdout means the GUI is gone; the next read will end too.
+    let _ = writeln!(out, "{line}").and_then(|()| out.flush());
+}
+
+/// The search in progress, if any.
+struct Searching {
+    cancel: CancelToken,
+    thread: JoinHandle<()>,
+}
+
+impl Searching {
+    fn stop(self) {
+        self.cancel.cancel();
+        self.finish();
+    }
+
+    /// Wait for the search to send its `bestmove`.
+    fn finish(self) {
+        let _ = self.thread.join();
+    }
+}
+
+fn table(mb: usize) -> Arc<TranspositionTable> {
+    Arc::new(TranspositionTable::with_bytes(mb << 20, Default::default()))
+}
+
+fn start(state: GameState, go: Go, table: Arc<TranspositionTable>, out: Output) -> Searching {
+    let cancel = CancelToken::new();
+    let started = Instant::now();
+    let mut limits = SearchLimits {
+        cancel: Some(cancel.clone()),
+        table: Some(table),
+        ..SearchLimits::default()
+    };
+    // Timed and open-ended searches deepen until the game is solved.
+    let options = match go {
+        Go::Depth(depth) => SearchOptions::new(depth),
+        Go::MoveTime(time) => {
+            limits.deadline = Some(started + time);
+            SearchOptions {
+                algorithm: Algorithm::Solver,
+                ..SearchOptions::new(15)
+            }
+        }
+        // Reproducible: no clock, and a table nothing else has touched.
+        Go::Nodes(nodes) => {
+            limits.max_nodes = Some(nodes);
+            limits.table = None;
+            SearchOptions {
+                algorithm: Algorithm::Solver,
+                ..SearchOptions::new(15)
+            }
+        }
+        Go::Infinite => SearchOptions {
+            algorithm: Algorithm::Solver,
+            ..SearchOptions::new(15)
+        },
+    };
+    let thread = thread::spawn(move || {
+        let result = search(&state, &options, &limits, |info| {
+            let pv: Vec<String> = info.pv.iter().map(usize::to_string).collect();
+            send(
+                &out,
+                &format!(
+                    "info depth {} score {} nodes {} time {} pv {}",
+                    info.depth,
+                    info.score,
+                    info.nodes,
+                    started.elapsed().as_millis(),
+                    pv.join(" ")
+                ),
+            );
+        });
+        match result {
+            // The first of equally scored moves, as in the `info` lines;
+            // `outcome.column` would pick among them at random.
+            Ok(outcome) => send(&out, &format!("bestmove {}", outcome.lines[0].column)),
+            Err(err) => send(&out, &format!("info string error: {err}")),
+        }
+    });
+    Searching { cancel, thread }
+}
+
+fn serve(input: impl BufRead, out: Output) -> io::Result<()> {
+    let mut state = GameState::empty(Player::Red);
+    let mut table = table(DEFAULT_HASH_MB);
+    let mut searching: Option<Searching> = None;
+    for line in input.lines() {
+        let command = match parse(&line?) {
+            Ok(Some(command)) => command,
+            Ok(None) => continue,
+            Err(message) => {
+                send(&out, &format!("info string error: {message}"));
+                continue;
+            }
+        };
+        // A finished search has nothing left to stop.
+        if let Some(search) = searching.take_if(|s| s.thread.is_finished()) {
+            search.finish();
+        }
+        match command {
+            Command::Uci => {
+                send(
+                    &out,
+                    &format!("id name connect4-engine {}", env!("CARGO_PKG_VERSION")),
+                );
+                send(
+                    &out,
+                    &format!(
+                        "option name Hash type spin default {DEFAULT_HASH_MB} min 1 max {MAX_HASH_MB}"
+                    ),
+                );
+                send(&out, "uciok");
+            }
+            Command::IsReady => send(&out, "readyok"),
+            Command::Go(_) if searching.is_some() => {
+                send(
+                    &out,
+                    "info string error: already searching; send stop first",
+                );
+            }
+            Command::Go(go) => {
+                searching = Some(start(state.clone(), go, table.clone(), out.clone()))
+            }
+            Command::Stop => {
+                if let Some(search) = searching.take() {
+                    search.stop();
+                }
+            }
+            Command::Quit => {
+                if let Some(search) = searching.take() {
+                    search.stop();
+                }
+                return Ok(());
+            }
+            Command::NewGame | Command::SetHash(_) | Command::Position(_)
+                if searching.is_some() =>
+            {
+                send(&out, "info string error: stop the search first");
+            }
+            Command::NewGame => {
+                state = GameState::empty(Player::Red);
+                table.clear();
+            }
+            Command::SetHash(mb) => table = self::table(mb),
+            Command::Position(history) => {
+                match parse_history(&history).and_then(|moves| GameState::from_history(&moves)) {
+                    Ok(position) => state = position,
+                    Err(err) => send(&out, &format!("info string error: {err}")),
+                }
+            }
+        }
+    }
+    // At the end of input the last search still reports its move.
+    if let Some(search) = searching {
+        search.finish();
+    }
+    Ok(())
+}
+
+fn main() -> io::Result<()> {
+    serve(io::stdin().lock(), Arc::new(Mutex::new(io::stdout())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn commands_parse() {
+        assert_eq!(parse("  "), Ok(None));
+        assert_eq!(
+            parse("position R3B3"),
+            Ok(Some(Command::Position("R3B3".to_string())))
+        );
+        assert_eq!(
+            parse("position startpos"),
+            Ok(Some(Command::Position(String::new())))
+        );
+        assert_eq!(
+            parse("setoption name Hash value 64"),
+            Ok(Some(Command::SetHash(64)))
+        );
+        assert_eq!(
+            parse("go movetime 250"),
+            Ok(Some(Command::Go(Go::MoveTime(Duration::from_millis(250)))))
+        );
+        assert_eq!(
+            parse("go nodes 50000"),
+            Ok(Some(Command::Go(Go::Nodes(50_000))))
+        );
+        for bad in [
+            "go depth 16",
+            "go nodes 0",
+            "go movetime soon",
+            "setoption name Hash value 0",
+            "position R3 B3",
+            "castle",
+        ] {
+            assert!(parse(bad).is_err(), "{bad:?} should not parse");
+        }
+    }
+
+    #[test]
+    fn searches_report_info_then_bestmove() {
+        let buffer = Arc::new(Mutex::new(Vec::new()));
+        let input = "isready\nposition R0B1R0B1R0B9\nposition R0B1R0B1R0B1\ngo depth 3\n";
+        serve(input.as_bytes(), buffer.clone()).unwrap();
+        let output = String::from_utf8(buffer.lock().unwrap().clone()).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines[0], "readyok");
+        assert!(lines[1].starts_with("info string error: "), "{output}");
+        assert!(lines[2].starts_with("info depth 1 score 1000000 nodes "));
+        assert!(lines[2].ends_with(" pv 0"));
+        assert_eq!(
+            lines[3..].iter().filter(|l| l.starts_with("info")).count(),
+            2
+        );
+        assert_eq!(lines.last(), Some(&"bestmove 0"));
+    }
+}
@@ -0,0 +1,96 @@
+//! `connect4-cli calibrate`: rate every level in Elo by matches between
+//! neighbouring levels, and print the table `connect4::Level` ships with.
+//! `connect4-cli elo` rates any other configuration against that table.
+use std::{
+    io::{self, IsTerminal},
+    time::Duration,
+};
+
+use connect4::{
+    strength::{calibrate, estimate_elo, CalibrationConfig, GauntletConfig},
+    Level,
+};
+
+use crate::tournament::parse_entrant;
+
+pub fn run(
+    top: u8,
+    games: usize,
+    openings: usize,
+    move_ms: Option<u64>,
+    seed: Option<u64>,
+) -> anyhow::Result<()> {
+    let config = CalibrationConfig {
+        top: Level::new(top)?,
+        opening_plies: openings,
+        move_time: move_ms.map(Duration::from_millis),
+        seed,
+        ..CalibrationConfig::new(games)
+    };
+    let progress = io::stderr().is_terminal();
+    println!("level   elo  vs below (+/=/-)");
+    let levels = calibrate(&config, |rated| {
+        let score = rated.score;
+        println!(
+            "{:>5} {:>5.0}  {}/{}/{}",
+            rated.level, rated.elo, score.wins, score.draws, score.losses
+        );
+        if progress {
+            eprint!("\rlevel {} rated", rated.level);
+        }
+    })?;
+    if progress {
+        eprintln!();
+    }
+    // A noisy step can rate a deeper level below a shallower one; the table
+    // never goes down, so a higher level is never advertised as weaker.
+    let mut highest = 0;
+    let table: Vec<String> = levels
+        .iter()
+        .map(|rated| {
+            highest = highest.max(rated.elo.round() as u32);
+            highest.to_string()
+        })
+        .collect();
+    println!("\n[{}]", table.join(", "));
+    Ok(())
+}
+
+pub fn estimate(
+    spec: &str,
+    levels: &[u8],
+    games: usize,
+    openings: usize,
+    move_ms: Option<u64>,
+    seed: Option<u64>,
+) -> anyhow::Result<()> {
+    let entrant = parse_entrant(spec)?;
+    let levels = levels
+        .iter()
+        .map(|&level| Level::new(level))
+        .collect::<Result<Vec<_>, _>>()?;
+    let config = GauntletConfig {
+        opening_plies: openings,
+        move_time: move_ms.map(Duration::from_millis),
+        seed,
+        ..GauntletConfig::new(entrant.options)
+    };
+    let estimate = estimate_elo(&config, &levels, games)?;
+    println!("level   elo  {} (+/=/-)", entrant.name);
+    for result in &estimate.results {
+        let score = result.score;
+        println!(
+            "{:>5} {:>5}  {}/{}/{}",
+            result.level.get(),
+            result.elo,
+            score.wins,
+            score.draws,
+            score.losses
+        );
+    }
+    println!(
+        "\n{}: Elo {:.0} ± {:.0}",
+        entrant.name, estimate.elo, estimate.margin
+    );
+    Ok(())
+}
@@ -0,0 +1,128 @@
+//! `connect4-cli dataset`: labelled self-play positions for training an
+//! evaluator, as JSON lines or CSV.
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::PathBuf,
+    time::Duration,
+};
+
+use anyhow::Context;
+use clap::ValueEnum;
+use connect4::{
+    dataset::{generate, DatasetConfig, Sample},
+    Player, SearchOptions, SelfPlayConfig, HEIGHT, WIDTH,
+};
+
+#[derive(Copy, Clone, Debug, ValueEnum)]
+pub enum Format {
+    /// One JSON object per line
+    Jsonl,
+    /// A header, then `key,to_move,result,score` and one column per cell
+    Csv,
+}
+
+pub struct Options {
+    pub games: usize,
+    pub level: u8,
+    pub openings: usize,
+    pub move_ms: Option<u64>,
+    pub seed: Option<u64>,
+    pub format: Format,
+    pub keep_duplicates: bool,
+    pub out: Option<PathBuf>,
+}
+
+pub fn run(options: Options) -> anyhow::Result<()> {
+    let config = DatasetConfig {
+        dedup: !options.keep_duplicates,
+        ..DatasetConfig::new(SelfPlayConfig {
+            games: options.games,
+            opening_plies: options.openings,
+            move_time: options.move_ms.map(Duration::from_millis),
+            seed: options.seed,
+            ..SelfPlayConfig::new(
+                SearchOptions::new(options.level),
+                SearchOptions::new(options.level),
+            )
+        })
+    };
+    let samples = generate(&config)?;
+    let mut out: Box<dyn Write> = match &options.out {
+        Some(path) => Box::new(BufWriter::new(
+            File::create(path).with_context(|| format!("cannot create {}", path.display()))?,
+        )),
+        None => Box::new(io::stdout().lock()),
+    };
+    match options.format {
+        Format::Jsonl => {
+            for sample in &samples {
+                serde_json::to_writer(&mut out, sample)?;
+                writeln!(out)?;
+            }
+        }
+        Format::Csv => {
+            writeln!(out, "{}", csv_header())?;
+            for sample in &samples {
+                writeln!(out, "{}", csv_row(sample))?;
+            }
+        }
+    }
+    out.flush()?;
+    eprintln!("{} positions from {} games", samples.len(), options.games);
+    Ok(())
+}
+
+/// Cells are named `r{row}c{column}`, bottom row first.
+fn csv_header() -> String {
+    let cells =
+        (0..HEIGHT).flat_map(|row| (0..WIDTH).map(move |column| format!("r{row}c{column}")));
+    ["key", "to_move", "result", "score"]
+        .into_iter()
+        .map(str::to_string)
+        .chain(cells)
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// An opening sample's missing score is an empty field.
+fn csv_row(sample: &Sample) -> String {
+    let to_move = match sample.to_move {
+        Player::Red => "red",
+        Player::Blue => "blue",
+    };
+    let score = sample
+        .score
+        .map_or(String::new(), |score| score.to_string());
+    [
+        sample.key.to_string(),
+        to_move.to_string(),
+        sample.result.to_string(),
+        score,
+    ]
+    .into_iter()
+    .chain(sample.cells.iter().map(i8::to_string))
+    .collect::<Vec<_>>()
+    .join(",")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn csv_rows_match_the_header() {
+        let sample = Sample {
+            key: 42,
+            cells: vec![0; WIDTH * HEIGHT],
+            to_move: Player::Blue,
+            result: -1,
+            score: None,
+        };
+        let header = csv_header();
+        let row = csv_row(&sample);
+        assert!(header.starts_with("key,to_move,result,score,r0c0,r0c1,"));
+        assert!(row.starts_with("42,blue,-1,,0,0,"));
+        assert_eq!(header.split(',').count(), row.split(',').count());
+    }
+}
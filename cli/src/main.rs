@@ -0,0 +1,625 @@
+//! Play the engine in a terminal.
+//!
+//! Type a column number (1-7) to drop a disc; the engine answers at once.
+//! `undo` takes back your last move and the engine's reply, `hint` asks the
+//! engine what it would play in your place, `level N` changes its strength
+//! mid-game, `new` starts over and `quit` (or end of input) leaves.
+//!
+//! Subcommands inspect a position instead of playing it.
+use std::{
+    io::{self, BufRead, IsTerminal, Write},
+    path::PathBuf,
+    str::FromStr,
+};
+
+mod analyze;
+mod bench;
+mod calibrate;
+mod dataset;
+mod puzzle;
+mod selfplay;
+mod solve;
+mod tournament;
+mod trace;
+#[cfg(feature = "tui")]
+mod tui;
+mod tune;
+
+use anyhow::Context;
+use clap::{Parser, Subcommand};
+use connect4::{
+    best_move,
+    game::Game,
+    parse_history,
+    render::{self, RenderOptions},
+    GameError, GameState, MoveRequest, Player, TypedMove, WIDTH,
+};
+
+#[derive(Debug, Parser)]
+#[command(about = "Play Connect 4 against the engine in the terminal")]
+struct Cli {
+    #[command(subcommand)]
+    task: Option<Task>,
+    /// Engine search depth, 1-15
+    #[arg(long, default_value_t = 7, value_parser = clap::value_parser!(u8).range(1..=15))]
+    level: u8,
+    /// Let the engine make the first move
+    #[arg(long)]
+    engine_first: bool,
+    /// Start from this position (move history such as `R3B3`)
+    #[arg(long)]
+    position: Option<String>,
+    /// Draw the board without ANSI colours (also set by `NO_COLOR`)
+    #[arg(long, global = true)]
+    no_color: bool,
+    /// Full-screen interface with an analysis sidebar
+    #[cfg(feature = "tui")]
+    #[arg(long)]
+    tui: bool,
+}
+
+#[derive(Debug, Subcommand)]
+enum Task {
+    /// Score every column of a position and print the expected line
+    Analyze {
+        /// Move history such as `R3B3`; `""` for the empty board
+        history: String,
+        /// Search depth, 1-15
+        #[arg(long, default_value_t = 10, value_parser = clap::value_parser!(u8).range(1..=15))]
+        depth: u8,
+    },
+    /// Solve a position exactly: win, draw or loss and a line of perfect play
+    Solve {
+        /// Move history such as `R3B3R2B2`; early positions can take very long
+        history: String,
+        /// Give up after this many seconds
+        #[arg(long, default_value_t = 60)]
+        time: u64,
+        /// Print depth, score, node count and transposition-table probes,
+        /// hits and fill after every solver iteration
+        #[arg(long)]
+        stats: bool,
+    },
+    /// Play engine-versus-engine games and write them in PGN-like notation
+    Selfplay {
+        #[arg(long, default_value_t = 100)]
+        games: usize,
+        /// Red's search depth, 1-15
+        #[arg(long, visible_alias = "white-level", default_value_t = 8, value_parser = clap::value_parser!(u8).range(1..=15))]
+        red_level: u8,
+        /// Blue's search depth, 1-15
+        #[arg(long, visible_alias = "black-level", default_value_t = 8, value_parser = clap::value_parser!(u8).range(1..=15))]
+        blue_level: u8,
+        /// Random moves opening each game, so the games differ
+        #[arg(long, default_value_t = 2)]
+        openings: usize,
+        /// Time limit per engine move in milliseconds
+        #[arg(long)]
+        move_ms: Option<u64>,
+        /// Repeat a run exactly
+        #[arg(long)]
+        seed: Option<u64>,
+        /// Add each engine move's score, from Red's side, as a comment
+        #[arg(long)]
+        evals: bool,
+        /// Write the games here instead of to standard output
+        #[arg(long)]
+        out: Option<PathBuf>,
+    },
+    /// Label self-play positions with results and scores, for training
+    Dataset {
+        #[arg(long, default_value_t = 100)]
+        games: usize,
+        /// Both engines' search depth, 1-15
+        #[arg(long, default_value_t = 8, value_parser = clap::value_parser!(u8).range(1..=15))]
+        level: u8,
+        /// Random moves opening each game, so the games differ
+        #[arg(long, default_value_t = 2)]
+        openings: usize,
+        /// Time limit per engine move in milliseconds
+        #[arg(long)]
+        move_ms: Option<u64>,
+        /// Repeat a run exactly
+        #[arg(long)]
+        seed: Option<u64>,
+        #[arg(long, value_enum, default_value_t = dataset::Format::Jsonl)]
+        format: dataset::Format,
+        /// Keep every occurrence of a position, not just the first
+        #[arg(long)]
+        keep_duplicates: bool,
+        /// Write the samples here instead of to standard output
+        #[arg(long)]
+        out: Option<PathBuf>,
+    },
+    /// Fit the evaluation weights to `dataset` samples (JSON lines)
+    Tune {
+        samples: PathBuf,
+        /// Most passes over the weights
+        #[arg(long, default_value_t = 200)]
+        passes: usize,
+        /// Write the weights here instead of to standard output
+        #[arg(long)]
+        out: Option<PathBuf>,
+    },
+    /// Search a fixed suite of positions and report nodes per second
+    Bench {
+        /// Search depth, 1-15
+        #[arg(long, default_value_t = 12, value_parser = clap::value_parser!(u8).range(1..=15))]
+        depth: u8,
+    },
+    /// Count the move sequences of a given length, split by first move
+    Perft {
+        depth: usize,
+        /// Start from this position instead of the empty board
+        #[arg(long, default_value = "")]
+        position: String,
+    },
+    /// Generate "find the winning move" puzzles as JSON
+    Puzzle {
+        #[arg(long, default_value_t = 10)]
+        count: usize,
+        #[arg(long, value_enum, default_value_t = puzzle::Level::Medium)]
+        difficulty: puzzle::Level,
+        /// Repeat a pack exactly
+        #[arg(long)]
+        seed: Option<u64>,
+    },
+    /// Rate each level in Elo from matches against the level below
+    Calibrate {
+        /// Highest level to rate, 2-15
+        #[arg(long, default_value_t = 15, value_parser = clap::value_parser!(u8).range(2..=15))]
+        top: u8,
+        /// Games between each pair of neighbouring levels
+        #[arg(long, default_value_t = 200)]
+        games: usize,
+        /// Random moves opening each pair of games
+        #[arg(long, default_value_t = 2)]
+        openings: usize,
+        /// Time limit per engine move in milliseconds; levels mean full depth
+        #[arg(long)]
+        move_ms: Option<u64>,
+        /// Repeat a run exactly
+        #[arg(long)]
+        seed: Option<u64>,
+    },
+    /// Rate one engine configuration in Elo by a gauntlet against the levels
+    Elo {
+        /// `name:key=value,...` as for `tournament --entrant`
+        entrant: String,
+        /// Levels to play, e.g. `4,8,12`; every level when omitted
+        #[arg(long, value_delimiter = ',', value_parser = clap::value_parser!(u8).range(1..=15))]
+        levels: Vec<u8>,
+        /// Games against each level
+        #[arg(long, default_value_t = 20)]
+        games: usize,
+        /// Random moves opening each pair of games
+        #[arg(long, default_value_t = 2)]
+        openings: usize,
+        /// Time limit per engine move in milliseconds; levels mean full depth
+        #[arg(long)]
+        move_ms: Option<u64>,
+        /// Repeat a run exactly
+        #[arg(long)]
+        seed: Option<u64>,
+    },
+    /// Play matches between engine configurations and estimate their Elo
+    Tournament {
+        /// `name:key=value,...`, e.g. `wide:level=8,three=70`; at least two.
+        /// Keys: level, algorithm, randomness, temperature, lmr, futility, countermoves,
+        /// ordering, center, one, two, three, weights (a `tune` file), network,
+        /// policy (`heuristic`)
+        #[arg(long = "entrant", required = true)]
+        entrants: Vec<String>,
+        /// The first entrant plays each other one, instead of all against all
+        #[arg(long)]
+        gauntlet: bool,
+        /// Most games per pairing
+        #[arg(long, default_value_t = 100)]
+        games: usize,
+        /// Random moves opening each pair of games
+        #[arg(long, default_value_t = 2)]
+        openings: usize,
+        /// Time limit per engine move in milliseconds
+        #[arg(long)]
+        move_ms: Option<u64>,
+        /// Repeat a run exactly
+        #[arg(long)]
+        seed: Option<u64>,
+        /// Stop a pairing once an SPRT of `elo0,elo1` decides it
+        #[arg(long)]
+        sprt: Option<String>,
+    },
+    /// Record every node of a search to a binary file and summarize it
+    Trace {
+        /// Move history such as `R3B3`; `""` for the empty board
+        history: String,
+        /// Search depth, 1-15
+        #[arg(long, default_value_t = 8, value_parser = clap::value_parser!(u8).range(1..=15))]
+        depth: u8,
+        /// Trace file to write
+        #[arg(long, default_value = "search.trace")]
+        out: PathBuf,
+    },
+}
+
+/// Scores at or beyond this are forced wins (see `connect4::ScoredMove`).
+const FORCED_WIN: i32 = 1_000_000;
+
+/// A score from the mover's view: `win`, `loss` or signed evaluation units.
+fn score_label(score: i32) -> String {
+    match score {
+        s if s >= FORCED_WIN => "win".to_string(),
+        s if s <= -FORCED_WIN => "loss".to_string(),
+        s => format!("{s:+}"),
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Command {
+    /// Zero-based column.
+    Play(usize),
+    Undo,
+    Hint,
+    Level(u8),
+    New,
+    Help,
+    Quit,
+}
+
+impl FromStr for Command {
+    type Err = String;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let mut words = line.split_whitespace();
+        let command = words.next().unwrap_or_default().to_ascii_lowercase();
+        let argument = words.next();
+        if words.next().is_some() {
+            return Err(format!("too many words in {line:?}; try `help`"));
+        }
+        let no_argument = |command: Command| match argument {
+            Some(_) => Err(format!("`{line}` takes no argument")),
+            None => Ok(command),
+        };
+        match command.as_str() {
+            "u" | "undo" => no_argument(Command::Undo),
+            "h" | "hint" => no_argument(Command::Hint),
+            "n" | "new" => no_argument(Command::New),
+            "?" | "help" => no_argument(Command::Help),
+            "q" | "quit" | "exit" => no_argument(Command::Quit),
+            "l" | "level" => argument
+                .and_then(|level| level.parse().ok())
+                .filter(|level| (1..=15).contains(level))
+                .map(Command::Level)
+                .ok_or_else(|| "usage: level N, with N from 1 to 15".to_string()),
+            column => match column.parse::<usize>() {
+                Ok(column @ 1..=WIDTH) if argument.is_none() => Ok(Command::Play(column - 1)),
+                _ => Err(format!("unknown command {line:?}; try `help`")),
+            },
+        }
+    }
+}
+
+/// A game between the human and the engine.
+struct Match {
+    start: Vec<TypedMove>,
+    game: Game,
+    human: Player,
+    level: u8,
+}
+
+impl Match {
+    fn new(start: Vec<TypedMove>, human: Player, level: u8) -> anyhow::Result<Self> {
+        Ok(Self {
+            game: Game::from_moves(&start)?,
+            start,
+            human,
+            level,
+        })
+    }
+
+    fn state(&self) -> &GameState {
+        self.game.state()
+    }
+
+    fn moves(&self) -> &[TypedMove] {
+        self.game.moves()
+    }
+
+    fn is_over(&self) -> bool {
+        self.game.is_over()
+    }
+
+    fn play(&mut self, column: usize) -> Result<(), String> {
+        match self.game.play(column) {
+            Ok(_) => Ok(()),
+            Err(GameError::NoMoves) => Err("the game is over; `new` starts another".to_string()),
+            Err(GameError::ColumnFull { .. }) => Err(format!("column {} is full", column + 1)),
+            Err(err) => Err(err.to_string()),
+        }
+    }
+
+    fn search(&self) -> anyhow::Result<usize> {
+        let request = MoveRequest {
+            position: self.game.history(),
+            level: self.level,
+            first_player: None,
+        };
+        Ok(best_move(request)?.column)
+    }
+
+    /// Let the engine move if it is its turn.
+    fn engine_reply(&mut self) -> anyhow::Result<Option<usize>> {
+        if self.is_over() || self.state().to_move() == self.human {
+            return Ok(None);
+        }
+        let column = self.search()?;
+        self.play(column).map_err(anyhow::Error::msg)?;
+        Ok(Some(column))
+    }
+
+    /// Take back moves until it is the human's turn again, never past the
+    /// starting position. Returns how many moves were taken back.
+    fn undo(&mut self) -> usize {
+        let before = self.moves().len();
+        while self.moves().len() > self.start.len() {
+            self.game.undo();
+            if !matches!(self.moves().last(), Some(mv) if mv.player == self.human) {
+                break;
+            }
+        }
+        before - self.moves().len()
+    }
+
+    fn board(&self, color: bool) -> String {
+        let options = RenderOptions {
+            last_move: self.moves().last().map(|mv| mv.column),
+            ..RenderOptions::default()
+        };
+        render::text(self.state(), &options, color)
+    }
+
+    fn status(&self) -> String {
+        match self.state().winner() {
+            Some(player) if player == self.human => "You win!".to_string(),
+            Some(_) => "The engine wins.".to_string(),
+            None if self.state().is_full() => "Draw: the board is full.".to_string(),
+            None => format!(
+                "You play {}. Column (1-7), or `help`:",
+                name(self.human).to_lowercase()
+            ),
+        }
+    }
+}
+
+fn name(player: Player) -> &'static str {
+    match player {
+        Player::Red => "Red",
+        Player::Blue => "Blue",
+    }
+}
+
+const HELP: &str = "\
+  1-7       drop a disc in that column
+  undo, u   take back your last move and the engine's reply
+  hint, h   ask the engine for a move
+  level N   set the engine level (1-15)
+  new, n    start a new game
+  quit, q   leave";
+
+fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+    let color =
+        !cli.no_color && std::env::var_os("NO_COLOR").is_none() && io::stdout().is_terminal();
+    match cli.task {
+        Some(Task::Analyze { history, depth }) => return analyze::run(history, depth, color),
+        Some(Task::Solve {
+            history,
+            time,
+            stats,
+        }) => return solve::run(history, time, stats),
+        Some(Task::Selfplay {
+            games,
+            red_level,
+            blue_level,
+            openings,
+            move_ms,
+            seed,
+            evals,
+            out,
+        }) => {
+            return selfplay::run(selfplay::Options {
+                games,
+                red_level,
+                blue_level,
+                openings,
+                move_ms,
+                seed,
+                evals,
+                out,
+            })
+        }
+        Some(Task::Dataset {
+            games,
+            level,
+            openings,
+            move_ms,
+            seed,
+            format,
+            keep_duplicates,
+            out,
+        }) => {
+            return dataset::run(dataset::Options {
+                games,
+                level,
+                openings,
+                move_ms,
+                seed,
+                format,
+                keep_duplicates,
+                out,
+            })
+        }
+        Some(Task::Tune {
+            samples,
+            passes,
+            out,
+        }) => return tune::run(samples, passes, out),
+        Some(Task::Bench { depth }) => return bench::bench(depth),
+        Some(Task::Perft { depth, position }) => return bench::run_perft(depth, &position),
+        Some(Task::Puzzle {
+            count,
+            difficulty,
+            seed,
+        }) => return puzzle::run(count, difficulty, seed),
+        Some(Task::Calibrate {
+            top,
+            games,
+            openings,
+            move_ms,
+            seed,
+        }) => return calibrate::run(top, games, openings, move_ms, seed),
+        Some(Task::Elo {
+            entrant,
+            levels,
+            games,
+            openings,
+            move_ms,
+            seed,
+        }) => return calibrate::estimate(&entrant, &levels, games, openings, move_ms, seed),
+        Some(Task::Tournament {
+            entrants,
+            gauntlet,
+            games,
+            openings,
+            move_ms,
+            seed,
+            sprt,
+        }) => {
+            return tournament::run(tournament::Options {
+                entrants,
+                gauntlet,
+                games,
+                openings,
+                move_ms,
+                seed,
+                sprt,
+            })
+        }
+        Some(Task::Trace {
+            history,
+            depth,
+            out,
+        }) => return trace::run(history, depth, out),
+        None => {}
+    }
+    let start = match &cli.position {
+        Some(position) => parse_history(position).context("invalid --position")?,
+        None => Vec::new(),
+    };
+    // The human plays the side to move unless the engine is to go first.
+    let to_move = GameState::from_history(&start)
+        .context("invalid --position")?
+        .to_move();
+    let human = if cli.engine_first {
+        to_move.opponent()
+    } else {
+        to_move
+    };
+    let mut game = Match::new(start.clone(), human, cli.level)?;
+    #[cfg(feature = "tui")]
+    if cli.tui {
+        return tui::run(game);
+    }
+
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    let mut out = io::stdout().lock();
+    writeln!(out, "Connect 4 against the engine at level {}.", game.level)?;
+    // The board is only drawn again after it changed.
+    let mut redraw = true;
+    loop {
+        if let Some(column) = game.engine_reply()? {
+            writeln!(out, "The engine plays {}.", column + 1)?;
+        }
+        if redraw {
+            write!(out, "\n{}\n", game.board(color))?;
+        }
+        write!(out, "{} ", game.status())?;
+        out.flush()?;
+        let Some(line) = lines.next().transpose()? else {
+            writeln!(out)?;
+            return Ok(());
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        let command = match line.parse::<Command>() {
+            Ok(command) => command,
+            Err(message) => {
+                writeln!(out, "{message}")?;
+                redraw = false;
+                continue;
+            }
+        };
+        redraw = matches!(command, Command::Play(_) | Command::Undo | Command::New);
+        match command {
+            Command::Play(column) => {
+                if let Err(message) = game.play(column) {
+                    writeln!(out, "{message}")?;
+                    redraw = false;
+                }
+            }
+            Command::Undo => match game.undo() {
+                0 => {
+                    writeln!(out, "Nothing to undo.")?;
+                    redraw = false;
+                }
+                taken => writeln!(out, "Took back {taken} moves.")?,
+            },
+            Command::Hint if game.is_over() => writeln!(out, "The game is over.")?,
+            Command::Hint => writeln!(out, "Hint: column {}.", game.search()? + 1)?,
+            Command::Level(level) => {
+                game.level = level;
+                writeln!(out, "The engine now plays at level {level}.")?;
+            }
+            Command::New => {
+                game = Match::new(start.clone(), human, game.level)?;
+                writeln!(out, "New game.")?;
+            }
+            Command::Help => writeln!(out, "{HELP}")?,
+            Command::Quit => return Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn commands_parse_one_based_columns() {
+        assert_eq!("4".parse(), Ok(Command::Play(3)));
+        assert_eq!(" Undo ".parse(), Ok(Command::Undo));
+        assert_eq!("level 12".parse(), Ok(Command::Level(12)));
+        for bad in ["0", "8", "level", "level 16", "undo now", "castle"] {
+            assert!(bad.parse::<Command>().is_err(), "{bad:?} should not parse");
+        }
+    }
+
+    #[test]
+    fn undo_returns_to_the_humans_turn() {
+        let mut game = Match::new(parse_history("R3").unwrap(), Player::Blue, 2).unwrap();
+        assert_eq!(game.undo(), 0);
+        game.play(3).unwrap();
+        game.engine_reply().unwrap().unwrap();
+        game.play(2).unwrap();
+        assert_eq!(game.undo(), 1);
+        assert_eq!(game.undo(), 2);
+        assert_eq!(game.game.history(), "R3");
+        assert_eq!(game.state().to_move(), Player::Blue);
+        for _ in 0..6 {
+            game.play(0).unwrap();
+        }
+        assert_eq!(game.play(0), Err("column 1 is full".to_string()));
+    }
+}
@@ -0,0 +1,33 @@
+//! `connect4-cli puzzle`: puzzle packs for the web client, produced offline.
+use std::io::{self, Write};
+
+use clap::ValueEnum;
+use connect4::puzzle::{puzzles, Difficulty};
+
+#[derive(Copy, Clone, Debug, ValueEnum)]
+pub enum Level {
+    /// Win in 2 moves
+    Easy,
+    /// Win in 3 moves
+    Medium,
+    /// Win in 4 moves
+    Hard,
+}
+
+impl From<Level> for Difficulty {
+    fn from(level: Level) -> Self {
+        match level {
+            Level::Easy => Difficulty::Easy,
+            Level::Medium => Difficulty::Medium,
+            Level::Hard => Difficulty::Hard,
+        }
+    }
+}
+
+pub fn run(count: usize, level: Level, seed: Option<u64>) -> anyhow::Result<()> {
+    let pack: Vec<_> = puzzles(level.into(), seed).take(count).collect();
+    let mut out = io::stdout().lock();
+    serde_json::to_writer_pretty(&mut out, &pack)?;
+    writeln!(out)?;
+    Ok(())
+}
@@ -0,0 +1,131 @@
+//! `connect4-cli selfplay`: engine-versus-engine games written in the
+//! `connect4::notation` format, for building tuning data locally.
+use std::{
+    fs::File,
+    io::{self, BufWriter, IsTerminal, Write},
+    path::PathBuf,
+    time::Duration,
+};
+
+use anyhow::Context;
+use connect4::{
+    notation::{Notation, Outcome},
+    parse_history,
+    selfplay::GameRecord,
+    selfplay_games, Player, SearchOptions, SelfPlayConfig,
+};
+
+pub struct Options {
+    pub games: usize,
+    pub red_level: u8,
+    pub blue_level: u8,
+    pub openings: usize,
+    pub move_ms: Option<u64>,
+    pub seed: Option<u64>,
+    pub evals: bool,
+    pub out: Option<PathBuf>,
+}
+
+pub fn run(options: Options) -> anyhow::Result<()> {
+    let config = SelfPlayConfig {
+        games: options.games,
+        opening_plies: options.openings,
+        move_time: options.move_ms.map(Duration::from_millis),
+        seed: options.seed,
+        ..SelfPlayConfig::new(
+            SearchOptions::new(options.red_level),
+            SearchOptions::new(options.blue_level),
+        )
+    };
+    let mut out: Box<dyn Write> = match &options.out {
+        Some(path) => Box::new(BufWriter::new(
+            File::create(path).with_context(|| format!("cannot create {}", path.display()))?,
+        )),
+        None => Box::new(io::stdout().lock()),
+    };
+    let progress = options.out.is_some() && io::stderr().is_terminal();
+    // Wins for Red, Blue, then draws.
+    let mut tally = [0; 3];
+    for (round, game) in selfplay_games(&config).enumerate() {
+        let game = game?;
+        tally[match game.winner {
+            Some(Player::Red) => 0,
+            Some(Player::Blue) => 1,
+            None => 2,
+        }] += 1;
+        if round > 0 {
+            writeln!(out)?;
+        }
+        write!(
+            out,
+            "{}",
+            notation(&game, &config, round + 1, options.evals)?
+        )?;
+        if progress {
+            eprint!("\r{}/{} games", round + 1, options.games);
+        }
+    }
+    out.flush()?;
+    if progress {
+        eprintln!();
+    }
+    eprintln!("Red {} - Blue {} - draws {}", tally[0], tally[1], tally[2]);
+    Ok(())
+}
+
+/// One game with its levels as tags and, with `evals`, the engine's score
+/// after each searched move as a comment, from Red's point of view.
+fn notation(
+    game: &GameRecord,
+    config: &SelfPlayConfig,
+    round: usize,
+    evals: bool,
+) -> anyhow::Result<Notation> {
+    let outcome = match game.winner {
+        Some(Player::Red) => Outcome::RedWin,
+        Some(Player::Blue) => Outcome::BlueWin,
+        None => Outcome::Draw,
+    };
+    let mut notation = Notation::new(parse_history(&game.history)?, outcome)
+        .with_tag("Event", "Self-play")
+        .with_tag("Round", round.to_string())
+        .with_tag("Red", format!("Engine level {}", config.red.level))
+        .with_tag("Blue", format!("Engine level {}", config.blue.level))
+        .with_tag("Opening", game.opening_plies.to_string());
+    if evals {
+        for (ply, (mv, eval)) in notation.moves.iter().zip(&game.evals).enumerate() {
+            if let Some(score) = eval {
+                let score = match mv.player {
+                    Player::Red => *score,
+                    Player::Blue => -score,
+                };
+                notation.comments.insert(ply, format!("{score:+}"));
+            }
+        }
+    }
+    Ok(notation)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn games_carry_levels_and_red_side_evals() {
+        let config = SelfPlayConfig {
+            seed: Some(5),
+            ..SelfPlayConfig::new(SearchOptions::new(3), SearchOptions::new(2))
+        };
+        let game = connect4::selfplay(&config).unwrap().remove(0);
+        let text = notation(&game, &config, 1, true).unwrap().to_string();
+        assert!(text.contains("[Red \"Engine level 3\"]\n[Blue \"Engine level 2\"]\n"));
+        let parsed: Notation = text.parse().unwrap();
+        assert_eq!(parsed.history(), game.history);
+        assert!(!parsed.comments.contains_key(&0));
+        let blue_eval: i32 = parsed.comments[&3].parse().unwrap();
+        assert_eq!(blue_eval, -game.evals[3].unwrap());
+
+        let plain = notation(&game, &config, 1, false).unwrap();
+        assert!(plain.comments.is_empty());
+    }
+}
@@ -0,0 +1,181 @@
+//! `connect4-cli solve`: the game-theoretic value of a position, for checking
+//! the heuristic engine against ground truth.
+use std::{
+    fmt,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use connect4::{
+    parse_history, search, tt::TranspositionTable, Algorithm, GameState, SearchInfo, SearchLimits,
+    SearchOptions, HEIGHT, WIDTH,
+};
+
+use crate::{name, FORCED_WIN};
+
+/// The outcome under perfect play, from the side to move's view. Distances
+/// count plies until the winning disc drops.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Verdict {
+    Win(usize),
+    Draw,
+    Loss(usize),
+    /// The time limit ran out first.
+    Unknown,
+}
+
+impl fmt::Display for Verdict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Verdict::Win(plies) => write!(f, "wins in {}", plies_label(*plies)),
+            Verdict::Draw => write!(f, "draws"),
+            Verdict::Loss(plies) => write!(f, "loses in {}", plies_label(*plies)),
+            Verdict::Unknown => write!(f, "is unresolved"),
+        }
+    }
+}
+
+fn plies_label(plies: usize) -> String {
+    match plies {
+        1 => "1 ply".to_string(),
+        n => format!("{n} plies"),
+    }
+}
+
+/// Run the solver on `state`, passing its per-depth progress to
+/// `on_iteration`. Also returns the moves that achieve the verdict.
+fn verdict(
+    state: &GameState,
+    limits: &SearchLimits,
+    on_iteration: impl FnMut(&SearchInfo),
+) -> anyhow::Result<(Verdict, Vec<usize>)> {
+    if state.winner().is_some() {
+        // Whoever just moved has won.
+        return Ok((Verdict::Loss(0), Vec::new()));
+    }
+    if state.is_full() {
+        return Ok((Verdict::Draw, Vec::new()));
+    }
+    let options = SearchOptions {
+        algorithm: Algorithm::Solver,
+        multipv: WIDTH,
+        ..SearchOptions::new(15)
+    };
+    let outcome = search(state, &options, limits, on_iteration)?;
+    let remaining = WIDTH * HEIGHT - state.board_model().moves_played;
+    // The solver deepens one ply at a time and stops at the first proven
+    // result, so no win or loss was found any closer than `outcome.depth`.
+    let result = match outcome.score {
+        s if s >= FORCED_WIN => Verdict::Win(outcome.depth),
+        s if s <= -FORCED_WIN => Verdict::Loss(outcome.depth),
+        // Every leaf of a search to the last cell is a finished game.
+        _ if outcome.depth == remaining => Verdict::Draw,
+        _ => Verdict::Unknown,
+    };
+    let best = outcome
+        .lines
+        .iter()
+        .filter(|line| line.score == outcome.score)
+        .map(|line| line.column)
+        .collect();
+    Ok((result, best))
+}
+
+/// One line of perfect play: the winner takes the quickest win and the loser
+/// holds out longest. Stops early if a position cannot be solved within
+/// `limits`.
+fn optimal_line(state: &GameState, limits: &SearchLimits) -> anyhow::Result<Vec<usize>> {
+    let mut state = state.clone();
+    let mut line = Vec::new();
+    loop {
+        let (result, best) = verdict(&state, limits, |_| {})?;
+        let column = match result {
+            Verdict::Unknown => break,
+            _ if best.is_empty() => break,
+            // Every move loses; only re-solving each one tells how slowly.
+            Verdict::Loss(_) if best.len() > 1 => {
+                let mut slowest = (best[0], 0);
+                for &column in &best {
+                    let mut child = state.clone();
+                    child.play(column)?;
+                    match verdict(&child, limits, |_| {})?.0 {
+                        Verdict::Win(plies) if plies > slowest.1 => slowest = (column, plies),
+                        Verdict::Win(_) => {}
+                        _ => break,
+                    }
+                }
+                slowest.0
+            }
+            _ => best[0],
+        };
+        state.play(column)?;
+        line.push(column);
+    }
+    Ok(line)
+}
+
+pub fn run(history: String, seconds: u64, stats: bool) -> anyhow::Result<()> {
+    let moves = parse_history(&history)?;
+    let state = GameState::from_history(&moves)?;
+    if let Some(winner) = state.winner() {
+        println!("{} has already won.", name(winner));
+        return Ok(());
+    }
+    let started = Instant::now();
+    // One table for the verdict and the line, so the line starts warm.
+    let table = Arc::new(TranspositionTable::default());
+    let limits = SearchLimits {
+        table: Some(table.clone()),
+        ..SearchLimits::with_deadline(started + Duration::from_secs(seconds))
+    };
+    let (result, _) = verdict(&state, &limits, |info| {
+        if stats {
+            let table = table.stats();
+            println!(
+                "depth {:>2}  best {}  score {:>8}  nodes {:>12}  probes {:>12}  hits {:>12} ({:>5.1}%)  fill {:>5.1}%  {:>7.2}s",
+                info.depth,
+                info.column + 1,
+                info.score,
+                info.nodes,
+                table.probes,
+                table.hits,
+                table.hit_rate() * 100.0,
+                table.occupancy * 100.0,
+                started.elapsed().as_secs_f64()
+            );
+        }
+    })?;
+    println!("{} {result}.", name(state.to_move()));
+    if result == Verdict::Unknown {
+        println!("Not solved within {seconds}s; try a later position or a longer --time.");
+        return Ok(());
+    }
+    let line = optimal_line(&state, &limits)?;
+    let line: Vec<String> = line.iter().map(|column| (column + 1).to_string()).collect();
+    println!("Line: {}", line.join(" "));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn solve(history: &str) -> (Verdict, Vec<usize>) {
+        let state = GameState::from_history(&parse_history(history).unwrap()).unwrap();
+        let limits = SearchLimits::default();
+        (
+            verdict(&state, &limits, |_| {}).unwrap().0,
+            optimal_line(&state, &limits).unwrap(),
+        )
+    }
+
+    #[test]
+    fn solves_wins_and_losses_with_their_distance() {
+        assert_eq!(solve("R0B1R0B1R0B1"), (Verdict::Win(1), vec![0]));
+        // Red's open three on the bottom row cannot be stopped.
+        let (result, line) = solve("R1B1R2B2R3");
+        assert_eq!(result, Verdict::Loss(2));
+        assert_eq!(line.len(), 2);
+        assert_eq!(solve("R0B1R0B1R0B1R0").0, Verdict::Loss(0));
+    }
+}
@@ -0,0 +1,260 @@
+//! `connect4-cli tournament`: round-robin or gauntlet matches between engine
+//! configurations, with optional SPRT early stopping.
+use std::{
+    io::{self, IsTerminal},
+    path::Path,
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::{bail, Context};
+use connect4::{
+    nn::NnEval,
+    policy::{HeuristicPolicy, SharedPolicy},
+    tournament::{self, Entrant, Format, Score, Sprt, TournamentConfig},
+    Algorithm, EvalPreset, MoveOrdering, SearchOptions,
+};
+
+pub struct Options {
+    pub entrants: Vec<String>,
+    pub gauntlet: bool,
+    pub games: usize,
+    pub openings: usize,
+    pub move_ms: Option<u64>,
+    pub seed: Option<u64>,
+    pub sprt: Option<String>,
+}
+
+/// `name:key=value,...`, for example `wide:level=8,three=70`. Keys are
+/// `level`, `algorithm`, `randomness`, `temperature`, `lmr`, `futility` and `countermoves`
+/// (`true` to turn those on), `ordering` (`center` or `threats`), the evaluation weights `center`, `one`, `two`,
+/// `three`, `mobility` and `buried`, `weights`, a file of them from `connect4-cli tune`, `style`, a preset
+/// (`aggressive`, `balanced` or `defensive`) that replaces them, `network`, a weights file for `connect4::nn` that
+/// replaces them, and `policy=heuristic` to guide MCTS by the evaluation; anything left out keeps its default, level 8.
+pub fn parse_entrant(spec: &str) -> anyhow::Result<Entrant> {
+    let (name, settings) = spec.split_once(':').unwrap_or((spec, ""));
+    if name.is_empty() {
+        bail!("entrant {spec:?} has no name");
+    }
+    let mut options = SearchOptions::new(8);
+    let mut heuristic_policy = false;
+    for setting in settings.split(',').filter(|s| !s.is_empty()) {
+        let (key, value) = setting
+            .split_once('=')
+            .with_context(|| format!("expected key=value, found {setting:?}"))?;
+        let invalid = || format!("invalid {key} {value:?} for {name}");
+        match key {
+            "level" => {
+                options.level = value.parse().with_context(invalid)?;
+                if !(1..=15).contains(&options.level) {
+                    bail!("level must be 1-15 for {name}");
+                }
+            }
+            "algorithm" => {
+                options.algorithm = match value {
+                    "alphabeta" => Algorithm::AlphaBeta,
+                    "mcts" => Algorithm::Mcts,
+                    "solver" => Algorithm::Solver,
+                    _ => bail!(invalid()),
+                }
+            }
+            "randomness" => options.randomness = value.parse().with_context(invalid)?,
+            "temperature" => options.temperature = value.parse().with_context(invalid)?,
+            "lmr" => options.late_move_reductions = value.parse().with_context(invalid)?,
+            "futility" => options.futility_pruning = value.parse().with_context(invalid)?,
+            "countermoves" => options.countermoves = value.parse().with_context(invalid)?,
+            "ordering" => {
+                options.ordering = match value {
+                    "center" => MoveOrdering::Center,
+                    "threats" => MoveOrdering::Threats,
+                    _ => bail!(invalid()),
+                }
+            }
+            "center" => options.weights.center = value.parse().with_context(invalid)?,
+            "one" => options.weights.one = value.parse().with_context(invalid)?,
+            "two" => options.weights.two = value.parse().with_context(invalid)?,
+            "three" => options.weights.three = value.parse().with_context(invalid)?,
+            "mobility" => options.weights.mobility = value.parse().with_context(invalid)?,
+            "buried" => options.weights.buried = value.parse().with_context(invalid)?,
+            "weights" => options.weights = crate::tune::read_weights(Path::new(value))?,
+            "style" => {
+                options.weights = match value {
+                    "aggressive" => EvalPreset::Aggressive,
+                    "balanced" => EvalPreset::Balanced,
+                    "defensive" => EvalPreset::Defensive,
+                    _ => bail!(invalid()),
+                }
+                .weights()
+            }
+            "network" => {
+                let network = NnEval::load(value).with_context(invalid)?;
+                options.network = Some(Arc::new(network));
+            }
+            "policy" => match value {
+                "heuristic" => heuristic_policy = true,
+                _ => bail!(invalid()),
+            },
+            _ => bail!("unknown setting {key:?} for {name}"),
+        }
+    }
+    // After the loop, so the policy sees weights given after it too.
+    if heuristic_policy {
+        options.policy = Some(SharedPolicy::new(HeuristicPolicy {
+            weights: options.weights,
+        }));
+    }
+    Ok(Entrant {
+        name: name.to_string(),
+        options,
+    })
+}
+
+/// `elo0,elo1`, such as `0,10`.
+fn parse_sprt(spec: &str) -> anyhow::Result<Sprt> {
+    let bounds = spec
+        .split_once(',')
+        .and_then(|(elo0, elo1)| Some((elo0.trim().parse().ok()?, elo1.trim().parse().ok()?)));
+    match bounds {
+        Some((elo0, elo1)) if elo0 < elo1 => Ok(Sprt::new(elo0, elo1)),
+        _ => bail!("--sprt takes elo0,elo1 with elo0 < elo1, such as 0,10"),
+    }
+}
+
+fn elo_label(score: &Score) -> String {
+    match score.elo() {
+        Some((elo, margin)) => format!("{elo:+.0} ± {margin:.0}"),
+        None if score.wins > 0 => "+inf".to_string(),
+        None => "-inf".to_string(),
+    }
+}
+
+pub fn run(options: Options) -> anyhow::Result<()> {
+    let entrants = options
+        .entrants
+        .iter()
+        .map(|spec| parse_entrant(spec))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    if entrants.len() < 2 {
+        bail!("a tournament needs at least two --entrant");
+    }
+    let config = TournamentConfig {
+        format: if options.gauntlet {
+            Format::Gauntlet
+        } else {
+            Format::RoundRobin
+        },
+        games: options.games,
+        opening_plies: options.openings,
+        move_time: options.move_ms.map(Duration::from_millis),
+        seed: options.seed,
+        sprt: options.sprt.as_deref().map(parse_sprt).transpose()?,
+        ..TournamentConfig::new(entrants)
+    };
+    let name = |index: usize| config.entrants[index].name.as_str();
+    let progress = io::stderr().is_terminal();
+    let pairings = tournament::tournament(&config, |pairing| {
+        if progress {
+            let score = pairing.score;
+            eprint!(
+                "\r{} vs {}: +{} ={} -{}   ",
+                name(pairing.first),
+                name(pairing.second),
+                score.wins,
+                score.draws,
+                score.losses
+            );
+        }
+    })?;
+    if progress {
+        eprintln!();
+    }
+
+    let width = config
+        .entrants
+        .iter()
+        .map(|e| e.name.len())
+        .max()
+        .unwrap_or(0);
+    for pairing in &pairings {
+        let score = pairing.score;
+        let mut line = format!(
+            "{:<width$} vs {:<width$}  +{} ={} -{}  {:.1}/{}  Elo {}",
+            name(pairing.first),
+            name(pairing.second),
+            score.wins,
+            score.draws,
+            score.losses,
+            score.points(),
+            score.games(),
+            elo_label(&score)
+        );
+        if let Some(sprt) = config.sprt {
+            line += &format!("  {}", sprt.status(&score));
+        }
+        println!("{line}");
+    }
+    println!();
+    let mut table: Vec<(usize, Score)> = tournament::standings(config.entrants.len(), &pairings)
+        .into_iter()
+        .enumerate()
+        .collect();
+    table.sort_by(|a, b| b.1.fraction().total_cmp(&a.1.fraction()));
+    for (rank, (entrant, score)) in table.iter().enumerate() {
+        println!(
+            "{:>2}. {:<width$}  {:.1}/{}  {:>5.1}%  Elo {}",
+            rank + 1,
+            name(*entrant),
+            score.points(),
+            score.games(),
+            score.fraction() * 100.0,
+            elo_label(score)
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entrants_parse_from_specs() {
+        let wide = parse_entrant(
+            "wide:level=6,three=70,buried=-20,algorithm=solver,lmr=true,futility=true,ordering=threats",
+        )
+        .unwrap();
+        assert_eq!(wide.name, "wide");
+        assert_eq!(wide.options.level, 6);
+        assert_eq!(wide.options.weights.three, 70);
+        assert_eq!(wide.options.weights.buried, -20);
+        assert_eq!(wide.options.algorithm, Algorithm::Solver);
+        assert!(wide.options.late_move_reductions);
+        assert!(wide.options.futility_pruning);
+        assert!(!wide.options.countermoves);
+        assert_eq!(wide.options.ordering, MoveOrdering::Threats);
+        assert_eq!(
+            parse_entrant("base").unwrap().options,
+            SearchOptions::new(8)
+        );
+        let defensive = parse_entrant("d:style=defensive").unwrap();
+        assert_eq!(defensive.options.weights, EvalPreset::Defensive.weights());
+        let guided = parse_entrant("az:algorithm=mcts,policy=heuristic").unwrap();
+        assert!(guided.options.policy.is_some());
+        for bad in [
+            "a:policy=network",
+            "a:style=reckless",
+            ":level=3",
+            "a:level=16",
+            "a:level",
+            "a:depth=3",
+            "a:algorithm=dfs",
+            "a:lmr=yes",
+            "a:network=/nonexistent.c4nn",
+            "a:weights=/nonexistent.json",
+        ] {
+            assert!(parse_entrant(bad).is_err(), "{bad:?} should not parse");
+        }
+        assert_eq!(parse_sprt("0, 10").unwrap(), Sprt::new(0.0, 10.0));
+        assert!(parse_sprt("10,0").is_err());
+    }
+}
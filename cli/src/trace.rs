@@ -0,0 +1,57 @@
+//! `connect4-cli trace`: record a search node by node and summarize the file.
+use std::{collections::BTreeMap, path::PathBuf};
+
+use anyhow::Context;
+use connect4::{
+    best_move_within, parse_history,
+    trace::{TraceReader, Tracer},
+    GameState, MoveRequest, SearchLimits,
+};
+
+use crate::score_label;
+
+pub fn run(history: String, depth: u8, out: PathBuf) -> anyhow::Result<()> {
+    let root = GameState::from_history(&parse_history(&history)?)?.key();
+    let tracer =
+        Tracer::create(&out).with_context(|| format!("cannot create {}", out.display()))?;
+    let limits = SearchLimits {
+        trace: Some(tracer.clone()),
+        ..SearchLimits::default()
+    };
+    let request = MoveRequest {
+        position: history,
+        level: depth,
+        first_player: None,
+    };
+    let response = best_move_within(request, &limits, |_| {})?;
+    let written = tracer
+        .finish()
+        .with_context(|| format!("cannot write {}", out.display()))?;
+
+    // Read the file back rather than trusting the counts in memory, so a
+    // summary that looks right also says the file is readable.
+    let mut by_depth = BTreeMap::new();
+    println!("{:>5} {:>6} {:>12}", "depth", "column", "score");
+    for node in TraceReader::open(&out)? {
+        let node = node.with_context(|| format!("cannot read {}", out.display()))?;
+        *by_depth.entry(node.depth).or_insert(0u64) += 1;
+        if node.key == root {
+            let column = node.best.map_or("-".to_string(), |c| (c + 1).to_string());
+            println!(
+                "{:>5} {:>6} {:>12}",
+                node.depth,
+                column,
+                score_label(node.score)
+            );
+        }
+    }
+    println!(
+        "\nPlays column {}. {written} nodes written to {}:",
+        response.column + 1,
+        out.display()
+    );
+    for (depth, nodes) in by_depth.iter().rev() {
+        println!("  depth {depth:>2}: {nodes}");
+    }
+    Ok(())
+}
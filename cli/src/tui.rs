@@ -0,0 +1,474 @@
+//! Full-screen play with a live analysis sidebar (`--tui`, `tui` feature).
+//!
+//! The board sits on the left under a column cursor. The sidebar shows an
+//! evaluation bar, the line the engine expects, both players' thinking time
+//! and the move list. Analysis and engine moves run on background threads and
+//! report back over a channel, tagged with the position they were started
+//! for, so the screen keeps redrawing while the engine thinks and results for
+//! a position that has since changed (after an undo, say) are dropped.
+use std::{
+    sync::mpsc::{self, Receiver, Sender},
+    thread,
+    time::Duration,
+};
+
+use connect4::{
+    best_move_within, format_history, principal_variation, CancelToken, MoveRequest, Player,
+    SearchInfo, SearchLimits, HEIGHT, WIDTH,
+};
+use ratatui::{
+    crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    layout::{Constraint, Layout},
+    style::{Color, Modifier, Style},
+    text::{Line, Span},
+    widgets::{Block, Gauge, Paragraph},
+    DefaultTerminal, Frame,
+};
+
+use crate::{Match, FORCED_WIN};
+
+/// How long to wait for a key before redrawing the clocks.
+const TICK: Duration = Duration::from_millis(100);
+/// Heuristic score that fills three quarters of the evaluation bar.
+const EVAL_SCALE: f64 = 150.0;
+const RED: Color = Color::LightRed;
+const BLUE: Color = Color::LightCyan;
+
+pub fn run(game: Match) -> anyhow::Result<()> {
+    let mut terminal = ratatui::init();
+    let result = App::new(game).run(&mut terminal);
+    ratatui::restore();
+    result
+}
+
+/// Results from the background threads, tagged with the position generation
+/// they were started for.
+enum Update {
+    Progress(u64, SearchInfo),
+    Line(u64, Vec<usize>),
+    EngineMove(u64, usize),
+    Failed(u64, String),
+}
+
+#[derive(Default)]
+struct Analysis {
+    depth: usize,
+    /// From Red's point of view.
+    score: i32,
+    best: Option<usize>,
+    nodes: u64,
+    line: Vec<usize>,
+}
+
+struct App {
+    game: Match,
+    cursor: usize,
+    /// Bumped whenever the position changes.
+    generation: u64,
+    cancel: CancelToken,
+    analysis: Analysis,
+    engine_thinking: bool,
+    message: String,
+    updates: Receiver<Update>,
+    sender: Sender<Update>,
+}
+
+impl App {
+    fn new(game: Match) -> Self {
+        let (sender, updates) = mpsc::channel();
+        Self {
+            game,
+            cursor: WIDTH / 2,
+            generation: 0,
+            cancel: CancelToken::new(),
+            analysis: Analysis::default(),
+            engine_thinking: false,
+            message: String::new(),
+            updates,
+            sender,
+        }
+    }
+
+    fn run(mut self, terminal: &mut DefaultTerminal) -> anyhow::Result<()> {
+        self.position_changed();
+        loop {
+            while let Ok(update) = self.updates.try_recv() {
+                self.apply(update);
+            }
+            terminal.draw(|frame| self.draw(frame))?;
+            if !event::poll(TICK)? {
+                continue;
+            }
+            if let Event::Key(key) = event::read()? {
+                if key.kind == KeyEventKind::Press && !self.handle_key(key) {
+                    self.cancel.cancel();
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    /// Returns `false` to quit.
+    fn handle_key(&mut self, key: KeyEvent) -> bool {
+        match key.code {
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => return false,
+            KeyCode::Char('q') | KeyCode::Esc => return false,
+            KeyCode::Left => self.cursor = self.cursor.saturating_sub(1),
+            KeyCode::Right => self.cursor = (self.cursor + 1).min(WIDTH - 1),
+            KeyCode::Enter | KeyCode::Char(' ') | KeyCode::Down => self.human_move(self.cursor),
+            KeyCode::Char(digit @ '1'..='7') => {
+                self.cursor = digit as usize - '1' as usize;
+                self.human_move(self.cursor);
+            }
+            KeyCode::Char('u') => {
+                if self.game.undo() == 0 {
+                    self.message = "Nothing to undo.".to_string();
+                } else {
+                    self.message.clear();
+                    self.position_changed();
+                }
+            }
+            KeyCode::Char('h') => {
+                self.message = match self.analysis.best {
+                    _ if self.game.is_over() => "The game is over.".to_string(),
+                    Some(column) if !self.engine_thinking => {
+                        self.cursor = column;
+                        format!("Hint: column {}.", column + 1)
+                    }
+                    _ => "Still thinking.".to_string(),
+                };
+            }
+            KeyCode::Char(sign @ ('+' | '-')) => {
+                let level = match sign {
+                    '+' => self.game.level.saturating_add(1).min(15),
+                    _ => self.game.level.saturating_sub(1).max(1),
+                };
+                if level != self.game.level {
+                    self.game.level = level;
+                    self.message = format!("The engine now plays at level {level}.");
+                    self.position_changed();
+                }
+            }
+            KeyCode::Char('n') => {
+                self.game = Match::new(self.game.start.clone(), self.game.human, self.game.level)
+                    .expect("the starting position was valid before");
+                self.message = "New game.".to_string();
+                self.position_changed();
+            }
+            _ => {}
+        }
+        true
+    }
+
+    fn human_move(&mut self, column: usize) {
+        if self.engine_thinking {
+            self.message = "Wait for the engine's move.".to_string();
+            return;
+        }
+        match self.game.play(column) {
+            Ok(()) => {
+                self.message.clear();
+                self.position_changed();
+            }
+            Err(message) => self.message = message,
+        }
+    }
+
+    /// Drop work for the old position and start analysing the new one. On
+    /// the engine's turn the same search picks its move; on the human's it is
+    /// followed by the expected line.
+    fn position_changed(&mut self) {
+        self.cancel.cancel();
+        self.cancel = CancelToken::new();
+        self.generation += 1;
+        self.analysis = Analysis::default();
+        self.engine_thinking = false;
+        if self.game.is_over() {
+            return;
+        }
+        let request = MoveRequest {
+            position: format_history(self.game.moves()),
+            level: self.game.level,
+            first_player: None,
+        };
+        let limits = SearchLimits {
+            cancel: Some(self.cancel.clone()),
+            ..SearchLimits::default()
+        };
+        let engine_to_move = self.game.state().to_move() != self.game.human;
+        self.engine_thinking = engine_to_move;
+        let (generation, sender) = (self.generation, self.sender.clone());
+        // Sends fail only once the app has quit, when nobody needs the result.
+        thread::spawn(move || {
+            let progress = |info: &SearchInfo| {
+                let _ = sender.send(Update::Progress(generation, info.clone()));
+            };
+            let update = match best_move_within(request.clone(), &limits, progress) {
+                Err(err) => Update::Failed(generation, err.to_string()),
+                Ok(mv) if engine_to_move => Update::EngineMove(generation, mv.column),
+                Ok(_)
+                    if limits
+                        .cancel
+                        .as_ref()
+                        .is_some_and(CancelToken::is_cancelled) =>
+                {
+                    return
+                }
+                Ok(_) => match principal_variation(request) {
+                    Ok(line) => Update::Line(generation, line),
+                    Err(err) => Update::Failed(generation, err.to_string()),
+                },
+            };
+            let _ = sender.send(update);
+        });
+    }
+
+    fn apply(&mut self, update: Update) {
+        match update {
+            Update::Progress(generation, info) if generation == self.generation => {
+                let mover = self.game.state().to_move();
+                self.analysis.depth = info.depth;
+                self.analysis.score = match mover {
+                    Player::Red => info.score,
+                    Player::Blue => -info.score,
+                };
+                self.analysis.best = Some(info.column);
+                self.analysis.nodes = info.nodes;
+            }
+            Update::Line(generation, line) if generation == self.generation => {
+                self.analysis.line = line;
+            }
+            Update::EngineMove(generation, column) if generation == self.generation => {
+                match self.game.play(column) {
+                    Ok(()) => {
+                        self.message = format!("The engine plays {}.", column + 1);
+                        self.position_changed();
+                    }
+                    Err(message) => self.message = message,
+                }
+            }
+            Update::Failed(generation, message) if generation == self.generation => {
+                self.engine_thinking = false;
+                self.message = message;
+            }
+            // Left over from a position that has changed since.
+            _ => {}
+        }
+    }
+
+    fn draw(&self, frame: &mut Frame) {
+        let [main, help] =
+            Layout::vertical([Constraint::Min(0), Constraint::Length(1)]).areas(frame.area());
+        let board_width = 3 * WIDTH as u16 + 2;
+        let [board, sidebar] =
+            Layout::horizontal([Constraint::Length(board_width), Constraint::Min(24)]).areas(main);
+        let [eval, analysis, clocks, moves] = Layout::vertical([
+            Constraint::Length(3),
+            Constraint::Length(5),
+            Constraint::Length(4),
+            Constraint::Min(3),
+        ])
+        .areas(sidebar);
+
+        let title = format!(" Connect 4 · level {} ", self.game.level);
+        frame.render_widget(
+            Paragraph::new(self.board_lines()).block(Block::bordered().title(title)),
+            board,
+        );
+        frame.render_widget(self.eval_gauge(), eval);
+        frame.render_widget(
+            Paragraph::new(self.analysis_lines()).block(Block::bordered().title(" Analysis ")),
+            analysis,
+        );
+        frame.render_widget(
+            Paragraph::new(self.clock_lines()).block(Block::bordered().title(" Clocks ")),
+            clocks,
+        );
+        let list = move_list(self.game.moves());
+        let visible = moves.height.saturating_sub(2) as usize;
+        let lines: Vec<Line> = list
+            .iter()
+            .skip(list.len().saturating_sub(visible))
+            .map(|line| Line::raw(line.as_str()))
+            .collect();
+        frame.render_widget(
+            Paragraph::new(lines).block(Block::bordered().title(" Moves ")),
+            moves,
+        );
+
+        let status = if self.message.is_empty() {
+            self.status()
+        } else {
+            self.message.clone()
+        };
+        let keys = "←/→ 1-7 ⏎ move · u undo · h hint · +/- level · n new · q quit";
+        frame.render_widget(Paragraph::new(format!(" {status}  │  {keys}")), help);
+    }
+
+    fn status(&self) -> String {
+        if self.engine_thinking {
+            "The engine is thinking…".to_string()
+        } else {
+            self.game.status()
+        }
+    }
+
+    fn board_lines(&self) -> Vec<Line<'static>> {
+        let state = self.game.state();
+        let winning = state.winning_line().unwrap_or_default();
+        let last = self.game.moves().last().map(|mv| {
+            let row = (0..HEIGHT)
+                .rev()
+                .find(|&row| state.cell(mv.column, row).is_some())
+                .expect("the last move left a disc");
+            (mv.column, row)
+        });
+        let cursor = (0..WIDTH)
+            .map(|column| {
+                let marker = if column == self.cursor && !self.game.is_over() {
+                    " ▼ "
+                } else {
+                    "   "
+                };
+                Span::styled(marker, Style::new().fg(player_color(self.game.human)))
+            })
+            .collect::<Vec<_>>();
+        let mut lines = vec![Line::from(cursor)];
+        for row in (0..HEIGHT).rev() {
+            let cells = (0..WIDTH).map(|column| {
+                let (glyph, mut style) = match state.cell(column, row) {
+                    Some(player) => ("●", Style::new().fg(player_color(player))),
+                    None => ("·", Style::new().fg(Color::DarkGray)),
+                };
+                if winning.iter().any(|c| c.column == column && c.row == row) {
+                    style = style.add_modifier(Modifier::REVERSED);
+                }
+                if last == Some((column, row)) {
+                    style = style.add_modifier(Modifier::BOLD | Modifier::UNDERLINED);
+                }
+                Span::styled(format!(" {glyph} "), style)
+            });
+            lines.push(cells.collect());
+        }
+        let numbers: String = (1..=WIDTH).map(|column| format!(" {column} ")).collect();
+        lines.push(Line::styled(numbers, Style::new().fg(Color::Gray)));
+        lines
+    }
+
+    fn eval_gauge(&self) -> Gauge<'static> {
+        let score = self.analysis.score;
+        let label = match score {
+            _ if self.analysis.depth == 0 => "…".to_string(),
+            s if s >= FORCED_WIN => "Red wins".to_string(),
+            s if s <= -FORCED_WIN => "Blue wins".to_string(),
+            s => format!("{s:+}"),
+        };
+        Gauge::default()
+            .block(Block::bordered().title(" Evaluation (Red) "))
+            .gauge_style(Style::new().fg(RED).bg(BLUE))
+            .ratio(eval_ratio(score))
+            .label(label)
+    }
+
+    fn analysis_lines(&self) -> Vec<Line<'static>> {
+        let analysis = &self.analysis;
+        if self.game.is_over() {
+            return vec![Line::raw(self.game.status())];
+        }
+        let best = analysis
+            .best
+            .map_or("-".to_string(), |column| (column + 1).to_string());
+        let line: Vec<String> = analysis
+            .line
+            .iter()
+            .map(|column| (column + 1).to_string())
+            .collect();
+        vec![
+            Line::raw(format!(
+                "Depth {}/{} · {} nodes",
+                analysis.depth, self.game.level, analysis.nodes
+            )),
+            Line::raw(format!("Best: {best}")),
+            Line::raw(format!(
+                "Line: {}",
+                if line.is_empty() {
+                    "…".to_string()
+                } else {
+                    line.join(" ")
+                }
+            )),
+        ]
+    }
+
+    fn clock_lines(&self) -> Vec<Line<'static>> {
+        let running = self.game.game.running();
+        [Player::Red, Player::Blue]
+            .into_iter()
+            .map(|player| {
+                let used = self.game.game.thinking_time(player);
+                let who = if player == self.game.human {
+                    "you"
+                } else {
+                    "engine"
+                };
+                let marker = if running == Some(player) { "▶" } else { " " };
+                let secs = used.as_secs();
+                Line::styled(
+                    format!(
+                        "{marker} {:<4} {:<6} {:>2}:{:02}",
+                        crate::name(player),
+                        who,
+                        secs / 60,
+                        secs % 60
+                    ),
+                    Style::new().fg(player_color(player)),
+                )
+            })
+            .collect()
+    }
+}
+
+fn player_color(player: Player) -> Color {
+    match player {
+        Player::Red => RED,
+        Player::Blue => BLUE,
+    }
+}
+
+/// Share of the evaluation bar filled in Red: half for a level position,
+/// approaching the ends as the score grows, and full for a forced win.
+fn eval_ratio(score: i32) -> f64 {
+    if score.abs() >= FORCED_WIN {
+        return if score > 0 { 1.0 } else { 0.0 };
+    }
+    0.5 + 0.5 * (f64::from(score) / EVAL_SCALE).tanh()
+}
+
+/// Numbered move pairs with one-based columns, `1. 4 4`.
+fn move_list(moves: &[connect4::TypedMove]) -> Vec<String> {
+    moves
+        .chunks(2)
+        .enumerate()
+        .map(|(i, pair)| {
+            let columns: Vec<String> = pair.iter().map(|mv| (mv.column + 1).to_string()).collect();
+            format!("{:>2}. {}", i + 1, columns.join(" "))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use connect4::parse_history;
+
+    #[test]
+    fn sidebar_formats_scores_and_moves() {
+        assert_eq!(eval_ratio(0), 0.5);
+        assert!(eval_ratio(100) > 0.75 && eval_ratio(100) < 1.0);
+        assert_eq!(eval_ratio(-FORCED_WIN), 0.0);
+        assert_eq!(
+            move_list(&parse_history("R3B3R2").unwrap()),
+            vec![" 1. 4 4", " 2. 3"]
+        );
+    }
+}
@@ -0,0 +1,82 @@
+//! `connect4-cli tune`: fit the evaluation weights to a `dataset` file and
+//! write them where `tournament` entrants can load them.
+use std::{
+    fs::{self, File},
+    io::{BufRead, BufReader},
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use connect4::{
+    dataset::Sample,
+    tune::{tune, TuneConfig},
+    EvalWeights,
+};
+
+pub fn run(samples: PathBuf, passes: usize, out: Option<PathBuf>) -> anyhow::Result<()> {
+    let samples = read_samples(&samples)?;
+    let report = tune(
+        &samples,
+        &TuneConfig {
+            max_passes: passes,
+            ..TuneConfig::default()
+        },
+    )?;
+    eprintln!(
+        "{} positions, scale {:.5}: error {:.5} -> {:.5} after {} passes",
+        samples.len(),
+        report.scale,
+        report.start_error,
+        report.error,
+        report.passes
+    );
+    let json = serde_json::to_string_pretty(&report.weights)?;
+    match out {
+        Some(path) => {
+            fs::write(&path, json + "\n")
+                .with_context(|| format!("cannot write {}", path.display()))?;
+        }
+        None => println!("{json}"),
+    }
+    Ok(())
+}
+
+/// The JSON lines `connect4-cli dataset` writes.
+fn read_samples(path: &Path) -> anyhow::Result<Vec<Sample>> {
+    let file = File::open(path).with_context(|| format!("cannot open {}", path.display()))?;
+    BufReader::new(file)
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.as_ref().is_ok_and(|line| line.trim().is_empty()))
+        .map(|(number, line)| {
+            serde_json::from_str(&line?)
+                .with_context(|| format!("{}:{}: not a sample", path.display(), number + 1))
+        })
+        .collect()
+}
+
+/// Weights as `run` writes them.
+pub fn read_weights(path: &Path) -> anyhow::Result<EvalWeights> {
+    let text =
+        fs::read_to_string(path).with_context(|| format!("cannot read {}", path.display()))?;
+    serde_json::from_str(&text).with_context(|| format!("{}: not a weights file", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn weights_files_round_trip() {
+        let path = std::env::temp_dir().join(format!("c4-weights-{}.json", std::process::id()));
+        let weights = EvalWeights {
+            three: 64,
+            ..EvalWeights::default()
+        };
+        fs::write(&path, serde_json::to_string_pretty(&weights).unwrap()).unwrap();
+        assert_eq!(read_weights(&path).unwrap(), weights);
+        fs::write(&path, "{\"center\": 3}").unwrap();
+        assert!(read_weights(&path).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,72 @@
+/*
+ * C interface to the Connect 4 engine. Build the shared library with
+ *
+ *     cargo build -p connect4 --release --features ffi
+ *
+ * and link target/release/libconnect4.so (connect4.dll, libconnect4.dylib).
+ * See connect4/src/ffi.rs for the details of each function.
+ *
+ * Every function except c4_abi_version and c4_error_message returns C4_OK
+ * or a negative C4_ERR_* code, and writes its out-parameters only on
+ * success. Histories are NUL-terminated UTF-8 in the "R3B3" notation;
+ * columns are zero-based. Nothing is allocated for the caller.
+ */
+#ifndef CONNECT4_H
+#define CONNECT4_H
+
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define C4_ABI_VERSION 1
+
+#define C4_OK 0
+#define C4_ERR_NULL (-1)
+#define C4_ERR_UTF8 (-2)
+#define C4_ERR_PARSE (-3)
+#define C4_ERR_ILLEGAL_MOVE (-4)
+#define C4_ERR_LEVEL (-5)
+#define C4_ERR_GAME_OVER (-6)
+#define C4_ERR_INTERNAL (-7)
+
+#define C4_NO_SCORE INT32_MIN
+
+#define C4_IN_PROGRESS 0
+#define C4_WON 1
+#define C4_DRAW 2
+
+#define C4_NOBODY 0
+#define C4_RED 1
+#define C4_BLUE 2
+
+/* Compare with C4_ABI_VERSION to detect a mismatched library. */
+uint32_t c4_abi_version(void);
+
+/* Static English text for a status code; never free it. */
+const char *c4_error_message(int32_t code);
+
+/* The engine's column for the side to move, searched to level 1-15. */
+int32_t c4_best_move(const char *history, uint8_t level, int32_t *out_column);
+
+/* As c4_best_move, stopping after max_ms milliseconds or max_nodes nodes
+ * (0 for no limit). out_depth, if not NULL, receives the depth reached. */
+int32_t c4_best_move_limited(const char *history, uint8_t level, uint32_t max_ms,
+                             uint64_t max_nodes, int32_t *out_column,
+                             int32_t *out_depth);
+
+/* Scores of all seven columns for the side to move; C4_NO_SCORE marks a
+ * full column and +/-1000000 a forced win or loss. */
+int32_t c4_score_columns(const char *history, uint8_t level, int32_t out_scores[7]);
+
+/* C4_IN_PROGRESS/C4_WON/C4_DRAW, the winner and the side to move. Any
+ * out-parameter may be NULL. */
+int32_t c4_status(const char *history, int32_t *out_status, int32_t *out_winner,
+                  int32_t *out_to_move);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* CONNECT4_H */
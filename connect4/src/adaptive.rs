@@ -0,0 +1,183 @@
+//! Difficulty that follows the player over a series of games.
+//!
+//! After every game the player's rating is re-estimated from the result, as
+//! an Elo update against the engine's rating for the game
+//! (`Level::approximate_elo`, less what its randomness gave away), and the
+//! next game is played at the level nearest that estimate, which holds the
+//! player near an even score. The engine also mirrors the player's
+//! carelessness: the more of their moves the review calls blunders, the more
+//! `randomness` it plays with. Below level 1 the gap is made up with
+//! randomness too.
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    review::{GameReview, MoveClass},
+    stats::Score,
+    strength::ANCHOR_ELO,
+    Level, Player,
+};
+
+/// Elo points a unit of `randomness` is taken to cost the engine.
+const RANDOMNESS_ELO: f64 = 400.0;
+/// `randomness` per unit of blunder rate: a player who blunders every other
+/// move faces an engine that picks anything within 200 points of its best.
+const BLUNDER_RANDOMNESS: f64 = 2.0;
+/// Update factor of the first game, shrinking towards `MIN_K` as games
+/// accumulate so early results move the level quickly.
+const FIRST_K: f64 = 120.0;
+const MIN_K: f64 = 40.0;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Adaptive {
+    rating: f64,
+    /// The player's results.
+    score: Score,
+    moves: u32,
+    blunders: u32,
+}
+
+/// Where an adaptive series stands, for display.
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct AdaptiveStatus {
+    /// Estimated Elo of the player.
+    pub rating: f64,
+    /// The player's results so far.
+    pub score: Score,
+    /// Share of the player's graded moves that were blunders.
+    pub blunder_rate: f64,
+    /// What the engine plays the next game with.
+    pub level: Level,
+    pub randomness: f64,
+}
+
+impl Adaptive {
+    /// Start at `level`, assuming the player is as strong as it.
+    pub fn new(level: Level) -> Self {
+        Self {
+            rating: f64::from(level.approximate_elo()),
+            score: Score::default(),
+            moves: 0,
+            blunders: 0,
+        }
+    }
+
+    pub fn rating(&self) -> f64 {
+        self.rating
+    }
+
+    pub fn blunder_rate(&self) -> f64 {
+        match self.moves {
+            0 => 0.0,
+            moves => f64::from(self.blunders) / f64::from(moves),
+        }
+    }
+
+    /// Level for the next game: the one rated nearest the player.
+    pub fn level(&self) -> Level {
+        Level::for_elo(self.rating.max(0.0).round() as u32)
+    }
+
+    /// Weakening for the next game, 0 to 1 as in `SearchOptions`.
+    pub fn randomness(&self) -> f64 {
+        let below_level_1 = (f64::from(ANCHOR_ELO) - self.rating) / RANDOMNESS_ELO;
+        let careless = self.blunder_rate() * BLUNDER_RANDOMNESS;
+        below_level_1.max(careless).clamp(0.0, 1.0)
+    }
+
+    /// What the engine is taken to be rated at `level` and `randomness`.
+    fn engine_rating(&self) -> f64 {
+        f64::from(self.level().approximate_elo()) - self.randomness() * RANDOMNESS_ELO
+    }
+
+    /// Take in a finished game, played by the engine at this series's
+    /// `level` and `randomness` against `human`, with its review.
+    pub fn record(&mut self, human: Player, winner: Option<Player>, review: &GameReview) {
+        let gap = self.engine_rating() - self.rating;
+        let points = match winner {
+            Some(winner) if winner == human => {
+                self.score.wins += 1;
+                1.0
+            }
+            Some(_) => {
+                self.score.losses += 1;
+                0.0
+            }
+            None => {
+                self.score.draws += 1;
+                0.5
+            }
+        };
+        let k = (FIRST_K * 2.0 / f64::from(self.score.games() + 1)).max(MIN_K);
+        let expected = 1.0 / (1.0 + 10f64.powf(gap / 400.0));
+        self.rating += k * (points - expected);
+        let graded = review.moves.iter().filter(|m| m.player == human).count();
+        self.moves += graded as u32;
+        self.blunders += review.count(human, MoveClass::Blunder) as u32;
+    }
+
+    pub fn status(&self) -> AdaptiveStatus {
+        AdaptiveStatus {
+            rating: self.rating.round(),
+            score: self.score,
+            blunder_rate: self.blunder_rate(),
+            level: self.level(),
+            randomness: self.randomness(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse_history, review::review_game};
+
+    fn review(history: &str) -> GameReview {
+        review_game(&parse_history(history).unwrap(), 4).unwrap()
+    }
+
+    #[test]
+    fn winning_raises_the_level_and_losing_lowers_it() {
+        let start = Level::new(8).unwrap();
+        let quiet = review("R3B3");
+        let mut strong = Adaptive::new(start);
+        for _ in 0..5 {
+            strong.record(Player::Red, Some(Player::Red), &quiet);
+        }
+        assert!(strong.level() > start);
+        assert_eq!(strong.randomness(), 0.0);
+
+        let mut weak = Adaptive::new(start);
+        for _ in 0..60 {
+            weak.record(Player::Red, Some(Player::Blue), &quiet);
+        }
+        assert_eq!(weak.level().get(), 1);
+        assert!(weak.randomness() > 0.0);
+        assert_eq!(weak.status().score.losses, 60);
+
+        // Trading wins and losses keeps it close to where it started.
+        let mut even = Adaptive::new(start);
+        for game in 0..10 {
+            let winner = if game % 2 == 0 {
+                Player::Red
+            } else {
+                Player::Blue
+            };
+            even.record(Player::Red, Some(winner), &quiet);
+        }
+        assert!((even.rating() - f64::from(start.approximate_elo())).abs() < 120.0);
+    }
+
+    #[test]
+    fn blunders_weaken_the_engine() {
+        // Blue ignores Red's bottom-row threat.
+        let careless = review("R1B1R2B2R3B6R0");
+        let mut adaptive = Adaptive::new(Level::new(6).unwrap());
+        adaptive.record(Player::Blue, Some(Player::Red), &careless);
+        assert!(adaptive.blunder_rate() > 0.0);
+        assert!(adaptive.randomness() > 0.0);
+        assert_eq!(adaptive.status().blunder_rate, adaptive.blunder_rate());
+    }
+}
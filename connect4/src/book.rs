@@ -0,0 +1,464 @@
+//! Opening book: prepared moves for early positions, played without a search.
+//!
+//! A book is a text file with a header line and one entry per line:
+//!
+//! ```text
+//! c4book 1 <version>
+//! # comments and blank lines are skipped
+//! <position> <column> [weight] [eval]
+//! ```
+//!
+//! `position` is a move history in the usual `R3B3…` notation, `-` for the
+//! empty board; `column` is zero-based. A position may list several moves;
+//! `probe` plays the heaviest, `weight` defaulting to 1. `eval` is the score
+//! the book's author gave the move, from the side to move's point of view.
+//! Entries are filed under the canonical position, so a line also answers its
+//! mirror image.
+//!
+//! `set_entry` and `remove_entry` correct a book in place; writing it back
+//! with `Display` drops the original file's comments and layout.
+use std::{collections::HashMap, fmt, fs, io, path::Path, str::FromStr};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{fnv1a, parse_history, GameState, MOVE_ORDER, WIDTH};
+
+const MAGIC: &str = "c4book";
+const FORMAT_VERSION: u32 = 1;
+/// Written for the empty board, whose history is empty.
+const START: &str = "-";
+
+#[derive(Debug, Error)]
+pub enum BookError {
+    #[error("cannot read book file: {0}")]
+    Io(#[from] io::Error),
+    #[error("not a version {FORMAT_VERSION} book file")]
+    Header,
+    #[error("line {line}: {message}")]
+    Entry { line: usize, message: String },
+    /// An edit that would make the book invalid.
+    #[error("{0}")]
+    Invalid(String),
+}
+
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct BookEntry {
+    /// Move history, empty for the start position.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub position: String,
+    pub column: usize,
+    /// Relative preference among the position's moves; 1 when omitted.
+    #[cfg_attr(feature = "serde", serde(default = "default_weight"))]
+    pub weight: u32,
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub eval: Option<i32>,
+}
+
+#[cfg(feature = "serde")]
+fn default_weight() -> u32 {
+    1
+}
+
+/// A book move for a particular position.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct BookMove {
+    pub column: usize,
+    pub weight: u32,
+    pub eval: Option<i32>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OpeningBook {
+    version: String,
+    entries: Vec<BookEntry>,
+    /// By canonical position key: each move's column in canonical
+    /// orientation and its index in `entries`.
+    index: HashMap<u64, Vec<(usize, usize)>>,
+    hash: u64,
+}
+
+impl OpeningBook {
+    /// Read a book file, checking every entry.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, BookError> {
+        fs::read_to_string(path)?.parse()
+    }
+
+    /// From the header line.
+    pub fn version(&self) -> &str {
+        &self.version
+    }
+
+    /// FNV-1a over the file's text, to tell two books with one version apart.
+    /// After an edit, over the text `Display` writes.
+    pub fn hash(&self) -> u64 {
+        self.hash
+    }
+
+    pub fn entries(&self) -> &[BookEntry] {
+        &self.entries
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Distinct positions, mirror images counted once.
+    pub fn positions(&self) -> usize {
+        self.index.len()
+    }
+
+    /// Every book move for `state`, heaviest first, ties in search order.
+    pub fn moves(&self, state: &GameState) -> Vec<BookMove> {
+        let (key, mirrored) = state.canonical_key();
+        let mut moves: Vec<BookMove> = self
+            .index
+            .get(&key)
+            .into_iter()
+            .flatten()
+            .map(|&(column, i)| BookMove {
+                column: if mirrored { WIDTH - 1 - column } else { column },
+                weight: self.entries[i].weight,
+                eval: self.entries[i].eval,
+            })
+            .collect();
+        let rank = |column| MOVE_ORDER.iter().position(|&c| c == column);
+        moves.sort_by_key(|m| (std::cmp::Reverse(m.weight), rank(m.column)));
+        moves
+    }
+
+    /// The move to play in `state`, if the book has one.
+    pub fn probe(&self, state: &GameState) -> Option<BookMove> {
+        self.moves(state).into_iter().next()
+    }
+}
+
+impl FromStr for OpeningBook {
+    type Err = BookError;
+
+    fn from_str(text: &str) -> Result<Self, BookError> {
+        let mut lines = text.lines().enumerate();
+        let header = lines.next().map_or("", |(_, line)| line);
+        let version = match header.split_whitespace().collect::<Vec<_>>()[..] {
+            [MAGIC, format, version] if format.parse() == Ok(FORMAT_VERSION) => version,
+            _ => return Err(BookError::Header),
+        };
+        let mut book = Self {
+            version: version.to_string(),
+            entries: Vec::new(),
+            index: HashMap::new(),
+            hash: fnv1a(text.bytes()),
+        };
+        for (i, line) in lines {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let error = |message: String| BookError::Entry {
+                line: i + 1,
+                message,
+            };
+            let entry = parse_entry(line).map_err(error)?;
+            book.insert(entry).map_err(error)?;
+        }
+        Ok(book)
+    }
+}
+
+impl OpeningBook {
+    /// Add `entry`, or replace the weight and eval of the same move already
+    /// in the book, directly or mirrored. Returns the entry replaced.
+    pub fn set_entry(&mut self, entry: BookEntry) -> Result<Option<BookEntry>, BookError> {
+        if entry.weight == 0 {
+            return Err(BookError::Invalid("weight must be positive".to_string()));
+        }
+        let (key, column) = slot(&entry.position, entry.column).map_err(BookError::Invalid)?;
+        let replaced = match self.find(key, column) {
+            Some(i) => Some(std::mem::replace(&mut self.entries[i], entry)),
+            None => {
+                self.index
+                    .entry(key)
+                    .or_default()
+                    .push((column, self.entries.len()));
+                self.entries.push(entry);
+                None
+            }
+        };
+        self.hash = fnv1a(self.to_string().bytes());
+        Ok(replaced)
+    }
+
+    /// Take `column` out of `position`'s moves, mirrors included. Returns the
+    /// entry removed, `None` if the book had no such move.
+    pub fn remove_entry(
+        &mut self,
+        position: &str,
+        column: usize,
+    ) -> Result<Option<BookEntry>, BookError> {
+        let (key, column) = slot(position, column).map_err(BookError::Invalid)?;
+        let Some(i) = self.find(key, column) else {
+            return Ok(None);
+        };
+        let removed = self.entries.remove(i);
+        for moves in self.index.values_mut() {
+            moves.retain(|&(_, entry)| entry != i);
+            for (_, entry) in moves.iter_mut() {
+                if *entry > i {
+                    *entry -= 1;
+                }
+            }
+        }
+        self.index.retain(|_, moves| !moves.is_empty());
+        self.hash = fnv1a(self.to_string().bytes());
+        Ok(Some(removed))
+    }
+
+    /// Index in `entries` of the move filed as `column` under `key`.
+    fn find(&self, key: u64, column: usize) -> Option<usize> {
+        self.index
+            .get(&key)?
+            .iter()
+            .find(|&&(filed, _)| filed == column)
+            .map(|&(_, i)| i)
+    }
+
+    /// File a new entry read from a book file; a move already in the book,
+    /// directly or mirrored, is refused.
+    fn insert(&mut self, entry: BookEntry) -> Result<(), String> {
+        let (key, column) = slot(&entry.position, entry.column)?;
+        if self.find(key, column).is_some() {
+            return Err(format!(
+                "column {} at {:?} is already in the book",
+                entry.column, entry.position
+            ));
+        }
+        self.index
+            .entry(key)
+            .or_default()
+            .push((column, self.entries.len()));
+        self.entries.push(entry);
+        Ok(())
+    }
+}
+
+/// The canonical key of `position` and `column` in canonical orientation,
+/// once both are checked: a legal, unfinished position and a playable column.
+fn slot(position: &str, column: usize) -> Result<(u64, usize), String> {
+    let state = parse_history(position)
+        .and_then(|moves| GameState::from_history(&moves))
+        .map_err(|err| format!("illegal position {position:?}: {err}"))?;
+    if state.winner().is_some() || state.legal_moves().is_empty() {
+        return Err(format!("the game is over at {position:?}"));
+    }
+    if !state.legal_moves().contains(&column) {
+        return Err(format!("column {column} is not playable at {position:?}"));
+    }
+    let (key, mirrored) = state.canonical_key();
+    Ok((key, if mirrored { WIDTH - 1 - column } else { column }))
+}
+
+impl fmt::Display for OpeningBook {
+    /// The book in its file format, one entry per line in book order.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{MAGIC} {FORMAT_VERSION} {}", self.version)?;
+        for entry in &self.entries {
+            let position = if entry.position.is_empty() {
+                START
+            } else {
+                &entry.position
+            };
+            write!(f, "{position} {} {}", entry.column, entry.weight)?;
+            if let Some(eval) = entry.eval {
+                write!(f, " {eval}")?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+fn parse_entry(line: &str) -> Result<BookEntry, String> {
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    let number = |i: usize, name: &str| -> Result<Option<i64>, String> {
+        fields
+            .get(i)
+            .map(|field| {
+                field
+                    .parse()
+                    .map_err(|_| format!("invalid {name} {field:?}"))
+            })
+            .transpose()
+    };
+    if !(2..=4).contains(&fields.len()) {
+        return Err("expected `<position> <column> [weight] [eval]`".to_string());
+    }
+    let position = match fields[0] {
+        START => String::new(),
+        position => position.to_string(),
+    };
+    let column = number(1, "column")?
+        .and_then(|column| usize::try_from(column).ok())
+        .filter(|&column| column < WIDTH)
+        .ok_or_else(|| format!("column must be 0 to {}", WIDTH - 1))?;
+    let weight = match number(2, "weight")? {
+        None => 1,
+        Some(weight) => u32::try_from(weight)
+            .ok()
+            .filter(|&weight| weight > 0)
+            .ok_or_else(|| format!("weight must be positive, got {weight}"))?,
+    };
+    let eval = number(3, "eval")?
+        .map(|eval| i32::try_from(eval).map_err(|_| format!("eval {eval} is out of range")))
+        .transpose()?;
+    Ok(BookEntry {
+        position,
+        column,
+        weight,
+        eval,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BOOK: &str = "c4book 1 2026.10
+# centre openings
+- 3 100 0
+R3 3 60 -5
+R3 2 40
+R2 2
+
+R3B3 4 1 12
+";
+
+    fn state(history: &str) -> GameState {
+        GameState::from_history(&parse_history(history).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn probes_play_the_heaviest_move_and_answer_mirrors() {
+        let book: OpeningBook = BOOK.parse().unwrap();
+        assert_eq!(
+            (book.version(), book.len(), book.positions()),
+            ("2026.10", 5, 4)
+        );
+        assert_eq!(book.hash(), fnv1a(BOOK.bytes()));
+        assert_eq!(
+            book.probe(&state("")),
+            Some(BookMove {
+                column: 3,
+                weight: 100,
+                eval: Some(0),
+            })
+        );
+        let columns: Vec<usize> = book.moves(&state("R3")).iter().map(|m| m.column).collect();
+        assert_eq!(columns, [3, 2]);
+        // `R4` is the mirror of `R2`; `R3B3` answers as its own mirror.
+        assert_eq!(book.probe(&state("R4")).map(|m| m.column), Some(4));
+        assert_eq!(book.probe(&state("R3B3")).unwrap().eval, Some(12));
+        assert_eq!(book.probe(&state("R0")), None);
+    }
+
+    #[test]
+    fn bad_books_name_the_offending_line() {
+        assert!(matches!(
+            "c4book 2 x\n".parse::<OpeningBook>(),
+            Err(BookError::Header)
+        ));
+        for (entry, line, message) in [
+            ("R3 7", 2, "column must be"),
+            ("R3B9 3", 2, "illegal position"),
+            ("R3 3 0", 2, "weight must be positive"),
+            ("- 3\nR3 3 1 1 1", 3, "expected"),
+            ("R2 2\nR4 4", 3, "already in the book"),
+            ("R0B1R0B1R0B1R0 3", 2, "the game is over"),
+        ] {
+            let err = format!("c4book 1 v\n{entry}\n")
+                .parse::<OpeningBook>()
+                .unwrap_err();
+            let text = err.to_string();
+            assert!(
+                text.starts_with(&format!("line {line}: ")) && text.contains(message),
+                "{entry:?}: {text}"
+            );
+        }
+        let full: String = "R0B0R0B0R0B0".to_string();
+        let err = format!("c4book 1 v\n{full} 0\n")
+            .parse::<OpeningBook>()
+            .unwrap_err();
+        assert!(err.to_string().contains("not playable"), "{err}");
+    }
+
+    #[test]
+    fn edits_override_and_remove_moves_and_round_trip() {
+        let mut book: OpeningBook = BOOK.parse().unwrap();
+        // `R4 4` is `R2 2` mirrored, so it replaces that entry.
+        let corrected = BookEntry {
+            position: "R4".to_string(),
+            column: 4,
+            weight: 3,
+            eval: Some(-20),
+        };
+        let replaced = book.set_entry(corrected.clone()).unwrap().unwrap();
+        assert_eq!((replaced.position.as_str(), replaced.weight), ("R2", 1));
+        assert_eq!(book.len(), 5);
+        assert_eq!(book.probe(&state("R2")).unwrap().eval, Some(-20));
+
+        let added = BookEntry {
+            position: "R3".to_string(),
+            column: 4,
+            weight: 80,
+            eval: None,
+        };
+        assert_eq!(book.set_entry(added).unwrap(), None);
+        assert_eq!(book.probe(&state("R3")).map(|m| m.column), Some(4));
+
+        let removed = book.remove_entry("", 3).unwrap().unwrap();
+        assert_eq!(removed.weight, 100);
+        assert_eq!(book.probe(&state("")), None);
+        assert_eq!(book.remove_entry("", 3).unwrap(), None);
+        assert_eq!((book.len(), book.positions()), (5, 3));
+        assert_eq!(book.probe(&state("R3B3")).unwrap().eval, Some(12));
+
+        assert!(matches!(
+            book.set_entry(BookEntry {
+                column: 3,
+                ..corrected.clone()
+            }),
+            Ok(None)
+        ));
+        for bad in [
+            BookEntry {
+                weight: 0,
+                ..corrected.clone()
+            },
+            BookEntry {
+                column: 7,
+                ..corrected.clone()
+            },
+            BookEntry {
+                position: "R9".to_string(),
+                ..corrected
+            },
+        ] {
+            assert!(matches!(book.set_entry(bad), Err(BookError::Invalid(_))));
+        }
+
+        let text = book.to_string();
+        assert!(text.starts_with("c4book 1 2026.10\n"));
+        let reread: OpeningBook = text.parse().unwrap();
+        assert_eq!(reread.entries(), book.entries());
+        assert_eq!(reread.hash(), book.hash());
+    }
+}
@@ -0,0 +1,148 @@
+//! Labelled positions from self-play, the training input for a learned
+//! evaluator.
+//!
+//! Every position an engine searched, and every random opening position, is
+//! labelled with how the game ended and what the search thought of it. The
+//! cells are laid out flat, bottom row first, so a column of samples loads as
+//! an `n × 6 × 7` array without reshuffling.
+use std::collections::HashSet;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    notation::to_grid, parse_history, selfplay_games, GameError, GameState, Player, SelfPlayConfig,
+    HEIGHT, WIDTH,
+};
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct DatasetConfig {
+    pub selfplay: SelfPlayConfig,
+    /// Keep only the first sample of a position or its mirror image.
+    pub dedup: bool,
+}
+
+impl DatasetConfig {
+    pub fn new(selfplay: SelfPlayConfig) -> Self {
+        Self {
+            selfplay,
+            dedup: true,
+        }
+    }
+}
+
+/// One labelled position, in canonical orientation: mirrored when its mirror
+/// has the smaller key.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Sample {
+    /// `GameState::canonical_key`, identical for a position and its mirror.
+    pub key: u64,
+    /// `WIDTH * HEIGHT` cells, row by row from the bottom: 1 for the side to
+    /// move, -1 for the opponent, 0 for empty.
+    pub cells: Vec<i8>,
+    pub to_move: Player,
+    /// How the game ended for the side to move: 1 won, 0 drawn, -1 lost.
+    pub result: i8,
+    /// The engine's score from the side to move's point of view, as in
+    /// `ScoredMove`; `None` for random opening moves.
+    pub score: Option<i32>,
+}
+
+/// Play `config.selfplay` and label the positions of every game.
+pub fn generate(config: &DatasetConfig) -> Result<Vec<Sample>, GameError> {
+    let mut seen = HashSet::new();
+    let mut samples = Vec::new();
+    for game in selfplay_games(&config.selfplay) {
+        let game = game?;
+        let mut state = GameState::empty(Player::Red);
+        for (mv, score) in parse_history(&game.history)?.into_iter().zip(game.evals) {
+            let sample = sample(&state, game.winner, score);
+            if !config.dedup || seen.insert(sample.key) {
+                samples.push(sample);
+            }
+            state.play(mv.column)?;
+        }
+    }
+    Ok(samples)
+}
+
+pub(crate) fn sample(state: &GameState, winner: Option<Player>, score: Option<i32>) -> Sample {
+    let (key, mirrored) = state.canonical_key();
+    let player = state.to_move();
+    let grid = to_grid(state);
+    let cells = (0..HEIGHT)
+        .flat_map(|row| (0..WIDTH).map(move |column| (row, column)))
+        .map(|(row, column)| {
+            let column = if mirrored { WIDTH - 1 - column } else { column };
+            match grid[row][column] {
+                None => 0,
+                Some(disc) if disc == player => 1,
+                Some(_) => -1,
+            }
+        })
+        .collect();
+    Sample {
+        key,
+        cells,
+        to_move: player,
+        result: match winner {
+            None => 0,
+            Some(winner) if winner == player => 1,
+            Some(_) => -1,
+        },
+        score,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SearchOptions;
+
+    #[test]
+    fn samples_label_positions_for_the_side_to_move() {
+        let selfplay = SelfPlayConfig {
+            games: 4,
+            seed: Some(7),
+            ..SelfPlayConfig::new(SearchOptions::new(3), SearchOptions::new(3))
+        };
+        let mut config = DatasetConfig::new(selfplay);
+        let samples = generate(&config).unwrap();
+        let keys: HashSet<_> = samples.iter().map(|sample| sample.key).collect();
+        assert_eq!(keys.len(), samples.len());
+        // Every game opens on the empty board; it is kept once.
+        assert_eq!(samples[0].cells, vec![0; WIDTH * HEIGHT]);
+        for sample in &samples {
+            let discs = sample.cells.iter().filter(|&&cell| cell != 0).count();
+            let mine = sample.cells.iter().filter(|&&cell| cell == 1).count();
+            // Red opens, so the side to move has as many discs as the
+            // opponent when it is Red, one fewer when it is Blue.
+            let expected = match sample.to_move {
+                Player::Red => discs / 2,
+                Player::Blue => (discs - 1) / 2,
+            };
+            assert_eq!(mine, expected);
+            assert!((-1..=1).contains(&sample.result));
+        }
+
+        config.dedup = false;
+        let all = generate(&config).unwrap();
+        assert!(all.len() > samples.len());
+        assert_eq!(
+            all.iter()
+                .filter(|sample| sample.key == samples[0].key)
+                .count(),
+            4
+        );
+    }
+
+    #[test]
+    fn mirror_images_share_a_sample() {
+        let state = GameState::from_history(&parse_history("R0B3").unwrap()).unwrap();
+        let mirror = GameState::from_history(&parse_history("R6B3").unwrap()).unwrap();
+        assert_eq!(sample(&state, None, None), sample(&mirror, None, None));
+        let won = sample(&state, Some(Player::Blue), Some(5));
+        assert_eq!((won.result, won.score), (-1, Some(5)));
+    }
+}
@@ -0,0 +1,424 @@
+//! The hand-tuned evaluation: what the search scores a position it does not
+//! search further, and the term by term breakdown and heatmap that explain
+//! it to players.
+use connect4_core::{has_won, WIN_MASKS};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    bit_for, fnv1a, winning_cells, GameState, Player, BOARD_MASK, COL_HEIGHT, HEIGHT, WIDTH,
+    WIN_SCORE,
+};
+
+// Heuristic weights shared by `evaluate` and `evaluate_explain` so the breakdown
+// always sums to what the search actually sees.
+pub(crate) const CENTER_WEIGHT: i32 = 3;
+pub(crate) const ONE_WEIGHT: i32 = 2;
+pub(crate) const TWO_WEIGHT: i32 = 10;
+pub(crate) const THREE_WEIGHT: i32 = 50;
+// The threat terms are off until a tuner or a tournament shows what they are
+// worth, so the default evaluation and its fingerprint are unchanged.
+const MOBILITY_WEIGHT: i32 = 0;
+const BURIED_WEIGHT: i32 = 0;
+// Percent of the opponent's score counted against the side: symmetric.
+const DEFENSE_WEIGHT: i32 = 100;
+
+/// Weights of the heuristic evaluation, for comparing variants in
+/// `tournament`s. The fixed-level API always uses the defaults.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct EvalWeights {
+    /// Per disc in the centre column.
+    pub center: i32,
+    /// Per open line of four holding one, two or three of the side's discs.
+    pub one: i32,
+    pub two: i32,
+    pub three: i32,
+    /// Per playable square that would give the side a new threat.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub mobility: i32,
+    /// Per threat above one of the opponent's in the same column, which can
+    /// never be realised; negative to discount them. With both threat weights
+    /// at 0, the default, the evaluation skips finding threats altogether.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub buried: i32,
+    /// Percent of the opponent's score counted against the side. Below 100
+    /// the engine prefers building its own lines to blocking the opponent's,
+    /// above 100 the reverse. The search applies it from the side to move at
+    /// the root, so the style is the engine's whoever moves at a leaf.
+    #[cfg_attr(feature = "serde", serde(default = "default_defense"))]
+    pub defense: i32,
+}
+
+#[cfg(feature = "serde")]
+fn default_defense() -> i32 {
+    DEFENSE_WEIGHT
+}
+
+impl Default for EvalWeights {
+    fn default() -> Self {
+        Self {
+            center: CENTER_WEIGHT,
+            one: ONE_WEIGHT,
+            two: TWO_WEIGHT,
+            three: THREE_WEIGHT,
+            mobility: MOBILITY_WEIGHT,
+            buried: BURIED_WEIGHT,
+            defense: DEFENSE_WEIGHT,
+        }
+    }
+}
+
+impl EvalWeights {
+    /// FNV-1a over the weights: unlike `Hash`, the same in every build and
+    /// on every platform, so it can be stored and compared. The threat and
+    /// defense weights count only when changed, so weights from before they
+    /// existed keep their fingerprint.
+    pub fn fingerprint(&self) -> u64 {
+        let extra = [self.mobility, self.buried, self.defense];
+        let threats = if extra == [MOBILITY_WEIGHT, BURIED_WEIGHT, DEFENSE_WEIGHT] {
+            &[][..]
+        } else {
+            &extra[..]
+        };
+        fnv1a(
+            [self.center, self.one, self.two, self.three]
+                .iter()
+                .chain(threats)
+                .flat_map(|weight| weight.to_le_bytes()),
+        )
+    }
+}
+
+pub(crate) fn evaluate(state: &GameState, player: Player) -> i32 {
+    evaluate_weighted(state, player, &EvalWeights::default())
+}
+
+pub(crate) fn evaluate_weighted(state: &GameState, player: Player, weights: &EvalWeights) -> i32 {
+    let mine = state.bits(player);
+    let theirs = state.bits(player.opponent());
+    if has_won(mine) {
+        return WIN_SCORE;
+    }
+    if has_won(theirs) {
+        return -WIN_SCORE;
+    }
+
+    let center_bits = center_mask();
+    let mut my_score = weights.center * (mine & center_bits).count_ones() as i32;
+    let mut their_score = weights.center * (theirs & center_bits).count_ones() as i32;
+    for mask in WIN_MASKS.iter() {
+        let mine_count = (mine & mask).count_ones();
+        let theirs_count = (theirs & mask).count_ones();
+        if mine_count > 0 && theirs_count > 0 {
+            continue; // blocked line
+        }
+        match (mine_count, theirs_count) {
+            (3, 0) => my_score += weights.three,
+            (2, 0) => my_score += weights.two,
+            (1, 0) => my_score += weights.one,
+            (0, 3) => their_score += weights.three,
+            (0, 2) => their_score += weights.two,
+            (0, 1) => their_score += weights.one,
+            _ => {}
+        }
+    }
+    if (weights.mobility, weights.buried) != (0, 0) {
+        let (my_mobility, my_buried) = threat_counts(state, player);
+        let (their_mobility, their_buried) = threat_counts(state, player.opponent());
+        my_score += weights.mobility * my_mobility as i32 + weights.buried * my_buried as i32;
+        their_score +=
+            weights.mobility * their_mobility as i32 + weights.buried * their_buried as i32;
+    }
+    my_score - their_score * weights.defense / DEFENSE_WEIGHT
+}
+
+/// Named styles for the engine, roughly equal in strength, that differ in
+/// how much it values making threats against stopping the opponent's.
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum EvalPreset {
+    /// Builds threats and squares that make them, and blocks late.
+    Aggressive,
+    /// The default weights.
+    #[default]
+    Balanced,
+    /// Takes the opponent's lines more seriously than its own, and discounts
+    /// its threats that can never come true.
+    Defensive,
+}
+
+impl EvalPreset {
+    pub const ALL: [EvalPreset; 3] = [
+        EvalPreset::Aggressive,
+        EvalPreset::Balanced,
+        EvalPreset::Defensive,
+    ];
+
+    pub fn weights(self) -> EvalWeights {
+        match self {
+            EvalPreset::Aggressive => EvalWeights {
+                mobility: 4,
+                defense: 75,
+                ..EvalWeights::default()
+            },
+            EvalPreset::Balanced => EvalWeights::default(),
+            EvalPreset::Defensive => EvalWeights {
+                buried: -10,
+                defense: 130,
+                ..EvalWeights::default()
+            },
+        }
+    }
+}
+
+/// Empty cells where `bits` would complete four.
+fn threat_cells(bits: u64, occupied: u64) -> u64 {
+    winning_cells(bits) & BOARD_MASK & !occupied
+}
+
+/// What the line counts miss about `player`'s threats: how many playable
+/// squares would create a new one, and how many existing ones sit above an
+/// opponent threat in the same column. Filling that column means someone
+/// plays the opponent's cell first, so the threat above it never comes true.
+fn threat_counts(state: &GameState, player: Player) -> (u32, u32) {
+    let mine = state.bits(player);
+    let occupied = mine | state.bits(player.opponent());
+    let threats = threat_cells(mine, occupied);
+
+    let mobility = (0..WIDTH)
+        .filter(|&column| usize::from(state.heights[column]) < HEIGHT)
+        .filter(|&column| {
+            let disc = bit_for(column, usize::from(state.heights[column]));
+            threat_cells(mine | disc, occupied | disc) & !threats != 0
+        })
+        .count() as u32;
+
+    // Shift one row at a time so nothing spills past the top of a column.
+    let mut shadow = threat_cells(state.bits(player.opponent()), occupied);
+    let mut above = 0;
+    for _ in 1..HEIGHT {
+        shadow = (shadow << 1) & BOARD_MASK;
+        above |= shadow;
+    }
+    (mobility, (threats & above).count_ones())
+}
+
+/// One heuristic feature: how often it occurs and what it adds to the score.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct EvalTerm {
+    pub count: u32,
+    pub score: i32,
+}
+
+impl EvalTerm {
+    fn weighted(count: u32, weight: i32) -> Self {
+        Self {
+            count,
+            score: count as i32 * weight,
+        }
+    }
+}
+
+/// Contributions of a single player. `parity` and `forks` are diagnostic only:
+/// the search does not weight them yet, so their `score` is always zero, but
+/// tuners can see them before deciding whether they deserve a weight.
+/// `mobility` and `buried` score zero too unless their default weights change;
+/// `EvalWeights` can weight them per search.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct PlayerEval {
+    /// Discs in the center column.
+    pub center: EvalTerm,
+    /// Open lines holding a single disc.
+    pub ones: EvalTerm,
+    /// Open lines holding two discs.
+    pub twos: EvalTerm,
+    /// Open lines holding three discs, i.e. threats.
+    pub threes: EvalTerm,
+    /// Threat cells on the rows this player wins the zugzwang race on
+    /// (odd rows for the first player, even rows for the second).
+    pub parity: EvalTerm,
+    /// Columns where this player owns two vertically stacked threat cells.
+    pub forks: EvalTerm,
+    /// Playable squares that would create a new threat.
+    pub mobility: EvalTerm,
+    /// Threats above an opponent threat in the same column, which can never
+    /// be realised.
+    pub buried: EvalTerm,
+}
+
+impl PlayerEval {
+    pub fn score(&self) -> i32 {
+        [
+            self.center,
+            self.ones,
+            self.twos,
+            self.threes,
+            self.parity,
+            self.forks,
+            self.mobility,
+            self.buried,
+        ]
+        .iter()
+        .map(|term| term.score)
+        .sum()
+    }
+}
+
+/// Static evaluation split into per-player terms. `total` is from the point of
+/// view of `to_move` and equals what the search's leaf evaluation returns.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct EvalBreakdown {
+    pub to_move: Player,
+    pub red: PlayerEval,
+    pub blue: PlayerEval,
+    pub winner: Option<Player>,
+    pub total: i32,
+}
+
+impl EvalBreakdown {
+    pub fn player(&self, player: Player) -> &PlayerEval {
+        match player {
+            Player::Red => &self.red,
+            Player::Blue => &self.blue,
+        }
+    }
+}
+
+/// Explain the static evaluation of `state` term by term instead of as a single
+/// scalar, so users can see why the engine prefers one position over another.
+pub fn evaluate_explain(state: &GameState) -> EvalBreakdown {
+    let to_move = state.to_move;
+    let red = player_eval(state, Player::Red);
+    let blue = player_eval(state, Player::Blue);
+    let winner = [Player::Red, Player::Blue]
+        .into_iter()
+        .find(|&p| has_won(state.bits(p)));
+    let total = match winner {
+        Some(p) if p == to_move => WIN_SCORE,
+        Some(_) => -WIN_SCORE,
+        None => {
+            let (mine, theirs) = match to_move {
+                Player::Red => (&red, &blue),
+                Player::Blue => (&blue, &red),
+            };
+            mine.score() - theirs.score()
+        }
+    };
+    EvalBreakdown {
+        to_move,
+        red,
+        blue,
+        winner,
+        total,
+    }
+}
+
+/// How much occupying one empty cell would swing the evaluation for the side
+/// to move. `playable` marks the cell a disc dropped in that column lands on.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct HeatCell {
+    pub column: usize,
+    pub row: usize,
+    pub score: i32,
+    pub playable: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Heatmap {
+    pub to_move: Player,
+    /// Empty cells in column-major order, bottom row first.
+    pub cells: Vec<HeatCell>,
+}
+
+impl Heatmap {
+    pub fn get(&self, column: usize, row: usize) -> Option<&HeatCell> {
+        self.cells
+            .iter()
+            .find(|cell| cell.column == column && cell.row == row)
+    }
+}
+
+/// Score every empty cell by the evaluation swing of the side to move owning
+/// it. Cells above the landing square are scored as if gravity did not apply,
+/// which is what makes the map useful for spotting future threats rather than
+/// only the next move.
+pub fn heatmap(state: &GameState) -> Heatmap {
+    let player = state.to_move;
+    let base = evaluate(state, player);
+    let mut cells = Vec::new();
+    for column in 0..WIDTH {
+        let landing = state.heights[column] as usize;
+        for row in landing..HEIGHT {
+            let mut probe = state.clone();
+            probe.players[player.idx()] |= bit_for(column, row);
+            cells.push(HeatCell {
+                column,
+                row,
+                score: evaluate(&probe, player) - base,
+                playable: row == landing,
+            });
+        }
+    }
+    Heatmap {
+        to_move: player,
+        cells,
+    }
+}
+
+pub(crate) fn player_eval(state: &GameState, player: Player) -> PlayerEval {
+    let mine = state.bits(player);
+    let theirs = state.bits(player.opponent());
+    let mut counts = [0u32; 4];
+    let mut threat_cells = 0u64;
+    for mask in WIN_MASKS.iter() {
+        if theirs & mask != 0 {
+            continue;
+        }
+        let n = (mine & mask).count_ones() as usize;
+        if n < 4 {
+            counts[n] += 1;
+        }
+        if n == 3 {
+            threat_cells |= mask & !mine;
+        }
+    }
+
+    // Zugzwang favours the first player on odd rows (1-based) and the second
+    // player on even rows, so only threats on "own" rows tend to be realised.
+    let first_rows = state.first_player() == player;
+    let parity_rows = (0..HEIGHT)
+        .filter(|row| (row % 2 == 0) == first_rows)
+        .flat_map(|row| (0..WIDTH).map(move |col| bit_for(col, row)))
+        .fold(0u64, |acc, bit| acc | bit);
+    // Two threats on top of each other cannot both be stopped by one disc.
+    let stacked = threat_cells & (threat_cells >> 1);
+    let (mobility, buried) = threat_counts(state, player);
+
+    PlayerEval {
+        center: EvalTerm::weighted((mine & center_mask()).count_ones(), CENTER_WEIGHT),
+        ones: EvalTerm::weighted(counts[1], ONE_WEIGHT),
+        twos: EvalTerm::weighted(counts[2], TWO_WEIGHT),
+        threes: EvalTerm::weighted(counts[3], THREE_WEIGHT),
+        parity: EvalTerm::weighted((threat_cells & parity_rows).count_ones(), 0),
+        forks: EvalTerm::weighted(stacked.count_ones(), 0),
+        mobility: EvalTerm::weighted(mobility, MOBILITY_WEIGHT),
+        buried: EvalTerm::weighted(buried, BURIED_WEIGHT),
+    }
+}
+
+pub(crate) fn center_mask() -> u64 {
+    let mut mask = 0;
+    let col = WIDTH / 2;
+    for row in 0..HEIGHT {
+        mask |= 1u64 << (col * COL_HEIGHT + row);
+    }
+    mask
+}
@@ -0,0 +1,437 @@
+//! Teaching mode: what a move does, as structured [`Reason`]s and as short
+//! sentences built from them.
+//!
+//! The reasons come from the board itself (lines of four completed, blocked
+//! or set up, wins handed to the opponent) and from the evaluation terms of
+//! `evaluate_explain`; a search score, when given, adds whether the move
+//! forces the result. The sentence templates live here so every client says
+//! the same thing. Columns in sentences count from 1, as players do; the
+//! structured reasons keep the API's zero-based columns.
+//!
+//! [`preview_moves`] is the cheap cousin for warning beginners before they
+//! commit: a few flags per column from one ply of lookahead, no search.
+use std::fmt;
+
+use connect4_core::WIN_MASKS;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    analyze, bit_for, eval::player_eval, winning_cells, Analysis, GameError, GameState, GameStatus,
+    MoveRequest, Player, ScoredMove, BOARD_MASK, COL_HEIGHT, HEIGHT, WIDTH, WIN_SCORE,
+};
+
+/// Scores at least this far from zero are forced results, as in `review`.
+const DECISIVE: i32 = WIN_SCORE / 2;
+
+/// How a line of four runs across the board.
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "direction", rename_all = "snake_case"))]
+pub enum Line {
+    /// Along `row`, 0 at the bottom.
+    Horizontal {
+        row: usize,
+    },
+    Vertical {
+        column: usize,
+    },
+    Diagonal,
+}
+
+/// One thing a move does. Listed most important first by [`reasons`].
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "kind", rename_all = "snake_case"))]
+pub enum Reason {
+    /// Completes four in a row.
+    Wins,
+    /// The search proves a win from here.
+    ForcesWin,
+    /// Takes the cell where `opponent` would have completed `line`.
+    Blocks { opponent: Player, line: Line },
+    /// Leaves two cells the opponent can play into, each completing four.
+    DoubleThreat,
+    /// New cells where the mover would complete four, by column.
+    CreatesThreat { columns: Vec<usize> },
+    /// Lines with two of the mover's discs and room for four, gained.
+    BuildsTwos { count: u32 },
+    /// A disc in the centre column, which lies on the most lines.
+    TakesCenter,
+    /// `opponent` could already win at `column`, and still can.
+    IgnoresWin { opponent: Player, column: usize },
+    /// Fills the cell under one where `opponent` completes four.
+    AllowsWin { opponent: Player, column: usize },
+    /// The search proves a loss from here.
+    ForcesLoss,
+}
+
+/// A move's reasons and the sentence they make.
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct MoveExplanation {
+    pub column: usize,
+    pub reasons: Vec<Reason>,
+    /// For example "Blocks Blue's three in a row on the bottom row; creates
+    /// a threat in column 5."
+    pub text: String,
+}
+
+/// What playing `column` does for the side to move in `state`. `score` is
+/// the move's search score from the mover's side, if there is one.
+pub fn reasons(
+    state: &GameState,
+    column: usize,
+    score: Option<i32>,
+) -> Result<Vec<Reason>, GameError> {
+    let mover = state.to_move();
+    let opponent = mover.opponent();
+    let mut after = state.clone();
+    let won = after.play(column)?.won;
+    let row = usize::from(after.heights[column]) - 1;
+    let cell = bit_for(column, row);
+    let mut reasons = Vec::new();
+    if won {
+        reasons.push(Reason::Wins);
+        return Ok(reasons);
+    }
+    if score.is_some_and(|score| score >= DECISIVE) {
+        reasons.push(Reason::ForcesWin);
+    }
+    if let Some(line) = completed_line(state.bits(opponent) | cell, cell) {
+        reasons.push(Reason::Blocks { opponent, line });
+    }
+    let playable = playable_cells(&after);
+    let threats = open_threats(&after, mover);
+    if (threats & playable).count_ones() >= 2 {
+        reasons.push(Reason::DoubleThreat);
+    }
+    let new = threats & !open_threats(state, mover);
+    if new != 0 {
+        let columns = (0..WIDTH).filter(|&c| new & column_cells(c) != 0).collect();
+        reasons.push(Reason::CreatesThreat { columns });
+    }
+    let twos = player_eval(&after, mover).twos.count;
+    let before = player_eval(state, mover).twos.count;
+    if twos > before {
+        reasons.push(Reason::BuildsTwos {
+            count: twos - before,
+        });
+    }
+    if column == WIDTH / 2 {
+        reasons.push(Reason::TakesCenter);
+    }
+    let their_wins = open_threats(&after, opponent) & playable;
+    if let Some(other) = (0..WIDTH).find(|&c| their_wins & column_cells(c) != 0 && c != column) {
+        reasons.push(Reason::IgnoresWin {
+            opponent,
+            column: other,
+        });
+    }
+    if their_wins & column_cells(column) != 0 {
+        reasons.push(Reason::AllowsWin { opponent, column });
+    }
+    if score.is_some_and(|score| score <= -DECISIVE) {
+        reasons.push(Reason::ForcesLoss);
+    }
+    Ok(reasons)
+}
+
+/// `reasons` as one sentence: clauses joined by semicolons, the first
+/// capitalised. A move with nothing to say is "A quiet move."
+pub fn sentence(reasons: &[Reason]) -> String {
+    if reasons.is_empty() {
+        return "A quiet move.".to_string();
+    }
+    let text = reasons
+        .iter()
+        .map(Reason::to_string)
+        .collect::<Vec<_>>()
+        .join("; ");
+    let mut chars = text.chars();
+    let first = chars.next().map(|c| c.to_ascii_uppercase());
+    format!(
+        "{}{}.",
+        first.into_iter().collect::<String>(),
+        chars.as_str()
+    )
+}
+
+/// Explain every scored move of a position, left to right.
+pub fn explain_moves(
+    state: &GameState,
+    scores: &[ScoredMove],
+) -> Result<Vec<MoveExplanation>, GameError> {
+    scores
+        .iter()
+        .map(|scored| {
+            let reasons = reasons(state, scored.column, Some(scored.score))?;
+            Ok(MoveExplanation {
+                column: scored.column,
+                text: sentence(&reasons),
+                reasons,
+            })
+        })
+        .collect()
+}
+
+/// `analyze` with every column explained.
+pub fn analyze_explained(request: MoveRequest) -> Result<Analysis, GameError> {
+    let state = GameState::from_history(&crate::parse_history(&request.position)?)?;
+    let mut analysis = analyze(request)?;
+    analysis.explanations = explain_moves(&state, &analysis.scores)?;
+    Ok(analysis)
+}
+
+/// What dropping a disc in one column leads to, without searching.
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct MovePreview {
+    pub column: usize,
+    /// Completes four.
+    pub wins_now: bool,
+    /// Another column would have won at once.
+    pub misses_win: bool,
+    /// Takes a cell where the opponent would have completed four.
+    pub blocks_threat: bool,
+    /// The opponent can complete four with their reply, on top of this disc
+    /// or anywhere else.
+    pub allows_opponent_win: bool,
+    /// The board after the move, bottom row first as in `BoardModel`.
+    pub grid: [[Option<Player>; WIDTH]; HEIGHT],
+}
+
+/// One preview per legal column, left to right; empty once the game is
+/// over.
+pub fn preview_moves(state: &GameState) -> Vec<MovePreview> {
+    if state.status() != GameStatus::InProgress {
+        return Vec::new();
+    }
+    let mover = state.to_move();
+    let opponent = mover.opponent();
+    let can_win = state.has_winning_move(mover);
+    (0..WIDTH)
+        .filter(|&column| usize::from(state.heights[column]) < HEIGHT)
+        .map(|column| {
+            let wins_now = state.wins_at(mover, column);
+            let mut after = state.clone();
+            after
+                .play(column)
+                .expect("the column has room and the game is running");
+            MovePreview {
+                column,
+                wins_now,
+                misses_win: can_win && !wins_now,
+                blocks_threat: state.wins_at(opponent, column),
+                allows_opponent_win: !wins_now && after.has_winning_move(opponent),
+                grid: after.board_model().grid,
+            }
+        })
+        .collect()
+}
+
+impl fmt::Display for Reason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Wins => f.write_str("wins the game"),
+            Self::ForcesWin => f.write_str("wins by force"),
+            Self::Blocks { opponent, line } => {
+                write!(f, "blocks {}'s ", name(*opponent))?;
+                match line {
+                    Line::Horizontal { row: 0 } => f.write_str("three in a row on the bottom row"),
+                    Line::Horizontal { row } => write!(f, "three in a row on row {}", row + 1),
+                    Line::Vertical { column } => {
+                        write!(f, "stack of three in column {}", column + 1)
+                    }
+                    Line::Diagonal => f.write_str("diagonal three"),
+                }
+            }
+            Self::DoubleThreat => {
+                f.write_str("makes two threats at once, which cannot both be blocked")
+            }
+            Self::CreatesThreat { columns } => match columns.as_slice() {
+                [column] => write!(f, "creates a threat in column {}", column + 1),
+                columns => write!(f, "creates threats in columns {}", list(columns)),
+            },
+            Self::BuildsTwos { count: 1 } => f.write_str("builds a two with room to grow"),
+            Self::BuildsTwos { count } => write!(f, "builds {count} twos with room to grow"),
+            Self::TakesCenter => f.write_str("takes the centre column"),
+            Self::IgnoresWin { opponent, column } => write!(
+                f,
+                "leaves {}'s win in column {} open",
+                name(*opponent),
+                column + 1
+            ),
+            Self::AllowsWin { opponent, column } => write!(
+                f,
+                "lets {} win on top of it in column {}",
+                name(*opponent),
+                column + 1
+            ),
+            Self::ForcesLoss => f.write_str("loses by force"),
+        }
+    }
+}
+
+fn name(player: Player) -> &'static str {
+    match player {
+        Player::Red => "Red",
+        Player::Blue => "Blue",
+    }
+}
+
+/// "2, 4 and 5" for zero-based columns 1, 3 and 4.
+fn list(columns: &[usize]) -> String {
+    let names: Vec<String> = columns.iter().map(|c| (c + 1).to_string()).collect();
+    match names.split_last() {
+        Some((last, rest)) if !rest.is_empty() => format!("{} and {last}", rest.join(", ")),
+        _ => names.concat(),
+    }
+}
+
+/// The line of four through `cell` that `bits` completes, if any.
+fn completed_line(bits: u64, cell: u64) -> Option<Line> {
+    let mask = WIN_MASKS
+        .iter()
+        .copied()
+        .find(|&mask| mask & cell != 0 && mask & bits == mask)?;
+    let (low, high) = (
+        mask.trailing_zeros() as usize,
+        63 - mask.leading_zeros() as usize,
+    );
+    let (column, row) = (low / COL_HEIGHT, low % COL_HEIGHT);
+    Some(if high / COL_HEIGHT == column {
+        Line::Vertical { column }
+    } else if high % COL_HEIGHT == row {
+        Line::Horizontal { row }
+    } else {
+        Line::Diagonal
+    })
+}
+
+/// Empty cells where `player` would complete four.
+fn open_threats(state: &GameState, player: Player) -> u64 {
+    let occupied = state.bits(Player::Red) | state.bits(Player::Blue);
+    winning_cells(state.bits(player)) & BOARD_MASK & !occupied
+}
+
+/// The cell each column's next disc lands on.
+fn playable_cells(state: &GameState) -> u64 {
+    (0..WIDTH)
+        .filter(|&column| usize::from(state.heights[column]) < HEIGHT)
+        .fold(0, |cells, column| {
+            cells | bit_for(column, usize::from(state.heights[column]))
+        })
+}
+
+fn column_cells(column: usize) -> u64 {
+    ((1u64 << HEIGHT) - 1) << (column * COL_HEIGHT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_history;
+
+    fn state(history: &str) -> GameState {
+        GameState::from_history(&parse_history(history).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn blocks_and_threats_are_named() {
+        // Red has columns 2-4 of the bottom row, open at both ends.
+        let threat = state("R1B1R2B2R3");
+        let block = reasons(&threat, 4, None).unwrap();
+        assert_eq!(
+            block[0],
+            Reason::Blocks {
+                opponent: Player::Red,
+                line: Line::Horizontal { row: 0 },
+            }
+        );
+        assert!(block.contains(&Reason::IgnoresWin {
+            opponent: Player::Red,
+            column: 0,
+        }));
+        assert!(sentence(&block).starts_with("Blocks Red's three in a row on the bottom row; "));
+
+        let win = reasons(&state("R1B1R2B2R3B6"), 0, None).unwrap();
+        assert_eq!(win, vec![Reason::Wins]);
+        assert_eq!(sentence(&win), "Wins the game.");
+
+        // Red's third disc on the bottom leaves both ends open.
+        let double = reasons(&state("R1B1R2B2"), 3, None).unwrap();
+        assert!(double.contains(&Reason::DoubleThreat));
+        assert!(double.contains(&Reason::CreatesThreat {
+            columns: vec![0, 4]
+        }));
+        assert!(sentence(&double).contains("creates threats in columns 1 and 5"));
+    }
+
+    #[test]
+    fn dropping_under_a_threat_is_flagged() {
+        // Red holds the second row in columns 1-3; Blue filling the bottom
+        // of column 4 lets Red complete it.
+        let position = state("R1B0R0B2R1B6R2B6R5");
+        let reasons = reasons(&position, 3, Some(-WIN_SCORE)).unwrap();
+        assert!(reasons.contains(&Reason::AllowsWin {
+            opponent: Player::Red,
+            column: 3,
+        }));
+        assert_eq!(reasons.last(), Some(&Reason::ForcesLoss));
+        assert!(sentence(&reasons).contains("lets Red win on top of it in column 4"));
+    }
+
+    #[test]
+    fn previews_flag_hanging_fours() {
+        // Blue to move with Red three along the bottom: only 0 and 4 block,
+        // and each still leaves the other end.
+        let previews = preview_moves(&state("R1B1R2B2R3"));
+        assert_eq!(previews.len(), 7);
+        let blocking: Vec<usize> = previews
+            .iter()
+            .filter(|p| p.blocks_threat)
+            .map(|p| p.column)
+            .collect();
+        assert_eq!(blocking, vec![0, 4]);
+        assert!(previews
+            .iter()
+            .all(|p| p.allows_opponent_win && !p.wins_now));
+        assert_eq!(previews[4].grid[0][4], Some(Player::Blue));
+
+        // Red can win at either end; anything else misses it.
+        let previews = preview_moves(&state("R1B1R2B2R3B6"));
+        assert!(previews[0].wins_now && !previews[0].misses_win);
+        assert!(!previews[0].allows_opponent_win);
+        for preview in &previews {
+            let end = preview.column == 0 || preview.column == 4;
+            assert_eq!((preview.wins_now, preview.misses_win), (end, !end));
+        }
+
+        // Filling the cell under Red's second-row four hangs it.
+        let previews = preview_moves(&state("R1B0R0B2R1B6R2B6R5"));
+        assert!(previews[3].allows_opponent_win);
+        assert!(!previews[4].allows_opponent_win);
+
+        assert!(preview_moves(&state("R1B1R2B2R3B6R0")).is_empty());
+    }
+
+    #[test]
+    fn analyses_explain_every_column() {
+        let analysis = analyze_explained(MoveRequest {
+            position: "R1B1R2B2R3".to_string(),
+            level: 4,
+            first_player: None,
+        })
+        .unwrap();
+        assert_eq!(analysis.explanations.len(), analysis.scores.len());
+        let blocking = &analysis.explanations[4];
+        assert_eq!(blocking.column, 4);
+        assert_eq!(blocking.text, sentence(&blocking.reasons));
+        assert_eq!(sentence(&[]), "A quiet move.");
+    }
+}
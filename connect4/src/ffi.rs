@@ -0,0 +1,298 @@
+//! C ABI for embedding the engine in native games, built with the `ffi`
+//! feature as part of the `cdylib`:
+//!
+//! ```text
+//! cargo build -p connect4 --release --features ffi
+//! ```
+//!
+//! `connect4/include/connect4.h` declares these functions. Every function
+//! returns a `C4_*` status code, `C4_OK` (0) on success or a negative error,
+//! and writes results through out-pointers only on success. Histories are
+//! NUL-terminated UTF-8 in the usual `R3B3` notation and columns are
+//! zero-based. Nothing allocated here is handed to the caller, so there is
+//! nothing to free. The functions are reentrant and may be called from any
+//! thread. Additions keep existing signatures and codes; `c4_abi_version`
+//! only changes if one of them has to.
+use std::{
+    ffi::{c_char, CStr},
+    panic::{catch_unwind, UnwindSafe},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    best_move_within, parse_history, score_columns, GameError, GameState, GameStatus, MoveRequest,
+    Player, SearchLimits, WIDTH,
+};
+
+pub const C4_ABI_VERSION: u32 = 1;
+
+pub const C4_OK: i32 = 0;
+/// A required pointer argument was null.
+pub const C4_ERR_NULL: i32 = -1;
+/// The history is not valid UTF-8.
+pub const C4_ERR_UTF8: i32 = -2;
+/// The history is malformed.
+pub const C4_ERR_PARSE: i32 = -3;
+/// The history drops a disc into a full or nonexistent column.
+pub const C4_ERR_ILLEGAL_MOVE: i32 = -4;
+/// The level is outside 1-15.
+pub const C4_ERR_LEVEL: i32 = -5;
+/// The game is already over.
+pub const C4_ERR_GAME_OVER: i32 = -6;
+/// An internal error; the engine caught it rather than unwinding into C.
+pub const C4_ERR_INTERNAL: i32 = -7;
+
+/// Marks an unplayable column in `c4_score_columns`.
+pub const C4_NO_SCORE: i32 = i32::MIN;
+
+/// `c4_status` values.
+pub const C4_IN_PROGRESS: i32 = 0;
+pub const C4_WON: i32 = 1;
+pub const C4_DRAW: i32 = 2;
+/// `c4_status` winners and sides to move.
+pub const C4_NOBODY: i32 = 0;
+pub const C4_RED: i32 = 1;
+pub const C4_BLUE: i32 = 2;
+
+fn code(err: &GameError) -> i32 {
+    match err {
+        GameError::ParseMove { .. } | GameError::InvalidGrid(_) => C4_ERR_PARSE,
+        GameError::ColumnFull { .. } | GameError::ColumnOutOfBounds { .. } => C4_ERR_ILLEGAL_MOVE,
+        GameError::NoMoves => C4_ERR_GAME_OVER,
+        GameError::DepthOutOfRange(_) => C4_ERR_LEVEL,
+        // The C functions only use alpha-beta, which is always built.
+        GameError::AlgorithmUnavailable(_) => C4_ERR_INTERNAL,
+    }
+}
+
+/// Run `body`, turning errors and panics into status codes.
+fn guard(body: impl FnOnce() -> Result<(), i32> + UnwindSafe) -> i32 {
+    match catch_unwind(body) {
+        Ok(Ok(())) => C4_OK,
+        Ok(Err(code)) => code,
+        Err(_) => C4_ERR_INTERNAL,
+    }
+}
+
+/// # Safety
+/// `history` must be null or point to a NUL-terminated string.
+unsafe fn history<'a>(history: *const c_char) -> Result<&'a str, i32> {
+    if history.is_null() {
+        return Err(C4_ERR_NULL);
+    }
+    CStr::from_ptr(history).to_str().map_err(|_| C4_ERR_UTF8)
+}
+
+fn state(history: &str) -> Result<GameState, i32> {
+    let moves = parse_history(history).map_err(|err| code(&err))?;
+    GameState::from_history(&moves).map_err(|err| code(&err))
+}
+
+fn player(player: Option<Player>) -> i32 {
+    match player {
+        Some(Player::Red) => C4_RED,
+        Some(Player::Blue) => C4_BLUE,
+        None => C4_NOBODY,
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn c4_abi_version() -> u32 {
+    C4_ABI_VERSION
+}
+
+/// A static, NUL-terminated English description of a status code.
+#[no_mangle]
+pub extern "C" fn c4_error_message(code: i32) -> *const c_char {
+    let message: &'static CStr = match code {
+        C4_OK => c"ok",
+        C4_ERR_NULL => c"a required pointer was null",
+        C4_ERR_UTF8 => c"the history is not valid UTF-8",
+        C4_ERR_PARSE => c"the history is malformed",
+        C4_ERR_ILLEGAL_MOVE => c"the history plays into a full or nonexistent column",
+        C4_ERR_LEVEL => c"the level must be 1-15",
+        C4_ERR_GAME_OVER => c"the game is already over",
+        C4_ERR_INTERNAL => c"internal engine error",
+        _ => c"unknown status code",
+    };
+    message.as_ptr()
+}
+
+/// The engine's move for the side to move after `history`, searched to
+/// `level` (1-15).
+///
+/// # Safety
+/// `history` must be null or a NUL-terminated string, and `out_column` null
+/// or valid for one write.
+#[no_mangle]
+pub unsafe extern "C" fn c4_best_move(
+    history: *const c_char,
+    level: u8,
+    out_column: *mut i32,
+) -> i32 {
+    c4_best_move_limited(history, level, 0, 0, out_column, std::ptr::null_mut())
+}
+
+/// `c4_best_move` with limits: the search stops after `max_ms` milliseconds
+/// or `max_nodes` nodes, whichever comes first (0 for no limit), and answers
+/// from the deepest depth it completed, written to `out_depth` if that is not
+/// null.
+///
+/// # Safety
+/// `history` must be null or a NUL-terminated string; `out_column` and
+/// `out_depth` must each be null or valid for one write.
+#[no_mangle]
+pub unsafe extern "C" fn c4_best_move_limited(
+    history: *const c_char,
+    level: u8,
+    max_ms: u32,
+    max_nodes: u64,
+    out_column: *mut i32,
+    out_depth: *mut i32,
+) -> i32 {
+    guard(|| {
+        let history = self::history(history)?;
+        if out_column.is_null() {
+            return Err(C4_ERR_NULL);
+        }
+        if state(history)?.status() != GameStatus::InProgress {
+            return Err(C4_ERR_GAME_OVER);
+        }
+        let limits = SearchLimits {
+            deadline: (max_ms > 0)
+                .then(|| Instant::now() + Duration::from_millis(u64::from(max_ms))),
+            max_nodes: (max_nodes > 0).then_some(max_nodes),
+            ..SearchLimits::default()
+        };
+        let request = MoveRequest {
+            position: history.to_string(),
+            level,
+            first_player: None,
+        };
+        let response = best_move_within(request, &limits, |_| {}).map_err(|err| code(&err))?;
+        *out_column = response.column as i32;
+        if !out_depth.is_null() {
+            *out_depth = response.depth.unwrap_or(0) as i32;
+        }
+        Ok(())
+    })
+}
+
+/// Every column's score at `level` from the side to move's point of view,
+/// written to `out_scores[0..7]`: evaluation units, ±1000000 for a forced
+/// win or loss, `C4_NO_SCORE` for a full column.
+///
+/// # Safety
+/// `history` must be null or a NUL-terminated string, and `out_scores` null
+/// or valid for seven writes.
+#[no_mangle]
+pub unsafe extern "C" fn c4_score_columns(
+    history: *const c_char,
+    level: u8,
+    out_scores: *mut i32,
+) -> i32 {
+    guard(|| {
+        let state = state(self::history(history)?)?;
+        if out_scores.is_null() {
+            return Err(C4_ERR_NULL);
+        }
+        if !(1..=15).contains(&level) {
+            return Err(C4_ERR_LEVEL);
+        }
+        if state.status() != GameStatus::InProgress {
+            return Err(C4_ERR_GAME_OVER);
+        }
+        let out = std::slice::from_raw_parts_mut(out_scores, WIDTH);
+        out.fill(C4_NO_SCORE);
+        for (column, score) in score_columns(&state, usize::from(level)) {
+            out[column] = score;
+        }
+        Ok(())
+    })
+}
+
+/// The state of the game after `history`: `C4_IN_PROGRESS`, `C4_WON` or
+/// `C4_DRAW`, the winner (`C4_NOBODY`, `C4_RED`, `C4_BLUE`) and the side to
+/// move. Any out-pointer may be null.
+///
+/// # Safety
+/// `history` must be null or a NUL-terminated string; each out-pointer must
+/// be null or valid for one write.
+#[no_mangle]
+pub unsafe extern "C" fn c4_status(
+    history: *const c_char,
+    out_status: *mut i32,
+    out_winner: *mut i32,
+    out_to_move: *mut i32,
+) -> i32 {
+    guard(|| {
+        let state = state(self::history(history)?)?;
+        let status = match state.status() {
+            GameStatus::InProgress => C4_IN_PROGRESS,
+            GameStatus::Won => C4_WON,
+            GameStatus::Draw => C4_DRAW,
+        };
+        for (out, value) in [
+            (out_status, status),
+            (out_winner, player(state.winner())),
+            (out_to_move, player(Some(state.to_move()))),
+        ] {
+            if !out.is_null() {
+                *out = value;
+            }
+        }
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn functions_report_results_and_error_codes() {
+        let mut column = -1;
+        let mut depth = -1;
+        unsafe {
+            assert_eq!(
+                c4_best_move(c"R0B1R0B1R0B1".as_ptr(), 5, &mut column),
+                C4_OK
+            );
+            assert_eq!(column, 0);
+            let code =
+                c4_best_move_limited(c"R3B3".as_ptr(), 15, 0, 2_000, &mut column, &mut depth);
+            assert_eq!(code, C4_OK);
+            assert!((1..15).contains(&depth));
+
+            assert_eq!(
+                c4_best_move(c"R3B3".as_ptr(), 16, &mut column),
+                C4_ERR_LEVEL
+            );
+            assert_eq!(c4_best_move(c"R3X9".as_ptr(), 5, &mut column), C4_ERR_PARSE);
+            assert_eq!(c4_best_move(std::ptr::null(), 5, &mut column), C4_ERR_NULL);
+            assert_eq!(
+                c4_best_move(c"R0B1R0B1R0B1R0".as_ptr(), 5, &mut column),
+                C4_ERR_GAME_OVER
+            );
+
+            let mut scores = [0; WIDTH];
+            let full = c"R0B0R0B0R0B0";
+            assert_eq!(
+                c4_score_columns(full.as_ptr(), 2, scores.as_mut_ptr()),
+                C4_OK
+            );
+            assert_eq!(scores[0], C4_NO_SCORE);
+            assert!(scores[1..].iter().all(|&s| s != C4_NO_SCORE));
+
+            let (mut status, mut winner, mut to_move) = (-1, -1, -1);
+            let won = c"R0B1R0B1R0B1R0";
+            assert_eq!(
+                c4_status(won.as_ptr(), &mut status, &mut winner, &mut to_move),
+                C4_OK
+            );
+            assert_eq!((status, winner, to_move), (C4_WON, C4_RED, C4_BLUE));
+            let message = CStr::from_ptr(c4_error_message(C4_ERR_LEVEL));
+            assert_eq!(message.to_str().unwrap(), "the level must be 1-15");
+        }
+    }
+}
@@ -0,0 +1,131 @@
+//! Inputs for fuzz targets, built from the raw bytes a fuzzer mutates.
+//!
+//! `Unstructured` and `Arbitrary` follow the shape of the `arbitrary` crate
+//! without depending on it: every byte string decodes to a valid value, an
+//! exhausted input reads as zeros, and small changes to the bytes make small
+//! changes to the value, so coverage-guided fuzzers can steer. A
+//! `cargo fuzz` target needs only a few lines:
+//!
+//! ```ignore
+//! fuzz_target!(|data: &[u8]| {
+//!     let mut input = Unstructured::new(data);
+//!     let history = History::arbitrary(&mut input);
+//!     let state = GameState::from_history(&parse_history(&history.0).unwrap()).unwrap();
+//!     state.assert_consistent();
+//! });
+//! ```
+//!
+//! Built with the `fuzzing` feature.
+use crate::{format_history, GameState, Player, TypedMove, MAX_CELLS};
+
+/// A cursor over fuzzer input.
+pub struct Unstructured<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Unstructured<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// The next byte, 0 once the input runs out.
+    pub fn byte(&mut self) -> u8 {
+        match self.data.split_first() {
+            Some((&first, rest)) => {
+                self.data = rest;
+                first
+            }
+            None => 0,
+        }
+    }
+
+    /// An index in `0..n`; `n` must be positive.
+    pub fn below(&mut self, n: usize) -> usize {
+        usize::from(self.byte()) % n
+    }
+}
+
+/// A value decoded from fuzzer input.
+pub trait Arbitrary: Sized {
+    fn arbitrary(input: &mut Unstructured<'_>) -> Self;
+}
+
+impl Arbitrary for Player {
+    fn arbitrary(input: &mut Unstructured<'_>) -> Self {
+        if input.byte() & 1 == 0 {
+            Player::Red
+        } else {
+            Player::Blue
+        }
+    }
+}
+
+/// A history that passes `parse_history_strict`: colours alternate from
+/// either side, no column overflows, and it stops at a win or a full board.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct History(pub String);
+
+impl Arbitrary for History {
+    /// One byte for the first player, one for the length, then one per move
+    /// choosing among the legal columns.
+    fn arbitrary(input: &mut Unstructured<'_>) -> Self {
+        let first = Player::arbitrary(input);
+        let plies = input.below(MAX_CELLS + 1);
+        let mut state = GameState::empty(first);
+        let mut moves = Vec::with_capacity(plies);
+        while moves.len() < plies && state.winner().is_none() {
+            let legal = state.legal_moves();
+            if legal.is_empty() {
+                break;
+            }
+            let column = legal[input.below(legal.len())];
+            moves.push(TypedMove {
+                player: state.to_move(),
+                column,
+            });
+            state.play(column).expect("legal moves can be played");
+        }
+        Self(format_history(&moves))
+    }
+}
+
+/// A position reached by legal play, decoded like `History`.
+impl Arbitrary for GameState {
+    fn arbitrary(input: &mut Unstructured<'_>) -> Self {
+        let History(history) = History::arbitrary(input);
+        let moves = crate::parse_history(&history).expect("generated histories parse");
+        GameState::from_history(&moves).expect("generated histories replay")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse_history_strict, rng::Rng};
+
+    #[test]
+    fn any_bytes_give_legal_histories_and_consistent_positions() {
+        let mut rng = Rng::new(426);
+        for _ in 0..500 {
+            let len = rng.below(64);
+            let data: Vec<u8> = (0..len).map(|_| rng.next_u64() as u8).collect();
+            let History(history) = History::arbitrary(&mut Unstructured::new(&data));
+            let moves = parse_history_strict(&history).unwrap();
+            assert_eq!(format_history(&moves), history);
+            let state = GameState::arbitrary(&mut Unstructured::new(&data));
+            state.assert_consistent();
+            assert_eq!(state, GameState::from_history(&moves).unwrap());
+        }
+    }
+
+    #[test]
+    fn exhausted_input_reads_as_zeros() {
+        let mut input = Unstructured::new(&[7]);
+        assert_eq!((input.byte(), input.byte(), input.is_empty()), (7, 0, true));
+        assert_eq!(History::arbitrary(&mut input), History(String::new()));
+    }
+}
@@ -0,0 +1,333 @@
+//! A game from first move to result, for front ends that would otherwise each
+//! keep their own move list, thinking times and ending.
+//!
+//! `GameState` knows only the board. A `Game` also remembers how it got
+//! there, how long each side has thought, and how it ended when the board did
+//! not decide it: a resignation, a flag falling, or a draw by agreement.
+//! Observers registered with [`Game::observe`] hear about every move, draw
+//! offer and ending as it happens.
+use std::{
+    fmt,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::{format_history, notation::Outcome, GameError, GameState, Player, TypedMove};
+
+/// How a finished game ended.
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum Ending {
+    /// Four in a row, or a full board.
+    Board,
+    Resignation,
+    /// A side ran out of time.
+    Timeout,
+    /// A draw offer was accepted.
+    Agreement,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum GameEvent {
+    MovePlayed(TypedMove),
+    /// `by` offers the side to move a draw.
+    DrawOffered {
+        by: Player,
+    },
+    GameOver {
+        outcome: Outcome,
+        ending: Ending,
+    },
+}
+
+type Observer = Arc<dyn Fn(&GameEvent) + Send + Sync>;
+
+#[derive(Clone)]
+pub struct Game {
+    moves: Vec<TypedMove>,
+    state: GameState,
+    ending: Option<(Outcome, Ending)>,
+    draw_offer: Option<Player>,
+    /// Thinking time of finished turns, by player.
+    used: [Duration; 2],
+    /// When the side to move began its turn, while the game runs.
+    turn_started: Option<Instant>,
+    observers: Vec<Observer>,
+}
+
+impl fmt::Debug for Game {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Game")
+            .field("moves", &self.history())
+            .field("ending", &self.ending)
+            .field("draw_offer", &self.draw_offer)
+            .field("used", &self.used)
+            .field("observers", &self.observers.len())
+            .finish()
+    }
+}
+
+impl Game {
+    /// An empty board with `first` to move, its clock running.
+    pub fn new(first: Player) -> Self {
+        Self::starting(Vec::new(), GameState::empty(first))
+    }
+
+    /// The game after `moves`, with the side to move's clock running. A
+    /// history that already ends the game starts finished.
+    pub fn from_moves(moves: &[TypedMove]) -> Result<Self, GameError> {
+        let state = GameState::from_history(moves)?;
+        Ok(Self::starting(moves.to_vec(), state))
+    }
+
+    fn starting(moves: Vec<TypedMove>, state: GameState) -> Self {
+        let outcome = Outcome::of(&state);
+        let over = outcome != Outcome::Unfinished;
+        Self {
+            moves,
+            state,
+            ending: over.then_some((outcome, Ending::Board)),
+            draw_offer: None,
+            used: [Duration::ZERO; 2],
+            turn_started: (!over).then(Instant::now),
+            observers: Vec::new(),
+        }
+    }
+
+    /// Call `observer` on every later event. Clones of the game keep it.
+    pub fn observe(&mut self, observer: impl Fn(&GameEvent) + Send + Sync + 'static) {
+        self.observers.push(Arc::new(observer));
+    }
+
+    fn emit(&self, event: GameEvent) {
+        for observer in &self.observers {
+            observer(&event);
+        }
+    }
+
+    pub fn state(&self) -> &GameState {
+        &self.state
+    }
+
+    pub fn moves(&self) -> &[TypedMove] {
+        &self.moves
+    }
+
+    pub fn history(&self) -> String {
+        format_history(&self.moves)
+    }
+
+    /// `Unfinished` while the game runs.
+    pub fn outcome(&self) -> Outcome {
+        self.ending
+            .map_or(Outcome::Unfinished, |(outcome, _)| outcome)
+    }
+
+    /// `None` while the game runs.
+    pub fn ending(&self) -> Option<Ending> {
+        self.ending.map(|(_, ending)| ending)
+    }
+
+    pub fn is_over(&self) -> bool {
+        self.ending.is_some()
+    }
+
+    /// The side whose draw offer stands, until the other one moves.
+    pub fn draw_offer(&self) -> Option<Player> {
+        self.draw_offer
+    }
+
+    /// The side whose clock is running; `None` once the game is over.
+    pub fn running(&self) -> Option<Player> {
+        self.turn_started.map(|_| self.state.to_move())
+    }
+
+    /// Time `player` has thought so far, the running turn included.
+    pub fn thinking_time(&self, player: Player) -> Duration {
+        let running = match self.turn_started {
+            Some(started) if self.state.to_move() == player => started.elapsed(),
+            _ => Duration::ZERO,
+        };
+        self.used[player.idx()] + running
+    }
+
+    /// Charge the side to move for its turn so far.
+    fn charge(&mut self) {
+        if let Some(started) = self.turn_started.take() {
+            self.used[self.state.to_move().idx()] += started.elapsed();
+        }
+    }
+
+    /// Play `column` for the side to move. Moving declines any draw offer.
+    pub fn play(&mut self, column: usize) -> Result<TypedMove, GameError> {
+        if self.is_over() {
+            return Err(GameError::NoMoves);
+        }
+        let player = self.state.to_move();
+        let mut next = self.state.clone();
+        next.play(column)?;
+        self.charge();
+        self.state = next;
+        let mv = TypedMove { player, column };
+        self.moves.push(mv.clone());
+        self.draw_offer = None;
+        self.emit(GameEvent::MovePlayed(mv.clone()));
+        match Outcome::of(&self.state) {
+            Outcome::Unfinished => self.turn_started = Some(Instant::now()),
+            outcome => self.end(outcome, Ending::Board),
+        }
+        Ok(mv)
+    }
+
+    /// Take back the last move, reopening the game if it ended on the board.
+    /// Games ended another way stay over.
+    pub fn undo(&mut self) -> Option<TypedMove> {
+        if !matches!(self.ending(), None | Some(Ending::Board)) {
+            return None;
+        }
+        let mv = self.moves.pop()?;
+        self.charge();
+        self.state = GameState::from_history(&self.moves).expect("a prefix of a legal game");
+        if self.moves.is_empty() {
+            self.state = GameState::empty(mv.player);
+        }
+        self.ending = None;
+        self.draw_offer = None;
+        self.turn_started = Some(Instant::now());
+        Some(mv)
+    }
+
+    /// `by` offers a draw; `false` when the game is already over.
+    pub fn offer_draw(&mut self, by: Player) -> bool {
+        if self.is_over() {
+            return false;
+        }
+        self.draw_offer = Some(by);
+        self.emit(GameEvent::DrawOffered { by });
+        true
+    }
+
+    /// `player` accepts the other side's standing offer, drawing the game;
+    /// `false` when there is none.
+    pub fn accept_draw(&mut self, player: Player) -> bool {
+        if self.is_over() || self.draw_offer != Some(player.opponent()) {
+            return false;
+        }
+        self.charge();
+        self.end(Outcome::Draw, Ending::Agreement);
+        true
+    }
+
+    /// `player` gives up; `false` when the game is already over.
+    pub fn resign(&mut self, player: Player) -> bool {
+        self.concede(player, Ending::Resignation)
+    }
+
+    /// `player`'s flag fell; `false` when the game is already over.
+    pub fn time_out(&mut self, player: Player) -> bool {
+        self.concede(player, Ending::Timeout)
+    }
+
+    fn concede(&mut self, player: Player, ending: Ending) -> bool {
+        if self.is_over() {
+            return false;
+        }
+        self.charge();
+        let outcome = match player {
+            Player::Red => Outcome::BlueWin,
+            Player::Blue => Outcome::RedWin,
+        };
+        self.end(outcome, ending);
+        true
+    }
+
+    fn end(&mut self, outcome: Outcome, ending: Ending) {
+        self.turn_started = None;
+        self.draw_offer = None;
+        self.ending = Some((outcome, ending));
+        self.emit(GameEvent::GameOver { outcome, ending });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::parse_history;
+
+    /// A game whose events are collected in the returned list.
+    fn recorded(history: &str) -> (Game, Arc<Mutex<Vec<GameEvent>>>) {
+        let mut game = Game::from_moves(&parse_history(history).unwrap()).unwrap();
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let sink = events.clone();
+        game.observe(move |event| sink.lock().unwrap().push(event.clone()));
+        (game, events)
+    }
+
+    #[test]
+    fn observers_hear_moves_offers_and_the_end() {
+        let (mut game, events) = recorded("R0B1R0B1R0");
+        assert!(game.offer_draw(Player::Red));
+        game.play(1).unwrap();
+        assert_eq!(game.draw_offer(), None);
+        game.play(0).unwrap();
+        assert!(game.is_over() && game.running().is_none());
+        assert_eq!(game.outcome(), Outcome::RedWin);
+        assert!(matches!(game.play(2), Err(GameError::NoMoves)));
+        let red = |column| TypedMove {
+            player: Player::Red,
+            column,
+        };
+        assert_eq!(
+            *events.lock().unwrap(),
+            [
+                GameEvent::DrawOffered { by: Player::Red },
+                GameEvent::MovePlayed(TypedMove {
+                    player: Player::Blue,
+                    column: 1
+                }),
+                GameEvent::MovePlayed(red(0)),
+                GameEvent::GameOver {
+                    outcome: Outcome::RedWin,
+                    ending: Ending::Board
+                },
+            ]
+        );
+
+        // Undo reopens a game the board decided.
+        assert_eq!(game.undo(), Some(red(0)));
+        assert_eq!((game.ending(), game.running()), (None, Some(Player::Red)));
+    }
+
+    #[test]
+    fn games_end_off_the_board() {
+        let (mut game, events) = recorded("R3");
+        assert!(!game.accept_draw(Player::Blue));
+        assert!(game.offer_draw(Player::Red));
+        assert!(!game.accept_draw(Player::Red));
+        assert!(game.accept_draw(Player::Blue));
+        assert_eq!(
+            (game.outcome(), game.ending()),
+            (Outcome::Draw, Some(Ending::Agreement))
+        );
+        assert!(!game.resign(Player::Blue) && game.undo().is_none());
+        assert_eq!(events.lock().unwrap().len(), 2);
+
+        let mut game = Game::new(Player::Blue);
+        assert!(game.time_out(Player::Blue));
+        assert_eq!(game.outcome(), Outcome::RedWin);
+        let mut game = Game::new(Player::Red);
+        game.play(3).unwrap();
+        assert!(game.resign(Player::Red));
+        assert_eq!(
+            (game.outcome(), game.ending()),
+            (Outcome::BlueWin, Some(Ending::Resignation))
+        );
+    }
+}
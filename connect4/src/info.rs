@@ -0,0 +1,78 @@
+//! What engine produced a result, so analyses and games stay attributable
+//! after an upgrade. A result can be reproduced by an engine whose
+//! `EngineInfo` matches: the same release, evaluation and book.
+use std::fmt;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::EvalWeights;
+
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct EngineInfo {
+    /// Release of the `connect4` crate.
+    pub version: String,
+    /// Fingerprint of the default evaluation weights, 16 hex digits. It
+    /// changes whenever the fixed-level evaluation does.
+    pub eval_hash: String,
+    /// Version of the opening book; `None` while the engine has none.
+    pub book: Option<String>,
+    /// Cargo features the engine was built with.
+    pub features: Vec<String>,
+}
+
+impl fmt::Display for EngineInfo {
+    /// `connect4 0.1.0 (eval 1f0e…)`, as exported games tag it.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "connect4 {} (eval {}", self.version, self.eval_hash)?;
+        if let Some(book) = &self.book {
+            write!(f, ", book {book}")?;
+        }
+        write!(f, ")")
+    }
+}
+
+/// The running engine.
+pub fn engine_info() -> EngineInfo {
+    let features = [
+        ("serde", cfg!(feature = "serde")),
+        ("book", cfg!(feature = "book")),
+        ("mcts", cfg!(feature = "mcts")),
+        ("solver", cfg!(feature = "solver")),
+        ("openapi", cfg!(feature = "openapi")),
+        ("png", cfg!(feature = "png")),
+        ("wasm", cfg!(feature = "wasm")),
+        ("ffi", cfg!(feature = "ffi")),
+    ];
+    EngineInfo {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        eval_hash: format!("{:016x}", EvalWeights::default().fingerprint()),
+        book: None,
+        features: features
+            .into_iter()
+            .filter(|&(_, enabled)| enabled)
+            .map(|(name, _)| name.to_string())
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn info_names_the_release_and_evaluation() {
+        let info = engine_info();
+        assert_eq!(info.version, env!("CARGO_PKG_VERSION"));
+        assert_eq!(info.eval_hash.len(), 16);
+        assert_eq!(info, engine_info());
+        assert!(info.to_string().starts_with("connect4 "));
+        let tuned = EvalWeights {
+            three: 60,
+            ..EvalWeights::default()
+        };
+        assert_ne!(tuned.fingerprint(), EvalWeights::default().fingerprint());
+    }
+}
@@ -2,24 +2,115 @@
 //! The game state is fully stateless: callers feed a move history string
 //! (e.g. `B3R3B2R4`) and request a search depth (1-15). The AI plays for the
 //! side whose turn is next after that history.
-use once_cell::sync::Lazy;
+pub mod adaptive;
+#[cfg(feature = "book")]
+pub mod book;
+pub mod dataset;
+mod eval;
+pub mod explain;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(feature = "fuzzing")]
+pub mod fuzz;
+pub mod game;
+mod info;
+#[cfg(feature = "mcts")]
+mod mcts;
+pub mod nn;
+pub mod notation;
+mod options;
+pub mod perft;
+pub mod policy;
+pub mod puzzle;
+pub mod render;
+pub mod resign;
+pub mod review;
+mod rng;
+mod search;
+pub mod selfplay;
+pub mod stats;
+pub mod strength;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+pub mod tournament;
+pub mod trace;
+pub mod tt;
+pub mod tune;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+use std::{
+    hash::{Hash, Hasher},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Instant,
+};
+
+use connect4_core::{has_won, WIN_MASKS};
+#[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
-const WIDTH: usize = 7;
-const HEIGHT: usize = 6;
+pub use eval::{
+    evaluate_explain, heatmap, EvalBreakdown, EvalPreset, EvalTerm, EvalWeights, HeatCell, Heatmap,
+    PlayerEval,
+};
+pub use info::{engine_info, EngineInfo};
+pub use options::{search, Algorithm, MoveOrdering, ScoredMove, SearchOptions, SearchOutcome};
+use search::Search;
+pub use search::TimeManager;
+pub use selfplay::{selfplay, selfplay_games, SelfPlayConfig};
+pub use strength::Level;
+use tt::TranspositionTable;
+
+/// Columns on the board.
+pub const WIDTH: usize = 7;
+/// Rows on the board.
+pub const HEIGHT: usize = 6;
 const COL_HEIGHT: usize = HEIGHT + 1; // sentinel row simplifies bit math
 const MAX_CELLS: usize = WIDTH * HEIGHT;
 const WIN_SCORE: i32 = 1_000_000;
 
+/// 64-bit FNV-1a, for fingerprints that must not change between builds.
+fn fnv1a(bytes: impl IntoIterator<Item = u8>) -> u64 {
+    bytes.into_iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
 /// Order legal moves so alpha-beta sees center-first branches.
 const MOVE_ORDER: [usize; WIDTH] = [3, 2, 4, 1, 5, 0, 6];
 
-/// Precomputed winning lines of four as bitmasks.
-static WIN_MASKS: Lazy<Vec<u64>> = Lazy::new(generate_win_masks);
+/// Zobrist keys per player and bitboard bit, then one for Blue to move.
+/// SplitMix64 from a fixed seed, computed at compile time, so hashes are the
+/// same in every build and run.
+static ZOBRIST: [[u64; WIDTH * COL_HEIGHT]; 2] = zobrist_table();
+const ZOBRIST_BLUE_TO_MOVE: u64 = splitmix64(2 * (WIDTH * COL_HEIGHT) as u64 + 1);
+
+const fn splitmix64(index: u64) -> u64 {
+    let mut z = 0x5EED_C0DE_u64.wrapping_add((index + 1).wrapping_mul(0x9E37_79B9_7F4A_7C15));
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+const fn zobrist_table() -> [[u64; WIDTH * COL_HEIGHT]; 2] {
+    let mut table = [[0; WIDTH * COL_HEIGHT]; 2];
+    let mut i = 0;
+    while i < WIDTH * COL_HEIGHT {
+        table[0][i] = splitmix64(2 * i as u64);
+        table[1][i] = splitmix64(2 * i as u64 + 1);
+        i += 1;
+    }
+    table
+}
 
-#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
-#[serde(rename_all = "lowercase")]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
 pub enum Player {
     Red,
     Blue,
@@ -44,7 +135,10 @@ impl Player {
 #[derive(Debug, Error)]
 pub enum GameError {
     #[error("invalid move string at position {position}: {reason}")]
-    ParseMove { position: usize, reason: String },
+    ParseMove {
+        position: usize,
+        reason: ParseMoveError,
+    },
     #[error("column {column} is full")]
     ColumnFull { column: usize },
     #[error("column {column} is out of bounds")]
@@ -53,6 +147,53 @@ pub enum GameError {
     NoMoves,
     #[error("depth {0} is out of range (1-15)")]
     DepthOutOfRange(u8),
+    #[error("invalid board: {0}")]
+    InvalidGrid(#[from] notation::GridError),
+    /// The algorithm's cargo feature was not enabled.
+    #[error("algorithm {0:?} is not available in this build")]
+    AlgorithmUnavailable(Algorithm),
+}
+
+/// Why a history or game record failed to parse. Serialised with a `kind`
+/// tag so clients can word the feedback themselves; the `Display` text is
+/// English for logs and CLIs.
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Clone, Debug, PartialEq, Eq, Error)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "kind", rename_all = "snake_case"))]
+pub enum ParseMoveError {
+    /// A character other than `R` or `B` where a player belongs, or a
+    /// non-digit where a column belongs.
+    #[error("unexpected character {found:?}")]
+    UnexpectedChar { found: char },
+    /// The history ends after a player letter.
+    #[error("missing column number")]
+    MissingColumn,
+    #[error("column {column} is not 0-6")]
+    ColumnOutOfRange { column: usize },
+    #[error("expected {expected:?} to move, found {found:?}")]
+    NonAlternatingTurn { expected: Player, found: Player },
+    #[error("move played after the game was already over")]
+    MoveAfterGameOver,
+    #[error("column {column} is full")]
+    ColumnFull { column: usize },
+    /// Game notation only: a `[Name "value"]` line that does not parse.
+    #[error("malformed tag line: {line}")]
+    MalformedTag { line: String },
+    /// Game notation only: a `First` or `Result` tag with an unknown value.
+    #[error("unknown {tag} value {value}")]
+    UnknownTagValue { tag: String, value: String },
+    /// Game notation only: a move number out of sequence.
+    #[error("expected move number {expected}.")]
+    WrongMoveNumber { expected: usize },
+    /// Game notation only: a token that is neither a column, a move number
+    /// nor a result, or anything after the result.
+    #[error("unexpected {token}")]
+    UnexpectedToken { token: String },
+    /// Game notation only: the move list ends on a different result than
+    /// its `Result` tag.
+    #[error("result {result} contradicts the Result tag {tag}")]
+    ResultContradictsTag { result: String, tag: String },
 }
 
 #[derive(Clone, Debug, PartialEq, Eq)]
@@ -62,15 +203,271 @@ pub struct MoveOutcome {
     pub won: bool,
 }
 
-#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct MoveRequest {
     pub position: String,
     pub level: u8,
+    /// Who opens the game. Only an empty `position` needs it, since a
+    /// history names the player of every move; with moves it must match the
+    /// first one. Red when absent.
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub first_player: Option<Player>,
 }
 
-#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+impl MoveRequest {
+    /// The position to search, with `first_player` to move on an empty board.
+    pub fn state(&self) -> Result<GameState, GameError> {
+        let moves = parse_history(&self.position)?;
+        match (moves.first(), self.first_player) {
+            (None, first) => Ok(GameState::empty(first.unwrap_or(Player::Red))),
+            (Some(opening), Some(first)) if opening.player != first => Err(GameError::ParseMove {
+                position: 0,
+                reason: ParseMoveError::NonAlternatingTurn {
+                    expected: first,
+                    found: opening.player,
+                },
+            }),
+            (Some(_), _) => GameState::from_history(&moves),
+        }
+    }
+}
+
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct MoveResponse {
     pub column: usize,
+    /// The side the move is for. The HTTP API fills it in; library callers
+    /// know it from the position they asked about.
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub player: Option<Player>,
+    /// Depth the search actually completed. Equal to the level unless a
+    /// deadline cut the search short; absent in responses from older servers.
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub depth: Option<usize>,
+    /// Score of the chosen move, reported by configured searches.
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub score: Option<i32>,
+    /// Ranked candidates when more than one was asked for.
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Vec::is_empty")
+    )]
+    pub lines: Vec<ScoredMove>,
+    /// Set by a `resign::ResignTracker` once the engine's game is lost.
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "std::ops::Not::not")
+    )]
+    pub should_resign: bool,
+    /// Set by a `resign::ResignTracker` once the game is a proven draw.
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "std::ops::Not::not")
+    )]
+    pub offers_draw: bool,
+    /// A deadline, node budget or cancellation stopped the search before
+    /// `target_depth`; `column` is from the deepest completed iteration,
+    /// `depth`, so clients can show "depth 9/12".
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "std::ops::Not::not")
+    )]
+    pub truncated: bool,
+    /// The depth asked for, when truncated.
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub target_depth: Option<usize>,
+    /// When truncated, the best move of the interrupted iteration among the
+    /// moves it finished, if it finished any. A hint for display, weaker
+    /// evidence than `column`.
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub best_so_far: Option<usize>,
+}
+
+impl MoveResponse {
+    /// A plain answer: just the column and the depth it was searched to.
+    pub fn new(column: usize, depth: usize) -> Self {
+        Self {
+            column,
+            player: None,
+            depth: Some(depth),
+            score: None,
+            lines: Vec::new(),
+            should_resign: false,
+            offers_draw: false,
+            truncated: false,
+            target_depth: None,
+            best_so_far: None,
+        }
+    }
+
+    /// Mark an answer whose search stopped short of `target_depth`.
+    pub fn cut_short(self, target_depth: usize, best_so_far: Option<usize>) -> Self {
+        Self {
+            truncated: true,
+            target_depth: Some(target_depth),
+            best_so_far,
+            ..self
+        }
+    }
+}
+
+/// Cooperative cancellation: whoever holds a clone can stop a running search,
+/// which then returns the best move of its last completed iteration.
+#[derive(Clone, Debug, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Bounds on a search beyond its depth. The first iteration always completes
+/// so there is a move to return even when the deadline has already passed.
+#[derive(Clone, Debug, Default)]
+pub struct SearchLimits {
+    pub deadline: Option<Instant>,
+    /// No new iteration of iterative deepening starts after it. An iteration
+    /// takes several times as long as the one before, so one begun late
+    /// would rarely finish by `deadline`; its time is better left on the
+    /// clock. See `TimeManager`.
+    pub soft_deadline: Option<Instant>,
+    pub cancel: Option<CancelToken>,
+    /// Node budget, summed over iterations (MCTS counts iterations). Unlike a
+    /// deadline it needs no clock, which `wasm32-unknown-unknown` lacks.
+    /// It is checked at every node, so the search stops at the same node on
+    /// any machine: with no deadline and a private table, the same position,
+    /// options and budget always give the same move, depth and node counts.
+    pub max_nodes: Option<u64>,
+    /// Transposition table to search with, possibly shared with other
+    /// searches; without one each search makes a private table of
+    /// `tt::DEFAULT_ENTRIES`. Tables change speed, never answers.
+    pub table: Option<Arc<TranspositionTable>>,
+    /// What an earlier search expects here, used by `best_move_within`.
+    /// Like the table, a hint changes speed, never answers.
+    pub hint: Option<SearchHint>,
+    /// Record every alpha-beta node for debugging; see `trace`.
+    pub trace: Option<trace::Tracer>,
+}
+
+/// The move and full-depth score an earlier search foresaw for this
+/// position, e.g. the engine's own last line when the opponent answered as
+/// it predicted. The move is searched first and the last iteration starts
+/// with a narrow window around the score. Scores are from the side to
+/// move's point of view.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct SearchHint {
+    pub column: usize,
+    pub score: i32,
+}
+
+impl SearchLimits {
+    pub fn with_deadline(deadline: Instant) -> Self {
+        Self {
+            deadline: Some(deadline),
+            ..Self::default()
+        }
+    }
+
+    pub fn with_max_nodes(max_nodes: u64) -> Self {
+        Self {
+            max_nodes: Some(max_nodes),
+            ..Self::default()
+        }
+    }
+
+    /// Whether another iteration may begin: within every limit and before
+    /// the soft deadline.
+    fn may_deepen(&self, nodes: u64) -> bool {
+        !self.exceeded(nodes) && !matches!(self.soft_deadline, Some(d) if Instant::now() >= d)
+    }
+
+    fn exceeded(&self, nodes: u64) -> bool {
+        self.out_of_nodes(nodes)
+            || self.cancel.as_ref().is_some_and(CancelToken::is_cancelled)
+            || self.deadline.is_some_and(|d| Instant::now() >= d)
+    }
+
+    /// The budget alone: cheap enough for every node, unlike the clock.
+    fn out_of_nodes(&self, nodes: u64) -> bool {
+        self.max_nodes.is_some_and(|max| nodes >= max)
+    }
+}
+
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum GameStatus {
+    InProgress,
+    Won,
+    Draw,
+}
+
+/// A board cell addressed by zero-based column and row (row 0 is the bottom).
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Cell {
+    pub column: usize,
+    pub row: usize,
+}
+
+/// A cell whose disc differs between two positions, from `GameState::diff`.
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct CellChange {
+    pub column: usize,
+    pub row: usize,
+    /// `None` for an empty cell: a disc was added.
+    pub before: Option<Player>,
+    /// `None` for an empty cell: a disc was removed.
+    pub after: Option<Player>,
+}
+
+/// Everything a client needs to draw a position without replaying history
+/// itself. `grid[0]` is the bottom row so indices match the engine's rows.
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct BoardModel {
+    pub grid: [[Option<Player>; WIDTH]; HEIGHT],
+    pub to_move: Player,
+    pub moves_played: usize,
+    pub legal_columns: Vec<usize>,
+    pub status: GameStatus,
+    pub winner: Option<Player>,
+    pub winning_line: Option<Vec<Cell>>,
 }
 
 #[derive(Clone, Debug, PartialEq, Eq)]
@@ -79,15 +476,58 @@ pub struct GameState {
     heights: [u8; WIDTH],
     to_move: Player,
     moves_played: u8,
+    /// Zobrist hash, kept up to date by every change to the fields above.
+    hash: u64,
+}
+
+/// Hashes the position key and the side to move, so equal positions hash
+/// equally however they were reached.
+impl Hash for GameState {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (self.key(), self.to_move.idx()).hash(state);
+    }
+}
+
+/// Orders by position key, then Red to move before Blue. The key encoding is
+/// stable, so the order is the same in every run and release, and
+/// `BTreeMap`s of positions serialise reproducibly.
+impl Ord for GameState {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        (self.key(), self.to_move.idx()).cmp(&(other.key(), other.to_move.idx()))
+    }
+}
+
+impl PartialOrd for GameState {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
 }
 
 impl GameState {
     pub fn empty(to_move: Player) -> Self {
-        Self {
+        let mut state = Self {
             players: [0, 0],
             heights: [0; WIDTH],
-            to_move,
+            to_move: Player::Red,
             moves_played: 0,
+            hash: 0,
+        };
+        state.set_to_move(to_move);
+        state
+    }
+
+    /// Zobrist hash of the discs and the side to move, maintained as moves
+    /// are played and undone. Equal positions hash equally however they were
+    /// reached. The table is fixed, so hashes are stable across runs, but
+    /// `key` stays the persisted key: it is exact where this can collide.
+    pub fn hash(&self) -> u64 {
+        self.hash
+    }
+
+    pub(crate) fn set_to_move(&mut self, player: Player) {
+        if self.to_move != player {
+            self.hash ^= ZOBRIST_BLUE_TO_MOVE;
+            self.to_move = player;
         }
     }
 
@@ -119,13 +559,53 @@ impl GameState {
         for mv in moves {
             state.force_play(mv.player, mv.column)?;
         }
-        state.to_move = moves
-            .last()
-            .map(|m| m.player.opponent())
-            .unwrap_or(Player::Red);
+        state.set_to_move(
+            moves
+                .last()
+                .map(|m| m.player.opponent())
+                .unwrap_or(Player::Red),
+        );
         Ok(state)
     }
 
+    pub fn to_move(&self) -> Player {
+        self.to_move
+    }
+
+    /// The side that opened the game, recovered from move-count parity.
+    pub fn first_player(&self) -> Player {
+        if self.moves_played & 1 == 0 {
+            self.to_move
+        } else {
+            self.to_move.opponent()
+        }
+    }
+
+    /// Compact position key: the side to move's discs plus the occupancy mask.
+    /// The sentinel row makes the sum unique, and the encoding is stable, so
+    /// keys can be persisted across runs.
+    pub fn key(&self) -> u64 {
+        let occupied = self.players[0] | self.players[1];
+        self.players[self.to_move.idx()] + occupied
+    }
+
+    /// Key of the left-right mirror image of this position.
+    pub fn mirrored_key(&self) -> u64 {
+        mirror_columns(self.key())
+    }
+
+    /// The smaller of `key` and `mirrored_key`, and whether that is the mirror.
+    /// A position and its mirror have mirrored best moves, so caches can store
+    /// one entry per pair and flip the column (`6 - c`) on the way out.
+    pub fn canonical_key(&self) -> (u64, bool) {
+        let (key, mirrored) = (self.key(), self.mirrored_key());
+        if mirrored < key {
+            (mirrored, true)
+        } else {
+            (key, false)
+        }
+    }
+
     pub fn bits(&self, player: Player) -> u64 {
         self.players[player.idx()]
     }
@@ -138,6 +618,153 @@ impl GameState {
             .collect()
     }
 
+    pub fn cell(&self, column: usize, row: usize) -> Option<Player> {
+        let bit = bit_for(column, row);
+        [Player::Red, Player::Blue]
+            .into_iter()
+            .find(|&p| self.players[p.idx()] & bit != 0)
+    }
+
+    /// The cells whose disc differs in `other`, by column and then from the
+    /// bottom up.
+    pub fn diff(&self, other: &GameState) -> Vec<CellChange> {
+        let changed = (self.players[0] ^ other.players[0]) | (self.players[1] ^ other.players[1]);
+        (0..WIDTH)
+            .flat_map(|column| (0..HEIGHT).map(move |row| (column, row)))
+            .filter(|&(column, row)| changed & bit_for(column, row) != 0)
+            .map(|(column, row)| CellChange {
+                column,
+                row,
+                before: self.cell(column, row),
+                after: other.cell(column, row),
+            })
+            .collect()
+    }
+
+    pub fn winner(&self) -> Option<Player> {
+        [Player::Red, Player::Blue]
+            .into_iter()
+            .find(|&p| has_won(self.players[p.idx()]))
+    }
+
+    /// Check the invariants every move keeps: no cell held by both sides and
+    /// none off the board, each column's discs stacked from the bottom to its
+    /// height, the move count equal to the discs on the board, the side to
+    /// move never ahead in discs, nobody ahead by more than one, and the
+    /// Zobrist hash matching the discs. Positions forced out of turn through
+    /// `from_history` fail the disc count check; `parse_history_strict` keeps
+    /// them out.
+    pub fn check_consistent(&self) -> Result<(), String> {
+        let [red, blue] = self.players;
+        if red & blue != 0 {
+            return Err(format!("both sides hold cells {:#x}", red & blue));
+        }
+        if (red | blue) & !BOARD_MASK != 0 {
+            return Err(format!(
+                "discs off the board at {:#x}",
+                (red | blue) & !BOARD_MASK
+            ));
+        }
+        for (column, &height) in self.heights.iter().enumerate() {
+            let stack = ((red | blue) >> (column * COL_HEIGHT)) & ((1 << COL_HEIGHT) - 1);
+            if usize::from(height) > HEIGHT || stack != (1 << height) - 1 {
+                return Err(format!(
+                    "column {column} has height {height} but discs {stack:#09b}"
+                ));
+            }
+        }
+        let (reds, blues) = (red.count_ones(), blue.count_ones());
+        if u32::from(self.moves_played) != reds + blues {
+            return Err(format!(
+                "{} moves played but {} discs on the board",
+                self.moves_played,
+                reds + blues
+            ));
+        }
+        let (mover, waiting) = match self.to_move {
+            Player::Red => (reds, blues),
+            Player::Blue => (blues, reds),
+        };
+        if mover > waiting || waiting > mover + 1 {
+            return Err(format!(
+                "{:?} to move with {mover} discs against {waiting}",
+                self.to_move
+            ));
+        }
+        let mut hash = if self.to_move == Player::Blue {
+            ZOBRIST_BLUE_TO_MOVE
+        } else {
+            0
+        };
+        for (player, keys) in ZOBRIST.iter().enumerate() {
+            let mut discs = self.players[player];
+            while discs != 0 {
+                hash ^= keys[discs.trailing_zeros() as usize];
+                discs &= discs - 1;
+            }
+        }
+        if hash != self.hash {
+            return Err(format!("hash {:#018x} should be {hash:#018x}", self.hash));
+        }
+        Ok(())
+    }
+
+    /// `check_consistent`, panicking with the broken invariant; for tests
+    /// and fuzz targets.
+    #[track_caller]
+    pub fn assert_consistent(&self) {
+        if let Err(broken) = self.check_consistent() {
+            panic!("inconsistent position: {broken}");
+        }
+    }
+
+    /// The first complete line of four owned by the winner, if any.
+    pub fn winning_line(&self) -> Option<Vec<Cell>> {
+        let bits = self.players[self.winner()?.idx()];
+        let mask = WIN_MASKS.iter().find(|&&mask| bits & mask == mask)?;
+        Some(
+            (0..WIDTH)
+                .flat_map(|column| (0..HEIGHT).map(move |row| Cell { column, row }))
+                .filter(|cell| mask & bit_for(cell.column, cell.row) != 0)
+                .collect(),
+        )
+    }
+
+    pub fn status(&self) -> GameStatus {
+        if self.winner().is_some() {
+            GameStatus::Won
+        } else if self.is_full() {
+            GameStatus::Draw
+        } else {
+            GameStatus::InProgress
+        }
+    }
+
+    pub fn board_model(&self) -> BoardModel {
+        let mut grid = [[None; WIDTH]; HEIGHT];
+        for (row, cells) in grid.iter_mut().enumerate() {
+            for (column, cell) in cells.iter_mut().enumerate() {
+                *cell = self.cell(column, row);
+            }
+        }
+        let status = self.status();
+        let mut legal_columns = if status == GameStatus::InProgress {
+            self.legal_moves()
+        } else {
+            Vec::new()
+        };
+        legal_columns.sort_unstable();
+        BoardModel {
+            grid,
+            to_move: self.to_move,
+            moves_played: self.moves_played as usize,
+            legal_columns,
+            status,
+            winner: self.winner(),
+            winning_line: self.winning_line(),
+        }
+    }
+
     pub fn is_full(&self) -> bool {
         self.moves_played as usize >= MAX_CELLS
     }
@@ -150,12 +777,13 @@ impl GameState {
         if height >= HEIGHT {
             return Err(GameError::ColumnFull { column });
         }
-        let bit = 1u64 << (column * COL_HEIGHT + height);
-        self.players[player.idx()] |= bit;
+        let index = column * COL_HEIGHT + height;
+        self.players[player.idx()] |= 1u64 << index;
+        self.hash ^= ZOBRIST[player.idx()][index];
         self.heights[column] += 1;
         self.moves_played += 1;
         let won = has_won(self.players[player.idx()]);
-        self.to_move = player.opponent();
+        self.set_to_move(player.opponent());
         Ok(MoveOutcome {
             player,
             column,
@@ -163,18 +791,70 @@ impl GameState {
         })
     }
 
-    fn play(&mut self, column: usize) -> Result<MoveOutcome, GameError> {
+    /// Whether `player` would complete four by dropping a disc in `column`.
+    pub(crate) fn wins_at(&self, player: Player, column: usize) -> bool {
+        let height = usize::from(self.heights[column]);
+        height < HEIGHT && has_won(self.players[player.idx()] | bit_for(column, height))
+    }
+
+    /// Whether `player` has a move that wins at once.
+    pub(crate) fn has_winning_move(&self, player: Player) -> bool {
+        (0..WIDTH).any(|column| self.wins_at(player, column))
+    }
+
+    /// Empty cells, playable now or later, where `player` would complete
+    /// four after dropping a disc in `column`.
+    pub(crate) fn threats_after(&self, player: Player, column: usize) -> u32 {
+        let disc = bit_for(column, usize::from(self.heights[column]));
+        let occupied = self.players[0] | self.players[1] | disc;
+        (winning_cells(self.players[player.idx()] | disc) & BOARD_MASK & !occupied).count_ones()
+    }
+
+    /// Take back the top disc of `column`, which becomes its owner's turn
+    /// again, and return that owner; `None` if the column is empty or out of
+    /// range. Callers undo in reverse order of play, as with a move list.
+    pub fn undo(&mut self, column: usize) -> Option<Player> {
+        let row = usize::from(*self.heights.get(column)?).checked_sub(1)?;
+        let player = self.cell(column, row)?;
+        let index = column * COL_HEIGHT + row;
+        self.players[player.idx()] &= !(1u64 << index);
+        self.hash ^= ZOBRIST[player.idx()][index];
+        self.heights[column] -= 1;
+        self.moves_played -= 1;
+        self.set_to_move(player);
+        Some(player)
+    }
+
+    /// Drop a disc for the side to move. Like `from_history` this does not
+    /// refuse moves after a win; callers that referee games check `status()`.
+    pub fn play(&mut self, column: usize) -> Result<MoveOutcome, GameError> {
         let player = self.to_move;
         self.force_play(player, column)
     }
 }
 
 #[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct TypedMove {
     pub player: Player,
     pub column: usize,
 }
 
+impl std::fmt::Display for TypedMove {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let color = match self.player {
+            Player::Red => 'R',
+            Player::Blue => 'B',
+        };
+        write!(f, "{color}{}", self.column)
+    }
+}
+
+/// Inverse of `parse_history`.
+pub fn format_history(moves: &[TypedMove]) -> String {
+    moves.iter().map(TypedMove::to_string).collect()
+}
+
 pub fn parse_history(history: &str) -> Result<Vec<TypedMove>, GameError> {
     if history.trim().is_empty() {
         return Ok(Vec::new());
@@ -190,7 +870,7 @@ pub fn parse_history(history: &str) -> Result<Vec<TypedMove>, GameError> {
             _ => {
                 return Err(GameError::ParseMove {
                     position: idx,
-                    reason: format!("expected R or B, found {color}"),
+                    reason: ParseMoveError::UnexpectedChar { found: color },
                 })
             }
         };
@@ -198,21 +878,21 @@ pub fn parse_history(history: &str) -> Result<Vec<TypedMove>, GameError> {
         if idx >= chars.len() {
             return Err(GameError::ParseMove {
                 position: idx,
-                reason: "missing column number".to_string(),
+                reason: ParseMoveError::MissingColumn,
             });
         }
         let column_char = chars[idx];
         if !column_char.is_ascii_digit() {
             return Err(GameError::ParseMove {
                 position: idx,
-                reason: format!("expected column digit, found {column_char}"),
+                reason: ParseMoveError::UnexpectedChar { found: column_char },
             });
         }
         let column = column_char.to_digit(10).unwrap() as usize;
         if column >= WIDTH {
             return Err(GameError::ParseMove {
                 position: idx,
-                reason: format!("column must be 0-{}", WIDTH - 1),
+                reason: ParseMoveError::ColumnOutOfRange { column },
             });
         }
         moves.push(TypedMove {
@@ -224,191 +904,326 @@ pub fn parse_history(history: &str) -> Result<Vec<TypedMove>, GameError> {
     Ok(moves)
 }
 
-pub fn best_move(request: MoveRequest) -> Result<MoveResponse, GameError> {
-    if !(1..=15).contains(&request.level) {
-        return Err(GameError::DepthOutOfRange(request.level));
-    }
-    let moves = parse_history(&request.position)?;
-    let mut state = GameState::from_history(&moves)?;
-    let candidate = choose_move(&mut state, request.level as usize)?;
-    Ok(MoveResponse { column: candidate })
-}
-
-fn choose_move(state: &mut GameState, depth: usize) -> Result<usize, GameError> {
-    let player = state.to_move;
-    let mut best_col = None;
-    let mut alpha = i32::MIN / 2;
-    let beta = i32::MAX / 2;
-    for col in state.legal_moves() {
-        let mut child = state.clone();
-        let outcome = child.play(col)?;
-        let val = if outcome.won {
-            WIN_SCORE
-        } else if child.is_full() {
-            0
-        } else {
-            -negamax(
-                &child,
-                depth.saturating_sub(1),
-                -beta,
-                -alpha,
-                player.opponent(),
-            )
-        };
-        if val > alpha {
-            alpha = val;
-            best_col = Some(col);
+/// Parse a history and additionally enforce the rules `parse_history` leaves
+/// to the caller: colours alternate, columns do not overflow, and nobody moves
+/// after the game is decided. Every failure is reported as `ParseMove` pointing
+/// at the offending character.
+pub fn parse_history_strict(history: &str) -> Result<Vec<TypedMove>, GameError> {
+    let moves = parse_history(history)?;
+    let Some(first) = moves.first() else {
+        return Ok(moves);
+    };
+    let mut state = GameState::empty(first.player);
+    for (index, mv) in moves.iter().enumerate() {
+        // Each move is exactly two characters, so indices map back directly.
+        let position = index * 2;
+        if state.winner().is_some() {
+            return Err(GameError::ParseMove {
+                position,
+                reason: ParseMoveError::MoveAfterGameOver,
+            });
+        }
+        if mv.player != state.to_move {
+            return Err(GameError::ParseMove {
+                position,
+                reason: ParseMoveError::NonAlternatingTurn {
+                    expected: state.to_move,
+                    found: mv.player,
+                },
+            });
+        }
+        if state.force_play(mv.player, mv.column).is_err() {
+            return Err(GameError::ParseMove {
+                position: position + 1,
+                reason: ParseMoveError::ColumnFull { column: mv.column },
+            });
         }
     }
+    Ok(moves)
+}
+
+/// Where a history string first goes wrong, in a shape UIs can highlight.
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct HistoryDiagnostic {
+    /// Zero-based character offset into the history string.
+    pub position: usize,
+    /// Zero-based index of the move containing that character.
+    pub move_index: usize,
+    pub reason: String,
+    /// `reason` in structured form, absent for errors found on replay.
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub detail: Option<ParseMoveError>,
+}
 
-    best_col.ok_or(GameError::NoMoves)
+/// Cheap strict validation: no search, just parsing and replay.
+pub fn validate_history(history: &str) -> Result<GameState, HistoryDiagnostic> {
+    let diagnostic = |err: GameError| match err {
+        GameError::ParseMove { position, reason } => HistoryDiagnostic {
+            position,
+            move_index: position / 2,
+            reason: reason.to_string(),
+            detail: Some(reason),
+        },
+        other => HistoryDiagnostic {
+            position: 0,
+            move_index: 0,
+            reason: other.to_string(),
+            detail: None,
+        },
+    };
+    let moves = parse_history_strict(history).map_err(diagnostic)?;
+    GameState::from_history(&moves).map_err(diagnostic)
 }
 
-fn negamax(state: &GameState, depth: usize, mut alpha: i32, beta: i32, player: Player) -> i32 {
-    if depth == 0 || state.is_full() {
-        return evaluate(state, player);
-    }
+pub fn best_move(request: MoveRequest) -> Result<MoveResponse, GameError> {
+    let (mut state, depth) = prepare(&request)?;
+    let (column, _) = Search::default()
+        .root(&mut state, depth, None)?
+        .expect("unlimited searches always finish");
+    Ok(MoveResponse::new(column, depth))
+}
 
-    let mut best = i32::MIN / 2;
+/// `best_move` on `table` (a private one when `None`), reporting its single
+/// full-depth pass to `on_done`. Without the shallow passes of
+/// `best_move_within` it cannot be stopped early, but it answers sooner when
+/// there is no deadline to stop for.
+pub fn best_move_with_table(
+    request: MoveRequest,
+    table: Option<Arc<TranspositionTable>>,
+    on_done: impl FnOnce(&SearchInfo),
+) -> Result<MoveResponse, GameError> {
+    let (mut state, depth) = prepare(&request)?;
+    let limits = SearchLimits {
+        table,
+        ..SearchLimits::default()
+    };
+    let mut search = Search::new(&limits, &SearchOptions::new(depth as u8));
+    let (column, score) = search
+        .root(&mut state, depth, None)?
+        .expect("unlimited searches always finish");
+    on_done(&SearchInfo {
+        depth,
+        column,
+        score,
+        nodes: search.nodes,
+        pv: search.line(depth).to_vec(),
+    });
+    Ok(MoveResponse::new(column, depth))
+}
 
-    for col in state.legal_moves() {
-        let mut child = state.clone();
-        let outcome = child.play(col).expect("legal move must succeed");
-        let score = if outcome.won {
-            WIN_SCORE
-        } else if child.is_full() {
-            0
-        } else {
-            -negamax(&child, depth - 1, -beta, -alpha, player.opponent())
-        };
-        best = best.max(score);
-        alpha = alpha.max(score);
-        if alpha >= beta {
+/// Progress snapshot emitted after each completed iterative-deepening pass.
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct SearchInfo {
+    pub depth: usize,
+    pub column: usize,
+    pub score: i32,
+    /// Nodes visited so far, summed over all iterations.
+    pub nodes: u64,
+    /// The expected line, starting with `column`. It can stop short of
+    /// `depth` where the game ends or a reply was cut off by pruning.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub pv: Vec<usize>,
+}
+
+/// Same answer as `best_move`, but searched at depth 1, 2, ... up to the level
+/// so callers can report progress. The final pass is identical to the single
+/// fixed-depth search, so the extra shallow passes only cost time.
+pub fn best_move_with_progress(
+    request: MoveRequest,
+    on_iteration: impl FnMut(&SearchInfo),
+) -> Result<MoveResponse, GameError> {
+    best_move_within(request, &SearchLimits::default(), on_iteration)
+}
+
+/// Iterative deepening that stops at `limits`. When cut short it returns the
+/// move of the deepest completed iteration, with `depth` saying how deep that
+/// was, and marks the answer `truncated`. The interrupted iteration's leading
+/// move is only reported as `best_so_far`: alpha-beta bounds from an
+/// unfinished pass are not trustworthy enough to play.
+pub fn best_move_within(
+    request: MoveRequest,
+    limits: &SearchLimits,
+    mut on_iteration: impl FnMut(&SearchInfo),
+) -> Result<MoveResponse, GameError> {
+    let (mut state, depth) = prepare(&request)?;
+    let mut search = Search::new(limits, &SearchOptions::new(depth as u8));
+    let mut best = None;
+    // Set when a limit stopped the search, with what the cut pass had found.
+    let mut cut = None;
+    for d in 1..=depth {
+        search.enforce_limits = d > 1;
+        if search.enforce_limits && !limits.may_deepen(search.nodes) {
+            cut = Some(None);
             break;
         }
+        // The hint's score is for the full depth; shallower passes only
+        // take its move first.
+        let guess = limits.hint.filter(|_| d == depth).map(|hint| hint.score);
+        let Some((column, score)) = search.root(&mut state, d, guess)? else {
+            cut = Some(search.partial_best);
+            break;
+        };
+        best = Some(MoveResponse::new(column, d));
+        on_iteration(&SearchInfo {
+            depth: d,
+            column,
+            score,
+            nodes: search.nodes,
+            pv: search.line(d).to_vec(),
+        });
     }
-    best
+    let best = best.ok_or(GameError::NoMoves)?;
+    Ok(match cut {
+        Some(best_so_far) => best.cut_short(depth, best_so_far),
+        None => best,
+    })
 }
 
-fn evaluate(state: &GameState, player: Player) -> i32 {
-    let mine = state.bits(player);
-    let theirs = state.bits(player.opponent());
-    if has_won(mine) {
-        return WIN_SCORE;
-    }
-    if has_won(theirs) {
-        return -WIN_SCORE;
-    }
-
-    let center_bits = center_mask();
-    let center_score =
-        3 * (mine & center_bits).count_ones() as i32 - 3 * (theirs & center_bits).count_ones() as i32;
+/// Score every legal column at `depth` from the side to move's point of view.
+/// Unlike `best_move` each column gets a full window, so the scores are exact
+/// (for this depth) rather than bounds, which is what comparing moves needs.
+pub fn score_columns(state: &GameState, depth: usize) -> Vec<(usize, i32)> {
+    let mut scores = Search::default()
+        .root_scores(state, depth)
+        .expect("unlimited searches always finish");
+    scores.sort_unstable_by_key(|&(col, _)| col);
+    scores
+}
 
-    let mut score = center_score;
-    for mask in WIN_MASKS.iter() {
-        let mine_count = (mine & mask).count_ones();
-        let theirs_count = (theirs & mask).count_ones();
-        if mine_count > 0 && theirs_count > 0 {
-            continue; // blocked line
-        }
-        match (mine_count, theirs_count) {
-            (3, 0) => score += 50,
-            (2, 0) => score += 10,
-            (1, 0) => score += 2,
-            (0, 3) => score -= 50,
-            (0, 2) => score -= 10,
-            (0, 1) => score -= 2,
-            _ => {}
+/// The line the engine expects from this position: its move at the request's
+/// level, then the best reply one ply shallower, and so on until the depth
+/// runs out or the game ends. A depth-`d` score is backed up from exactly these
+/// re-searches, so this is the line the score of `best_move` came from.
+pub fn principal_variation(request: MoveRequest) -> Result<Vec<usize>, GameError> {
+    let (mut state, depth) = prepare(&request)?;
+    let mut line = Vec::new();
+    for d in (1..=depth).rev() {
+        if state.winner().is_some() || state.is_full() {
+            break;
         }
+        let (column, _) = Search::default()
+            .root(&mut state, d, None)?
+            .expect("unlimited searches always finish");
+        state.play(column)?;
+        line.push(column);
     }
-    score
+    Ok(line)
 }
 
-fn center_mask() -> u64 {
-    let mut mask = 0;
-    let col = WIDTH / 2;
-    for row in 0..HEIGHT {
-        mask |= 1u64 << (col * COL_HEIGHT + row);
-    }
-    mask
+/// A position examined at one depth by `analyze`.
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Analysis {
+    pub status: GameStatus,
+    pub to_move: Player,
+    pub winner: Option<Player>,
+    pub depth: usize,
+    /// Every legal column, left to right, scored from the side to move's view
+    /// (±1 000 000 is a forced win or loss). Empty once the game is over.
+    pub scores: Vec<ScoredMove>,
+    /// The move `best_move` plays, followed by the expected replies.
+    pub line: Vec<usize>,
+    pub engine: EngineInfo,
+    /// What each scored move does, in words; filled in by
+    /// `explain::analyze_explained`.
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Vec::is_empty")
+    )]
+    pub explanations: Vec<explain::MoveExplanation>,
 }
 
-fn has_won(bits: u64) -> bool {
-    // Vertical
-    let mut m = bits & (bits >> 1);
-    if (m & (m >> 2)) != 0 {
-        return true;
+impl Analysis {
+    pub fn best(&self) -> Option<usize> {
+        self.line.first().copied()
     }
-    // Horizontal
-    m = bits & (bits >> COL_HEIGHT);
-    if (m & (m >> (2 * COL_HEIGHT))) != 0 {
-        return true;
-    }
-    // Diagonal /
-    m = bits & (bits >> (COL_HEIGHT - 1));
-    if (m & (m >> (2 * (COL_HEIGHT - 1)))) != 0 {
-        return true;
-    }
-    // Diagonal \
-    m = bits & (bits >> (COL_HEIGHT + 1));
-    if (m & (m >> (2 * (COL_HEIGHT + 1)))) != 0 {
-        return true;
-    }
-    false
 }
 
-fn generate_win_masks() -> Vec<u64> {
-    let mut masks = Vec::new();
-    // Horizontal
-    for row in 0..HEIGHT {
-        for col in 0..=WIDTH - 4 {
-            let mut mask = 0;
-            for offset in 0..4 {
-                mask |= bit_for(col + offset, row);
-            }
-            masks.push(mask);
-        }
-    }
-    // Vertical
-    for col in 0..WIDTH {
-        for row in 0..=HEIGHT - 4 {
-            let mut mask = 0;
-            for offset in 0..4 {
-                mask |= bit_for(col, row + offset);
-            }
-            masks.push(mask);
-        }
-    }
-    // Diagonal \
-    for col in 0..=WIDTH - 4 {
-        for row in 0..=HEIGHT - 4 {
-            let mut mask = 0;
-            for offset in 0..4 {
-                mask |= bit_for(col + offset, row + offset);
-            }
-            masks.push(mask);
-        }
-    }
-    // Diagonal /
-    for col in 0..=WIDTH - 4 {
-        for row in 3..HEIGHT {
-            let mut mask = 0;
-            for offset in 0..4 {
-                mask |= bit_for(col + offset, row - offset);
-            }
-            masks.push(mask);
-        }
+/// Scores for every column, the principal variation and the game status of
+/// `request.position`, searched at `request.level`.
+pub fn analyze(request: MoveRequest) -> Result<Analysis, GameError> {
+    let (state, depth) = prepare(&request)?;
+    let over = state.status() != GameStatus::InProgress;
+    let scores = if over {
+        Vec::new()
+    } else {
+        score_columns(&state, depth)
+            .into_iter()
+            .map(|(column, score)| ScoredMove { column, score })
+            .collect()
+    };
+    Ok(Analysis {
+        status: state.status(),
+        to_move: state.to_move(),
+        winner: state.winner(),
+        depth,
+        scores,
+        line: principal_variation(request)?,
+        engine: engine_info(),
+        explanations: Vec::new(),
+    })
+}
+
+fn prepare(request: &MoveRequest) -> Result<(GameState, usize), GameError> {
+    if !(1..=15).contains(&request.level) {
+        return Err(GameError::DepthOutOfRange(request.level));
     }
-    masks
+    Ok((request.state()?, request.level as usize))
 }
 
 fn bit_for(col: usize, row: usize) -> u64 {
     1u64 << (col * COL_HEIGHT + row)
 }
 
+/// Every cell of the board, without the sentinel row.
+const BOARD_MASK: u64 = {
+    let column = (1u64 << HEIGHT) - 1;
+    let mut mask = 0;
+    let mut col = 0;
+    while col < WIDTH {
+        mask |= column << (col * COL_HEIGHT);
+        col += 1;
+    }
+    mask
+};
+
+/// Cells that would complete a line of four with `bits`, occupied or off
+/// the board ones included. Three discs and a gap, shifted along each
+/// direction: up, across and both diagonals.
+fn winning_cells(bits: u64) -> u64 {
+    let mut cells = (bits << 1) & (bits << 2) & (bits << 3);
+    for step in [COL_HEIGHT, COL_HEIGHT - 1, COL_HEIGHT + 1] {
+        let pair = (bits << step) & (bits << (2 * step));
+        cells |= pair & (bits << (3 * step));
+        cells |= pair & (bits >> step);
+        let pair = (bits >> step) & (bits >> (2 * step));
+        cells |= pair & (bits << step);
+        cells |= pair & (bits >> (3 * step));
+    }
+    cells
+}
+
+/// Swap column `c` with column `WIDTH - 1 - c`, sentinel bits included.
+fn mirror_columns(bits: u64) -> u64 {
+    let column_mask = (1u64 << COL_HEIGHT) - 1;
+    (0..WIDTH).fold(0, |mirrored, col| {
+        let column = (bits >> (col * COL_HEIGHT)) & column_mask;
+        mirrored | column << ((WIDTH - 1 - col) * COL_HEIGHT)
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::eval::evaluate;
 
     #[test]
     fn parse_and_replay_history() {
@@ -419,6 +1234,176 @@ mod tests {
         assert_eq!(state.to_move, Player::Blue);
     }
 
+    #[test]
+    fn format_history_round_trips() {
+        let history = "R3B3R4B2";
+        assert_eq!(format_history(&parse_history(history).unwrap()), history);
+    }
+
+    #[test]
+    fn position_keys_distinguish_positions() {
+        let key = |h: &str| {
+            GameState::from_history(&parse_history(h).unwrap())
+                .unwrap()
+                .key()
+        };
+        assert_eq!(key("R3B4R2"), key("R2B4R3"));
+        assert_ne!(key("R3B4"), key("R4B3"));
+        assert_ne!(key(""), key("R0B0"));
+    }
+
+    #[test]
+    fn zobrist_hash_follows_play_and_undo() {
+        let state = |h: &str| GameState::from_history(&parse_history(h).unwrap()).unwrap();
+        assert_eq!(state("R3B4R2").hash(), state("R2B4R3").hash());
+        assert_ne!(state("R3B4").hash(), state("R4B3").hash());
+        assert_ne!(state("").hash(), state("B3").hash());
+        assert_ne!(
+            GameState::empty(Player::Red).hash(),
+            GameState::empty(Player::Blue).hash()
+        );
+
+        let mut game = state("R3B3R2");
+        let before = game.clone();
+        game.play(4).unwrap();
+        game.play(4).unwrap();
+        assert_eq!(game, state("R3B3R2B4R4"));
+        assert_eq!(game.undo(4), Some(Player::Red));
+        assert_eq!(game.undo(4), Some(Player::Blue));
+        assert_eq!(game, before);
+        assert_eq!(game.hash(), before.hash());
+        assert_eq!(game.undo(0), None);
+        assert_eq!(game.undo(WIDTH), None);
+    }
+
+    #[test]
+    fn mirror_images_share_canonical_keys() {
+        for seed in 0..100 {
+            let position = testing::Gen::new(seed).position(30);
+            let mirrored: Vec<TypedMove> = parse_history(&position.history)
+                .unwrap()
+                .into_iter()
+                .map(|mv| TypedMove {
+                    column: WIDTH - 1 - mv.column,
+                    ..mv
+                })
+                .collect();
+            let mirror = GameState::from_history(&mirrored).unwrap();
+            assert_eq!(mirror.key(), position.state.mirrored_key());
+            assert_eq!(mirror.canonical_key().0, position.state.canonical_key().0);
+        }
+    }
+
+    #[test]
+    fn consistency_check_holds_through_play_and_catches_broken_states() {
+        let mut game = GameState::empty(Player::Blue);
+        for column in [3, 3, 2, 4, 4, 0, 6, 3] {
+            game.play(column).unwrap();
+            game.assert_consistent();
+        }
+        game.undo(3).unwrap();
+        game.assert_consistent();
+
+        let out_of_turn = GameState::from_history(&parse_history("R3R3").unwrap()).unwrap();
+        assert!(out_of_turn
+            .check_consistent()
+            .unwrap_err()
+            .contains("discs against"));
+        let mut floating = game.clone();
+        floating.players[0] |= bit_for(5, 3);
+        floating.moves_played += 1;
+        assert!(floating
+            .check_consistent()
+            .unwrap_err()
+            .contains("column 5"));
+        let mut stale = game;
+        stale.hash ^= 1;
+        assert!(stale.check_consistent().unwrap_err().starts_with("hash"));
+    }
+
+    #[test]
+    fn shared_tables_do_not_change_answers() {
+        let table = Arc::new(TranspositionTable::new(1 << 12, tt::Replacement::TwoTier));
+        let limits = SearchLimits {
+            table: Some(table.clone()),
+            ..SearchLimits::default()
+        };
+        // Twice over a small table, so later searches meet full buckets and
+        // entries from other positions and earlier generations.
+        for history in ["", "R3B3", "R3B3R2B4R4", "B0R6B3R3B3", "", "R3B3R2B4R4"] {
+            let request = || MoveRequest {
+                position: history.to_string(),
+                level: 8,
+                first_player: None,
+            };
+            let mut fresh = Vec::new();
+            let mut shared = Vec::new();
+            best_move_with_progress(request(), |info| fresh.push(info.clone())).unwrap();
+            best_move_within(request(), &limits, |info| shared.push(info.clone())).unwrap();
+            let lines = |infos: &[SearchInfo]| -> Vec<_> {
+                infos
+                    .iter()
+                    .map(|i| (i.column, i.score, i.pv.clone()))
+                    .collect()
+            };
+            assert_eq!(lines(&shared), lines(&fresh), "{history:?}");
+        }
+        let stats = table.stats();
+        assert!(stats.hits > 0 && stats.hits <= stats.probes);
+        assert!(stats.occupancy > 0.5);
+        assert!(table.clear() > 1 << 11);
+        assert_eq!(table.stats().occupancy, 0.0);
+    }
+
+    #[test]
+    fn hints_do_not_change_answers() {
+        for history in [
+            "",
+            "R3B3",
+            "R3B3R2B4R4",
+            "B0R6B3R3B3",
+            "R3B2R3B3R3B4R2B3R4B4",
+        ] {
+            let request = || MoveRequest {
+                position: history.to_string(),
+                level: 7,
+                first_player: None,
+            };
+            let mut plain = None;
+            best_move_with_progress(request(), |info| plain = Some((info.column, info.score)))
+                .unwrap();
+            let (column, score) = plain.unwrap();
+            // Right and wrong moves, and scores near, far and on the mark.
+            for hint_column in [column, (column + 3) % WIDTH, 0] {
+                for hint_score in [score, score + 20, score - 400, WIN_SCORE] {
+                    let limits = SearchLimits {
+                        hint: Some(SearchHint {
+                            column: hint_column,
+                            score: hint_score,
+                        }),
+                        ..SearchLimits::default()
+                    };
+                    let mut hinted = None;
+                    best_move_within(request(), &limits, |info| {
+                        hinted = Some((info.column, info.score))
+                    })
+                    .unwrap();
+                    assert_eq!(hinted, plain, "{history:?} {hint_column} {hint_score}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn canonical_keys_identify_mirror_images() {
+        let state = |h: &str| GameState::from_history(&parse_history(h).unwrap()).unwrap();
+        let (left, right) = (state("R0B1R1"), state("R6B5R5"));
+        assert_eq!(left.mirrored_key(), right.key());
+        assert_eq!(left.canonical_key().0, right.canonical_key().0);
+        assert_ne!(left.canonical_key().1, right.canonical_key().1);
+        assert_eq!(state("R3").canonical_key(), (state("R3").key(), false));
+    }
+
     #[test]
     fn detect_vertical_win() {
         let history = "B0R1B0R1B0R1B0";
@@ -427,15 +1412,477 @@ mod tests {
         assert!(has_won(state.bits(Player::Blue)));
     }
 
+    #[test]
+    fn counts_threats_a_move_would_leave() {
+        let state =
+            |history: &str| GameState::from_history(&parse_history(history).unwrap()).unwrap();
+        let open = state("R1B1R2B2");
+        assert_eq!(open.threats_after(Player::Red, 3), 2);
+        assert_eq!(open.threats_after(Player::Red, 0), 1);
+        assert_eq!(open.threats_after(Player::Red, 6), 0);
+        // Covered cells and cells off the board do not count.
+        assert_eq!(state("R0B1R0B1").threats_after(Player::Red, 0), 1);
+        assert_eq!(state("R0B3R1B1").threats_after(Player::Red, 2), 0);
+    }
+
+    #[test]
+    fn explain_matches_evaluate() {
+        for history in ["", "R3", "B2R2B1R3", "R3B3R4B2R2B4R1"] {
+            let state = GameState::from_history(&parse_history(history).unwrap()).unwrap();
+            let breakdown = evaluate_explain(&state);
+            assert_eq!(breakdown.total, evaluate(&state, state.to_move()), "{history}");
+        }
+    }
+
+    #[test]
+    fn explain_reports_threats_and_parity() {
+        // Red (first player) holds three on the bottom row; the threat cell at
+        // column 3, row 0 sits on Red's favourable parity row.
+        let state = GameState::from_history(&parse_history("R0B0R1B1R2B6").unwrap()).unwrap();
+        let breakdown = evaluate_explain(&state);
+        assert_eq!(breakdown.red.threes.count, 1);
+        assert_eq!(breakdown.red.parity.count, 1);
+        assert_eq!(breakdown.blue.threes.count, 0);
+        assert_eq!(breakdown.winner, None);
+    }
+
+    #[test]
+    fn threat_terms_count_playable_and_buried_threats() {
+        // Red's two on the bottom row becomes a threat from four squares;
+        // Blue's only threat-making square is on top of its pair in column 6.
+        let state = GameState::from_history(&parse_history("R2B6R3B6").unwrap()).unwrap();
+        let breakdown = evaluate_explain(&state);
+        assert_eq!(breakdown.red.mobility.count, 4);
+        assert_eq!(breakdown.blue.mobility.count, 1);
+        assert_eq!(breakdown.total, evaluate(&state, Player::Red));
+        let mobile = EvalWeights {
+            mobility: 5,
+            ..EvalWeights::default()
+        };
+        assert_eq!(
+            eval::evaluate_weighted(&state, Player::Red, &mobile),
+            evaluate(&state, Player::Red) + 5 * (4 - 1)
+        );
+
+        // Red's threats at both ends of row 1 sit above Blue's on row 0.
+        let state = GameState::from_history(&parse_history("B1R1B2R2B3R3").unwrap()).unwrap();
+        let breakdown = evaluate_explain(&state);
+        assert_eq!(breakdown.red.threes.count, 2);
+        assert_eq!(breakdown.red.buried.count, 2);
+        assert_eq!(breakdown.blue.buried.count, 0);
+        let discounted = EvalWeights {
+            buried: -30,
+            ..EvalWeights::default()
+        };
+        assert_eq!(
+            eval::evaluate_weighted(&state, Player::Red, &discounted),
+            evaluate(&state, Player::Red) - 30 * 2
+        );
+        assert_ne!(
+            discounted.fingerprint(),
+            EvalWeights::default().fingerprint()
+        );
+    }
+
+    #[test]
+    fn presets_play_in_different_styles() {
+        assert_eq!(EvalPreset::Balanced.weights(), EvalWeights::default());
+        let choices = |history: &str| {
+            let state = GameState::from_history(&parse_history(history).unwrap()).unwrap();
+            EvalPreset::ALL.map(|preset| {
+                let options = SearchOptions {
+                    weights: preset.weights(),
+                    ..SearchOptions::new(8)
+                };
+                search(&state, &options, &SearchLimits::default(), |_| {})
+                    .unwrap()
+                    .column
+            })
+        };
+        // Aggressive, balanced and defensive, in that order. Each has a
+        // single best move here, so no tie is broken at random.
+        assert_eq!(choices("R2B4R6B3"), [2, 3, 4]);
+        assert_eq!(choices("R1B2"), [2, 5, 5]);
+
+        // The style stays with the side that searches: scores are not simply
+        // negated between the two sides.
+        let state = GameState::from_history(&parse_history("R2B4R6B3").unwrap()).unwrap();
+        let aggressive = EvalPreset::Aggressive.weights();
+        assert_ne!(
+            eval::evaluate_weighted(&state, Player::Red, &aggressive),
+            -eval::evaluate_weighted(&state, Player::Blue, &aggressive)
+        );
+    }
+
+    #[test]
+    fn heatmap_highlights_winning_cell() {
+        let state = GameState::from_history(&parse_history("R0B0R1B1R2").unwrap()).unwrap();
+        // Blue to move: every empty cell is present, and blocking Red's bottom
+        // threat is the hottest playable square.
+        let map = heatmap(&state);
+        assert_eq!(map.cells.len(), MAX_CELLS - 5);
+        let hottest = map
+            .cells
+            .iter()
+            .filter(|cell| cell.playable)
+            .max_by_key(|cell| cell.score)
+            .unwrap();
+        assert_eq!((hottest.column, hottest.row), (3, 0));
+        assert!(!map.get(0, 3).unwrap().playable);
+    }
+
+    #[test]
+    fn board_model_reports_winning_line() {
+        let state = GameState::from_history(&parse_history("B0R1B0R1B0R1B0").unwrap()).unwrap();
+        let board = state.board_model();
+        assert_eq!(board.status, GameStatus::Won);
+        assert_eq!(board.winner, Some(Player::Blue));
+        assert!(board.legal_columns.is_empty());
+        let line = board.winning_line.unwrap();
+        assert!(line.iter().all(|cell| cell.column == 0));
+        assert_eq!(board.grid[0][1], Some(Player::Red));
+        assert_eq!(board.grid[5][0], None);
+    }
+
+    #[test]
+    fn positions_work_as_map_keys() {
+        use std::collections::{BTreeSet, HashSet};
+        let state = |history| GameState::from_history(&parse_history(history).unwrap()).unwrap();
+        // The same position by two move orders, and its colour-swapped twin,
+        // which shares the key but not the side to move.
+        let histories = ["R3B2R4", "R4B2R3", "B3R2B4", "R3B3"];
+        let hashed: HashSet<_> = histories.iter().map(|h| state(h)).collect();
+        assert_eq!(hashed.len(), 3);
+        assert!(hashed.contains(&state("R4B2R3")));
+        let ordered: Vec<_> = histories
+            .iter()
+            .map(|h| state(h))
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect();
+        assert_eq!(ordered.len(), 3);
+        assert!(ordered
+            .windows(2)
+            .all(|pair| pair[0].key() <= pair[1].key()));
+        let twins = (state("R3B2R4"), state("B3R2B4"));
+        assert_eq!(twins.0.key(), twins.1.key());
+        assert_eq!(twins.1.cmp(&twins.0), std::cmp::Ordering::Less);
+    }
+
+    #[test]
+    fn diff_reports_added_and_removed_discs() {
+        let state = |history| GameState::from_history(&parse_history(history).unwrap()).unwrap();
+        let (before, after) = (state("R3B3"), state("R3B3R4B3"));
+        assert!(before.diff(&before).is_empty());
+        let added = |column, row, player| CellChange {
+            column,
+            row,
+            before: None,
+            after: Some(player),
+        };
+        assert_eq!(
+            before.diff(&after),
+            [added(3, 2, Player::Blue), added(4, 0, Player::Red)]
+        );
+        let removed: Vec<_> = after
+            .diff(&before)
+            .iter()
+            .map(|c| (c.column, c.row, c.before, c.after))
+            .collect();
+        assert_eq!(
+            removed,
+            [
+                (3, 2, Some(Player::Blue), None),
+                (4, 0, Some(Player::Red), None)
+            ]
+        );
+        // Swapped colours show as one change per cell.
+        let swapped = state("R0").diff(&state("B0"));
+        assert_eq!(swapped.len(), 1);
+        assert_eq!(
+            (swapped[0].before, swapped[0].after),
+            (Some(Player::Red), Some(Player::Blue))
+        );
+    }
+
+    #[test]
+    fn strict_parse_rejects_rule_violations() {
+        assert!(parse_history_strict("R3B3R4").is_ok());
+        let repeated = validate_history("R3B3B4").unwrap_err();
+        assert_eq!((repeated.position, repeated.move_index), (4, 2));
+        assert_eq!(
+            repeated.detail,
+            Some(ParseMoveError::NonAlternatingTurn {
+                expected: Player::Red,
+                found: Player::Blue
+            })
+        );
+        let overflow = validate_history("R0B0R0B0R0B0R0").unwrap_err();
+        assert_eq!((overflow.position, overflow.move_index), (13, 6));
+        assert_eq!(
+            overflow.detail,
+            Some(ParseMoveError::ColumnFull { column: 0 })
+        );
+        let after_win = validate_history("B0R1B0R1B0R1B0R2").unwrap_err();
+        assert_eq!(after_win.move_index, 7);
+        assert_eq!(after_win.detail, Some(ParseMoveError::MoveAfterGameOver));
+        // The lenient parser still accepts all of these.
+        assert!(parse_history("R3B3B4").is_ok());
+    }
+
+    #[test]
+    fn progress_reports_every_depth_and_matches_best_move() {
+        let request = MoveRequest {
+            position: "R3B3R4B2".to_string(),
+            level: 6,
+            first_player: None,
+        };
+        let mut infos = Vec::new();
+        let res =
+            best_move_with_progress(request.clone(), |info| infos.push(info.clone())).unwrap();
+        assert_eq!(
+            infos.iter().map(|i| i.depth).collect::<Vec<_>>(),
+            (1..=6).collect::<Vec<_>>()
+        );
+        assert!(infos.windows(2).all(|w| w[0].nodes < w[1].nodes));
+        assert_eq!(infos.last().unwrap().column, res.column);
+        assert_eq!(best_move(request).unwrap(), res);
+    }
+
+    #[test]
+    fn single_pass_on_a_table_matches_deepening() {
+        let table = Arc::new(TranspositionTable::new(1 << 16, tt::Replacement::TwoTier));
+        for position in ["", "R3B3R4B2", "B3R3B2R4B3R3B3R4B2R2B1R0B5"] {
+            let request = MoveRequest {
+                position: position.to_string(),
+                level: 7,
+                first_player: None,
+            };
+            let mut deepened = Vec::new();
+            let expected =
+                best_move_with_progress(request.clone(), |info| deepened.push(info.clone()))
+                    .unwrap();
+            let mut done = None;
+            let mv = best_move_with_table(request, Some(table.clone()), |info| {
+                done = Some(info.clone())
+            })
+            .unwrap();
+            assert_eq!(mv, expected, "{position}");
+            let (done, last) = (done.unwrap(), deepened.last().unwrap());
+            assert_eq!((done.depth, done.column), (7, last.column), "{position}");
+        }
+    }
+
+    #[test]
+    fn deadline_returns_last_completed_depth() {
+        let request = MoveRequest {
+            position: "R3B3R4".to_string(),
+            level: 15,
+            first_player: None,
+        };
+        let past = SearchLimits::with_deadline(Instant::now());
+        let res = best_move_within(request.clone(), &past, |_| {}).unwrap();
+        assert_eq!(res.depth, Some(1));
+        // Stopped between iterations: nothing of depth 2 to show.
+        assert!(res.truncated);
+        assert_eq!((res.target_depth, res.best_so_far), (Some(15), None));
+        let full = best_move_within(
+            MoveRequest {
+                level: 4,
+                ..request.clone()
+            },
+            &SearchLimits::default(),
+            |_| {},
+        );
+        assert!(!full.unwrap().truncated);
+
+        let cancel = CancelToken::new();
+        let limits = SearchLimits {
+            cancel: Some(cancel.clone()),
+            ..SearchLimits::default()
+        };
+        let res = best_move_within(request.clone(), &limits, |info| {
+            if info.depth == 3 {
+                cancel.cancel();
+            }
+        })
+        .unwrap();
+        assert_eq!(res.depth, Some(3));
+
+        // A node budget stops at the same depth every time.
+        let capped = || {
+            let mut nodes = 0;
+            let res = best_move_within(
+                request.clone(),
+                &SearchLimits::with_max_nodes(50_000),
+                |info| nodes = info.nodes,
+            )
+            .unwrap();
+            (res, nodes)
+        };
+        let (res, nodes) = capped();
+        assert!(res.depth < Some(15) && nodes < 50_000);
+        assert_eq!(capped(), (res.clone(), nodes));
+        // Cut inside an iteration, which had finished some root moves.
+        assert!(res.truncated && res.best_so_far.is_some());
+    }
+
+    #[test]
+    fn node_budgets_give_the_same_answer_everywhere() {
+        // Fixed figures, so a change of platform, toolchain or hasher that
+        // moved a budgeted search would fail here rather than in the field.
+        let run = |options: &SearchOptions| {
+            let mut last = None;
+            let state = GameState::from_history(&parse_history("R3B3R2").unwrap()).unwrap();
+            let limits = SearchLimits::with_max_nodes(40_000);
+            let outcome = options::search(&state, options, &limits, |info| {
+                last = Some((info.depth, info.column, info.score, info.nodes))
+            })
+            .unwrap();
+            (outcome.column, last.unwrap())
+        };
+        let plain = SearchOptions::new(15);
+        let salted = SearchOptions {
+            late_move_reductions: true,
+            ..SearchOptions::new(15)
+        };
+        assert_eq!(run(&plain), (1, (8, 1, -42, 23_760)));
+        assert_eq!(run(&salted), (4, (9, 4, 26, 31_869)));
+    }
+
+    #[test]
+    fn principal_variation_starts_with_the_best_move() {
+        let request = |position: &str, level| MoveRequest {
+            position: position.to_string(),
+            level,
+            first_player: None,
+        };
+        let line = principal_variation(request("R3B3", 6)).unwrap();
+        assert_eq!(line.len(), 6);
+        assert_eq!(line[0], best_move(request("R3B3", 6)).unwrap().column);
+        // The line stops at the winning move.
+        assert_eq!(
+            principal_variation(request("R0B1R0B1R0B1", 5)).unwrap(),
+            vec![0]
+        );
+    }
+
+    #[test]
+    fn progress_reports_the_line_behind_each_iteration() {
+        let request = |position: &str| MoveRequest {
+            position: position.to_string(),
+            level: 6,
+            first_player: None,
+        };
+        let mut infos = Vec::new();
+        best_move_with_progress(request("R3B3"), |info| infos.push(info.clone())).unwrap();
+        for info in &infos {
+            assert_eq!(info.pv[0], info.column);
+            assert!(info.pv.len() <= info.depth);
+        }
+        let last = infos.last().unwrap();
+        assert_eq!(last.pv, principal_variation(request("R3B3")).unwrap());
+
+        best_move_with_progress(request("R0B1R0B1R0B1"), |info| {
+            assert_eq!(info.pv, vec![0]);
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn analysis_scores_every_column() {
+        let request = |position: &str| MoveRequest {
+            position: position.to_string(),
+            level: 4,
+            first_player: None,
+        };
+        let analysis = analyze(request("R0B1R0B1R0B1")).unwrap();
+        assert_eq!(analysis.status, GameStatus::InProgress);
+        assert_eq!(analysis.scores.len(), 7);
+        assert_eq!(analysis.scores[0].score, WIN_SCORE);
+        assert!(analysis.scores[1..].iter().all(|m| m.score < WIN_SCORE));
+        assert_eq!(analysis.best(), Some(0));
+
+        let finished = analyze(request("R0B1R0B1R0B1R0")).unwrap();
+        assert_eq!(finished.winner, Some(Player::Red));
+        assert!(finished.scores.is_empty() && finished.best().is_none());
+    }
+
+    #[test]
+    fn no_std_core_chooses_the_same_moves() {
+        for history in ["", "R3B3", "R3B2R4B5", "B0R6B3R3B3"] {
+            let moves = parse_history(history).unwrap();
+            let mut board = connect4_core::Board::new(match moves.first() {
+                Some(TypedMove {
+                    player: Player::Blue,
+                    ..
+                }) => connect4_core::Player::Blue,
+                _ => connect4_core::Player::Red,
+            });
+            for mv in &moves {
+                board.play(mv.column).unwrap();
+            }
+            for level in [1, 4, 7] {
+                let request = MoveRequest {
+                    position: history.to_string(),
+                    level,
+                    first_player: None,
+                };
+                let core = connect4_core::Search::new()
+                    .best_move(&board, usize::from(level))
+                    .unwrap();
+                assert_eq!(
+                    core.column,
+                    best_move(request).unwrap().column,
+                    "{history} {level}"
+                );
+            }
+        }
+    }
+
     #[test]
     fn rejects_bad_depth() {
         let res = best_move(MoveRequest {
             position: "".to_string(),
             level: 0,
+            first_player: None,
         });
         assert!(matches!(res, Err(GameError::DepthOutOfRange(_))));
     }
 
+    #[test]
+    fn requests_name_who_opens_an_empty_board() {
+        let request = |position: &str, first_player| MoveRequest {
+            position: position.to_string(),
+            level: 4,
+            first_player,
+        };
+        assert_eq!(request("", None).state().unwrap().to_move(), Player::Red);
+        let blue = request("", Some(Player::Blue)).state().unwrap();
+        assert_eq!(
+            (blue.to_move(), blue.first_player()),
+            (Player::Blue, Player::Blue)
+        );
+        assert_eq!(
+            best_move(request("", Some(Player::Blue))).unwrap().column,
+            3
+        );
+
+        // A history already says who opened; a contradiction is refused.
+        let state = request("B3R3", Some(Player::Blue)).state().unwrap();
+        assert_eq!(state.to_move(), Player::Blue);
+        assert!(matches!(
+            request("R3", Some(Player::Blue)).state(),
+            Err(GameError::ParseMove {
+                position: 0,
+                reason: ParseMoveError::NonAlternatingTurn {
+                    expected: Player::Blue,
+                    found: Player::Red,
+                },
+            })
+        ));
+    }
+
     #[test]
     fn parses_invalid_column() {
         let res = parse_history("R7");
@@ -448,6 +1895,7 @@ mod tests {
             // Red threatens a horizontal four on the bottom row; Blue must block at column 3 (0-based).
             position: "R0B0R1B1R2".to_string(),
             level: 5,
+            first_player: None,
         };
         let res = best_move(req).unwrap();
         assert_eq!(res.column, 3);
@@ -458,6 +1906,7 @@ mod tests {
         let res = best_move(MoveRequest {
             position: "B0R0B1R1B2R3B4R4B5R5B6R3B6R3".to_string(),
             level: 9,
+            first_player: None,
         })
         .unwrap();
         assert_eq!(res.column, 3);
@@ -469,6 +1918,7 @@ mod tests {
         let res = best_move(MoveRequest {
             position: "B0R3B1R4B2R5".to_string(),
             level: 8,
+            first_player: None,
         })
         .unwrap();
         assert_eq!(res.column, 6);
@@ -480,6 +1930,7 @@ mod tests {
         let res = best_move(MoveRequest {
             position: "B0R1B0R1B0R1".to_string(),
             level: 6,
+            first_player: None,
         })
         .unwrap();
         assert_eq!(res.column, 0);
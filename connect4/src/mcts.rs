@@ -0,0 +1,243 @@
+//! Monte Carlo tree search (UCT with random playouts).
+//!
+//! Plays very differently from alpha-beta: no heuristic, just statistics from
+//! random games, so it is weak at short tactics but has a style of its own.
+//! Playouts take an immediate win when one exists, which removes the most
+//! absurd misses at little cost.
+//!
+//! With a [`PolicyValue`] the search is AlphaZero's instead: a new leaf gets
+//! all its children at once, weighted by the policy's priors, and the
+//! policy's value replaces the playout. Children are then chosen by PUCT.
+use crate::{policy::PolicyValue, rng::Rng, GameState, Player, SearchLimits};
+
+/// Exploration constant; `sqrt(2)` is the textbook value for rewards in 0..=1.
+const EXPLORATION: f64 = std::f64::consts::SQRT_2;
+/// Weight of the priors against the observed rewards in PUCT.
+const PUCT_EXPLORATION: f64 = 1.5;
+/// Iterations between limit checks (a power of two).
+const LIMIT_CHECK_INTERVAL: u32 = 64;
+
+struct Node {
+    column: usize,
+    /// Player who dropped the disc leading here; rewards are from their side.
+    mover: Player,
+    parent: Option<usize>,
+    children: Vec<usize>,
+    untried: Vec<usize>,
+    visits: u32,
+    reward: f64,
+    /// Set when the move ended the game: its reward for `mover`.
+    terminal: Option<f64>,
+    /// The policy's normalised prior for this move; unused without one.
+    prior: f64,
+}
+
+impl Node {
+    /// The node for `column` played from `board`, which it advances.
+    fn child(board: &mut GameState, column: usize, parent: usize, prior: f64) -> Self {
+        let mover = board.to_move;
+        let outcome = board.play(column).expect("untried moves are legal");
+        let terminal = if outcome.won {
+            Some(1.0)
+        } else if board.is_full() {
+            Some(0.5)
+        } else {
+            None
+        };
+        Self {
+            column,
+            mover,
+            parent: Some(parent),
+            children: Vec::new(),
+            untried: if terminal.is_some() {
+                Vec::new()
+            } else {
+                board.legal_moves()
+            },
+            visits: 0,
+            reward: 0.0,
+            terminal,
+            prior,
+        }
+    }
+}
+
+/// Visit statistics for each root move after the search.
+pub(crate) struct RootStats {
+    pub column: usize,
+    pub visits: u32,
+    /// Mean reward for the side to move, 0 (loss) to 1 (win).
+    pub value: f64,
+}
+
+pub(crate) struct MctsResult {
+    pub children: Vec<RootStats>,
+    /// Deepest ply below the root that the tree reached.
+    pub depth: usize,
+}
+
+pub(crate) fn search(
+    state: &GameState,
+    iterations: u32,
+    limits: &SearchLimits,
+    rng: &mut Rng,
+    policy: Option<&dyn PolicyValue>,
+) -> MctsResult {
+    let mut nodes = vec![Node {
+        column: usize::MAX,
+        mover: state.to_move.opponent(),
+        parent: None,
+        children: Vec::new(),
+        untried: state.legal_moves(),
+        visits: 0,
+        reward: 0.0,
+        terminal: None,
+        prior: 1.0,
+    }];
+    let mut depth = 0;
+    let mut done = 0;
+    // At least one iteration, so there is always a move to report.
+    while done < iterations.max(1) {
+        let stop = done > 0
+            && (limits.out_of_nodes(u64::from(done))
+                || done & (LIMIT_CHECK_INTERVAL - 1) == 0 && limits.exceeded(u64::from(done)));
+        if stop {
+            break;
+        }
+        done += 1;
+        let mut board = state.clone();
+        let mut id = 0;
+        let mut ply = 0;
+        // Selection: descend through fully expanded nodes.
+        while nodes[id].untried.is_empty() && nodes[id].terminal.is_none() {
+            let chosen = match policy {
+                Some(_) => best_puct_child(&nodes, id),
+                None => best_child(&nodes, id),
+            };
+            let Some(child) = chosen else {
+                break;
+            };
+            board
+                .play(nodes[child].column)
+                .expect("tree moves are legal");
+            id = child;
+            ply += 1;
+        }
+        // Expansion: one random child, or with a policy all of them.
+        let mut value = None;
+        if nodes[id].terminal.is_none() && !nodes[id].untried.is_empty() {
+            match policy {
+                None => {
+                    let pick = rng.below(nodes[id].untried.len());
+                    let column = nodes[id].untried.swap_remove(pick);
+                    nodes.push(Node::child(&mut board, column, id, 1.0));
+                    let child = nodes.len() - 1;
+                    nodes[id].children.push(child);
+                    id = child;
+                    ply += 1;
+                }
+                Some(policy) => {
+                    let (priors, leaf_value) = policy.evaluate(&board);
+                    value = Some(leaf_value.clamp(-1.0, 1.0));
+                    let columns = std::mem::take(&mut nodes[id].untried);
+                    let total: f64 = columns.iter().map(|&c| priors[c].max(0.0)).sum();
+                    let count = columns.len() as f64;
+                    for column in columns {
+                        let prior = if total > 0.0 {
+                            priors[column].max(0.0) / total
+                        } else {
+                            1.0 / count
+                        };
+                        let child = Node::child(&mut board.clone(), column, id, prior);
+                        nodes.push(child);
+                        let child = nodes.len() - 1;
+                        nodes[id].children.push(child);
+                    }
+                }
+            }
+        }
+        depth = depth.max(ply);
+        // Simulation, scored for the player who moved into the leaf.
+        let leaf_mover = nodes[id].mover;
+        let mut reward = match (nodes[id].terminal, value) {
+            (Some(reward), _) => reward,
+            // The value is for the side to move at the leaf.
+            (None, Some(value)) => (1.0 - value) / 2.0,
+            (None, None) => match playout(&mut board, rng) {
+                Some(winner) if winner == leaf_mover => 1.0,
+                Some(_) => 0.0,
+                None => 0.5,
+            },
+        };
+        // Backpropagation, flipping perspective at every level.
+        let mut cursor = Some(id);
+        while let Some(node) = cursor {
+            nodes[node].visits += 1;
+            nodes[node].reward += reward;
+            reward = 1.0 - reward;
+            cursor = nodes[node].parent;
+        }
+    }
+    let children = nodes[0]
+        .children
+        .iter()
+        .map(|&child| {
+            let node = &nodes[child];
+            RootStats {
+                column: node.column,
+                visits: node.visits,
+                value: node.reward / f64::from(node.visits.max(1)),
+            }
+        })
+        .collect();
+    MctsResult { children, depth }
+}
+
+fn best_child(nodes: &[Node], id: usize) -> Option<usize> {
+    let log_visits = f64::from(nodes[id].visits.max(1)).ln();
+    nodes[id].children.iter().copied().max_by(|&a, &b| {
+        let uct = |n: &Node| {
+            let visits = f64::from(n.visits.max(1));
+            n.reward / visits + EXPLORATION * (log_visits / visits).sqrt()
+        };
+        uct(&nodes[a]).total_cmp(&uct(&nodes[b]))
+    })
+}
+
+/// PUCT: the mean reward plus an exploration bonus proportional to the
+/// prior, shrinking as the child is visited. Unvisited children count as
+/// even.
+fn best_puct_child(nodes: &[Node], id: usize) -> Option<usize> {
+    let parent_visits = f64::from(nodes[id].visits.max(1)).sqrt();
+    nodes[id].children.iter().copied().max_by(|&a, &b| {
+        let puct = |n: &Node| {
+            let visits = f64::from(n.visits);
+            let mean = if n.visits == 0 {
+                0.5
+            } else {
+                n.reward / visits
+            };
+            mean + PUCT_EXPLORATION * n.prior * parent_visits / (1.0 + visits)
+        };
+        puct(&nodes[a]).total_cmp(&puct(&nodes[b]))
+    })
+}
+
+/// Random game to the end; returns the winner, `None` for a draw.
+fn playout(board: &mut GameState, rng: &mut Rng) -> Option<Player> {
+    loop {
+        let moves = board.legal_moves();
+        if moves.is_empty() {
+            return None;
+        }
+        let winning = moves.iter().copied().find(|&column| {
+            let mut trial = board.clone();
+            trial.play(column).is_ok_and(|outcome| outcome.won)
+        });
+        let column = winning.unwrap_or_else(|| moves[rng.below(moves.len())]);
+        let outcome = board.play(column).expect("legal move must succeed");
+        if outcome.won {
+            return Some(outcome.player);
+        }
+    }
+}
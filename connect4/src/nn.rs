@@ -0,0 +1,302 @@
+//! A learned evaluation: a small network that scores positions in place of
+//! the hand-tuned `EvalWeights`.
+//!
+//! The network has one hidden layer over 84 inputs, one per cell for the
+//! side to move's discs and one per cell for the opponent's. As in NNUE, an
+//! input is 0 or 1, so the first layer is just a sum of one weight row per
+//! disc, and everything runs in integers. Hidden units clip to
+//! `0..=ACTIVATION_MAX` and the output is divided by `OUTPUT_SCALE`, which
+//! gives evaluation units.
+//!
+//! Weights are read from a text file of whitespace-separated integers:
+//!
+//! ```text
+//! c4nn 1 <hidden>
+//! <84 × hidden input weights, input by input, each within i16>
+//! <hidden biases>
+//! <hidden output weights>
+//! <output bias>
+//! ```
+//!
+//! Inputs are numbered as `dataset::Sample::cells`: cell `row * 7 + column`,
+//! bottom row first, then the same again plus 42 for the opponent's discs.
+use std::{fmt, fs, io, path::Path, str::FromStr};
+
+use connect4_core::has_won;
+use thiserror::Error;
+
+use crate::{fnv1a, GameState, COL_HEIGHT, MAX_CELLS, WIDTH, WIN_SCORE};
+
+/// Inputs to the network: each cell once per side.
+const INPUTS: usize = 2 * MAX_CELLS;
+/// Largest hidden layer accepted; evaluation keeps the layer on the stack.
+pub const MAX_HIDDEN: usize = 256;
+/// A hidden unit's output is its sum clipped to `0..=ACTIVATION_MAX`.
+pub const ACTIVATION_MAX: i32 = 127;
+/// The output layer's sum is divided by this to give evaluation units.
+pub const OUTPUT_SCALE: i32 = 64;
+/// Scores are clipped well short of a forced result, so the search never
+/// mistakes an evaluation for one.
+const MAX_SCORE: i32 = WIN_SCORE / 100;
+
+const MAGIC: &str = "c4nn";
+const FORMAT_VERSION: u32 = 1;
+
+#[derive(Debug, Error)]
+pub enum NetworkError {
+    #[error("cannot read network file: {0}")]
+    Io(#[from] io::Error),
+    #[error("not a version {FORMAT_VERSION} network file")]
+    Header,
+    #[error("{hidden} hidden units; between 1 and {MAX_HIDDEN} are supported")]
+    Hidden { hidden: usize },
+    #[error("expected {expected} parameters, found {found}")]
+    Size { expected: usize, found: usize },
+    #[error("invalid parameter {token:?}")]
+    Value { token: String },
+}
+
+#[derive(Clone, PartialEq, Eq)]
+pub struct NnEval {
+    hidden: usize,
+    /// `INPUTS` rows of `hidden` weights.
+    input: Vec<i16>,
+    biases: Vec<i32>,
+    output: Vec<i32>,
+    output_bias: i32,
+    fingerprint: u64,
+}
+
+impl fmt::Debug for NnEval {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NnEval")
+            .field("hidden", &self.hidden)
+            .field("fingerprint", &format_args!("{:016x}", self.fingerprint))
+            .finish()
+    }
+}
+
+impl NnEval {
+    /// Read a weights file, normally once at startup.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, NetworkError> {
+        fs::read_to_string(path)?.parse()
+    }
+
+    pub fn hidden(&self) -> usize {
+        self.hidden
+    }
+
+    /// FNV-1a over the parameters, like `EvalWeights::fingerprint`.
+    pub fn fingerprint(&self) -> u64 {
+        self.fingerprint
+    }
+
+    /// Score of `state` from the side to move's point of view, ±1 000 000
+    /// for a won game as with the heuristic evaluation.
+    pub fn evaluate(&self, state: &GameState) -> i32 {
+        let player = state.to_move();
+        let mine = state.bits(player);
+        let theirs = state.bits(player.opponent());
+        if has_won(mine) {
+            return WIN_SCORE;
+        }
+        if has_won(theirs) {
+            return -WIN_SCORE;
+        }
+        let mut sums = [0; MAX_HIDDEN];
+        let sums = &mut sums[..self.hidden];
+        sums.copy_from_slice(&self.biases);
+        for (offset, mut bits) in [(0, mine), (MAX_CELLS, theirs)] {
+            while bits != 0 {
+                let bit = bits.trailing_zeros() as usize;
+                bits &= bits - 1;
+                let cell = bit % COL_HEIGHT * WIDTH + bit / COL_HEIGHT;
+                let row = &self.input[(offset + cell) * self.hidden..][..self.hidden];
+                for (sum, &weight) in sums.iter_mut().zip(row) {
+                    *sum += i32::from(weight);
+                }
+            }
+        }
+        let total = sums
+            .iter()
+            .zip(&self.output)
+            .fold(self.output_bias, |total, (&sum, &weight)| {
+                total + sum.clamp(0, ACTIVATION_MAX) * weight
+            });
+        (total / OUTPUT_SCALE).clamp(-MAX_SCORE, MAX_SCORE)
+    }
+}
+
+impl FromStr for NnEval {
+    type Err = NetworkError;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let mut tokens = text.split_whitespace();
+        let magic = tokens.next();
+        let version = tokens.next().and_then(|version| version.parse().ok());
+        if magic != Some(MAGIC) || version != Some(FORMAT_VERSION) {
+            return Err(NetworkError::Header);
+        }
+        let hidden = tokens.next().ok_or(NetworkError::Header)?;
+        let hidden: usize = hidden.parse().map_err(|_| NetworkError::Header)?;
+        if !(1..=MAX_HIDDEN).contains(&hidden) {
+            return Err(NetworkError::Hidden { hidden });
+        }
+        let values = tokens
+            .map(|token| {
+                token.parse::<i32>().map_err(|_| NetworkError::Value {
+                    token: token.to_string(),
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let expected = (INPUTS + 2) * hidden + 1;
+        if values.len() != expected {
+            return Err(NetworkError::Size {
+                expected,
+                found: values.len(),
+            });
+        }
+        let (input, rest) = values.split_at(INPUTS * hidden);
+        let (biases, rest) = rest.split_at(hidden);
+        let (output, output_bias) = rest.split_at(hidden);
+        let input = input
+            .iter()
+            .map(|&weight| {
+                i16::try_from(weight).map_err(|_| NetworkError::Value {
+                    token: weight.to_string(),
+                })
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            hidden,
+            input,
+            biases: biases.to_vec(),
+            output: output.to_vec(),
+            output_bias: output_bias[0],
+            fingerprint: fnv1a(values.iter().flat_map(|value| value.to_le_bytes())),
+        })
+    }
+}
+
+impl fmt::Display for NnEval {
+    /// The weights file format that `from_str` and `load` read.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{MAGIC} {FORMAT_VERSION} {}", self.hidden)?;
+        for row in self.input.chunks(self.hidden) {
+            writeln!(f, "{}", join(row))?;
+        }
+        writeln!(f, "{}", join(&self.biases))?;
+        writeln!(f, "{}", join(&self.output))?;
+        writeln!(f, "{}", self.output_bias)
+    }
+}
+
+fn join<T: ToString>(values: &[T]) -> String {
+    values
+        .iter()
+        .map(T::to_string)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{parse_history, search, EvalWeights, SearchLimits, SearchOptions};
+
+    /// Two hidden units counting each side's centre discs, so the network
+    /// scores like the heuristic with only a centre weight of 10.
+    fn center_counter() -> NnEval {
+        let mut text = format!("{MAGIC} {FORMAT_VERSION} 2\n");
+        for input in 0..INPUTS {
+            let (side, cell) = (input / MAX_CELLS, input % MAX_CELLS);
+            let weight = if cell % WIDTH == WIDTH / 2 { 10 } else { 0 };
+            let row = if side == 0 { [weight, 0] } else { [0, weight] };
+            text += &format!("{} {}\n", row[0], row[1]);
+        }
+        text += &format!("0 0\n{OUTPUT_SCALE} -{OUTPUT_SCALE}\n0\n");
+        text.parse().unwrap()
+    }
+
+    fn state(history: &str) -> GameState {
+        GameState::from_history(&parse_history(history).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn network_matches_the_heuristic_it_encodes() {
+        let network = center_counter();
+        let center_only = EvalWeights {
+            center: 10,
+            one: 0,
+            two: 0,
+            three: 0,
+            mobility: 0,
+            buried: 0,
+            defense: 100,
+        };
+        for history in ["", "R3", "R3B3R3", "R0B3R3B3R2", "R3B2R3B2R3B2R3"] {
+            let state = state(history);
+            let expected = crate::eval::evaluate_weighted(&state, state.to_move(), &center_only);
+            assert_eq!(network.evaluate(&state), expected, "{history}");
+        }
+
+        let position = state("R2B3R4");
+        let with_network = SearchOptions {
+            network: Some(Arc::new(network.clone())),
+            ..SearchOptions::new(5)
+        };
+        let with_weights = SearchOptions {
+            weights: center_only,
+            ..SearchOptions::new(5)
+        };
+        assert!(!with_network.is_default());
+        let limits = SearchLimits::default();
+        let a = search(&position, &with_network, &limits, |_| {}).unwrap();
+        let b = search(&position, &with_weights, &limits, |_| {}).unwrap();
+        assert_eq!((a.column, a.score), (b.column, b.score));
+    }
+
+    #[test]
+    fn weights_files_round_trip_and_are_checked() {
+        let network = center_counter();
+        let reread: NnEval = network.to_string().parse().unwrap();
+        assert_eq!(reread, network);
+        assert_eq!(reread.hidden(), 2);
+        assert_eq!(
+            format!("{network:?}"),
+            format!(
+                "NnEval {{ hidden: 2, fingerprint: {:016x} }}",
+                network.fingerprint()
+            )
+        );
+
+        assert!(matches!(
+            "c4nn 2 4".parse::<NnEval>(),
+            Err(NetworkError::Header)
+        ));
+        assert!(matches!(
+            "c4nn 1 0".parse::<NnEval>(),
+            Err(NetworkError::Hidden { hidden: 0 })
+        ));
+        assert!(matches!(
+            "c4nn 1 1 5".parse::<NnEval>(),
+            Err(NetworkError::Size {
+                expected: 87,
+                found: 1
+            })
+        ));
+        let mut too_big = vec!["0"; (INPUTS + 2) + 1];
+        too_big[0] = "40000";
+        let text = format!("c4nn 1 1 {}", too_big.join(" "));
+        assert!(matches!(
+            text.parse::<NnEval>(),
+            Err(NetworkError::Value { .. })
+        ));
+        assert!(matches!(
+            NnEval::load("/nonexistent/net.c4nn"),
+            Err(NetworkError::Io(_))
+        ));
+    }
+}
@@ -0,0 +1,589 @@
+//! A PGN-like text format for sharing games.
+//!
+//! ```text
+//! [Event "Club night"]
+//! [Red "Alice"]
+//! [Blue "Engine level 8"]
+//! [Result "1-0"]
+//!
+//! 1. d d 2. c e 3. b a 4. a 1-0
+//! ```
+//!
+//! Tags are `[Name "value"]` lines, with `\"` and `\\` escaped inside the
+//! value. The move list numbers each pair of moves and names columns `a` to
+//! `g` from the left, so it reads the same whoever moved; the player who
+//! opened is the `First` tag, Red when absent. The list ends with the result:
+//! `1-0` Red won, `0-1` Blue won, `1/2-1/2` draw, `*` unfinished. A `{...}`
+//! comment belongs to the move before it, such as an engine evaluation;
+//! comments ahead of the first move are dropped.
+//!
+//! The module also converts between the other ways a game or position gets
+//! written down. Moves go between the `R3B4` history (`parse_history` and
+//! `format_history`), plain 1-based column digits (`4453`, as most published
+//! solvers and test suites write games) and this format. Positions go between
+//! a `GameState`, the JSON grid of `BoardModel` and the plain ASCII board the
+//! terminal client draws. A grid or ASCII board says nothing of move order,
+//! so converting back yields a position rather than a history.
+use std::{collections::BTreeMap, fmt, str::FromStr};
+
+use thiserror::Error;
+
+use crate::{
+    format_history,
+    render::{self, RenderOptions},
+    GameError, GameState, ParseMoveError, Player, TypedMove, HEIGHT, WIDTH,
+};
+
+/// Movetext lines are wrapped before this many characters.
+const LINE_WIDTH: usize = 80;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    RedWin,
+    BlueWin,
+    Draw,
+    Unfinished,
+}
+
+impl Outcome {
+    pub fn token(self) -> &'static str {
+        match self {
+            Self::RedWin => "1-0",
+            Self::BlueWin => "0-1",
+            Self::Draw => "1/2-1/2",
+            Self::Unfinished => "*",
+        }
+    }
+
+    fn from_token(token: &str) -> Option<Self> {
+        [Self::RedWin, Self::BlueWin, Self::Draw, Self::Unfinished]
+            .into_iter()
+            .find(|outcome| outcome.token() == token)
+    }
+
+    /// What the board alone says; a game decided another way, such as on
+    /// time, records its outcome explicitly.
+    pub fn of(state: &GameState) -> Self {
+        match state.winner() {
+            Some(Player::Red) => Self::RedWin,
+            Some(Player::Blue) => Self::BlueWin,
+            None if state.is_full() => Self::Draw,
+            None => Self::Unfinished,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Notation {
+    /// Tags in the order they are written, without `First` and `Result`,
+    /// which `moves` and `outcome` stand for.
+    pub tags: Vec<(String, String)>,
+    pub moves: Vec<TypedMove>,
+    pub outcome: Outcome,
+    /// Comments keyed by the zero-based index of the move they follow. They
+    /// cannot contain `}`, which is written as `)`.
+    pub comments: BTreeMap<usize, String>,
+}
+
+impl Notation {
+    pub fn new(moves: Vec<TypedMove>, outcome: Outcome) -> Self {
+        Self {
+            tags: Vec::new(),
+            moves,
+            outcome,
+            comments: BTreeMap::new(),
+        }
+    }
+
+    pub fn with_comment(mut self, ply: usize, comment: impl Into<String>) -> Self {
+        self.comments.insert(ply, comment.into());
+        self
+    }
+
+    pub fn with_tag(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.tags.push((name.into(), value.into()));
+        self
+    }
+
+    pub fn tag(&self, name: &str) -> Option<&str> {
+        self.tags
+            .iter()
+            .find(|(tag, _)| tag == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// The moves as a compact `R3B3` history.
+    pub fn history(&self) -> String {
+        format_history(&self.moves)
+    }
+}
+
+impl fmt::Display for Notation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let first = self.moves.first().map_or(Player::Red, |m| m.player);
+        let tags = self
+            .tags
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()));
+        let first_tag = (first == Player::Blue).then_some(("First", "Blue"));
+        let result_tag = ("Result", self.outcome.token());
+        for (name, value) in tags.chain(first_tag).chain([result_tag]) {
+            let value = value.replace('\\', "\\\\").replace('"', "\\\"");
+            writeln!(f, "[{name} \"{value}\"]")?;
+        }
+        writeln!(f)?;
+        let mut tokens = Vec::new();
+        for (ply, mv) in self.moves.iter().enumerate() {
+            if ply % 2 == 0 {
+                tokens.push(format!("{}.", ply / 2 + 1));
+            }
+            tokens.push(char::from(b'a' + mv.column as u8).to_string());
+            if let Some(comment) = self.comments.get(&ply) {
+                tokens.push(format!("{{{}}}", comment.replace('}', ")")));
+            }
+        }
+        tokens.push(self.outcome.token().to_string());
+        let mut line = String::new();
+        for token in tokens {
+            if !line.is_empty() && line.len() + 1 + token.len() > LINE_WIDTH {
+                writeln!(f, "{line}")?;
+                line.clear();
+            }
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line.push_str(&token);
+        }
+        writeln!(f, "{line}")
+    }
+}
+
+impl FromStr for Notation {
+    type Err = GameError;
+
+    /// Parse one game. Moves are replayed, so an illegal or post-game move is
+    /// an error, and so is a result that contradicts the `Result` tag.
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let error = |position: usize, reason| GameError::ParseMove { position, reason };
+        let unknown = |tag: &str, value: &str| ParseMoveError::UnknownTagValue {
+            tag: tag.to_string(),
+            value: value.to_string(),
+        };
+        let mut tags = Vec::new();
+        let mut first = Player::Red;
+        let mut result_tag = None;
+        let mut offset = 0;
+        let mut movetext_start = text.len();
+        for line in text.split_inclusive('\n') {
+            let trimmed = line.trim();
+            if trimmed.is_empty() {
+                offset += line.len();
+                continue;
+            }
+            if !trimmed.starts_with('[') {
+                movetext_start = offset;
+                break;
+            }
+            let (name, value) = parse_tag(trimmed).ok_or_else(|| {
+                let line = trimmed.to_string();
+                error(offset, ParseMoveError::MalformedTag { line })
+            })?;
+            match name.as_str() {
+                "First" => {
+                    first = match value.as_str() {
+                        "Red" => Player::Red,
+                        "Blue" => Player::Blue,
+                        _ => return Err(error(offset, unknown("First", &value))),
+                    }
+                }
+                "Result" => {
+                    let outcome = Outcome::from_token(&value)
+                        .ok_or_else(|| error(offset, unknown("Result", &value)))?;
+                    result_tag = Some(outcome);
+                }
+                _ => tags.push((name, value)),
+            }
+            offset += line.len();
+        }
+
+        let mut state = GameState::empty(first);
+        let mut moves = Vec::new();
+        let mut outcome = None;
+        let mut comments = BTreeMap::new();
+        for (position, token) in movetext_tokens(&text[movetext_start..]) {
+            let position = movetext_start + position;
+            if let Some(comment) = token.strip_prefix('{') {
+                if let Some(ply) = moves.len().checked_sub(1) {
+                    let comment = comment.strip_suffix('}').unwrap_or(comment);
+                    comments.insert(ply, comment.trim().to_string());
+                }
+                continue;
+            }
+            if outcome.is_some() {
+                let token = token.to_string();
+                return Err(error(position, ParseMoveError::UnexpectedToken { token }));
+            }
+            if let Some(token) = Outcome::from_token(token) {
+                outcome = Some(token);
+                continue;
+            }
+            if token.ends_with('.') && token[..token.len() - 1].parse::<usize>().is_ok() {
+                let expected = moves.len() / 2 + 1;
+                if moves.len() % 2 != 0 || token[..token.len() - 1] != expected.to_string() {
+                    return Err(error(
+                        position,
+                        ParseMoveError::WrongMoveNumber { expected },
+                    ));
+                }
+                continue;
+            }
+            let column = match token.as_bytes() {
+                [letter @ b'a'..=b'z'] if usize::from(letter - b'a') < WIDTH => {
+                    usize::from(letter - b'a')
+                }
+                _ => {
+                    let token = token.to_string();
+                    return Err(error(position, ParseMoveError::UnexpectedToken { token }));
+                }
+            };
+            if Outcome::of(&state) != Outcome::Unfinished {
+                return Err(error(position, ParseMoveError::MoveAfterGameOver));
+            }
+            let player = state.to_move();
+            state
+                .play(column)
+                .map_err(|_| error(position, ParseMoveError::ColumnFull { column }))?;
+            moves.push(TypedMove { player, column });
+        }
+        let outcome = match (outcome, result_tag) {
+            (Some(outcome), Some(tag)) if outcome != tag => {
+                return Err(error(
+                    text.len(),
+                    ParseMoveError::ResultContradictsTag {
+                        result: outcome.token().to_string(),
+                        tag: tag.token().to_string(),
+                    },
+                ))
+            }
+            (Some(outcome), _) | (None, Some(outcome)) => outcome,
+            (None, None) => Outcome::of(&state),
+        };
+        Ok(Self {
+            tags,
+            moves,
+            outcome,
+            comments,
+        })
+    }
+}
+
+/// Why a board is not a position some game could reach.
+#[derive(Clone, Debug, PartialEq, Eq, Error)]
+pub enum GridError {
+    #[error("the disc at column {column}, row {row} floats over an empty cell")]
+    FloatingDisc { column: usize, row: usize },
+    #[error("{red} red and {blue} blue discs cannot come from alternating moves")]
+    DiscCounts { red: usize, blue: usize },
+    #[error("expected {} rows of {} cells", HEIGHT, WIDTH)]
+    Shape,
+}
+
+/// A board as rows of cells, `grid[0]` the bottom row, as in
+/// `BoardModel::grid`. Serialised, it is the JSON grid clients draw.
+pub type Grid = [[Option<Player>; WIDTH]; HEIGHT];
+
+/// `moves` as 1-based column digits; the colours are left implied.
+pub fn to_digits(moves: &[TypedMove]) -> String {
+    moves
+        .iter()
+        .map(|mv| char::from(b'1' + mv.column as u8))
+        .collect()
+}
+
+/// Inverse of `to_digits`, with moves alternating from `first`. Like
+/// `parse_history`, it does not replay the moves.
+pub fn from_digits(digits: &str, first: Player) -> Result<Vec<TypedMove>, GameError> {
+    let mut player = first;
+    let mut moves = Vec::new();
+    for (position, found) in digits.trim().char_indices() {
+        let column = match found {
+            '1'..='7' => found as usize - '1' as usize,
+            _ => {
+                return Err(GameError::ParseMove {
+                    position,
+                    reason: ParseMoveError::UnexpectedChar { found },
+                })
+            }
+        };
+        moves.push(TypedMove { player, column });
+        player = player.opponent();
+    }
+    Ok(moves)
+}
+
+/// The game as this module's text format, its result taken from the board.
+pub fn to_text(moves: &[TypedMove]) -> Result<String, GameError> {
+    let outcome = Outcome::of(&GameState::from_history(moves)?);
+    Ok(Notation::new(moves.to_vec(), outcome).to_string())
+}
+
+/// The moves of a game in this module's text format.
+pub fn from_text(text: &str) -> Result<Vec<TypedMove>, GameError> {
+    Ok(text.parse::<Notation>()?.moves)
+}
+
+pub fn to_grid(state: &GameState) -> Grid {
+    state.board_model().grid
+}
+
+/// The position `grid` shows. Who is to move follows from the disc counts,
+/// except on a board with as many of each, where it is `first`, the side
+/// that opened.
+pub fn from_grid(grid: &Grid, first: Player) -> Result<GameState, GameError> {
+    let mut state = GameState::empty(first);
+    let mut counts = [0; 2];
+    // Column by column from the bottom, so every disc lands where it sits.
+    let cells = (0..WIDTH).flat_map(|column| (0..HEIGHT).map(move |row| (column, row)));
+    for (column, row) in cells {
+        let Some(player) = grid[row][column] else {
+            continue;
+        };
+        if row > 0 && grid[row - 1][column].is_none() {
+            return Err(GridError::FloatingDisc { column, row }.into());
+        }
+        state.force_play(player, column)?;
+        counts[player.idx()] += 1;
+    }
+    let [red, blue] = counts;
+    let to_move = match first {
+        _ if red == blue => first,
+        Player::Red if red == blue + 1 => Player::Blue,
+        Player::Blue if blue == red + 1 => Player::Red,
+        _ => return Err(GridError::DiscCounts { red, blue }.into()),
+    };
+    state.set_to_move(to_move);
+    Ok(state)
+}
+
+/// The board as the terminal client draws it without colour: `R`, `B` and
+/// `.` between bars, top row first, column numbers underneath.
+pub fn to_ascii(state: &GameState) -> String {
+    let options = RenderOptions {
+        highlight_win: false,
+        ..RenderOptions::default()
+    };
+    render::text(state, &options, false)
+}
+
+/// Inverse of `to_ascii`, with `first` as in `from_grid`. Lines that do not
+/// start with a bar, such as the column numbers, are skipped.
+pub fn from_ascii(text: &str, first: Player) -> Result<GameState, GameError> {
+    let mut rows = Vec::new();
+    let mut offset = 0;
+    for line in text.split_inclusive('\n') {
+        let start = offset;
+        offset += line.len();
+        if !line.trim_start().starts_with('|') {
+            continue;
+        }
+        let mut row = Vec::new();
+        for (i, found) in line.char_indices() {
+            match found {
+                'R' | 'r' => row.push(Some(Player::Red)),
+                'B' | 'b' => row.push(Some(Player::Blue)),
+                '.' => row.push(None),
+                '|' => {}
+                _ if found.is_whitespace() => {}
+                _ => {
+                    return Err(GameError::ParseMove {
+                        position: start + i,
+                        reason: ParseMoveError::UnexpectedChar { found },
+                    })
+                }
+            }
+        }
+        rows.push(row);
+    }
+    if rows.len() != HEIGHT || rows.iter().any(|row| row.len() != WIDTH) {
+        return Err(GridError::Shape.into());
+    }
+    let mut grid = [[None; WIDTH]; HEIGHT];
+    for (cells, row) in grid.iter_mut().zip(rows.iter().rev()) {
+        cells.copy_from_slice(row);
+    }
+    from_grid(&grid, first)
+}
+
+fn parse_tag(line: &str) -> Option<(String, String)> {
+    let inner = line.strip_prefix('[')?.strip_suffix(']')?;
+    let (name, value) = inner.split_once(' ')?;
+    let value = value.trim().strip_prefix('"')?.strip_suffix('"')?;
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => unescaped.push(chars.next()?),
+            '"' => return None,
+            c => unescaped.push(c),
+        }
+    }
+    let valid_name =
+        !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    valid_name.then(|| (name.to_string(), unescaped))
+}
+
+/// Whitespace-separated tokens with their byte offsets; a comment, braces
+/// included, is one token. A move number may be written against its move
+/// (`1.d`).
+fn movetext_tokens(text: &str) -> Vec<(usize, &str)> {
+    let mut tokens = Vec::new();
+    let mut comment_start = None;
+    let mut start = None;
+    for (i, c) in text.char_indices() {
+        let in_comment = comment_start.is_some();
+        let ends_token = in_comment || c.is_whitespace() || c == '{';
+        if ends_token {
+            if let Some(s) = start.take() {
+                tokens.push((s, &text[s..i]));
+            }
+        }
+        match c {
+            '{' if !in_comment => comment_start = Some(i),
+            '}' if in_comment => {
+                let s = comment_start.take().expect("inside a comment");
+                tokens.push((s, &text[s..=i]));
+            }
+            _ if ends_token => {}
+            '.' => {
+                let s = start.take().unwrap_or(i);
+                tokens.push((s, &text[s..=i]));
+            }
+            _ => {
+                start.get_or_insert(i);
+            }
+        }
+    }
+    if let Some(s) = start.or(comment_start) {
+        tokens.push((s, &text[s..]));
+    }
+    tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_history;
+
+    #[test]
+    fn round_trips_through_text() {
+        let moves = parse_history("R3B3R2B4R1B0R0").unwrap();
+        let game = Notation::new(moves, Outcome::RedWin)
+            .with_tag("Event", "Club \"night\"")
+            .with_tag("Red", "Alice");
+        let text = game.to_string();
+        assert!(text.starts_with("[Event \"Club \\\"night\\\"\"]\n[Red \"Alice\"]\n"));
+        assert!(text.ends_with("\n1. d d 2. c e 3. b a 4. a 1-0\n"));
+        assert_eq!(text.parse::<Notation>().unwrap(), game);
+
+        let annotated = Notation::new(parse_history("R3B3").unwrap(), Outcome::Unfinished)
+            .with_comment(0, "+12")
+            .with_comment(1, "book}");
+        let text = annotated.to_string();
+        assert!(text.ends_with("\n1. d {+12} d {book)} *\n"));
+        let parsed: Notation = text.parse().unwrap();
+        assert_eq!(parsed.comments[&0], "+12");
+        assert_eq!(parsed.comments[&1], "book)");
+
+        let blue_first = Notation::new(parse_history("B3R4").unwrap(), Outcome::Unfinished);
+        let text = blue_first.to_string();
+        assert!(text.contains("[First \"Blue\"]"));
+        assert_eq!(text.parse::<Notation>().unwrap(), blue_first);
+    }
+
+    #[test]
+    fn converts_between_formats() {
+        let moves = parse_history("R3B3R2B2R4B4R5").unwrap();
+        let state = GameState::from_history(&moves).unwrap();
+
+        assert_eq!(to_digits(&moves), "4433556");
+        assert_eq!(from_digits("4433556", Player::Red).unwrap(), moves);
+        let text = to_text(&moves).unwrap();
+        assert!(text.ends_with("1-0\n"));
+        assert_eq!(from_text(&text).unwrap(), moves);
+        assert_eq!(from_grid(&to_grid(&state), Player::Red).unwrap(), state);
+        let ascii = to_ascii(&state);
+        assert!(ascii.contains("|.|.|R|R|R|R|.|\n"));
+        assert_eq!(from_ascii(&ascii, Player::Red).unwrap(), state);
+
+        // Blue opening, and an even count where only `first` says who moves.
+        for history in ["B3R4", "B3R4B3", ""] {
+            let moves = parse_history(history).unwrap();
+            let first = moves.first().map_or(Player::Red, |mv| mv.player);
+            let state = GameState::from_history(&moves).unwrap();
+            assert_eq!(from_digits(&to_digits(&moves), first).unwrap(), moves);
+            assert_eq!(from_grid(&to_grid(&state), first).unwrap(), state);
+            assert_eq!(from_ascii(&to_ascii(&state), first).unwrap(), state);
+        }
+    }
+
+    #[test]
+    fn conversions_reject_impossible_input() {
+        assert!(matches!(
+            from_digits("448", Player::Red),
+            Err(GameError::ParseMove { position: 2, .. })
+        ));
+        let mut grid = [[None; WIDTH]; HEIGHT];
+        grid[1][2] = Some(Player::Red);
+        assert!(matches!(
+            from_grid(&grid, Player::Red),
+            Err(GameError::InvalidGrid(GridError::FloatingDisc {
+                column: 2,
+                row: 1
+            }))
+        ));
+        grid[0][2] = Some(Player::Red);
+        assert!(matches!(
+            from_grid(&grid, Player::Red),
+            Err(GameError::InvalidGrid(GridError::DiscCounts {
+                red: 2,
+                blue: 0
+            }))
+        ));
+        assert!(matches!(
+            from_ascii("|R|.|.|.|.|.|.|", Player::Red),
+            Err(GameError::InvalidGrid(GridError::Shape))
+        ));
+        let ascii = to_ascii(&GameState::empty(Player::Red)).replacen('.', "X", 1);
+        assert!(matches!(
+            from_ascii(&ascii, Player::Red),
+            Err(GameError::ParseMove { position: 1, .. })
+        ));
+    }
+
+    #[test]
+    fn parser_is_lenient_with_layout_but_checks_the_game() {
+        let game: Notation = "[Result \"*\"]\n{opening} 1.d {centre} d\n2. c"
+            .parse()
+            .unwrap();
+        assert_eq!(game.history(), "R3B3R2");
+        assert_eq!(game.comments, BTreeMap::from([(0, "centre".to_string())]));
+        assert_eq!(game.outcome, Outcome::Unfinished);
+        // Without a result the board decides.
+        let game: Notation = "1. d a 2. d a 3. d a 4. d".parse().unwrap();
+        assert_eq!(game.outcome, Outcome::RedWin);
+
+        for bad in [
+            "1. d a 2. d a 3. d a 4. d a",
+            "1. h",
+            "2. d",
+            "[Result \"0-1\"]\n1. d 1-0",
+            "1. d 1-0 2. d",
+            "[Red Alice]",
+        ] {
+            assert!(
+                matches!(bad.parse::<Notation>(), Err(GameError::ParseMove { .. })),
+                "{bad:?} should not parse"
+            );
+        }
+    }
+}
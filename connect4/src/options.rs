@@ -0,0 +1,584 @@
+//! Searches configured beyond a single `level`: choice of algorithm, several
+//! ranked candidate moves, and deliberate randomness for more human-like play.
+//!
+//! `best_move` remains the fast path for the common case; [`search`] with
+//! default options returns the same column.
+use std::sync::Arc;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "mcts")]
+use crate::mcts;
+use crate::{
+    nn::NnEval, policy::SharedPolicy, rng::Rng, EvalWeights, GameError, GameState, Search,
+    SearchInfo, SearchLimits, WIN_SCORE,
+};
+
+/// Score gap, in evaluation units, that `randomness = 1` tolerates: about four
+/// open threes, enough for visibly varied play without handing away tactics.
+const RANDOM_MARGIN: f64 = 200.0;
+/// Score gap, in evaluation units, that makes a move `e` times less likely
+/// at `temperature = 1`: about two open threes.
+const TEMPERATURE_SCALE: f64 = 100.0;
+/// MCTS iterations granted per level.
+#[cfg(feature = "mcts")]
+const MCTS_ITERATIONS_PER_LEVEL: u32 = 2_000;
+
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum Algorithm {
+    /// Depth-limited negamax with alpha-beta pruning, searched to `level`.
+    #[default]
+    AlphaBeta,
+    /// Monte Carlo tree search; `level` scales the number of playouts.
+    Mcts,
+    /// Alpha-beta to the end of the game, ignoring `level`. Only the deadline
+    /// stops it early, so always give it one outside of endgames.
+    Solver,
+}
+
+/// How alpha-beta orders a node's moves after the transposition table's best
+/// move and, when enabled, the countermove. Only the speed depends on it.
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum MoveOrdering {
+    /// Center columns first.
+    #[default]
+    Center,
+    /// Moves that leave the most cells completing four first, center-first
+    /// among equals.
+    Threats,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SearchOptions {
+    pub algorithm: Algorithm,
+    pub level: u8,
+    /// 0 always plays the top move; up to 1 picks uniformly among moves
+    /// scoring within `randomness * 200` of it.
+    pub randomness: f64,
+    /// Above 0, sample the move instead of playing the best: from a softmax
+    /// over the root scores, each `temperature * 100` evaluation points
+    /// costing a factor of `e`, or for MCTS in proportion to
+    /// `visits^(1 / temperature)`. Overrides `randomness`; near 0 it is
+    /// the argmax, and large values approach a uniform choice.
+    pub temperature: f64,
+    /// Ranked candidate moves to report, 1 to 7.
+    pub multipv: usize,
+    /// Fixes the random choices; unseeded searches vary from call to call.
+    pub seed: Option<u64>,
+    /// Evaluation used by alpha-beta and the solver; MCTS plays out games
+    /// instead of evaluating.
+    pub weights: EvalWeights,
+    /// Learned evaluation used instead of `weights` (`connect4::nn`), shared
+    /// between searches since a network is loaded once.
+    pub network: Option<Arc<NnEval>>,
+    /// Priors and leaf values for MCTS in place of random playouts
+    /// (`connect4::policy`); the other algorithms ignore it.
+    pub policy: Option<SharedPolicy>,
+    /// Search late, quiet moves a ply shallower unless they look good
+    /// (alpha-beta and the solver). Faster, but no longer exactly the
+    /// full-width answer for the level, so off by default.
+    pub late_move_reductions: bool,
+    /// One or two plies from the horizon, skip quiet moves when the
+    /// position's evaluation is too far below the best score found so far
+    /// to catch up (alpha-beta and the solver). Off by default, like the
+    /// reductions.
+    pub futility_pruning: bool,
+    /// Try the reply that last refuted the opponent's move early. Changes
+    /// only the speed, not the answer, but has not paid for its bookkeeping.
+    pub countermoves: bool,
+    pub ordering: MoveOrdering,
+}
+
+impl SearchOptions {
+    pub fn new(level: u8) -> Self {
+        Self {
+            algorithm: Algorithm::AlphaBeta,
+            level,
+            randomness: 0.0,
+            temperature: 0.0,
+            multipv: 1,
+            seed: None,
+            weights: EvalWeights::default(),
+            network: None,
+            policy: None,
+            late_move_reductions: false,
+            futility_pruning: false,
+            countermoves: false,
+            ordering: MoveOrdering::Center,
+        }
+    }
+
+    /// Plain fixed-depth alpha-beta, whose answer depends only on the
+    /// position and level and may therefore be cached.
+    pub fn is_default(&self) -> bool {
+        self.algorithm == Algorithm::AlphaBeta
+            && self.randomness <= 0.0
+            && self.temperature <= 0.0
+            && self.multipv <= 1
+            && self.weights == EvalWeights::default()
+            && self.network.is_none()
+            && !self.late_move_reductions
+            && !self.futility_pruning
+    }
+}
+
+/// A candidate root move. Scores are from the mover's point of view:
+/// evaluation units for alpha-beta and the solver (±1 000 000 is a forced
+/// win or loss), and for MCTS the win rate mapped onto -1000..=1000.
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ScoredMove {
+    pub column: usize,
+    pub score: i32,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SearchOutcome {
+    pub column: usize,
+    /// Deepest completed iteration; for MCTS, the deepest ply the tree reached.
+    pub depth: usize,
+    pub score: i32,
+    /// Best first, at most `multipv` long. The chosen `column` may be any of
+    /// them when randomness is on.
+    pub lines: Vec<ScoredMove>,
+    /// Set when a limit stopped an alpha-beta or solver search before
+    /// `target_depth`, with the interrupted iteration's best finished move,
+    /// as in `MoveResponse`. MCTS answers are never marked.
+    pub truncated: bool,
+    pub target_depth: Option<usize>,
+    pub best_so_far: Option<usize>,
+}
+
+/// Run the configured search on `state`. `on_iteration` fires after each
+/// completed depth of the alpha-beta based algorithms; MCTS does not call it.
+pub fn search(
+    state: &GameState,
+    options: &SearchOptions,
+    limits: &SearchLimits,
+    on_iteration: impl FnMut(&SearchInfo),
+) -> Result<SearchOutcome, GameError> {
+    if !(1..=15).contains(&options.level) {
+        return Err(GameError::DepthOutOfRange(options.level));
+    }
+    if state.legal_moves().is_empty() {
+        return Err(GameError::NoMoves);
+    }
+    let mut rng = options.seed.map_or_else(Rng::from_entropy, Rng::new);
+    // Each arm ranks its candidates best first; MCTS adds their visits.
+    let Ranked {
+        mut lines,
+        depth,
+        visits,
+        cut,
+    } = match options.algorithm {
+        Algorithm::AlphaBeta => {
+            let depth = usize::from(options.level);
+            deepen(state, depth, false, options, limits, on_iteration)?.ranked(depth)
+        }
+        #[cfg(feature = "solver")]
+        Algorithm::Solver => {
+            let remaining = crate::MAX_CELLS - usize::from(state.moves_played);
+            deepen(state, remaining, true, options, limits, on_iteration)?.ranked(remaining)
+        }
+        #[cfg(feature = "mcts")]
+        Algorithm::Mcts => mcts_ranked(state, options, limits, &mut rng),
+        // Only reachable when a feature left an algorithm out.
+        #[allow(unreachable_patterns)]
+        algorithm => return Err(GameError::AlgorithmUnavailable(algorithm)),
+    };
+    let best = lines[0];
+    let chosen = if options.temperature > 0.0 {
+        let weights = temperature_weights(&lines, visits.as_deref(), options.temperature);
+        lines[rng.weighted(&weights)]
+    } else {
+        let margin = (options.randomness.clamp(0.0, 1.0) * RANDOM_MARGIN) as i32;
+        let candidates: Vec<ScoredMove> = lines
+            .iter()
+            .copied()
+            .filter(|line| line.score >= best.score.saturating_sub(margin))
+            .collect();
+        if candidates.len() > 1 {
+            candidates[rng.below(candidates.len())]
+        } else {
+            best
+        }
+    };
+    lines.truncate(options.multipv.clamp(1, 7));
+    Ok(SearchOutcome {
+        column: chosen.column,
+        depth,
+        score: chosen.score,
+        lines,
+        truncated: cut.is_some(),
+        target_depth: cut.map(|(target, _)| target),
+        best_so_far: cut.and_then(|(_, best_so_far)| best_so_far),
+    })
+}
+
+/// Root moves best first, as one of the algorithms left them.
+struct Ranked {
+    lines: Vec<ScoredMove>,
+    depth: usize,
+    /// MCTS visit counts, line by line.
+    visits: Option<Vec<u32>>,
+    /// Set when a limit stopped the search: the depth it was after and the
+    /// interrupted pass's best finished move.
+    cut: Option<(usize, Option<usize>)>,
+}
+
+#[cfg(feature = "mcts")]
+fn mcts_ranked(
+    state: &GameState,
+    options: &SearchOptions,
+    limits: &SearchLimits,
+    rng: &mut Rng,
+) -> Ranked {
+    let iterations = MCTS_ITERATIONS_PER_LEVEL * u32::from(options.level);
+    let policy = options.policy.as_ref().map(|policy| &*policy.0);
+    let mut result = mcts::search(state, iterations, limits, rng, policy);
+    // The most visited move is the robust choice; a rarely tried one can show
+    // a lucky win rate.
+    result
+        .children
+        .sort_by_key(|child| std::cmp::Reverse(child.visits));
+    Ranked {
+        lines: result
+            .children
+            .iter()
+            .map(|child| ScoredMove {
+                column: child.column,
+                score: ((child.value - 0.5) * 2000.0).round() as i32,
+            })
+            .collect(),
+        depth: result.depth,
+        visits: Some(result.children.iter().map(|child| child.visits).collect()),
+        cut: None,
+    }
+}
+
+/// Sampling weights for `lines`: a softmax over the scores, or with MCTS
+/// visit counts `visits^(1 / temperature)`. Both are taken relative to the
+/// best line, so neither overflows however small the temperature.
+fn temperature_weights(lines: &[ScoredMove], visits: Option<&[u32]>, temperature: f64) -> Vec<f64> {
+    match visits {
+        Some(visits) => {
+            let most = f64::from(visits.iter().copied().max().unwrap_or(0).max(1));
+            visits
+                .iter()
+                .map(|&visits| (f64::from(visits) / most).powf(temperature.recip()))
+                .collect()
+        }
+        None => {
+            let best = lines.iter().map(|line| line.score).max().unwrap_or(0);
+            lines
+                .iter()
+                .map(|line| {
+                    let gap = f64::from(best.saturating_sub(line.score));
+                    (-gap / (temperature * TEMPERATURE_SCALE)).exp()
+                })
+                .collect()
+        }
+    }
+}
+
+/// What `deepen` reached.
+struct Deepened {
+    lines: Vec<ScoredMove>,
+    depth: usize,
+    /// Set when a limit stopped the search, with the interrupted pass's best
+    /// finished move.
+    cut: Option<Option<usize>>,
+}
+
+impl Deepened {
+    fn ranked(self, target_depth: usize) -> Ranked {
+        Ranked {
+            lines: self.lines,
+            depth: self.depth,
+            visits: None,
+            cut: self.cut.map(|best_so_far| (target_depth, best_so_far)),
+        }
+    }
+}
+
+/// Iterative deepening with exact scores for every root move. `stop_when_decided`
+/// ends early once the result is a proven win or loss, which is what lets
+/// the solver finish long before the board fills.
+fn deepen(
+    state: &GameState,
+    max_depth: usize,
+    stop_when_decided: bool,
+    options: &SearchOptions,
+    limits: &SearchLimits,
+    mut on_iteration: impl FnMut(&SearchInfo),
+) -> Result<Deepened, GameError> {
+    let mut search = Search::new(limits, options);
+    let mut best = None;
+    let mut cut = None;
+    for depth in 1..=max_depth {
+        search.enforce_limits = depth > 1;
+        if search.enforce_limits && !limits.may_deepen(search.nodes) {
+            cut = Some(None);
+            break;
+        }
+        let Some(scores) = search.root_scores(state, depth) else {
+            cut = Some(search.partial_best);
+            break;
+        };
+        // First of the best in move order, matching `best_move`'s tie-break.
+        let top = scores
+            .iter()
+            .copied()
+            .reduce(|top, line| if line.1 > top.1 { line } else { top })
+            .expect("root has legal moves");
+        search.flush_table_counts();
+        on_iteration(&SearchInfo {
+            depth,
+            column: top.0,
+            score: top.1,
+            nodes: search.nodes,
+            pv: search.line(depth).to_vec(),
+        });
+        let mut lines: Vec<ScoredMove> = scores
+            .into_iter()
+            .map(|(column, score)| ScoredMove { column, score })
+            .collect();
+        // Stable, so equal scores stay in center-first move order.
+        lines.sort_by_key(|line| std::cmp::Reverse(line.score));
+        best = Some((lines, depth));
+        if stop_when_decided && top.1.abs() >= WIN_SCORE {
+            break;
+        }
+    }
+    let (lines, depth) = best.ok_or(GameError::NoMoves)?;
+    Ok(Deepened { lines, depth, cut })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{best_move, parse_history, MoveRequest};
+
+    fn state(history: &str) -> GameState {
+        GameState::from_history(&parse_history(history).unwrap()).unwrap()
+    }
+
+    fn run(history: &str, options: &SearchOptions) -> SearchOutcome {
+        search(&state(history), options, &SearchLimits::default(), |_| {}).unwrap()
+    }
+
+    #[test]
+    fn default_options_agree_with_best_move() {
+        for history in ["", "R3B3R4", "R3B2R3B4R2"] {
+            let expected = best_move(MoveRequest {
+                position: history.to_string(),
+                level: 5,
+                first_player: None,
+            })
+            .unwrap();
+            let outcome = run(history, &SearchOptions::new(5));
+            assert_eq!(outcome.column, expected.column, "{history}");
+            assert_eq!(outcome.lines.len(), 1);
+        }
+    }
+
+    #[test]
+    fn multipv_ranks_every_candidate() {
+        let options = SearchOptions {
+            multipv: 7,
+            ..SearchOptions::new(4)
+        };
+        let outcome = run("R3B3R4", &options);
+        assert_eq!(outcome.lines.len(), 7);
+        assert!(outcome.lines.windows(2).all(|w| w[0].score >= w[1].score));
+        assert_eq!(outcome.lines[0].column, outcome.column);
+    }
+
+    #[test]
+    fn randomness_varies_play_but_keeps_wins() {
+        let options = |seed| SearchOptions {
+            randomness: 1.0,
+            seed: Some(seed),
+            ..SearchOptions::new(3)
+        };
+        let openings: std::collections::HashSet<usize> =
+            (0..20).map(|seed| run("", &options(seed)).column).collect();
+        assert!(openings.len() > 1);
+        assert_eq!(run("", &options(7)), run("", &options(7)));
+        for seed in 0..20 {
+            assert_eq!(run("R0B1R0B1R0B1", &options(seed)).column, 0);
+        }
+    }
+
+    #[test]
+    fn temperature_samples_by_score_or_visits() {
+        let algorithms = [
+            Algorithm::AlphaBeta,
+            #[cfg(feature = "mcts")]
+            Algorithm::Mcts,
+        ];
+        for algorithm in algorithms {
+            let options = |seed, temperature| SearchOptions {
+                algorithm,
+                temperature,
+                seed: Some(seed),
+                ..SearchOptions::new(2)
+            };
+            let openings: std::collections::HashSet<usize> = (0..30)
+                .map(|seed| run("", &options(seed, 5.0)).column)
+                .collect();
+            assert!(openings.len() > 2, "{algorithm:?}");
+            // Cold, it plays the argmax like no temperature at all.
+            let argmax = run("R3B3R2", &options(1, 0.0));
+            assert_eq!(run("R3B3R2", &options(1, 1e-3)).column, argmax.column);
+            for seed in 0..20 {
+                assert_eq!(run("R0B1R0B1R0B1", &options(seed, 1.0)).column, 0);
+            }
+        }
+        assert!(!SearchOptions {
+            temperature: 1.0,
+            ..SearchOptions::new(4)
+        }
+        .is_default());
+    }
+
+    #[cfg(feature = "solver")]
+    #[test]
+    fn solver_stops_once_the_result_is_proven() {
+        let options = SearchOptions {
+            algorithm: Algorithm::Solver,
+            ..SearchOptions::new(1)
+        };
+        let outcome = run("R0B1R0B1R0B1", &options);
+        assert_eq!((outcome.column, outcome.depth), (0, 1));
+        assert_eq!(outcome.score, WIN_SCORE);
+        assert!(!outcome.truncated);
+
+        // The opening cannot be solved on a small budget.
+        let budget = SearchLimits::with_max_nodes(20_000);
+        let outcome = search(&state(""), &options, &budget, |_| {}).unwrap();
+        assert!(outcome.truncated);
+        assert_eq!(outcome.target_depth, Some(crate::MAX_CELLS));
+        assert!(outcome.depth < crate::MAX_CELLS);
+    }
+
+    #[cfg(feature = "mcts")]
+    #[test]
+    fn mcts_finds_immediate_wins_and_blocks() {
+        let options = SearchOptions {
+            algorithm: Algorithm::Mcts,
+            seed: Some(1),
+            ..SearchOptions::new(2)
+        };
+        assert_eq!(run("R0B1R0B1R0B1", &options).column, 0);
+        assert_eq!(run("R0B1R0B1R0", &options).column, 0);
+    }
+
+    #[test]
+    fn late_move_reductions_search_less_and_keep_tactics() {
+        let reduced = SearchOptions {
+            late_move_reductions: true,
+            ..SearchOptions::new(10)
+        };
+        assert!(!reduced.is_default());
+        assert_eq!(run("R0B1R0B1R0B1", &reduced).column, 0);
+        assert_eq!(run("R0B1R0B1R0", &reduced).column, 0);
+
+        let nodes = |options: &SearchOptions| {
+            let mut nodes = 0;
+            search(&state("R3B3"), options, &SearchLimits::default(), |info| {
+                nodes = info.nodes
+            })
+            .unwrap();
+            nodes
+        };
+        assert!(nodes(&reduced) < nodes(&SearchOptions::new(10)) / 2);
+    }
+
+    #[test]
+    fn futility_pruning_keeps_forced_results() {
+        let pruned = SearchOptions {
+            futility_pruning: true,
+            ..SearchOptions::new(10)
+        };
+        assert!(!pruned.is_default());
+        assert_eq!(run("R0B1R0B1R0B1", &pruned).column, 0);
+        assert_eq!(run("R0B1R0B1R0", &pruned).column, 0);
+
+        // Pruning only skips quiet moves, so both prove the same wins and
+        // losses for every column.
+        let plain = SearchOptions {
+            multipv: 7,
+            ..SearchOptions::new(10)
+        };
+        let pruned = SearchOptions {
+            futility_pruning: true,
+            ..plain.clone()
+        };
+        for history in ["R0B1R0B1R0", "R3B3R2B2"] {
+            let decided = |options: &SearchOptions| -> Vec<usize> {
+                run(history, options)
+                    .lines
+                    .iter()
+                    .filter(|l| l.score.abs() >= WIN_SCORE)
+                    .map(|l| l.column)
+                    .collect()
+            };
+            assert_eq!(decided(&pruned), decided(&plain), "{history}");
+        }
+    }
+
+    #[test]
+    fn move_ordering_changes_only_the_speed() {
+        let plain = SearchOptions {
+            multipv: 7,
+            ..SearchOptions::new(9)
+        };
+        let reordered = [
+            (MoveOrdering::Center, true),
+            (MoveOrdering::Threats, false),
+            (MoveOrdering::Threats, true),
+        ]
+        .map(|(ordering, countermoves)| SearchOptions {
+            ordering,
+            countermoves,
+            ..plain.clone()
+        });
+        for history in ["", "R3B3R2B4R4", "B0R6B3R3B3", "R3B2R3B3R3B4R2B3R4B4"] {
+            let expected = run(history, &plain).lines;
+            for options in &reordered {
+                assert_eq!(
+                    run(history, options).lines,
+                    expected,
+                    "{history} {options:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn table_counts_include_each_finished_iteration() {
+        let table = std::sync::Arc::new(crate::tt::TranspositionTable::default());
+        let limits = SearchLimits {
+            table: Some(table.clone()),
+            ..SearchLimits::default()
+        };
+        let mut probes = Vec::new();
+        search(&state(""), &SearchOptions::new(6), &limits, |_| {
+            probes.push(table.stats().probes);
+        })
+        .unwrap();
+        assert_eq!(probes.len(), 6);
+        assert!(probes.windows(2).all(|pair| pair[0] < pair[1]), "{probes:?}");
+        // Nothing was left to add when the search finished.
+        assert_eq!(table.stats().probes, probes[5]);
+    }
+}
@@ -0,0 +1,72 @@
+//! Move-generation counts for checking `GameState` against known values.
+//!
+//! `perft(state, depth)` is the number of move sequences of exactly `depth`
+//! plies from `state`. A game that is won or full has no moves, so lines that
+//! end early do not count. From the empty board every count up to depth 7 is
+//! `7^depth`, since no game can finish sooner.
+use crate::GameState;
+
+pub fn perft(state: &GameState, depth: usize) -> u64 {
+    if depth == 0 {
+        return 1;
+    }
+    if state.winner().is_some() {
+        return 0;
+    }
+    state
+        .legal_moves()
+        .into_iter()
+        .map(|column| {
+            let mut child = state.clone();
+            child.play(column).expect("legal move");
+            perft(&child, depth - 1)
+        })
+        .sum()
+}
+
+/// `perft` split by the first move, as `(column, count)` for each legal
+/// column from left to right, to narrow a wrong total down to one subtree.
+pub fn divide(state: &GameState, depth: usize) -> Vec<(usize, u64)> {
+    if depth == 0 || state.winner().is_some() {
+        return Vec::new();
+    }
+    let mut counts: Vec<(usize, u64)> = state
+        .legal_moves()
+        .into_iter()
+        .map(|column| {
+            let mut child = state.clone();
+            child.play(column).expect("legal move");
+            (column, perft(&child, depth - 1))
+        })
+        .collect();
+    counts.sort_unstable();
+    counts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse_history, Player};
+
+    fn state(history: &str) -> GameState {
+        GameState::from_history(&parse_history(history).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn counts_match_the_opening_and_stop_at_wins() {
+        let empty = GameState::empty(Player::Red);
+        for depth in 0..=6 {
+            assert_eq!(perft(&empty, depth), 7u64.pow(depth as u32));
+        }
+        // Red's winning drop in column 0 ends that line; the six other moves
+        // leave Blue seven replies each.
+        let threat = state("R0B1R0B1R0B1");
+        assert_eq!(perft(&threat, 2), 42);
+        let split = divide(&threat, 2);
+        assert_eq!(split[0], (0, 0));
+        assert_eq!(split.iter().map(|(_, n)| n).sum::<u64>(), 42);
+        // A full column takes no more discs.
+        assert_eq!(perft(&state("R0B0R0B0R0B0"), 1), 6);
+        assert!(divide(&state("R0B1R0B1R0B1R0"), 3).is_empty());
+    }
+}
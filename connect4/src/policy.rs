@@ -0,0 +1,153 @@
+//! Policy/value functions for MCTS, the plug-in point for a network trained
+//! elsewhere, AlphaZero style.
+//!
+//! Given one, MCTS stops playing random games: every new leaf is scored by
+//! the function's value, and its children are explored in proportion to the
+//! function's move priors (PUCT). [`HeuristicPolicy`] builds both from the
+//! alpha-beta evaluation; a learned agent implements [`PolicyValue`] itself
+//! and is handed to the search through `SearchOptions::policy`.
+use std::{fmt, sync::Arc};
+
+use crate::{eval::evaluate_weighted, EvalWeights, GameState, WIDTH, WIN_SCORE};
+
+/// Evaluation units per unit of `tanh` in the heuristic value: about four
+/// open threes make a clear advantage.
+const VALUE_SCALE: f64 = 200.0;
+/// Evaluation units that multiply a move's heuristic prior by `e`: one open
+/// three.
+const PRIOR_TEMPERATURE: f64 = 50.0;
+
+pub trait PolicyValue: Send + Sync {
+    /// Move priors by column and the value of `state` for the side to move,
+    /// from -1 (lost) to 1 (won). Priors need not sum to 1; the search
+    /// ignores full columns and normalises the rest. `state` is never
+    /// finished.
+    fn evaluate(&self, state: &GameState) -> ([f64; WIDTH], f64);
+}
+
+/// Priors and value from the heuristic evaluation: each move's prior grows
+/// exponentially with the evaluation after it, and the value is the
+/// position's evaluation squashed into -1..=1.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct HeuristicPolicy {
+    pub weights: EvalWeights,
+}
+
+impl PolicyValue for HeuristicPolicy {
+    fn evaluate(&self, state: &GameState) -> ([f64; WIDTH], f64) {
+        let player = state.to_move();
+        let mut scores = [None; WIDTH];
+        for column in state.legal_moves() {
+            let mut next = state.clone();
+            let won = next.play(column).is_ok_and(|outcome| outcome.won);
+            scores[column] = Some(if won {
+                WIN_SCORE
+            } else {
+                -evaluate_weighted(&next, player.opponent(), &self.weights)
+            });
+        }
+        // Relative to the best move, so the exponent never overflows.
+        let best = scores.iter().flatten().copied().max().unwrap_or(0);
+        let priors = scores.map(|score| {
+            score.map_or(0.0, |score| {
+                (f64::from(score - best).max(-20.0 * PRIOR_TEMPERATURE) / PRIOR_TEMPERATURE).exp()
+            })
+        });
+        let value = f64::from(evaluate_weighted(state, player, &self.weights)) / VALUE_SCALE;
+        (priors, value.tanh())
+    }
+}
+
+/// A `PolicyValue` shared between searches. Two are equal only when they are
+/// the same function, which is all `SearchOptions` needs to compare.
+#[derive(Clone)]
+pub struct SharedPolicy(pub Arc<dyn PolicyValue>);
+
+impl SharedPolicy {
+    pub fn new(policy: impl PolicyValue + 'static) -> Self {
+        Self(Arc::new(policy))
+    }
+}
+
+impl fmt::Debug for SharedPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SharedPolicy(..)")
+    }
+}
+
+impl PartialEq for SharedPolicy {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_history;
+    #[cfg(feature = "mcts")]
+    use crate::{search, Algorithm, SearchLimits, SearchOptions};
+
+    fn state(history: &str) -> GameState {
+        GameState::from_history(&parse_history(history).unwrap()).unwrap()
+    }
+
+    /// Always likes one column and never knows who is winning.
+    #[cfg(feature = "mcts")]
+    struct Stubborn(usize);
+
+    #[cfg(feature = "mcts")]
+    impl PolicyValue for Stubborn {
+        fn evaluate(&self, _: &GameState) -> ([f64; WIDTH], f64) {
+            let mut priors = [0.01; WIDTH];
+            priors[self.0] = 1.0;
+            (priors, 0.0)
+        }
+    }
+
+    #[cfg(feature = "mcts")]
+    fn mcts(policy: SharedPolicy, level: u8) -> SearchOptions {
+        SearchOptions {
+            algorithm: Algorithm::Mcts,
+            seed: Some(1),
+            policy: Some(policy),
+            randomness: 0.0,
+            ..SearchOptions::new(level)
+        }
+    }
+
+    #[test]
+    fn heuristic_priors_favour_wins() {
+        // Red threatens the bottom row on both sides of 1-3.
+        let (_, value) = HeuristicPolicy::default().evaluate(&state("R1B1R2B2R3"));
+        assert!(value < 0.0);
+        let (priors, value) = HeuristicPolicy::default().evaluate(&state("R1B1R2B2R3B6"));
+        assert_eq!(priors[0], 1.0);
+        assert_eq!(priors[4], 1.0);
+        assert!(priors[0] > 0.9 * priors.iter().sum::<f64>() / 2.0);
+        assert!(value > 0.0);
+    }
+
+    #[test]
+    #[cfg(feature = "mcts")]
+    fn mcts_follows_the_policy() {
+        let empty = GameState::empty(crate::Player::Red);
+        let limits = SearchLimits::default();
+        let stubborn = search(
+            &empty,
+            &mcts(SharedPolicy::new(Stubborn(6)), 1),
+            &limits,
+            |_| {},
+        );
+        // Every score is even, so only the visit counts rank the moves.
+        assert_eq!(stubborn.unwrap().lines[0].column, 6);
+
+        // With real guidance it finds the win in one.
+        let heuristic = mcts(SharedPolicy::new(HeuristicPolicy::default()), 1);
+        let win = search(&state("R1B1R2B2R3B6"), &heuristic, &limits, |_| {}).unwrap();
+        assert!(matches!(win.column, 0 | 4));
+        let policy = SharedPolicy::new(HeuristicPolicy::default());
+        assert_eq!(policy, policy.clone());
+        assert_ne!(policy, SharedPolicy::new(HeuristicPolicy::default()));
+    }
+}
@@ -0,0 +1,198 @@
+//! "Find the winning move" puzzles cut from random games.
+//!
+//! A puzzle is a position where the side to move forces a win in exactly the
+//! difficulty's number of plies, and only one first move does it. Positions
+//! come from random play, so the same seed always yields the same pack.
+use std::collections::HashSet;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    format_history, principal_variation, rng::Rng, score_columns, GameState, MoveRequest, Player,
+    TypedMove, WIN_SCORE,
+};
+
+/// Random games are cut at a ply in this range; earlier positions rarely hold
+/// a forced win and later ones are mostly decided already.
+const MIN_PLIES: usize = 8;
+const MAX_PLIES: usize = 32;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum Difficulty {
+    Easy,
+    Medium,
+    Hard,
+}
+
+impl Difficulty {
+    /// Plies from the puzzle position to the winning disc, both sides'
+    /// moves counted: win in 2, 3 or 4 moves.
+    pub fn plies(self) -> usize {
+        match self {
+            Self::Easy => 3,
+            Self::Medium => 5,
+            Self::Hard => 7,
+        }
+    }
+}
+
+/// What makes the winning move work.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum Theme {
+    /// The move threatens to win in two places at once.
+    DoubleThreat,
+    /// The move stops the opponent's own winning threat and still wins.
+    Counterattack,
+    /// Forcing moves that only build the decisive threat later on.
+    Combination,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Puzzle {
+    /// Move history in the usual `R3B3…` notation.
+    pub position: String,
+    pub to_move: Player,
+    /// The winning line, zero-based columns: the unique key move, the
+    /// defence's best replies and the winner's moves, ending with the win.
+    pub solution: Vec<usize>,
+    pub theme: Theme,
+    pub difficulty: Difficulty,
+}
+
+/// An endless stream of distinct puzzles. Each takes a few searches at the
+/// difficulty's depth, so `Hard` is the slowest.
+pub fn puzzles(difficulty: Difficulty, seed: Option<u64>) -> impl Iterator<Item = Puzzle> {
+    let mut rng = seed.map_or_else(Rng::from_entropy, Rng::new);
+    let mut seen = HashSet::new();
+    std::iter::repeat_with(move || loop {
+        if let Some((state, puzzle)) = candidate(difficulty, &mut rng) {
+            // By position, so a transposition of an earlier puzzle is skipped.
+            if seen.insert(state) {
+                return puzzle;
+            }
+        }
+    })
+}
+
+/// A random position, if it makes a puzzle of this difficulty.
+fn candidate(difficulty: Difficulty, rng: &mut Rng) -> Option<(GameState, Puzzle)> {
+    let plies = MIN_PLIES + rng.below(MAX_PLIES - MIN_PLIES + 1);
+    let mut state = GameState::empty(Player::Red);
+    let mut moves = Vec::new();
+    for _ in 0..plies {
+        let legal = state.legal_moves();
+        if state.winner().is_some() || legal.is_empty() {
+            return None;
+        }
+        let column = legal[rng.below(legal.len())];
+        moves.push(TypedMove {
+            player: state.to_move(),
+            column,
+        });
+        state.play(column).ok()?;
+    }
+    if state.winner().is_some() || state.is_full() {
+        return None;
+    }
+    let depth = difficulty.plies();
+    // Exactly this long: no win two plies sooner, as the loser moves in
+    // between.
+    if score_columns(&state, depth - 2)
+        .iter()
+        .any(|&(_, score)| score >= WIN_SCORE)
+    {
+        return None;
+    }
+    let winning: Vec<usize> = score_columns(&state, depth)
+        .into_iter()
+        .filter(|&(_, score)| score >= WIN_SCORE)
+        .map(|(column, _)| column)
+        .collect();
+    let [key] = winning[..] else {
+        return None;
+    };
+    let position = format_history(&moves);
+    let solution = principal_variation(MoveRequest {
+        position: position.clone(),
+        level: depth as u8,
+        first_player: None,
+    })
+    .ok()?;
+    debug_assert_eq!(solution.first(), Some(&key));
+    let puzzle = Puzzle {
+        theme: theme(&state, key),
+        to_move: state.to_move(),
+        position,
+        solution,
+        difficulty,
+    };
+    Some((state, puzzle))
+}
+
+/// Columns where `player` would win by dropping a disc right now.
+fn wins_now(state: &GameState, player: Player) -> Vec<usize> {
+    state
+        .legal_moves()
+        .into_iter()
+        .filter(|&column| {
+            let mut probe = state.clone();
+            probe.set_to_move(player);
+            probe.play(column).is_ok_and(|outcome| outcome.won)
+        })
+        .collect()
+}
+
+fn theme(state: &GameState, key: usize) -> Theme {
+    let player = state.to_move();
+    let mut after = state.clone();
+    after.play(key).expect("the key move is legal");
+    if wins_now(&after, player).len() >= 2 {
+        Theme::DoubleThreat
+    } else if wins_now(state, player.opponent()).contains(&key) {
+        Theme::Counterattack
+    } else {
+        Theme::Combination
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_history;
+
+    #[test]
+    fn puzzles_have_one_winning_move_at_their_distance() {
+        let pack: Vec<Puzzle> = puzzles(Difficulty::Easy, Some(1)).take(5).collect();
+        assert_eq!(
+            pack,
+            puzzles(Difficulty::Easy, Some(1))
+                .take(5)
+                .collect::<Vec<_>>()
+        );
+        for puzzle in &pack {
+            let moves = parse_history(&puzzle.position).unwrap();
+            let mut state = GameState::from_history(&moves).unwrap();
+            assert_eq!(state.to_move(), puzzle.to_move);
+            assert_eq!(puzzle.solution.len(), 3, "{puzzle:?}");
+            for &column in &puzzle.solution {
+                state.play(column).unwrap();
+            }
+            assert_eq!(state.winner(), Some(puzzle.to_move));
+        }
+    }
+
+    #[test]
+    fn themes_describe_the_key_move() {
+        let state = |history| GameState::from_history(&parse_history(history).unwrap()).unwrap();
+        // Red's disc in column 3 makes an open three on the bottom row.
+        assert_eq!(theme(&state("R1B1R2B2"), 3), Theme::DoubleThreat);
+        // Blue threatens column 0; Red blocks it with the winning move.
+        assert_eq!(theme(&state("R6B0R6B0R5B0"), 0), Theme::Counterattack);
+    }
+}
@@ -0,0 +1,293 @@
+//! Static board images as SVG, for embedding positions in pages, posts and
+//! link previews. The palette follows the web UI so a shared image looks like
+//! the game it came from.
+//!
+//! The `png` feature adds rasterization for places that will not display SVG,
+//! such as chat unfurlers. It draws the same SVG, so both formats always match.
+//! `text` draws the board for terminals, in ANSI colours or plain letters.
+use std::fmt::Write;
+
+use crate::{GameState, Player, HEIGHT, WIDTH};
+
+const BACKGROUND: &str = "#0b1221";
+const BOARD: &str = "#111827";
+const HOLE: &str = "#1f2937";
+const RED: &str = "#e11d48";
+const BLUE: &str = "#38bdf8";
+const HIGHLIGHT: &str = "#e5e7eb";
+
+/// ANSI escapes for `text`.
+const ANSI_RED: &str = "\x1b[91m";
+const ANSI_BLUE: &str = "\x1b[96m";
+const ANSI_HOLE: &str = "\x1b[90m";
+const ANSI_REVERSE: &str = "\x1b[7m";
+const ANSI_UNDERLINE: &str = "\x1b[4m";
+const ANSI_RESET: &str = "\x1b[0m";
+
+/// Cell sizes `with_width` will produce; beyond these images are either
+/// unreadable or needlessly large.
+pub const MIN_CELL_SIZE: u32 = 16;
+pub const MAX_CELL_SIZE: u32 = 256;
+
+/// Disc radius and board padding as fractions of the cell size.
+const DISC_RADIUS: f64 = 0.35;
+const PADDING: f64 = 0.25;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RenderOptions {
+    /// Pixel size of one cell; the image is `7.5 × 6.5` cells.
+    pub cell_size: u32,
+    /// Column of the move just played; its top disc gets a marker.
+    pub last_move: Option<usize>,
+    /// Outline the discs of a completed line of four.
+    pub highlight_win: bool,
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        Self {
+            cell_size: 64,
+            last_move: None,
+            highlight_win: true,
+        }
+    }
+}
+
+impl RenderOptions {
+    /// Options whose image is about `width` pixels wide, within the cell size bounds.
+    pub fn with_width(width: u32) -> Self {
+        let cells = WIDTH as f64 + 2.0 * PADDING;
+        let cell_size = (f64::from(width) / cells).round() as u32;
+        Self {
+            cell_size: cell_size.clamp(MIN_CELL_SIZE, MAX_CELL_SIZE),
+            ..Self::default()
+        }
+    }
+
+    /// Width and height of the rendered image in pixels.
+    pub fn dimensions(&self) -> (u32, u32) {
+        let cell = f64::from(self.cell_size);
+        let side = |cells: usize| (cells as f64 * cell + 2.0 * PADDING * cell).round() as u32;
+        (side(WIDTH), side(HEIGHT))
+    }
+}
+
+pub fn svg(state: &GameState, options: &RenderOptions) -> String {
+    let cell = f64::from(options.cell_size);
+    let (width, height) = options.dimensions();
+    let pad = PADDING * cell;
+    let radius = DISC_RADIUS * cell;
+    let center = |column: usize, row: usize| {
+        let x = pad + (column as f64 + 0.5) * cell;
+        let y = pad + ((HEIGHT - 1 - row) as f64 + 0.5) * cell;
+        (x, y)
+    };
+    let (winning, last) = highlights(state, options);
+
+    let mut out = String::new();
+    // Writing to a `String` cannot fail, hence the ignored results below.
+    let _ = write!(
+        out,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{width}" height="{height}" viewBox="0 0 {width} {height}">"#
+    );
+    let _ = write!(
+        out,
+        r#"<rect width="{width}" height="{height}" fill="{BACKGROUND}"/>"#
+    );
+    let _ = write!(
+        out,
+        r#"<rect x="{x:.1}" y="{x:.1}" width="{w:.1}" height="{h:.1}" rx="{r:.1}" fill="{BOARD}"/>"#,
+        x = pad / 2.0,
+        w = WIDTH as f64 * cell + pad,
+        h = HEIGHT as f64 * cell + pad,
+        r = cell / 4.0,
+    );
+    for column in 0..WIDTH {
+        for row in 0..HEIGHT {
+            let (x, y) = center(column, row);
+            let fill = match state.cell(column, row) {
+                Some(Player::Red) => RED,
+                Some(Player::Blue) => BLUE,
+                None => HOLE,
+            };
+            let _ = write!(
+                out,
+                r#"<circle cx="{x:.1}" cy="{y:.1}" r="{radius:.1}" fill="{fill}""#
+            );
+            if winning.contains(&(column, row)) {
+                let _ = write!(
+                    out,
+                    r#" stroke="{HIGHLIGHT}" stroke-width="{:.1}""#,
+                    cell / 16.0
+                );
+            }
+            out.push_str("/>");
+            if last == Some((column, row)) {
+                let _ = write!(
+                    out,
+                    r#"<circle cx="{x:.1}" cy="{y:.1}" r="{:.1}" fill="{HIGHLIGHT}" fill-opacity="0.8"/>"#,
+                    cell / 10.0
+                );
+            }
+        }
+    }
+    out.push_str("</svg>");
+    out
+}
+
+/// The board as text, top row first, with column numbers 1-7 underneath.
+/// With `color` discs are ANSI-coloured, the winning line is drawn in reverse
+/// video and the last move underlined; without it discs are `R` and `B` and
+/// the highlights are left out.
+pub fn text(state: &GameState, options: &RenderOptions, color: bool) -> String {
+    let (winning, last) = highlights(state, options);
+    let mut out = String::new();
+    for row in (0..HEIGHT).rev() {
+        out.push('|');
+        for column in 0..WIDTH {
+            let disc = state.cell(column, row);
+            if !color {
+                out.push(match disc {
+                    Some(Player::Red) => 'R',
+                    Some(Player::Blue) => 'B',
+                    None => '.',
+                });
+            } else {
+                if winning.contains(&(column, row)) {
+                    out.push_str(ANSI_REVERSE);
+                }
+                if last == Some((column, row)) {
+                    out.push_str(ANSI_UNDERLINE);
+                }
+                let (paint, glyph) = match disc {
+                    Some(Player::Red) => (ANSI_RED, '●'),
+                    Some(Player::Blue) => (ANSI_BLUE, '●'),
+                    None => (ANSI_HOLE, '·'),
+                };
+                let _ = write!(out, "{paint}{glyph}{ANSI_RESET}");
+            }
+            out.push('|');
+        }
+        out.push('\n');
+    }
+    out.push(' ');
+    for column in 1..=WIDTH {
+        let _ = write!(out, "{column} ");
+    }
+    out.pop();
+    out.push('\n');
+    out
+}
+
+/// Cells of the winning line to outline and the disc of the last move, as
+/// `(column, row)` pairs.
+type Highlights = (Vec<(usize, usize)>, Option<(usize, usize)>);
+
+fn highlights(state: &GameState, options: &RenderOptions) -> Highlights {
+    let winning = if options.highlight_win {
+        state.winning_line().unwrap_or_default()
+    } else {
+        Vec::new()
+    };
+    let last = options.last_move.and_then(|column| {
+        (0..HEIGHT)
+            .rev()
+            .find(|&row| state.cell(column, row).is_some())
+            .map(|row| (column, row))
+    });
+    let winning = winning.into_iter().map(|c| (c.column, c.row)).collect();
+    (winning, last)
+}
+
+#[cfg(feature = "png")]
+#[derive(Debug, thiserror::Error)]
+pub enum RenderError {
+    #[error("cannot parse rendered SVG: {0}")]
+    Svg(#[from] resvg::usvg::Error),
+    #[error("image of {0}x{1} pixels cannot be allocated")]
+    Size(u32, u32),
+    #[error("cannot encode PNG: {0}")]
+    Encode(String),
+}
+
+/// The `svg` image rasterized to PNG at its natural size.
+#[cfg(feature = "png")]
+pub fn png(state: &GameState, options: &RenderOptions) -> Result<Vec<u8>, RenderError> {
+    use resvg::{tiny_skia, usvg};
+
+    let tree = usvg::Tree::from_str(&svg(state, options), &usvg::Options::default())?;
+    let (width, height) = options.dimensions();
+    let mut pixmap =
+        tiny_skia::Pixmap::new(width, height).ok_or(RenderError::Size(width, height))?;
+    resvg::render(&tree, tiny_skia::Transform::default(), &mut pixmap.as_mut());
+    pixmap
+        .encode_png()
+        .map_err(|err| RenderError::Encode(err.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_history;
+
+    fn state(history: &str) -> GameState {
+        GameState::from_history(&parse_history(history).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn renders_every_cell_with_highlights() {
+        let options = RenderOptions {
+            last_move: Some(0),
+            ..RenderOptions::default()
+        };
+        let image = svg(&state("R0B1R0B1R0B1R0"), &options);
+        assert!(image.starts_with("<svg") && image.ends_with("</svg>"));
+        assert!(image.contains(r#"width="480" height="416""#));
+        // 42 holes or discs plus the last-move marker.
+        assert_eq!(image.matches("<circle").count(), 43);
+        assert_eq!(image.matches(&format!(r#"fill="{RED}""#)).count(), 4);
+        assert_eq!(image.matches(&format!(r#"fill="{BLUE}""#)).count(), 3);
+        assert_eq!(image.matches("stroke=").count(), 4);
+
+        let plain = svg(&state("R0B1"), &RenderOptions::default());
+        assert_eq!(plain.matches("<circle").count(), 42);
+        assert!(!plain.contains("stroke="));
+    }
+
+    #[test]
+    fn text_boards_in_letters_or_colour() {
+        let board = text(&state("R0B1R0B1R0B1R0"), &RenderOptions::default(), false);
+        let lines: Vec<&str> = board.lines().collect();
+        assert_eq!(lines.len(), HEIGHT + 1);
+        assert_eq!(lines[2], "|R|.|.|.|.|.|.|");
+        assert_eq!(lines[5], "|R|B|.|.|.|.|.|");
+        assert_eq!(lines[6], " 1 2 3 4 5 6 7");
+
+        let options = RenderOptions {
+            last_move: Some(0),
+            ..RenderOptions::default()
+        };
+        let board = text(&state("R0B1R0B1R0B1R0"), &options, true);
+        assert_eq!(board.matches(ANSI_RED).count(), 4);
+        assert_eq!(board.matches(ANSI_REVERSE).count(), 4);
+        assert_eq!(board.matches(ANSI_UNDERLINE).count(), 1);
+    }
+
+    #[test]
+    fn width_picks_a_bounded_cell_size() {
+        assert_eq!(RenderOptions::with_width(480).dimensions(), (480, 416));
+        assert_eq!(RenderOptions::with_width(1).cell_size, MIN_CELL_SIZE);
+        assert_eq!(RenderOptions::with_width(100_000).cell_size, MAX_CELL_SIZE);
+    }
+
+    #[cfg(feature = "png")]
+    #[test]
+    fn png_has_the_svg_dimensions() {
+        let options = RenderOptions::with_width(240);
+        let png = png(&state("R3B3"), &options).unwrap();
+        assert_eq!(&png[1..4], b"PNG");
+        // IHDR width and height, big-endian, right after the 16-byte header.
+        let dimension = |at: usize| u32::from_be_bytes(png[at..at + 4].try_into().unwrap());
+        assert_eq!((dimension(16), dimension(20)), options.dimensions());
+    }
+}
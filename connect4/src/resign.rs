@@ -0,0 +1,124 @@
+//! When the engine should stop playing on: resigning a lost game, offering
+//! a drawn one.
+//!
+//! A single search is judged together with the ones before it, so one
+//! pessimistic score does not end a game. Losses go by the score alone;
+//! draws are offered only once a search has reached the end of the board and
+//! proved the draw, since a heuristic score near zero says little.
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::{GameState, MoveResponse, SearchInfo, MAX_CELLS, WIN_SCORE};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, deny_unknown_fields))]
+pub struct ResignPolicy {
+    /// Resign at or below this score, from the engine's point of view. The
+    /// default, -1 000 000, waits for a forced loss.
+    pub threshold: i32,
+    /// Consecutive moves the score must stay at or below `threshold`, or
+    /// stay a proven draw, before resigning or offering a draw.
+    pub moves: usize,
+}
+
+impl Default for ResignPolicy {
+    fn default() -> Self {
+        Self {
+            threshold: -WIN_SCORE,
+            moves: 2,
+        }
+    }
+}
+
+/// One engine's searches through a game, judged against a `ResignPolicy`.
+#[derive(Clone, Debug, Default)]
+pub struct ResignTracker {
+    policy: ResignPolicy,
+    losing: usize,
+    drawn: usize,
+}
+
+impl ResignTracker {
+    pub fn new(policy: ResignPolicy) -> Self {
+        Self {
+            policy,
+            losing: 0,
+            drawn: 0,
+        }
+    }
+
+    /// Note the engine's search from `state`, whose deepest completed
+    /// iteration was `last`, and set `should_resign` and `offers_draw` on the
+    /// move it returned.
+    pub fn observe(&mut self, state: &GameState, last: &SearchInfo, response: &mut MoveResponse) {
+        // Every line ends on a full board, so every leaf is a real result.
+        let remaining = MAX_CELLS - usize::from(state.moves_played);
+        let proven_draw = last.score == 0 && last.depth >= remaining;
+        self.losing = if last.score <= self.policy.threshold {
+            self.losing + 1
+        } else {
+            0
+        };
+        self.drawn = if proven_draw { self.drawn + 1 } else { 0 };
+        let moves = self.policy.moves.max(1);
+        response.should_resign = self.losing >= moves;
+        response.offers_draw = !response.should_resign && self.drawn >= moves;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{best_move_within, parse_history, MoveRequest, SearchLimits};
+
+    /// The engine's move in `history` at `level`, judged by `tracker`.
+    fn judged(tracker: &mut ResignTracker, history: &str, level: u8) -> MoveResponse {
+        let state = GameState::from_history(&parse_history(history).unwrap()).unwrap();
+        let request = MoveRequest {
+            position: history.to_string(),
+            level,
+            first_player: None,
+        };
+        let mut last = None;
+        let mut response = best_move_within(request, &SearchLimits::default(), |info| {
+            last = Some(info.clone())
+        })
+        .unwrap();
+        tracker.observe(&state, &last.unwrap(), &mut response);
+        response
+    }
+
+    #[test]
+    fn resigns_after_consecutive_forced_losses() {
+        // Red has three on the bottom row with both ends open.
+        let lost = "R1B1R2B2R3";
+        let mut tracker = ResignTracker::default();
+        let first = judged(&mut tracker, lost, 4);
+        assert!(!first.should_resign && !first.offers_draw);
+        assert!(judged(&mut tracker, lost, 4).should_resign);
+        assert!(!judged(&mut tracker, "", 4).should_resign);
+
+        let mut impatient = ResignTracker::new(ResignPolicy {
+            threshold: -100,
+            moves: 1,
+        });
+        assert!(judged(&mut impatient, lost, 4).should_resign);
+    }
+
+    #[test]
+    fn offers_draws_only_once_proven() {
+        // Columns 0-5 are full, and filling the last one makes no four.
+        let drawn = "R3B3R3B3R3B3R2B2R2B2R2B2R4B4R4B4R4B4R0B1R1B1R1B1R1B5R5B5R5B5R5B0R0B0R0B0";
+        let mut tracker = ResignTracker::default();
+        assert!(!judged(&mut tracker, drawn, 8).offers_draw);
+        assert!(judged(&mut tracker, drawn, 8).offers_draw);
+
+        // A quiet opening scores near zero but is far from proven.
+        let mut tracker = ResignTracker::new(ResignPolicy {
+            moves: 1,
+            ..ResignPolicy::default()
+        });
+        assert!(!judged(&mut tracker, "R3B3", 2).offers_draw);
+    }
+}
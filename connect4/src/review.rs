@@ -0,0 +1,269 @@
+//! Post-game review: replay a finished game and grade every move against the
+//! engine's preferred alternative at a fixed depth, and show with [`refute`]
+//! how a poor move would be punished.
+//!
+//! Grades are driven by the score lost relative to the best column, measured
+//! in the same heuristic units as `evaluate` (an open three is worth 50), plus
+//! one special case: throwing away a forced win, or walking into a forced
+//! loss, is always a blunder however small the numeric gap looks.
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    engine_info, score_columns, EngineInfo, GameError, GameState, GameStatus, Player, ScoredMove,
+    TypedMove, MOVE_ORDER, WIN_SCORE,
+};
+#[cfg(feature = "solver")]
+use crate::{search, Algorithm, SearchLimits, SearchOptions};
+
+/// Depth used when callers have no preference: deep enough to see short
+/// tactics, shallow enough to review a full game in well under a second.
+pub const DEFAULT_REVIEW_DEPTH: usize = 6;
+
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum MoveClass {
+    Best,
+    Good,
+    Inaccuracy,
+    Mistake,
+    Blunder,
+}
+
+impl MoveClass {
+    fn grade(best: i32, played: i32) -> Self {
+        let decisive = WIN_SCORE / 2;
+        let threw_win = best >= decisive && played < decisive;
+        let walked_into_loss = played <= -decisive && best > -decisive;
+        match best - played {
+            _ if threw_win || walked_into_loss => Self::Blunder,
+            0 => Self::Best,
+            loss if loss < 20 => Self::Good,
+            loss if loss < 60 => Self::Inaccuracy,
+            loss if loss < 150 => Self::Mistake,
+            _ => Self::Blunder,
+        }
+    }
+}
+
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct MoveReview {
+    /// Zero-based index of the move in the game.
+    pub ply: usize,
+    pub player: Player,
+    pub played: usize,
+    pub best: usize,
+    /// Scores from the mover's point of view.
+    pub played_score: i32,
+    pub best_score: i32,
+    pub class: MoveClass,
+    /// Columns that scored better than the one played, best first.
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Vec::is_empty")
+    )]
+    pub alternatives: Vec<ScoredMove>,
+}
+
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct GameReview {
+    pub depth: usize,
+    pub moves: Vec<MoveReview>,
+    /// The engine that graded the moves.
+    pub engine: EngineInfo,
+}
+
+impl GameReview {
+    pub fn count(&self, player: Player, class: MoveClass) -> usize {
+        self.moves
+            .iter()
+            .filter(|m| m.player == player && m.class == class)
+            .count()
+    }
+}
+
+/// Grade every move of `moves`. Moves played after the game was decided are
+/// not graded, since there was nothing left to play for.
+pub fn review_game(moves: &[TypedMove], depth: usize) -> Result<GameReview, GameError> {
+    review_game_with(moves, depth, |_| {})
+}
+
+/// `review_game`, handing each move's review to `on_move` as soon as it is
+/// graded, so a long game can be shown while the rest is still searched.
+pub fn review_game_with(
+    moves: &[TypedMove],
+    depth: usize,
+    mut on_move: impl FnMut(&MoveReview),
+) -> Result<GameReview, GameError> {
+    let mut state = match moves.first() {
+        Some(first) => GameState::empty(first.player),
+        None => GameState::empty(Player::Red),
+    };
+    let mut reviews = Vec::with_capacity(moves.len());
+    for (ply, mv) in moves.iter().enumerate() {
+        if state.status() != GameStatus::InProgress {
+            break;
+        }
+        let scores = score_columns(&state, depth);
+        // Ties go to the column the engine itself would try first.
+        let order = |col: usize| MOVE_ORDER.iter().position(|&c| c == col);
+        let &(best, best_score) = scores
+            .iter()
+            .max_by_key(|&&(col, score)| (score, std::cmp::Reverse(order(col))))
+            .ok_or(GameError::NoMoves)?;
+        let played_score = scores
+            .iter()
+            .find(|&&(col, _)| col == mv.column)
+            .map(|&(_, score)| score)
+            .ok_or(GameError::ColumnFull { column: mv.column })?;
+        let mut alternatives: Vec<ScoredMove> = scores
+            .iter()
+            .filter(|&&(_, score)| score > played_score)
+            .map(|&(column, score)| ScoredMove { column, score })
+            .collect();
+        alternatives.sort_by_key(|alt| (std::cmp::Reverse(alt.score), order(alt.column)));
+        let review = MoveReview {
+            ply,
+            player: mv.player,
+            played: mv.column,
+            best,
+            played_score,
+            best_score,
+            class: MoveClass::grade(best_score, played_score),
+            alternatives,
+        };
+        on_move(&review);
+        reviews.push(review);
+        state.force_play(mv.player, mv.column)?;
+    }
+    Ok(GameReview {
+        depth,
+        moves: reviews,
+        engine: engine_info(),
+    })
+}
+
+/// The opponent's best answer to a move, e.g. "if you had played 2, Red
+/// wins with 3-3-4".
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Refutation {
+    /// The move refuted.
+    pub column: usize,
+    /// The opponent's reply and the expected play after it. Empty when
+    /// `column` itself ends the game.
+    pub line: Vec<usize>,
+    /// Score of `column` from the point of view of the side playing it;
+    /// -1 000 000 is a forced loss.
+    pub score: i32,
+    /// Plies searched after `column`.
+    pub depth: usize,
+}
+
+impl Refutation {
+    /// Whether `line` forces a win for the opponent.
+    pub fn loses(&self) -> bool {
+        self.score <= -WIN_SCORE
+    }
+}
+
+/// Search the position after `column` until its result is proven or `limits`
+/// run out, and return the opponent's punishing line. Searches end early
+/// only on a deadline, cancellation or node budget, so give one outside of
+/// endgames. Needs the `solver` feature.
+#[cfg(feature = "solver")]
+pub fn refute(
+    state: &GameState,
+    column: usize,
+    limits: &SearchLimits,
+) -> Result<Refutation, GameError> {
+    if state.status() != GameStatus::InProgress {
+        return Err(GameError::NoMoves);
+    }
+    let mut after = state.clone();
+    let played = after.play(column)?;
+    if played.won || after.is_full() {
+        return Ok(Refutation {
+            column,
+            line: Vec::new(),
+            score: if played.won { WIN_SCORE } else { 0 },
+            depth: 0,
+        });
+    }
+    let options = SearchOptions {
+        algorithm: Algorithm::Solver,
+        ..SearchOptions::new(1)
+    };
+    let mut line = Vec::new();
+    let reply = search(&after, &options, limits, |info| line.clone_from(&info.pv))?;
+    Ok(Refutation {
+        column,
+        line,
+        score: -reply.score,
+        depth: reply.depth,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_history;
+
+    #[test]
+    fn missing_a_block_is_a_blunder() {
+        // Red threatens 3 on the bottom row; Blue ignores it and Red wins.
+        let moves = parse_history("R0B0R1B1R2B6R3").unwrap();
+        let review = review_game(&moves, 4).unwrap();
+        assert_eq!(review.moves.len(), moves.len());
+        let blue_last = &review.moves[5];
+        assert_eq!((blue_last.played, blue_last.best), (6, 3));
+        assert_eq!(blue_last.class, MoveClass::Blunder);
+        assert_eq!(blue_last.alternatives[0].column, 3);
+        assert!(blue_last
+            .alternatives
+            .windows(2)
+            .all(|pair| pair[0].score >= pair[1].score));
+        assert!(review.moves[6].alternatives.is_empty());
+        let mut streamed = Vec::new();
+        let again = review_game_with(&moves, 4, |m| streamed.push(m.clone())).unwrap();
+        assert_eq!((streamed, again), (review.moves.clone(), review.clone()));
+        assert_eq!(review.moves[6].class, MoveClass::Best);
+        assert_eq!(review.count(Player::Blue, MoveClass::Blunder), 1);
+    }
+
+    #[test]
+    #[cfg(feature = "solver")]
+    fn refutations_show_the_punishing_line() {
+        let state =
+            |history: &str| GameState::from_history(&parse_history(history).unwrap()).unwrap();
+        let limits = SearchLimits::default();
+
+        // Blue ignores Red's bottom-row three.
+        let missed_block = refute(&state("R0B0R1B1R2"), 6, &limits).unwrap();
+        assert_eq!(missed_block.line, [3]);
+        assert!(missed_block.loses());
+
+        // Blue lets Red open a three with both ends free.
+        let open_three = refute(&state("R1B1R2"), 6, &limits).unwrap();
+        assert_eq!((open_three.line.len(), open_three.line[0]), (3, 3));
+        assert!(open_three.loses());
+
+        // Far from the end, the budget decides how deep the search looks.
+        let budget = SearchLimits::with_max_nodes(100_000);
+        let block = refute(&state("R0B0R1B1R2"), 3, &budget).unwrap();
+        assert!(!block.loses() && block.depth > 4 && !block.line.is_empty());
+        let win = refute(&state("R0B0R1B1R2B6"), 3, &limits).unwrap();
+        assert_eq!((win.line.len(), win.score), (0, WIN_SCORE));
+        assert!(matches!(
+            refute(&state("R0B0R1B1R2B6"), 9, &limits),
+            Err(GameError::ColumnOutOfBounds { column: 9 })
+        ));
+    }
+}
@@ -0,0 +1,59 @@
+//! Tiny deterministic RNG for move randomization and MCTS playouts.
+//!
+//! SplitMix64: statistically fine for picking moves, one `u64` of state, and
+//! reproducible from a seed, which keeps randomized searches testable. Not
+//! suitable for anything that has to be unpredictable to an adversary.
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+};
+
+pub(crate) struct Rng(u64);
+
+impl Rng {
+    pub(crate) fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    /// Seeded from the process's hash randomization, so unseeded searches
+    /// differ between calls without pulling in an entropy crate.
+    pub(crate) fn from_entropy() -> Self {
+        Self(RandomState::new().build_hasher().finish())
+    }
+
+    pub(crate) fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform index in `0..n`; `n` must be positive.
+    pub(crate) fn below(&mut self, n: usize) -> usize {
+        // Multiply-shift keeps the bias negligible for the tiny `n` used here.
+        ((u128::from(self.next_u64()) * n as u128) >> 64) as usize
+    }
+
+    /// Uniform in `0.0..1.0`, from the top 53 bits.
+    pub(crate) fn unit(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Index drawn in proportion to `weights`, which must not all be zero.
+    pub(crate) fn weighted(&mut self, weights: &[f64]) -> usize {
+        let total: f64 = weights.iter().sum();
+        let mut target = self.unit() * total;
+        for (index, &weight) in weights.iter().enumerate() {
+            if target < weight {
+                return index;
+            }
+            target -= weight;
+        }
+        // Rounding can leave a sliver past the last weight.
+        weights
+            .iter()
+            .rposition(|&weight| weight > 0.0)
+            .unwrap_or(0)
+    }
+}
@@ -0,0 +1,666 @@
+//! The alpha-beta engine behind every search: negamax with a transposition
+//! table, iterative deepening's bookkeeping and the optional reductions,
+//! pruning and move ordering heuristics of `SearchOptions`.
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    eval::{evaluate_weighted, THREE_WEIGHT},
+    fnv1a, nn, trace,
+    tt::{Bound, Entry, TranspositionTable},
+    EvalWeights, GameError, GameState, MoveOrdering, Player, SearchLimits, SearchOptions,
+    COL_HEIGHT, WIDTH, WIN_SCORE,
+};
+
+/// How many nodes pass between deadline checks (a power of two); reading the clock at every
+/// node would cost more than the evaluation itself.
+const LIMIT_CHECK_INTERVAL: u64 = 1024;
+/// Moves searched at full depth at each node before late move reductions
+/// may apply: the table's move and the next best in move order.
+const LMR_FULL_DEPTH_MOVES: usize = 3;
+/// Shallowest depth at which late moves are reduced.
+const LMR_MIN_DEPTH: usize = 4;
+/// Plies taken off a reduced move.
+const LMR_REDUCTION: usize = 2;
+/// Shallowest depth at which `MoveOrdering::Threats` sorts moves; nearer the
+/// leaves counting threats costs more than the better order saves.
+const THREAT_ORDERING_MIN_DEPTH: usize = 3;
+/// How far one or two more plies may lift the static evaluation, by
+/// remaining depth, before futility pruning assumes a quiet move cannot
+/// reach alpha: an open three for the last ply, two for the last two.
+const FUTILITY_MARGINS: [i32; 2] = [THREE_WEIGHT, 2 * THREE_WEIGHT];
+
+/// Half-width of the window a hinted search tries first, in evaluation
+/// units: one open three either way.
+const ASPIRATION_WINDOW: i32 = THREE_WEIGHT;
+
+/// Per-search bookkeeping threaded through the recursion.
+pub(crate) struct Search {
+    pub(crate) nodes: u64,
+    limits: SearchLimits,
+    pub(crate) enforce_limits: bool,
+    aborted: bool,
+    /// Best root move the current pass has finished searching; what is left
+    /// of an iteration a limit interrupted.
+    pub(crate) partial_best: Option<usize>,
+    /// Best line found so far at each remaining depth; a node's line is its
+    /// best move followed by the line of the child one depth below.
+    lines: Vec<Vec<usize>>,
+    weights: EvalWeights,
+    /// With `weights` that are not symmetric between the sides: the side to
+    /// move at the root, from whose view every leaf is scored.
+    side: Option<Player>,
+    /// Replaces `weights` when set.
+    network: Option<Arc<nn::NnEval>>,
+    late_move_reductions: bool,
+    futility_pruning: bool,
+    ordering: MoveOrdering,
+    /// With `SearchOptions::countermoves`: by the opponent's colour and the
+    /// cell of their last disc, the reply that most recently caused a
+    /// cutoff there.
+    countermoves: Option<Box<[[Option<usize>; WIDTH * COL_HEIGHT]; 2]>>,
+    eval_cache: EvalCache,
+    table: Arc<TranspositionTable>,
+    /// Mixed into table keys so that searches with different weights or
+    /// reductions can share a table without reading each other's scores.
+    salt: u64,
+    table_probes: u64,
+    table_hits: u64,
+    table_stores: u64,
+}
+
+impl Search {
+    /// A search with the tree-shaping parts of `options`: the weights, the
+    /// reductions and pruning, and the ordering heuristics.
+    pub(crate) fn new(limits: &SearchLimits, options: &SearchOptions) -> Self {
+        let table = limits.table.clone().unwrap_or_default();
+        table.new_search();
+        let weights = options.weights;
+        let (late_move_reductions, futility_pruning) =
+            (options.late_move_reductions, options.futility_pruning);
+        let network = options
+            .network
+            .as_ref()
+            .map(|network| network.fingerprint());
+        // FNV-1a rather than `Hash`, whose output may differ between
+        // platforms and toolchains, which would move table collisions and
+        // so the node counts of budgeted searches.
+        let salt = if weights == EvalWeights::default()
+            && network.is_none()
+            && !late_move_reductions
+            && !futility_pruning
+        {
+            0
+        } else {
+            fnv1a(
+                weights
+                    .fingerprint()
+                    .to_le_bytes()
+                    .into_iter()
+                    .chain(network.unwrap_or(0).to_le_bytes())
+                    .chain([
+                        u8::from(network.is_some()),
+                        u8::from(late_move_reductions),
+                        u8::from(futility_pruning),
+                    ]),
+            )
+        };
+        Self {
+            nodes: 0,
+            limits: limits.clone(),
+            enforce_limits: false,
+            aborted: false,
+            partial_best: None,
+            lines: Vec::new(),
+            weights,
+            side: None,
+            network: options.network.clone(),
+            late_move_reductions,
+            futility_pruning,
+            ordering: options.ordering,
+            countermoves: options
+                .countermoves
+                .then(|| Box::new([[None; WIDTH * COL_HEIGHT]; 2])),
+            eval_cache: EvalCache::default(),
+            table,
+            salt,
+            table_probes: 0,
+            table_hits: 0,
+            table_stores: 0,
+        }
+    }
+}
+
+impl Default for Search {
+    fn default() -> Self {
+        Self::new(&SearchLimits::default(), &SearchOptions::new(1))
+    }
+}
+
+impl Search {
+    /// Add this search's table counts to the table's own and start counting
+    /// afresh, so the table's stats include the search so far.
+    pub(crate) fn flush_table_counts(&mut self) {
+        self.table
+            .record(self.table_probes, self.table_hits, self.table_stores);
+        (self.table_probes, self.table_hits, self.table_stores) = (0, 0, 0);
+    }
+}
+
+impl Drop for Search {
+    fn drop(&mut self) {
+        self.flush_table_counts();
+    }
+}
+
+/// Entries in a search's evaluation cache (a power of two), 128 KiB in all.
+const EVAL_CACHE_ENTRIES: usize = 1 << 13;
+
+/// Static evaluations by Zobrist hash, direct-mapped: a new entry overwrites
+/// whatever shared its slot. Iterative deepening reaches the same leaves in
+/// every pass, and transpositions reach them more than once in one pass.
+/// Valid for one evaluation, so each search owns one.
+struct EvalCache {
+    /// `(hash, score)`. The zeroed start is a correct entry too: hash 0 is
+    /// the empty board with Red to move, which evaluates to 0.
+    entries: Vec<(u64, i32)>,
+}
+
+impl Default for EvalCache {
+    fn default() -> Self {
+        Self {
+            entries: vec![(0, 0); EVAL_CACHE_ENTRIES],
+        }
+    }
+}
+
+impl Search {
+    pub(crate) fn line(&self, depth: usize) -> &[usize] {
+        self.lines.get(depth).map_or(&[], Vec::as_slice)
+    }
+
+    /// Make `column`, followed by the line just found one depth below unless
+    /// the move ended the game, the best line at `depth`.
+    fn set_line(&mut self, depth: usize, column: usize, searched_child: bool) {
+        if self.lines.len() <= depth {
+            self.lines.resize_with(depth + 1, Vec::new);
+        }
+        let mut line = std::mem::take(&mut self.lines[depth]);
+        line.clear();
+        line.push(column);
+        if searched_child && depth > 0 {
+            line.extend_from_slice(self.line(depth - 1));
+        }
+        self.lines[depth] = line;
+    }
+
+    /// `evaluate_weighted`, or the network's evaluation, through the cache.
+    fn evaluate(&mut self, state: &GameState, player: Player) -> i32 {
+        // The hash covers the side to move, which is always `player` here.
+        debug_assert_eq!(player, state.to_move);
+        let hash = state.hash();
+        let slot = &mut self.eval_cache.entries[hash as usize & (EVAL_CACHE_ENTRIES - 1)];
+        if slot.0 == hash {
+            return slot.1;
+        }
+        let score = match (&self.network, self.side) {
+            (Some(network), _) => network.evaluate(state),
+            (None, Some(side)) if side != player => -evaluate_weighted(state, side, &self.weights),
+            (None, _) => evaluate_weighted(state, player, &self.weights),
+        };
+        *slot = (hash, score);
+        score
+    }
+
+    /// Fix the root side of a search with asymmetric weights, and keep its
+    /// table entries apart from a search for the other side.
+    fn orient(&mut self, root: Player) {
+        if self.weights.defense == EvalWeights::default().defense || self.side.is_some() {
+            return;
+        }
+        self.side = Some(root);
+        self.salt = fnv1a(
+            self.salt
+                .to_le_bytes()
+                .into_iter()
+                .chain([root.idx() as u8]),
+        );
+    }
+
+    /// Sticky: once a limit trips, every frame unwinds without further work.
+    /// The node budget is checked at every node, the clock and cancellation
+    /// only every `LIMIT_CHECK_INTERVAL` nodes.
+    fn should_stop(&mut self) -> bool {
+        if !self.aborted
+            && self.enforce_limits
+            && (self.limits.out_of_nodes(self.nodes)
+                || self.nodes & (LIMIT_CHECK_INTERVAL - 1) == 0 && self.limits.exceeded(self.nodes))
+        {
+            self.aborted = true;
+        }
+        self.aborted
+    }
+
+    /// The best move at `depth` and its exact score, or `None` if a limit
+    /// interrupted the pass. With a `guess` the search first looks only for
+    /// scores near it, and searches again with the full window if the score
+    /// turns out to lie outside.
+    pub(crate) fn root(
+        &mut self,
+        state: &mut GameState,
+        depth: usize,
+        guess: Option<i32>,
+    ) -> Result<Option<(usize, i32)>, GameError> {
+        if let Some(guess) = guess {
+            let (alpha, beta) = (guess - ASPIRATION_WINDOW, guess + ASPIRATION_WINDOW);
+            match self.root_window(state, depth, alpha, beta)? {
+                None => return Ok(None),
+                Some((Some(col), score)) if alpha < score && score < beta => {
+                    return Ok(Some((col, score)))
+                }
+                Some(_) => {}
+            }
+        }
+        let Some((best_col, score)) = self.root_window(state, depth, i32::MIN / 2, i32::MAX / 2)?
+        else {
+            return Ok(None);
+        };
+        best_col
+            .map(|col| Some((col, score)))
+            .ok_or(GameError::NoMoves)
+    }
+
+    /// The root within `(alpha, beta)`: the best move, if any scored above
+    /// `alpha`, and its score, a bound when outside the window. `None` if a
+    /// limit interrupted the pass. The hinted move is searched first, but
+    /// ties still go to the first move in move order, so the answer is the
+    /// one a search without the hint gives.
+    fn root_window(
+        &mut self,
+        state: &mut GameState,
+        depth: usize,
+        mut alpha: i32,
+        beta: i32,
+    ) -> Result<Option<(Option<usize>, i32)>, GameError> {
+        let player = state.to_move;
+        self.orient(player);
+        let window = (alpha, beta);
+        let moves = state.legal_moves();
+        let mut order: Vec<usize> = (0..moves.len()).collect();
+        let hinted = self
+            .limits
+            .hint
+            .and_then(|hint| moves.iter().position(|&m| m == hint.column));
+        if let Some(first) = hinted {
+            order[..=first].rotate_right(1);
+        }
+        // Index into `moves` of the best move so far.
+        let mut best: Option<usize> = None;
+        self.partial_best = None;
+        for i in order {
+            let col = moves[i];
+            // A move ahead of the best in move order takes it on a tie, so it
+            // must be searched for scores equal to alpha too.
+            let floor = if best.is_some_and(|b| i < b) {
+                alpha - 1
+            } else {
+                alpha
+            };
+            let mut child = state.clone();
+            let outcome = child.play(col)?;
+            self.nodes += 1;
+            let val = if outcome.won {
+                WIN_SCORE
+            } else if child.is_full() {
+                0
+            } else {
+                -self.negamax(
+                    &child,
+                    col,
+                    depth.saturating_sub(1),
+                    -beta,
+                    -floor,
+                    player.opponent(),
+                )
+            };
+            // The move being searched when a limit tripped has no score.
+            if self.aborted {
+                break;
+            }
+            if val > floor {
+                alpha = val;
+                best = Some(i);
+                self.partial_best = Some(col);
+                self.set_line(depth, col, !outcome.won && !child.is_full());
+                if alpha >= beta {
+                    break;
+                }
+            }
+        }
+
+        if self.aborted {
+            return Ok(None);
+        }
+        let best = best.map(|i| moves[i]);
+        self.trace(state, depth, window, alpha, best);
+        Ok(Some((best, alpha)))
+    }
+
+    /// Every legal column with its exact score, in move order, or `None` if a
+    /// limit interrupted the pass.
+    pub(crate) fn root_scores(
+        &mut self,
+        state: &GameState,
+        depth: usize,
+    ) -> Option<Vec<(usize, i32)>> {
+        let player = state.to_move;
+        self.orient(player);
+        let mut best = i32::MIN;
+        self.partial_best = None;
+        let scores = state
+            .legal_moves()
+            .into_iter()
+            .map(|col| {
+                let mut child = state.clone();
+                let outcome = child.play(col).expect("legal move must succeed");
+                self.nodes += 1;
+                let terminal = outcome.won || child.is_full();
+                let score = if outcome.won {
+                    WIN_SCORE
+                } else if child.is_full() {
+                    0
+                } else {
+                    -self.negamax(
+                        &child,
+                        col,
+                        depth.saturating_sub(1),
+                        i32::MIN / 2,
+                        i32::MAX / 2,
+                        player.opponent(),
+                    )
+                };
+                if score > best && !self.aborted {
+                    best = score;
+                    self.partial_best = Some(col);
+                    self.set_line(depth, col, !terminal);
+                }
+                (col, score)
+            })
+            .collect();
+        (!self.aborted).then_some(scores)
+    }
+
+    /// Append a finished node to the trace, if there is one.
+    fn trace(
+        &self,
+        state: &GameState,
+        depth: usize,
+        (alpha, beta): (i32, i32),
+        score: i32,
+        best: Option<usize>,
+    ) {
+        if let Some(tracer) = &self.limits.trace {
+            tracer.record(&trace::TraceNode {
+                key: state.key(),
+                depth: depth as u8,
+                alpha,
+                beta,
+                score,
+                best,
+            });
+        }
+    }
+
+    /// `previous` is the column the opponent just played.
+    fn negamax(
+        &mut self,
+        state: &GameState,
+        previous: usize,
+        depth: usize,
+        alpha: i32,
+        beta: i32,
+        player: Player,
+    ) -> i32 {
+        let (score, best) = self.negamax_node(state, previous, depth, alpha, beta, player);
+        if !self.aborted {
+            self.trace(state, depth, (alpha, beta), score, best);
+        }
+        score
+    }
+
+    /// `negamax`'s score and the column it came from.
+    fn negamax_node(
+        &mut self,
+        state: &GameState,
+        previous: usize,
+        depth: usize,
+        mut alpha: i32,
+        beta: i32,
+        player: Player,
+    ) -> (i32, Option<usize>) {
+        if self.should_stop() {
+            return (0, None);
+        }
+        if let Some(line) = self.lines.get_mut(depth) {
+            line.clear();
+        }
+        if depth == 0 || state.is_full() {
+            return (self.evaluate(state, player), None);
+        }
+
+        // Only results of this same depth may end the node early, so a
+        // level's answer never depends on what the table held. Scores inside
+        // the window are searched anyway, to keep the line beneath them.
+        let key = state.hash() ^ self.salt;
+        let mut moves = state.legal_moves();
+        // Moves already placed first.
+        let mut ordered = 0;
+        self.table_probes += 1;
+        if let Some(entry) = self.table.probe(key) {
+            self.table_hits += 1;
+            if usize::from(entry.depth) == depth {
+                let cuts = match entry.bound {
+                    Bound::Lower => entry.score >= beta,
+                    Bound::Upper => entry.score <= alpha,
+                    Bound::Exact => entry.score >= beta || entry.score <= alpha,
+                };
+                if cuts {
+                    return (entry.score, entry.best);
+                }
+            }
+            if let Some(first) = entry.best.and_then(|c| moves.iter().position(|&m| m == c)) {
+                moves[..=first].rotate_right(1);
+                ordered = 1;
+            }
+        }
+        // Then the reply that last refuted a disc in the opponent's cell.
+        let cell = || previous * COL_HEIGHT + usize::from(state.heights[previous]);
+        if let Some(countermoves) = &self.countermoves {
+            let counter = countermoves[player.opponent().idx()][cell()];
+            if let Some(counter) = counter.and_then(|c| moves.iter().position(|&m| m == c)) {
+                if counter >= ordered {
+                    moves[ordered..=counter].rotate_right(1);
+                    ordered += 1;
+                }
+            }
+        }
+        // And the rest by the strategy, center-first as listed or by threats.
+        if self.ordering == MoveOrdering::Threats && depth >= THREAT_ORDERING_MIN_DEPTH {
+            let mut threats = [0; WIDTH];
+            for &col in &moves[ordered..] {
+                threats[col] = state.threats_after(player, col);
+            }
+            moves[ordered..].sort_by_key(|&col| std::cmp::Reverse(threats[col]));
+        }
+
+        // Near the horizon, a node whose evaluation trails alpha by more
+        // than a margin skips its quiet moves, scoring them at the margin.
+        let futile =
+            (self.futility_pruning && depth <= FUTILITY_MARGINS.len() && alpha.abs() < WIN_SCORE)
+                .then(|| self.evaluate(state, player) + FUTILITY_MARGINS[depth - 1])
+                .filter(|&optimistic| optimistic <= alpha);
+
+        let original_alpha = alpha;
+        let mut best = i32::MIN / 2;
+        let mut best_col = None;
+
+        for (index, col) in moves.into_iter().enumerate() {
+            let mut child = state.clone();
+            let outcome = child.play(col).expect("legal move must succeed");
+            self.nodes += 1;
+            let score = if outcome.won {
+                WIN_SCORE
+            } else if child.is_full() {
+                0
+            } else if let Some(optimistic) = futile.filter(|_| {
+                index > 0
+                    && !state.wins_at(player.opponent(), col)
+                    && !child.has_winning_move(player)
+            }) {
+                optimistic
+            } else {
+                // A late move that neither blocks nor makes a threat is first
+                // searched shallower, only to show it fails low; if it does
+                // not, it gets the full search after all.
+                let reduce = self.late_move_reductions
+                    && depth >= LMR_MIN_DEPTH
+                    && index >= LMR_FULL_DEPTH_MOVES
+                    && !state.wins_at(player.opponent(), col)
+                    && !child.has_winning_move(player);
+                let opponent = player.opponent();
+                let reduced = reduce.then(|| {
+                    let depth = depth - 1 - LMR_REDUCTION;
+                    -self.negamax(&child, col, depth, -alpha - 1, -alpha, opponent)
+                });
+                match reduced {
+                    Some(score) if score <= alpha => score,
+                    _ => -self.negamax(&child, col, depth - 1, -beta, -alpha, opponent),
+                }
+            };
+            if score > alpha {
+                self.set_line(depth, col, !outcome.won && !child.is_full());
+            }
+            if score > best {
+                best = score;
+                best_col = Some(col);
+            }
+            alpha = alpha.max(score);
+            if alpha >= beta {
+                if let Some(countermoves) = &mut self.countermoves {
+                    countermoves[player.opponent().idx()][cell()] = Some(col);
+                }
+                break;
+            }
+        }
+        if !self.aborted {
+            let bound = if best <= original_alpha {
+                Bound::Upper
+            } else if best >= beta {
+                Bound::Lower
+            } else {
+                Bound::Exact
+            };
+            self.table
+                .store(key, Entry::new(best, depth, bound, best_col));
+            self.table_stores += 1;
+        }
+        (best, best_col)
+    }
+}
+
+/// Thinking time for a move under a game clock. The budget is a share of
+/// the time left plus part of the increment, `left / moves_to_go +
+/// increment × increment_percent / 100`, and never more than half the clock.
+/// Searches stop deepening once half the budget is gone and are cut off
+/// after twice the budget, so an easy move is played quickly and a hard one
+/// may borrow from later ones.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct TimeManager {
+    /// Moves the time left is spread over.
+    pub moves_to_go: u32,
+    /// Part of the increment spent on each move, in percent.
+    pub increment_percent: u32,
+}
+
+impl Default for TimeManager {
+    fn default() -> Self {
+        Self {
+            moves_to_go: 20,
+            increment_percent: 50,
+        }
+    }
+}
+
+impl TimeManager {
+    /// What a move may take on average with `left` on the clock.
+    pub fn budget(&self, left: Duration, increment: Duration) -> Duration {
+        let share = left / self.moves_to_go.max(1) + increment * self.increment_percent / 100;
+        share.min(left / 2)
+    }
+
+    /// Limits for a search starting now: a soft deadline at half the budget
+    /// and a hard one at twice the budget, within half the clock.
+    pub fn limits(&self, left: Duration, increment: Duration) -> SearchLimits {
+        let budget = self.budget(left, increment);
+        let now = Instant::now();
+        SearchLimits {
+            soft_deadline: Some(now + budget / 2),
+            ..SearchLimits::with_deadline(now + (budget * 2).min(left / 2))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{best_move_within, eval::evaluate, parse_history, MoveRequest};
+
+    #[test]
+    fn cached_evaluations_match_fresh_ones() {
+        let mut search = Search::default();
+        let histories = ["", "R3B3", "R3B3R2B4R4", "B0R6B3R3B3", "R3B3R2B4R4B2R1"];
+        // Twice, so the second round reads what the first stored.
+        for history in histories.iter().chain(&histories) {
+            let state = GameState::from_history(&parse_history(history).unwrap()).unwrap();
+            let player = state.to_move();
+            assert_eq!(search.evaluate(&state, player), evaluate(&state, player));
+        }
+    }
+
+    #[test]
+    fn clocks_budget_a_share_of_the_time_left() {
+        let manager = TimeManager::default();
+        let seconds = Duration::from_secs;
+        assert_eq!(
+            manager.budget(seconds(60), seconds(1)),
+            Duration::from_millis(3_500)
+        );
+        assert_eq!(manager.budget(seconds(60), seconds(0)), seconds(3));
+        // Short of time, half the clock is the most a move gets.
+        assert_eq!(
+            manager.budget(seconds(1), seconds(10)),
+            Duration::from_millis(500)
+        );
+
+        let limits = manager.limits(seconds(60), seconds(1));
+        let (soft, hard) = (limits.soft_deadline.unwrap(), limits.deadline.unwrap());
+        assert_eq!(hard - soft, Duration::from_millis(5_250));
+        let limits = manager.limits(seconds(1), seconds(10));
+        assert!(limits.deadline.unwrap() <= Instant::now() + Duration::from_millis(500));
+
+        // Past the soft deadline no new iteration starts.
+        let request = MoveRequest {
+            position: "R3B3".to_string(),
+            level: 8,
+            first_player: None,
+        };
+        let limits = SearchLimits {
+            soft_deadline: Some(Instant::now()),
+            ..SearchLimits::default()
+        };
+        let reply = best_move_within(request, &limits, |_| {}).unwrap();
+        assert_eq!((reply.depth, reply.truncated), (Some(1), true));
+    }
+}
@@ -0,0 +1,151 @@
+//! Engine-versus-engine games, for comparing configurations and tuning.
+//!
+//! Two searches play each other from the empty board. Both sides are
+//! deterministic at `randomness = 0`, so a few random opening moves keep the
+//! games from all being the same; with a `seed` the whole run is repeatable.
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    format_history, options::search, rng::Rng, GameError, GameState, Player, SearchLimits,
+    SearchOptions, TypedMove,
+};
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SelfPlayConfig {
+    pub games: usize,
+    pub red: SearchOptions,
+    pub blue: SearchOptions,
+    /// Uniformly random moves played before the engines take over.
+    pub opening_plies: usize,
+    /// Time limit for each engine move; `None` searches to full depth.
+    pub move_time: Option<Duration>,
+    /// Fixes the openings and every engine's random choices.
+    pub seed: Option<u64>,
+}
+
+impl SelfPlayConfig {
+    pub fn new(red: SearchOptions, blue: SearchOptions) -> Self {
+        Self {
+            games: 1,
+            red,
+            blue,
+            opening_plies: 2,
+            move_time: None,
+            seed: None,
+        }
+    }
+}
+
+/// One finished self-play game.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct GameRecord {
+    /// Move history in the usual `R3B3…` notation, openings included.
+    pub history: String,
+    /// `None` for a draw.
+    pub winner: Option<Player>,
+    pub opening_plies: usize,
+    /// The engine's score for each ply from the mover's point of view, as
+    /// in `ScoredMove`; `None` for the random opening moves.
+    pub evals: Vec<Option<i32>>,
+}
+
+/// Play `config.games` games, Red always using `config.red`.
+pub fn selfplay(config: &SelfPlayConfig) -> Result<Vec<GameRecord>, GameError> {
+    selfplay_games(config).collect()
+}
+
+/// The games of `selfplay`, each yielded as soon as it ends, for runs too
+/// long to hold or wait for.
+pub fn selfplay_games(
+    config: &SelfPlayConfig,
+) -> impl Iterator<Item = Result<GameRecord, GameError>> + '_ {
+    let mut rng = config.seed.map_or_else(Rng::from_entropy, Rng::new);
+    (0..config.games).map(move |_| play_one(config, &mut rng))
+}
+
+fn play_one(config: &SelfPlayConfig, rng: &mut Rng) -> Result<GameRecord, GameError> {
+    let mut state = GameState::empty(Player::Red);
+    let mut moves = Vec::new();
+    let mut opening_plies = 0;
+    let mut evals = Vec::new();
+    loop {
+        let legal = state.legal_moves();
+        if state.winner().is_some() || legal.is_empty() {
+            break;
+        }
+        let player = state.to_move;
+        let (column, eval) = if opening_plies < config.opening_plies {
+            opening_plies += 1;
+            (legal[rng.below(legal.len())], None)
+        } else {
+            let options = SearchOptions {
+                seed: Some(rng.next_u64()),
+                ..match player {
+                    Player::Red => config.red.clone(),
+                    Player::Blue => config.blue.clone(),
+                }
+            };
+            let limits = config
+                .move_time
+                .map(|time| SearchLimits::with_deadline(Instant::now() + time))
+                .unwrap_or_default();
+            let outcome = search(&state, &options, &limits, |_| {})?;
+            (outcome.column, Some(outcome.score))
+        };
+        state.play(column)?;
+        moves.push(TypedMove { player, column });
+        evals.push(eval);
+    }
+    Ok(GameRecord {
+        history: format_history(&moves),
+        winner: state.winner(),
+        opening_plies,
+        evals,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_history;
+
+    #[test]
+    fn games_are_complete_and_repeatable() {
+        let config = SelfPlayConfig {
+            games: 3,
+            seed: Some(11),
+            ..SelfPlayConfig::new(SearchOptions::new(3), SearchOptions::new(1))
+        };
+        let games = selfplay(&config).unwrap();
+        assert_eq!(games.len(), 3);
+        for game in &games {
+            let state = GameState::from_history(&parse_history(&game.history).unwrap()).unwrap();
+            assert_eq!(state.winner(), game.winner);
+            assert!(game.winner.is_some() || state.is_full());
+            assert_eq!(game.opening_plies, 2);
+            assert_eq!(game.evals.len(), game.history.len() / 2);
+            assert!(game.evals[..2].iter().all(Option::is_none));
+            assert!(game.evals[2..].iter().all(Option::is_some));
+        }
+        assert_eq!(games, selfplay(&config).unwrap());
+    }
+
+    #[test]
+    fn the_deeper_engine_wins_more() {
+        let config = SelfPlayConfig {
+            games: 6,
+            seed: Some(3),
+            ..SelfPlayConfig::new(SearchOptions::new(1), SearchOptions::new(6))
+        };
+        let games = selfplay(&config).unwrap();
+        let blue = games
+            .iter()
+            .filter(|game| game.winner == Some(Player::Blue))
+            .count();
+        assert!(blue * 2 > games.len(), "{games:?}");
+    }
+}
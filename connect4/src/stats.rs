@@ -0,0 +1,255 @@
+//! Statistics for telling engine versions apart: Elo differences with
+//! their error, and the sequential probability ratio test (SPRT) that engine
+//! testers use to accept or reject a change after as few games as the
+//! evidence allows.
+//!
+//! `tournament` runs the test over self-play pairings and the server's arena
+//! over rated bot games; both report through [`SprtStatus`].
+use std::fmt;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// Sequential probability ratio test of "the candidate is `elo1` stronger"
+/// against "it is only `elo0` stronger", on scores from the candidate's
+/// side. In a tournament the candidate is the first entrant of a pairing.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Sprt {
+    pub elo0: f64,
+    pub elo1: f64,
+    /// Chance of accepting `elo1` when `elo0` is true.
+    pub alpha: f64,
+    /// Chance of accepting `elo0` when `elo1` is true.
+    pub beta: f64,
+}
+
+impl Sprt {
+    pub fn new(elo0: f64, elo1: f64) -> Self {
+        Self {
+            elo0,
+            elo1,
+            alpha: 0.05,
+            beta: 0.05,
+        }
+    }
+
+    /// Log-likelihood ratio bounds: at or below the first `elo0` is
+    /// accepted, at or above the second `elo1`.
+    pub fn bounds(&self) -> (f64, f64) {
+        (
+            (self.beta / (1.0 - self.alpha)).ln(),
+            ((1.0 - self.beta) / self.alpha).ln(),
+        )
+    }
+
+    /// Log-likelihood ratio of `score`, using the normal approximation of the
+    /// trinomial win/draw/loss model that engine testers commonly use.
+    pub fn llr(&self, score: &Score) -> f64 {
+        let games = f64::from(score.games());
+        let variance = score.variance();
+        if games == 0.0 || variance == 0.0 {
+            return 0.0;
+        }
+        let (s0, s1) = (expected_score(self.elo0), expected_score(self.elo1));
+        (s1 - s0) * (2.0 * score.fraction() - s0 - s1) * games / (2.0 * variance)
+    }
+
+    pub fn decision(&self, score: &Score) -> Option<Decision> {
+        self.status(score).decision
+    }
+
+    /// Where the test stands on `score`, for reports.
+    pub fn status(&self, score: &Score) -> SprtStatus {
+        let llr = self.llr(score);
+        let (lower, upper) = self.bounds();
+        let decision = if llr >= upper {
+            Some(Decision::Elo1)
+        } else if llr <= lower {
+            Some(Decision::Elo0)
+        } else {
+            None
+        };
+        SprtStatus {
+            elo0: self.elo0,
+            elo1: self.elo1,
+            llr,
+            lower,
+            upper,
+            decision,
+        }
+    }
+}
+
+/// An [`Sprt`]'s log-likelihood ratio on a score and what it decided.
+/// Displayed, it reads `LLR 2.95 (-2.94, 2.94): change accepted at elo0=0,
+/// elo1=5`.
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct SprtStatus {
+    pub elo0: f64,
+    pub elo1: f64,
+    pub llr: f64,
+    /// `elo0` is accepted at or below `lower`, `elo1` at or above `upper`.
+    pub lower: f64,
+    pub upper: f64,
+    /// `None` while the test is undecided.
+    pub decision: Option<Decision>,
+}
+
+impl fmt::Display for SprtStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let verdict = match self.decision {
+            Some(Decision::Elo1) => "change accepted",
+            Some(Decision::Elo0) => "change rejected",
+            None => "undecided",
+        };
+        write!(
+            f,
+            "LLR {:.2} ({:.2}, {:.2}): {verdict} at elo0={}, elo1={}",
+            self.llr, self.lower, self.upper, self.elo0, self.elo1
+        )
+    }
+}
+
+/// The hypothesis an [`Sprt`] accepted.
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum Decision {
+    /// The gain is `elo0` or less: the change did not help enough.
+    Elo0,
+    /// The gain is at least `elo1`.
+    Elo1,
+}
+
+/// Games from one side's point of view.
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Score {
+    pub wins: u32,
+    pub draws: u32,
+    pub losses: u32,
+}
+
+impl Score {
+    pub fn games(&self) -> u32 {
+        self.wins + self.draws + self.losses
+    }
+
+    /// Wins plus half the draws.
+    pub fn points(&self) -> f64 {
+        f64::from(self.wins) + f64::from(self.draws) / 2.0
+    }
+
+    /// Points per game, 0.5 before any game.
+    pub fn fraction(&self) -> f64 {
+        match self.games() {
+            0 => 0.5,
+            games => self.points() / f64::from(games),
+        }
+    }
+
+    /// Per-game variance of the points.
+    fn variance(&self) -> f64 {
+        let games = f64::from(self.games());
+        if games == 0.0 {
+            return 0.0;
+        }
+        let s = self.fraction();
+        (f64::from(self.wins) * (1.0 - s).powi(2)
+            + f64::from(self.draws) * (0.5 - s).powi(2)
+            + f64::from(self.losses) * s.powi(2))
+            / games
+    }
+
+    /// Elo difference implied by the score, with the half-width of its 95%
+    /// confidence interval. `None` while the score is all wins or all
+    /// losses, which no finite difference explains.
+    pub fn elo(&self) -> Option<(f64, f64)> {
+        let s = self.fraction();
+        if self.games() == 0 || s <= 0.0 || s >= 1.0 {
+            return None;
+        }
+        let error = 1.96 * (self.variance() / f64::from(self.games())).sqrt();
+        let bound = |s: f64| elo_of(s.clamp(1e-6, 1.0 - 1e-6));
+        Some((elo_of(s), (bound(s + error) - bound(s - error)) / 2.0))
+    }
+
+    /// The same games from the other side.
+    pub fn flipped(self) -> Self {
+        Self {
+            wins: self.losses,
+            draws: self.draws,
+            losses: self.wins,
+        }
+    }
+
+    pub(crate) fn add(&mut self, other: Self) {
+        self.wins += other.wins;
+        self.draws += other.draws;
+        self.losses += other.losses;
+    }
+}
+
+pub(crate) fn expected_score(elo: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf(-elo / 400.0))
+}
+
+pub(crate) fn elo_of(score: f64) -> f64 {
+    -400.0 * (1.0 / score - 1.0).log10()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scores_turn_into_elo_and_likelihood_ratios() {
+        let even = Score {
+            wins: 10,
+            draws: 5,
+            losses: 10,
+        };
+        let (elo, margin) = even.elo().unwrap();
+        assert!(elo.abs() < 1e-9 && margin > 0.0);
+        let ahead = Score {
+            wins: 30,
+            draws: 0,
+            losses: 10,
+        };
+        // 75% is about +191 Elo.
+        assert!((ahead.elo().unwrap().0 - 190.8).abs() < 0.1);
+        assert_eq!(
+            Score {
+                wins: 3,
+                ..Score::default()
+            }
+            .elo(),
+            None
+        );
+
+        let sprt = Sprt::new(0.0, 50.0);
+        assert!(sprt.llr(&ahead) > 0.0 && sprt.llr(&ahead.flipped()) < 0.0);
+        assert_eq!(sprt.decision(&Score::default()), None);
+        assert_eq!(sprt.decision(&ahead), Some(Decision::Elo1));
+        assert_eq!(sprt.decision(&ahead.flipped()), Some(Decision::Elo0));
+        assert_eq!(
+            sprt.status(&ahead).to_string(),
+            format!(
+                "LLR {:.2} (-2.94, 2.94): change accepted at elo0=0, elo1=50",
+                sprt.llr(&ahead)
+            )
+        );
+        assert!(sprt
+            .status(&ahead.flipped())
+            .to_string()
+            .contains("change rejected"));
+        assert!(sprt
+            .status(&Score::default())
+            .to_string()
+            .ends_with("undecided at elo0=0, elo1=50"));
+    }
+}
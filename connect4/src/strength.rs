@@ -0,0 +1,385 @@
+//! Playing strength in Elo-like terms instead of plies.
+//!
+//! Each level plays the one below it; the Elo differences those matches
+//! imply are chained upwards from level 1, which is pinned at
+//! [`ANCHOR_ELO`]. The anchor is a convention, not a measurement against
+//! people: only the gaps between levels come from games. [`calibrate`] runs
+//! the matches and `connect4-cli calibrate` prints the table that
+//! [`Level::approximate_elo`] reads from. [`estimate_elo`] rates any other
+//! configuration on the same scale by a gauntlet against the levels.
+use std::{fmt, time::Duration};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    stats::{elo_of, expected_score, Score},
+    tournament::{tournament, Entrant, Format, TournamentConfig},
+    GameError, SearchOptions,
+};
+
+/// Rating given to level 1, roughly a beginner who knows not to ignore a
+/// three in a row.
+pub const ANCHOR_ELO: u32 = 600;
+
+/// `connect4-cli calibrate --games 100 --seed 1`: full-depth searches from
+/// two random opening plies. Levels 8 to 10 and 11 to 12 played even, so
+/// they share a rating.
+const CALIBRATED_ELO: [u32; Level::MAX as usize] = [
+    600, 992, 1062, 1151, 1254, 1340, 1451, 1461, 1461, 1461, 1514, 1514, 1633, 1654, 1766,
+];
+
+/// A search level, 1 to 15: the alpha-beta depth in plies.
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "u8", into = "u8"))]
+pub struct Level(u8);
+
+impl Level {
+    pub const MIN: u8 = 1;
+    pub const MAX: u8 = 15;
+
+    pub fn new(level: u8) -> Result<Self, GameError> {
+        if (Self::MIN..=Self::MAX).contains(&level) {
+            Ok(Self(level))
+        } else {
+            Err(GameError::DepthOutOfRange(level))
+        }
+    }
+
+    pub fn get(self) -> u8 {
+        self.0
+    }
+
+    /// Every level, weakest first.
+    pub fn all() -> impl Iterator<Item = Self> {
+        (Self::MIN..=Self::MAX).map(Self)
+    }
+
+    /// Rating of a plain full-depth search at this level, from the
+    /// calibration table.
+    pub fn approximate_elo(self) -> u32 {
+        CALIBRATED_ELO[usize::from(self.0 - 1)]
+    }
+
+    /// The level whose rating is closest to `elo`, the weaker one on a tie.
+    pub fn for_elo(elo: u32) -> Self {
+        Self::all()
+            .min_by_key(|level| level.approximate_elo().abs_diff(elo))
+            .expect("there are levels")
+    }
+}
+
+impl TryFrom<u8> for Level {
+    type Error = GameError;
+
+    fn try_from(level: u8) -> Result<Self, Self::Error> {
+        Self::new(level)
+    }
+}
+
+impl From<Level> for u8 {
+    fn from(level: Level) -> Self {
+        level.0
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct CalibrationConfig {
+    /// Highest level to rate; every level from 1 up to it is played.
+    pub top: Level,
+    /// Games between each pair of neighbouring levels.
+    pub games: usize,
+    pub opening_plies: usize,
+    /// Time limit for each engine move; `None` searches to full depth, which
+    /// is what the levels mean.
+    pub move_time: Option<Duration>,
+    pub seed: Option<u64>,
+}
+
+impl CalibrationConfig {
+    pub fn new(games: usize) -> Self {
+        Self {
+            top: Level(Level::MAX),
+            games,
+            opening_plies: 2,
+            move_time: None,
+            seed: None,
+        }
+    }
+}
+
+/// One level's rating and the match against the level below it that set it.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct CalibratedLevel {
+    pub level: Level,
+    pub elo: f64,
+    /// From this level's point of view; empty for level 1.
+    pub score: Score,
+}
+
+/// Rate every level up to `config.top`, calling `on_level` as each one is
+/// done. A match the stronger level wins outright counts as if it had
+/// dropped half a game, so the step stays finite.
+pub fn calibrate(
+    config: &CalibrationConfig,
+    mut on_level: impl FnMut(&CalibratedLevel),
+) -> Result<Vec<CalibratedLevel>, GameError> {
+    let first = CalibratedLevel {
+        level: Level(Level::MIN),
+        elo: f64::from(ANCHOR_ELO),
+        score: Score::default(),
+    };
+    on_level(&first);
+    let mut levels = vec![first];
+    for level in Level::all()
+        .skip(1)
+        .take_while(|&level| level <= config.top)
+    {
+        let entrant = |level: u8| Entrant {
+            name: level.to_string(),
+            options: SearchOptions::new(level),
+        };
+        let matches = TournamentConfig {
+            games: config.games,
+            opening_plies: config.opening_plies,
+            move_time: config.move_time,
+            seed: config.seed.map(|seed| seed ^ u64::from(level.0)),
+            ..TournamentConfig::new(vec![entrant(level.0), entrant(level.0 - 1)])
+        };
+        let score = tournament(&matches, |_| {})?.remove(0).score;
+        let games = f64::from(score.games().max(1));
+        let fraction = score.fraction().clamp(0.5 / games, 1.0 - 0.5 / games);
+        let below = levels.last().expect("level 1 is rated").elo;
+        let rated = CalibratedLevel {
+            level,
+            elo: below + elo_of(fraction),
+            score,
+        };
+        on_level(&rated);
+        levels.push(rated);
+    }
+    Ok(levels)
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct GauntletConfig {
+    /// The configuration to rate.
+    pub options: SearchOptions,
+    pub opening_plies: usize,
+    /// Time limit for each engine move, on both sides; `None` searches to
+    /// full depth, which is what the reference ratings assume.
+    pub move_time: Option<Duration>,
+    pub seed: Option<u64>,
+}
+
+impl GauntletConfig {
+    pub fn new(options: SearchOptions) -> Self {
+        Self {
+            options,
+            opening_plies: 2,
+            move_time: None,
+            seed: None,
+        }
+    }
+}
+
+/// The candidate's games against one reference level.
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ReferenceResult {
+    pub level: Level,
+    /// The level's calibrated rating.
+    pub elo: u32,
+    /// From the candidate's point of view.
+    pub score: Score,
+}
+
+/// A configuration's rating on the scale of [`Level::approximate_elo`].
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct EloEstimate {
+    pub elo: f64,
+    /// Half-width of the 95% confidence interval.
+    pub margin: f64,
+    pub results: Vec<ReferenceResult>,
+}
+
+/// Rate `config.options` by `games` games (rounded up to whole pairs)
+/// against each of `reference_levels`, every level if it is empty. The
+/// rating is the one whose expected score over those opponents equals the
+/// points taken, draws counting half; a clean sweep counts as if half a
+/// game had been dropped, so the rating stays finite.
+pub fn estimate_elo(
+    config: &GauntletConfig,
+    reference_levels: &[Level],
+    games: usize,
+) -> Result<EloEstimate, GameError> {
+    let levels: Vec<Level> = if reference_levels.is_empty() {
+        Level::all().collect()
+    } else {
+        reference_levels.to_vec()
+    };
+    let mut entrants = vec![Entrant {
+        name: "candidate".to_string(),
+        options: config.options.clone(),
+    }];
+    entrants.extend(levels.iter().map(|level| Entrant {
+        name: level.to_string(),
+        options: SearchOptions::new(level.0),
+    }));
+    let gauntlet = TournamentConfig {
+        format: Format::Gauntlet,
+        games: games.max(1),
+        opening_plies: config.opening_plies,
+        move_time: config.move_time,
+        seed: config.seed,
+        ..TournamentConfig::new(entrants)
+    };
+    let results: Vec<ReferenceResult> = tournament(&gauntlet, |_| {})?
+        .into_iter()
+        .map(|pairing| {
+            let level = levels[pairing.second - 1];
+            ReferenceResult {
+                level,
+                elo: level.approximate_elo(),
+                score: pairing.score,
+            }
+        })
+        .collect();
+    let (elo, margin) = performance(&results);
+    Ok(EloEstimate {
+        elo,
+        margin,
+        results,
+    })
+}
+
+/// The rating explaining `results` and the half-width of its 95% interval,
+/// from the Fisher information of the games.
+fn performance(results: &[ReferenceResult]) -> (f64, f64) {
+    let games: f64 = results.iter().map(|r| f64::from(r.score.games())).sum();
+    let points: f64 = results.iter().map(|r| r.score.points()).sum();
+    let points = points.clamp(0.5, games - 0.5);
+    let expected = |elo: f64| -> f64 {
+        results
+            .iter()
+            .map(|r| f64::from(r.score.games()) * expected_score(elo - f64::from(r.elo)))
+            .sum()
+    };
+    // The expected points only grow with the rating, so halve the range.
+    let (mut low, mut high) = (-4000.0, 8000.0);
+    for _ in 0..100 {
+        let mid = (low + high) / 2.0;
+        if expected(mid) < points {
+            low = mid;
+        } else {
+            high = mid;
+        }
+    }
+    let elo = (low + high) / 2.0;
+    let slope = std::f64::consts::LN_10 / 400.0;
+    let information: f64 = results
+        .iter()
+        .map(|r| {
+            let p = expected_score(elo - f64::from(r.elo));
+            f64::from(r.score.games()) * p * (1.0 - p) * slope * slope
+        })
+        .sum();
+    (elo, 1.96 / information.sqrt())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn levels_are_checked_and_rated_in_order() {
+        assert!(Level::new(0).is_err());
+        assert!(Level::new(16).is_err());
+        assert_eq!(Level::new(7).unwrap().get(), 7);
+        assert_eq!(Level::all().count(), 15);
+        assert_eq!(Level::new(1).unwrap().approximate_elo(), ANCHOR_ELO);
+        let ratings: Vec<u32> = Level::all().map(Level::approximate_elo).collect();
+        assert!(ratings.windows(2).all(|pair| pair[0] <= pair[1]));
+        for level in Level::all() {
+            let elo = level.approximate_elo();
+            assert_eq!(Level::for_elo(elo).approximate_elo(), elo);
+        }
+        assert_eq!(Level::for_elo(0).get(), 1);
+        assert_eq!(Level::for_elo(u32::MAX).get(), 15);
+        #[cfg(feature = "serde")]
+        {
+            assert_eq!(serde_json::to_string(&Level(4)).unwrap(), "4");
+            assert!(serde_json::from_str::<Level>("0").is_err());
+        }
+    }
+
+    #[test]
+    fn calibration_chains_matches_between_neighbours() {
+        let config = CalibrationConfig {
+            top: Level::new(3).unwrap(),
+            seed: Some(1),
+            ..CalibrationConfig::new(8)
+        };
+        let mut seen = 0;
+        let levels = calibrate(&config, |_| seen += 1).unwrap();
+        assert_eq!(seen, 3);
+        assert_eq!(levels.len(), 3);
+        assert_eq!(levels[0].elo, f64::from(ANCHOR_ELO));
+        for pair in levels.windows(2) {
+            assert_eq!(pair[1].level.get(), pair[0].level.get() + 1);
+            assert_eq!(pair[1].score.games(), 8);
+        }
+        assert_eq!(calibrate(&config, |_| {}).unwrap(), levels);
+    }
+
+    #[test]
+    fn gauntlets_rate_a_configuration_against_the_levels() {
+        let result = |level: u8, wins: u32, losses: u32| {
+            let level = Level::new(level).unwrap();
+            ReferenceResult {
+                level,
+                elo: level.approximate_elo(),
+                score: Score {
+                    wins,
+                    draws: 0,
+                    losses,
+                },
+            }
+        };
+        // Even against level 4 is level 4's rating; sweeping it is finite.
+        let (elo, margin) = performance(&[result(4, 10, 10)]);
+        assert!((elo - 1151.0).abs() < 0.01 && margin > 0.0);
+        let (sweep, _) = performance(&[result(4, 20, 0)]);
+        assert!(sweep > 1151.0 && sweep.is_finite());
+        // Beating one level and losing to a stronger one lands in between.
+        let (between, narrower) = performance(&[result(1, 10, 0), result(13, 0, 10)]);
+        assert!(600.0 < between && between < 1633.0);
+        let (_, wider) = performance(&[result(1, 5, 0), result(13, 0, 5)]);
+        assert!(narrower < wider);
+
+        let config = GauntletConfig {
+            seed: Some(3),
+            ..GauntletConfig::new(SearchOptions::new(3))
+        };
+        let levels = [Level::new(1).unwrap(), Level::new(3).unwrap()];
+        let estimate = estimate_elo(&config, &levels, 3).unwrap();
+        assert_eq!(estimate.results.len(), 2);
+        for (result, level) in estimate.results.iter().zip(levels) {
+            assert_eq!((result.level, result.score.games()), (level, 4));
+        }
+        assert!(estimate.elo.is_finite() && estimate.margin > 0.0);
+        assert_eq!(estimate_elo(&config, &levels, 3).unwrap(), estimate);
+    }
+}
@@ -0,0 +1,160 @@
+//! Ready-made random positions for property tests.
+//!
+//! A `Gen` is seeded, so every position it makes can be made again from the
+//! seed alone. That is all a property-testing framework needs: with proptest,
+//! `any::<u64>().prop_map(|seed| Gen::new(seed).position(20))` is a strategy
+//! over legal positions, and a failure is reported with the seed that
+//! reproduces it. The crate's own tests draw from the same generators.
+//!
+//! Built with the `testing` feature.
+use crate::{
+    format_history, rng::Rng, score_columns, GameState, Player, TypedMove, MAX_CELLS, WIN_SCORE,
+};
+
+/// A generated position and the history that reaches it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Position {
+    /// In the usual `R3B3…` notation, valid for `parse_history_strict`.
+    pub history: String,
+    pub state: GameState,
+}
+
+impl Position {
+    fn start(first: Player) -> (Vec<TypedMove>, GameState) {
+        (Vec::new(), GameState::empty(first))
+    }
+
+    fn new(moves: &[TypedMove], state: GameState) -> Self {
+        Self {
+            history: format_history(moves),
+            state,
+        }
+    }
+}
+
+/// Random play from the empty board, Red moving first.
+pub struct Gen(Rng);
+
+impl Gen {
+    pub fn new(seed: u64) -> Self {
+        Self(Rng::new(seed))
+    }
+
+    /// An unfinished position after up to `max_plies` random moves, every
+    /// length equally likely. A move that would end the game is not played,
+    /// so the game may stop a little short.
+    pub fn position(&mut self, max_plies: usize) -> Position {
+        let plies = self.0.below(max_plies.min(MAX_CELLS - 1) + 1);
+        self.play_quietly(plies)
+    }
+
+    /// Up to `plies` random moves that do not win, fewer if every move left
+    /// would.
+    fn play_quietly(&mut self, plies: usize) -> Position {
+        let (mut moves, mut state) = Position::start(Player::Red);
+        while moves.len() < plies {
+            let quiet: Vec<usize> = state
+                .legal_moves()
+                .into_iter()
+                .filter(|&column| !state.wins_at(state.to_move(), column))
+                .collect();
+            if quiet.is_empty() {
+                break;
+            }
+            let column = quiet[self.0.below(quiet.len())];
+            moves.push(TypedMove {
+                player: state.to_move(),
+                column,
+            });
+            state.play(column).expect("legal moves can be played");
+        }
+        Position::new(&moves, state)
+    }
+
+    /// A position whose side to move forces a win in exactly `plies` plies,
+    /// both sides' moves counted, and not sooner. `plies` must be odd; each
+    /// candidate costs a search that deep, so keep it small.
+    pub fn forced_win(&mut self, plies: usize) -> Position {
+        assert!(
+            plies % 2 == 1,
+            "wins come on the winner's own move, an odd ply"
+        );
+        loop {
+            let candidate = self.position(MAX_CELLS - 8);
+            let wins_within = |depth| {
+                score_columns(&candidate.state, depth)
+                    .iter()
+                    .any(|&(_, score)| score >= WIN_SCORE)
+            };
+            if wins_within(plies) && (plies < 3 || !wins_within(plies - 2)) {
+                return candidate;
+            }
+        }
+    }
+
+    /// A full board with no line of four.
+    pub fn drawn_full_board(&mut self) -> Position {
+        loop {
+            let candidate = self.play_quietly(MAX_CELLS - 1);
+            if let [column] = candidate.state.legal_moves()[..] {
+                let mut state = candidate.state.clone();
+                let player = state.to_move();
+                if !state.play(column).expect("the column has room").won {
+                    let mut moves = crate::parse_history(&candidate.history)
+                        .expect("generated histories parse");
+                    moves.push(TypedMove { player, column });
+                    return Position::new(&moves, state);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse_history_strict, GameStatus};
+
+    #[test]
+    fn positions_are_legal_unfinished_and_reproducible() {
+        for seed in 0..200 {
+            let position = Gen::new(seed).position(30);
+            assert_eq!(position, Gen::new(seed).position(30));
+            let moves = parse_history_strict(&position.history).unwrap();
+            assert!(moves.len() <= 30);
+            assert_eq!(GameState::from_history(&moves).unwrap(), position.state);
+            position.state.assert_consistent();
+            assert!(position.state.winner().is_none() && !position.state.is_full());
+        }
+    }
+
+    #[test]
+    fn forced_wins_take_exactly_the_asked_plies() {
+        let mut gen = Gen::new(7);
+        for plies in [1, 3, 5] {
+            let state = gen.forced_win(plies).state;
+            let best = |depth| {
+                score_columns(&state, depth)
+                    .into_iter()
+                    .map(|(_, score)| score)
+                    .max()
+                    .unwrap()
+            };
+            assert!(best(plies) >= WIN_SCORE);
+            if plies > 1 {
+                assert!(best(plies - 2) < WIN_SCORE);
+            }
+        }
+    }
+
+    #[test]
+    fn drawn_boards_are_full_without_a_winner() {
+        let mut gen = Gen::new(3);
+        for _ in 0..5 {
+            let position = gen.drawn_full_board();
+            parse_history_strict(&position.history).unwrap();
+            assert_eq!(position.state.board_model().status, GameStatus::Draw);
+            position.state.assert_consistent();
+        }
+    }
+}
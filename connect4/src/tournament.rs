@@ -0,0 +1,181 @@
+//! Matches between engine configurations, to tell whether a change to the
+//! search or the evaluation weights actually gains strength.
+//!
+//! Each pairing plays its games two at a time from the same random opening
+//! with colours swapped, so neither side profits from a lucky opening or from
+//! moving first. With an [`Sprt`] a pairing stops as soon as the sequential
+//! probability ratio test accepts either hypothesis, which usually takes far
+//! fewer games than the fixed maximum.
+use std::time::Duration;
+
+pub use crate::stats::{Decision, Score, Sprt};
+use crate::{rng::Rng, selfplay, GameError, Player, SearchOptions, SelfPlayConfig};
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Entrant {
+    pub name: String,
+    pub options: SearchOptions,
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Format {
+    /// Every entrant plays every other.
+    #[default]
+    RoundRobin,
+    /// The first entrant, typically the candidate, plays each of the others.
+    Gauntlet,
+}
+
+/// One match between two entrants, indices into `TournamentConfig::entrants`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Pairing {
+    pub first: usize,
+    pub second: usize,
+    /// From `first`'s point of view.
+    pub score: Score,
+    /// The test's verdict, if one was configured and reached.
+    pub decision: Option<Decision>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TournamentConfig {
+    pub entrants: Vec<Entrant>,
+    pub format: Format,
+    /// Most games per pairing; an odd number is rounded up so that both
+    /// entrants open equally often.
+    pub games: usize,
+    /// Random moves opening each pair of games.
+    pub opening_plies: usize,
+    /// Time limit for each engine move; `None` searches to full depth.
+    pub move_time: Option<Duration>,
+    /// Fixes the openings and every engine's random choices.
+    pub seed: Option<u64>,
+    /// Stops a pairing early once the test decides it.
+    pub sprt: Option<Sprt>,
+}
+
+impl TournamentConfig {
+    pub fn new(entrants: Vec<Entrant>) -> Self {
+        Self {
+            entrants,
+            format: Format::RoundRobin,
+            games: 100,
+            opening_plies: 2,
+            move_time: None,
+            seed: None,
+            sprt: None,
+        }
+    }
+
+    fn schedule(&self) -> Vec<(usize, usize)> {
+        let count = self.entrants.len();
+        match self.format {
+            Format::RoundRobin => (0..count)
+                .flat_map(|first| (first + 1..count).map(move |second| (first, second)))
+                .collect(),
+            Format::Gauntlet => (1..count).map(|second| (0, second)).collect(),
+        }
+    }
+}
+
+/// Play every pairing of `config`, calling `on_game` with the pairing's
+/// standing after each game.
+pub fn tournament(
+    config: &TournamentConfig,
+    mut on_game: impl FnMut(&Pairing),
+) -> Result<Vec<Pairing>, GameError> {
+    let mut rng = config.seed.map_or_else(Rng::from_entropy, Rng::new);
+    let mut pairings = Vec::new();
+    for (first, second) in config.schedule() {
+        let mut pairing = Pairing {
+            first,
+            second,
+            score: Score::default(),
+            decision: None,
+        };
+        let (a, b) = (&config.entrants[first], &config.entrants[second]);
+        while pairing.decision.is_none() && (pairing.score.games() as usize) < config.games {
+            // The same seed replays the same opening with colours swapped.
+            let seed = rng.next_u64();
+            for (red, blue, a_plays) in [(a, b, Player::Red), (b, a, Player::Blue)] {
+                let game = SelfPlayConfig {
+                    opening_plies: config.opening_plies,
+                    move_time: config.move_time,
+                    seed: Some(seed),
+                    ..SelfPlayConfig::new(red.options.clone(), blue.options.clone())
+                };
+                let record = selfplay(&game)?.remove(0);
+                match record.winner {
+                    Some(winner) if winner == a_plays => pairing.score.wins += 1,
+                    Some(_) => pairing.score.losses += 1,
+                    None => pairing.score.draws += 1,
+                }
+                on_game(&pairing);
+            }
+            pairing.decision = config.sprt.and_then(|sprt| sprt.decision(&pairing.score));
+        }
+        pairings.push(pairing);
+    }
+    Ok(pairings)
+}
+
+/// Each entrant's combined score against everyone it played, in entrant
+/// order.
+pub fn standings(entrants: usize, pairings: &[Pairing]) -> Vec<Score> {
+    let mut scores = vec![Score::default(); entrants];
+    for pairing in pairings {
+        scores[pairing.first].add(pairing.score);
+        scores[pairing.second].add(pairing.score.flipped());
+    }
+    scores
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entrant(name: &str, level: u8) -> Entrant {
+        Entrant {
+            name: name.to_string(),
+            options: SearchOptions::new(level),
+        }
+    }
+
+    #[test]
+    fn gauntlets_play_paired_games_and_stop_on_a_decision() {
+        let config = TournamentConfig {
+            format: Format::Gauntlet,
+            games: 3,
+            seed: Some(7),
+            ..TournamentConfig::new(vec![
+                entrant("deep", 5),
+                entrant("shallow", 1),
+                entrant("middle", 3),
+            ])
+        };
+        let mut games = 0;
+        let pairings = tournament(&config, |_| games += 1).unwrap();
+        assert_eq!(games, 8);
+        assert_eq!(
+            pairings
+                .iter()
+                .map(|p| (p.first, p.second))
+                .collect::<Vec<_>>(),
+            [(0, 1), (0, 2)]
+        );
+        assert!(pairings.iter().all(|p| p.score.games() == 4));
+        assert_eq!(pairings, tournament(&config, |_| {}).unwrap());
+        let table = standings(3, &pairings);
+        assert_eq!(table[0].games(), 8);
+        assert_eq!(table[1], pairings[0].score.flipped());
+
+        let decided = TournamentConfig {
+            games: 200,
+            sprt: Some(Sprt::new(0.0, 100.0)),
+            ..TournamentConfig::new(vec![entrant("deep", 6), entrant("shallow", 1)])
+        };
+        let pairing = &tournament(&decided, |_| {}).unwrap()[0];
+        assert_eq!(pairing.decision, Some(Decision::Elo1));
+        assert!(pairing.score.games() < 200);
+    }
+}
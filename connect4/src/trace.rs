@@ -0,0 +1,260 @@
+//! Node-by-node recording of alpha-beta searches, for post-mortems.
+//!
+//! Set `SearchLimits::trace` and every node the search finishes is appended
+//! to the tracer's file: the position's `GameState::key`, the remaining
+//! depth, the window it was searched with, the score it returned and the
+//! child it chose. Nodes are written in the order they finish, so children
+//! come before their parent and each root record closes one iteration.
+//! Nodes cut short by a limit are left out.
+//!
+//! The format is an 8-byte header (`C4TRACE` and a version byte) followed by
+//! fixed 22-byte records, little-endian. Tracing costs a lock and a write per
+//! node, so it is for reproducing a bad answer, not for play.
+use std::{
+    fmt,
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+const MAGIC: &[u8; 7] = b"C4TRACE";
+const VERSION: u8 = 1;
+const RECORD_LEN: usize = 22;
+/// `best` of a node that chose no child.
+const NO_CHILD: u8 = u8::MAX;
+
+/// One finished node.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct TraceNode {
+    /// `GameState::key` of the position searched.
+    pub key: u64,
+    /// Remaining depth; 0 for leaves.
+    pub depth: u8,
+    pub alpha: i32,
+    pub beta: i32,
+    /// From the side to move's point of view; a bound when outside the
+    /// window.
+    pub score: i32,
+    /// Column of the best child, `None` for leaves, table cutoffs without a
+    /// move, and roots where nothing beat alpha.
+    pub best: Option<usize>,
+}
+
+impl TraceNode {
+    fn encode(&self) -> [u8; RECORD_LEN] {
+        let mut bytes = [0; RECORD_LEN];
+        bytes[..8].copy_from_slice(&self.key.to_le_bytes());
+        bytes[8] = self.depth;
+        bytes[9..13].copy_from_slice(&self.alpha.to_le_bytes());
+        bytes[13..17].copy_from_slice(&self.beta.to_le_bytes());
+        bytes[17..21].copy_from_slice(&self.score.to_le_bytes());
+        bytes[21] = self.best.map_or(NO_CHILD, |column| column as u8);
+        bytes
+    }
+
+    fn decode(bytes: &[u8; RECORD_LEN]) -> Self {
+        let i32_at = |at: usize| i32::from_le_bytes(bytes[at..at + 4].try_into().unwrap());
+        Self {
+            key: u64::from_le_bytes(bytes[..8].try_into().unwrap()),
+            depth: bytes[8],
+            alpha: i32_at(9),
+            beta: i32_at(13),
+            score: i32_at(17),
+            best: (bytes[21] != NO_CHILD).then_some(usize::from(bytes[21])),
+        }
+    }
+}
+
+struct Sink {
+    out: Box<dyn Write + Send>,
+    nodes: u64,
+    /// The first write error; later nodes are dropped.
+    error: Option<io::Error>,
+}
+
+/// Where a search writes its trace. Clones share the output, so one tracer
+/// can record several searches one after another.
+#[derive(Clone)]
+pub struct Tracer(Arc<Mutex<Sink>>);
+
+impl fmt::Debug for Tracer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Tracer")
+            .field("nodes", &self.0.lock().unwrap().nodes)
+            .finish()
+    }
+}
+
+impl Tracer {
+    /// Trace to a new file at `path`, replacing any file there.
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::new(BufWriter::new(File::create(path)?))
+    }
+
+    /// Trace to any writer; the header is written at once.
+    pub fn new(mut out: impl Write + Send + 'static) -> io::Result<Self> {
+        out.write_all(MAGIC)?;
+        out.write_all(&[VERSION])?;
+        Ok(Self(Arc::new(Mutex::new(Sink {
+            out: Box::new(out),
+            nodes: 0,
+            error: None,
+        }))))
+    }
+
+    pub(crate) fn record(&self, node: &TraceNode) {
+        let mut sink = self.0.lock().unwrap();
+        if sink.error.is_some() {
+            return;
+        }
+        match sink.out.write_all(&node.encode()) {
+            Ok(()) => sink.nodes += 1,
+            Err(err) => sink.error = Some(err),
+        }
+    }
+
+    /// Flush the output and return the number of nodes written, or the
+    /// first error any write met.
+    pub fn finish(&self) -> io::Result<u64> {
+        let mut sink = self.0.lock().unwrap();
+        if let Some(err) = sink.error.take() {
+            return Err(err);
+        }
+        sink.out.flush()?;
+        Ok(sink.nodes)
+    }
+}
+
+/// Reads a trace back, one node at a time.
+pub struct TraceReader<R> {
+    input: R,
+}
+
+impl TraceReader<BufReader<File>> {
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read> TraceReader<R> {
+    /// Checks the header; `InvalidData` if this is not a trace this version
+    /// can read.
+    pub fn new(mut input: R) -> io::Result<Self> {
+        let mut header = [0; 8];
+        input.read_exact(&mut header)?;
+        if &header[..7] != MAGIC || header[7] != VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not a connect4 search trace",
+            ));
+        }
+        Ok(Self { input })
+    }
+}
+
+impl<R: Read> Iterator for TraceReader<R> {
+    type Item = io::Result<TraceNode>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut bytes = [0; RECORD_LEN];
+        let mut filled = 0;
+        while filled < RECORD_LEN {
+            match self.input.read(&mut bytes[filled..]) {
+                Ok(0) if filled == 0 => return None,
+                Ok(0) => {
+                    return Some(Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "trace ends inside a record",
+                    )))
+                }
+                Ok(n) => filled += n,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Some(Err(err)),
+            }
+        }
+        Some(Ok(TraceNode::decode(&bytes)))
+    }
+}
+
+/// Every node of the trace at `path`.
+pub fn read_trace(path: impl AsRef<Path>) -> io::Result<Vec<TraceNode>> {
+    TraceReader::open(path)?.collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{best_move_within, parse_history, GameState, MoveRequest, SearchLimits};
+
+    /// A `Write` the test can read back after the tracer is done with it.
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn records_round_trip() {
+        let node = TraceNode {
+            key: 0x0123_4567_89ab_cdef,
+            depth: 7,
+            alpha: i32::MIN / 2,
+            beta: -3,
+            score: 1_000_000,
+            best: Some(6),
+        };
+        assert_eq!(TraceNode::decode(&node.encode()), node);
+        let leaf = TraceNode { best: None, ..node };
+        assert_eq!(TraceNode::decode(&leaf.encode()), leaf);
+    }
+
+    #[test]
+    fn traced_search_ends_each_iteration_with_its_root() {
+        let position = "B3R3B2R4B3R3B3R4B2R2B1R0B5";
+        let out = Shared::default();
+        let tracer = Tracer::new(out.clone()).unwrap();
+        let limits = SearchLimits {
+            trace: Some(tracer.clone()),
+            ..SearchLimits::default()
+        };
+        let request = MoveRequest {
+            position: position.to_string(),
+            level: 4,
+            first_player: None,
+        };
+        let mut roots = Vec::new();
+        let response = best_move_within(request, &limits, |info| roots.push(info.clone())).unwrap();
+        let written = tracer.finish().unwrap();
+
+        let bytes = out.0.lock().unwrap().clone();
+        let nodes: Vec<TraceNode> = TraceReader::new(bytes.as_slice())
+            .unwrap()
+            .collect::<io::Result<_>>()
+            .unwrap();
+        assert_eq!(nodes.len() as u64, written);
+        let root_key = GameState::from_history(&parse_history(position).unwrap())
+            .unwrap()
+            .key();
+        let root_records: Vec<&TraceNode> =
+            nodes.iter().filter(|node| node.key == root_key).collect();
+        assert_eq!(root_records.len(), roots.len());
+        let last = root_records.last().unwrap();
+        assert_eq!(last.best, Some(response.column));
+        assert_eq!(usize::from(last.depth), 4);
+        assert_eq!(nodes.last(), Some(*last));
+        assert!(nodes.iter().any(|node| node.depth == 0));
+
+        assert!(TraceReader::new(&b"NOTATRACE"[..]).is_err());
+        let truncated = &bytes[..bytes.len() - 1];
+        let last = TraceReader::new(truncated).unwrap().last().unwrap();
+        assert_eq!(last.unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
@@ -0,0 +1,363 @@
+//! Transposition table: what earlier searches learned about a position,
+//! keyed by Zobrist hash, so that transpositions and the next iteration of
+//! iterative deepening can skip or reorder work.
+//!
+//! Capacity is fixed when the table is made, in entries or in bytes (16 per
+//! entry), so memory use is predictable on a small VPS and can be generous on
+//! an analysis box. Entries are two atomic words written without locks; a
+//! reader accepts one only if its key matches the data it read, which also
+//! rejects an entry torn by a concurrent write. One table can therefore be
+//! shared by searches on several threads (`SearchLimits::table`).
+use std::sync::atomic::{AtomicU64, AtomicU8, Ordering::Relaxed};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// Bytes per entry: the key check word and the data word.
+pub const ENTRY_BYTES: usize = 16;
+/// Size of the private table a search makes when it is not given one: 1 MiB.
+pub const DEFAULT_ENTRIES: usize = 1 << 16;
+/// Entries `stats` looks at to estimate occupancy and age.
+const SAMPLE: usize = 4096;
+
+/// Which entry a store may overwrite.
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum Replacement {
+    /// The newest result always wins its slot.
+    AlwaysReplace,
+    /// A slot keeps its deeper result unless that is from an older search.
+    DepthPreferred,
+    /// Buckets of two slots: one depth-preferred, and one always replaced
+    /// that catches what the first refused.
+    #[default]
+    TwoTier,
+}
+
+/// How a stored score relates to the position's true score at that depth.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Bound {
+    Exact,
+    /// The search failed high: the true score is at least this.
+    Lower,
+    /// The search failed low: the true score is at most this.
+    Upper,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Entry {
+    pub score: i32,
+    pub depth: u8,
+    pub bound: Bound,
+    /// The move that produced the score, tried first next time.
+    pub best: Option<usize>,
+    generation: u8,
+}
+
+impl Entry {
+    pub fn new(score: i32, depth: usize, bound: Bound, best: Option<usize>) -> Self {
+        Self {
+            score,
+            depth: depth.min(usize::from(u8::MAX)) as u8,
+            bound,
+            best,
+            generation: 0,
+        }
+    }
+
+    /// Score in bits 0-31, depth 32-39, bound 40-41 (0 for an empty slot),
+    /// best move plus one 42-45 and generation 48-55.
+    fn pack(self) -> u64 {
+        let bound: u64 = match self.bound {
+            Bound::Exact => 1,
+            Bound::Lower => 2,
+            Bound::Upper => 3,
+        };
+        let best = self.best.map_or(0, |column| column as u64 + 1);
+        u64::from(self.score as u32)
+            | u64::from(self.depth) << 32
+            | bound << 40
+            | best << 42
+            | u64::from(self.generation) << 48
+    }
+
+    fn unpack(data: u64) -> Option<Self> {
+        let bound = match (data >> 40) & 0b11 {
+            0 => return None,
+            1 => Bound::Exact,
+            2 => Bound::Lower,
+            _ => Bound::Upper,
+        };
+        let best = ((data >> 42) & 0b1111) as usize;
+        Some(Self {
+            score: data as u32 as i32,
+            depth: (data >> 32) as u8,
+            bound,
+            best: best.checked_sub(1),
+            generation: (data >> 48) as u8,
+        })
+    }
+}
+
+/// A snapshot of a table's use.
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct TableStats {
+    pub capacity: usize,
+    pub bytes: usize,
+    pub replacement: Replacement,
+    /// Fraction of entries in use, estimated from the first few thousand.
+    pub occupancy: f64,
+    /// Of the entries in use, the fraction written since the latest search
+    /// began; the rest are older and the first to be replaced.
+    pub current: f64,
+    /// Lookups, lookups that found the position, and writes, summed over
+    /// the searches that have used the table, up to their latest finished
+    /// iteration.
+    pub probes: u64,
+    pub hits: u64,
+    pub stores: u64,
+}
+
+impl TableStats {
+    /// Hits per probe, 0 before any probe.
+    pub fn hit_rate(&self) -> f64 {
+        match self.probes {
+            0 => 0.0,
+            probes => self.hits as f64 / probes as f64,
+        }
+    }
+}
+
+pub struct TranspositionTable {
+    /// `[key ^ data, data]` per entry.
+    slots: Box<[[AtomicU64; 2]]>,
+    replacement: Replacement,
+    generation: AtomicU8,
+    probes: AtomicU64,
+    hits: AtomicU64,
+    stores: AtomicU64,
+}
+
+impl TranspositionTable {
+    /// A table of `entries` entries, rounded down to a power of two (at
+    /// least two).
+    pub fn new(entries: usize, replacement: Replacement) -> Self {
+        let capacity = if entries < 2 { 2 } else { 1 << entries.ilog2() };
+        Self {
+            slots: (0..capacity).map(|_| Default::default()).collect(),
+            replacement,
+            generation: AtomicU8::new(0),
+            probes: AtomicU64::new(0),
+            hits: AtomicU64::new(0),
+            stores: AtomicU64::new(0),
+        }
+    }
+
+    /// The largest table that fits in `bytes`.
+    pub fn with_bytes(bytes: usize, replacement: Replacement) -> Self {
+        Self::new(bytes / ENTRY_BYTES, replacement)
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.slots.len()
+    }
+
+    pub fn bytes(&self) -> usize {
+        self.capacity() * ENTRY_BYTES
+    }
+
+    pub fn replacement(&self) -> Replacement {
+        self.replacement
+    }
+
+    /// Forget every entry and reset the counters; returns the number of
+    /// entries that were in use.
+    pub fn clear(&self) -> usize {
+        let mut used = 0;
+        for slot in self.slots.iter() {
+            slot[0].store(0, Relaxed);
+            used += usize::from(slot[1].swap(0, Relaxed) != 0);
+        }
+        for counter in [&self.probes, &self.hits, &self.stores] {
+            counter.store(0, Relaxed);
+        }
+        used
+    }
+
+    /// Start a new generation: entries stored before now count as old.
+    pub(crate) fn new_search(&self) {
+        self.generation.fetch_add(1, Relaxed);
+    }
+
+    pub fn stats(&self) -> TableStats {
+        let generation = self.generation.load(Relaxed);
+        let sample = &self.slots[..self.capacity().min(SAMPLE)];
+        let used: Vec<Entry> = sample
+            .iter()
+            .filter_map(|slot| Entry::unpack(slot[1].load(Relaxed)))
+            .collect();
+        let current = used.iter().filter(|e| e.generation == generation).count();
+        TableStats {
+            capacity: self.capacity(),
+            bytes: self.bytes(),
+            replacement: self.replacement,
+            occupancy: used.len() as f64 / sample.len() as f64,
+            current: if used.is_empty() {
+                0.0
+            } else {
+                current as f64 / used.len() as f64
+            },
+            probes: self.probes.load(Relaxed),
+            hits: self.hits.load(Relaxed),
+            stores: self.stores.load(Relaxed),
+        }
+    }
+
+    /// Add a finished search's counts. Searches count locally so that
+    /// threads sharing a table do not contend on these on every node.
+    pub(crate) fn record(&self, probes: u64, hits: u64, stores: u64) {
+        self.probes.fetch_add(probes, Relaxed);
+        self.hits.fetch_add(hits, Relaxed);
+        self.stores.fetch_add(stores, Relaxed);
+    }
+
+    /// The slots `key` may occupy.
+    fn bucket(&self, key: u64) -> &[[AtomicU64; 2]] {
+        let index = key as usize & (self.capacity() - 1);
+        match self.replacement {
+            Replacement::TwoTier => &self.slots[index & !1..=index | 1],
+            _ => &self.slots[index..=index],
+        }
+    }
+
+    fn read(slot: &[AtomicU64; 2], key: u64) -> Option<Entry> {
+        let data = slot[1].load(Relaxed);
+        let check = slot[0].load(Relaxed);
+        (check ^ data == key).then(|| Entry::unpack(data)).flatten()
+    }
+
+    fn write(slot: &[AtomicU64; 2], key: u64, data: u64) {
+        slot[0].store(key ^ data, Relaxed);
+        slot[1].store(data, Relaxed);
+    }
+
+    pub(crate) fn probe(&self, key: u64) -> Option<Entry> {
+        self.bucket(key)
+            .iter()
+            .find_map(|slot| Self::read(slot, key))
+    }
+
+    pub(crate) fn store(&self, key: u64, mut entry: Entry) {
+        let generation = self.generation.load(Relaxed);
+        entry.generation = generation;
+        let data = entry.pack();
+        let bucket = self.bucket(key);
+        let keeps = |slot: &[AtomicU64; 2]| {
+            let held = slot[1].load(Relaxed);
+            let same_key = slot[0].load(Relaxed) ^ held == key;
+            Entry::unpack(held).is_some_and(|old| {
+                !same_key && old.generation == generation && old.depth > entry.depth
+            })
+        };
+        match self.replacement {
+            Replacement::AlwaysReplace => Self::write(&bucket[0], key, data),
+            Replacement::DepthPreferred => {
+                if !keeps(&bucket[0]) {
+                    Self::write(&bucket[0], key, data);
+                }
+            }
+            Replacement::TwoTier => {
+                let slot = if keeps(&bucket[0]) { 1 } else { 0 };
+                Self::write(&bucket[slot], key, data);
+            }
+        }
+    }
+}
+
+impl Default for TranspositionTable {
+    fn default() -> Self {
+        Self::new(DEFAULT_ENTRIES, Replacement::default())
+    }
+}
+
+impl std::fmt::Debug for TranspositionTable {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TranspositionTable")
+            .field("capacity", &self.capacity())
+            .field("replacement", &self.replacement)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entries_round_trip_and_reject_other_keys() {
+        let table = TranspositionTable::new(100, Replacement::TwoTier);
+        assert_eq!((table.capacity(), table.bytes()), (64, 1024));
+        assert_eq!(
+            TranspositionTable::with_bytes(1 << 20, Replacement::TwoTier).capacity(),
+            1 << 16
+        );
+        let entry = Entry::new(-1_000_000, 12, Bound::Upper, Some(6));
+        table.store(0xABCD, entry);
+        assert_eq!(table.probe(0xABCD), Some(entry));
+        assert_eq!(table.probe(0xABCD + 64), None);
+        // Key 0 against an empty slot, whose words are also 0.
+        assert_eq!(table.probe(0), None);
+        let none = Entry::new(7, 0, Bound::Exact, None);
+        table.store(0, none);
+        assert_eq!(table.probe(0), Some(none));
+        table.clear();
+        assert_eq!(table.probe(0xABCD), None);
+    }
+
+    #[test]
+    fn replacement_policies_decide_what_stays() {
+        let (deep, shallow) = (
+            Entry::new(1, 9, Bound::Exact, Some(3)),
+            Entry::new(2, 3, Bound::Lower, None),
+        );
+        // Two keys sharing a bucket (and, below two-tier, a slot).
+        let (a, b) = (0x10, 0x10 + (1 << 20));
+        for (replacement, keeps_deep, keeps_shallow) in [
+            (Replacement::AlwaysReplace, false, true),
+            (Replacement::DepthPreferred, true, false),
+            (Replacement::TwoTier, true, true),
+        ] {
+            let table = TranspositionTable::new(16, replacement);
+            table.store(a, deep);
+            table.store(b, shallow);
+            assert_eq!(table.probe(a).is_some(), keeps_deep, "{replacement:?}");
+            assert_eq!(table.probe(b).is_some(), keeps_shallow, "{replacement:?}");
+        }
+        // A new search's results push out the old generation's.
+        let table = TranspositionTable::new(16, Replacement::DepthPreferred);
+        table.store(a, deep);
+        table.new_search();
+        table.store(b, shallow);
+        assert_eq!(table.probe(b).map(|e| e.score), Some(2));
+    }
+
+    #[test]
+    fn stats_report_occupancy_age_and_hits() {
+        let table = TranspositionTable::new(8, Replacement::AlwaysReplace);
+        for key in 0..4 {
+            table.store(key, Entry::new(0, 1, Bound::Exact, None));
+        }
+        table.new_search();
+        table.store(4, Entry::new(0, 1, Bound::Exact, None));
+        table.record(10, 4, 5);
+        let stats = table.stats();
+        assert_eq!(stats.occupancy, 5.0 / 8.0);
+        assert_eq!(stats.current, 1.0 / 5.0);
+        assert_eq!((stats.probes, stats.hits, stats.stores), (10, 4, 5));
+        assert_eq!(stats.hit_rate(), 0.4);
+    }
+}
@@ -0,0 +1,228 @@
+//! Texel tuning: fitting `EvalWeights` to how games actually ended.
+//!
+//! The heuristic evaluation is linear in its weights, so every position is
+//! reduced once to its feature counts, the side to move's minus the
+//! opponent's. A logistic curve turns a score into an expected result, and a
+//! local search moves one weight at a time for as long as the mean squared
+//! error against the real results keeps falling.
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    dataset::Sample, evaluate_explain, notation::from_grid, EvalWeights, GameError, HEIGHT, WIDTH,
+};
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct TuneConfig {
+    /// Weights the search starts from; the scale of the logistic curve is
+    /// fitted to them.
+    pub start: EvalWeights,
+    /// Most passes over the weights.
+    pub max_passes: usize,
+}
+
+impl Default for TuneConfig {
+    fn default() -> Self {
+        Self {
+            start: EvalWeights::default(),
+            max_passes: 200,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct TuneReport {
+    pub weights: EvalWeights,
+    /// `k` of the expected result `1 / (1 + e^(-k × score))`.
+    pub scale: f64,
+    /// Mean squared error of the starting and the tuned weights.
+    pub start_error: f64,
+    pub error: f64,
+    pub passes: usize,
+}
+
+/// A position's feature counts and its result for the side to move, 0 to 1.
+struct Position {
+    features: [i32; 6],
+    target: f64,
+}
+
+/// Fit `config.start` to `samples`, such as those of `dataset::generate`.
+pub fn tune(samples: &[Sample], config: &TuneConfig) -> Result<TuneReport, GameError> {
+    let positions = samples
+        .iter()
+        .map(position)
+        .collect::<Result<Vec<_>, _>>()?;
+    let scale = fit_scale(&positions, &config.start);
+    let start_error = error(&positions, &config.start, scale);
+    let (mut weights, mut best) = (config.start, start_error);
+    let mut step = 16;
+    let mut passes = 0;
+    while passes < config.max_passes {
+        passes += 1;
+        let mut improved = false;
+        for index in 0..6 {
+            for delta in [step, -step] {
+                let mut candidate = weights;
+                *weight_mut(&mut candidate, index) += delta;
+                let candidate_error = error(&positions, &candidate, scale);
+                if candidate_error < best {
+                    (weights, best) = (candidate, candidate_error);
+                    improved = true;
+                    break;
+                }
+            }
+        }
+        if !improved {
+            if step == 1 {
+                break;
+            }
+            step /= 2;
+        }
+    }
+    Ok(TuneReport {
+        weights,
+        scale,
+        start_error,
+        error: best,
+        passes,
+    })
+}
+
+fn weight_mut(weights: &mut EvalWeights, index: usize) -> &mut i32 {
+    match index {
+        0 => &mut weights.center,
+        1 => &mut weights.one,
+        2 => &mut weights.two,
+        3 => &mut weights.three,
+        4 => &mut weights.mobility,
+        _ => &mut weights.buried,
+    }
+}
+
+fn position(sample: &Sample) -> Result<Position, GameError> {
+    let player = sample.to_move;
+    let mut grid = [[None; WIDTH]; HEIGHT];
+    for (cell, &value) in sample.cells.iter().enumerate() {
+        grid[cell / WIDTH][cell % WIDTH] = match value {
+            1 => Some(player),
+            -1 => Some(player.opponent()),
+            _ => None,
+        };
+    }
+    // With as many discs of each colour the side to move opened, otherwise
+    // the opponent is a disc ahead.
+    let balance: i32 = sample.cells.iter().map(|&value| i32::from(value)).sum();
+    let first = if balance == 0 {
+        player
+    } else {
+        player.opponent()
+    };
+    let state = from_grid(&grid, first)?;
+    let breakdown = evaluate_explain(&state);
+    let (mine, theirs) = (
+        breakdown.player(player),
+        breakdown.player(player.opponent()),
+    );
+    Ok(Position {
+        features: [
+            mine.center.count as i32 - theirs.center.count as i32,
+            mine.ones.count as i32 - theirs.ones.count as i32,
+            mine.twos.count as i32 - theirs.twos.count as i32,
+            mine.threes.count as i32 - theirs.threes.count as i32,
+            mine.mobility.count as i32 - theirs.mobility.count as i32,
+            mine.buried.count as i32 - theirs.buried.count as i32,
+        ],
+        target: f64::from(sample.result + 1) / 2.0,
+    })
+}
+
+fn score(position: &Position, weights: &EvalWeights) -> i32 {
+    let [center, one, two, three, mobility, buried] = position.features;
+    center * weights.center
+        + one * weights.one
+        + two * weights.two
+        + three * weights.three
+        + mobility * weights.mobility
+        + buried * weights.buried
+}
+
+fn error(positions: &[Position], weights: &EvalWeights, scale: f64) -> f64 {
+    if positions.is_empty() {
+        return 0.0;
+    }
+    let total: f64 = positions
+        .iter()
+        .map(|position| {
+            let expected = 1.0 / (1.0 + (-scale * f64::from(score(position, weights))).exp());
+            (position.target - expected).powi(2)
+        })
+        .sum();
+    total / positions.len() as f64
+}
+
+/// The `scale` that fits `weights` best, by golden-section search over its
+/// logarithm.
+fn fit_scale(positions: &[Position], weights: &EvalWeights) -> f64 {
+    let ratio = (5f64.sqrt() - 1.0) / 2.0;
+    let at = |log: f64| error(positions, weights, log.exp());
+    let (mut low, mut high) = (1e-5f64.ln(), 1f64.ln());
+    for _ in 0..60 {
+        let a = high - ratio * (high - low);
+        let b = low + ratio * (high - low);
+        if at(a) < at(b) {
+            high = b;
+        } else {
+            low = a;
+        }
+    }
+    ((low + high) / 2.0).exp()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{dataset, parse_history, GameState, Player};
+
+    fn state(history: &str) -> GameState {
+        GameState::from_history(&parse_history(history).unwrap()).unwrap()
+    }
+
+    fn sample(history: &str, winner: Option<Player>) -> Sample {
+        dataset::sample(&state(history), winner, None)
+    }
+
+    #[test]
+    fn features_reproduce_the_evaluation() {
+        for history in ["R3", "R3B2R4", "R0B3R3B4R2B2", "R6B5R6"] {
+            let position = position(&sample(history, None)).unwrap();
+            assert_eq!(
+                score(&position, &EvalWeights::default()),
+                evaluate_explain(&state(history)).total,
+                "{history}"
+            );
+        }
+    }
+
+    #[test]
+    fn tuning_lowers_the_error() {
+        // Whoever holds the center wins, whatever the lines say.
+        let samples = [
+            sample("R3B0", Some(Player::Red)),
+            sample("R3B0R6", Some(Player::Red)),
+            sample("R0B3", Some(Player::Blue)),
+            sample("R0B3R1", Some(Player::Blue)),
+            sample("R3B3R0", Some(Player::Red)),
+            sample("R1B3R1B3", Some(Player::Blue)),
+        ];
+        let report = tune(&samples, &TuneConfig::default()).unwrap();
+        assert!(report.error < report.start_error, "{report:?}");
+        assert!(report.scale > 0.0);
+        assert!(report.weights.center > EvalWeights::default().center);
+        assert_eq!(tune(&samples, &TuneConfig::default()).unwrap(), report);
+
+        let empty = tune(&[], &TuneConfig::default()).unwrap();
+        assert_eq!(empty.weights, EvalWeights::default());
+    }
+}
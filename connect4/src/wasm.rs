@@ -0,0 +1,85 @@
+//! JavaScript bindings for running the engine in the browser, built with the
+//! `wasm` feature:
+//!
+//! ```text
+//! wasm-pack build connect4 --target web -- --features wasm
+//! ```
+//!
+//! WebAssembly in a browser has no clock, so these searches are bounded by a
+//! node budget instead of a deadline. About a million nodes take a few hundred
+//! milliseconds, which keeps low levels usable on the main thread; run deeper
+//! searches in a web worker. Errors are thrown as JavaScript `Error`s with the
+//! same messages the server sends.
+use wasm_bindgen::prelude::*;
+
+use crate::{GameState, MoveRequest, SearchLimits};
+
+fn state(history: &str) -> Result<GameState, JsError> {
+    let moves = crate::parse_history(history)?;
+    Ok(GameState::from_history(&moves)?)
+}
+
+fn to_js(value: &impl serde::Serialize) -> Result<JsValue, JsError> {
+    serde_wasm_bindgen::to_value(value).map_err(|err| JsError::new(&err.to_string()))
+}
+
+/// Best column (zero-based) for the side to move after `history`, searched
+/// to `level`. With `maxNodes` the search stops after about that many nodes
+/// and answers from the deepest depth it completed.
+#[wasm_bindgen(js_name = bestMove)]
+pub fn best_move(history: &str, level: u8, max_nodes: Option<u32>) -> Result<usize, JsError> {
+    let request = MoveRequest {
+        position: history.to_string(),
+        level,
+        first_player: None,
+    };
+    let limits = SearchLimits {
+        max_nodes: max_nodes.map(u64::from),
+        ..SearchLimits::default()
+    };
+    Ok(crate::best_move_within(request, &limits, |_| {})?.column)
+}
+
+/// `connect4::analyze`: status, every column's score and the expected line.
+/// It searches each column fully, so keep `level` low on the main thread.
+#[wasm_bindgen]
+pub fn analyze(history: &str, level: u8) -> Result<JsValue, JsError> {
+    let analysis = crate::analyze(MoveRequest {
+        position: history.to_string(),
+        level,
+        first_player: None,
+    })?;
+    to_js(&analysis)
+}
+
+/// The moves of `history` as `{ player, column }` objects.
+#[wasm_bindgen(js_name = parseHistory)]
+pub fn parse_history(history: &str) -> Result<JsValue, JsError> {
+    to_js(&crate::parse_history(history)?)
+}
+
+/// The board after `history`, shaped like the server's `BoardModel`.
+#[wasm_bindgen(js_name = boardModel)]
+pub fn board_model(history: &str) -> Result<JsValue, JsError> {
+    to_js(&state(history)?.board_model())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn node_budget_bounds_the_search() {
+        assert_eq!(best_move("R0B1R0B1R0B1", 8, Some(1_000)).unwrap(), 0);
+        assert_eq!(
+            best_move("R3B3", 6, None).unwrap(),
+            crate::best_move(MoveRequest {
+                position: "R3B3".to_string(),
+                level: 6,
+                first_player: None,
+            })
+            .unwrap()
+            .column
+        );
+    }
+}
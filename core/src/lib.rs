@@ -0,0 +1,436 @@
+//! The engine's board and alpha-beta search without `std` or an allocator,
+//! for microcontrollers driving a physical board.
+//!
+//! Bitboards, move order and evaluation are the same as the `connect4` crate
+//! uses, so at equal depth both pick the same column. What is left out needs
+//! `std`: move histories, cancellation and deadlines (budget nodes instead),
+//! and the per-depth principal variation. There is no transposition table;
+//! the search lives on the stack, a few dozen bytes per ply of depth.
+#![no_std]
+
+use core::fmt;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// Columns on the board.
+pub const WIDTH: usize = 7;
+/// Rows on the board.
+pub const HEIGHT: usize = 6;
+/// Bits per column in a bitboard; the extra sentinel row above the top
+/// keeps lines from wrapping into the next column.
+pub const COL_HEIGHT: usize = HEIGHT + 1;
+const MAX_CELLS: usize = WIDTH * HEIGHT;
+/// Score of a won position, from the winner's point of view.
+pub const WIN_SCORE: i32 = 1_000_000;
+
+/// Columns in the order the search tries them: centre first.
+pub const MOVE_ORDER: [usize; WIDTH] = [3, 2, 4, 1, 5, 0, 6];
+
+/// Every line of four on the board: 24 horizontal, 21 vertical and 12 of
+/// each diagonal.
+pub const WIN_LINES: usize = 69;
+
+/// Bitmasks of the lines of four, built at compile time.
+pub static WIN_MASKS: [u64; WIN_LINES] = win_masks();
+
+/// The centre column, which the evaluation rewards.
+pub const CENTER_MASK: u64 = ((1 << HEIGHT) - 1) << (WIDTH / 2 * COL_HEIGHT);
+
+/// The bitboard bit of a cell; row 0 is the bottom.
+pub const fn bit(column: usize, row: usize) -> u64 {
+    1 << (column * COL_HEIGHT + row)
+}
+
+const fn win_masks() -> [u64; WIN_LINES] {
+    // Column and row steps of the four directions.
+    const DIRECTIONS: [(usize, isize); 4] = [(1, 0), (0, 1), (1, 1), (1, -1)];
+    let mut masks = [0; WIN_LINES];
+    let mut count = 0;
+    let mut d = 0;
+    while d < DIRECTIONS.len() {
+        let (dc, dr) = DIRECTIONS[d];
+        let mut column = 0;
+        while column < WIDTH {
+            let mut row = 0;
+            while row < HEIGHT {
+                let end_column = column + 3 * dc;
+                let end_row = row as isize + 3 * dr;
+                if end_column < WIDTH && end_row >= 0 && end_row < HEIGHT as isize {
+                    let mut mask = 0;
+                    let mut i = 0;
+                    while i < 4 {
+                        mask |= bit(column + i * dc, (row as isize + i as isize * dr) as usize);
+                        i += 1;
+                    }
+                    masks[count] = mask;
+                    count += 1;
+                }
+                row += 1;
+            }
+            column += 1;
+        }
+        d += 1;
+    }
+    assert!(count == WIN_LINES);
+    masks
+}
+
+/// Whether `bits` contain a line of four.
+pub const fn has_won(bits: u64) -> bool {
+    let mut d = 0;
+    // Vertical, horizontal and both diagonals.
+    let shifts = [1, COL_HEIGHT, COL_HEIGHT - 1, COL_HEIGHT + 1];
+    while d < shifts.len() {
+        let m = bits & (bits >> shifts[d]);
+        if m & (m >> (2 * shifts[d])) != 0 {
+            return true;
+        }
+        d += 1;
+    }
+    false
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum Player {
+    Red,
+    Blue,
+}
+
+impl Player {
+    pub const fn opponent(self) -> Player {
+        match self {
+            Player::Red => Player::Blue,
+            Player::Blue => Player::Red,
+        }
+    }
+
+    const fn idx(self) -> usize {
+        match self {
+            Player::Red => 0,
+            Player::Blue => 1,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum MoveError {
+    ColumnOutOfBounds,
+    ColumnFull,
+}
+
+impl fmt::Display for MoveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            MoveError::ColumnOutOfBounds => "column is out of bounds",
+            MoveError::ColumnFull => "column is full",
+        })
+    }
+}
+
+/// A position. It is `Copy`, so trying a move is playing it on a copy.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Board {
+    players: [u64; 2],
+    heights: [u8; WIDTH],
+    to_move: Player,
+    moves_played: u8,
+}
+
+impl Board {
+    pub const fn new(to_move: Player) -> Self {
+        Self {
+            players: [0, 0],
+            heights: [0; WIDTH],
+            to_move,
+            moves_played: 0,
+        }
+    }
+
+    pub fn to_move(&self) -> Player {
+        self.to_move
+    }
+
+    pub fn moves_played(&self) -> usize {
+        usize::from(self.moves_played)
+    }
+
+    pub fn bits(&self, player: Player) -> u64 {
+        self.players[player.idx()]
+    }
+
+    pub fn cell(&self, column: usize, row: usize) -> Option<Player> {
+        [Player::Red, Player::Blue]
+            .into_iter()
+            .find(|&p| self.bits(p) & bit(column, row) != 0)
+    }
+
+    pub fn can_play(&self, column: usize) -> bool {
+        column < WIDTH && usize::from(self.heights[column]) < HEIGHT
+    }
+
+    /// Playable columns in search order.
+    pub fn legal_moves(&self) -> impl Iterator<Item = usize> {
+        let board = *self;
+        MOVE_ORDER
+            .into_iter()
+            .filter(move |&column| board.can_play(column))
+    }
+
+    /// Drop a disc for the side to move; `Ok(true)` if it completes a line.
+    /// Like the `connect4` crate this does not refuse moves after a win.
+    pub fn play(&mut self, column: usize) -> Result<bool, MoveError> {
+        if column >= WIDTH {
+            return Err(MoveError::ColumnOutOfBounds);
+        }
+        if !self.can_play(column) {
+            return Err(MoveError::ColumnFull);
+        }
+        let player = self.to_move;
+        self.players[player.idx()] |= bit(column, usize::from(self.heights[column]));
+        self.heights[column] += 1;
+        self.moves_played += 1;
+        self.to_move = player.opponent();
+        Ok(has_won(self.bits(player)))
+    }
+
+    pub fn winner(&self) -> Option<Player> {
+        [Player::Red, Player::Blue]
+            .into_iter()
+            .find(|&p| has_won(self.bits(p)))
+    }
+
+    pub fn is_full(&self) -> bool {
+        usize::from(self.moves_played) >= MAX_CELLS
+    }
+}
+
+/// Weights of the heuristic evaluation; the defaults match `connect4`'s.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct EvalWeights {
+    /// Per disc in the centre column.
+    pub center: i32,
+    /// Per open line of four holding one, two or three of the side's discs.
+    pub one: i32,
+    pub two: i32,
+    pub three: i32,
+}
+
+impl Default for EvalWeights {
+    fn default() -> Self {
+        Self {
+            center: 3,
+            one: 2,
+            two: 10,
+            three: 50,
+        }
+    }
+}
+
+/// Heuristic score of `board` from `player`'s point of view.
+pub fn evaluate(board: &Board, player: Player, weights: &EvalWeights) -> i32 {
+    let mine = board.bits(player);
+    let theirs = board.bits(player.opponent());
+    if has_won(mine) {
+        return WIN_SCORE;
+    }
+    if has_won(theirs) {
+        return -WIN_SCORE;
+    }
+    let mut score = weights.center * (mine & CENTER_MASK).count_ones() as i32
+        - weights.center * (theirs & CENTER_MASK).count_ones() as i32;
+    for mask in &WIN_MASKS {
+        let line = |count| match count {
+            1 => weights.one,
+            2 => weights.two,
+            3 => weights.three,
+            _ => 0,
+        };
+        match ((mine & mask).count_ones(), (theirs & mask).count_ones()) {
+            (n, 0) => score += line(n),
+            (0, n) => score -= line(n),
+            _ => {} // blocked line
+        }
+    }
+    score
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct SearchResult {
+    pub column: usize,
+    /// From the side to move's point of view; `WIN_SCORE` is a forced win.
+    pub score: i32,
+    /// Depth of the deepest completed iteration.
+    pub depth: usize,
+}
+
+/// Iterative-deepening alpha-beta search.
+#[derive(Clone, Debug, Default)]
+pub struct Search {
+    pub weights: EvalWeights,
+    /// Node budget, summed over iterations. The first iteration always
+    /// completes so there is a move to return.
+    pub max_nodes: Option<u64>,
+    nodes: u64,
+    enforce_limits: bool,
+    aborted: bool,
+}
+
+impl Search {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_max_nodes(max_nodes: u64) -> Self {
+        Self {
+            max_nodes: Some(max_nodes),
+            ..Self::default()
+        }
+    }
+
+    /// Nodes visited by the last `best_move`.
+    pub fn nodes(&self) -> u64 {
+        self.nodes
+    }
+
+    /// The best column at `depth`, or from the deepest iteration the node
+    /// budget allowed. `None` if the board has no legal move.
+    pub fn best_move(&mut self, board: &Board, depth: usize) -> Option<SearchResult> {
+        self.nodes = 0;
+        self.aborted = false;
+        let mut best = None;
+        for d in 1..=depth.max(1) {
+            self.enforce_limits = d > 1;
+            if self.enforce_limits && self.exceeded() {
+                break;
+            }
+            match self.root(board, d) {
+                Some((column, score)) => {
+                    best = Some(SearchResult {
+                        column,
+                        score,
+                        depth: d,
+                    })
+                }
+                None => break,
+            }
+        }
+        best
+    }
+
+    fn exceeded(&self) -> bool {
+        self.max_nodes.is_some_and(|max| self.nodes >= max)
+    }
+
+    fn should_stop(&mut self) -> bool {
+        if !self.aborted && self.enforce_limits && self.exceeded() {
+            self.aborted = true;
+        }
+        self.aborted
+    }
+
+    fn root(&mut self, board: &Board, depth: usize) -> Option<(usize, i32)> {
+        let mut best = None;
+        let mut alpha = i32::MIN / 2;
+        let beta = i32::MAX / 2;
+        for column in board.legal_moves() {
+            let score = self.child(board, column, depth, -beta, -alpha);
+            if score > alpha {
+                alpha = score;
+                best = Some(column);
+            }
+        }
+        if self.aborted {
+            return None;
+        }
+        best.map(|column| (column, alpha))
+    }
+
+    /// Score of playing `column` from the mover's point of view.
+    fn child(&mut self, board: &Board, column: usize, depth: usize, alpha: i32, beta: i32) -> i32 {
+        let mut child = *board;
+        let won = child.play(column).expect("legal move must succeed");
+        self.nodes += 1;
+        if won {
+            WIN_SCORE
+        } else if child.is_full() {
+            0
+        } else {
+            -self.negamax(&child, depth.saturating_sub(1), alpha, beta)
+        }
+    }
+
+    fn negamax(&mut self, board: &Board, depth: usize, mut alpha: i32, beta: i32) -> i32 {
+        if self.should_stop() {
+            return 0;
+        }
+        if depth == 0 || board.is_full() {
+            return evaluate(board, board.to_move, &self.weights);
+        }
+        let mut best = i32::MIN / 2;
+        for column in board.legal_moves() {
+            let score = self.child(board, column, depth, -beta, -alpha);
+            best = best.max(score);
+            alpha = alpha.max(score);
+            if alpha >= beta {
+                break;
+            }
+        }
+        best
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn board(columns: &[usize]) -> Board {
+        let mut board = Board::new(Player::Red);
+        for &column in columns {
+            board.play(column).unwrap();
+        }
+        board
+    }
+
+    #[test]
+    fn lines_and_wins_are_detected() {
+        assert_eq!(WIN_MASKS.iter().filter(|m| m.count_ones() == 4).count(), 69);
+        assert!(WIN_MASKS.iter().all(|&mask| has_won(mask)));
+        assert!(!has_won(bit(0, 0) | bit(1, 0) | bit(2, 0) | bit(3, 1)));
+        let mut won = board(&[0, 1, 0, 1, 0, 1]);
+        assert_eq!(won.play(0), Ok(true));
+        assert_eq!(won.winner(), Some(Player::Red));
+        assert_eq!(board(&[0; 6]).play(0), Err(MoveError::ColumnFull));
+        assert_eq!(board(&[]).play(7), Err(MoveError::ColumnOutOfBounds));
+    }
+
+    #[test]
+    fn search_takes_wins_blocks_threats_and_respects_budgets() {
+        let mut search = Search::new();
+        let win = search.best_move(&board(&[0, 1, 0, 1, 0, 1]), 5).unwrap();
+        assert_eq!((win.column, win.score), (0, WIN_SCORE));
+        // Blue must block Red's three in column 3.
+        assert_eq!(
+            search
+                .best_move(&board(&[3, 0, 3, 6, 3]), 5)
+                .unwrap()
+                .column,
+            3
+        );
+
+        let mut limited = Search::with_max_nodes(500);
+        let result = limited.best_move(&Board::new(Player::Red), 12).unwrap();
+        assert!(result.depth < 12);
+        assert_eq!(
+            result.column,
+            Search::new()
+                .best_move(&Board::new(Player::Red), result.depth)
+                .unwrap()
+                .column
+        );
+    }
+}
@@ -0,0 +1,124 @@
+//! Node.js bindings (N-API), so a Node backend can call the engine
+//! in-process instead of going through the HTTP server:
+//!
+//! ```text
+//! cd node && npm install && npm run build
+//! ```
+//!
+//! Searches run as `AsyncTask`s on libuv's thread pool and resolve a
+//! `Promise`, so they never block the event loop. Errors reject with the
+//! same messages the server sends, and results are plain objects shaped like
+//! its JSON.
+use std::time::{Duration, Instant};
+
+use connect4::{Analysis, GameError, MoveRequest, SearchLimits};
+use napi::{bindgen_prelude::AsyncTask, Env, Error, JsUnknown, Result, Task};
+use napi_derive::napi;
+
+fn error(err: GameError) -> Error {
+    Error::from_reason(err.to_string())
+}
+
+fn request(history: &str, level: u32) -> Result<MoveRequest> {
+    let level = u8::try_from(level)
+        .map_err(|_| Error::from_reason(format!("depth {level} is out of range (1-15)")))?;
+    Ok(MoveRequest {
+        position: history.to_string(),
+        level,
+        first_player: None,
+    })
+}
+
+pub struct BestMove {
+    history: String,
+    level: u32,
+    limits: SearchLimits,
+}
+
+impl Task for BestMove {
+    type Output = usize;
+    type JsValue = u32;
+
+    fn compute(&mut self) -> Result<usize> {
+        let request = request(&self.history, self.level)?;
+        connect4::best_move_within(request, &self.limits, |_| {})
+            .map(|response| response.column)
+            .map_err(error)
+    }
+
+    fn resolve(&mut self, _env: Env, column: usize) -> Result<u32> {
+        Ok(column as u32)
+    }
+}
+
+pub struct Analyze {
+    history: String,
+    level: u32,
+}
+
+impl Task for Analyze {
+    type Output = Analysis;
+    type JsValue = JsUnknown;
+
+    fn compute(&mut self) -> Result<Analysis> {
+        connect4::analyze(request(&self.history, self.level)?).map_err(error)
+    }
+
+    fn resolve(&mut self, env: Env, analysis: Analysis) -> Result<JsUnknown> {
+        env.to_js_value(&analysis)
+    }
+}
+
+/// Best column (zero-based) for the side to move after `history`, searched
+/// to `level`. `timeMs` and `maxNodes` stop the search early; it then
+/// answers from the deepest depth it completed.
+#[napi]
+pub fn best_move(
+    history: String,
+    level: u32,
+    time_ms: Option<u32>,
+    max_nodes: Option<u32>,
+) -> AsyncTask<BestMove> {
+    let limits = SearchLimits {
+        // The clock starts now rather than when a pool thread picks the task up.
+        deadline: time_ms.map(|ms| Instant::now() + Duration::from_millis(u64::from(ms))),
+        max_nodes: max_nodes.map(u64::from),
+        ..SearchLimits::default()
+    };
+    AsyncTask::new(BestMove {
+        history,
+        level,
+        limits,
+    })
+}
+
+/// `connect4::analyze`: status, every column's score and the expected line.
+#[napi]
+pub fn analyze(history: String, level: u32) -> AsyncTask<Analyze> {
+    AsyncTask::new(Analyze { history, level })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tasks_search_and_report_engine_errors() {
+        let mut task = BestMove {
+            history: "R0B1R0B1R0B1".to_string(),
+            level: 6,
+            limits: SearchLimits::default(),
+        };
+        assert_eq!(task.compute().unwrap(), 0);
+        for (level, message) in [
+            (16, "depth 16 is out of range (1-15)"),
+            (300, "depth 300 is out of range (1-15)"),
+        ] {
+            let mut task = Analyze {
+                history: "R3B3".to_string(),
+                level,
+            };
+            assert_eq!(task.compute().unwrap_err().reason, message);
+        }
+    }
+}
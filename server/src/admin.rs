@@ -0,0 +1,353 @@
+//! Operator endpoints, open to admin API keys only.
+//!
+//! `GET /admin/stats` is a snapshot of everything that grows or queues:
+//! caches, the transposition table, live sessions and rooms, the search pool
+//! and job queue, and recent search latency per level. `POST /admin/flush`
+//! empties caches, e.g. after an engine change made stored answers stale.
+//! `GET /admin/book/info` tells which opening book is in use, and
+//! `/admin/book/entries` lists, sets and deletes its moves by hand.
+use std::time::Instant;
+
+use axum::{
+    extract::State,
+    http::StatusCode,
+    routing::{get, post},
+    Extension, Json, Router,
+};
+use connect4::{book::BookEntry, parse_history, tt::TableStats, GameState};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{
+    auth::Tier,
+    book::BookInfo,
+    error::{ApiError, ApiJson, ApiQuery},
+    jobs::JobQueueStats,
+    metrics::LevelLatency,
+    move_cache::MoveCacheStats,
+    search_pool::SearchPoolStats,
+    sessions::PonderStats,
+    AppState,
+};
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/admin/stats", get(stats))
+        .route("/admin/flush", post(flush))
+        .route("/admin/book/info", get(book_info))
+        .route(
+            "/admin/book/entries",
+            get(book_entries)
+                .put(set_book_entry)
+                .delete(delete_book_entry),
+        )
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct AdminStats {
+    pub uptime_secs: u64,
+    pub move_cache: MoveCacheStats,
+    /// The table all searches share; `hit_rate` is over the searches since
+    /// start-up or the last flush.
+    pub transposition_table: TableReport,
+    pub ponder: PonderStats,
+    /// Live sessions and rooms; expired ones awaiting the sweeper are excluded.
+    pub sessions: usize,
+    pub rooms: usize,
+    pub search_pool: SearchPoolStats,
+    pub jobs: JobQueueStats,
+    /// Recent searches per level; cache hits are not included.
+    pub latency: Vec<LevelLatency>,
+}
+
+/// [`TableStats`] with its hit rate spelled out for clients.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct TableReport {
+    #[serde(flatten)]
+    pub stats: TableStats,
+    /// Hits per probe, 0 before any probe.
+    pub hit_rate: f64,
+}
+
+impl TableReport {
+    fn new(stats: TableStats) -> Self {
+        Self {
+            hit_rate: stats.hit_rate(),
+            stats,
+        }
+    }
+}
+
+/// Server health and load at a glance (admin keys only).
+#[utoipa::path(
+    get,
+    path = "/api/v1/admin/stats",
+    tag = "admin",
+    responses(
+        (status = 200, body = AdminStats),
+        (status = 401, description = "Unknown API key", body = ErrorBody),
+        (status = 403, description = "Not an admin key", body = ErrorBody),
+    ),
+)]
+pub async fn stats(
+    State(app): State<AppState>,
+    Extension(tier): Extension<Tier>,
+) -> Result<Json<AdminStats>, ApiError> {
+    tier.require_admin()?;
+    Ok(Json(AdminStats {
+        uptime_secs: app.started.elapsed().as_secs(),
+        move_cache: app.move_cache.stats(),
+        transposition_table: TableReport::new(app.table.stats()),
+        ponder: app.ponder.stats(),
+        sessions: app.sessions.live(),
+        rooms: app.rooms.live(),
+        search_pool: app.search_pool.stats(),
+        jobs: app.jobs.stats(),
+        latency: app.latencies.summary(),
+    }))
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum CacheKind {
+    /// The in-process LRU of searched moves.
+    Memory,
+    /// The position cache in storage.
+    Positions,
+    /// The latency windows.
+    Latency,
+    /// The transposition table.
+    Table,
+    #[default]
+    All,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct FlushQuery {
+    /// What to empty; everything when omitted.
+    #[serde(default)]
+    pub cache: CacheKind,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct FlushResult {
+    pub memory_entries: usize,
+    pub position_entries: usize,
+    pub table_entries: usize,
+}
+
+/// Empty caches (admin keys only).
+#[utoipa::path(
+    post,
+    path = "/api/v1/admin/flush",
+    tag = "admin",
+    params(FlushQuery),
+    responses(
+        (status = 200, description = "Entries removed from each cache", body = FlushResult),
+        (status = 401, description = "Unknown API key", body = ErrorBody),
+        (status = 403, description = "Not an admin key", body = ErrorBody),
+    ),
+)]
+pub async fn flush(
+    State(app): State<AppState>,
+    Extension(tier): Extension<Tier>,
+    ApiQuery(query): ApiQuery<FlushQuery>,
+) -> Result<Json<FlushResult>, ApiError> {
+    tier.require_admin()?;
+    let started = Instant::now();
+    let all = query.cache == CacheKind::All;
+    let mut result = FlushResult {
+        memory_entries: 0,
+        position_entries: 0,
+        table_entries: 0,
+    };
+    if all || query.cache == CacheKind::Memory {
+        result.memory_entries = app.move_cache.clear();
+    }
+    if all || query.cache == CacheKind::Positions {
+        let storage = app.storage.clone();
+        result.position_entries =
+            tokio::task::spawn_blocking(move || storage.clear_cached_moves()).await??;
+    }
+    if all || query.cache == CacheKind::Latency {
+        app.latencies.clear();
+    }
+    if all || query.cache == CacheKind::Table {
+        result.table_entries = app.table.clear();
+    }
+    info!(
+        key = tier.key.as_deref().unwrap_or_default(),
+        cache = ?query.cache,
+        memory = result.memory_entries,
+        positions = result.position_entries,
+        table = result.table_entries,
+        elapsed_ms = started.elapsed().as_millis() as u64,
+        "caches flushed"
+    );
+    Ok(Json(result))
+}
+
+/// The opening book in use and whether its file last loaded (admin keys
+/// only).
+#[utoipa::path(
+    get,
+    path = "/api/v1/admin/book/info",
+    tag = "admin",
+    responses(
+        (status = 200, body = BookInfo),
+        (status = 401, description = "Unknown API key", body = ErrorBody),
+        (status = 403, description = "Not an admin key", body = ErrorBody),
+    ),
+)]
+pub async fn book_info(
+    State(app): State<AppState>,
+    Extension(tier): Extension<Tier>,
+) -> Result<Json<BookInfo>, ApiError> {
+    tier.require_admin()?;
+    Ok(Json(app.book.info()))
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct BookPositionQuery {
+    /// Move history; the empty board when omitted.
+    #[serde(default)]
+    pub position: String,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct BookMoveQuery {
+    #[serde(default)]
+    pub position: String,
+    pub column: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct BookEdit {
+    /// The entry replaced or deleted, if there was one.
+    pub previous: Option<BookEntry>,
+    /// The book after the edit.
+    pub book: BookInfo,
+}
+
+/// The book's moves for a position, best first, mirrored entries included
+/// (admin keys only).
+#[utoipa::path(
+    get,
+    path = "/api/v1/admin/book/entries",
+    tag = "admin",
+    params(BookPositionQuery),
+    responses(
+        (status = 200, body = [BookEntry]),
+        (status = 401, description = "Unknown API key", body = ErrorBody),
+        (status = 403, description = "Not an admin key", body = ErrorBody),
+        (status = 422, description = "Malformed or illegal position", body = ErrorBody),
+    ),
+)]
+pub async fn book_entries(
+    State(app): State<AppState>,
+    Extension(tier): Extension<Tier>,
+    ApiQuery(query): ApiQuery<BookPositionQuery>,
+) -> Result<Json<Vec<BookEntry>>, ApiError> {
+    tier.require_admin()?;
+    let state = GameState::from_history(&parse_history(&query.position)?)?;
+    let moves = app.book.current().map(|book| book.moves(&state));
+    Ok(Json(
+        moves
+            .unwrap_or_default()
+            .into_iter()
+            .map(|m| BookEntry {
+                position: query.position.clone(),
+                column: m.column,
+                weight: m.weight,
+                eval: m.eval,
+            })
+            .collect(),
+    ))
+}
+
+/// Add a book move, or replace the weight and eval of one already there; the
+/// book file is rewritten (admin keys only).
+#[utoipa::path(
+    put,
+    path = "/api/v1/admin/book/entries",
+    tag = "admin",
+    request_body = BookEntry,
+    responses(
+        (status = 200, body = BookEdit),
+        (status = 401, description = "Unknown API key", body = ErrorBody),
+        (status = 403, description = "Not an admin key", body = ErrorBody),
+        (status = 409, description = "No opening book is loaded", body = ErrorBody),
+        (status = 422, description = "Illegal position or move, or a zero weight", body = ErrorBody),
+    ),
+)]
+pub async fn set_book_entry(
+    State(app): State<AppState>,
+    Extension(tier): Extension<Tier>,
+    ApiJson(entry): ApiJson<BookEntry>,
+) -> Result<Json<BookEdit>, ApiError> {
+    tier.require_admin()?;
+    let (position, column) = (entry.position.clone(), entry.column);
+    let store = app.book.clone();
+    let previous =
+        tokio::task::spawn_blocking(move || store.edit(|book| book.set_entry(entry))).await??;
+    info!(
+        key = tier.key.as_deref().unwrap_or_default(),
+        position,
+        column,
+        replaced = previous.is_some(),
+        "book entry set"
+    );
+    Ok(Json(BookEdit {
+        previous,
+        book: app.book.info(),
+    }))
+}
+
+/// Delete a book move; the book file is rewritten (admin keys only).
+#[utoipa::path(
+    delete,
+    path = "/api/v1/admin/book/entries",
+    tag = "admin",
+    params(BookMoveQuery),
+    responses(
+        (status = 200, body = BookEdit),
+        (status = 401, description = "Unknown API key", body = ErrorBody),
+        (status = 403, description = "Not an admin key", body = ErrorBody),
+        (status = 404, description = "The book has no such move", body = ErrorBody),
+        (status = 409, description = "No opening book is loaded", body = ErrorBody),
+        (status = 422, description = "Illegal position or move", body = ErrorBody),
+    ),
+)]
+pub async fn delete_book_entry(
+    State(app): State<AppState>,
+    Extension(tier): Extension<Tier>,
+    ApiQuery(query): ApiQuery<BookMoveQuery>,
+) -> Result<Json<BookEdit>, ApiError> {
+    tier.require_admin()?;
+    let store = app.book.clone();
+    let (position, column) = (query.position.clone(), query.column);
+    let previous = tokio::task::spawn_blocking(move || {
+        store.edit(|book| book.remove_entry(&position, column))
+    })
+    .await??;
+    if previous.is_none() {
+        return Err(ApiError::new(
+            StatusCode::NOT_FOUND,
+            "the book has no such move",
+        ));
+    }
+    info!(
+        key = tier.key.as_deref().unwrap_or_default(),
+        position = query.position,
+        column = query.column,
+        "book entry deleted"
+    );
+    Ok(Json(BookEdit {
+        previous,
+        book: app.book.info(),
+    }))
+}
@@ -0,0 +1,280 @@
+//! Read side of the game archive: listing finished games, replaying one
+//! with the engine's review attached, and exporting games as text. Any
+//! history, archived or not, can also be reviewed on request.
+use axum::{
+    extract::{Path, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Response,
+    },
+    Extension, Json,
+};
+use connect4::{
+    engine_info,
+    notation::{Notation, Outcome},
+    parse_history,
+    review::{review_game, review_game_with, GameReview, DEFAULT_REVIEW_DEPTH},
+    Player,
+};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::UnboundedReceiverStream;
+use utoipa::ToSchema;
+
+use crate::{
+    auth::Tier,
+    error::{ApiError, ApiJson, ApiQuery},
+    storage::{GamePage, GameQuery, GameRecord, GameResult},
+    AppState,
+};
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ArchivedGame {
+    #[serde(flatten)]
+    pub record: GameRecord,
+    pub review: GameReview,
+}
+
+/// List archived games, newest first.
+#[utoipa::path(
+    get,
+    path = "/api/v1/games",
+    tag = "archive",
+    params(GameQuery),
+    responses((status = 200, body = GamePage)),
+)]
+pub async fn list_games(
+    State(app): State<AppState>,
+    ApiQuery(query): ApiQuery<GameQuery>,
+) -> Result<Json<GamePage>, ApiError> {
+    let page = app
+        .storage
+        .list_games(&query)
+        .map_err(|err| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, err))?;
+    Ok(Json(page))
+}
+
+/// Look up a finished game and review it. Reviews are recomputed on demand:
+/// they are cheap at the default depth and this keeps the stored record free
+/// of engine-version-specific data.
+pub async fn archived_game(app: &AppState, id: &str) -> Result<Option<ArchivedGame>, ApiError> {
+    let record = app
+        .storage
+        .game(id)
+        .map_err(|err| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, err))?;
+    let Some(record) = record else {
+        return Ok(None);
+    };
+    let history = record.history.clone();
+    let review = tokio::task::spawn_blocking(move || {
+        review_game(&parse_history(&history)?, DEFAULT_REVIEW_DEPTH)
+    })
+    .await
+    .map_err(|err| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, err))??;
+    Ok(Some(ArchivedGame { record, review }))
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct ReviewRequest {
+    /// Move history such as `R3B3R2`, usually a whole game.
+    pub position: String,
+    /// Search depth for grading, 1 to 15 (default 6, as in the archive).
+    pub depth: Option<u8>,
+}
+
+/// Grade every move of a game in one call. With `Accept: text/event-stream`
+/// the review is streamed instead: a `move` event per graded move as soon as
+/// it is searched, then `done` with the whole review (or `error`), so long
+/// games and deep reviews render progressively. The review runs on the search
+/// pool at its depth.
+#[utoipa::path(
+    post,
+    path = "/api/v1/review",
+    tag = "archive",
+    request_body = ReviewRequest,
+    responses(
+        (
+            status = 200,
+            description = "The review, or with `Accept: text/event-stream` `move` events \
+                           carrying a `MoveReview` each, then `done` with the `GameReview`",
+            body = GameReview,
+        ),
+        (status = 400, description = "Depth out of range", body = ErrorBody),
+        (status = 422, description = "Malformed or illegal position", body = ErrorBody),
+        (status = 429, description = "Rate limit exceeded; see `Retry-After`", body = ErrorBody),
+        (status = 503, description = "Search queue is full", body = ErrorBody),
+    ),
+)]
+pub async fn review_history(
+    State(app): State<AppState>,
+    Extension(tier): Extension<Tier>,
+    headers: HeaderMap,
+    ApiJson(req): ApiJson<ReviewRequest>,
+) -> Result<Response, ApiError> {
+    let depth = req.depth.unwrap_or(DEFAULT_REVIEW_DEPTH as u8);
+    if depth == 0 {
+        return Err(connect4::GameError::DepthOutOfRange(depth).into());
+    }
+    app.check_level(&tier, depth)?;
+    let moves = parse_history(&req.position)?;
+    connect4::GameState::from_history(&moves)?;
+    let streamed = headers
+        .get(header::ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .is_some_and(|accept| accept.contains("text/event-stream"));
+    if !streamed {
+        let review = app
+            .search_pool
+            .run(depth, move || Ok(review_game(&moves, usize::from(depth))?))
+            .await?;
+        return Ok(Json(review).into_response());
+    }
+
+    let admission = app.search_pool.admit(depth)?;
+    let (tx, rx) = mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        let graded = tx.clone();
+        let result = admission
+            .run(move || {
+                Ok(review_game_with(&moves, usize::from(depth), |review| {
+                    let event = Event::default().event("move").json_data(review);
+                    let _ = graded.send(event.expect("move review serializes"));
+                })?)
+            })
+            .await;
+        let event = match result {
+            Ok(review) => Event::default()
+                .event("done")
+                .json_data(review)
+                .expect("review serializes"),
+            Err(err) => Event::default().event("error").data(err.to_string()),
+        };
+        let _ = tx.send(event);
+    });
+    let stream = tokio_stream::StreamExt::map(
+        UnboundedReceiverStream::new(rx),
+        Ok::<_, std::convert::Infallible>,
+    );
+    Ok(Sse::new(stream)
+        .keep_alive(KeepAlive::default())
+        .into_response())
+}
+
+/// Export a game in the text notation of `connect4::notation`. Finished games
+/// come from the archive; a session still being played exports as
+/// unfinished (`*`).
+#[utoipa::path(
+    get,
+    path = "/api/v1/games/{id}/export",
+    tag = "archive",
+    params(("id" = String, Path, description = "Session or archive ID")),
+    responses(
+        (status = 200, description = "The game as text", content_type = "text/plain", body = String),
+        (status = 404, description = "Unknown or expired ID", body = ErrorBody),
+    ),
+)]
+pub(crate) async fn export_game(
+    State(app): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<impl IntoResponse, ApiError> {
+    let stored = app
+        .storage
+        .game(&id)
+        .map_err(|err| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, err))?;
+    let game = match stored {
+        Some(record) => notation(
+            &record.id,
+            &record.history,
+            [record.red_level, record.blue_level],
+            record.engine.as_deref(),
+            record.started_at,
+            record.result.into(),
+        )?,
+        None => app.sessions.with(&id, |session| {
+            let engine = |player: Player| (player != session.human).then_some(session.level);
+            let outcome = session
+                .record()
+                .map_or(Outcome::Unfinished, |record| record.result.into());
+            notation(
+                &id,
+                &session.history(),
+                [engine(Player::Red), engine(Player::Blue)],
+                Some(&engine_info().to_string()),
+                session.started_at,
+                outcome,
+            )
+        })?,
+    };
+    let disposition = format!("attachment; filename=\"{id}.c4n\"");
+    Ok((
+        [
+            (
+                header::CONTENT_TYPE,
+                "text/plain; charset=utf-8".to_string(),
+            ),
+            (header::CONTENT_DISPOSITION, disposition),
+        ],
+        game.to_string(),
+    ))
+}
+
+impl From<GameResult> for Outcome {
+    fn from(result: GameResult) -> Self {
+        match result {
+            GameResult::RedWin => Self::RedWin,
+            GameResult::BlueWin => Self::BlueWin,
+            GameResult::Draw => Self::Draw,
+        }
+    }
+}
+
+fn notation(
+    id: &str,
+    history: &str,
+    levels: [Option<u8>; 2],
+    engine: Option<&str>,
+    started_at: u64,
+    outcome: Outcome,
+) -> Result<Notation, ApiError> {
+    let player =
+        |level: Option<u8>| level.map_or("Human".to_string(), |l| format!("Engine level {l}"));
+    let game = Notation::new(parse_history(history)?, outcome)
+        .with_tag("Event", "Connect 4")
+        .with_tag("Date", date(started_at))
+        .with_tag("Red", player(levels[0]))
+        .with_tag("Blue", player(levels[1]))
+        .with_tag("Id", id);
+    Ok(match engine {
+        Some(engine) => game.with_tag("Engine", engine),
+        None => game,
+    })
+}
+
+/// `YYYY.MM.DD` (UTC) of a Unix timestamp, as PGN writes dates.
+fn date(unix: u64) -> String {
+    // Howard Hinnant's days-to-civil algorithm.
+    let days = (unix / 86_400) as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!("{year:04}.{month:02}.{day:02}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dates_are_utc_calendar_days() {
+        assert_eq!(date(0), "1970.01.01");
+        assert_eq!(date(951_782_400), "2000.02.29");
+        assert_eq!(date(1_791_935_999), "2026.10.13");
+    }
+}
@@ -0,0 +1,828 @@
+//! Rated games between bots.
+//!
+//! A bot opens `GET /api/v1/arena/ws?name=<bot>` and is paired with the next
+//! bot that connects under another name, or with the built-in engine when it
+//! passes `engine=<level>`. The server referees: it sends `turn` with the
+//! position and clock to the side to move and waits for a `move`. An illegal
+//! move, a flag falling or a dropped connection loses the game. The engine
+//! resigns lost games as the `[resign]` policy says, and when it offers a
+//! draw the bot's next `turn` says so; answering `accept_draw` instead of a
+//! move ends the game drawn. Every arena
+//! game runs on the same clock (`[arena] time_control`) and is rated with Elo
+//! (K = 32, starting at 1500); ratings live in memory and start over with the
+//! server. After `over` the bot is queued for its next game on the same socket.
+//! Rated play needs an API key, and the first key to enter under a name owns
+//! it: nobody else can play as that bot or spend its rating.
+//! `GET /api/v1/arena/sprt` runs a sequential probability ratio test on the
+//! games between two players, to accept or reject a new engine version.
+use std::{
+    collections::HashMap,
+    future,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use axum::{
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        State,
+    },
+    http::StatusCode,
+    response::Response,
+    routing::get,
+    Extension, Json, Router,
+};
+use connect4::{
+    best_move_within, engine_info, format_history,
+    resign::ResignTracker,
+    stats::{Score, Sprt, SprtStatus},
+    GameError, GameState, MoveRequest, MoveResponse, Player, SearchInfo, TypedMove,
+};
+use serde::{Deserialize, Serialize};
+use tokio::sync::oneshot;
+use tracing::warn;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{
+    auth::Tier,
+    clock::{Clock, GameClock, TimeControl},
+    error::{ApiError, ApiQuery},
+    sessions::new_id,
+    storage::{unix_now, GameRecord, GameResult},
+    AppState,
+};
+
+const INITIAL_RATING: i32 = 1500;
+const K_FACTOR: f64 = 32.0;
+const MAX_NAME_LEN: usize = 32;
+/// Wait before asking a busy search pool again for an engine move.
+const BUSY_RETRY: Duration = Duration::from_millis(50);
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ArenaConfig {
+    /// Clock of every arena game.
+    pub time_control: TimeControl,
+}
+
+impl Default for ArenaConfig {
+    fn default() -> Self {
+        Self {
+            time_control: TimeControl {
+                initial_ms: 60_000,
+                increment_ms: 1_000,
+            },
+        }
+    }
+}
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/arena/ws", get(arena_socket))
+        .route("/arena/ratings", get(list_ratings))
+        .route("/arena/sprt", get(arena_sprt))
+}
+
+/// Bots waiting for an opponent, and everyone's rating.
+#[derive(Clone, Default)]
+pub struct Arena {
+    waiting: Arc<Mutex<Vec<Waiter>>>,
+    ratings: Arc<Mutex<HashMap<String, Rating>>>,
+    /// Games between each two players, by both orders of their names, from
+    /// the first one's side.
+    results: Arc<Mutex<HashMap<(String, String), Score>>>,
+    /// Name of the API key each bot name was first entered with.
+    owners: Arc<Mutex<HashMap<String, String>>>,
+}
+
+/// A bot waiting for an opponent. The opponent's task hands its bot over,
+/// with a channel to get it back on after the game.
+struct Waiter {
+    name: String,
+    opponent: oneshot::Sender<(Bot, oneshot::Sender<Bot>)>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct Rating {
+    /// Bot name, or `engine-<level>` for the built-in engine.
+    pub name: String,
+    pub rating: i32,
+    pub games: u32,
+    pub wins: u32,
+    pub losses: u32,
+    pub draws: u32,
+}
+
+impl Rating {
+    fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            rating: INITIAL_RATING,
+            games: 0,
+            wins: 0,
+            losses: 0,
+            draws: 0,
+        }
+    }
+}
+
+impl Arena {
+    /// Claim `name` for `key`, or fail if another key already owns it.
+    fn claim(&self, name: &str, key: &str) -> bool {
+        let mut owners = self.owners.lock().unwrap();
+        owners
+            .entry(name.to_string())
+            .or_insert_with(|| key.to_string())
+            == key
+    }
+
+    pub fn rating(&self, name: &str) -> i32 {
+        let ratings = self.ratings.lock().unwrap();
+        ratings.get(name).map_or(INITIAL_RATING, |r| r.rating)
+    }
+
+    /// Update both players' ratings for a finished game, returning the new
+    /// ratings, Red then Blue.
+    fn rate(&self, names: [&str; 2], result: GameResult) -> [i32; 2] {
+        let mut ratings = self.ratings.lock().unwrap();
+        let [red, blue] = names.map(|name| {
+            ratings
+                .get(name)
+                .map_or(INITIAL_RATING, |r: &Rating| r.rating)
+        });
+        let expected = 1.0 / (1.0 + 10f64.powf(f64::from(blue - red) / 400.0));
+        let score = match result {
+            GameResult::RedWin => 1.0,
+            GameResult::BlueWin => 0.0,
+            GameResult::Draw => 0.5,
+        };
+        let delta = (K_FACTOR * (score - expected)).round() as i32;
+        let mut new = [0; 2];
+        for (i, (name, change)) in names.into_iter().zip([delta, -delta]).enumerate() {
+            let entry = ratings
+                .entry(name.to_string())
+                .or_insert_with(|| Rating::new(name));
+            entry.rating += change;
+            entry.games += 1;
+            match (result, i) {
+                (GameResult::Draw, _) => entry.draws += 1,
+                (GameResult::RedWin, 0) | (GameResult::BlueWin, 1) => entry.wins += 1,
+                _ => entry.losses += 1,
+            }
+            new[i] = entry.rating;
+        }
+        let mut results = self.results.lock().unwrap();
+        let [red, blue] = names.map(str::to_string);
+        let red_score = results.entry((red.clone(), blue.clone())).or_default();
+        match result {
+            GameResult::RedWin => red_score.wins += 1,
+            GameResult::BlueWin => red_score.losses += 1,
+            GameResult::Draw => red_score.draws += 1,
+        }
+        let flipped = red_score.flipped();
+        results.insert((blue, red), flipped);
+        new
+    }
+
+    /// Games between `a` and `b`, from `a`'s side.
+    fn head_to_head(&self, a: &str, b: &str) -> Score {
+        let results = self.results.lock().unwrap();
+        results
+            .get(&(a.to_string(), b.to_string()))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Take the longest-waiting bot not called `name`, dropping waiters whose
+    /// connection has gone.
+    fn take_waiter(&self, name: &str) -> Option<Waiter> {
+        let mut waiting = self.waiting.lock().unwrap();
+        waiting.retain(|waiter| !waiter.opponent.is_closed());
+        let index = waiting.iter().position(|waiter| waiter.name != name)?;
+        Some(waiting.remove(index))
+    }
+}
+
+/// Why a game ended.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Finish {
+    /// Four in a row, or a full board.
+    Board,
+    Time,
+    IllegalMove,
+    Disconnect,
+    /// The engine gave up a lost game.
+    Resignation,
+    /// The bot accepted the engine's draw offer.
+    Agreement,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ArenaMessage {
+    /// No opponent yet; the game starts when one connects.
+    Waiting,
+    Start {
+        game: String,
+        color: Player,
+        opponent: String,
+        opponent_rating: i32,
+        time_control: TimeControl,
+    },
+    /// Your move: answer with a `move` message before your time runs out.
+    Turn {
+        history: String,
+        clock: Clock,
+        /// The opponent offers a draw: answer `accept_draw` to take it.
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        draw_offered: bool,
+    },
+    Over {
+        game: String,
+        result: GameResult,
+        reason: Finish,
+        history: String,
+        /// Your rating after this game.
+        rating: i32,
+    },
+    Error {
+        message: String,
+    },
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum BotMessage {
+    Move {
+        column: usize,
+    },
+    /// Only while a draw is on offer.
+    AcceptDraw,
+}
+
+struct Bot {
+    name: String,
+    /// Boxed so seats stay small while they are moved between tasks.
+    socket: Box<WebSocket>,
+}
+
+enum Seat {
+    Bot(Bot),
+    /// The engine at a level, and its outlook on the game so far.
+    Engine(u8, ResignTracker),
+}
+
+impl Seat {
+    fn name(&self) -> String {
+        match self {
+            Self::Bot(bot) => bot.name.clone(),
+            Self::Engine(level, _) => format!("engine-{level}"),
+        }
+    }
+
+    fn level(&self) -> Option<u8> {
+        match self {
+            Self::Bot(_) => None,
+            Self::Engine(level, _) => Some(*level),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(crate) struct ArenaQuery {
+    /// Name the bot is rated under: letters, digits, `-` and `_`.
+    name: String,
+    /// Play the built-in engine at this level instead of another bot.
+    engine: Option<u8>,
+}
+
+/// Enter the arena: a WebSocket of `BotMessage`s in and `ArenaMessage`s out.
+#[utoipa::path(
+    get,
+    path = "/api/v1/arena/ws",
+    tag = "arena",
+    params(ArenaQuery),
+    responses(
+        (status = 101, description = "Switching to the WebSocket protocol"),
+        (status = 400, description = "Bad bot name or engine level", body = ErrorBody),
+        (status = 401, description = "No API key, or an unknown one", body = ErrorBody),
+        (status = 403, description = "The name belongs to another API key", body = ErrorBody),
+    ),
+)]
+pub(crate) async fn arena_socket(
+    State(app): State<AppState>,
+    Extension(tier): Extension<Tier>,
+    ApiQuery(query): ApiQuery<ArenaQuery>,
+    ws: WebSocketUpgrade,
+) -> Result<Response, ApiError> {
+    let valid_name = !query.name.is_empty()
+        && query.name.len() <= MAX_NAME_LEN
+        && !query.name.starts_with("engine")
+        && query
+            .name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if !valid_name {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            format!(
+                "bot names are 1-{MAX_NAME_LEN} letters, digits, '-' or '_' and may not start with \"engine\""
+            ),
+        ));
+    }
+    if let Some(level) = query.engine {
+        if !(1..=15).contains(&level) {
+            return Err(GameError::DepthOutOfRange(level).into());
+        }
+        app.check_level(&tier, level)?;
+    }
+    let Some(key) = &tier.key else {
+        return Err(ApiError::new(
+            StatusCode::UNAUTHORIZED,
+            "rated arena play needs an API key",
+        ));
+    };
+    if !app.arena.claim(&query.name, key) {
+        return Err(ApiError::new(
+            StatusCode::FORBIDDEN,
+            format!("bot name {} belongs to another API key", query.name),
+        ));
+    }
+    let ArenaQuery { name, engine } = query;
+    Ok(ws.on_upgrade(move |socket| {
+        run_bot(
+            app,
+            Bot {
+                name,
+                socket: Box::new(socket),
+            },
+            engine,
+        )
+    }))
+}
+
+/// Ratings of every bot and engine level that has played, best first.
+#[utoipa::path(
+    get,
+    path = "/api/v1/arena/ratings",
+    tag = "arena",
+    responses((status = 200, body = [Rating])),
+)]
+pub(crate) async fn list_ratings(State(app): State<AppState>) -> Json<Vec<Rating>> {
+    let mut ratings: Vec<Rating> = app
+        .arena
+        .ratings
+        .lock()
+        .unwrap()
+        .values()
+        .cloned()
+        .collect();
+    ratings.sort_by(|a, b| b.rating.cmp(&a.rating).then_with(|| a.name.cmp(&b.name)));
+    Json(ratings)
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(crate) struct SprtQuery {
+    /// The bot or `engine-<level>` under test.
+    candidate: String,
+    /// The player it is measured against.
+    baseline: String,
+    /// Elo gain of the null hypothesis.
+    #[serde(default)]
+    #[param(default = 0.0)]
+    elo0: f64,
+    /// Elo gain the test should detect.
+    #[serde(default = "default_elo1")]
+    #[param(default = 5.0)]
+    elo1: f64,
+}
+
+fn default_elo1() -> f64 {
+    5.0
+}
+
+/// Games between two arena players and what a test of their difference
+/// concludes.
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct HeadToHead {
+    pub candidate: String,
+    pub baseline: String,
+    /// From the candidate's side.
+    pub score: Score,
+    /// Elo difference with the half-width of its 95% interval; absent until
+    /// both sides have scored.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub elo: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub elo_margin: Option<f64>,
+    pub sprt: SprtStatus,
+    /// `sprt` in words, such as `LLR 3.01 (-2.94, 2.94): change accepted at
+    /// elo0=0, elo1=5`.
+    pub summary: String,
+}
+
+/// Sequential probability ratio test (5% error rates) of "the candidate is
+/// `elo1` stronger than the baseline" against "only `elo0` stronger", over
+/// their arena games so far.
+#[utoipa::path(
+    get,
+    path = "/api/v1/arena/sprt",
+    tag = "arena",
+    params(SprtQuery),
+    responses(
+        (status = 200, body = HeadToHead),
+        (status = 400, description = "`elo0` is not below `elo1`", body = ErrorBody),
+    ),
+)]
+pub(crate) async fn arena_sprt(
+    State(app): State<AppState>,
+    ApiQuery(query): ApiQuery<SprtQuery>,
+) -> Result<Json<HeadToHead>, ApiError> {
+    if query.elo0.partial_cmp(&query.elo1) != Some(std::cmp::Ordering::Less) {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "elo0 must be below elo1",
+        ));
+    }
+    let score = app.arena.head_to_head(&query.candidate, &query.baseline);
+    let sprt = Sprt::new(query.elo0, query.elo1).status(&score);
+    let elo = score.elo();
+    Ok(Json(HeadToHead {
+        candidate: query.candidate,
+        baseline: query.baseline,
+        score,
+        elo: elo.map(|(elo, _)| elo),
+        elo_margin: elo.map(|(_, margin)| margin),
+        summary: sprt.to_string(),
+        sprt,
+    }))
+}
+
+/// How a bot got into its next game.
+enum Pairing {
+    /// This task runs the game; `mine` is the bot's seat, and the opponent
+    /// goes back to its own task on `back` afterwards.
+    Host {
+        seats: [Seat; 2],
+        mine: usize,
+        back: oneshot::Sender<Bot>,
+    },
+    /// Another task ran a game with this bot and handed it back.
+    Returned(Bot),
+    /// The bot disconnected.
+    Gone,
+}
+
+/// Play games with one bot until it disconnects.
+async fn run_bot(app: AppState, mut bot: Bot, engine: Option<u8>) {
+    let mut red = coin_flip();
+    loop {
+        let (seats, mine, back) = match engine {
+            Some(level) => {
+                red = !red;
+                let engine = Seat::Engine(level, ResignTracker::new(app.config.resign));
+                let seats = if red {
+                    [Seat::Bot(bot), engine]
+                } else {
+                    [engine, Seat::Bot(bot)]
+                };
+                (seats, usize::from(!red), None)
+            }
+            None => match pair(&app, bot).await {
+                Pairing::Host { seats, mine, back } => (seats, mine, Some(back)),
+                Pairing::Returned(returned) => {
+                    bot = returned;
+                    continue;
+                }
+                Pairing::Gone => return,
+            },
+        };
+        let mut survivors = play(&app, seats).await;
+        if let (Some(back), Some(opponent)) = (back, survivors[1 - mine].take()) {
+            // The opponent's task is gone if it was cancelled; nothing to do.
+            let _ = back.send(opponent);
+        }
+        match survivors[mine].take() {
+            Some(survivor) => bot = survivor,
+            None => return,
+        }
+    }
+}
+
+/// Join a waiting bot, or wait for one to join.
+async fn pair(app: &AppState, bot: Bot) -> Pairing {
+    let (back, returned) = oneshot::channel();
+    let mut offer = (bot, back);
+    while let Some(waiter) = app.arena.take_waiter(&offer.0.name) {
+        match waiter.opponent.send(offer) {
+            Ok(()) => {
+                return match returned.await {
+                    Ok(bot) => Pairing::Returned(bot),
+                    Err(_) => Pairing::Gone,
+                }
+            }
+            // It disconnected just now; try the next one.
+            Err(taken_back) => offer = taken_back,
+        }
+    }
+
+    let (mut bot, _) = offer;
+    let (opponent, mut arrived) = oneshot::channel();
+    app.arena.waiting.lock().unwrap().push(Waiter {
+        name: bot.name.clone(),
+        opponent,
+    });
+    if send(&mut bot.socket, &ArenaMessage::Waiting).await.is_err() {
+        return Pairing::Gone;
+    }
+    loop {
+        tokio::select! {
+            opponent = &mut arrived => {
+                let Ok((opponent, back)) = opponent else {
+                    return Pairing::Gone;
+                };
+                let mine = usize::from(coin_flip());
+                let seats = if mine == 0 {
+                    [Seat::Bot(bot), Seat::Bot(opponent)]
+                } else {
+                    [Seat::Bot(opponent), Seat::Bot(bot)]
+                };
+                return Pairing::Host { seats, mine, back };
+            }
+            incoming = bot.socket.recv() => {
+                if !answer_idle(&mut bot.socket, incoming, "no game in progress").await {
+                    // Dropping `arrived` closes the waiter, so nobody pairs with it.
+                    return Pairing::Gone;
+                }
+            }
+        }
+    }
+}
+
+/// Handle a message from a bot that is not expected to move. Returns `false`
+/// once the bot has disconnected.
+async fn answer_idle(
+    socket: &mut WebSocket,
+    incoming: Option<Result<Message, axum::Error>>,
+    reason: &str,
+) -> bool {
+    match incoming {
+        Some(Ok(Message::Text(_))) => {
+            let message = ArenaMessage::Error {
+                message: reason.to_string(),
+            };
+            send(socket, &message).await.is_ok()
+        }
+        Some(Ok(Message::Close(_))) | Some(Err(_)) | None => false,
+        Some(Ok(_)) => true,
+    }
+}
+
+/// Referee one game and return the bots still connected, Red then Blue.
+async fn play(app: &AppState, mut seats: [Seat; 2]) -> [Option<Bot>; 2] {
+    let game = new_id();
+    let names = [seats[0].name(), seats[1].name()];
+    let control = app.config.arena.time_control;
+    let mut connected = [true; 2];
+    for (i, seat) in seats.iter_mut().enumerate() {
+        if let Seat::Bot(bot) = seat {
+            let start = ArenaMessage::Start {
+                game: game.clone(),
+                color: [Player::Red, Player::Blue][i],
+                opponent: names[1 - i].clone(),
+                opponent_rating: app.arena.rating(&names[1 - i]),
+                time_control: control,
+            };
+            connected[i] = send(&mut bot.socket, &start).await.is_ok();
+        }
+    }
+
+    let started_at = unix_now();
+    let mut state = GameState::empty(Player::Red);
+    let mut moves = Vec::new();
+    let mut clock = GameClock::new(Some(control));
+    clock.start(Player::Red);
+    // Whether the last move came with a draw offer.
+    let mut draw_offered = false;
+    let (result, reason) = loop {
+        if let Some(loser) = [Player::Red, Player::Blue]
+            .into_iter()
+            .find(|&player| !connected[player as usize])
+        {
+            break (GameResult::won_by(loser.opponent()), Finish::Disconnect);
+        }
+        let player = state.to_move();
+        let history = format_history(&moves);
+        let column = match turn(app, &mut seats, &state, history, &clock, draw_offered).await {
+            Ok(Action::Move {
+                column,
+                offers_draw,
+            }) => {
+                draw_offered = offers_draw;
+                column
+            }
+            Ok(Action::AcceptDraw) => break (GameResult::Draw, Finish::Agreement),
+            Err((loser, reason)) => {
+                if reason == Finish::Disconnect {
+                    connected[loser as usize] = false;
+                }
+                break (GameResult::won_by(loser.opponent()), reason);
+            }
+        };
+        if state.play(column).is_err() {
+            break (GameResult::won_by(player.opponent()), Finish::IllegalMove);
+        }
+        moves.push(TypedMove { player, column });
+        if let Some(result) = GameResult::of(&state) {
+            break (result, Finish::Board);
+        }
+        clock.moved(player);
+    };
+    clock.stop();
+
+    let history = format_history(&moves);
+    let ratings = app.arena.rate([&names[0], &names[1]], result);
+    app.record_game(&GameRecord {
+        id: game.clone(),
+        history: history.clone(),
+        result,
+        red_level: seats[0].level(),
+        blue_level: seats[1].level(),
+        started_at,
+        finished_at: unix_now(),
+        engine: seats
+            .iter()
+            .any(|seat| seat.level().is_some())
+            .then(|| engine_info().to_string()),
+        hints: None,
+        player: None,
+        clock: Some(clock.reading()),
+    });
+    let mut survivors = [None, None];
+    for (i, seat) in seats.into_iter().enumerate() {
+        let Seat::Bot(mut bot) = seat else { continue };
+        let over = ArenaMessage::Over {
+            game: game.clone(),
+            result,
+            reason,
+            history: history.clone(),
+            rating: ratings[i],
+        };
+        if connected[i] && send(&mut bot.socket, &over).await.is_ok() {
+            survivors[i] = Some(bot);
+        }
+    }
+    survivors
+}
+
+/// Colours are drawn at random so neither bot always moves first.
+fn coin_flip() -> bool {
+    u128::from_str_radix(&new_id(), 16).expect("ids are hex") & 1 == 1
+}
+
+/// What the side to move did with its turn.
+enum Action {
+    Move { column: usize, offers_draw: bool },
+    AcceptDraw,
+}
+
+/// Get the next move of the side to move in `state` within its time. An
+/// error names the side that lost and why; a bot that drops out while
+/// waiting for its turn loses too.
+async fn turn(
+    app: &AppState,
+    seats: &mut [Seat; 2],
+    state: &GameState,
+    history: String,
+    clock: &GameClock,
+    draw_offered: bool,
+) -> Result<Action, (Player, Finish)> {
+    let player = state.to_move();
+    let left = clock.left(player).expect("arena games are timed");
+    let [red, blue] = seats;
+    let (mover, other) = match player {
+        Player::Red => (red, blue),
+        Player::Blue => (blue, red),
+    };
+    let idle = async {
+        let Seat::Bot(bot) = other else {
+            return future::pending().await;
+        };
+        loop {
+            let incoming = bot.socket.recv().await;
+            if !answer_idle(&mut bot.socket, incoming, "it is not your turn").await {
+                return;
+            }
+        }
+    };
+    let moved = async {
+        match mover {
+            Seat::Bot(bot) => bot_move(bot, history, clock.reading(), draw_offered).await,
+            Seat::Engine(level, resign) => {
+                let manager = app.config.engine.time_manager();
+                let limits = clock
+                    .engine_limits(player, &manager)
+                    .expect("arena games are timed");
+                let (mut reply, last) = engine_move(app, history, *level, limits).await?;
+                resign.observe(state, &last, &mut reply);
+                if reply.should_resign {
+                    return Err(Finish::Resignation);
+                }
+                Ok(Action::Move {
+                    column: reply.column,
+                    offers_draw: reply.offers_draw,
+                })
+            }
+        }
+    };
+    tokio::select! {
+        moved = tokio::time::timeout(left, moved) => match moved {
+            Ok(Ok(action)) => Ok(action),
+            Ok(Err(reason)) => Err((player, reason)),
+            Err(_) => Err((player, Finish::Time)),
+        },
+        () = idle => Err((player.opponent(), Finish::Disconnect)),
+    }
+}
+
+async fn bot_move(
+    bot: &mut Bot,
+    history: String,
+    clock: Clock,
+    draw_offered: bool,
+) -> Result<Action, Finish> {
+    let turn = ArenaMessage::Turn {
+        history,
+        clock,
+        draw_offered,
+    };
+    send(&mut bot.socket, &turn)
+        .await
+        .map_err(|_| Finish::Disconnect)?;
+    loop {
+        let text = match bot.socket.recv().await {
+            Some(Ok(Message::Text(text))) => text,
+            Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return Err(Finish::Disconnect),
+            Some(Ok(_)) => continue,
+        };
+        let message = match serde_json::from_str::<BotMessage>(&text) {
+            Ok(BotMessage::Move { column }) => {
+                return Ok(Action::Move {
+                    column,
+                    offers_draw: false,
+                })
+            }
+            Ok(BotMessage::AcceptDraw) if draw_offered => return Ok(Action::AcceptDraw),
+            Ok(BotMessage::AcceptDraw) => "no draw is on offer".to_string(),
+            Err(err) => format!("invalid message: {err}"),
+        };
+        send(&mut bot.socket, &ArenaMessage::Error { message })
+            .await
+            .map_err(|_| Finish::Disconnect)?;
+    }
+}
+
+/// The engine's move on the shared search pool, waiting for a free slot on
+/// its own clock, and its deepest completed iteration. A failed search
+/// forfeits like a dropped bot.
+async fn engine_move(
+    app: &AppState,
+    position: String,
+    level: u8,
+    limits: connect4::SearchLimits,
+) -> Result<(MoveResponse, SearchInfo), Finish> {
+    loop {
+        let request = MoveRequest {
+            position: position.clone(),
+            level,
+            first_player: None,
+        };
+        let limits = app.with_table(limits.clone());
+        let search = app.search_pool.run(level, move || {
+            let mut last = None;
+            let reply = best_move_within(request, &limits, |info| last = Some(info.clone()))?;
+            Ok((
+                reply,
+                last.expect("every returned move completed an iteration"),
+            ))
+        });
+        match search.await {
+            Ok(searched) => return Ok(searched),
+            Err(err) if err.status == StatusCode::SERVICE_UNAVAILABLE => {
+                tokio::time::sleep(BUSY_RETRY).await;
+            }
+            Err(err) => {
+                warn!("arena engine search failed: {err}");
+                return Err(Finish::Disconnect);
+            }
+        }
+    }
+}
+
+async fn send(socket: &mut WebSocket, message: &ArenaMessage) -> Result<(), axum::Error> {
+    let text = serde_json::to_string(message).expect("arena messages serialize");
+    socket.send(Message::Text(text)).await
+}
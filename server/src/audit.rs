@@ -0,0 +1,194 @@
+//! Append-only JSONL log of finished games, kept apart from tracing output.
+//!
+//! Every game the server archives is also written here as one JSON object per
+//! line: its moves, result, who played, the engine and the time each side
+//! spent. Unlike the archive it needs no database, and unlike the logs it is
+//! meant to be read by scripts. Once the file would grow past `max_mb` it is
+//! renamed to `<path>.1`, older files shifting up to `<path>.<keep>`, and a
+//! fresh file started.
+use std::{
+    fs::{self, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+use crate::storage::{GameRecord, GameResult};
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuditConfig {
+    /// JSONL file to append to; no audit log when unset.
+    pub path: Option<PathBuf>,
+    /// Size at which the file is rotated, in MiB.
+    pub max_mb: u64,
+    /// Rotated files kept besides the current one.
+    pub keep: usize,
+}
+
+impl Default for AuditConfig {
+    fn default() -> Self {
+        Self {
+            path: None,
+            max_mb: 64,
+            keep: 5,
+        }
+    }
+}
+
+/// One line of the log.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub id: String,
+    pub history: String,
+    /// Columns played, from the first move.
+    pub moves: Vec<usize>,
+    pub result: GameResult,
+    pub red_level: Option<u8>,
+    pub blue_level: Option<u8>,
+    pub engine: Option<String>,
+    /// Per-search time budget the server was configured with.
+    pub max_search_ms: u64,
+    pub started_at: u64,
+    pub finished_at: u64,
+    /// Thinking time each side used, when the game was clocked.
+    pub red_ms: Option<u64>,
+    pub blue_ms: Option<u64>,
+    pub hints: Option<u32>,
+}
+
+impl AuditEntry {
+    pub fn new(game: &GameRecord, max_search_ms: u64) -> Self {
+        let moves = connect4::parse_history(&game.history)
+            .map(|moves| moves.iter().map(|m| m.column).collect())
+            .unwrap_or_default();
+        Self {
+            id: game.id.clone(),
+            history: game.history.clone(),
+            moves,
+            result: game.result,
+            red_level: game.red_level,
+            blue_level: game.blue_level,
+            engine: game.engine.clone(),
+            max_search_ms,
+            started_at: game.started_at,
+            finished_at: game.finished_at,
+            red_ms: game.clock.map(|clock| clock.red_ms),
+            blue_ms: game.clock.map(|clock| clock.blue_ms),
+            hints: game.hints,
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct AuditLog {
+    config: Arc<AuditConfig>,
+    /// Serializes appends and rotations.
+    lock: Arc<Mutex<()>>,
+}
+
+impl AuditLog {
+    pub fn new(config: AuditConfig) -> Self {
+        Self {
+            config: Arc::new(config),
+            lock: Arc::default(),
+        }
+    }
+
+    /// Append one entry, rotating first if it would not fit. A no-op without
+    /// a configured path.
+    pub fn append(&self, entry: &AuditEntry) -> anyhow::Result<()> {
+        let Some(path) = &self.config.path else {
+            return Ok(());
+        };
+        let mut line = serde_json::to_vec(entry)?;
+        line.push(b'\n');
+        let _guard = self.lock.lock().unwrap();
+        let size = fs::metadata(path).map_or(0, |meta| meta.len());
+        if size > 0 && size + line.len() as u64 > self.config.max_mb << 20 {
+            rotate(path, self.config.keep)
+                .with_context(|| format!("cannot rotate {}", path.display()))?;
+        }
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .and_then(|mut file| file.write_all(&line))
+            .with_context(|| format!("cannot append to {}", path.display()))
+    }
+}
+
+/// `<path>.<n>`, the n-th most recent rotated file.
+fn rotated(path: &Path, n: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{n}"));
+    PathBuf::from(name)
+}
+
+fn rotate(path: &Path, keep: usize) -> std::io::Result<()> {
+    if keep == 0 {
+        return fs::remove_file(path);
+    }
+    match fs::remove_file(rotated(path, keep)) {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(err),
+        _ => {}
+    }
+    for n in (1..keep).rev() {
+        let from = rotated(path, n);
+        if from.exists() {
+            fs::rename(from, rotated(path, n + 1))?;
+        }
+    }
+    fs::rename(path, rotated(path, 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::GameClock;
+
+    #[test]
+    fn entries_are_appended_and_rotated() {
+        let dir = std::env::temp_dir().join(format!("connect4-audit-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("games.jsonl");
+        let log = AuditLog::new(AuditConfig {
+            path: Some(path.clone()),
+            max_mb: 0,
+            keep: 2,
+        });
+        let game = GameRecord {
+            id: "g".to_string(),
+            history: "R3B3R2".to_string(),
+            result: GameResult::RedWin,
+            red_level: None,
+            blue_level: Some(4),
+            started_at: 1,
+            finished_at: 2,
+            engine: Some("connect4 test".to_string()),
+            hints: Some(1),
+            player: Some("secret".to_string()),
+            clock: Some(GameClock::new(None).reading()),
+        };
+        let entry = AuditEntry::new(&game, 500);
+        assert_eq!(entry.moves, vec![3, 3, 2]);
+        assert_eq!(entry.red_ms, Some(0));
+        for _ in 0..4 {
+            log.append(&entry).unwrap();
+        }
+
+        // A zero-sized limit rotates before every append but the first.
+        let text = fs::read_to_string(&path).unwrap();
+        assert_eq!(text.lines().count(), 1);
+        assert!(!text.contains("secret"));
+        let parsed: AuditEntry = serde_json::from_str(text.trim()).unwrap();
+        assert_eq!(parsed, entry);
+        assert!(rotated(&path, 1).exists() && rotated(&path, 2).exists());
+        assert!(!rotated(&path, 3).exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,249 @@
+//! API keys and the limits that come with them.
+//!
+//! A request may present a key as `Authorization: Bearer <key>` or in an
+//! `X-Api-Key` header. Keys are looked up in the config file first, then in
+//! storage. An unknown key is refused with `401` rather than treated as
+//! anonymous, so a partner with a typo notices at once instead of quietly
+//! getting shallow answers; each refusal is charged to the client's IP, which
+//! gets `429` once it has made too many. Requests without a key get the
+//! anonymous tier.
+//!
+//! Keys are compared by their SHA-256 digest, never as plaintext, and
+//! storage only ever holds the digest: see [`hash_key`].
+//!
+//! The resolved [`Tier`] rides along as a request extension: handlers read its
+//! level and time ceilings, and the rate limiter gives each key a bucket of
+//! its own instead of sharing one per IP.
+use std::time::Duration;
+
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderMap, StatusCode},
+    middleware::Next,
+    response::Response,
+};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use tracing::warn;
+
+use crate::{config::Config, rate_limit::RateLimitConfig, ApiError, AppState};
+
+pub const API_KEY_HEADER: &str = "x-api-key";
+
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    /// Limits for requests that present no key.
+    pub anonymous: TierLimits,
+    pub keys: Vec<ApiKey>,
+}
+
+/// Ceilings for one tier. Unset fields fall back to the server-wide values,
+/// which also cap whatever is set here.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TierLimits {
+    pub max_level: Option<u8>,
+    pub max_search_ms: Option<u64>,
+    /// `trust_forwarded_for` is always taken from the top-level `[rate_limit]`.
+    pub rate_limit: Option<RateLimitConfig>,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ApiKey {
+    /// The key itself in the config file; in storage, its `hash_key` digest.
+    pub key: String,
+    /// Appears in logs and names the rate-limit bucket; the key itself never does.
+    pub name: String,
+    #[serde(default)]
+    pub limits: TierLimits,
+    /// May call administrative endpoints such as `/api/selfplay`.
+    #[serde(default)]
+    pub admin: bool,
+}
+
+/// What the current request may ask of the engine.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Tier {
+    /// Name of the presented key; `None` for anonymous requests.
+    pub key: Option<String>,
+    pub max_level: u8,
+    pub max_search: Duration,
+    pub rate_limit: RateLimitConfig,
+    pub admin: bool,
+}
+
+impl Tier {
+    pub fn anonymous(config: &Config) -> Self {
+        Self::new(config, None, &config.auth.anonymous)
+    }
+
+    pub fn for_key(config: &Config, key: &ApiKey) -> Self {
+        Self {
+            admin: key.admin,
+            ..Self::new(config, Some(key.name.clone()), &key.limits)
+        }
+    }
+
+    /// The server-wide limits, for local callers that present no key
+    /// (JSON-RPC over stdio or a socket).
+    pub fn local(config: &Config) -> Self {
+        Self::new(config, Some("local".to_string()), &TierLimits::default())
+    }
+
+    /// Gate for administrative endpoints.
+    pub fn require_admin(&self) -> Result<(), ApiError> {
+        if self.admin {
+            return Ok(());
+        }
+        Err(ApiError::new(
+            StatusCode::FORBIDDEN,
+            "this endpoint needs an admin API key",
+        ))
+    }
+
+    fn new(config: &Config, key: Option<String>, limits: &TierLimits) -> Self {
+        let max_level = limits
+            .max_level
+            .map_or(config.max_level, |level| level.min(config.max_level));
+        let max_search = limits.max_search_ms.map_or(config.max_search(), |ms| {
+            Duration::from_millis(ms).min(config.max_search())
+        });
+        Self {
+            key,
+            max_level,
+            max_search,
+            rate_limit: limits.rate_limit.unwrap_or(config.rate_limit),
+            admin: false,
+        }
+    }
+}
+
+/// A fresh secret: 32 bytes from the operating system's CSPRNG, in hex. For
+/// API keys and anything else that proves who holds it; IDs that are merely
+/// hard to guess come from `sessions::new_id`.
+pub fn new_secret() -> String {
+    let mut bytes = [0; 32];
+    getrandom::getrandom(&mut bytes).expect("the operating system has a random source");
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// What stands in for a key wherever it is compared or stored: its SHA-256,
+/// in hex. Keys come from [`new_secret`], so the digest needs no salt, and a
+/// lookup that takes longer for a longer matching prefix only leaks the
+/// digest's.
+pub fn hash_key(key: &str) -> String {
+    Sha256::digest(key.as_bytes())
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+fn presented_key(headers: &HeaderMap) -> Option<&str> {
+    let bearer = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    let header = || {
+        headers
+            .get(API_KEY_HEADER)
+            .and_then(|value| value.to_str().ok())
+    };
+    bearer.or_else(header).map(str::trim)
+}
+
+/// Middleware resolving the request's [`Tier`].
+pub async fn authenticate(
+    State(app): State<AppState>,
+    mut req: Request,
+    next: Next,
+) -> Result<Response, ApiError> {
+    let tier = match presented_key(req.headers()) {
+        None => Tier::anonymous(&app.config),
+        Some(presented) => {
+            if let Some(refused) = app.auth_failures.refuse(&req) {
+                return Ok(refused);
+            }
+            let digest = hash_key(presented);
+            let key = app
+                .config
+                .auth
+                .keys
+                .iter()
+                .find(|k| hash_key(&k.key) == digest);
+            let key = match key {
+                Some(key) => Some(key.clone()),
+                None => app.storage.api_key(&digest).unwrap_or_else(|err| {
+                    warn!("API key lookup failed: {err:#}");
+                    None
+                }),
+            };
+            let Some(key) = key else {
+                app.auth_failures.charge(&req);
+                return Err(ApiError {
+                    code: "invalid_api_key",
+                    ..ApiError::new(StatusCode::UNAUTHORIZED, "unknown API key")
+                });
+            };
+            Tier::for_key(&app.config, &key)
+        }
+    };
+    req.extensions_mut().insert(tier);
+    Ok(next.run(req).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tiers_are_capped_by_the_server_limits() {
+        let config = Config {
+            max_level: 12,
+            ..Config::default()
+        };
+        let key = ApiKey {
+            key: "secret".to_string(),
+            name: "partner".to_string(),
+            limits: TierLimits {
+                max_level: Some(15),
+                max_search_ms: Some(2_000),
+                rate_limit: None,
+            },
+            admin: false,
+        };
+        let tier = Tier::for_key(&config, &key);
+        assert_eq!(tier.key.as_deref(), Some("partner"));
+        assert_eq!(tier.max_level, 12);
+        assert_eq!(tier.max_search, Duration::from_secs(2));
+        assert_eq!(tier.rate_limit, config.rate_limit);
+        assert_eq!(Tier::anonymous(&config).max_level, 12);
+    }
+
+    #[test]
+    fn keys_hash_to_hex_sha256() {
+        assert_eq!(
+            hash_key("abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
+    #[test]
+    fn secrets_are_32_random_bytes() {
+        let (a, b) = (new_secret(), new_secret());
+        assert_eq!(a.len(), 64);
+        assert!(a.bytes().all(|c| c.is_ascii_hexdigit()));
+        assert_ne!(a, b);
+    }
+
+    #[test]
+    fn keys_are_read_from_either_header() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(presented_key(&headers), None);
+        headers.insert(API_KEY_HEADER, "plain".parse().unwrap());
+        assert_eq!(presented_key(&headers), Some("plain"));
+        headers.insert(header::AUTHORIZATION, "Bearer bearer".parse().unwrap());
+        assert_eq!(presented_key(&headers), Some("bearer"));
+    }
+}
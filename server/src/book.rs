@@ -0,0 +1,324 @@
+//! The opening book, read from disk and swapped for a new one when the file
+//! changes.
+//!
+//! The file is polled every `poll_secs` for a new modification time or size.
+//! A changed file is parsed in full before it replaces the book in use, so
+//! searches see either the old book or the new one, never a mix; a file that
+//! does not parse leaves the old book in place and is reported by
+//! `GET /admin/book/info` until a later change fixes it. A book that fails to
+//! load at startup stops the server, like any other configuration error.
+//!
+//! Admin edits (`/admin/book/entries`) change a copy of the book, replace the
+//! file with it and swap the copy in; edits and reloads take turns, so an
+//! edit is never lost to a reload of the file it is replacing. An edit first
+//! reads the file again if it changed since the last poll, so a change made
+//! on disk is not lost to the edit either; a changed file that does not
+//! parse refuses the edit with `409`.
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, SystemTime},
+};
+
+use axum::http::StatusCode;
+use connect4::{
+    book::{BookError, BookMove, OpeningBook},
+    GameState,
+};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+use utoipa::ToSchema;
+
+use crate::{error::ApiError, storage::unix_now};
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BookConfig {
+    /// `c4book` file answering opening positions; no book when unset.
+    pub path: Option<PathBuf>,
+    /// How often the file is checked for changes; 0 loads it only at startup.
+    pub poll_secs: u64,
+}
+
+impl Default for BookConfig {
+    fn default() -> Self {
+        Self {
+            path: None,
+            poll_secs: 5,
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct BookStore {
+    config: Arc<BookConfig>,
+    loaded: Arc<RwLock<Loaded>>,
+    /// Held by edits and reloads, which both end by replacing the book.
+    writer: Arc<Mutex<()>>,
+}
+
+#[derive(Default)]
+struct Loaded {
+    book: Option<Arc<OpeningBook>>,
+    /// Modification time and size of the file last read, parsed or not.
+    stamp: Option<(SystemTime, u64)>,
+    loaded_at: Option<u64>,
+    last_error: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct BookInfo {
+    pub path: Option<String>,
+    /// From the book's header; absent while no book is loaded.
+    pub version: Option<String>,
+    /// FNV-1a of the file, 16 hex digits.
+    pub hash: Option<String>,
+    pub entries: usize,
+    /// Distinct positions, mirror images counted once.
+    pub positions: usize,
+    /// Unix time the book in use was read.
+    pub loaded_at: Option<u64>,
+    /// Why the latest version of the file was not loaded.
+    pub last_error: Option<String>,
+}
+
+impl BookStore {
+    pub fn new(config: &BookConfig) -> Self {
+        Self {
+            config: Arc::new(config.clone()),
+            loaded: Arc::default(),
+            writer: Arc::default(),
+        }
+    }
+
+    /// The configured book, read now; errors are for startup to report.
+    pub fn load(&self) -> anyhow::Result<()> {
+        let Some(path) = &self.config.path else {
+            return Ok(());
+        };
+        self.reload()
+            .map(drop)
+            .map_err(|err| anyhow::anyhow!("{}: {err}", path.display()))
+    }
+
+    /// The book in use, for as long as the caller holds it.
+    pub fn current(&self) -> Option<Arc<OpeningBook>> {
+        self.loaded.read().unwrap().book.clone()
+    }
+
+    pub fn probe(&self, state: &GameState) -> Option<BookMove> {
+        self.current()?.probe(state)
+    }
+
+    pub fn info(&self) -> BookInfo {
+        let loaded = self.loaded.read().unwrap();
+        let book = loaded.book.as_deref();
+        BookInfo {
+            path: self
+                .config
+                .path
+                .as_ref()
+                .map(|path| path.display().to_string()),
+            version: book.map(|book| book.version().to_string()),
+            hash: book.map(|book| format!("{:016x}", book.hash())),
+            entries: book.map_or(0, OpeningBook::len),
+            positions: book.map_or(0, OpeningBook::positions),
+            loaded_at: loaded.loaded_at,
+            last_error: loaded.last_error.clone(),
+        }
+    }
+
+    /// Read the file again if it changed since the last read. True when a new
+    /// book is now in use.
+    pub fn reload_if_changed(&self) -> bool {
+        let Some(path) = &self.config.path else {
+            return false;
+        };
+        let stamp = file_stamp(path);
+        if stamp.is_none() || stamp == self.loaded.read().unwrap().stamp {
+            return false;
+        }
+        match self.reload() {
+            Ok(book) => {
+                info!(
+                    path = %path.display(),
+                    version = book.version(),
+                    entries = book.len(),
+                    "opening book reloaded"
+                );
+                true
+            }
+            Err(err) => {
+                warn!(path = %path.display(), "opening book not reloaded, keeping the old one: {err}");
+                false
+            }
+        }
+    }
+
+    /// Parse the file and swap it in; on failure the old book stays.
+    fn reload(&self) -> Result<Arc<OpeningBook>, String> {
+        let _writer = self.writer.lock().unwrap();
+        self.read_file()
+    }
+
+    /// `reload` for a caller already holding the writer lock.
+    fn read_file(&self) -> Result<Arc<OpeningBook>, String> {
+        let path = self.config.path.as_ref().expect("reload needs a path");
+        let stamp = file_stamp(path);
+        let parsed = OpeningBook::load(path);
+        let mut loaded = self.loaded.write().unwrap();
+        loaded.stamp = stamp;
+        match parsed {
+            Ok(book) => {
+                let book = Arc::new(book);
+                loaded.book = Some(book.clone());
+                loaded.loaded_at = Some(unix_now());
+                loaded.last_error = None;
+                Ok(book)
+            }
+            Err(err) => {
+                loaded.last_error = Some(err.to_string());
+                Err(err.to_string())
+            }
+        }
+    }
+
+    /// Apply `change` to a copy of the book in use, write the copy to the
+    /// file and put it in use. Nothing changes when `change` fails.
+    pub fn edit<T>(
+        &self,
+        change: impl FnOnce(&mut OpeningBook) -> Result<T, BookError>,
+    ) -> Result<T, ApiError> {
+        let _writer = self.writer.lock().unwrap();
+        if let Some(path) = &self.config.path {
+            if file_stamp(path) != self.loaded.read().unwrap().stamp {
+                self.read_file().map_err(|err| {
+                    ApiError::new(
+                        StatusCode::CONFLICT,
+                        format!("the book file changed and does not load: {err}"),
+                    )
+                })?;
+            }
+        }
+        let (Some(path), Some(current)) = (&self.config.path, self.current()) else {
+            return Err(ApiError::new(
+                StatusCode::CONFLICT,
+                "no opening book is loaded",
+            ));
+        };
+        let mut book = OpeningBook::clone(&current);
+        let result = change(&mut book)
+            .map_err(|err| ApiError::new(StatusCode::UNPROCESSABLE_ENTITY, err.to_string()))?;
+        // Write beside the file and rename over it, so the file is always
+        // a whole book.
+        let mut temp = path.as_os_str().to_owned();
+        temp.push(".tmp");
+        fs::write(&temp, book.to_string())
+            .and_then(|()| fs::rename(&temp, path))
+            .map_err(|err| anyhow::anyhow!("cannot write {}: {err}", path.display()))?;
+        let mut loaded = self.loaded.write().unwrap();
+        loaded.stamp = file_stamp(path);
+        loaded.book = Some(Arc::new(book));
+        loaded.loaded_at = Some(unix_now());
+        loaded.last_error = None;
+        Ok(result)
+    }
+
+    /// Poll the file for changes in the background; nothing to watch without
+    /// a path or with `poll_secs = 0`.
+    pub fn spawn_watcher(&self) -> Option<tokio::task::JoinHandle<()>> {
+        if self.config.path.is_none() || self.config.poll_secs == 0 {
+            return None;
+        }
+        let store = self.clone();
+        let period = Duration::from_secs(self.config.poll_secs);
+        Some(tokio::spawn(async move {
+            let mut tick = tokio::time::interval(period);
+            loop {
+                tick.tick().await;
+                let store = store.clone();
+                let _ = tokio::task::spawn_blocking(move || store.reload_if_changed()).await;
+            }
+        }))
+    }
+}
+
+fn file_stamp(path: &Path) -> Option<(SystemTime, u64)> {
+    let meta = fs::metadata(path).ok()?;
+    Some((meta.modified().ok()?, meta.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn changed_files_are_swapped_in_and_bad_ones_kept_out() {
+        let dir = std::env::temp_dir().join(format!("connect4-book-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("book.txt");
+        fs::write(&path, "c4book 1 v1\n- 3\n").unwrap();
+        let store = BookStore::new(&BookConfig {
+            path: Some(path.clone()),
+            poll_secs: 0,
+        });
+        store.load().unwrap();
+        let start = GameState::from_history(&[]).unwrap();
+        assert_eq!(store.probe(&start).map(|m| m.column), Some(3));
+        assert!(!store.reload_if_changed());
+        let old = store.current().unwrap();
+
+        // A different size is a change even within the clock's resolution.
+        fs::write(&path, "c4book 1 v2\n- 2 5\nR2 3\n").unwrap();
+        assert!(store.reload_if_changed());
+        assert_eq!(store.probe(&start).map(|m| m.column), Some(2));
+        assert_eq!(old.version(), "v1");
+        let info = store.info();
+        assert_eq!((info.version.as_deref(), info.entries), (Some("v2"), 2));
+        assert_eq!(info.hash.unwrap().len(), 16);
+
+        fs::write(&path, "c4book 1 v3\n- 9\n").unwrap();
+        assert!(!store.reload_if_changed());
+        let info = store.info();
+        assert_eq!(info.version.as_deref(), Some("v2"));
+        assert!(info.last_error.unwrap().contains("line 2"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn edits_build_on_changes_the_poller_has_not_seen() {
+        let dir = std::env::temp_dir().join(format!("connect4-book-race-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("book.txt");
+        fs::write(&path, "c4book 1 v1\n- 3\n").unwrap();
+        let store = BookStore::new(&BookConfig {
+            path: Some(path.clone()),
+            poll_secs: 0,
+        });
+        store.load().unwrap();
+
+        // Changed on disk and edited before any poll.
+        fs::write(&path, "c4book 1 v2\n- 3\nR3 3\n").unwrap();
+        let entry = connect4::book::BookEntry {
+            position: "R3B3".to_string(),
+            column: 2,
+            weight: 1,
+            eval: None,
+        };
+        store.edit(|book| book.set_entry(entry)).unwrap();
+        let on_disk = OpeningBook::load(&path).unwrap();
+        assert_eq!(on_disk.version(), "v2");
+        assert_eq!(on_disk.len(), 3);
+        assert_eq!(store.current().unwrap().len(), 3);
+
+        // A changed file that does not parse is not overwritten.
+        fs::write(&path, "c4book 1 v3\n- 9\n").unwrap();
+        let refused = store.edit(|book| book.remove_entry("", 3));
+        assert_eq!(refused.unwrap_err().status, StatusCode::CONFLICT);
+        assert!(fs::read_to_string(&path).unwrap().contains("v3"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
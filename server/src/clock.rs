@@ -0,0 +1,235 @@
+//! Game clocks for sessions and rooms.
+//!
+//! Every game keeps time, so spectators can see how long each side has
+//! thought. A game created with a `time_control` also has a budget: each side
+//! starts with `initial_ms` and gains `increment_ms` after each of its moves,
+//! and a side whose time runs out loses. The server decides timeouts itself,
+//! on the next request touching the game or the ticker below, whichever comes
+//! first, so a client that stops sending moves cannot stall the game.
+use std::time::{Duration, Instant};
+
+use axum::http::StatusCode;
+use connect4::{Player, SearchLimits, TimeManager};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::{ApiError, AppState};
+
+/// How often the ticker looks for flags that fell.
+const TICK: Duration = Duration::from_millis(100);
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct TimeControl {
+    /// Time each side starts with.
+    pub initial_ms: u64,
+    /// Time added after each of a side's moves.
+    #[serde(default)]
+    pub increment_ms: u64,
+}
+
+impl TimeControl {
+    /// Reject a control no game could be played under.
+    pub fn validate(control: Option<Self>) -> Result<(), ApiError> {
+        match control {
+            Some(control) if control.initial_ms == 0 => Err(ApiError::new(
+                StatusCode::BAD_REQUEST,
+                "time_control.initial_ms must be positive",
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// A reading of a game clock, as sent to clients.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct Clock {
+    /// Thinking time used so far.
+    pub red_ms: u64,
+    pub blue_ms: u64,
+    /// Side whose clock is running; `null` before the game starts and once
+    /// it is over.
+    pub running: Option<Player>,
+    pub time_control: Option<TimeControl>,
+    /// Time left under the time control.
+    pub red_left_ms: Option<u64>,
+    pub blue_left_ms: Option<u64>,
+    /// Side that ran out of time and lost.
+    pub flagged: Option<Player>,
+}
+
+impl Clock {
+    /// The reading `elapsed` later, for clients extrapolating between updates.
+    pub fn advanced(mut self, elapsed: Duration) -> Self {
+        let elapsed = elapsed.as_millis() as u64;
+        let (used, left) = match self.running {
+            Some(Player::Red) => (&mut self.red_ms, &mut self.red_left_ms),
+            Some(Player::Blue) => (&mut self.blue_ms, &mut self.blue_left_ms),
+            None => return self,
+        };
+        *used += elapsed;
+        if let Some(left) = left {
+            *left = left.saturating_sub(elapsed);
+        }
+        self
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct GameClock {
+    control: Option<TimeControl>,
+    used: [Duration; 2],
+    moves: [u32; 2],
+    /// Side to move and when its turn began, while the clock runs.
+    running: Option<(Player, Instant)>,
+    flagged: Option<Player>,
+}
+
+impl GameClock {
+    pub fn new(control: Option<TimeControl>) -> Self {
+        Self {
+            control,
+            used: [Duration::ZERO; 2],
+            moves: [0; 2],
+            running: None,
+            flagged: None,
+        }
+    }
+
+    /// Start (or restart) `player`'s turn.
+    pub fn start(&mut self, player: Player) {
+        self.running = Some((player, Instant::now()));
+    }
+
+    /// Stop for good once the game is decided on the board.
+    pub fn stop(&mut self) {
+        self.charge();
+        self.running = None;
+    }
+
+    /// Charge `player` for the move just played and start the opponent's turn.
+    pub fn moved(&mut self, player: Player) {
+        self.charge();
+        self.moves[player as usize] += 1;
+        self.start(player.opponent());
+    }
+
+    fn charge(&mut self) {
+        if let Some((player, since)) = self.running {
+            self.used[player as usize] += since.elapsed();
+        }
+    }
+
+    pub fn flagged(&self) -> Option<Player> {
+        self.flagged
+    }
+
+    /// Time `player` has left, counting a running turn; `None` without a
+    /// time control.
+    pub fn left(&self, player: Player) -> Option<Duration> {
+        let control = self.control?;
+        let budget = Duration::from_millis(control.initial_ms)
+            + Duration::from_millis(control.increment_ms) * self.moves[player as usize];
+        let mut used = self.used[player as usize];
+        if let Some((running, since)) = self.running {
+            if running == player {
+                used += since.elapsed();
+            }
+        }
+        Some(budget.saturating_sub(used))
+    }
+
+    /// Flag the side to move if its time is up. Returns `true` only on the
+    /// call that flagged it, so the caller can finish the game exactly once.
+    pub fn check(&mut self) -> bool {
+        let Some((player, _)) = self.running else {
+            return false;
+        };
+        if self.left(player) != Some(Duration::ZERO) {
+            return false;
+        }
+        self.stop();
+        self.flagged = Some(player);
+        true
+    }
+
+    /// Limits for an engine move, budgeted by `manager` from the time left
+    /// and the increment.
+    pub fn engine_limits(&self, player: Player, manager: &TimeManager) -> Option<SearchLimits> {
+        let left = self.left(player)?;
+        let increment = Duration::from_millis(self.control?.increment_ms);
+        Some(manager.limits(left, increment))
+    }
+
+    pub fn reading(&self) -> Clock {
+        let millis = |d: Duration| d.as_millis() as u64;
+        let mut used = self.used;
+        if let Some((player, since)) = self.running {
+            used[player as usize] += since.elapsed();
+        }
+        Clock {
+            red_ms: millis(used[Player::Red as usize]),
+            blue_ms: millis(used[Player::Blue as usize]),
+            running: self.running.map(|(player, _)| player),
+            time_control: self.control,
+            red_left_ms: self.left(Player::Red).map(millis),
+            blue_left_ms: self.left(Player::Blue).map(millis),
+            flagged: self.flagged,
+        }
+    }
+}
+
+/// Decide timeouts in games nobody is touching. Games busy in a request are
+/// skipped; that request checks the clock itself.
+pub fn spawn_ticker(app: AppState) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut tick = tokio::time::interval(TICK);
+        loop {
+            tick.tick().await;
+            let mut records = app
+                .sessions
+                .update(|_, session| session.check_clock().then(|| session.record())?);
+            records.extend(app.rooms.update(|_, room| room.check_clock()));
+            for record in &records {
+                app.record_game(record);
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn increments_and_flags() {
+        let control = TimeControl {
+            initial_ms: 50,
+            increment_ms: 1_000,
+        };
+        let mut clock = GameClock::new(Some(control));
+        assert_eq!(clock.reading().running, None);
+        clock.start(Player::Red);
+        clock.moved(Player::Red);
+        let reading = clock.reading();
+        assert_eq!(reading.running, Some(Player::Blue));
+        assert!(reading.red_left_ms.unwrap() > 1_000);
+        assert!(!clock.check());
+
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(clock.check());
+        assert!(!clock.check());
+        let reading = clock.reading();
+        assert_eq!(reading.flagged, Some(Player::Blue));
+        assert_eq!((reading.running, reading.blue_left_ms), (None, Some(0)));
+
+        let mut untimed = GameClock::new(None);
+        untimed.start(Player::Red);
+        assert!(!untimed.check());
+        assert!(untimed
+            .engine_limits(Player::Red, &TimeManager::default())
+            .is_none());
+        let later = untimed.reading().advanced(Duration::from_secs(2));
+        assert!(later.red_ms >= 2_000 && later.red_left_ms.is_none());
+    }
+}
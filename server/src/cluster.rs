@@ -0,0 +1,394 @@
+//! Solver jobs split across worker processes.
+//!
+//! With `[cluster] workers` set, a solver job is not searched here. Each legal
+//! move from the root becomes a subtree, and every subtree goes to a worker as
+//! an ordinary solver request (`POST /api/v1/move`, `algorithm: solver`) for
+//! the position after that move. Workers are servers running this same
+//! binary. Their scores are from the opponent's side and are negated here.
+//! The best column wins; among wins the one proven at the shallowest depth,
+//! among losses the deepest, and otherwise the one nearer the centre, as in a
+//! local search. A subtree whose worker fails or times out moves on to the next
+//! worker, and is solved locally when none answers.
+//!
+//! Every subtree gets the job's budget: the submitter's tier's `max_search`,
+//! lowered by the request's `max_ms`. The workers may lower it further.
+//!
+//! A root already in the tablebase is answered from it, and a finished merge
+//! is added to it, as for a local solve.
+//!
+//! Sending requests needs the `cluster` feature (reqwest); configuring workers
+//! without it is a startup error.
+use std::{cmp::Reverse, time::Duration};
+
+use connect4::{
+    format_history, parse_history, Algorithm, GameState, MoveRequest, MoveResponse, ScoredMove,
+    TypedMove, HEIGHT, WIDTH,
+};
+use futures_util::future::join_all;
+use serde::Deserialize;
+use tracing::{info, warn};
+
+use crate::{auth::Tier, error::ApiError, jobs, AppState, EngineParams};
+
+/// Level sent with subtree requests; the solver ignores it, but the worker
+/// checks it against the key's tier like any solver request.
+const SOLVER_LEVEL: u8 = 15;
+/// What the solver scores a won position.
+const WIN_SCORE: i32 = 1_000_000;
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ClusterConfig {
+    /// Base URLs of the worker servers, e.g. `http://10.0.0.2:3000`; solver
+    /// jobs run locally when empty.
+    pub workers: Vec<String>,
+    /// Presented to the workers as `x-api-key`. Its tier there must allow
+    /// level 15 and the search time a subtree needs.
+    pub api_key: Option<String>,
+    /// Wait for one subtree before trying the next worker.
+    pub timeout_secs: u64,
+}
+
+impl Default for ClusterConfig {
+    fn default() -> Self {
+        Self {
+            workers: Vec::new(),
+            api_key: None,
+            timeout_secs: 10 * 60,
+        }
+    }
+}
+
+/// One root move and what solving the position after it found.
+#[derive(Clone, Debug, PartialEq)]
+struct Subtree {
+    column: usize,
+    /// From the root's side to move.
+    score: i32,
+    /// Plies below the root the answer reached.
+    depth: usize,
+    truncated: bool,
+}
+
+impl Subtree {
+    /// `reply` is the worker's answer for the position after `column`.
+    fn from_reply(column: usize, reply: &MoveResponse) -> Self {
+        Self {
+            column,
+            score: -reply.score.unwrap_or(0),
+            depth: reply.depth.map_or(1, |depth| depth + 1),
+            truncated: reply.truncated,
+        }
+    }
+}
+
+/// Solve `req` by farming its root moves out to the cluster's workers.
+pub async fn solve(
+    app: &AppState,
+    tier: &Tier,
+    req: MoveRequest,
+    params: EngineParams,
+) -> Result<MoveResponse, ApiError> {
+    let history = parse_history(&req.position)?;
+    let state = GameState::from_history(&history)?;
+    if params.multipv.unwrap_or(1) <= 1 {
+        if let Some(mv) = app.solved(&state) {
+            return Ok(mv);
+        }
+    }
+    let mut subtrees = Vec::new();
+    let mut pending = Vec::new();
+    for column in state.legal_moves() {
+        let mut child = state.clone();
+        if child.play(column)?.won {
+            let mut mv = MoveResponse::new(column, 1);
+            mv.score = Some(WIN_SCORE);
+            return Ok(mv);
+        }
+        if child.is_full() {
+            subtrees.push(Subtree {
+                column,
+                score: 0,
+                depth: 1,
+                truncated: false,
+            });
+            continue;
+        }
+        let mut moves = history.clone();
+        moves.push(TypedMove {
+            player: state.to_move(),
+            column,
+        });
+        pending.push((column, format_history(&moves)));
+    }
+    info!(
+        position = %req.position,
+        subtrees = pending.len(),
+        workers = app.config.cluster.workers.len(),
+        "distributing solve"
+    );
+    let max_ms = params.max_ms.map_or(tier.max_search, |wanted| {
+        Duration::from_millis(wanted).min(tier.max_search)
+    });
+    let client = Client::new(app, max_ms)?;
+    let solved = pending
+        .into_iter()
+        .enumerate()
+        .map(|(i, (column, position))| solve_subtree(app, tier, &client, i, column, position));
+    for subtree in join_all(solved).await {
+        subtrees.push(subtree?);
+    }
+    let order = state.legal_moves();
+    subtrees.sort_by_key(|subtree| order.iter().position(|&column| column == subtree.column));
+    let remaining = WIDTH * HEIGHT - history.len();
+    let mv = merge(subtrees, remaining, params.multipv.unwrap_or(1));
+    app.record_solved(&state, &mv);
+    Ok(mv)
+}
+
+/// Worker `index % workers` first, then the others in turn, then this server.
+async fn solve_subtree(
+    app: &AppState,
+    tier: &Tier,
+    client: &Client,
+    index: usize,
+    column: usize,
+    position: String,
+) -> Result<Subtree, ApiError> {
+    let workers = &app.config.cluster.workers;
+    for attempt in 0..workers.len() {
+        let worker = &workers[(index + attempt) % workers.len()];
+        match client.solve(worker, &position).await {
+            Ok(reply) => return Ok(Subtree::from_reply(column, &reply)),
+            Err(err) => warn!(worker, position, "subtree not solved: {err:#}"),
+        }
+    }
+    warn!(position, "no worker solved the subtree, solving it here");
+    let req = MoveRequest {
+        position,
+        level: SOLVER_LEVEL,
+        first_player: None,
+    };
+    let params = EngineParams {
+        algorithm: Some(Algorithm::Solver),
+        max_ms: Some(client.max_ms()),
+        ..EngineParams::default()
+    };
+    let reply = jobs::search_when_free(app, tier, req, params).await?;
+    Ok(Subtree::from_reply(column, &reply))
+}
+
+/// Best subtree first; `subtrees` come in root move order, which breaks the
+/// remaining ties.
+fn merge(mut subtrees: Vec<Subtree>, remaining: usize, multipv: usize) -> MoveResponse {
+    subtrees.sort_by_key(|subtree| {
+        // Win soonest, lose latest.
+        let urgency = match subtree.score.signum() {
+            1 => subtree.depth as i64,
+            -1 => -(subtree.depth as i64),
+            _ => 0,
+        };
+        (Reverse(subtree.score), urgency)
+    });
+    let best = &subtrees[0];
+    let mut response = MoveResponse::new(best.column, best.depth);
+    if subtrees.iter().any(|subtree| subtree.truncated) {
+        response = response.cut_short(remaining, None);
+    }
+    response.score = Some(best.score);
+    if multipv > 1 {
+        response.lines = subtrees
+            .iter()
+            .take(multipv.min(WIDTH))
+            .map(|subtree| ScoredMove {
+                column: subtree.column,
+                score: subtree.score,
+            })
+            .collect();
+    }
+    response
+}
+
+#[cfg(feature = "cluster")]
+struct Client {
+    http: reqwest::Client,
+    api_key: Option<String>,
+    /// Search budget asked of the workers; their tier may lower it.
+    max_ms: u64,
+}
+
+#[cfg(feature = "cluster")]
+impl Client {
+    fn new(app: &AppState, max_ms: Duration) -> Result<Self, ApiError> {
+        let cluster = &app.config.cluster;
+        let http = reqwest::Client::builder()
+            .timeout(Duration::from_secs(cluster.timeout_secs))
+            .build()
+            .map_err(|err| anyhow::anyhow!("cannot build the cluster client: {err}"))?;
+        Ok(Self {
+            http,
+            api_key: cluster.api_key.clone(),
+            max_ms: max_ms.as_millis() as u64,
+        })
+    }
+
+    fn max_ms(&self) -> u64 {
+        self.max_ms
+    }
+
+    async fn solve(&self, worker: &str, position: &str) -> anyhow::Result<MoveResponse> {
+        let url = format!("{}/api/v1/move", worker.trim_end_matches('/'));
+        let body = serde_json::json!({
+            "position": position,
+            "level": SOLVER_LEVEL,
+            "algorithm": "solver",
+            "max_ms": self.max_ms,
+        });
+        let mut request = self.http.post(url).json(&body);
+        if let Some(key) = &self.api_key {
+            request = request.header(crate::auth::API_KEY_HEADER, key);
+        }
+        Ok(request.send().await?.error_for_status()?.json().await?)
+    }
+}
+
+/// Without the feature no workers can be configured, so every subtree is
+/// solved locally.
+#[cfg(not(feature = "cluster"))]
+struct Client {
+    max_ms: u64,
+}
+
+#[cfg(not(feature = "cluster"))]
+impl Client {
+    fn new(_app: &AppState, max_ms: Duration) -> Result<Self, ApiError> {
+        Ok(Self {
+            max_ms: max_ms.as_millis() as u64,
+        })
+    }
+
+    fn max_ms(&self) -> u64 {
+        self.max_ms
+    }
+
+    async fn solve(&self, _worker: &str, _position: &str) -> anyhow::Result<MoveResponse> {
+        anyhow::bail!("the server was built without the `cluster` feature")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn subtree(column: usize, score: i32, truncated: bool) -> Subtree {
+        Subtree {
+            column,
+            score,
+            depth: 9,
+            truncated,
+        }
+    }
+
+    #[test]
+    fn merge_prefers_the_best_score_then_the_fastest_win() {
+        let subtrees = vec![
+            subtree(3, 0, false),
+            subtree(2, WIN_SCORE, false),
+            Subtree {
+                depth: 5,
+                ..subtree(4, WIN_SCORE, false)
+            },
+            subtree(0, -WIN_SCORE, false),
+        ];
+        let mv = merge(subtrees.clone(), 20, 3);
+        assert_eq!(
+            (mv.column, mv.score, mv.depth),
+            (4, Some(WIN_SCORE), Some(5))
+        );
+        let columns: Vec<usize> = mv.lines.iter().map(|line| line.column).collect();
+        assert_eq!(columns, [4, 2, 3]);
+        assert!(!mv.truncated);
+
+        let mut cut = subtrees;
+        cut[3].truncated = true;
+        let mv = merge(cut, 20, 1);
+        assert!(mv.truncated && mv.lines.is_empty());
+        assert_eq!(mv.target_depth, Some(20));
+    }
+
+    #[test]
+    fn replies_are_negated_and_one_ply_deeper() {
+        let mut reply = MoveResponse::new(5, 12);
+        reply.score = Some(WIN_SCORE);
+        assert_eq!(
+            Subtree::from_reply(1, &reply),
+            Subtree {
+                column: 1,
+                score: -WIN_SCORE,
+                depth: 13,
+                truncated: false,
+            }
+        );
+    }
+
+    #[cfg(feature = "cluster")]
+    #[tokio::test]
+    async fn subtrees_carry_the_submitters_budget() {
+        use std::sync::{Arc, Mutex};
+
+        use axum::{routing::post, Json, Router};
+
+        let asked = Arc::new(Mutex::new(Vec::new()));
+        let worker = Router::new().route(
+            "/api/v1/move",
+            post({
+                let asked = asked.clone();
+                move |Json(body): Json<serde_json::Value>| async move {
+                    asked.lock().unwrap().push(body["max_ms"].as_u64());
+                    let mut reply = MoveResponse::new(0, 1);
+                    reply.score = Some(0);
+                    Json(reply)
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, worker).await.unwrap() });
+
+        let config: crate::config::Config = toml::from_str(&format!(
+            r#"
+            jobs = {{ max_search_ms = 300000 }}
+            auth.anonymous = {{ max_search_ms = 200 }}
+            cluster = {{ workers = ["http://{addr}"] }}
+            "#
+        ))
+        .unwrap();
+        let app = AppState::new(config, Arc::new(crate::storage::MemoryStorage::default()));
+        let req = MoveRequest {
+            position: "R3B3R3B3R3B2R2B4R4B4R2B2R4B1R1B5R5B0R0B6".to_string(),
+            level: SOLVER_LEVEL,
+            first_player: None,
+        };
+        let params = |max_ms| EngineParams {
+            algorithm: Some(Algorithm::Solver),
+            max_ms,
+            // Past the tablebase, so the second solve asks the worker again.
+            multipv: Some(2),
+            ..EngineParams::default()
+        };
+        let anonymous = Tier::anonymous(&app.config);
+        solve(&app, &anonymous, req.clone(), params(None))
+            .await
+            .unwrap();
+        let budgets = std::mem::take(&mut *asked.lock().unwrap());
+        assert!(!budgets.is_empty());
+        assert!(budgets.iter().all(|&ms| ms == Some(200)), "{budgets:?}");
+        // A shorter `max_ms` lowers it further.
+        solve(&app, &anonymous, req, params(Some(50)))
+            .await
+            .unwrap();
+        let budgets = asked.lock().unwrap();
+        assert!(!budgets.is_empty());
+        assert!(budgets.iter().all(|&ms| ms == Some(50)), "{budgets:?}");
+    }
+}
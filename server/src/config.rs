@@ -0,0 +1,583 @@
+//! Startup configuration.
+//!
+//! Values are layered, later sources winning: built-in defaults, then a TOML
+//! file (`--config`, or `config.toml` in the working directory when present),
+//! then `CONNECT4_*` environment variables, then command-line flags. The
+//! result is validated once so a typo fails at startup instead of at the
+//! first request that happens to need the value.
+use std::{
+    net::{IpAddr, Ipv4Addr},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::{bail, Context};
+use axum::http::HeaderValue;
+use clap::Parser;
+use connect4::{resign::ResignPolicy, Algorithm, TimeManager};
+use serde::Deserialize;
+
+use crate::{
+    arena::ArenaConfig,
+    audit::AuditConfig,
+    auth::{AuthConfig, TierLimits},
+    book::BookConfig,
+    cluster::ClusterConfig,
+    jobs::JobConfig,
+    rate_limit::RateLimitConfig,
+    search_pool::SearchPoolConfig,
+    sessions::PonderConfig,
+    shared_cache::RedisConfig,
+    tls::TlsConfig,
+};
+
+const DEFAULT_CONFIG_FILE: &str = "config.toml";
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub bind: IpAddr,
+    pub port: u16,
+    /// Listen on this Unix socket instead of `bind:port`.
+    pub unix_socket: Option<PathBuf>,
+    /// Serve the socket systemd passes in (`LISTEN_FDS`) instead of binding.
+    pub socket_activation: bool,
+    /// Built web UI served at `/`.
+    pub static_dir: PathBuf,
+    /// Allowed CORS origins; `*` allows any.
+    pub cors_origins: Vec<String>,
+    /// Highest level clients may ask for, at most 15.
+    pub max_level: u8,
+    /// Searches running longer return their deepest finished iteration.
+    pub max_search_ms: u64,
+    /// Idle time after which sessions and rooms are dropped.
+    pub session_ttl_secs: u64,
+    /// Takebacks allowed in each session or room game; 0 disables them.
+    pub max_takebacks: u32,
+    /// Hints allowed in each session game; 0 disables them.
+    pub max_hints: u32,
+    /// Searched moves kept in memory; 0 disables the in-process cache.
+    pub move_cache_entries: usize,
+    /// Size of the transposition table every search shares, in MiB.
+    pub transposition_table_mb: usize,
+    /// Async runtime threads; one per CPU when unset.
+    pub worker_threads: Option<usize>,
+    /// SQLite file for the archive and position cache (`sqlite` feature).
+    pub database: Option<PathBuf>,
+    pub search: SearchPoolConfig,
+    pub engine: EngineConfig,
+    /// Background searches behind `POST /api/jobs`.
+    pub jobs: JobConfig,
+    /// Engine answers prepared while session players think.
+    pub ponder: PonderConfig,
+    /// Rated games between bots.
+    pub arena: ArenaConfig,
+    /// When the engine resigns or offers a draw in sessions and the arena.
+    pub resign: ResignPolicy,
+    pub rate_limit: RateLimitConfig,
+    /// API keys and the anonymous tier's limits.
+    pub auth: AuthConfig,
+    /// Serve HTTPS instead of HTTP (`tls` feature).
+    pub tls: Option<TlsConfig>,
+    /// JSONL log of every finished game.
+    pub audit: AuditConfig,
+    /// Share searched moves between replicas through Redis (`redis` feature).
+    pub redis: Option<RedisConfig>,
+    /// Worker servers that solver jobs are split across (`cluster` feature).
+    pub cluster: ClusterConfig,
+    /// Opening book answering early positions without a search.
+    pub book: BookConfig,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            bind: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            port: 3000,
+            unix_socket: None,
+            socket_activation: false,
+            static_dir: PathBuf::from("web/dist"),
+            cors_origins: vec!["*".to_string()],
+            max_level: 15,
+            max_search_ms: 10_000,
+            session_ttl_secs: 30 * 60,
+            max_takebacks: 3,
+            max_hints: 3,
+            move_cache_entries: 10_000,
+            transposition_table_mb: 16,
+            worker_threads: None,
+            database: None,
+            search: SearchPoolConfig::default(),
+            engine: EngineConfig::default(),
+            jobs: JobConfig::default(),
+            ponder: PonderConfig::default(),
+            arena: ArenaConfig::default(),
+            resign: ResignPolicy::default(),
+            rate_limit: RateLimitConfig::default(),
+            auth: AuthConfig::default(),
+            tls: None,
+            audit: AuditConfig::default(),
+            redis: None,
+            cluster: ClusterConfig::default(),
+            book: BookConfig::default(),
+        }
+    }
+}
+
+/// Which per-request engine options clients may use.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EngineConfig {
+    /// Algorithms clients may select; alpha-beta is always allowed.
+    pub algorithms: Vec<Algorithm>,
+    /// Upper bound for `multipv`; larger requests are clamped.
+    pub max_multipv: usize,
+    /// Positions accepted by one `POST /api/moves`.
+    pub max_batch: usize,
+    /// Under a time control the engine spreads its time left over this many
+    /// moves...
+    pub moves_to_go: u32,
+    /// ...and adds this percentage of the increment.
+    pub increment_percent: u32,
+}
+
+impl Default for EngineConfig {
+    fn default() -> Self {
+        let clock = TimeManager::default();
+        Self {
+            algorithms: vec![Algorithm::AlphaBeta, Algorithm::Mcts, Algorithm::Solver],
+            max_multipv: 7,
+            max_batch: 64,
+            moves_to_go: clock.moves_to_go,
+            increment_percent: clock.increment_percent,
+        }
+    }
+}
+
+impl EngineConfig {
+    /// How the engine spends its clock in timed sessions and arena games.
+    pub fn time_manager(&self) -> TimeManager {
+        TimeManager {
+            moves_to_go: self.moves_to_go,
+            increment_percent: self.increment_percent,
+        }
+    }
+}
+
+/// Command-line flags. Each one also reads the environment variable named
+/// next to it, so flags override the environment, which overrides the file.
+#[derive(Debug, Parser)]
+#[command(about = "Connect 4 engine and HTTP API")]
+pub struct Cli {
+    /// TOML config file [default: ./config.toml if it exists]
+    #[arg(long, env = "CONNECT4_CONFIG")]
+    pub config: Option<PathBuf>,
+    #[arg(long, env = "CONNECT4_BIND")]
+    pub bind: Option<IpAddr>,
+    #[arg(long, env = "CONNECT4_PORT")]
+    pub port: Option<u16>,
+    /// Listen on a Unix socket instead of TCP
+    #[arg(long, env = "CONNECT4_UNIX_SOCKET")]
+    pub unix_socket: Option<PathBuf>,
+    /// Use the socket passed by systemd socket activation
+    #[arg(long, env = "CONNECT4_SOCKET_ACTIVATION")]
+    pub socket_activation: Option<bool>,
+    #[arg(long, env = "CONNECT4_STATIC_DIR")]
+    pub static_dir: Option<PathBuf>,
+    /// Repeat or comma-separate for several origins
+    #[arg(
+        long = "cors-origin",
+        env = "CONNECT4_CORS_ORIGINS",
+        value_delimiter = ','
+    )]
+    pub cors_origins: Vec<String>,
+    #[arg(long, env = "CONNECT4_MAX_LEVEL")]
+    pub max_level: Option<u8>,
+    #[arg(long, env = "CONNECT4_MAX_SEARCH_MS")]
+    pub max_search_ms: Option<u64>,
+    #[arg(long, env = "CONNECT4_SESSION_TTL_SECS")]
+    pub session_ttl_secs: Option<u64>,
+    #[arg(long, env = "CONNECT4_MAX_TAKEBACKS")]
+    pub max_takebacks: Option<u32>,
+    #[arg(long, env = "CONNECT4_MAX_HINTS")]
+    pub max_hints: Option<u32>,
+    #[arg(long, env = "CONNECT4_MOVE_CACHE_ENTRIES")]
+    pub move_cache_entries: Option<usize>,
+    #[arg(long, env = "CONNECT4_TRANSPOSITION_TABLE_MB")]
+    pub transposition_table_mb: Option<usize>,
+    #[arg(long, env = "CONNECT4_WORKER_THREADS")]
+    pub worker_threads: Option<usize>,
+    #[arg(long = "db", env = "CONNECT4_DB")]
+    pub database: Option<PathBuf>,
+    #[arg(long, env = "CONNECT4_SEARCH_WORKERS")]
+    pub search_workers: Option<usize>,
+    #[arg(long, env = "CONNECT4_SEARCH_QUEUE")]
+    pub search_queue: Option<usize>,
+    #[arg(long, env = "CONNECT4_DEEP_LEVEL")]
+    pub deep_level: Option<u8>,
+    #[arg(long, env = "CONNECT4_DEEP_WORKERS")]
+    pub deep_workers: Option<usize>,
+    #[arg(long, env = "CONNECT4_RATE_BURST")]
+    pub rate_burst: Option<u32>,
+    #[arg(long, env = "CONNECT4_RATE_PER_SEC")]
+    pub rate_per_sec: Option<f64>,
+    #[arg(long, env = "CONNECT4_TRUST_FORWARDED_FOR")]
+    pub trust_forwarded_for: Option<bool>,
+    /// Highest level for requests without an API key
+    #[arg(long, env = "CONNECT4_ANONYMOUS_MAX_LEVEL")]
+    pub anonymous_max_level: Option<u8>,
+    /// Time budget for requests without an API key
+    #[arg(long, env = "CONNECT4_ANONYMOUS_MAX_SEARCH_MS")]
+    pub anonymous_max_search_ms: Option<u64>,
+    /// PEM certificate chain; enables HTTPS together with --tls-key
+    #[arg(long, env = "CONNECT4_TLS_CERT")]
+    pub tls_cert: Option<PathBuf>,
+    /// PEM private key
+    #[arg(long, env = "CONNECT4_TLS_KEY")]
+    pub tls_key: Option<PathBuf>,
+    /// Redirect plain HTTP on this port to HTTPS
+    #[arg(long, env = "CONNECT4_HTTP_REDIRECT_PORT")]
+    pub http_redirect_port: Option<u16>,
+    /// Append every finished game to this JSONL file
+    #[arg(long, env = "CONNECT4_AUDIT_LOG")]
+    pub audit_log: Option<PathBuf>,
+    /// Share searched moves with other replicas through Redis at HOST:PORT
+    #[arg(long, env = "CONNECT4_REDIS", value_name = "HOST:PORT")]
+    pub redis: Option<String>,
+    /// Split solver jobs across these worker servers (base URLs)
+    #[arg(
+        long = "cluster-worker",
+        env = "CONNECT4_CLUSTER_WORKERS",
+        value_delimiter = ','
+    )]
+    pub cluster_workers: Vec<String>,
+    /// Opening book file, reloaded when it changes
+    #[arg(long, env = "CONNECT4_BOOK")]
+    pub book: Option<PathBuf>,
+    /// Store a new API key with this name in the database, print it and exit
+    #[arg(long, value_name = "NAME")]
+    pub issue_api_key: Option<String>,
+    /// Make the key issued with `--issue-api-key` an admin key
+    #[arg(long, requires = "issue_api_key")]
+    pub admin: bool,
+    /// Speak JSON-RPC 2.0 on stdin/stdout (`stdio`) or a TCP address instead of HTTP
+    #[arg(long, value_name = "stdio|ADDR")]
+    pub rpc: Option<crate::rpc::Transport>,
+}
+
+impl Config {
+    pub fn resolve(cli: Cli) -> anyhow::Result<Self> {
+        let mut config = match &cli.config {
+            Some(path) => Self::from_file(path)?,
+            None if Path::new(DEFAULT_CONFIG_FILE).exists() => {
+                Self::from_file(Path::new(DEFAULT_CONFIG_FILE))?
+            }
+            None => Self::default(),
+        };
+        config.apply(cli)?;
+        config.validate().context("invalid configuration")?;
+        Ok(config)
+    }
+
+    pub fn from_file(path: &Path) -> anyhow::Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("cannot read config file {}", path.display()))?;
+        toml::from_str(&text).with_context(|| format!("cannot parse {}", path.display()))
+    }
+
+    fn apply(&mut self, cli: Cli) -> anyhow::Result<()> {
+        fn set<T>(field: &mut T, value: Option<T>) {
+            if let Some(value) = value {
+                *field = value;
+            }
+        }
+        set(&mut self.bind, cli.bind);
+        set(&mut self.port, cli.port);
+        if cli.unix_socket.is_some() {
+            self.unix_socket = cli.unix_socket;
+        }
+        set(&mut self.socket_activation, cli.socket_activation);
+        set(&mut self.static_dir, cli.static_dir);
+        if !cli.cors_origins.is_empty() {
+            self.cors_origins = cli.cors_origins;
+        }
+        set(&mut self.max_level, cli.max_level);
+        set(&mut self.max_search_ms, cli.max_search_ms);
+        set(&mut self.session_ttl_secs, cli.session_ttl_secs);
+        set(&mut self.max_takebacks, cli.max_takebacks);
+        set(&mut self.max_hints, cli.max_hints);
+        set(&mut self.move_cache_entries, cli.move_cache_entries);
+        set(&mut self.transposition_table_mb, cli.transposition_table_mb);
+        if cli.worker_threads.is_some() {
+            self.worker_threads = cli.worker_threads;
+        }
+        if cli.database.is_some() {
+            self.database = cli.database;
+        }
+        set(&mut self.search.workers, cli.search_workers);
+        set(&mut self.search.queue, cli.search_queue);
+        set(&mut self.search.deep_level, cli.deep_level);
+        set(&mut self.search.deep_workers, cli.deep_workers);
+        set(&mut self.rate_limit.burst, cli.rate_burst);
+        set(&mut self.rate_limit.per_second, cli.rate_per_sec);
+        set(
+            &mut self.rate_limit.trust_forwarded_for,
+            cli.trust_forwarded_for,
+        );
+        let anonymous = &mut self.auth.anonymous;
+        if cli.anonymous_max_level.is_some() {
+            anonymous.max_level = cli.anonymous_max_level;
+        }
+        if cli.anonymous_max_search_ms.is_some() {
+            anonymous.max_search_ms = cli.anonymous_max_search_ms;
+        }
+        match (&mut self.tls, cli.tls_cert, cli.tls_key) {
+            (Some(tls), cert, key) => {
+                set(&mut tls.cert, cert);
+                set(&mut tls.key, key);
+            }
+            (None, Some(cert), Some(key)) => {
+                self.tls = Some(TlsConfig {
+                    cert,
+                    key,
+                    redirect_http_port: None,
+                })
+            }
+            (None, None, None) => {}
+            (None, _, _) => bail!("--tls-cert and --tls-key must be given together"),
+        }
+        if let Some(port) = cli.http_redirect_port {
+            let Some(tls) = &mut self.tls else {
+                bail!("--http-redirect-port needs TLS to be configured");
+            };
+            tls.redirect_http_port = Some(port);
+        }
+        if cli.audit_log.is_some() {
+            self.audit.path = cli.audit_log;
+        }
+        if let Some(addr) = cli.redis {
+            self.redis.get_or_insert_with(RedisConfig::default).addr = addr;
+        }
+        if cli.book.is_some() {
+            self.book.path = cli.book;
+        }
+        if !cli.cluster_workers.is_empty() {
+            self.cluster.workers = cli.cluster_workers;
+        }
+        Ok(())
+    }
+
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if !(1..=15).contains(&self.max_level) {
+            bail!("max_level must be between 1 and 15, got {}", self.max_level);
+        }
+        if self.max_search_ms == 0 {
+            bail!("max_search_ms must be positive");
+        }
+        if self.session_ttl_secs == 0 {
+            bail!("session_ttl_secs must be positive");
+        }
+        if !(1..=65_536).contains(&self.transposition_table_mb) {
+            bail!(
+                "transposition_table_mb must be between 1 and 65536, got {}",
+                self.transposition_table_mb
+            );
+        }
+        if self.audit.max_mb == 0 {
+            bail!("audit.max_mb must be positive");
+        }
+        if self.worker_threads == Some(0) {
+            bail!("worker_threads must be positive when set");
+        }
+        if self.search.workers == 0 || self.search.deep_workers == 0 {
+            bail!("search.workers and search.deep_workers must be positive");
+        }
+        if !(1..=7).contains(&self.engine.max_multipv) {
+            bail!(
+                "engine.max_multipv must be between 1 and 7, got {}",
+                self.engine.max_multipv
+            );
+        }
+        if self.engine.max_batch == 0 {
+            bail!("engine.max_batch must be positive");
+        }
+        if self.jobs.workers == 0 || self.jobs.queue == 0 || self.jobs.max_search_ms == 0 {
+            bail!("jobs.workers, jobs.queue and jobs.max_search_ms must be positive");
+        }
+        if self.arena.time_control.initial_ms == 0 {
+            bail!("arena.time_control.initial_ms must be positive");
+        }
+        if self.resign.moves == 0 {
+            bail!("resign.moves must be at least 1");
+        }
+        validate_rate_limit("rate_limit", &self.rate_limit)?;
+        validate_tier("auth.anonymous", &self.auth.anonymous)?;
+        for (i, key) in self.auth.keys.iter().enumerate() {
+            if key.key.is_empty() || key.name.is_empty() {
+                bail!("auth.keys[{i}] needs a non-empty key and name");
+            }
+            if self.auth.keys[..i].iter().any(|other| other.key == key.key) {
+                bail!("auth.keys[{i}] ({}) repeats an earlier key", key.name);
+            }
+            validate_tier(&format!("auth.keys[{i}]"), &key.limits)?;
+        }
+        if self.unix_socket.is_some() || self.socket_activation {
+            if cfg!(not(unix)) {
+                bail!("unix_socket and socket_activation need a Unix platform");
+            }
+            if self.unix_socket.is_some() && self.socket_activation {
+                bail!("unix_socket and socket_activation are mutually exclusive");
+            }
+            if self.unix_socket.is_some() && self.tls.is_some() {
+                bail!("tls cannot be served on a unix_socket; terminate TLS in the proxy");
+            }
+        }
+        if let Some(tls) = &self.tls {
+            if cfg!(not(feature = "tls")) {
+                bail!("tls is configured but the server was built without the `tls` feature");
+            }
+            if tls.redirect_http_port == Some(self.port) {
+                bail!("tls.redirect_http_port must differ from port");
+            }
+        }
+        if let Some(redis) = &self.redis {
+            if cfg!(not(feature = "redis")) {
+                bail!("redis is configured but the server was built without the `redis` feature");
+            }
+            if redis.addr.is_empty() {
+                bail!("redis.addr must not be empty");
+            }
+            if redis.ttl_secs == 0 || redis.timeout_ms == 0 || redis.breaker_failures == 0 {
+                bail!(
+                    "redis.ttl_secs, redis.timeout_ms and redis.breaker_failures must be positive"
+                );
+            }
+            if !(0.0..1.0).contains(&redis.ttl_jitter) {
+                bail!(
+                    "redis.ttl_jitter must be at least 0 and below 1, got {}",
+                    redis.ttl_jitter
+                );
+            }
+        }
+        if !self.cluster.workers.is_empty() {
+            if cfg!(not(feature = "cluster")) {
+                bail!("cluster.workers are configured but the server was built without the `cluster` feature");
+            }
+            for worker in &self.cluster.workers {
+                if !(worker.starts_with("http://") || worker.starts_with("https://")) {
+                    bail!("cluster worker {worker:?} must be an http or https URL");
+                }
+            }
+            if self.cluster.timeout_secs == 0 {
+                bail!("cluster.timeout_secs must be positive");
+            }
+        }
+        if self.cors_origins.is_empty() {
+            bail!("cors_origins must list at least one origin (use \"*\" for any)");
+        }
+        for origin in &self.cors_origins {
+            HeaderValue::from_str(origin)
+                .with_context(|| format!("cors origin {origin:?} is not a valid header value"))?;
+        }
+        Ok(())
+    }
+
+    pub fn max_search(&self) -> Duration {
+        Duration::from_millis(self.max_search_ms)
+    }
+
+    pub fn session_ttl(&self) -> Duration {
+        Duration::from_secs(self.session_ttl_secs)
+    }
+}
+
+fn validate_rate_limit(name: &str, rate_limit: &RateLimitConfig) -> anyhow::Result<()> {
+    if rate_limit.burst == 0 {
+        bail!("{name}.burst must be positive");
+    }
+    if !(rate_limit.per_second.is_finite() && rate_limit.per_second >= 0.0) {
+        bail!(
+            "{name}.per_second must be a non-negative number, got {}",
+            rate_limit.per_second
+        );
+    }
+    Ok(())
+}
+
+fn validate_tier(name: &str, limits: &TierLimits) -> anyhow::Result<()> {
+    if let Some(level) = limits.max_level {
+        if !(1..=15).contains(&level) {
+            bail!("{name}.max_level must be between 1 and 15, got {level}");
+        }
+    }
+    if limits.max_search_ms == Some(0) {
+        bail!("{name}.max_search_ms must be positive");
+    }
+    if let Some(rate_limit) = &limits.rate_limit {
+        validate_rate_limit(&format!("{name}.rate_limit"), rate_limit)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_values_are_overridden_by_flags() {
+        let mut config: Config = toml::from_str(
+            r#"
+            port = 8080
+            max_level = 10
+            [rate_limit]
+            burst = 5
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.rate_limit.per_second, 2.0);
+        let cli =
+            Cli::try_parse_from(["server", "--port", "9000", "--cors-origin", "a,b"]).unwrap();
+        config.apply(cli).unwrap();
+        assert_eq!((config.port, config.max_level), (9000, 10));
+        assert_eq!(config.rate_limit.burst, 5);
+        assert_eq!(config.cors_origins, ["a", "b"]);
+        config.validate().unwrap();
+    }
+
+    #[test]
+    fn invalid_values_are_rejected() {
+        let config = Config {
+            max_level: 16,
+            ..Config::default()
+        };
+        assert!(config.validate().is_err());
+        assert!(toml::from_str::<Config>("prot = 1").is_err());
+        let cli = Cli::try_parse_from(["server", "--tls-cert", "cert.pem"]).unwrap();
+        assert!(Config::default().apply(cli).is_err());
+        let duplicate_keys: Config = toml::from_str(
+            r#"
+            [[auth.keys]]
+            key = "secret"
+            name = "a"
+            [[auth.keys]]
+            key = "secret"
+            name = "b"
+            "#,
+        )
+        .unwrap();
+        assert!(duplicate_keys.validate().is_err());
+        let jitter: Config = toml::from_str("[redis]\nttl_jitter = 1.5").unwrap();
+        assert!(jitter.validate().is_err());
+        let cli = Cli::try_parse_from([
+            "server",
+            "--unix-socket",
+            "/run/connect4.sock",
+            "--socket-activation",
+            "true",
+        ])
+        .unwrap();
+        let mut config = Config::default();
+        config.apply(cli).unwrap();
+        assert!(config.validate().is_err());
+    }
+}
@@ -0,0 +1,145 @@
+//! Error responses. Every failure is answered with a JSON body whose `code`
+//! is stable and meant for clients to branch on; `message` is for humans and
+//! may change wording at any time.
+use axum::{
+    extract::{
+        rejection::{JsonRejection, QueryRejection},
+        FromRequest, FromRequestParts,
+    },
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use connect4::{GameError, ParseMoveError};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct ErrorBody {
+    pub code: String,
+    pub message: String,
+    /// Character offset into the submitted history, for parse errors.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub position: Option<usize>,
+    /// What is wrong at `position`, for parse errors; `kind` is stable like
+    /// `code`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<ParseMoveError>,
+}
+
+/// Status and code come from the error's type when it is one we recognise
+/// (engine errors, extractor rejections); anything else reaching a handler
+/// through `?` is an internal failure.
+#[derive(Debug)]
+pub struct ApiError {
+    pub status: StatusCode,
+    pub code: &'static str,
+    pub position: Option<usize>,
+    pub error: anyhow::Error,
+}
+
+impl ApiError {
+    pub fn new(status: StatusCode, message: impl std::fmt::Display) -> Self {
+        Self {
+            status,
+            code: code_for_status(status),
+            position: None,
+            error: anyhow::anyhow!("{message}"),
+        }
+    }
+}
+
+fn code_for_status(status: StatusCode) -> &'static str {
+    match status {
+        StatusCode::BAD_REQUEST => "bad_request",
+        StatusCode::UNAUTHORIZED => "unauthorized",
+        StatusCode::FORBIDDEN => "forbidden",
+        StatusCode::NOT_FOUND => "not_found",
+        StatusCode::CONFLICT => "conflict",
+        StatusCode::UNPROCESSABLE_ENTITY => "unprocessable",
+        StatusCode::TOO_MANY_REQUESTS => "rate_limited",
+        StatusCode::NOT_IMPLEMENTED => "not_implemented",
+        StatusCode::SERVICE_UNAVAILABLE => "busy",
+        _ => "internal",
+    }
+}
+
+impl<E: Into<anyhow::Error>> From<E> for ApiError {
+    fn from(err: E) -> Self {
+        let error = err.into();
+        let (status, code, position) = if let Some(err) = error.downcast_ref::<GameError>() {
+            match *err {
+                GameError::ParseMove { position, .. } => (
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                    "invalid_position",
+                    Some(position),
+                ),
+                GameError::InvalidGrid(_) => {
+                    (StatusCode::UNPROCESSABLE_ENTITY, "invalid_position", None)
+                }
+                GameError::ColumnOutOfBounds { .. } => (
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                    "column_out_of_bounds",
+                    None,
+                ),
+                GameError::ColumnFull { .. } => (StatusCode::CONFLICT, "column_full", None),
+                GameError::NoMoves => (StatusCode::CONFLICT, "no_moves", None),
+                GameError::DepthOutOfRange(_) => {
+                    (StatusCode::BAD_REQUEST, "depth_out_of_range", None)
+                }
+                GameError::AlgorithmUnavailable(_) => {
+                    (StatusCode::NOT_IMPLEMENTED, "not_implemented", None)
+                }
+            }
+        } else if let Some(rejection) = error.downcast_ref::<QueryRejection>() {
+            (rejection.status(), "invalid_query", None)
+        } else if let Some(rejection) = error.downcast_ref::<JsonRejection>() {
+            (rejection.status(), "invalid_body", None)
+        } else {
+            (StatusCode::INTERNAL_SERVER_ERROR, "internal", None)
+        };
+        Self {
+            status,
+            code,
+            position,
+            error,
+        }
+    }
+}
+
+impl std::fmt::Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.error.fmt(f)
+    }
+}
+
+impl ApiError {
+    pub fn body(&self) -> ErrorBody {
+        ErrorBody {
+            code: self.code.to_string(),
+            message: self.error.to_string(),
+            position: self.position,
+            reason: match self.error.downcast_ref::<GameError>() {
+                Some(GameError::ParseMove { reason, .. }) => Some(reason.clone()),
+                _ => None,
+            },
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.status, Json(self.body())).into_response()
+    }
+}
+
+/// `Query` whose rejection is an `ApiError`, so malformed query strings get
+/// the same JSON body as every other failure.
+#[derive(FromRequestParts)]
+#[from_request(via(axum::extract::Query), rejection(ApiError))]
+pub struct ApiQuery<T>(pub T);
+
+/// `Json` counterpart of [`ApiQuery`].
+#[derive(FromRequest)]
+#[from_request(via(axum::Json), rejection(ApiError))]
+pub struct ApiJson<T>(pub T);
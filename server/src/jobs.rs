@@ -0,0 +1,548 @@
+//! Background searches for requests too slow to hold a connection open.
+//!
+//! `POST /api/jobs` takes the body of `POST /api/move`, checks it like the
+//! move endpoint would, and answers `202 Accepted` at once. The search then
+//! runs under the submitter's tier while the client polls `GET /api/jobs/{id}`,
+//! or waits for the optional webhook. Jobs submitted with an API key get the
+//! longer `jobs.max_search_ms` budget; anonymous ones keep their tier's.
+//!
+//! Jobs are saved in storage at every step, so with a database they survive
+//! restarts: unfinished jobs are queued again when the server comes back.
+//! The job queue is bounded like the search pool and turns submissions away
+//! with `503` when full; a running job still takes its search-pool slot.
+//! Solver jobs are split across the `[cluster]` workers when there are any.
+//!
+//! Webhooks are a request the server makes on a client's behalf, so they are
+//! kept from reaching anything the client could not: only API keys may set
+//! one, its host must be listed in `jobs.webhook_hosts`, and every address
+//! the host resolves to when the job finishes must be public. Redirects are
+//! not followed.
+use std::{net::IpAddr, sync::Arc, time::Duration};
+
+use axum::{
+    extract::{Path, State},
+    http::{header, StatusCode, Uri},
+    response::IntoResponse,
+    routing::get,
+    Extension, Json, Router,
+};
+use connect4::{parse_history, Algorithm, GameState, MoveRequest, MoveResponse};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tracing::{info, warn};
+use utoipa::ToSchema;
+
+use crate::{
+    auth::Tier,
+    cluster,
+    error::{ApiError, ApiJson, ErrorBody},
+    sessions::new_id,
+    storage::unix_now,
+    AppState, EngineParams,
+};
+
+/// Wait before asking a busy search pool again.
+const BUSY_RETRY: Duration = Duration::from_millis(500);
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct JobConfig {
+    /// Jobs searching at the same time.
+    pub workers: usize,
+    /// Jobs accepted but not finished, running ones included.
+    pub queue: usize,
+    /// Search budget of a job submitted with an API key, when it exceeds the
+    /// key's own `max_search_ms`.
+    pub max_search_ms: u64,
+    /// Hosts webhooks may be sent to, matched exactly; none by default.
+    pub webhook_hosts: Vec<String>,
+}
+
+impl Default for JobConfig {
+    fn default() -> Self {
+        Self {
+            workers: 1,
+            queue: 64,
+            max_search_ms: 5 * 60 * 1000,
+            webhook_hosts: Vec::new(),
+        }
+    }
+}
+
+pub fn routes() -> Router<AppState> {
+    Router::new().route("/jobs/:id", get(get_job))
+}
+
+#[derive(Clone)]
+pub struct JobQueue {
+    admitted: Arc<Semaphore>,
+    running: Arc<Semaphore>,
+    config: JobConfig,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct JobQueueStats {
+    pub running: usize,
+    pub queued: usize,
+    pub queue_capacity: usize,
+}
+
+impl JobQueue {
+    pub fn new(config: &JobConfig) -> Self {
+        Self {
+            admitted: Arc::new(Semaphore::new(config.queue.max(1))),
+            running: Arc::new(Semaphore::new(config.workers.max(1))),
+            config: config.clone(),
+        }
+    }
+
+    pub fn stats(&self) -> JobQueueStats {
+        let capacity = self.config.queue.max(1);
+        let unfinished = capacity - self.admitted.available_permits();
+        let running = self.config.workers.max(1) - self.running.available_permits();
+        JobQueueStats {
+            running,
+            queued: unfinished.saturating_sub(running),
+            queue_capacity: capacity,
+        }
+    }
+
+    fn admit(&self) -> Option<OwnedSemaphorePermit> {
+        self.admitted.clone().try_acquire_owned().ok()
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Done,
+    Failed,
+}
+
+/// Body of `POST /api/jobs`: a move request plus where to report the result.
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct JobRequest {
+    #[serde(flatten)]
+    pub request: MoveRequest,
+    #[serde(flatten)]
+    pub params: EngineParams,
+    /// `http(s)` URL that receives the finished job as a JSON `POST`
+    /// (`webhooks` feature). Needs an API key and a host listed in
+    /// `jobs.webhook_hosts`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub webhook: Option<String>,
+}
+
+/// The ceilings a job searches under, fixed at submission so a job resumed
+/// after a restart keeps them.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct JobLimits {
+    pub max_level: u8,
+    pub max_search_ms: u64,
+}
+
+impl JobLimits {
+    /// The submitter's tier, with the search budget raised to
+    /// `jobs.max_search_ms` for API keys.
+    fn for_tier(tier: &Tier, config: &JobConfig) -> Self {
+        let mut max_search = tier.max_search;
+        if tier.key.is_some() {
+            max_search = max_search.max(Duration::from_millis(config.max_search_ms));
+        }
+        Self {
+            max_level: tier.max_level,
+            max_search_ms: max_search.as_millis() as u64,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct Job {
+    pub id: String,
+    pub status: JobStatus,
+    pub request: JobRequest,
+    /// Missing on jobs saved before limits were recorded; those run with the
+    /// anonymous tier's.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub limits: Option<JobLimits>,
+    /// Set once the job is `done`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<MoveResponse>,
+    /// Set when the job `failed`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<ErrorBody>,
+    /// Unix timestamps in seconds.
+    pub created_at: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub finished_at: Option<u64>,
+}
+
+impl Job {
+    pub fn is_finished(&self) -> bool {
+        matches!(self.status, JobStatus::Done | JobStatus::Failed)
+    }
+}
+
+/// Queue a search and return at once with its job ID.
+#[utoipa::path(
+    post,
+    path = "/api/v1/jobs",
+    tag = "engine",
+    request_body = JobRequest,
+    responses(
+        (status = 202, description = "Queued; poll the `Location` header", body = Job),
+        (status = 400, description = "Level out of range or bad webhook URL", body = ErrorBody),
+        (status = 401, description = "Unknown API key, or a webhook without one", body = ErrorBody),
+        (status = 409, description = "The game is already over", body = ErrorBody),
+        (status = 422, description = "Malformed or illegal position", body = ErrorBody),
+        (status = 429, description = "Rate limit exceeded; see `Retry-After`", body = ErrorBody),
+        (status = 501, description = "Webhook given but the server was built without `webhooks`", body = ErrorBody),
+        (status = 503, description = "Job queue is full", body = ErrorBody),
+    ),
+)]
+pub async fn create_job(
+    State(app): State<AppState>,
+    Extension(tier): Extension<Tier>,
+    ApiJson(request): ApiJson<JobRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    // Refuse now what the search would refuse later.
+    app.search_options(&tier, &request.request, &request.params)?;
+    let state = GameState::from_history(&parse_history(&request.request.position)?)?;
+    if state.legal_moves().is_empty() || state.winner().is_some() {
+        return Err(connect4::GameError::NoMoves.into());
+    }
+    if let Some(url) = &request.webhook {
+        if !cfg!(feature = "webhooks") {
+            return Err(ApiError::new(
+                StatusCode::NOT_IMPLEMENTED,
+                "webhooks need the server's `webhooks` feature",
+            ));
+        }
+        if tier.key.is_none() {
+            return Err(ApiError::new(
+                StatusCode::UNAUTHORIZED,
+                "webhooks need an API key",
+            ));
+        }
+        webhook_target(url, &app.config.jobs.webhook_hosts)
+            .map_err(|reason| ApiError::new(StatusCode::BAD_REQUEST, reason))?;
+    }
+    let permit = app.jobs.admit().ok_or_else(|| {
+        ApiError::new(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "job queue is full, try again later",
+        )
+    })?;
+    let job = Job {
+        id: new_id(),
+        status: JobStatus::Queued,
+        request,
+        limits: Some(JobLimits::for_tier(&tier, &app.jobs.config)),
+        result: None,
+        error: None,
+        created_at: unix_now(),
+        finished_at: None,
+    };
+    app.save_job(&job);
+    tokio::spawn(run(app, job.clone(), permit));
+    let location = format!("/api/v1/jobs/{}", job.id);
+    Ok((
+        StatusCode::ACCEPTED,
+        [(header::LOCATION, location)],
+        Json(job),
+    ))
+}
+
+/// Current state of a job, with the move once it is done.
+#[utoipa::path(
+    get,
+    path = "/api/v1/jobs/{id}",
+    tag = "engine",
+    params(("id" = String, Path, description = "Job ID")),
+    responses(
+        (status = 200, body = Job),
+        (status = 404, description = "Unknown job", body = ErrorBody),
+    ),
+)]
+pub async fn get_job(
+    State(app): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<Json<Job>, ApiError> {
+    app.storage
+        .job(&id)?
+        .map(Json)
+        .ok_or_else(|| ApiError::new(StatusCode::NOT_FOUND, "unknown job"))
+}
+
+/// Queue the jobs a previous run left unfinished. Jobs beyond the queue's
+/// capacity fail rather than wait.
+pub fn resume(app: &AppState) {
+    let jobs = match app.storage.unfinished_jobs() {
+        Ok(jobs) => jobs,
+        Err(err) => {
+            warn!("cannot load unfinished jobs: {err:#}");
+            return;
+        }
+    };
+    if !jobs.is_empty() {
+        info!("Resuming {} unfinished jobs", jobs.len());
+    }
+    for mut job in jobs {
+        match app.jobs.admit() {
+            Some(permit) => {
+                job.status = JobStatus::Queued;
+                tokio::spawn(run(app.clone(), job, permit));
+            }
+            None => {
+                let err = ApiError::new(StatusCode::SERVICE_UNAVAILABLE, "job queue was full");
+                finish(app, job, Err(err));
+            }
+        }
+    }
+}
+
+async fn run(app: AppState, mut job: Job, _admitted: OwnedSemaphorePermit) {
+    let _running = app
+        .jobs
+        .running
+        .clone()
+        .acquire_owned()
+        .await
+        .expect("job semaphores are never closed");
+    job.status = JobStatus::Running;
+    app.save_job(&job);
+    let anonymous = Tier::anonymous(&app.config);
+    let tier = match job.limits {
+        Some(limits) => Tier {
+            max_level: limits.max_level,
+            max_search: Duration::from_millis(limits.max_search_ms),
+            ..anonymous
+        },
+        None => anonymous,
+    };
+    let (request, params) = (job.request.request.clone(), job.request.params.clone());
+    let result =
+        if params.algorithm == Some(Algorithm::Solver) && !app.config.cluster.workers.is_empty() {
+            cluster::solve(&app, &tier, request, params).await
+        } else {
+            search_when_free(&app, &tier, request, params).await
+        };
+    let job = finish(&app, job, result);
+    if let Some(url) = &job.request.webhook {
+        notify(&app.config.jobs.webhook_hosts, url, &job).await;
+    }
+}
+
+/// `AppState::search`, waiting out a full search pool instead of failing.
+pub async fn search_when_free(
+    app: &AppState,
+    tier: &Tier,
+    request: MoveRequest,
+    params: EngineParams,
+) -> Result<MoveResponse, ApiError> {
+    loop {
+        match app.search(tier, request.clone(), params.clone()).await {
+            Err(err) if err.status == StatusCode::SERVICE_UNAVAILABLE => {
+                tokio::time::sleep(BUSY_RETRY).await;
+            }
+            result => return result,
+        }
+    }
+}
+
+fn finish(app: &AppState, mut job: Job, result: Result<MoveResponse, ApiError>) -> Job {
+    match result {
+        Ok(mv) => {
+            job.status = JobStatus::Done;
+            job.result = Some(mv);
+        }
+        Err(err) => {
+            job.status = JobStatus::Failed;
+            job.error = Some(err.body());
+        }
+    }
+    job.finished_at = Some(unix_now());
+    app.save_job(&job);
+    job
+}
+
+/// The host and port `url` may be sent to, or why it may not. Hosts given as
+/// an address are checked here; names are checked once resolved.
+fn webhook_target(url: &str, allowed: &[String]) -> Result<(String, u16), &'static str> {
+    let uri: Uri = url
+        .parse()
+        .map_err(|_| "webhook must be an http or https URL")?;
+    let default_port = match uri.scheme_str() {
+        Some("http") => 80,
+        Some("https") => 443,
+        _ => return Err("webhook must be an http or https URL"),
+    };
+    let host = uri
+        .host()
+        .ok_or("webhook must be an http or https URL")?
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .to_ascii_lowercase();
+    if !allowed
+        .iter()
+        .any(|allowed| allowed.eq_ignore_ascii_case(&host))
+    {
+        return Err("webhook host is not listed in jobs.webhook_hosts");
+    }
+    if host.parse().is_ok_and(|ip| !is_public(ip)) {
+        return Err("webhook host is not a public address");
+    }
+    Ok((host, uri.port_u16().unwrap_or(default_port)))
+}
+
+/// Whether `ip` is on the public internet rather than the server's own
+/// machine or network.
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                || ip.is_documentation()
+                || a == 0
+                // Carrier-grade NAT, 100.64.0.0/10.
+                || (a == 100 && b & 0xc0 == 64))
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public(IpAddr::V4(ip)),
+            None => {
+                !(ip.is_loopback()
+                    || ip.is_unspecified()
+                    || ip.is_multicast()
+                    || ip.is_unique_local()
+                    || ip.is_unicast_link_local())
+            }
+        },
+    }
+}
+
+#[cfg(feature = "webhooks")]
+async fn notify(allowed: &[String], url: &str, job: &Job) {
+    if let Err(err) = deliver(allowed, url, job).await {
+        warn!(id = %job.id, "job webhook failed: {err:#}");
+    }
+}
+
+/// Checks the webhook again, since the config may have changed since the job
+/// was submitted, and sends it to the address that was checked so the name
+/// cannot resolve elsewhere in between.
+#[cfg(feature = "webhooks")]
+async fn deliver(allowed: &[String], url: &str, job: &Job) -> anyhow::Result<()> {
+    let (host, port) = webhook_target(url, allowed).map_err(anyhow::Error::msg)?;
+    let addrs: Vec<_> = tokio::net::lookup_host((host.as_str(), port))
+        .await?
+        .collect();
+    let Some(&addr) = addrs.first() else {
+        anyhow::bail!("{host} does not resolve");
+    };
+    if !addrs.iter().all(|addr| is_public(addr.ip())) {
+        anyhow::bail!("{host} resolves to a private address");
+    }
+    reqwest::Client::builder()
+        .timeout(Duration::from_secs(10))
+        .redirect(reqwest::redirect::Policy::none())
+        .resolve(&host, addr)
+        .build()?
+        .post(url)
+        .json(job)
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(())
+}
+
+/// Webhooks are refused at submission without the feature; a job resumed
+/// from a database written by a build that had it is only logged.
+#[cfg(not(feature = "webhooks"))]
+async fn notify(_allowed: &[String], _url: &str, job: &Job) {
+    warn!(id = %job.id, "job webhook skipped: server built without `webhooks`");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn jobs_round_trip_through_json() {
+        let job = Job {
+            id: "abc".to_string(),
+            status: JobStatus::Done,
+            request: serde_json::from_str(r#"{"position":"R3","level":9,"algorithm":"solver"}"#)
+                .unwrap(),
+            limits: Some(JobLimits {
+                max_level: 15,
+                max_search_ms: 1000,
+            }),
+            result: Some(MoveResponse::new(3, 9)),
+            error: None,
+            created_at: 1,
+            finished_at: Some(2),
+        };
+        let json = serde_json::to_value(&job).unwrap();
+        assert_eq!(json["request"]["algorithm"], "solver");
+        assert_eq!(json["status"], "done");
+        assert!(json.get("error").is_none());
+        let back: Job = serde_json::from_value(json).unwrap();
+        assert_eq!(back.request.request.level, 9);
+        assert_eq!(back.limits, job.limits);
+        assert!(back.is_finished());
+    }
+
+    #[test]
+    fn webhooks_only_reach_listed_public_hosts() {
+        let allowed = [
+            "hooks.example.com".to_string(),
+            "10.0.0.5".to_string(),
+            "[::1]".to_string(),
+            "::1".to_string(),
+        ];
+        assert_eq!(
+            webhook_target("https://Hooks.example.com/done", &allowed),
+            Ok(("hooks.example.com".to_string(), 443))
+        );
+        assert_eq!(
+            webhook_target("http://hooks.example.com:8080/", &allowed),
+            Ok(("hooks.example.com".to_string(), 8080))
+        );
+        for url in [
+            "ftp://hooks.example.com/",
+            "hooks.example.com/done",
+            "http://example.com/",
+            "http://hooks.example.com.evil.test/",
+            "http://10.0.0.5/",
+            "http://[::1]/",
+        ] {
+            assert!(webhook_target(url, &allowed).is_err(), "{url}");
+        }
+
+        for ip in ["93.184.216.34", "2606:2800:220:1::1"] {
+            assert!(is_public(ip.parse().unwrap()), "{ip}");
+        }
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "::1",
+            "fe80::1",
+            "fd00::1",
+            "::ffff:127.0.0.1",
+        ] {
+            assert!(!is_public(ip.parse().unwrap()), "{ip}");
+        }
+    }
+}
@@ -0,0 +1,168 @@
+//! Where the server accepts connections.
+//!
+//! By default it binds `bind:port` itself. Behind a reverse proxy it can
+//! listen on a Unix socket instead (`unix_socket`), or take over a socket that
+//! systemd opened for it (`socket_activation`, see `systemd.socket(5)`), so
+//! the unit can bind a privileged port or start the server on the first
+//! connection while the process itself runs without privileges.
+//!
+//! Unix-socket connections carry no client address: unless
+//! `rate_limit.trust_forwarded_for` lets the proxy's `X-Forwarded-For`
+//! through, every client shares one rate-limit bucket.
+use std::{fmt, net::SocketAddr};
+
+use anyhow::{bail, Context};
+
+use crate::config::Config;
+
+pub enum Listener {
+    Tcp(std::net::TcpListener),
+    #[cfg(unix)]
+    Unix(std::os::unix::net::UnixListener),
+}
+
+impl Listener {
+    /// Bind (or inherit) the listening socket named by `config`. Call this
+    /// before starting anything else so a taken port fails startup at once.
+    pub fn open(config: &Config) -> anyhow::Result<Self> {
+        if config.socket_activation {
+            return inherit();
+        }
+        #[cfg(unix)]
+        if let Some(path) = &config.unix_socket {
+            return bind_unix(path).map(Self::Unix);
+        }
+        let addr = SocketAddr::new(config.bind, config.port);
+        let listener = std::net::TcpListener::bind(addr)
+            .with_context(|| format!("cannot listen on {addr}"))?;
+        Ok(Self::Tcp(listener))
+    }
+}
+
+impl fmt::Display for Listener {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tcp(listener) => match listener.local_addr() {
+                Ok(addr) => write!(f, "{addr}"),
+                Err(_) => f.write_str("an inherited TCP socket"),
+            },
+            #[cfg(unix)]
+            Self::Unix(listener) => match listener.local_addr() {
+                Ok(addr) => match addr.as_pathname() {
+                    Some(path) => write!(f, "unix:{}", path.display()),
+                    None => f.write_str("an unnamed Unix socket"),
+                },
+                Err(_) => f.write_str("an inherited Unix socket"),
+            },
+        }
+    }
+}
+
+/// The first socket passed by systemd (`LISTEN_FDS`), TCP or Unix stream.
+fn inherit() -> anyhow::Result<Listener> {
+    let mut fds = listenfd::ListenFd::from_env();
+    if fds.len() == 0 {
+        bail!("socket_activation is set but systemd passed no socket (LISTEN_FDS)");
+    }
+    if fds.len() > 1 {
+        tracing::warn!("systemd passed {} sockets; using the first", fds.len());
+    }
+    if let Ok(Some(listener)) = fds.take_tcp_listener(0) {
+        return Ok(Listener::Tcp(listener));
+    }
+    #[cfg(unix)]
+    if let Some(listener) = fds.take_unix_listener(0)? {
+        return Ok(Listener::Unix(listener));
+    }
+    bail!("the socket passed by systemd is not a TCP or Unix stream socket")
+}
+
+/// Bind `path`, replacing a socket file left behind by an earlier run. Any
+/// other file in the way is an error rather than something to delete.
+#[cfg(unix)]
+fn bind_unix(path: &std::path::Path) -> anyhow::Result<std::os::unix::net::UnixListener> {
+    use std::os::unix::fs::FileTypeExt;
+
+    if let Ok(meta) = std::fs::symlink_metadata(path) {
+        if meta.file_type().is_socket() {
+            std::fs::remove_file(path)
+                .with_context(|| format!("cannot remove stale socket {}", path.display()))?;
+        }
+    }
+    std::os::unix::net::UnixListener::bind(path)
+        .with_context(|| format!("cannot listen on {}", path.display()))
+}
+
+/// `axum::serve` for a Unix socket: one hyper connection per client, with
+/// upgrades enabled for the WebSocket endpoints.
+#[cfg(unix)]
+pub async fn serve_unix(listener: tokio::net::UnixListener, app: axum::Router) {
+    use hyper_util::{
+        rt::{TokioExecutor, TokioIo},
+        server::conn::auto,
+        service::TowerToHyperService,
+    };
+
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(err) => {
+                // Usually out of file descriptors; back off like `axum::serve`.
+                tracing::warn!("accept failed: {err}");
+                tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+                continue;
+            }
+        };
+        let service = TowerToHyperService::new(app.clone());
+        tokio::spawn(async move {
+            let builder = auto::Builder::new(TokioExecutor::new());
+            let connection = builder.serve_connection_with_upgrades(TokioIo::new(stream), service);
+            if let Err(err) = connection.await {
+                tracing::debug!("connection closed: {err}");
+            }
+        });
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use axum::routing::get;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn serves_http_over_a_unix_socket() {
+        let path = std::env::temp_dir().join(format!("connect4-{}.sock", std::process::id()));
+        let config = Config {
+            unix_socket: Some(path.clone()),
+            ..Config::default()
+        };
+        // The second bind replaces the socket file the first one left.
+        drop(Listener::open(&config).unwrap());
+        let listener = Listener::open(&config).unwrap();
+        assert_eq!(listener.to_string(), format!("unix:{}", path.display()));
+        let Listener::Unix(listener) = listener else {
+            panic!("expected a Unix listener");
+        };
+        listener.set_nonblocking(true).unwrap();
+        let listener = tokio::net::UnixListener::from_std(listener).unwrap();
+        let app = axum::Router::new().route("/", get(|| async { "hello" }));
+        tokio::spawn(serve_unix(listener, app));
+
+        let mut stream = tokio::net::UnixStream::connect(&path).await.unwrap();
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+        assert!(response.ends_with("hello"));
+        std::fs::remove_file(&path).unwrap();
+
+        std::fs::write(&path, "not a socket").unwrap();
+        assert!(Listener::open(&config).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,233 @@
+//! Read-only spectating of games in progress.
+//!
+//! Sessions and rooms created with `spectators: true` keep a broadcast channel
+//! that every accepted move is published on, together with the game clock.
+//! A spectator gets the whole board once, on connect, and after that only the
+//! cells that changed since the last message it was sent, so a socket that
+//! fell behind catches up in one message. Spectator sockets never send anything
+//! back into the game; between moves they get a `clock` message every second
+//! so a projector view can show the running clock without keeping time itself.
+//! `GET /games/live` lists what can be watched.
+use std::time::{Duration, Instant};
+
+use axum::{
+    extract::{ws::Message, ws::WebSocket, State},
+    routing::get,
+    Json, Router,
+};
+use connect4::{parse_history, BoardModel, Cell, CellChange, GameState, GameStatus, Player};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+use utoipa::ToSchema;
+
+use crate::{clock::Clock, AppState};
+
+/// How often spectators get a `clock` message between moves.
+const CLOCK_TICK: Duration = Duration::from_secs(1);
+
+pub fn routes() -> Router<AppState> {
+    Router::new().route("/games/live", get(list_live))
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SpectatorMessage {
+    /// Sent on connect.
+    State {
+        history: String,
+        board: BoardModel,
+        clock: Clock,
+    },
+    /// Sent after every move and when a flag falls: the discs added or
+    /// removed since the previous message, and the board's new status.
+    Update {
+        history: String,
+        changes: Vec<CellChange>,
+        status: GameStatus,
+        winner: Option<Player>,
+        winning_line: Option<Vec<Cell>>,
+        clock: Clock,
+    },
+    Clock {
+        clock: Clock,
+    },
+}
+
+impl SpectatorMessage {
+    fn clock(&self) -> Clock {
+        match self {
+            Self::State { clock, .. } | Self::Update { clock, .. } | Self::Clock { clock } => {
+                *clock
+            }
+        }
+    }
+
+    /// The position a `State` or `Update` shows.
+    fn position(&self) -> Option<GameState> {
+        let (Self::State { history, .. } | Self::Update { history, .. }) = self else {
+            return None;
+        };
+        let moves = parse_history(history).ok()?;
+        GameState::from_history(&moves).ok()
+    }
+
+    /// A published `State` as the `Update` from `shown` to `position`.
+    fn update(self, shown: &GameState, position: &GameState) -> Self {
+        match self {
+            Self::State {
+                history,
+                board,
+                clock,
+            } => Self::Update {
+                history,
+                changes: shown.diff(position),
+                status: board.status,
+                winner: board.winner,
+                winning_line: board.winning_line,
+                clock,
+            },
+            other => other,
+        }
+    }
+}
+
+/// Channel a game that opted in publishes its moves on. Dropping it (the
+/// game expires) ends every spectator socket.
+#[derive(Clone, Debug)]
+pub struct Broadcast {
+    events: broadcast::Sender<SpectatorMessage>,
+}
+
+impl Default for Broadcast {
+    fn default() -> Self {
+        Self {
+            events: broadcast::channel(32).0,
+        }
+    }
+}
+
+impl Broadcast {
+    /// Publish the position to everyone watching.
+    pub fn send(&self, history: String, board: BoardModel, clock: Clock) {
+        // No receivers simply means nobody is watching right now.
+        let _ = self.events.send(SpectatorMessage::State {
+            history,
+            board,
+            clock,
+        });
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<SpectatorMessage> {
+        self.events.subscribe()
+    }
+
+    pub fn spectators(&self) -> usize {
+        self.events.receiver_count()
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum LiveKind {
+    /// A human against the engine.
+    Session,
+    /// Two humans.
+    Room,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct LiveGame {
+    pub kind: LiveKind,
+    /// Session ID or room code.
+    pub id: String,
+    /// Engine level of a session.
+    pub level: Option<u8>,
+    pub history: String,
+    pub board: BoardModel,
+    pub clock: Clock,
+    /// Sockets currently watching.
+    pub spectators: usize,
+    /// WebSocket path to watch the game on.
+    pub watch: String,
+}
+
+/// Games in progress that accept spectators, newest first.
+#[utoipa::path(
+    get,
+    path = "/api/v1/games/live",
+    tag = "spectators",
+    responses((status = 200, body = [LiveGame])),
+)]
+pub(crate) async fn list_live(State(app): State<AppState>) -> Json<Vec<LiveGame>> {
+    let mut games: Vec<(u64, LiveGame)> = app.sessions.filter_map(|id, session| {
+        let live = session.broadcast.as_ref()?;
+        let board = session.game.state().board_model();
+        let clock = session.clock.reading();
+        (board.status == GameStatus::InProgress && clock.flagged.is_none()).then(|| {
+            let game = LiveGame {
+                kind: LiveKind::Session,
+                id: id.to_string(),
+                level: Some(session.level),
+                history: session.history(),
+                clock,
+                board,
+                spectators: live.spectators(),
+                watch: format!("/api/v1/games/{id}/watch"),
+            };
+            (session.started_at, game)
+        })
+    });
+    games.extend(app.rooms.filter_map(|code, room| room.live_game(code)));
+    games.sort_by_key(|&(started_at, _)| std::cmp::Reverse(started_at));
+    Json(games.into_iter().map(|(_, game)| game).collect())
+}
+
+/// Relay a game's updates to one spectator until either side goes away.
+pub async fn watch(
+    mut socket: WebSocket,
+    mut events: broadcast::Receiver<SpectatorMessage>,
+    snapshot: SpectatorMessage,
+) {
+    let (mut clock, mut read_at) = (snapshot.clock(), Instant::now());
+    let mut shown = snapshot.position();
+    if send(&mut socket, &snapshot).await.is_err() {
+        return;
+    }
+    let mut tick = tokio::time::interval_at(tokio::time::Instant::now() + CLOCK_TICK, CLOCK_TICK);
+    loop {
+        let message = tokio::select! {
+            incoming = socket.recv() => match incoming {
+                // Spectators have nothing to say; anything but a close is ignored.
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => continue,
+            },
+            event = events.recv() => match event {
+                Ok(event) => {
+                    (clock, read_at) = (event.clock(), Instant::now());
+                    match (event.position(), &shown) {
+                        (Some(position), Some(before)) => {
+                            let update = event.update(before, &position);
+                            shown = Some(position);
+                            update
+                        }
+                        _ => event,
+                    }
+                }
+                // The next update is from the last position sent anyway.
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+            _ = tick.tick(), if clock.running.is_some() => SpectatorMessage::Clock {
+                clock: clock.advanced(read_at.elapsed()),
+            },
+        };
+        if send(&mut socket, &message).await.is_err() {
+            break;
+        }
+    }
+}
+
+async fn send(socket: &mut WebSocket, message: &SpectatorMessage) -> Result<(), axum::Error> {
+    let text = serde_json::to_string(message).expect("spectator messages serialize");
+    socket.send(Message::Text(text)).await
+}
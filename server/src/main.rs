@@ -1,81 +1,1396 @@
+mod admin;
+mod archive;
+mod arena;
+mod audit;
+mod auth;
+mod book;
+mod clock;
+mod cluster;
+mod config;
+mod error;
+mod jobs;
+mod listen;
+mod live;
+mod metrics;
+mod move_cache;
+mod openapi;
+mod rate_limit;
+mod rooms;
+mod rpc;
+mod search_pool;
+mod selfplay;
+mod sessions;
+mod shared_cache;
+mod storage;
+mod telemetry;
+mod tls;
+mod users;
+mod web;
+
+use std::{
+    convert::Infallible,
+    net::SocketAddr,
+    path::Path,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use auth::Tier;
 use axum::{
-    extract::Query,
-    http::{header, StatusCode},
-    response::{IntoResponse, Response},
-    routing::get,
-    Json, Router,
+    extract::State,
+    http::{header, HeaderMap, StatusCode},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Response,
+    },
+    routing::{get, post},
+    Extension, Json, Router,
+};
+use clap::Parser;
+use config::Config;
+use connect4::{
+    best_move_with_table, best_move_within,
+    explain::{preview_moves, MovePreview},
+    parse_history,
+    render::{self, RenderOptions},
+    review::{review_game, DEFAULT_REVIEW_DEPTH},
+    tt::{Replacement, TranspositionTable},
+    validate_history, Algorithm, Analysis, BoardModel, CancelToken, EvalPreset, GameError,
+    GameState, GameStatus, HistoryDiagnostic, MoveOrdering, MoveRequest, MoveResponse, Player,
+    SearchInfo, SearchLimits, SearchOptions, SearchOutcome,
 };
-use connect4::{best_move, MoveRequest, MoveResponse};
-use tokio::net::TcpListener;
-use tower_http::{cors::CorsLayer, services::ServeDir, trace::TraceLayer};
-use tracing::info;
-
-#[tokio::main]
-async fn main() -> anyhow::Result<()> {
-    init_tracing();
-    let app = app_router();
-
-    let addr = "0.0.0.0:3000";
-    let listener = TcpListener::bind(addr).await?;
+use error::{ApiError, ApiJson, ApiQuery, ErrorBody};
+use move_cache::{MoveCache, MoveCacheStats};
+use rate_limit::RateLimitLayer;
+use search_pool::SearchPool;
+use storage::Storage;
+use tokio::{net::TcpListener, sync::mpsc};
+use tokio_stream::{wrappers::UnboundedReceiverStream, Stream};
+use tower_http::{
+    cors::{AllowOrigin, CorsLayer},
+    trace::{DefaultMakeSpan, DefaultOnResponse, TraceLayer},
+    LatencyUnit,
+};
+use tracing::{info, warn, Level};
+
+fn main() -> anyhow::Result<()> {
+    let cli = config::Cli::parse();
+    let (issue_api_key, admin, rpc) = (cli.issue_api_key.clone(), cli.admin, cli.rpc.clone());
+    // Over stdio, stdout carries the JSON-RPC replies.
+    init_tracing(rpc == Some(rpc::Transport::Stdio));
+    let config = Config::resolve(cli)?;
+    if let Some(name) = issue_api_key {
+        return issue_key(&config, name, admin);
+    }
+    let mut runtime = tokio::runtime::Builder::new_multi_thread();
+    if let Some(threads) = config.worker_threads {
+        runtime.worker_threads(threads);
+    }
+    let runtime = runtime.enable_all().build()?;
+    match rpc {
+        Some(transport) => runtime.block_on(rpc::serve(config, transport)),
+        None => runtime.block_on(serve(config)),
+    }
+}
+
+async fn serve(config: Config) -> anyhow::Result<()> {
+    if !config.static_dir.is_dir() {
+        if web::routes(&config.static_dir).is_some() {
+            info!(
+                "static dir {} does not exist; serving the embedded web UI",
+                config.static_dir.display()
+            );
+        } else {
+            warn!(
+                "static dir {} does not exist; only the API will be served",
+                config.static_dir.display()
+            );
+        }
+    }
+    let listener = listen::Listener::open(&config)?;
+    let tls_config = config.tls.clone();
+    let trust_forwarded_for = config.rate_limit.trust_forwarded_for;
+    let storage = open_storage(config.database.as_deref())?;
+    let state = AppState::new(config, storage);
+    state.book.load()?;
+    state.book.spawn_watcher();
+    state.sessions.spawn_sweeper();
+    state.rooms.spawn_sweeper();
+    clock::spawn_ticker(state.clone());
+    jobs::resume(&state);
+    let app = app_router(state);
+
+    let listening_on = listener.to_string();
+    let listener = match listener {
+        listen::Listener::Tcp(listener) => listener,
+        #[cfg(unix)]
+        listen::Listener::Unix(listener) => {
+            if tls_config.is_some() {
+                anyhow::bail!("tls cannot be served on a Unix socket");
+            }
+            if !trust_forwarded_for {
+                warn!("without rate_limit.trust_forwarded_for, Unix-socket clients share one rate-limit bucket");
+            }
+            info!("Listening on {listening_on}");
+            listener.set_nonblocking(true)?;
+            listen::serve_unix(tokio::net::UnixListener::from_std(listener)?, app).await;
+            return Ok(());
+        }
+    };
+    let addr = listener.local_addr()?;
+    // Connection info feeds the per-client rate limiter.
+    let app = app.into_make_service_with_connect_info::<SocketAddr>();
+    if let Some(tls) = &tls_config {
+        if let Some(port) = tls.redirect_http_port {
+            let redirect_addr = SocketAddr::new(addr.ip(), port);
+            let listener = TcpListener::bind(redirect_addr).await?;
+            info!("Redirecting http://{redirect_addr} to HTTPS");
+            tokio::spawn(async move {
+                if let Err(err) = axum::serve(listener, tls::redirect_router(addr.port())).await {
+                    warn!("HTTP redirect listener failed: {err}");
+                }
+            });
+        }
+        #[cfg(feature = "tls")]
+        {
+            info!("Listening on https://{addr}");
+            return tls::serve(listener, tls, app).await;
+        }
+    }
+    listener.set_nonblocking(true)?;
+    let listener = TcpListener::from_std(listener)?;
     info!("Listening on http://{addr}");
     axum::serve(listener, app).await?;
     Ok(())
 }
 
-fn init_tracing() {
-    let _ = tracing_subscriber::fmt()
-        .with_env_filter("info,tower_http=debug")
-        .try_init();
+/// Keys issued here live in the database, so partners can be added without
+/// editing the config file or restarting the server. Only the key's digest is
+/// stored; this is the one time the key itself is shown.
+fn issue_key(config: &Config, name: String, admin: bool) -> anyhow::Result<()> {
+    if config.database.is_none() || cfg!(not(feature = "sqlite")) {
+        anyhow::bail!("issuing API keys needs a database (`--db`, `sqlite` feature)");
+    }
+    let storage = open_storage(config.database.as_deref())?;
+    let secret = format!("c4_{}", auth::new_secret());
+    storage.put_api_key(&auth::ApiKey {
+        key: auth::hash_key(&secret),
+        name,
+        limits: auth::TierLimits::default(),
+        admin,
+    })?;
+    println!("{secret}");
+    Ok(())
+}
+
+/// `RUST_LOG` overrides the default filter. `CONNECT4_LOG_FORMAT=json` writes
+/// one JSON object per line, span fields included, for log pipelines.
+fn init_tracing(to_stderr: bool) {
+    use tracing_subscriber::fmt::writer::BoxMakeWriter;
+    let filter = tracing_subscriber::EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| "info,tower_http=debug".into());
+    let writer = if to_stderr {
+        BoxMakeWriter::new(std::io::stderr)
+    } else {
+        BoxMakeWriter::new(std::io::stdout)
+    };
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(writer);
+    let _ = match std::env::var("CONNECT4_LOG_FORMAT").as_deref() {
+        Ok("json") => builder.json().try_init(),
+        _ => builder.try_init(),
+    };
+}
+
+/// Use SQLite when a database file is configured and the binary was built
+/// with the `sqlite` feature; otherwise keep records in memory.
+fn open_storage(path: Option<&Path>) -> anyhow::Result<Arc<dyn Storage>> {
+    let Some(path) = path else {
+        return Ok(Arc::new(storage::MemoryStorage::default()));
+    };
+    #[cfg(feature = "sqlite")]
+    {
+        info!("Persisting games to {}", path.display());
+        Ok(Arc::new(storage::SqliteStorage::open(path)?))
+    }
+    #[cfg(not(feature = "sqlite"))]
+    {
+        warn!(
+            "database {} ignored: server built without the `sqlite` feature",
+            path.display()
+        );
+        Ok(Arc::new(storage::MemoryStorage::default()))
+    }
+}
+
+/// Shared handles for the handlers that need them; the stateless endpoints
+/// ignore it entirely.
+#[derive(Clone)]
+struct AppState {
+    config: Arc<Config>,
+    sessions: sessions::SessionStore,
+    rooms: rooms::RoomStore,
+    storage: Arc<dyn Storage>,
+    move_cache: MoveCache,
+    /// Moves shared with other replicas through Redis, when configured.
+    shared_cache: shared_cache::SharedCache,
+    search_pool: SearchPool,
+    jobs: jobs::JobQueue,
+    arena: arena::Arena,
+    latencies: metrics::Latencies,
+    telemetry: telemetry::Telemetry,
+    audit: audit::AuditLog,
+    book: book::BookStore,
+    /// One transposition table for every search, kept for the server's life.
+    table: Arc<TranspositionTable>,
+    ponder: sessions::PonderCounters,
+    /// Unknown API keys presented per IP, which authentication throttles.
+    auth_failures: rate_limit::AuthFailures,
+    /// Token buckets shared by every route that runs a search.
+    rate_limit: RateLimitLayer,
+    started: Instant,
+}
+
+impl Default for AppState {
+    fn default() -> Self {
+        Self::new(
+            Config::default(),
+            Arc::new(storage::MemoryStorage::default()),
+        )
+    }
+}
+
+impl AppState {
+    fn new(config: Config, storage: Arc<dyn Storage>) -> Self {
+        Self {
+            sessions: sessions::SessionStore::new(config.session_ttl()),
+            rooms: rooms::RoomStore::new(config.session_ttl()),
+            search_pool: SearchPool::new(config.search),
+            jobs: jobs::JobQueue::new(&config.jobs),
+            arena: arena::Arena::default(),
+            move_cache: MoveCache::new(config.move_cache_entries),
+            shared_cache: shared_cache::SharedCache::new(config.redis.as_ref()),
+            latencies: metrics::Latencies::default(),
+            telemetry: telemetry::Telemetry::default(),
+            audit: audit::AuditLog::new(config.audit.clone()),
+            book: book::BookStore::new(&config.book),
+            table: Arc::new(TranspositionTable::with_bytes(
+                config.transposition_table_mb << 20,
+                Replacement::default(),
+            )),
+            ponder: sessions::PonderCounters::default(),
+            auth_failures: rate_limit::AuthFailures::new(config.rate_limit.trust_forwarded_for),
+            rate_limit: RateLimitLayer::new(config.rate_limit),
+            started: Instant::now(),
+            storage,
+            config: Arc::new(config),
+        }
+    }
+
+    /// Levels above the tier's maximum are refused like any other
+    /// out-of-range depth.
+    fn check_level(&self, tier: &Tier, level: u8) -> Result<(), ApiError> {
+        let max = tier.max_level;
+        if level > max {
+            let hint = if tier.key.is_none() && max < self.config.max_level {
+                "; an API key allows deeper searches"
+            } else {
+                ""
+            };
+            return Err(ApiError {
+                code: "depth_out_of_range",
+                ..ApiError::new(
+                    StatusCode::BAD_REQUEST,
+                    format!("level {level} exceeds the maximum of {max}{hint}"),
+                )
+            });
+        }
+        Ok(())
+    }
+
+    fn record_game(&self, game: &storage::GameRecord) {
+        if let Err(err) = self.storage.record_game(game) {
+            warn!(id = %game.id, "failed to record game: {err:#}");
+        }
+        let entry = audit::AuditEntry::new(game, self.config.max_search_ms);
+        if let Err(err) = self.audit.append(&entry) {
+            warn!(id = %game.id, "failed to write audit log: {err:#}");
+        }
+        // Only games between a human and the engine are reviewed: their
+        // blunders feed both the player's and the aggregate statistics.
+        let review = if game.red_level.is_some() != game.blue_level.is_some() {
+            parse_history(&game.history)
+                .and_then(|moves| review_game(&moves, DEFAULT_REVIEW_DEPTH))
+                .ok()
+        } else {
+            None
+        };
+        self.telemetry.record(game, review.as_ref());
+        users::record_result(self, game, review.as_ref());
+    }
+
+    fn save_job(&self, job: &jobs::Job) {
+        if let Err(err) = self.storage.put_job(job) {
+            warn!(id = %job.id, "failed to save job: {err:#}");
+        }
+    }
+
+    /// Deadline for a search starting now: the client's `max_ms` if given,
+    /// never more than the tier allows.
+    fn search_limits(&self, tier: &Tier, max_ms: Option<u64>) -> SearchLimits {
+        let max_search = tier.max_search;
+        let budget = max_ms
+            .map(Duration::from_millis)
+            .map_or(max_search, |wanted| wanted.min(max_search));
+        self.with_table(SearchLimits::with_deadline(Instant::now() + budget))
+    }
+
+    fn with_table(&self, limits: SearchLimits) -> SearchLimits {
+        SearchLimits {
+            table: Some(self.table.clone()),
+            ..limits
+        }
+    }
+
+    /// `best_move` with the position cache in front: results are deterministic
+    /// for a position and level, so a stored column is as good as a new search.
+    /// With `limits` the search deepens until they stop it; answers cut short
+    /// are returned but never cached.
+    fn best_move(
+        &self,
+        req: MoveRequest,
+        limits: Option<SearchLimits>,
+    ) -> Result<(MoveResponse, SearchStats), GameError> {
+        let state = req.state()?;
+        let (key, level) = (state.key(), req.level);
+        let full_depth = |column| MoveResponse::new(column, usize::from(level));
+        match self.storage.cached_move(key, level) {
+            Ok(Some(column)) => {
+                self.move_cache.insert(&state, level, column);
+                return Ok((full_depth(column), SearchStats::cached("position_cache")));
+            }
+            Ok(None) => {}
+            Err(err) => warn!("position cache lookup failed: {err:#}"),
+        }
+        if let Some(column) = self.shared_cache.get(&state, level) {
+            self.move_cache.insert(&state, level, column);
+            return Ok((full_depth(column), SearchStats::cached("shared_cache")));
+        }
+        let mut stats = SearchStats::searched();
+        let mv = match limits {
+            Some(limits) => best_move_within(req, &limits, |info| stats.update(info))?,
+            None => best_move_with_table(req, Some(self.table.clone()), |info| stats.update(info))?,
+        };
+        if mv.depth == Some(usize::from(level)) {
+            self.move_cache.insert(&state, level, mv.column);
+            self.shared_cache.insert(&state, level, mv.column);
+            if let Err(err) = self.storage.cache_move(key, level, mv.column) {
+                warn!("position cache write failed: {err:#}");
+            }
+        }
+        Ok((mv, stats))
+    }
+
+    /// The solver's earlier answer for `state` from the tablebase, in the
+    /// asked orientation.
+    fn solved(&self, state: &GameState) -> Option<MoveResponse> {
+        let (key, mirrored) = state.canonical_key();
+        let solved = match self.storage.solved(key) {
+            Ok(solved) => solved?,
+            Err(err) => {
+                warn!("tablebase lookup failed: {err:#}");
+                return None;
+            }
+        };
+        let column = if mirrored {
+            connect4::WIDTH - 1 - solved.column
+        } else {
+            solved.column
+        };
+        let mut mv = MoveResponse::new(column, solved.depth);
+        mv.score = Some(solved.score);
+        Some(mv)
+    }
+
+    /// Keep a finished solve of `state`; searches cut short prove nothing.
+    fn record_solved(&self, state: &GameState, mv: &MoveResponse) {
+        let (Some(score), Some(depth), false) = (mv.score, mv.depth, mv.truncated) else {
+            return;
+        };
+        let (key, mirrored) = state.canonical_key();
+        let column = if mirrored {
+            connect4::WIDTH - 1 - mv.column
+        } else {
+            mv.column
+        };
+        let solved = storage::Solved {
+            column,
+            score,
+            depth,
+        };
+        if let Err(err) = self.storage.put_solved(key, &solved) {
+            warn!("tablebase write failed: {err:#}");
+        }
+    }
+
+    /// Turn a request's engine parameters into search options, refusing what
+    /// the tier or config forbids and clamping the rest. The solver ignores
+    /// `level` and may search to the end of the game, so it needs a tier that
+    /// allows level 15 and counts as level 15 on the search pool.
+    fn search_options(
+        &self,
+        tier: &Tier,
+        req: &MoveRequest,
+        params: &EngineParams,
+    ) -> Result<SearchOptions, ApiError> {
+        let algorithm = params.algorithm.unwrap_or_default();
+        if algorithm != Algorithm::AlphaBeta && !self.config.engine.algorithms.contains(&algorithm)
+        {
+            return Err(ApiError {
+                code: "algorithm_disabled",
+                ..ApiError::new(
+                    StatusCode::BAD_REQUEST,
+                    format!("algorithm {algorithm:?} is disabled on this server"),
+                )
+            });
+        }
+        self.check_level(tier, req.level)?;
+        let level = match algorithm {
+            Algorithm::Solver => {
+                self.check_level(tier, 15)?;
+                15
+            }
+            _ => req.level,
+        };
+        let randomness = params.randomness.unwrap_or(0.0);
+        let temperature = params.temperature.unwrap_or(0.0);
+        Ok(SearchOptions {
+            algorithm,
+            level,
+            // NaN and negatives mean "none".
+            randomness: if randomness > 0.0 {
+                randomness.min(1.0)
+            } else {
+                0.0
+            },
+            temperature: if temperature > 0.0 { temperature } else { 0.0 },
+            multipv: params
+                .multipv
+                .unwrap_or(1)
+                .clamp(1, self.config.engine.max_multipv),
+            seed: None,
+            weights: params.style.unwrap_or_default().weights(),
+            network: None,
+            policy: None,
+            late_move_reductions: false,
+            futility_pruning: false,
+            countermoves: false,
+            ordering: MoveOrdering::Center,
+        })
+    }
+
+    /// `best_move` on the search pool, keeping the runtime threads free. The
+    /// in-memory cache is checked first so hits never queue behind searches.
+    /// Non-default options bypass both caches: their answers are not a
+    /// function of position and level alone.
+    ///
+    /// Every decision is logged as an `engine decision` event inside an
+    /// `engine` span carrying the position key, level, algorithm, chosen
+    /// column, score, depth reached, node count, where the answer came from
+    /// and the elapsed milliseconds.
+    async fn search(
+        &self,
+        tier: &Tier,
+        req: MoveRequest,
+        params: EngineParams,
+    ) -> Result<MoveResponse, ApiError> {
+        let options = self.search_options(tier, &req, &params)?;
+        let state = req.state()?;
+        let player = state.to_move();
+        let span = tracing::info_span!(
+            "engine",
+            key = %format_args!("{:016x}", state.key()),
+            level = options.level,
+            algorithm = ?options.algorithm,
+            column = tracing::field::Empty,
+            score = tracing::field::Empty,
+            depth = tracing::field::Empty,
+            nodes = tracing::field::Empty,
+            source = tracing::field::Empty,
+            elapsed_ms = tracing::field::Empty,
+        );
+        let level = options.level;
+        let started = Instant::now();
+        let result = self.decide(tier, state, req, params, options).await;
+        let elapsed = started.elapsed();
+        span.record("elapsed_ms", elapsed.as_millis() as u64);
+        let _entered = span.enter();
+        match &result {
+            Ok((mv, stats)) => {
+                span.record("column", mv.column);
+                if let Some(depth) = mv.depth {
+                    span.record("depth", depth);
+                }
+                if let Some(score) = stats.score {
+                    span.record("score", score);
+                }
+                span.record("nodes", stats.nodes);
+                span.record("source", stats.source);
+                if stats.source == "search" {
+                    self.latencies.record(level, elapsed);
+                }
+                info!("engine decision");
+            }
+            Err(err) => info!(code = err.code, "engine decision failed"),
+        }
+        result.map(|(mv, _)| MoveResponse {
+            player: Some(player),
+            ..mv
+        })
+    }
+
+    async fn decide(
+        &self,
+        tier: &Tier,
+        state: GameState,
+        req: MoveRequest,
+        params: EngineParams,
+        options: SearchOptions,
+    ) -> Result<(MoveResponse, SearchStats), ApiError> {
+        let limits = self.search_limits(tier, params.max_ms);
+        if !options.is_default() {
+            let exact = solves_exactly(&options);
+            let app = self.clone();
+            return self
+                .search_pool
+                .run(options.level, move || {
+                    if let Some(mv) = app.solved(&state).filter(|_| exact) {
+                        let stats = SearchStats {
+                            score: mv.score,
+                            ..SearchStats::cached("tablebase")
+                        };
+                        return Ok((mv, stats));
+                    }
+                    let mut stats = SearchStats::searched();
+                    let outcome =
+                        connect4::search(&state, &options, &limits, |info| stats.update(info))?;
+                    stats.score = Some(outcome.score);
+                    let mv = outcome_response(outcome, &options);
+                    if exact {
+                        app.record_solved(&state, &mv);
+                    }
+                    Ok((mv, stats))
+                })
+                .await;
+        }
+        if let Some(book) = self.book.probe(&state) {
+            let mv = MoveResponse::new(book.column, usize::from(req.level));
+            let stats = SearchStats {
+                score: book.eval,
+                ..SearchStats::cached("book")
+            };
+            return Ok((mv, stats));
+        }
+        if let Some(column) = self.move_cache.get(&state, req.level) {
+            let mv = MoveResponse::new(column, usize::from(req.level));
+            return Ok((mv, SearchStats::cached("memory_cache")));
+        }
+        // Only a deadline the client asked for is worth the shallow passes
+        // that make a search interruptible.
+        let limits = params.max_ms.is_some().then_some(limits);
+        let app = self.clone();
+        self.search_pool
+            .run(req.level, move || Ok(app.best_move(req, limits)?))
+            .await
+    }
+}
+
+/// How an answer was reached, for the `engine` log span.
+#[derive(Copy, Clone, Debug)]
+struct SearchStats {
+    /// `search`, `book`, `memory_cache`, `position_cache`, `shared_cache` or
+    /// `tablebase`.
+    source: &'static str,
+    /// Unknown for cached answers, which store only the column, and for book
+    /// moves without an eval.
+    score: Option<i32>,
+    nodes: u64,
+}
+
+impl SearchStats {
+    fn cached(source: &'static str) -> Self {
+        Self {
+            source,
+            score: None,
+            nodes: 0,
+        }
+    }
+
+    fn searched() -> Self {
+        Self::cached("search")
+    }
+
+    /// Keep the latest completed iteration; node counts are cumulative.
+    fn update(&mut self, info: &SearchInfo) {
+        self.score = Some(info.score);
+        self.nodes = info.nodes;
+    }
+}
+
+/// Whether `options` ask the solver for one move with nothing to blur its
+/// proof, so that the answer is the position's exact result and can come
+/// from, or go to, the tablebase.
+fn solves_exactly(options: &SearchOptions) -> bool {
+    options.algorithm == Algorithm::Solver
+        && options.randomness <= 0.0
+        && options.temperature <= 0.0
+        && options.multipv <= 1
+        && !options.late_move_reductions
+        && !options.futility_pruning
+}
+
+/// Default options answer exactly like `best_move`; the others also report
+/// the score, and the ranked lines when more than one was asked for.
+fn outcome_response(outcome: SearchOutcome, options: &SearchOptions) -> MoveResponse {
+    let mut response = MoveResponse::new(outcome.column, outcome.depth);
+    if let Some(target_depth) = outcome.target_depth {
+        response = response.cut_short(target_depth, outcome.best_so_far);
+    }
+    if !options.is_default() {
+        response.score = Some(outcome.score);
+        if options.multipv > 1 {
+            response.lines = outcome.lines;
+        }
+    }
+    response
 }
 
-fn app_router() -> Router {
-    let api = Router::new().route("/move", get(handle_move));
-    let spa = Router::new().nest_service(
-        "/",
-        ServeDir::new("web/dist").append_index_html_on_directories(true),
+/// Each API version is a router of its own, nested under `/api/v{n}`; a new
+/// version gets an `api_v2` beside this one and both are mounted side by side.
+/// The unversioned `/api/*` paths predate versioning and stay an alias of v1
+/// so existing clients keep working; their responses carry a `Deprecation`
+/// header pointing at the versioned path.
+fn app_router(state: AppState) -> Router {
+    let config = state.config.clone();
+    let v1 = api_v1(state);
+    let mut router = Router::new().nest("/api/v1", v1.clone()).nest(
+        "/api",
+        v1.layer(axum::middleware::map_response(mark_deprecated)),
     );
-    Router::new()
-        .nest("/api", api)
-        .merge(spa)
+    if let Some(ui) = web::routes(&config.static_dir) {
+        router = router.merge(ui);
+    }
+    router
         .layer(
             CorsLayer::new()
-                .allow_methods([axum::http::Method::GET])
-                .allow_origin(axum::http::HeaderValue::from_static("*"))
-                .allow_headers([header::CONTENT_TYPE]),
+                .allow_methods([axum::http::Method::GET, axum::http::Method::POST])
+                .allow_origin(allowed_origins(&config.cors_origins))
+                .allow_headers([
+                    header::CONTENT_TYPE,
+                    header::AUTHORIZATION,
+                    header::HeaderName::from_static(auth::API_KEY_HEADER),
+                    header::HeaderName::from_static(users::USER_TOKEN_HEADER),
+                ])
+                .expose_headers([header::HeaderName::from_static("deprecation"), header::LINK]),
+        )
+        .layer(
+            TraceLayer::new_for_http()
+                .make_span_with(DefaultMakeSpan::new().level(Level::INFO))
+                .on_response(
+                    DefaultOnResponse::new()
+                        .level(Level::INFO)
+                        .latency_unit(LatencyUnit::Millis),
+                ),
         )
-        .layer(TraceLayer::new_for_http())
 }
 
-#[derive(Debug, serde::Deserialize)]
+async fn mark_deprecated(mut response: Response) -> Response {
+    let headers = response.headers_mut();
+    headers.insert("deprecation", header::HeaderValue::from_static("true"));
+    headers.insert(
+        header::LINK,
+        header::HeaderValue::from_static("</api/v1>; rel=\"successor-version\""),
+    );
+    response
+}
+
+fn api_v1(state: AppState) -> Router {
+    // Only the endpoints that run a search are worth throttling; sessions
+    // and rooms throttle theirs the same way.
+    let searches = Router::new()
+        .route("/move", get(handle_move).post(handle_move_json))
+        .route("/moves", post(handle_moves))
+        .route("/jobs", post(jobs::create_job))
+        .route("/move/stream", get(handle_move_stream))
+        .route("/analyze", get(handle_analyze))
+        .route("/review", post(archive::review_history))
+        .route_layer(state.rate_limit.clone());
+    Router::new()
+        .merge(searches)
+        .route("/state", get(handle_state))
+        .route("/preview", get(handle_preview))
+        .route("/validate", get(handle_validate))
+        .route("/render.svg", get(handle_render_svg))
+        .route("/render.png", get(handle_render_png))
+        .route("/stats/cache", get(handle_cache_stats))
+        .route("/stats/aggregate", get(handle_aggregate_stats))
+        .route("/levels", get(handle_levels))
+        .merge(sessions::routes(&state))
+        .merge(rooms::routes(&state))
+        .merge(live::routes())
+        .merge(arena::routes())
+        .merge(selfplay::routes())
+        .merge(jobs::routes())
+        .merge(users::routes())
+        .merge(admin::routes())
+        .merge(openapi::routes())
+        .layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            auth::authenticate,
+        ))
+        .with_state(state)
+}
+
+fn allowed_origins(origins: &[String]) -> AllowOrigin {
+    if origins.iter().any(|origin| origin == "*") {
+        return AllowOrigin::any();
+    }
+    AllowOrigin::list(
+        origins
+            .iter()
+            .map(|origin| origin.parse().expect("origins are validated at startup")),
+    )
+}
+
+#[derive(Debug, serde::Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
 struct MoveQuery {
+    /// Move history such as `B3R3B2R4`.
     position: String,
+    /// Search depth, 1 to 15.
     level: u8,
+    /// Time budget in milliseconds, clamped to the server's maximum.
+    /// `time_ms` is accepted as a synonym.
+    #[serde(alias = "time_ms")]
+    max_ms: Option<u64>,
+    /// `alphabeta` (default), `mcts` or `solver`.
+    algorithm: Option<Algorithm>,
+    /// 0 (default) to 1: how far below the best move a random pick may go.
+    randomness: Option<f64>,
+    /// Above 0, sample the move from a softmax over the scores (or MCTS
+    /// visit counts) instead; overrides `randomness`.
+    temperature: Option<f64>,
+    /// Ranked candidate moves to return, 1 to 7.
+    multipv: Option<usize>,
+    /// `aggressive`, `balanced` (default) or `defensive`.
+    style: Option<EvalPreset>,
+    /// Who opens when `position` is empty; Red when absent.
+    first_player: Option<Player>,
 }
 
-async fn handle_move(Query(query): Query<MoveQuery>) -> Result<impl IntoResponse, ApiError> {
-    let req = MoveRequest {
-        position: query.position,
-        level: query.level,
-    };
-    let mv = best_move(req)?;
+impl MoveQuery {
+    fn split(self) -> (MoveRequest, EngineParams) {
+        let params = EngineParams {
+            max_ms: self.max_ms,
+            algorithm: self.algorithm,
+            randomness: self.randomness,
+            temperature: self.temperature,
+            multipv: self.multipv,
+            style: self.style,
+        };
+        let request = MoveRequest {
+            position: self.position,
+            level: self.level,
+            first_player: self.first_player,
+        };
+        (request, params)
+    }
+}
+
+/// Per-request engine knobs shared by the GET and POST move endpoints.
+/// Query strings cannot use `serde(flatten)`, so `MoveQuery` repeats them.
+#[derive(Clone, Debug, Default, serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
+struct EngineParams {
+    #[serde(alias = "time_ms", skip_serializing_if = "Option::is_none")]
+    max_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    algorithm: Option<Algorithm>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    randomness: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    multipv: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    style: Option<EvalPreset>,
+}
+
+/// JSON body for `POST /move`: a `MoveRequest` plus the optional engine
+/// parameters of the GET endpoint.
+#[derive(Debug, serde::Deserialize, utoipa::ToSchema)]
+struct MoveBody {
+    #[serde(flatten)]
+    request: MoveRequest,
+    #[serde(flatten)]
+    params: EngineParams,
+}
+
+/// Best column for the side to move.
+#[utoipa::path(
+    get,
+    path = "/api/v1/move",
+    tag = "engine",
+    params(MoveQuery),
+    responses(
+        (status = 200, body = MoveResponse),
+        (status = 304, description = "`If-None-Match` matched the position's ETag"),
+        (status = 400, description = "Level out of range", body = ErrorBody),
+        (status = 401, description = "Unknown API key", body = ErrorBody),
+        (status = 409, description = "The game is already over", body = ErrorBody),
+        (status = 422, description = "Malformed or illegal position", body = ErrorBody),
+        (status = 429, description = "Rate limit exceeded; see `Retry-After`", body = ErrorBody),
+        (status = 503, description = "Search queue is full", body = ErrorBody),
+    ),
+)]
+async fn handle_move(
+    State(app): State<AppState>,
+    Extension(tier): Extension<Tier>,
+    headers: HeaderMap,
+    ApiQuery(query): ApiQuery<MoveQuery>,
+) -> Result<Response, ApiError> {
+    let (req, params) = query.split();
+    let no_store = [(header::CACHE_CONTROL, "no-store")];
+    if !app.search_options(&tier, &req, &params)?.is_default() {
+        let mv = app.search(&tier, req, params).await?;
+        return Ok((no_store, Json(mv)).into_response());
+    }
+    let state = req.state()?;
+    let level = req.level;
+    let book = app
+        .book
+        .current()
+        .filter(|book| book.probe(&state).is_some())
+        .map(|book| book.hash());
+    let etag = move_etag(&state, level, book);
+    let cacheable = [
+        (header::ETAG, etag.clone()),
+        (header::CACHE_CONTROL, CACHEABLE_MOVE.to_string()),
+    ];
+    if if_none_match_weakly(&headers, &etag) {
+        return Ok((StatusCode::NOT_MODIFIED, cacheable).into_response());
+    }
+    let mv = app.search(&tier, req, params).await?;
+    if mv.depth == Some(usize::from(level)) {
+        Ok((cacheable, Json(mv)).into_response())
+    } else {
+        // A deadline-truncated answer depends on timing, not just the position.
+        Ok((no_store, Json(mv)).into_response())
+    }
+}
+
+/// Full-depth answers depend only on the position, level, engine version and,
+/// for positions the opening book covers, the book, so shared caches may keep
+/// them; the ETag lets them revalidate for free.
+const CACHEABLE_MOVE: &str = "public, max-age=86400";
+
+/// Strong validator for a full-depth answer. The canonical key folds mirror
+/// images together, so the orientation is part of the tag; the version
+/// retires every tag when the engine changes. `book` is the hash of the
+/// opening book answering the position, which retires its tags when the book
+/// is reloaded or edited.
+fn move_etag(state: &GameState, level: u8, book: Option<u64>) -> String {
+    let (key, mirrored) = state.canonical_key();
+    let side = if mirrored { "m" } else { "n" };
+    let version = env!("CARGO_PKG_VERSION");
+    match book {
+        Some(book) => format!("\"{version}-{key:x}{side}-{level}-b{book:x}\""),
+        None => format!("\"{version}-{key:x}{side}-{level}\""),
+    }
+}
+
+/// Whether `If-None-Match` lists `etag`, by the weak comparison RFC 9110
+/// prescribes for it: a `W/` prefix on either side is ignored. `If-Match`
+/// needs the strong comparison, which this is not.
+fn if_none_match_weakly(headers: &HeaderMap, etag: &str) -> bool {
+    headers
+        .get_all(header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|tag| tag.trim().trim_start_matches("W/"))
+        .any(|tag| tag == "*" || tag == etag.trim_start_matches("W/"))
+}
+
+/// JSON twin of the GET endpoint: bots that already build a `MoveRequest`
+/// can post it as-is instead of flattening it into a query string.
+#[utoipa::path(
+    post,
+    path = "/api/v1/move",
+    tag = "engine",
+    request_body = MoveBody,
+    responses(
+        (status = 200, body = MoveResponse),
+        (status = 400, description = "Level out of range", body = ErrorBody),
+        (status = 401, description = "Unknown API key", body = ErrorBody),
+        (status = 409, description = "The game is already over", body = ErrorBody),
+        (status = 422, description = "Malformed or illegal position", body = ErrorBody),
+        (status = 429, description = "Rate limit exceeded; see `Retry-After`", body = ErrorBody),
+        (status = 503, description = "Search queue is full", body = ErrorBody),
+    ),
+)]
+async fn handle_move_json(
+    State(app): State<AppState>,
+    Extension(tier): Extension<Tier>,
+    ApiJson(body): ApiJson<MoveBody>,
+) -> Result<impl IntoResponse, ApiError> {
+    let mv = app.search(&tier, body.request, body.params).await?;
     let headers = [(header::CACHE_CONTROL, "no-store")];
     Ok((headers, Json(mv)))
 }
 
-#[derive(Debug)]
-struct ApiError(anyhow::Error);
+/// One entry of a `POST /moves` answer: the move, or why that position failed.
+#[derive(Debug, serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
+#[serde(untagged)]
+enum BatchItem {
+    Move(MoveResponse),
+    Error { error: ErrorBody },
+}
+
+/// Best moves for several positions in one call, in request order. Positions
+/// are searched concurrently, but never more at once than the pool has
+/// workers, so one batch cannot fill the queue and turn other clients away.
+/// A failing position only fails its own entry. Each position costs a
+/// rate-limit token, and a batch the caller's bucket cannot cover is refused
+/// with `429` before anything is searched.
+#[utoipa::path(
+    post,
+    path = "/api/v1/moves",
+    tag = "engine",
+    request_body = [MoveBody],
+    responses(
+        (status = 200, body = [BatchItem]),
+        (status = 400, description = "Too many positions", body = ErrorBody),
+        (status = 401, description = "Unknown API key", body = ErrorBody),
+        (status = 429, description = "Rate limit exceeded; see `Retry-After`", body = ErrorBody),
+    ),
+)]
+async fn handle_moves(
+    State(app): State<AppState>,
+    Extension(tier): Extension<Tier>,
+    Extension(tokens): Extension<rate_limit::Tokens>,
+    ApiJson(batch): ApiJson<Vec<MoveBody>>,
+) -> Response {
+    use futures_util::{stream, StreamExt};
+
+    let max = app.config.engine.max_batch;
+    if batch.len() > max {
+        return ApiError {
+            code: "batch_too_large",
+            ..ApiError::new(
+                StatusCode::BAD_REQUEST,
+                format!("{} positions exceed the maximum of {max}", batch.len()),
+            )
+        }
+        .into_response();
+    }
+    // The rate limiter already took one token for the request itself.
+    if let Err(retry_after) = tokens.spend(batch.len().saturating_sub(1)) {
+        return rate_limit::too_many_requests(retry_after);
+    }
+    let results: Vec<BatchItem> = stream::iter(batch)
+        .map(|body| {
+            let (app, tier) = (app.clone(), tier.clone());
+            async move {
+                match app.search(&tier, body.request, body.params).await {
+                    Ok(mv) => BatchItem::Move(mv),
+                    Err(err) => BatchItem::Error { error: err.body() },
+                }
+            }
+        })
+        .buffered(app.config.search.workers.max(1))
+        .collect()
+        .await;
+    let headers = [(header::CACHE_CONTROL, "no-store")];
+    (headers, Json(results)).into_response()
+}
+
+/// Streams one `progress` event per completed search depth and a final `done`
+/// (or `error`) event. The search runs on the search pool because it is pure
+/// CPU work; events flow back through a channel. A client hanging up closes
+/// the channel, which cancels the search and frees its pool slot. A full
+/// queue is refused with `503` before the stream opens.
+#[utoipa::path(
+    get,
+    path = "/api/v1/move/stream",
+    tag = "engine",
+    params(MoveQuery),
+    responses(
+        (
+            status = 200,
+            description = "`progress` events carrying `SearchInfo`, then `done` with a \
+                           `MoveResponse` or `error` with a message",
+            content_type = "text/event-stream",
+            body = String,
+        ),
+        (status = 429, description = "Rate limit exceeded; see `Retry-After`", body = ErrorBody),
+        (status = 503, description = "Search queue is full", body = ErrorBody),
+    ),
+)]
+async fn handle_move_stream(
+    State(app): State<AppState>,
+    Extension(tier): Extension<Tier>,
+    ApiQuery(query): ApiQuery<MoveQuery>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, ApiError> {
+    let (req, params) = query.split();
+    let options = app.search_options(&tier, &req, &params)?;
+    let state = req.state()?;
+    let cancel = CancelToken::new();
+    let limits = SearchLimits {
+        cancel: Some(cancel.clone()),
+        ..app.search_limits(&tier, params.max_ms)
+    };
+    let admission = app.search_pool.admit(options.level)?;
+    let (tx, rx) = mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        let progress = tx.clone();
+        let stop = cancel.clone();
+        let search = admission.run(move || {
+            // A client that went away stops the search at its next
+            // iteration instead of holding a pool slot until the deadline.
+            let outcome = connect4::search(&state, &options, &limits, |info| {
+                if progress.is_closed() {
+                    cancel.cancel();
+                    return;
+                }
+                let event = Event::default().event("progress").json_data(info);
+                let _ = progress.send(event.expect("search info serializes"));
+            })?;
+            Ok(outcome_response(outcome, &options))
+        });
+        tokio::pin!(search);
+        // MCTS reports no progress and a deep iteration can run for seconds,
+        // so the hang-up is also watched for while the search runs.
+        let result = tokio::select! {
+            result = &mut search => result,
+            () = tx.closed() => {
+                stop.cancel();
+                search.await
+            }
+        };
+        let event = match result {
+            Ok(mv) => Event::default()
+                .event("done")
+                .json_data(mv)
+                .expect("move serializes"),
+            Err(err) => Event::default().event("error").data(err.to_string()),
+        };
+        let _ = tx.send(event);
+    });
+    let stream = tokio_stream::StreamExt::map(UnboundedReceiverStream::new(rx), Ok);
+    Ok(Sse::new(stream).keep_alive(KeepAlive::default()))
+}
+
+/// Hit rate and occupancy of the in-process move cache.
+#[utoipa::path(
+    get,
+    path = "/api/v1/stats/cache",
+    tag = "engine",
+    responses((status = 200, body = MoveCacheStats)),
+)]
+async fn handle_cache_stats(State(app): State<AppState>) -> Json<MoveCacheStats> {
+    Json(app.move_cache.stats())
+}
+
+/// Anonymous outcomes of games against the engine since the server started:
+/// results and game length per level, and the blunders humans lose with most.
+#[utoipa::path(
+    get,
+    path = "/api/v1/stats/aggregate",
+    tag = "engine",
+    responses((status = 200, body = AggregateStats)),
+)]
+async fn handle_aggregate_stats(State(app): State<AppState>) -> Json<telemetry::AggregateStats> {
+    Json(app.telemetry.snapshot())
+}
+
+#[derive(Debug, serde::Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+struct AnalyzeQuery {
+    /// Move history such as `B3R3B2R4`.
+    position: String,
+    /// Search depth, 1 to 15.
+    level: u8,
+    /// Also say what each column does, in words (default `false`).
+    #[serde(default)]
+    explain: bool,
+}
+
+impl AppState {
+    /// `connect4::analyze` on the search pool, with explanations if asked.
+    async fn analyze(&self, tier: &Tier, query: AnalyzeQuery) -> Result<Analysis, ApiError> {
+        self.check_level(tier, query.level)?;
+        let request = MoveRequest {
+            position: query.position,
+            level: query.level,
+            first_player: None,
+        };
+        let explain = query.explain;
+        self.search_pool
+            .run(query.level, move || {
+                Ok(if explain {
+                    connect4::explain::analyze_explained(request)?
+                } else {
+                    connect4::analyze(request)?
+                })
+            })
+            .await
+    }
+}
+
+/// Scores for every column and the principal variation. With `explain`,
+/// each column also gets teaching-mode reasons and a sentence built from
+/// them.
+#[utoipa::path(
+    get,
+    path = "/api/v1/analyze",
+    tag = "engine",
+    params(AnalyzeQuery),
+    responses(
+        (status = 200, body = Analysis),
+        (status = 400, description = "Level out of range", body = ErrorBody),
+        (status = 401, description = "Unknown API key", body = ErrorBody),
+        (status = 422, description = "Malformed or illegal position", body = ErrorBody),
+        (status = 429, description = "Rate limit exceeded; see `Retry-After`", body = ErrorBody),
+        (status = 503, description = "Search queue is full", body = ErrorBody),
+    ),
+)]
+async fn handle_analyze(
+    State(app): State<AppState>,
+    Extension(tier): Extension<Tier>,
+    ApiQuery(query): ApiQuery<AnalyzeQuery>,
+) -> Result<Json<Analysis>, ApiError> {
+    Ok(Json(app.analyze(&tier, query).await?))
+}
+
+/// A level and the rating it plays at, for showing difficulty in Elo terms.
+#[derive(Debug, serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
+struct LevelInfo {
+    level: u8,
+    /// `connect4::Level::approximate_elo`: calibrated against the other
+    /// levels, with level 1 pinned at 600.
+    elo: u32,
+    /// Whether the caller's key may search this deep.
+    available: bool,
+}
+
+/// Every level with its approximate Elo, weakest first.
+#[utoipa::path(
+    get,
+    path = "/api/v1/levels",
+    tag = "engine",
+    responses((status = 200, body = [LevelInfo])),
+)]
+async fn handle_levels(Extension(tier): Extension<Tier>) -> Json<Vec<LevelInfo>> {
+    Json(
+        connect4::Level::all()
+            .map(|level| LevelInfo {
+                level: level.get(),
+                elo: level.approximate_elo(),
+                available: level.get() <= tier.max_level,
+            })
+            .collect(),
+    )
+}
+
+#[derive(Debug, serde::Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+struct StateQuery {
+    /// Move history; empty for the starting position.
+    #[serde(default)]
+    position: String,
+}
+
+/// Replays the history server-side so clients can draw the board, know whose
+/// turn it is and highlight a finished line without duplicating the rules.
+#[utoipa::path(
+    get,
+    path = "/api/v1/state",
+    tag = "engine",
+    params(StateQuery),
+    responses(
+        (status = 200, body = BoardModel),
+        (status = 422, description = "Malformed or illegal position", body = ErrorBody),
+    ),
+)]
+async fn handle_state(ApiQuery(query): ApiQuery<StateQuery>) -> Result<Json<BoardModel>, ApiError> {
+    let moves = parse_history(&query.position)?;
+    let state = GameState::from_history(&moves)?;
+    Ok(Json(state.board_model()))
+}
+
+/// What each legal column would lead to, from one ply of lookahead: whether
+/// it wins, misses a win, blocks a four or hands the opponent one, plus the
+/// resulting grid. Cheap enough to call on every hover.
+#[utoipa::path(
+    get,
+    path = "/api/v1/preview",
+    tag = "engine",
+    params(StateQuery),
+    responses(
+        (status = 200, body = [MovePreview]),
+        (status = 422, description = "Malformed or illegal position", body = ErrorBody),
+    ),
+)]
+async fn handle_preview(
+    ApiQuery(query): ApiQuery<StateQuery>,
+) -> Result<Json<Vec<MovePreview>>, ApiError> {
+    let state = GameState::from_history(&parse_history(&query.position)?)?;
+    Ok(Json(preview_moves(&state)))
+}
+
+#[derive(Debug, serde::Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+struct RenderQuery {
+    /// Move history; empty for the starting position.
+    #[serde(default)]
+    position: String,
+    /// Mark the disc played last (default `true`).
+    #[serde(default = "yes")]
+    last_move: bool,
+    /// Outline a completed line of four (default `true`).
+    #[serde(default = "yes")]
+    highlight_win: bool,
+    /// Image width in pixels (default 480); the height follows the board's
+    /// aspect ratio. Clamped to roughly 120-1920.
+    width: Option<u32>,
+}
+
+fn yes() -> bool {
+    true
+}
+
+impl RenderQuery {
+    fn render(&self) -> Result<(GameState, RenderOptions), ApiError> {
+        let moves = parse_history(&self.position)?;
+        let state = GameState::from_history(&moves)?;
+        let options = RenderOptions {
+            last_move: moves.last().filter(|_| self.last_move).map(|mv| mv.column),
+            highlight_win: self.highlight_win,
+            ..self
+                .width
+                .map_or_else(RenderOptions::default, RenderOptions::with_width)
+        };
+        Ok((state, options))
+    }
+}
+
+/// An image depends only on the query, so shared caches may keep it.
+const CACHEABLE_IMAGE: &str = "public, max-age=86400";
+
+/// The board as an SVG image, for embedding in pages and posts.
+#[utoipa::path(
+    get,
+    path = "/api/v1/render.svg",
+    tag = "render",
+    params(RenderQuery),
+    responses(
+        (status = 200, content_type = "image/svg+xml", body = String),
+        (status = 422, description = "Malformed or illegal position", body = ErrorBody),
+    ),
+)]
+async fn handle_render_svg(
+    ApiQuery(query): ApiQuery<RenderQuery>,
+) -> Result<impl IntoResponse, ApiError> {
+    let (state, options) = query.render()?;
+    let headers = [
+        (header::CONTENT_TYPE, "image/svg+xml"),
+        (header::CACHE_CONTROL, CACHEABLE_IMAGE),
+    ];
+    Ok((headers, render::svg(&state, &options)))
+}
 
-impl<E: Into<anyhow::Error>> From<E> for ApiError {
-    fn from(err: E) -> Self {
-        Self(err.into())
+/// The board as a PNG image, for chat unfurlers and sites that refuse SVG.
+/// Needs the server's `png` feature; without it the endpoint answers `501`.
+#[utoipa::path(
+    get,
+    path = "/api/v1/render.png",
+    tag = "render",
+    params(RenderQuery),
+    responses(
+        (status = 200, content_type = "image/png", body = Vec<u8>),
+        (status = 422, description = "Malformed or illegal position", body = ErrorBody),
+        (status = 501, description = "Built without the `png` feature", body = ErrorBody),
+    ),
+)]
+async fn handle_render_png(ApiQuery(query): ApiQuery<RenderQuery>) -> Result<Response, ApiError> {
+    let (state, options) = query.render()?;
+    #[cfg(feature = "png")]
+    {
+        let png = tokio::task::spawn_blocking(move || render::png(&state, &options))
+            .await
+            .map_err(|err| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, err))??;
+        let headers = [
+            (header::CONTENT_TYPE, "image/png"),
+            (header::CACHE_CONTROL, CACHEABLE_IMAGE),
+        ];
+        Ok((headers, png).into_response())
     }
+    #[cfg(not(feature = "png"))]
+    {
+        let _ = (state, options);
+        Err(ApiError::new(
+            StatusCode::NOT_IMPLEMENTED,
+            "PNG rendering needs a server built with the `png` feature",
+        ))
+    }
+}
+
+/// Validation outcomes are data, not failures, so this always answers 200.
+#[derive(Debug, serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
+struct ValidateResponse {
+    valid: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    moves: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    to_move: Option<Player>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    status: Option<GameStatus>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<HistoryDiagnostic>,
+}
+
+/// Strictly check a history without searching.
+#[utoipa::path(
+    get,
+    path = "/api/v1/validate",
+    tag = "engine",
+    params(StateQuery),
+    responses((status = 200, body = ValidateResponse)),
+)]
+async fn handle_validate(ApiQuery(query): ApiQuery<StateQuery>) -> Json<ValidateResponse> {
+    Json(validate(&query.position))
 }
 
-impl IntoResponse for ApiError {
-    fn into_response(self) -> Response {
-        let status = StatusCode::BAD_REQUEST;
-        let body = format!("{}", self.0);
-        (status, body).into_response()
+fn validate(position: &str) -> ValidateResponse {
+    match validate_history(position) {
+        Ok(state) => {
+            let board = state.board_model();
+            ValidateResponse {
+                valid: true,
+                moves: Some(board.moves_played),
+                to_move: Some(board.to_move),
+                status: Some(board.status),
+                error: None,
+            }
+        }
+        Err(diagnostic) => ValidateResponse {
+            valid: false,
+            moves: None,
+            to_move: None,
+            status: None,
+            error: Some(diagnostic),
+        },
     }
 }
 
@@ -84,11 +1399,33 @@ mod tests {
     use super::*;
     use axum::body::to_bytes;
     use axum::http::Request;
+    use rate_limit::RateLimitConfig;
+    use search_pool::SearchPoolConfig;
     use tower::util::ServiceExt;
 
+    fn with_config(config: Config) -> AppState {
+        AppState::new(config, Arc::new(storage::MemoryStorage::default()))
+    }
+
+    #[tokio::test]
+    async fn levels_above_the_configured_maximum_are_refused() {
+        let app = app_router(with_config(Config {
+            max_level: 6,
+            ..Config::default()
+        }));
+        let (status, _) = send_json(&app, "GET", "/api/move?position=&level=6", "").await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, body) = send_json(&app, "GET", "/api/move?position=&level=7", "").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let error: error::ErrorBody = serde_json::from_slice(&body).unwrap();
+        assert_eq!(error.code, "depth_out_of_range");
+        let (status, _) = send_json(&app, "POST", "/api/games", r#"{"level":7}"#).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
     #[tokio::test]
     async fn http_move_endpoint() {
-        let app = app_router();
+        let app = app_router(AppState::default());
         let response = app
             .oneshot(
                 Request::builder()
@@ -103,4 +1440,2297 @@ mod tests {
         let mv: MoveResponse = serde_json::from_slice(&bytes).unwrap();
         assert!(mv.column < 7);
     }
+
+    #[tokio::test]
+    async fn move_errors_carry_status_and_code() {
+        let app = app_router(AppState::default());
+        let cases = [
+            (
+                "position=R3X&level=4",
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "invalid_position",
+            ),
+            (
+                "position=R3&level=0",
+                StatusCode::BAD_REQUEST,
+                "depth_out_of_range",
+            ),
+            ("position=R3", StatusCode::BAD_REQUEST, "invalid_query"),
+        ];
+        for (query, status, code) in cases {
+            let (got, body) = send_json(&app, "GET", &format!("/api/move?{query}"), "").await;
+            assert_eq!(got, status, "{query}");
+            let error: error::ErrorBody = serde_json::from_slice(&body).unwrap();
+            assert_eq!(error.code, code);
+            assert_eq!(error.position, (code == "invalid_position").then_some(2));
+            assert_eq!(
+                error.reason,
+                (code == "invalid_position")
+                    .then_some(connect4::ParseMoveError::UnexpectedChar { found: 'X' })
+            );
+        }
+        let (status, _) = send_json(&app, "POST", "/api/move", "{").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn move_responses_revalidate_with_etags() {
+        let app = app_router(AppState::default());
+        let uri = "/api/move?position=R3B3&level=3";
+        let response = app.clone().oneshot(get(uri, &[])).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CACHE_CONTROL], CACHEABLE_MOVE);
+        let etag = response.headers()[header::ETAG]
+            .to_str()
+            .unwrap()
+            .to_string();
+
+        let response = app
+            .clone()
+            .oneshot(get(uri, &[("if-none-match", &etag)]))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+        // If-None-Match compares weakly, so the weak form of the tag in a
+        // list matches too.
+        let weak = format!("\"other\", W/{etag}");
+        let response = app
+            .clone()
+            .oneshot(get(uri, &[("if-none-match", &weak)]))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+        let other = "/api/move?position=R3B3&level=4";
+        let response = app
+            .clone()
+            .oneshot(get(other, &[("if-none-match", &etag)]))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let truncated = "/api/move?position=&level=15&max_ms=0";
+        let response = app.oneshot(get(truncated, &[])).await.unwrap();
+        assert_eq!(response.headers()[header::CACHE_CONTROL], "no-store");
+        assert!(response.headers().get(header::ETAG).is_none());
+    }
+
+    fn get(uri: &str, headers: &[(&str, &str)]) -> Request<axum::body::Body> {
+        let mut request = Request::builder().uri(uri);
+        for &(name, value) in headers {
+            request = request.header(name, value);
+        }
+        request.body(axum::body::Body::empty()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn move_endpoint_fills_the_position_cache() {
+        let state = AppState::default();
+        let app = app_router(state.clone());
+        let (status, body) =
+            send_json(&app, "GET", "/api/move?position=B0R3B1R4B2R5&level=4", "").await;
+        assert_eq!(status, StatusCode::OK);
+        let mv: MoveResponse = serde_json::from_slice(&body).unwrap();
+        let key = GameState::from_history(&parse_history("B0R3B1R4B2R5").unwrap())
+            .unwrap()
+            .key();
+        assert_eq!(state.storage.cached_move(key, 4).unwrap(), Some(mv.column));
+
+        // The mirrored position is answered from memory with the mirrored column.
+        let (_, body) = send_json(&app, "GET", "/api/move?position=B6R3B5R2B4R1&level=4", "").await;
+        let mirrored: MoveResponse = serde_json::from_slice(&body).unwrap();
+        assert_eq!(mirrored.column, 6 - mv.column);
+        let (_, body) = send_json(&app, "GET", "/api/stats/cache", "").await;
+        let stats: MoveCacheStats = serde_json::from_slice(&body).unwrap();
+        assert_eq!((stats.hits, stats.misses, stats.entries), (1, 1, 1));
+    }
+
+    #[tokio::test]
+    async fn solved_positions_go_to_the_tablebase_and_answer_mirrors() {
+        let state = AppState::default();
+        let app = app_router(state.clone());
+        let solve = |position: &str| {
+            format!(r#"{{"position":"{position}","level":15,"algorithm":"solver"}}"#)
+        };
+        let position = "R3B3R3B3R3B2R2B4R4B4R2B2R4B1R1B5R5B0R0B6";
+        let (status, body) = send_json(&app, "POST", "/api/v1/move", &solve(position)).await;
+        assert_eq!(status, StatusCode::OK);
+        let mv: MoveResponse = serde_json::from_slice(&body).unwrap();
+        let game = GameState::from_history(&parse_history(position).unwrap()).unwrap();
+        let (key, mirrored) = game.canonical_key();
+        let solved = state.storage.solved(key).unwrap().unwrap();
+        let column = if mirrored { 6 - mv.column } else { mv.column };
+        assert_eq!(
+            (Some(solved.column), Some(solved.score), Some(solved.depth)),
+            (Some(column), mv.score, mv.depth)
+        );
+
+        // A planted entry proves the answer comes from the table, mirrored.
+        let planted = storage::Solved {
+            column: 0,
+            score: 7,
+            depth: 3,
+        };
+        state.storage.put_solved(key, &planted).unwrap();
+        let (_, body) = send_json(
+            &app,
+            "POST",
+            "/api/v1/move",
+            &solve("R3B3R3B3R3B4R4B2R2B2R4B4R2B5R5B1R1B6R6B0"),
+        )
+        .await;
+        let mv: MoveResponse = serde_json::from_slice(&body).unwrap();
+        let column = if mirrored { 0 } else { 6 };
+        assert_eq!((mv.column, mv.score, mv.depth), (column, Some(7), Some(3)));
+
+        // Several lines are not what the table holds, so they are searched.
+        let body =
+            format!(r#"{{"position":"{position}","level":15,"algorithm":"solver","multipv":3}}"#);
+        let (_, body) = send_json(&app, "POST", "/api/v1/move", &body).await;
+        let mv: MoveResponse = serde_json::from_slice(&body).unwrap();
+        assert_ne!(mv.score, Some(7));
+    }
+
+    #[tokio::test]
+    async fn levels_list_ratings_and_what_the_key_allows() {
+        let app = app_router(AppState::default());
+        let (status, body) = send_json(&app, "GET", "/api/v1/levels", "").await;
+        assert_eq!(status, StatusCode::OK);
+        let levels: Vec<LevelInfo> = serde_json::from_slice(&body).unwrap();
+        assert_eq!(levels.len(), 15);
+        assert_eq!(levels[0].elo, connect4::strength::ANCHOR_ELO);
+        assert!(levels.windows(2).all(|pair| pair[0].elo <= pair[1].elo));
+        let max = Config::default().max_level;
+        assert!(levels
+            .iter()
+            .all(|info| info.available == (info.level <= max)));
+    }
+
+    #[tokio::test]
+    async fn analysis_explains_columns_on_request() {
+        let app = app_router(AppState::default());
+        let uri = "/api/v1/analyze?position=R1B1R2B2R3&level=4";
+        let (status, body) = send_json(&app, "GET", uri, "").await;
+        assert_eq!(status, StatusCode::OK);
+        let plain: Analysis = serde_json::from_slice(&body).unwrap();
+        assert_eq!(plain.scores.len(), 7);
+        assert!(plain.explanations.is_empty());
+
+        let uri = format!("{uri}&explain=true");
+        let (_, body) = send_json(&app, "GET", &uri, "").await;
+        let explained: Analysis = serde_json::from_slice(&body).unwrap();
+        assert_eq!(explained.explanations.len(), 7);
+        assert!(explained.explanations[0]
+            .text
+            .starts_with("Blocks Red's three in a row on the bottom row"));
+
+        let (status, _) = send_json(&app, "GET", "/api/v1/analyze?position=R1&level=99", "").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn previews_warn_before_hanging_a_four() {
+        let app = app_router(AppState::default());
+        let (status, body) =
+            send_json(&app, "GET", "/api/v1/preview?position=R1B1R2B2R3", "").await;
+        assert_eq!(status, StatusCode::OK);
+        let previews: Vec<MovePreview> = serde_json::from_slice(&body).unwrap();
+        assert_eq!(previews.len(), 7);
+        assert!(previews[0].blocks_threat && previews[0].allows_opponent_win);
+        let (status, _) = send_json(&app, "GET", "/api/v1/preview?position=R9", "").await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[tokio::test]
+    async fn move_deadline_returns_partial_result_uncached() {
+        let state = with_config(Config {
+            max_search_ms: 50,
+            ..Config::default()
+        });
+        let app = app_router(state.clone());
+        // Level 15 from the empty board takes far longer than the budget.
+        let (status, body) =
+            send_json(&app, "GET", "/api/move?position=&level=15&max_ms=50", "").await;
+        assert_eq!(status, StatusCode::OK);
+        let mv: MoveResponse = serde_json::from_slice(&body).unwrap();
+        assert!(mv.depth.unwrap() < 15);
+        let key = GameState::empty(Player::Red).key();
+        assert_eq!(state.storage.cached_move(key, 15).unwrap(), None);
+
+        let body = r#"{"position":"","level":15,"max_ms":0}"#;
+        let (status, body) = send_json(&app, "POST", "/api/move", body).await;
+        assert_eq!(status, StatusCode::OK);
+        let mv: MoveResponse = serde_json::from_slice(&body).unwrap();
+        assert_eq!(mv.depth, Some(1));
+        assert!(mv.truncated);
+        assert_eq!(mv.target_depth, Some(15));
+    }
+
+    #[tokio::test]
+    async fn busy_search_pool_answers_503() {
+        let state = with_config(Config {
+            search: SearchPoolConfig {
+                workers: 1,
+                queue: 0,
+                ..SearchPoolConfig::default()
+            },
+            ..Config::default()
+        });
+        let app = app_router(state.clone());
+        let held = state.search_pool.admit(1).unwrap();
+        let (status, _) = send_json(&app, "GET", "/api/move?position=&level=1", "").await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        let (status, _) = send_json(&app, "GET", "/api/move/stream?position=&level=1", "").await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        drop(held);
+        let (status, _) = send_json(&app, "GET", "/api/move?position=&level=1", "").await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn archive_lists_and_reviews_finished_games() {
+        let state = AppState::default();
+        let app = app_router(state.clone());
+        state.record_game(&storage::GameRecord {
+            id: "archived".to_string(),
+            history: "R0B0R1B1R2B6R3".to_string(),
+            result: storage::GameResult::RedWin,
+            red_level: None,
+            blue_level: Some(3),
+            started_at: 100,
+            finished_at: 200,
+            engine: None,
+            hints: None,
+            player: None,
+            clock: None,
+        });
+
+        let (status, body) = send_json(&app, "GET", "/api/games?result=red_win&level=3", "").await;
+        assert_eq!(status, StatusCode::OK);
+        let page: storage::GamePage = serde_json::from_slice(&body).unwrap();
+        assert_eq!(page.total, 1);
+        let (_, body) = send_json(&app, "GET", "/api/games?result=draw", "").await;
+        let page: storage::GamePage = serde_json::from_slice(&body).unwrap();
+        assert_eq!(page.total, 0);
+
+        let (status, body) = send_json(&app, "GET", "/api/games/archived", "").await;
+        assert_eq!(status, StatusCode::OK);
+        let game: archive::ArchivedGame = serde_json::from_slice(&body).unwrap();
+        assert_eq!(game.record.history, "R0B0R1B1R2B6R3");
+        assert_eq!(game.review.moves.len(), 7);
+        assert_eq!(
+            game.review
+                .count(Player::Blue, connect4::review::MoveClass::Blunder),
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn games_export_as_notation() {
+        use connect4::notation::{Notation, Outcome};
+
+        let state = AppState::default();
+        let app = app_router(state.clone());
+        state.record_game(&storage::GameRecord {
+            id: "archived".to_string(),
+            history: "R0B0R1B1R2B6R3".to_string(),
+            result: storage::GameResult::RedWin,
+            red_level: None,
+            blue_level: Some(3),
+            started_at: 100,
+            finished_at: 200,
+            engine: Some("connect4 0.1.0 (eval 0123456789abcdef)".to_string()),
+            hints: None,
+            player: None,
+            clock: None,
+        });
+        let (status, body) = send_json(&app, "GET", "/api/games/archived/export", "").await;
+        assert_eq!(status, StatusCode::OK);
+        let text = String::from_utf8(body).unwrap();
+        assert!(text.contains("[Blue \"Engine level 3\"]\n"));
+        assert!(text.contains("[Date \"1970.01.01\"]\n"));
+        assert!(text.ends_with("\n1. a a 2. b b 3. c g 4. d 1-0\n"));
+        let game: Notation = text.parse().unwrap();
+        assert_eq!(game.history(), "R0B0R1B1R2B6R3");
+        assert_eq!(game.tag("Red"), Some("Human"));
+        assert_eq!(
+            game.tag("Engine"),
+            Some("connect4 0.1.0 (eval 0123456789abcdef)")
+        );
+
+        let (_, body) = send_json(&app, "POST", "/api/games", r#"{"level":2}"#).await;
+        let live: sessions::GameView = serde_json::from_slice(&body).unwrap();
+        let uri = format!("/api/games/{}/export", live.id);
+        let (status, body) = send_json(&app, "GET", &uri, "").await;
+        assert_eq!(status, StatusCode::OK);
+        let game: Notation = String::from_utf8(body).unwrap().parse().unwrap();
+        assert_eq!(game.outcome, Outcome::Unfinished);
+        let (status, _) = send_json(&app, "GET", "/api/games/nope/export", "").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn search_endpoints_are_rate_limited_per_client() {
+        let state = with_config(Config {
+            rate_limit: RateLimitConfig {
+                burst: 2,
+                per_second: 0.5,
+                trust_forwarded_for: true,
+            },
+            ..Config::default()
+        });
+        let app = app_router(state);
+        let request = |client: &str| {
+            Request::builder()
+                .uri("/api/move?position=&level=1")
+                .header("x-forwarded-for", client)
+                .body(axum::body::Body::empty())
+                .unwrap()
+        };
+        for _ in 0..2 {
+            let response = app.clone().oneshot(request("10.0.0.1")).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+        let response = app.clone().oneshot(request("10.0.0.1")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()[header::RETRY_AFTER], "2");
+        let response = app.clone().oneshot(request("10.0.0.2")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        // Cheap endpoints are not throttled.
+        let (status, _) = send_json(&app, "GET", "/api/state?position=", "").await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn either_colour_can_open_and_answers_name_the_mover() {
+        let app = app_router(AppState::default());
+        for (uri, player) in [
+            ("/api/move?position=&level=4", Player::Red),
+            (
+                "/api/move?position=&level=4&first_player=blue",
+                Player::Blue,
+            ),
+            (
+                "/api/move?position=B3R3&level=4&first_player=blue",
+                Player::Blue,
+            ),
+        ] {
+            let (status, body) = send_json(&app, "GET", uri, "").await;
+            assert_eq!(status, StatusCode::OK, "{uri}");
+            let mv: MoveResponse = serde_json::from_slice(&body).unwrap();
+            assert_eq!(mv.player, Some(player), "{uri}");
+        }
+        let uri = "/api/move?position=R3&level=4&first_player=blue";
+        let (status, _) = send_json(&app, "GET", uri, "").await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+
+        // The engine plays Blue and opens.
+        let body = r#"{"level":4,"first_player":"blue","human_color":"red"}"#;
+        let (status, body) = send_json(&app, "POST", "/api/games", body).await;
+        assert_eq!(status, StatusCode::CREATED);
+        let game: sessions::GameView = serde_json::from_slice(&body).unwrap();
+        assert_eq!((game.first_player, game.human), (Player::Blue, Player::Red));
+        assert!(game.history.starts_with('B'));
+        assert!(game.engine_move.is_some());
+
+        // Blue opens and the human plays it; the engine waits.
+        let body = r#"{"level":4,"first_player":"blue"}"#;
+        let (_, body) = send_json(&app, "POST", "/api/games", body).await;
+        let game: sessions::GameView = serde_json::from_slice(&body).unwrap();
+        assert_eq!(game.human, Player::Blue);
+        assert!(game.engine_move.is_none());
+        let uri = format!("/api/games/{}/move", game.id);
+        let (_, body) = send_json(&app, "POST", &uri, r#"{"column":3}"#).await;
+        let game: sessions::GameView = serde_json::from_slice(&body).unwrap();
+        assert!(game.history.starts_with("B3R"), "{}", game.history);
+
+        let body = r#"{"level":4,"engine_first":true,"human_color":"red"}"#;
+        let (status, _) = send_json(&app, "POST", "/api/games", body).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn styles_change_how_the_engine_plays() {
+        let app = app_router(AppState::default());
+        for (style, column) in [("aggressive", 2), ("balanced", 3), ("defensive", 4)] {
+            let uri = format!("/api/move?position=R2B4R6B3&level=8&style={style}");
+            let (status, body) = send_json(&app, "GET", &uri, "").await;
+            assert_eq!(status, StatusCode::OK, "{style}");
+            let mv: MoveResponse = serde_json::from_slice(&body).unwrap();
+            assert_eq!(mv.column, column, "{style}");
+        }
+        let uri = "/api/move?position=&level=3&style=reckless";
+        let (status, _) = send_json(&app, "GET", uri, "").await;
+        assert!(status.is_client_error());
+
+        let body = r#"{"level":4,"engine_first":true,"style":"defensive"}"#;
+        let (status, body) = send_json(&app, "POST", "/api/games", body).await;
+        assert_eq!(status, StatusCode::CREATED);
+        let game: sessions::GameView = serde_json::from_slice(&body).unwrap();
+        assert_eq!(game.style, EvalPreset::Defensive);
+        assert!(game.engine_move.is_some());
+        let (_, body) = send_json(&app, "POST", "/api/games", r#"{"level":4}"#).await;
+        let game: sessions::GameView = serde_json::from_slice(&body).unwrap();
+        assert_eq!(game.style, EvalPreset::Balanced);
+    }
+
+    #[tokio::test]
+    async fn engine_options_pick_the_algorithm_and_lines() {
+        let app = app_router(AppState::default());
+        let uri = "/api/move?position=R3B3R4&level=4&multipv=3&time_ms=5000";
+        let response = app.clone().oneshot(get(uri, &[])).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CACHE_CONTROL], "no-store");
+        assert!(response.headers().get(header::ETAG).is_none());
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let mv: MoveResponse = serde_json::from_slice(&body).unwrap();
+        assert_eq!(mv.lines.len(), 3);
+        assert_eq!(mv.score, Some(mv.lines[0].score));
+
+        let body = r#"{"position":"R0B1R0B1R0B1","level":2,"algorithm":"mcts"}"#;
+        let (status, body) = send_json(&app, "POST", "/api/move", body).await;
+        assert_eq!(status, StatusCode::OK);
+        let mv: MoveResponse = serde_json::from_slice(&body).unwrap();
+        assert_eq!(mv.column, 0);
+
+        let uri = "/api/move?position=R0B1R0B1R0B1&level=1&algorithm=solver";
+        let (status, body) = send_json(&app, "GET", uri, "").await;
+        assert_eq!(status, StatusCode::OK);
+        let mv: MoveResponse = serde_json::from_slice(&body).unwrap();
+        assert_eq!((mv.column, mv.score), (0, Some(1_000_000)));
+
+        let restricted = app_router(with_config(Config {
+            max_level: 10,
+            engine: config::EngineConfig {
+                algorithms: vec![Algorithm::Solver],
+                ..config::EngineConfig::default()
+            },
+            ..Config::default()
+        }));
+        for (uri, code) in [
+            (
+                "/api/move?position=&level=3&algorithm=mcts",
+                "algorithm_disabled",
+            ),
+            (
+                "/api/move?position=&level=3&algorithm=solver",
+                "depth_out_of_range",
+            ),
+        ] {
+            let (status, body) = send_json(&restricted, "GET", uri, "").await;
+            assert_eq!(status, StatusCode::BAD_REQUEST);
+            let error: error::ErrorBody = serde_json::from_slice(&body).unwrap();
+            assert_eq!(error.code, code);
+        }
+    }
+
+    #[tokio::test]
+    async fn render_endpoint_serves_svg() {
+        let app = app_router(AppState::default());
+        let uri = "/api/render.svg?position=R0B1R0B1R0B1R0";
+        let response = app.clone().oneshot(get(uri, &[])).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "image/svg+xml");
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let svg = String::from_utf8(body.to_vec()).unwrap();
+        assert!(svg.starts_with("<svg") && svg.contains("stroke="));
+
+        let uri = "/api/render.svg?position=R0B1R0B1R0B1R0&highlight_win=false";
+        let (_, body) = send_json(&app, "GET", uri, "").await;
+        assert!(!String::from_utf8(body).unwrap().contains("stroke="));
+        let (status, _) = send_json(&app, "GET", "/api/render.svg?position=R9", "").await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+
+        let uri = "/api/render.png?position=R3&width=240";
+        let response = app.oneshot(get(uri, &[])).await.unwrap();
+        if cfg!(feature = "png") {
+            assert_eq!(response.headers()[header::CONTENT_TYPE], "image/png");
+            let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            assert_eq!(&body[1..4], b"PNG");
+        } else {
+            assert_eq!(response.status(), StatusCode::NOT_IMPLEMENTED);
+        }
+    }
+
+    #[tokio::test]
+    async fn api_keys_lift_the_anonymous_limits() {
+        let config: Config = toml::from_str(
+            r#"
+            [auth.anonymous]
+            max_level = 4
+            [[auth.keys]]
+            key = "config-secret"
+            name = "partner"
+            limits = { rate_limit = { burst = 1, per_second = 0.0 } }
+            "#,
+        )
+        .unwrap();
+        let state = with_config(config);
+        state
+            .storage
+            .put_api_key(&auth::ApiKey {
+                key: auth::hash_key("stored-secret"),
+                name: "stored".to_string(),
+                limits: auth::TierLimits::default(),
+                admin: false,
+            })
+            .unwrap();
+        let app = app_router(state);
+        let uri = "/api/move?position=&level=6";
+        let status = |headers: &'static [(&'static str, &'static str)]| {
+            let app = app.clone();
+            async move { app.oneshot(get(uri, headers)).await.unwrap().status() }
+        };
+
+        assert_eq!(status(&[]).await, StatusCode::BAD_REQUEST);
+        const BEARER: &[(&str, &str)] = &[("authorization", "Bearer config-secret")];
+        assert_eq!(status(BEARER).await, StatusCode::OK);
+        // The key's single token is spent; other keys have buckets of their own.
+        assert_eq!(status(BEARER).await, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(
+            status(&[("x-api-key", "stored-secret")]).await,
+            StatusCode::OK
+        );
+        let (code, body) = {
+            let response = app
+                .clone()
+                .oneshot(get(uri, &[("x-api-key", "wrong")]))
+                .await
+                .unwrap();
+            let status = response.status();
+            let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            (status, body)
+        };
+        assert_eq!(code, StatusCode::UNAUTHORIZED);
+        let error: error::ErrorBody = serde_json::from_slice(&body).unwrap();
+        assert_eq!(error.code, "invalid_api_key");
+        let (status, _) = send_json(&app, "GET", "/api/move?position=&level=4", "").await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn guessing_api_keys_is_throttled_per_ip() {
+        let config: Config = toml::from_str(
+            r#"
+            [[auth.keys]]
+            key = "config-secret"
+            name = "partner"
+            "#,
+        )
+        .unwrap();
+        let app = app_router(with_config(config));
+        let status = |key: &'static str| {
+            let app = app.clone();
+            async move {
+                let request = get("/api/state?position=", &[("x-api-key", key)]);
+                app.oneshot(request).await.unwrap().status()
+            }
+        };
+        assert_eq!(status("config-secret").await, StatusCode::OK);
+        for _ in 0..10 {
+            assert_eq!(status("guess").await, StatusCode::UNAUTHORIZED);
+        }
+        assert_eq!(status("guess").await, StatusCode::TOO_MANY_REQUESTS);
+        // Refused before the lookup, so even a right guess learns nothing.
+        assert_eq!(status("config-secret").await, StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    #[tokio::test]
+    async fn batch_answers_each_position_in_order() {
+        let state = with_config(Config {
+            engine: config::EngineConfig {
+                max_batch: 3,
+                ..config::EngineConfig::default()
+            },
+            ..Config::default()
+        });
+        let app = app_router(state);
+        let body = r#"[
+            {"position": "R0B1R0B1R0B1", "level": 4},
+            {"position": "R0X1", "level": 4},
+            {"position": "", "level": 3, "multipv": 2}
+        ]"#;
+        let (status, bytes) = send_json(&app, "POST", "/api/moves", body).await;
+        assert_eq!(status, StatusCode::OK);
+        let items: Vec<BatchItem> = serde_json::from_slice(&bytes).unwrap();
+        assert!(matches!(&items[0], BatchItem::Move(mv) if mv.column == 0));
+        assert!(
+            matches!(&items[1], BatchItem::Error { error } if error.code == "invalid_position")
+        );
+        assert!(matches!(&items[2], BatchItem::Move(mv) if mv.lines.len() == 2));
+
+        let too_many = format!("[{}]", [r#"{"position":"","level":1}"#; 4].join(","));
+        let (status, bytes) = send_json(&app, "POST", "/api/moves", &too_many).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let error: error::ErrorBody = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(error.code, "batch_too_large");
+    }
+
+    #[tokio::test]
+    async fn batches_spend_a_token_per_position() {
+        let state = with_config(Config {
+            rate_limit: RateLimitConfig {
+                burst: 5,
+                per_second: 0.5,
+                trust_forwarded_for: false,
+            },
+            ..Config::default()
+        });
+        let app = app_router(state);
+        let batch = format!("[{}]", [r#"{"position":"","level":1}"#; 3].join(","));
+        let (status, _) = send_json(&app, "POST", "/api/moves", &batch).await;
+        assert_eq!(status, StatusCode::OK);
+        // Two tokens left: the request takes one and cannot cover the rest.
+        let response = app
+            .clone()
+            .oneshot(
+                Request::post("/api/moves")
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(axum::body::Body::from(batch))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()[header::RETRY_AFTER], "2");
+        // The refused batch spent nothing beyond its request token.
+        let (status, _) = send_json(&app, "GET", "/api/move?position=&level=1", "").await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn jobs_run_in_the_background() {
+        let app = app_router(AppState::default());
+        let response = app
+            .clone()
+            .oneshot(
+                Request::post("/api/jobs")
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(axum::body::Body::from(
+                        r#"{"position": "R0B1R0B1R0B1", "level": 6}"#,
+                    ))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        let location = response.headers()[header::LOCATION]
+            .to_str()
+            .unwrap()
+            .to_string();
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let job: jobs::Job = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(location, format!("/api/v1/jobs/{}", job.id));
+
+        let mut job = job;
+        for _ in 0..100 {
+            if job.is_finished() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            let (status, bytes) = send_json(&app, "GET", &location, "").await;
+            assert_eq!(status, StatusCode::OK);
+            job = serde_json::from_slice(&bytes).unwrap();
+        }
+        assert_eq!(job.status, jobs::JobStatus::Done);
+        assert_eq!(job.result.map(|mv| mv.column), Some(0));
+
+        let (status, _) = send_json(&app, "GET", "/api/jobs/missing", "").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let body = r#"{"position": "", "level": 6, "webhook": "ftp://example.com"}"#;
+        let (status, _) = send_json(&app, "POST", "/api/jobs", body).await;
+        // Anonymous callers may not make the server send requests for them.
+        let expected = if cfg!(feature = "webhooks") {
+            StatusCode::UNAUTHORIZED
+        } else {
+            StatusCode::NOT_IMPLEMENTED
+        };
+        assert_eq!(status, expected);
+        let (status, _) = send_json(
+            &app,
+            "POST",
+            "/api/jobs",
+            r#"{"position": "R0X", "level": 6}"#,
+        )
+        .await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[tokio::test]
+    async fn jobs_search_under_the_submitters_tier() {
+        let config: Config = toml::from_str(
+            r#"
+            [auth.anonymous]
+            max_level = 8
+            max_search_ms = 200
+            [jobs]
+            max_search_ms = 60000
+            [[auth.keys]]
+            key = "partner-secret"
+            name = "partner"
+            "#,
+        )
+        .unwrap();
+        let app = app_router(with_config(config));
+        let submit = |headers: &'static [(&'static str, &'static str)]| {
+            let mut request =
+                Request::post("/api/jobs").header(header::CONTENT_TYPE, "application/json");
+            for (name, value) in headers {
+                request = request.header(*name, *value);
+            }
+            let request = request
+                .body(axum::body::Body::from(
+                    r#"{"position": "R0B1R0B1R0B1", "level": 6}"#,
+                ))
+                .unwrap();
+            let app = app.clone();
+            async move {
+                let response = app.oneshot(request).await.unwrap();
+                assert_eq!(response.status(), StatusCode::ACCEPTED);
+                let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+                serde_json::from_slice::<jobs::Job>(&bytes)
+                    .unwrap()
+                    .limits
+                    .unwrap()
+            }
+        };
+        // Anonymous jobs get no more time than an anonymous move.
+        let limits = submit(&[]).await;
+        assert_eq!((limits.max_level, limits.max_search_ms), (8, 200));
+        let limits = submit(&[("x-api-key", "partner-secret")]).await;
+        assert_eq!(limits.max_search_ms, 60_000);
+        assert_eq!(limits.max_level, Config::default().max_level);
+    }
+
+    #[cfg(feature = "cluster")]
+    #[tokio::test]
+    async fn solver_jobs_are_split_across_cluster_workers() {
+        let worker = AppState::default();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let router = app_router(worker.clone());
+        tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+        // The first worker is down; its subtrees move on to the second.
+        let coordinator = app_router(with_config(Config {
+            cluster: cluster::ClusterConfig {
+                workers: vec!["http://127.0.0.1:1".to_string(), format!("http://{addr}")],
+                ..cluster::ClusterConfig::default()
+            },
+            ..Config::default()
+        }));
+        let position = "R3B3R3B3R3B2R2B4R4B4R2B2R4B1R1B5R5B0R0B6";
+        let body =
+            format!(r#"{{"position":"{position}","level":15,"algorithm":"solver","multipv":7}}"#);
+        let (status, bytes) = send_json(&coordinator, "POST", "/api/jobs", &body).await;
+        assert_eq!(status, StatusCode::ACCEPTED);
+        let mut job: jobs::Job = serde_json::from_slice(&bytes).unwrap();
+        let location = format!("/api/jobs/{}", job.id);
+        for _ in 0..500 {
+            if job.is_finished() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            let (_, bytes) = send_json(&coordinator, "GET", &location, "").await;
+            job = serde_json::from_slice(&bytes).unwrap();
+        }
+        assert_eq!(job.status, jobs::JobStatus::Done, "{:?}", job.error);
+        let distributed = job.result.unwrap();
+
+        let (_, bytes) =
+            send_json(&app_router(AppState::default()), "POST", "/api/move", &body).await;
+        let local: MoveResponse = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(distributed.column, local.column);
+        assert_eq!(distributed.score, local.score);
+        // Only the chosen line is proven; the solver stops once it is.
+        assert_eq!(distributed.depth, local.depth);
+        let solved: usize = worker.latencies.summary().iter().map(|l| l.samples).sum();
+        assert_eq!(solved, distributed.lines.len());
+    }
+
+    #[tokio::test]
+    async fn engine_decisions_are_logged_with_their_stats() {
+        #[derive(Clone, Default)]
+        struct Buffer(Arc<std::sync::Mutex<Vec<u8>>>);
+        impl std::io::Write for Buffer {
+            fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
+                self.0.lock().unwrap().extend_from_slice(bytes);
+                Ok(bytes.len())
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+        let buffer = Buffer::default();
+        let writer = buffer.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .json()
+            .with_writer(move || writer.clone())
+            .finish();
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let app = app_router(AppState::default());
+        let uri = "/api/v1/move?position=R3B3&level=4";
+        for _ in 0..2 {
+            let (status, _) = send_json(&app, "GET", uri, "").await;
+            assert_eq!(status, StatusCode::OK);
+        }
+        let logs = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let decisions: Vec<serde_json::Value> = logs
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+            .filter(|event| event["fields"]["message"] == "engine decision")
+            .map(|event| event["span"].clone())
+            .collect();
+        assert_eq!(decisions.len(), 2);
+        let (first, second) = (&decisions[0], &decisions[1]);
+        assert_eq!(first["name"], "engine");
+        assert_eq!(first["level"], 4);
+        assert_eq!(first["depth"], 4);
+        assert_eq!(first["source"], "search");
+        assert!(first["nodes"].as_u64().unwrap() > 0);
+        assert!(first["score"].is_i64() && first["elapsed_ms"].is_u64());
+        assert_eq!(second["source"], "memory_cache");
+        assert_eq!(
+            (&second["key"], &second["column"]),
+            (&first["key"], &first["column"])
+        );
+    }
+
+    #[tokio::test]
+    async fn selfplay_needs_an_admin_key() {
+        let config: Config = toml::from_str(
+            r#"
+            [[auth.keys]]
+            key = "admin-secret"
+            name = "admin"
+            admin = true
+            [[auth.keys]]
+            key = "partner-secret"
+            name = "partner"
+            "#,
+        )
+        .unwrap();
+        let app = app_router(with_config(config));
+        let body = r#"{"games":2,"red":{"level":4},"blue":{"level":1,"randomness":0.5},"seed":5}"#;
+        let post = |uri: &'static str, key: &'static str, body: &'static str| {
+            let app = app.clone();
+            async move {
+                let response = app
+                    .oneshot(
+                        Request::post(uri)
+                            .header(header::CONTENT_TYPE, "application/json")
+                            .header(auth::API_KEY_HEADER, key)
+                            .body(axum::body::Body::from(body))
+                            .unwrap(),
+                    )
+                    .await
+                    .unwrap();
+                let status = response.status();
+                let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+                (status, bytes)
+            }
+        };
+        let (status, _) = send_json(&app, "POST", "/api/selfplay", body).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(
+            post("/api/selfplay", "partner-secret", body).await.0,
+            StatusCode::FORBIDDEN
+        );
+        let (status, _) = post(
+            "/api/selfplay",
+            "admin-secret",
+            r#"{"games":0,"red":{"level":1},"blue":{"level":1}}"#,
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let (status, bytes) = post("/api/selfplay", "admin-secret", body).await;
+        assert_eq!(status, StatusCode::OK);
+        let games: Vec<storage::GameRecord> = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(games.len(), 2);
+        for game in &games {
+            assert_eq!((game.red_level, game.blue_level), (Some(4), Some(1)));
+            let state = GameState::from_history(&parse_history(&game.history).unwrap()).unwrap();
+            assert_eq!(storage::GameResult::of(&state), Some(game.result));
+        }
+        // Seeded matches replay move for move.
+        let again: Vec<storage::GameRecord> =
+            serde_json::from_slice(&post("/api/selfplay", "admin-secret", body).await.1).unwrap();
+        let histories = |games: &[storage::GameRecord]| {
+            games.iter().map(|g| g.history.clone()).collect::<Vec<_>>()
+        };
+        assert_eq!(histories(&again), histories(&games));
+
+        let body = r#"{"engine":{"level":3},"levels":[1,3],"games":2,"seed":5}"#;
+        let (status, _) = send_json(&app, "POST", "/api/selfplay/elo", body).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let (status, bytes) = post("/api/selfplay/elo", "admin-secret", body).await;
+        assert_eq!(status, StatusCode::OK);
+        let estimate: connect4::strength::EloEstimate = serde_json::from_slice(&bytes).unwrap();
+        let levels: Vec<u8> = estimate.results.iter().map(|r| r.level.get()).collect();
+        assert_eq!(levels, [1, 3]);
+        assert!(estimate.results.iter().all(|r| r.score.games() == 2));
+        assert!(estimate.elo.is_finite() && estimate.margin > 0.0);
+        let (status, _) = post(
+            "/api/selfplay/elo",
+            "admin-secret",
+            r#"{"engine":{"level":3},"games":101}"#,
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn admin_stats_and_flush() {
+        let config: Config = toml::from_str(
+            r#"
+            [[auth.keys]]
+            key = "admin-secret"
+            name = "admin"
+            admin = true
+            "#,
+        )
+        .unwrap();
+        let app = app_router(with_config(config));
+        let admin = |method: &'static str, uri: &'static str| {
+            let app = app.clone();
+            async move {
+                let response = app
+                    .oneshot(
+                        Request::builder()
+                            .method(method)
+                            .uri(uri)
+                            .header(auth::API_KEY_HEADER, "admin-secret")
+                            .body(axum::body::Body::empty())
+                            .unwrap(),
+                    )
+                    .await
+                    .unwrap();
+                let status = response.status();
+                let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+                (
+                    status,
+                    serde_json::from_slice::<serde_json::Value>(&bytes).unwrap(),
+                )
+            }
+        };
+        let (status, _) = send_json(&app, "GET", "/api/v1/admin/stats", "").await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let (status, _) = send_json(&app, "POST", "/api/v1/admin/flush", "").await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        for _ in 0..2 {
+            let (status, _) = send_json(&app, "GET", "/api/v1/move?position=R3&level=5", "").await;
+            assert_eq!(status, StatusCode::OK);
+        }
+        let (status, stats) = admin("GET", "/api/v1/admin/stats").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(stats["move_cache"]["entries"], 1);
+        assert_eq!(stats["search_pool"]["running"], 0);
+        assert_eq!(stats["jobs"]["queued"], 0);
+        // The second request was a cache hit and is not a latency sample.
+        assert_eq!(stats["latency"][0]["level"], 5);
+        assert_eq!(stats["latency"][0]["samples"], 1);
+
+        let table = &stats["transposition_table"];
+        assert_eq!(table["bytes"], 16 << 20);
+        assert_eq!(table["replacement"], "two_tier");
+        assert!(table["hits"].as_u64().unwrap() > 0);
+        assert!(table["hit_rate"].as_f64().unwrap() > 0.0);
+
+        let (status, flushed) = admin("POST", "/api/v1/admin/flush?cache=memory").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(flushed["memory_entries"], 1);
+        assert_eq!(flushed["table_entries"], 0);
+        let (_, stats) = admin("GET", "/api/v1/admin/stats").await;
+        assert_eq!(stats["move_cache"]["entries"], 0);
+        let (_, flushed) = admin("POST", "/api/v1/admin/flush?cache=table").await;
+        assert!(flushed["table_entries"].as_u64().unwrap() > 0);
+        let (_, stats) = admin("GET", "/api/v1/admin/stats").await;
+        assert_eq!(stats["transposition_table"]["occupancy"], 0.0);
+        assert_eq!(stats["transposition_table"]["probes"], 0);
+        assert_eq!(stats["latency"][0]["samples"], 1);
+        admin("POST", "/api/v1/admin/flush").await;
+        let (_, stats) = admin("GET", "/api/v1/admin/stats").await;
+        assert_eq!(stats["latency"], serde_json::json!([]));
+    }
+
+    #[tokio::test]
+    async fn shared_table_hits_grow_across_moves_until_flushed() {
+        let config: Config = toml::from_str(
+            r#"
+            [[auth.keys]]
+            key = "admin-secret"
+            name = "admin"
+            admin = true
+            "#,
+        )
+        .unwrap();
+        let app = app_router(with_config(config));
+        let admin = |method: &str, uri: &str| {
+            let request = Request::builder()
+                .method(method)
+                .uri(uri)
+                .header(auth::API_KEY_HEADER, "admin-secret")
+                .body(axum::body::Body::empty())
+                .unwrap();
+            let app = app.clone();
+            async move {
+                let response = app.oneshot(request).await.unwrap();
+                let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+                serde_json::from_slice::<serde_json::Value>(&bytes).unwrap()
+            }
+        };
+        let hits = || async {
+            let stats = admin("GET", "/api/v1/admin/stats").await;
+            stats["transposition_table"]["hits"].as_u64().unwrap()
+        };
+        assert_eq!(hits().await, 0);
+        let mut seen = 0;
+        // Different positions, so neither answer comes from the move cache.
+        for uri in [
+            "/api/v1/move?position=R3&level=7",
+            "/api/v1/move?position=R3B3&level=7",
+        ] {
+            let (status, _) = send_json(&app, "GET", uri, "").await;
+            assert_eq!(status, StatusCode::OK);
+            let now = hits().await;
+            assert!(now > seen, "{uri}: {now} hits after {seen}");
+            seen = now;
+        }
+        admin("POST", "/api/v1/admin/flush?cache=table").await;
+        assert_eq!(hits().await, 0);
+    }
+
+    #[tokio::test]
+    async fn opening_book_answers_moves_and_reports_its_version() {
+        let path = std::env::temp_dir().join(format!("connect4-book-{}.txt", std::process::id()));
+        std::fs::write(&path, "c4book 1 2026.10\n- 2 1 15\nR3 4\n").unwrap();
+        let mut config: Config = toml::from_str(
+            r#"
+            [[auth.keys]]
+            key = "admin-secret"
+            name = "admin"
+            admin = true
+            "#,
+        )
+        .unwrap();
+        config.book.path = Some(path.clone());
+        let state = with_config(config);
+        state.book.load().unwrap();
+        let app = app_router(state);
+
+        let (_, body) = send_json(&app, "GET", "/api/v1/move?position=&level=8", "").await;
+        let mv: MoveResponse = serde_json::from_slice(&body).unwrap();
+        assert_eq!((mv.column, mv.depth), (2, Some(8)));
+        let (_, body) = send_json(&app, "GET", "/api/v1/move?position=R3&level=2", "").await;
+        let mv: MoveResponse = serde_json::from_slice(&body).unwrap();
+        assert_eq!(mv.column, 4);
+
+        let (status, _) = send_json(&app, "GET", "/api/v1/admin/book/info", "").await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let response = app
+            .oneshot(
+                Request::get("/api/admin/book/info")
+                    .header(auth::API_KEY_HEADER, "admin-secret")
+                    .body(axum::body::Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let info: book::BookInfo = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(info.version.as_deref(), Some("2026.10"));
+        assert_eq!((info.entries, info.positions), (2, 2));
+        assert!(info.loaded_at.is_some() && info.last_error.is_none());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn admins_edit_the_opening_book_and_the_file_follows() {
+        let path =
+            std::env::temp_dir().join(format!("connect4-book-edit-{}.txt", std::process::id()));
+        std::fs::write(&path, "c4book 1 v1\nR0 1\n").unwrap();
+        let mut config: Config = toml::from_str(
+            r#"
+            [[auth.keys]]
+            key = "admin-secret"
+            name = "admin"
+            admin = true
+            "#,
+        )
+        .unwrap();
+        config.book.path = Some(path.clone());
+        let state = with_config(config);
+        state.book.load().unwrap();
+        let app = app_router(state);
+        let admin = |method: &str, uri: &str, body: &str| {
+            Request::builder()
+                .method(method)
+                .uri(uri)
+                .header(auth::API_KEY_HEADER, "admin-secret")
+                .header("content-type", "application/json")
+                .body(axum::body::Body::from(body.to_string()))
+                .unwrap()
+        };
+        let response = app
+            .clone()
+            .oneshot(get("/api/v1/move?position=R0&level=4", &[]))
+            .await
+            .unwrap();
+        assert_eq!(response.headers()[header::CACHE_CONTROL], CACHEABLE_MOVE);
+        let etag = response.headers()[header::ETAG]
+            .to_str()
+            .unwrap()
+            .to_string();
+
+        let (status, _) = send_json(
+            &app,
+            "PUT",
+            "/api/v1/admin/book/entries",
+            r#"{"position":"","column":3}"#,
+        )
+        .await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        // Column 5 after R6 is the mirror image of the existing 1 after R0.
+        let response = app
+            .clone()
+            .oneshot(admin(
+                "PUT",
+                "/api/v1/admin/book/entries",
+                r#"{"position":"R6","column":5,"weight":3,"eval":7}"#,
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let edit: admin::BookEdit = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(edit.previous.map(|entry| entry.weight), Some(1));
+        assert_eq!(edit.book.entries, 1);
+        // The book answers R0, so the edit retired the tag a client holds.
+        let response = app
+            .clone()
+            .oneshot(get(
+                "/api/v1/move?position=R0&level=4",
+                &[("if-none-match", &etag)],
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_ne!(response.headers()[header::ETAG], etag.as_str());
+        let response = app
+            .clone()
+            .oneshot(admin(
+                "PUT",
+                "/api/v1/admin/book/entries",
+                r#"{"position":"R3","column":3}"#,
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let reread = connect4::book::OpeningBook::load(&path).unwrap();
+        assert_eq!(reread.len(), 2);
+
+        let response = app
+            .clone()
+            .oneshot(admin("GET", "/api/v1/admin/book/entries?position=R0", ""))
+            .await
+            .unwrap();
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let entries: Vec<connect4::book::BookEntry> = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(
+            (entries[0].column, entries[0].weight, entries[0].eval),
+            (1, 3, Some(7))
+        );
+
+        for (body, status) in [
+            (
+                r#"{"position":"R3B9","column":3}"#,
+                StatusCode::UNPROCESSABLE_ENTITY,
+            ),
+            (
+                r#"{"position":"","column":7}"#,
+                StatusCode::UNPROCESSABLE_ENTITY,
+            ),
+            (
+                r#"{"position":"","column":3,"weight":0}"#,
+                StatusCode::UNPROCESSABLE_ENTITY,
+            ),
+        ] {
+            let response = app
+                .clone()
+                .oneshot(admin("PUT", "/api/v1/admin/book/entries", body))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), status, "{body}");
+        }
+
+        let response = app
+            .clone()
+            .oneshot(admin(
+                "DELETE",
+                "/api/v1/admin/book/entries?position=R3&column=3",
+                "",
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = app
+            .clone()
+            .oneshot(admin(
+                "DELETE",
+                "/api/v1/admin/book/entries?position=R3&column=3",
+                "",
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(connect4::book::OpeningBook::load(&path).unwrap().len(), 1);
+        std::fs::remove_file(&path).unwrap();
+
+        let app = app_router(with_config(
+            toml::from_str(
+                r#"
+                [[auth.keys]]
+                key = "admin-secret"
+                name = "admin"
+                admin = true
+                "#,
+            )
+            .unwrap(),
+        ));
+        let response = app
+            .oneshot(admin(
+                "PUT",
+                "/api/v1/admin/book/entries",
+                r#"{"position":"","column":3}"#,
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+    }
+
+    #[tokio::test]
+    async fn openapi_spec_describes_the_api() {
+        let app = app_router(AppState::default());
+        let (status, body) = send_json(&app, "GET", "/api/v1/openapi.json", "").await;
+        assert_eq!(status, StatusCode::OK);
+        let spec: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let paths = spec["paths"].as_object().unwrap();
+        assert!(paths["/api/v1/move"].get("get").is_some());
+        assert!(paths["/api/v1/move"].get("post").is_some());
+        assert!(paths.contains_key("/api/v1/rooms/{code}/ws"));
+        assert!(spec["components"]["schemas"]["BoardModel"].is_object());
+        let (status, body) = send_json(&app, "GET", "/api/v1/docs", "").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(String::from_utf8(body)
+            .unwrap()
+            .contains("/api/v1/openapi.json"));
+    }
+
+    #[tokio::test]
+    async fn unversioned_paths_alias_v1() {
+        let app = app_router(AppState::default());
+        let fetch = |uri: &'static str| {
+            let app = app.clone();
+            async move {
+                let response = app.oneshot(get(uri, &[])).await.unwrap();
+                let deprecation = response.headers().get("deprecation").cloned();
+                let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+                (deprecation, body)
+            }
+        };
+        let (current, body) = fetch("/api/v1/state?position=R3B3").await;
+        let (legacy, legacy_body) = fetch("/api/state?position=R3B3").await;
+        assert_eq!(current, None);
+        assert_eq!(legacy.unwrap(), "true");
+        assert_eq!(body, legacy_body);
+    }
+
+    #[tokio::test]
+    async fn http_state_endpoint() {
+        let app = app_router(AppState::default());
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/api/state?position=R0B1R0B1R0B1R0")
+                    .body(axum::body::Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let board: BoardModel = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(board.status, connect4::GameStatus::Won);
+        assert_eq!(board.winner, Some(connect4::Player::Red));
+        assert_eq!(board.winning_line.map(|line| line.len()), Some(4));
+    }
+
+    #[tokio::test]
+    async fn http_validate_endpoint_reports_diagnostics() {
+        let app = app_router(AppState::default());
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/api/validate?position=R3B3B4")
+                    .body(axum::body::Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let result: ValidateResponse = serde_json::from_slice(&bytes).unwrap();
+        assert!(!result.valid);
+        let error = result.error.unwrap();
+        assert_eq!((error.position, error.move_index), (4, 2));
+    }
+
+    #[tokio::test]
+    async fn http_move_stream_reports_progress_then_done() {
+        let app = app_router(AppState::default());
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/api/move/stream?position=B0R3B1R4B2R5&level=4")
+                    .body(axum::body::Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let text = String::from_utf8(bytes.to_vec()).unwrap();
+        assert_eq!(text.matches("event: progress").count(), 4);
+        assert!(
+            text.trim_end().ends_with(r#"data: {"column":6,"depth":4}"#),
+            "{text}"
+        );
+    }
+
+    #[tokio::test]
+    async fn abandoned_move_stream_frees_its_worker() {
+        let state = with_config(Config {
+            max_search_ms: 60_000,
+            ..Config::default()
+        });
+        let app = app_router(state.clone());
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/api/move/stream?position=&level=15")
+                    .body(axum::body::Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let mut body = response.into_body().into_data_stream();
+        let first = futures_util::StreamExt::next(&mut body).await.unwrap();
+        assert!(String::from_utf8_lossy(&first.unwrap()).contains("event: progress"));
+        drop(body);
+        let started = Instant::now();
+        while state.search_pool.stats().running > 0 {
+            assert!(
+                started.elapsed() < Duration::from_secs(5),
+                "search still running"
+            );
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn review_grades_a_whole_game_at_once_or_move_by_move() {
+        let app = app_router(AppState::default());
+        let body = r#"{"position":"R0B0R1B1R2B6R3","depth":4}"#;
+        let (status, bytes) = send_json(&app, "POST", "/api/review", body).await;
+        assert_eq!(status, StatusCode::OK);
+        let review: connect4::review::GameReview = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(review.moves.len(), 7);
+        assert_eq!(review.moves[5].alternatives[0].column, 3);
+        let bad = r#"{"position":"R0B0R9","depth":4}"#;
+        let (status, _) = send_json(&app, "POST", "/api/review", bad).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/api/review")
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .header(header::ACCEPT, "text/event-stream")
+                    .body(axum::body::Body::from(body))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let text = String::from_utf8(bytes.to_vec()).unwrap();
+        assert_eq!(text.matches("event: move").count(), 7);
+        let done = text.split("event: done\ndata: ").nth(1).unwrap();
+        let streamed: connect4::review::GameReview = serde_json::from_str(done.trim()).unwrap();
+        assert_eq!(streamed, review);
+    }
+
+    async fn send_json(app: &Router, method: &str, uri: &str, body: &str) -> (StatusCode, Vec<u8>) {
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method(method)
+                    .uri(uri)
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(axum::body::Body::from(body.to_string()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let status = response.status();
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, bytes.to_vec())
+    }
+
+    #[tokio::test]
+    async fn session_lifecycle() {
+        let app = app_router(AppState::default());
+        let (status, body) = send_json(&app, "POST", "/api/games", r#"{"level":3}"#).await;
+        assert_eq!(status, StatusCode::CREATED);
+        let game: sessions::GameView = serde_json::from_slice(&body).unwrap();
+        assert_eq!(game.history, "");
+        assert_eq!(game.engine_move, None);
+
+        let uri = format!("/api/games/{}/move", game.id);
+        let (status, body) = send_json(&app, "POST", &uri, r#"{"column":3}"#).await;
+        assert_eq!(status, StatusCode::OK);
+        let game: sessions::GameView = serde_json::from_slice(&body).unwrap();
+        let reply = game.engine_move.expect("engine answers");
+        assert_eq!(game.history, format!("R3B{reply}"));
+        assert_eq!(game.board.to_move, Player::Red);
+
+        let (status, body) = send_json(&app, "POST", &uri, r#"{"column":9}"#).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        let error: error::ErrorBody = serde_json::from_slice(&body).unwrap();
+        assert_eq!(error.code, "column_out_of_bounds");
+        let (status, _) = send_json(&app, "POST", "/api/games/nope/move", r#"{"column":3}"#).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn sessions_take_back_move_pairs_up_to_the_limit() {
+        let app = app_router(with_config(Config {
+            max_takebacks: 1,
+            ..Config::default()
+        }));
+        let (_, body) = send_json(&app, "POST", "/api/games", r#"{"level":3}"#).await;
+        let game: sessions::GameView = serde_json::from_slice(&body).unwrap();
+        assert_eq!(game.takebacks_left, 1);
+        let takeback = format!("/api/games/{}/takeback", game.id);
+        let (status, _) = send_json(&app, "POST", &takeback, "").await;
+        assert_eq!(status, StatusCode::CONFLICT);
+
+        let uri = format!("/api/games/{}/move", game.id);
+        send_json(&app, "POST", &uri, r#"{"column":3}"#).await;
+        let (status, body) = send_json(&app, "POST", &takeback, "").await;
+        assert_eq!(status, StatusCode::OK);
+        let game: sessions::GameView = serde_json::from_slice(&body).unwrap();
+        assert_eq!(game.history, "");
+        assert_eq!(game.board.to_move, Player::Red);
+        assert_eq!(game.takebacks_left, 0);
+
+        send_json(&app, "POST", &uri, r#"{"column":2}"#).await;
+        let (status, body) = send_json(&app, "POST", &takeback, "").await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        let error: error::ErrorBody = serde_json::from_slice(&body).unwrap();
+        assert!(error.message.contains("no takebacks left"));
+    }
+
+    #[tokio::test]
+    async fn hints_are_budgeted_and_archived() {
+        let state = with_config(Config {
+            max_hints: 1,
+            ..Config::default()
+        });
+        let app = app_router(state.clone());
+        let (_, body) = send_json(&app, "POST", "/api/games", r#"{"level":4}"#).await;
+        let mut game: sessions::GameView = serde_json::from_slice(&body).unwrap();
+        assert_eq!(game.hints_left, 1);
+        let hint = format!("/api/games/{}/hint", game.id);
+        let (status, body) = send_json(&app, "POST", &hint, "").await;
+        assert_eq!(status, StatusCode::OK);
+        let given: sessions::HintView = serde_json::from_slice(&body).unwrap();
+        assert_eq!(given.column, 3);
+        assert!(given
+            .reasons
+            .contains(&connect4::explain::Reason::TakesCenter));
+        assert_eq!(given.hints_left, 0);
+        let (status, _) = send_json(&app, "POST", &hint, "").await;
+        assert_eq!(status, StatusCode::CONFLICT);
+
+        // The human stacks one column and loses; the record keeps the count.
+        while !game.is_over() {
+            let column = game.board.legal_columns[0];
+            let uri = format!("/api/games/{}/move", game.id);
+            let (_, body) =
+                send_json(&app, "POST", &uri, &format!(r#"{{"column":{column}}}"#)).await;
+            game = serde_json::from_slice(&body).unwrap();
+        }
+        assert_eq!(game.hints_left, 0);
+        let record = state.storage.game(&game.id).unwrap().unwrap();
+        assert_eq!(record.hints, Some(1));
+    }
+
+    #[tokio::test]
+    async fn session_searches_spend_rate_limit_tokens() {
+        let app = app_router(with_config(Config {
+            max_hints: 10,
+            rate_limit: RateLimitConfig {
+                burst: 3,
+                per_second: 0.01,
+                trust_forwarded_for: true,
+            },
+            ..Config::default()
+        }));
+        let post = |uri: &str, client: &str| {
+            let request = Request::post(uri)
+                .header(header::CONTENT_TYPE, "application/json")
+                .header("x-forwarded-for", client)
+                .body(axum::body::Body::from(r#"{"level":4}"#))
+                .unwrap();
+            let app = app.clone();
+            async move { app.oneshot(request).await.unwrap() }
+        };
+        let response = post("/api/games", "10.0.0.1").await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let game: sessions::GameView = serde_json::from_slice(&bytes).unwrap();
+        let hint = format!("/api/games/{}/hint", game.id);
+        for _ in 0..2 {
+            assert_eq!(post(&hint, "10.0.0.1").await.status(), StatusCode::OK);
+        }
+        // Creating the game and two hints spent the burst.
+        let response = post(&hint, "10.0.0.1").await;
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert!(response.headers().contains_key(header::RETRY_AFTER));
+        assert_eq!(post(&hint, "10.0.0.2").await.status(), StatusCode::OK);
+        // Reading the game searches nothing and costs nothing.
+        let (status, _) = send_json(&app, "GET", &format!("/api/games/{}", game.id), "").await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn players_keep_statistics_across_games_and_puzzles() {
+        let app = app_router(AppState::default());
+        let send = |method: &'static str, uri: String, token: &str, body: &'static str| {
+            let request = Request::builder()
+                .method(method)
+                .uri(uri)
+                .header(header::CONTENT_TYPE, "application/json")
+                .header(users::USER_TOKEN_HEADER, token)
+                .body(axum::body::Body::from(body))
+                .unwrap();
+            let app = app.clone();
+            async move {
+                let response = app.oneshot(request).await.unwrap();
+                let status = response.status();
+                let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+                (status, bytes)
+            }
+        };
+        let (status, _) = send_json(&app, "POST", "/api/users", r#"{"name":" "}"#).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let (status, body) = send_json(&app, "POST", "/api/users", r#"{"name":"Ada"}"#).await;
+        assert_eq!(status, StatusCode::CREATED);
+        let ticket: users::UserTicket = serde_json::from_slice(&body).unwrap();
+        let (status, _) = send_json(&app, "GET", "/api/users/me/stats", "").await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let (status, _) = send("GET", "/api/users/me/stats".into(), "forged", "").await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let (status, _) = send("POST", "/api/games".into(), "forged", r#"{"level":3}"#).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        // Stacking one column loses quickly, blundering on the way.
+        let (_, body) = send("POST", "/api/games".into(), &ticket.token, r#"{"level":3}"#).await;
+        let mut game: sessions::GameView = serde_json::from_slice(&body).unwrap();
+        while !game.is_over() {
+            let column = game.board.legal_columns[0];
+            let uri = format!("/api/games/{}/move", game.id);
+            let (_, body) =
+                send_json(&app, "POST", &uri, &format!(r#"{{"column":{column}}}"#)).await;
+            game = serde_json::from_slice(&body).unwrap();
+        }
+        for solved in [r#"{"solved":true}"#, r#"{"solved":true}"#] {
+            send(
+                "POST",
+                "/api/users/me/puzzles".into(),
+                &ticket.token,
+                solved,
+            )
+            .await;
+        }
+        let (status, body) = send("GET", "/api/users/me/stats".into(), &ticket.token, "").await;
+        assert_eq!(status, StatusCode::OK);
+        let stats: users::StatsView = serde_json::from_slice(&body).unwrap();
+        assert_eq!((stats.name.as_str(), stats.games), ("Ada", 1));
+        assert_eq!(stats.score.losses, 1);
+        assert_eq!(stats.by_level[0].level, 3);
+        assert!(stats.average_blunders > 0.0);
+        assert_eq!((stats.puzzle_streak, stats.best_puzzle_streak), (2, 2));
+        // The archive never shows whose game it was.
+        let (_, body) = send_json(&app, "GET", &format!("/api/games/{}", game.id), "").await;
+        assert!(!String::from_utf8_lossy(&body).contains(&ticket.token));
+    }
+
+    #[tokio::test]
+    async fn aggregate_stats_count_lost_games_and_their_blunders() {
+        let app = app_router(AppState::default());
+        let (_, body) = send_json(&app, "GET", "/api/stats/aggregate", "").await;
+        let stats: telemetry::AggregateStats = serde_json::from_slice(&body).unwrap();
+        assert_eq!(stats.games, 0);
+
+        let (_, body) = send_json(&app, "POST", "/api/games", r#"{"level":3}"#).await;
+        let mut game: sessions::GameView = serde_json::from_slice(&body).unwrap();
+        while !game.is_over() {
+            let column = game.board.legal_columns[0];
+            let uri = format!("/api/games/{}/move", game.id);
+            let (_, body) =
+                send_json(&app, "POST", &uri, &format!(r#"{{"column":{column}}}"#)).await;
+            game = serde_json::from_slice(&body).unwrap();
+        }
+        let (status, body) = send_json(&app, "GET", "/api/stats/aggregate", "").await;
+        assert_eq!(status, StatusCode::OK);
+        let stats: telemetry::AggregateStats = serde_json::from_slice(&body).unwrap();
+        assert_eq!(stats.games, 1);
+        assert_eq!(stats.levels[0].level, 3);
+        assert_eq!(stats.levels[0].human_score.losses, 1);
+        assert_eq!(stats.levels[0].human_win_rate, 0.0);
+        assert!(stats.levels[0].average_length >= 7.0);
+        assert!(!stats.losing_blunders.is_empty());
+    }
+
+    #[tokio::test]
+    async fn adaptive_sessions_continue_as_a_series() {
+        let app = app_router(AppState::default());
+        let (_, body) =
+            send_json(&app, "POST", "/api/games", r#"{"level":4,"adaptive":true}"#).await;
+        let mut game: sessions::GameView = serde_json::from_slice(&body).unwrap();
+        let status = game.adaptive.expect("adaptive games report the series");
+        assert_eq!((status.level.get(), status.score.games()), (4, 0));
+        let next = format!("/api/games/{}/next", game.id);
+        let (status, _) = send_json(&app, "POST", &next, "").await;
+        assert_eq!(status, StatusCode::CONFLICT);
+
+        // The human stacks one column and loses quickly.
+        while !game.is_over() {
+            let column = game.board.legal_columns[0];
+            let uri = format!("/api/games/{}/move", game.id);
+            let (_, body) =
+                send_json(&app, "POST", &uri, &format!(r#"{{"column":{column}}}"#)).await;
+            game = serde_json::from_slice(&body).unwrap();
+        }
+        let (status, body) = send_json(&app, "POST", &next, "").await;
+        assert_eq!(status, StatusCode::CREATED);
+        let second: sessions::GameView = serde_json::from_slice(&body).unwrap();
+        assert_ne!(second.id, game.id);
+        assert_eq!(second.human, Player::Blue);
+        assert!(second.engine_move.is_some());
+        let status = second.adaptive.unwrap();
+        assert_eq!(status.score.losses, 1);
+        assert!(status.level.get() < 4);
+        assert!(status.blunder_rate > 0.0);
+        let (status, _) = send_json(&app, "POST", &next, "").await;
+        assert_eq!(status, StatusCode::CONFLICT);
+
+        let (_, body) = send_json(&app, "POST", "/api/games", r#"{"level":2}"#).await;
+        let plain: sessions::GameView = serde_json::from_slice(&body).unwrap();
+        assert!(plain.adaptive.is_none());
+        let uri = format!("/api/games/{}/next", plain.id);
+        let (status, _) = send_json(&app, "POST", &uri, "").await;
+        assert_eq!(status, StatusCode::CONFLICT);
+    }
+
+    #[tokio::test]
+    async fn sessions_answer_pondered_replies_at_once() {
+        let state = AppState::default();
+        let app = app_router(state.clone());
+        let (_, body) = send_json(&app, "POST", "/api/games", r#"{"level":6}"#).await;
+        let game: sessions::GameView = serde_json::from_slice(&body).unwrap();
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while state.ponder.stats().prepared < 3 {
+            assert!(Instant::now() < deadline, "pondering did not finish");
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        assert_eq!(state.ponder.stats().started, 1);
+
+        // The centre is the likeliest opening, so its answer is ready.
+        let uri = format!("/api/games/{}/move", game.id);
+        let (_, body) = send_json(&app, "POST", &uri, r#"{"column":3}"#).await;
+        let game: sessions::GameView = serde_json::from_slice(&body).unwrap();
+        assert_eq!(state.ponder.stats().hits, 1);
+        let expected = connect4::best_move(MoveRequest {
+            position: "R3".to_string(),
+            level: 6,
+            first_player: None,
+        })
+        .unwrap();
+        assert_eq!(game.engine_move, Some(expected.column));
+    }
+
+    #[tokio::test]
+    async fn session_moves_wait_for_a_search_worker() {
+        let state = with_config(Config {
+            search: SearchPoolConfig {
+                workers: 1,
+                queue: 0,
+                ..SearchPoolConfig::default()
+            },
+            ponder: sessions::PonderConfig {
+                enabled: false,
+                ..sessions::PonderConfig::default()
+            },
+            ..Config::default()
+        });
+        let app = app_router(state.clone());
+        let (_, body) = send_json(&app, "POST", "/api/games", r#"{"level":2}"#).await;
+        let game: sessions::GameView = serde_json::from_slice(&body).unwrap();
+        let uri = format!("/api/games/{}/move", game.id);
+
+        let held = state.search_pool.admit(1).unwrap();
+        let (status, _) = send_json(&app, "POST", &uri, r#"{"column":3}"#).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        let (status, _) = send_json(
+            &app,
+            "POST",
+            "/api/games",
+            r#"{"engine_first":true,"level":2}"#,
+        )
+        .await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        drop(held);
+        // The refused move was never played, so it can be played now.
+        let (status, body) = send_json(&app, "POST", &uri, r#"{"column":3}"#).await;
+        assert_eq!(status, StatusCode::OK);
+        let game: sessions::GameView = serde_json::from_slice(&body).unwrap();
+        assert!(game.history.starts_with("R3B"));
+        assert_eq!(state.search_pool.stats().running, 0);
+    }
+
+    #[tokio::test]
+    async fn untimed_sessions_search_within_the_tier_budget() {
+        let app = app_router(with_config(Config {
+            auth: auth::AuthConfig {
+                anonymous: auth::TierLimits {
+                    max_search_ms: Some(1),
+                    ..auth::TierLimits::default()
+                },
+                ..auth::AuthConfig::default()
+            },
+            ponder: sessions::PonderConfig {
+                enabled: false,
+                ..sessions::PonderConfig::default()
+            },
+            ..Config::default()
+        }));
+        // Unbounded, these two level-15 searches take seconds.
+        let started = Instant::now();
+        let body = r#"{"engine_first":true,"level":15}"#;
+        let (status, body) = send_json(&app, "POST", "/api/games", body).await;
+        assert_eq!(status, StatusCode::CREATED);
+        let game: sessions::GameView = serde_json::from_slice(&body).unwrap();
+        let uri = format!("/api/games/{}/move", game.id);
+        let (status, body) = send_json(&app, "POST", &uri, r#"{"column":3}"#).await;
+        assert_eq!(status, StatusCode::OK);
+        let game: sessions::GameView = serde_json::from_slice(&body).unwrap();
+        assert_eq!(game.history.len(), 6);
+        assert!(started.elapsed() < Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn idle_sessions_expire() {
+        let state = AppState {
+            sessions: sessions::SessionStore::new(Duration::ZERO),
+            ..AppState::default()
+        };
+        let app = app_router(state.clone());
+        let (_, body) = send_json(&app, "POST", "/api/games", r#"{"level":2}"#).await;
+        let game: sessions::GameView = serde_json::from_slice(&body).unwrap();
+        let uri = format!("/api/games/{}", game.id);
+        let (status, _) = send_json(&app, "GET", &uri, "").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(state.sessions.evict_expired(), 1);
+    }
+
+    #[tokio::test]
+    async fn rooms_seat_two_players_and_matchmake() {
+        let app = app_router(AppState::default());
+        let (status, body) = send_json(&app, "POST", "/api/rooms", "{}").await;
+        assert_eq!(status, StatusCode::CREATED);
+        let host: rooms::RoomTicket = serde_json::from_slice(&body).unwrap();
+        assert_eq!(host.color, Player::Red);
+
+        let join = format!("/api/rooms/{}/join", host.code);
+        let (_, body) = send_json(&app, "POST", &join, "").await;
+        let guest: rooms::RoomTicket = serde_json::from_slice(&body).unwrap();
+        assert_eq!(guest.color, Player::Blue);
+        assert_ne!(guest.token, host.token);
+        let (status, _) = send_json(&app, "POST", &join, "").await;
+        assert_eq!(status, StatusCode::CONFLICT);
+
+        // Private rooms are never matched; two quick-match calls pair up.
+        let (_, body) = send_json(&app, "POST", "/api/rooms/match", "").await;
+        let first: rooms::RoomTicket = serde_json::from_slice(&body).unwrap();
+        let (_, body) = send_json(&app, "POST", "/api/rooms/match", "").await;
+        let second: rooms::RoomTicket = serde_json::from_slice(&body).unwrap();
+        assert_eq!(first.code, second.code);
+        assert_ne!(first.code, host.code);
+        assert_eq!(second.color, Player::Blue);
+    }
+
+    #[tokio::test]
+    async fn room_websocket_relays_moves_and_enforces_turns() {
+        use tokio_tungstenite::tungstenite::Message;
+
+        let app = app_router(AppState::default());
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (_, body) = send_json(&app, "POST", "/api/rooms", r#"{"kibitzer":true}"#).await;
+        let red: rooms::RoomTicket = serde_json::from_slice(&body).unwrap();
+        let (_, body) = send_json(&app, "POST", &format!("/api/rooms/{}/join", red.code), "").await;
+        let blue: rooms::RoomTicket = serde_json::from_slice(&body).unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let connect = |ticket: &rooms::RoomTicket| {
+            let url = format!(
+                "ws://{addr}/api/rooms/{}/ws?token={}",
+                ticket.code, ticket.token
+            );
+            async move { tokio_tungstenite::connect_async(url).await.unwrap().0 }
+        };
+        async fn next(
+            ws: &mut (impl futures_util::Stream<
+                Item = Result<Message, tokio_tungstenite::tungstenite::Error>,
+            > + Unpin),
+        ) -> rooms::ServerMessage {
+            use futures_util::StreamExt;
+            let msg = ws.next().await.unwrap().unwrap();
+            serde_json::from_str(msg.to_text().unwrap()).unwrap()
+        }
+        use futures_util::SinkExt;
+
+        let mut red_ws = connect(&red).await;
+        let mut blue_ws = connect(&blue).await;
+        assert!(matches!(
+            next(&mut red_ws).await,
+            rooms::ServerMessage::State { .. }
+        ));
+        assert!(matches!(
+            next(&mut blue_ws).await,
+            rooms::ServerMessage::State { .. }
+        ));
+
+        blue_ws
+            .send(Message::Text(r#"{"type":"move","column":3}"#.into()))
+            .await
+            .unwrap();
+        assert!(matches!(
+            next(&mut blue_ws).await,
+            rooms::ServerMessage::Error { .. }
+        ));
+
+        red_ws
+            .send(Message::Text(r#"{"type":"move","column":3}"#.into()))
+            .await
+            .unwrap();
+        for ws in [&mut red_ws, &mut blue_ws] {
+            match next(ws).await {
+                rooms::ServerMessage::State { history, .. } => assert_eq!(history, "R3"),
+                other => panic!("unexpected {other:?}"),
+            }
+        }
+
+        blue_ws
+            .send(Message::Text(r#"{"type":"hint","level":3}"#.into()))
+            .await
+            .unwrap();
+        assert!(matches!(
+            next(&mut blue_ws).await,
+            rooms::ServerMessage::Hint { level: 3, .. }
+        ));
+
+        // A takeback needs both players.
+        blue_ws
+            .send(Message::Text(r#"{"type":"move","column":4}"#.into()))
+            .await
+            .unwrap();
+        for ws in [&mut red_ws, &mut blue_ws] {
+            assert!(matches!(next(ws).await, rooms::ServerMessage::State { .. }));
+        }
+        red_ws
+            .send(Message::Text(r#"{"type":"takeback"}"#.into()))
+            .await
+            .unwrap();
+        for ws in [&mut red_ws, &mut blue_ws] {
+            assert!(matches!(
+                next(ws).await,
+                rooms::ServerMessage::TakebackOffered { by: Player::Red }
+            ));
+        }
+        red_ws
+            .send(Message::Text(r#"{"type":"takeback"}"#.into()))
+            .await
+            .unwrap();
+        assert!(matches!(
+            next(&mut red_ws).await,
+            rooms::ServerMessage::Error { .. }
+        ));
+        blue_ws
+            .send(Message::Text(r#"{"type":"takeback"}"#.into()))
+            .await
+            .unwrap();
+        for ws in [&mut red_ws, &mut blue_ws] {
+            match next(ws).await {
+                rooms::ServerMessage::State { history, .. } => assert_eq!(history, ""),
+                other => panic!("unexpected {other:?}"),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn spectators_watch_opted_in_games() {
+        use futures_util::StreamExt;
+
+        let app = app_router(AppState::default());
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (_, body) = send_json(
+            &app,
+            "POST",
+            "/api/games",
+            r#"{"level":2,"spectators":true}"#,
+        )
+        .await;
+        let watched: sessions::GameView = serde_json::from_slice(&body).unwrap();
+        let (_, body) = send_json(&app, "POST", "/api/games", r#"{"level":2}"#).await;
+        let private: sessions::GameView = serde_json::from_slice(&body).unwrap();
+        let (_, body) = send_json(&app, "GET", "/api/games/live", "").await;
+        let listed: Vec<live::LiveGame> = serde_json::from_slice(&body).unwrap();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].id, watched.id);
+        assert_eq!(listed[0].clock.running, Some(Player::Red));
+        tokio::spawn({
+            let app = app.clone();
+            async move { axum::serve(listener, app).await.unwrap() }
+        });
+
+        let url = |id: &str| format!("ws://{addr}/api/games/{id}/watch");
+        assert!(tokio_tungstenite::connect_async(url(&private.id))
+            .await
+            .is_err());
+        let (mut ws, _) = tokio_tungstenite::connect_async(url(&watched.id))
+            .await
+            .unwrap();
+        async fn next(
+            ws: &mut (impl futures_util::Stream<
+                Item = Result<
+                    tokio_tungstenite::tungstenite::Message,
+                    tokio_tungstenite::tungstenite::Error,
+                >,
+            > + Unpin),
+        ) -> live::SpectatorMessage {
+            let msg = ws.next().await.unwrap().unwrap();
+            serde_json::from_str(msg.to_text().unwrap()).unwrap()
+        }
+        assert!(matches!(
+            next(&mut ws).await,
+            live::SpectatorMessage::State { ref history, .. } if history.is_empty()
+        ));
+
+        let uri = format!("/api/games/{}/move", watched.id);
+        let (status, _) = send_json(&app, "POST", &uri, r#"{"column":3}"#).await;
+        assert_eq!(status, StatusCode::OK);
+        // The human move, then the engine's answer.
+        let mut histories = Vec::new();
+        while histories.len() < 2 {
+            if let live::SpectatorMessage::Update {
+                history,
+                changes,
+                clock,
+                ..
+            } = next(&mut ws).await
+            {
+                assert_eq!(changes.len(), 1);
+                histories.push((history, clock.running));
+            }
+        }
+        assert_eq!(histories[0], ("R3".to_string(), Some(Player::Blue)));
+        assert_eq!(histories[1].1, Some(Player::Red));
+        let (_, body) = send_json(&app, "GET", "/api/games/live", "").await;
+        let listed: Vec<live::LiveGame> = serde_json::from_slice(&body).unwrap();
+        assert_eq!(listed[0].spectators, 1);
+    }
+
+    #[tokio::test]
+    async fn sessions_lose_on_time() {
+        let app = app_router(AppState::default());
+        let body = r#"{"level":2,"time_control":{"initial_ms":60000,"increment_ms":1000}}"#;
+        let (status, body) = send_json(&app, "POST", "/api/games", body).await;
+        assert_eq!(status, StatusCode::CREATED);
+        let game: sessions::GameView = serde_json::from_slice(&body).unwrap();
+        assert_eq!(game.clock.running, Some(Player::Red));
+        let uri = format!("/api/games/{}/move", game.id);
+        let (_, body) = send_json(&app, "POST", &uri, r#"{"column":3}"#).await;
+        let game: sessions::GameView = serde_json::from_slice(&body).unwrap();
+        // Each side has moved once and gained its increment.
+        assert_eq!(game.clock.running, Some(Player::Red));
+        assert!(game.clock.red_left_ms.unwrap() > 60_000);
+        assert!(game.clock.blue_left_ms.unwrap() > 60_000);
+
+        let body = r#"{"level":2,"time_control":{"initial_ms":50}}"#;
+        let (_, body) = send_json(&app, "POST", "/api/games", body).await;
+        let game: sessions::GameView = serde_json::from_slice(&body).unwrap();
+        tokio::time::sleep(Duration::from_millis(80)).await;
+        let uri = format!("/api/games/{}/move", game.id);
+        let (status, _) = send_json(&app, "POST", &uri, r#"{"column":3}"#).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        let (_, body) = send_json(&app, "GET", &format!("/api/games/{}", game.id), "").await;
+        match serde_json::from_slice(&body).unwrap() {
+            sessions::GameLookup::Archived(archived) => {
+                assert_eq!(archived.record.result, storage::GameResult::BlueWin);
+            }
+            other => panic!("expected the archived game, got {other:?}"),
+        }
+
+        let body = r#"{"level":2,"time_control":{"initial_ms":0}}"#;
+        let (status, _) = send_json(&app, "POST", "/api/games", body).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn engines_resign_hopeless_sessions() {
+        // A policy that finds every position hopeless.
+        let app = app_router(with_config(Config {
+            resign: connect4::resign::ResignPolicy {
+                threshold: i32::MAX,
+                moves: 1,
+            },
+            ..Config::default()
+        }));
+        let (_, body) = send_json(&app, "POST", "/api/games", r#"{"level":2}"#).await;
+        let game: sessions::GameView = serde_json::from_slice(&body).unwrap();
+        let draw = format!("/api/games/{}/draw", game.id);
+        let (status, _) = send_json(&app, "POST", &draw, "").await;
+        assert_eq!(status, StatusCode::CONFLICT);
+
+        let uri = format!("/api/games/{}/move", game.id);
+        let (_, body) = send_json(&app, "POST", &uri, r#"{"column":3}"#).await;
+        let game: sessions::GameView = serde_json::from_slice(&body).unwrap();
+        assert_eq!(game.engine_move, None);
+        assert_eq!(game.ending, Some(connect4::game::Ending::Resignation));
+        assert!(game.is_over() && !game.draw_offered);
+        let (status, _) = send_json(&app, "POST", &uri, r#"{"column":3}"#).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        let (_, body) = send_json(&app, "GET", &format!("/api/games/{}", game.id), "").await;
+        match serde_json::from_slice(&body).unwrap() {
+            sessions::GameLookup::Archived(archived) => {
+                assert_eq!(archived.record.history, "R3");
+                assert_eq!(archived.record.result, storage::GameResult::RedWin);
+            }
+            other => panic!("expected the archived game, got {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn engine_first_session_opens_the_game() {
+        let app = app_router(AppState::default());
+        let (_, body) = send_json(
+            &app,
+            "POST",
+            "/api/games",
+            r#"{"level":2,"engine_first":true}"#,
+        )
+        .await;
+        let game: sessions::GameView = serde_json::from_slice(&body).unwrap();
+        assert_eq!(game.human, Player::Blue);
+        assert_eq!(game.history.len(), 2);
+        assert_eq!(game.board.to_move, Player::Blue);
+    }
+
+    #[tokio::test]
+    async fn http_move_endpoint_accepts_json_body() {
+        let app = app_router(AppState::default());
+        let body = serde_json::to_vec(&MoveRequest {
+            position: "B0R3B1R4B2R5".to_string(),
+            level: 6,
+            first_player: None,
+        })
+        .unwrap();
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/api/move")
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(axum::body::Body::from(body))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let mv: MoveResponse = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(mv.column, 6);
+    }
+
+    #[tokio::test]
+    async fn arena_referees_and_rates_bot_games() {
+        use arena::{ArenaMessage, Finish};
+        use futures_util::{SinkExt, StreamExt};
+        use tokio_tungstenite::tungstenite::{
+            client::IntoClientRequest, http::HeaderValue, Message as WsMessage,
+        };
+
+        let config: Config = toml::from_str(
+            r#"
+            [[auth.keys]]
+            key = "lab-secret"
+            name = "lab"
+            [[auth.keys]]
+            key = "rival-secret"
+            name = "rival"
+            "#,
+        )
+        .unwrap();
+        let state = with_config(config);
+        let app = app_router(state.clone());
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        let keyed = |query: &str, key: &str| {
+            let mut request = format!("ws://{addr}/api/arena/ws?{query}")
+                .into_client_request()
+                .unwrap();
+            request
+                .headers_mut()
+                .insert("x-api-key", HeaderValue::from_str(key).unwrap());
+            request
+        };
+        let url = |query: &str| keyed(query, "lab-secret");
+        assert!(tokio_tungstenite::connect_async(url("name=engine-9"))
+            .await
+            .is_err());
+        assert!(tokio_tungstenite::connect_async(url("name=x&engine=0"))
+            .await
+            .is_err());
+        // Rated play needs a key.
+        let unkeyed = format!("ws://{addr}/api/arena/ws?name=delta");
+        assert!(tokio_tungstenite::connect_async(unkeyed).await.is_err());
+
+        async fn next(
+            ws: &mut (impl futures_util::Stream<
+                Item = Result<WsMessage, tokio_tungstenite::tungstenite::Error>,
+            > + Unpin),
+        ) -> ArenaMessage {
+            let msg = ws.next().await.unwrap().unwrap();
+            serde_json::from_str(msg.to_text().unwrap()).unwrap()
+        }
+        let (mut alpha, _) = tokio_tungstenite::connect_async(url("name=alpha"))
+            .await
+            .unwrap();
+        assert!(matches!(next(&mut alpha).await, ArenaMessage::Waiting));
+        let (mut beta, _) = tokio_tungstenite::connect_async(url("name=beta"))
+            .await
+            .unwrap();
+        let ArenaMessage::Start {
+            color, opponent, ..
+        } = next(&mut alpha).await
+        else {
+            panic!("expected the game to start");
+        };
+        assert_eq!(opponent, "beta");
+        assert!(matches!(next(&mut beta).await, ArenaMessage::Start { .. }));
+        // Red opens with a column that does not exist and loses.
+        let (red, blue) = match color {
+            Player::Red => (&mut alpha, &mut beta),
+            Player::Blue => (&mut beta, &mut alpha),
+        };
+        assert!(
+            matches!(next(red).await, ArenaMessage::Turn { ref history, .. } if history.is_empty())
+        );
+        blue.send(WsMessage::Text(r#"{"type":"move","column":3}"#.into()))
+            .await
+            .unwrap();
+        assert!(matches!(next(blue).await, ArenaMessage::Error { .. }));
+        red.send(WsMessage::Text(r#"{"type":"move","column":9}"#.into()))
+            .await
+            .unwrap();
+        for (ws, rating) in [(red, 1484), (blue, 1516)] {
+            match next(ws).await {
+                ArenaMessage::Over {
+                    result,
+                    reason: Finish::IllegalMove,
+                    rating: new_rating,
+                    ..
+                } => {
+                    assert_eq!(result, storage::GameResult::BlueWin);
+                    assert_eq!(new_rating, rating);
+                }
+                other => panic!("expected the game to end, got {other:?}"),
+            }
+        }
+        drop((alpha, beta));
+        // Alpha is the lab's bot; another key cannot play under its name.
+        let taken = tokio_tungstenite::connect_async(keyed("name=alpha", "rival-secret")).await;
+        assert!(matches!(
+            taken,
+            Err(tokio_tungstenite::tungstenite::Error::Http(response))
+                if response.status() == StatusCode::FORBIDDEN
+        ));
+
+        let (mut gamma, _) = tokio_tungstenite::connect_async(url("name=gamma&engine=1"))
+            .await
+            .unwrap();
+        assert!(
+            matches!(next(&mut gamma).await, ArenaMessage::Start { ref opponent, .. } if opponent == "engine-1")
+        );
+        let reason = loop {
+            match next(&mut gamma).await {
+                ArenaMessage::Turn { history, .. } => {
+                    let moves = connect4::parse_history(&history).unwrap();
+                    let column = GameState::from_history(&moves).unwrap().legal_moves()[0];
+                    let reply = format!(r#"{{"type":"move","column":{column}}}"#);
+                    gamma.send(WsMessage::Text(reply)).await.unwrap();
+                }
+                ArenaMessage::Over { reason, .. } => break reason,
+                other => panic!("unexpected {other:?}"),
+            }
+        };
+        assert_eq!(reason, Finish::Board);
+        // The next game against the engine starts on the same socket.
+        assert!(matches!(next(&mut gamma).await, ArenaMessage::Start { .. }));
+
+        let (_, body) =
+            send_json(&app_router(state.clone()), "GET", "/api/arena/ratings", "").await;
+        let ratings: Vec<arena::Rating> = serde_json::from_slice(&body).unwrap();
+        let names: Vec<&str> = ratings.iter().map(|r| r.name.as_str()).collect();
+        assert_eq!(names.len(), 4);
+        assert!(names.contains(&"engine-1") && names.contains(&"gamma"));
+        // Alpha and beta were paired again and lost that game by leaving.
+        let games = |name: &str| ratings.iter().find(|r| r.name == name).unwrap().games;
+        assert_eq!(
+            (games("gamma"), games("engine-1"), games("alpha")),
+            (1, 1, 2)
+        );
+
+        let uri = "/api/arena/sprt?candidate=engine-1&baseline=gamma&elo1=50";
+        let (status, body) = send_json(&app_router(state.clone()), "GET", uri, "").await;
+        assert_eq!(status, StatusCode::OK);
+        let test: arena::HeadToHead = serde_json::from_slice(&body).unwrap();
+        assert_eq!(test.score.games(), 1);
+        assert_eq!(test.score.wins + test.score.losses + test.score.draws, 1);
+        assert!(test.summary.ends_with("undecided at elo0=0, elo1=50"));
+        let uri = "/api/arena/sprt?candidate=engine-1&baseline=gamma&elo0=5&elo1=5";
+        let (status, _) = send_json(&app_router(state), "GET", uri, "").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
 }
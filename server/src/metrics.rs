@@ -0,0 +1,108 @@
+//! Search latency per level, for the admin stats.
+//!
+//! Each level keeps a ring of its most recent searches, so percentiles follow
+//! current load instead of averaging over the whole uptime, and memory stays
+//! fixed. Cache hits are not recorded: they would drown out the searches.
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+/// Samples kept per level.
+const WINDOW: usize = 1_000;
+const LEVELS: usize = 15;
+
+#[derive(Clone, Default)]
+pub struct Latencies {
+    levels: Arc<Mutex<Vec<VecDeque<u32>>>>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct LevelLatency {
+    pub level: u8,
+    /// Searches in the window the percentiles come from.
+    pub samples: usize,
+    pub p50_ms: u32,
+    pub p90_ms: u32,
+    pub p99_ms: u32,
+    pub max_ms: u32,
+}
+
+impl Latencies {
+    pub fn record(&self, level: u8, elapsed: Duration) {
+        let Some(index) = usize::from(level).checked_sub(1).filter(|&i| i < LEVELS) else {
+            return;
+        };
+        let mut levels = self.levels.lock().unwrap();
+        if levels.is_empty() {
+            levels.resize_with(LEVELS, VecDeque::new);
+        }
+        let samples = &mut levels[index];
+        if samples.len() == WINDOW {
+            samples.pop_front();
+        }
+        samples.push_back(u32::try_from(elapsed.as_millis()).unwrap_or(u32::MAX));
+    }
+
+    /// Levels with at least one recorded search, shallowest first.
+    pub fn summary(&self) -> Vec<LevelLatency> {
+        let levels = self.levels.lock().unwrap();
+        levels
+            .iter()
+            .zip(1u8..)
+            .filter(|(samples, _)| !samples.is_empty())
+            .map(|(samples, level)| {
+                let mut sorted: Vec<u32> = samples.iter().copied().collect();
+                sorted.sort_unstable();
+                // Nearest-rank percentile.
+                let rank = |p: usize| sorted[(sorted.len() * p).div_ceil(100).max(1) - 1];
+                LevelLatency {
+                    level,
+                    samples: sorted.len(),
+                    p50_ms: rank(50),
+                    p90_ms: rank(90),
+                    p99_ms: rank(99),
+                    max_ms: sorted[sorted.len() - 1],
+                }
+            })
+            .collect()
+    }
+
+    pub fn clear(&self) {
+        self.levels.lock().unwrap().clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percentiles_use_the_recent_window() {
+        let latencies = Latencies::default();
+        for ms in 1..=100 {
+            latencies.record(6, Duration::from_millis(ms));
+        }
+        latencies.record(0, Duration::from_secs(1));
+        latencies.record(16, Duration::from_secs(1));
+        let summary = latencies.summary();
+        assert_eq!(summary.len(), 1);
+        let six = &summary[0];
+        assert_eq!((six.level, six.samples), (6, 100));
+        assert_eq!(
+            (six.p50_ms, six.p90_ms, six.p99_ms, six.max_ms),
+            (50, 90, 99, 100)
+        );
+
+        for _ in 0..WINDOW {
+            latencies.record(6, Duration::from_millis(7));
+        }
+        assert_eq!(latencies.summary()[0].max_ms, 7);
+        latencies.clear();
+        assert!(latencies.summary().is_empty());
+    }
+}
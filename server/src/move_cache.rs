@@ -0,0 +1,149 @@
+//! Bounded in-process cache of searched moves.
+//!
+//! Sits in front of the storage-backed position cache: lookups here are a
+//! hash probe under a short lock, with no serialization or I/O. Entries are
+//! keyed by the canonical (mirror-folded) position and level, so a position
+//! and its mirror image share one entry; the column is stored in canonical
+//! orientation and flipped back for the mirrored caller.
+use std::{
+    num::NonZeroUsize,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use connect4::GameState;
+use lru::LruCache;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+/// Rightmost column index; mirroring maps column `c` to `LAST_COLUMN - c`.
+const LAST_COLUMN: usize = 6;
+
+#[derive(Clone)]
+pub struct MoveCache {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    /// `None` when the cache is disabled (capacity 0).
+    entries: Option<Mutex<LruCache<(u64, u8), usize>>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct MoveCacheStats {
+    pub entries: usize,
+    pub capacity: usize,
+    pub hits: u64,
+    pub misses: u64,
+    /// `hits / (hits + misses)`, zero before the first lookup.
+    pub hit_rate: f64,
+}
+
+impl MoveCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                entries: NonZeroUsize::new(capacity).map(|n| Mutex::new(LruCache::new(n))),
+                hits: AtomicU64::new(0),
+                misses: AtomicU64::new(0),
+            }),
+        }
+    }
+
+    pub fn get(&self, state: &GameState, level: u8) -> Option<usize> {
+        let entries = self.inner.entries.as_ref()?;
+        let (key, mirrored) = state.canonical_key();
+        let column = entries.lock().unwrap().get(&(key, level)).copied();
+        let counter = match column {
+            Some(_) => &self.inner.hits,
+            None => &self.inner.misses,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        column.map(|col| if mirrored { LAST_COLUMN - col } else { col })
+    }
+
+    pub fn insert(&self, state: &GameState, level: u8, column: usize) {
+        let Some(entries) = &self.inner.entries else {
+            return;
+        };
+        let (key, mirrored) = state.canonical_key();
+        let column = if mirrored {
+            LAST_COLUMN - column
+        } else {
+            column
+        };
+        entries.lock().unwrap().put((key, level), column);
+    }
+
+    /// Drop every entry, returning how many there were. Hit and miss counts
+    /// are kept.
+    pub fn clear(&self) -> usize {
+        let Some(entries) = &self.inner.entries else {
+            return 0;
+        };
+        let mut entries = entries.lock().unwrap();
+        let len = entries.len();
+        entries.clear();
+        len
+    }
+
+    pub fn stats(&self) -> MoveCacheStats {
+        let (entries, capacity) = self.inner.entries.as_ref().map_or((0, 0), |entries| {
+            let entries = entries.lock().unwrap();
+            (entries.len(), entries.cap().get())
+        });
+        let hits = self.inner.hits.load(Ordering::Relaxed);
+        let misses = self.inner.misses.load(Ordering::Relaxed);
+        let lookups = hits + misses;
+        MoveCacheStats {
+            entries,
+            capacity,
+            hits,
+            misses,
+            hit_rate: if lookups == 0 {
+                0.0
+            } else {
+                hits as f64 / lookups as f64
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use connect4::parse_history;
+
+    fn state(history: &str) -> GameState {
+        GameState::from_history(&parse_history(history).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn mirrored_positions_share_an_entry() {
+        let cache = MoveCache::new(2);
+        cache.insert(&state("R0B1"), 4, 2);
+        assert_eq!(cache.get(&state("R6B5"), 4), Some(4));
+        assert_eq!(cache.get(&state("R0B1"), 4), Some(2));
+        assert_eq!(cache.get(&state("R0B1"), 5), None);
+
+        cache.insert(&state("R3"), 4, 3);
+        cache.insert(&state("R2"), 4, 3);
+        // "R0B1" was least recently used and got evicted.
+        assert_eq!(cache.get(&state("R0B1"), 4), None);
+        let stats = cache.stats();
+        assert_eq!((stats.entries, stats.hits, stats.misses), (2, 2, 2));
+        assert_eq!(stats.hit_rate, 0.5);
+    }
+
+    #[test]
+    fn zero_capacity_disables_the_cache() {
+        let cache = MoveCache::new(0);
+        cache.insert(&state("R3"), 4, 3);
+        assert_eq!(cache.get(&state("R3"), 4), None);
+        assert_eq!(cache.stats().misses, 0);
+    }
+}
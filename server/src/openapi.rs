@@ -0,0 +1,199 @@
+//! Machine-readable API description for client generators, plus a Swagger UI
+//! page to try the endpoints from a browser.
+use axum::{response::Html, routing::get, Json, Router};
+use utoipa::OpenApi;
+
+use crate::AppState;
+
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "Connect 4 API",
+        description = "Stateless engine endpoints, optional server-side sessions, \
+                       human-vs-human rooms and the game archive."
+    ),
+    paths(
+        crate::handle_move,
+        crate::handle_move_json,
+        crate::handle_moves,
+        crate::handle_move_stream,
+        crate::handle_state,
+        crate::handle_preview,
+        crate::handle_validate,
+        crate::handle_render_svg,
+        crate::handle_render_png,
+        crate::handle_cache_stats,
+        crate::handle_aggregate_stats,
+        crate::handle_levels,
+        crate::handle_analyze,
+        crate::sessions::create_game,
+        crate::sessions::get_game,
+        crate::sessions::play_move,
+        crate::sessions::accept_draw,
+        crate::sessions::take_back,
+        crate::sessions::give_hint,
+        crate::sessions::next_game,
+        crate::archive::list_games,
+        crate::archive::export_game,
+        crate::archive::review_history,
+        crate::rooms::create_room,
+        crate::selfplay::run_selfplay,
+        crate::selfplay::rate_engine,
+        crate::jobs::create_job,
+        crate::jobs::get_job,
+        crate::rooms::match_room,
+        crate::rooms::get_room,
+        crate::rooms::join_room,
+        crate::rooms::room_socket,
+        crate::live::list_live,
+        crate::sessions::watch_game,
+        crate::rooms::watch_room,
+        crate::arena::arena_socket,
+        crate::arena::list_ratings,
+        crate::arena::arena_sprt,
+        crate::users::create_user,
+        crate::users::my_stats,
+        crate::users::record_puzzle,
+        crate::admin::stats,
+        crate::admin::flush,
+        crate::admin::book_info,
+        crate::admin::book_entries,
+        crate::admin::set_book_entry,
+        crate::admin::delete_book_entry,
+    ),
+    components(schemas(
+        connect4::Player,
+        connect4::MoveRequest,
+        connect4::MoveResponse,
+        connect4::Algorithm,
+        connect4::EvalPreset,
+        connect4::ScoredMove,
+        connect4::SearchInfo,
+        connect4::GameStatus,
+        connect4::Cell,
+        connect4::BoardModel,
+        connect4::HistoryDiagnostic,
+        connect4::ParseMoveError,
+        connect4::review::MoveClass,
+        connect4::review::MoveReview,
+        connect4::review::GameReview,
+        crate::error::ErrorBody,
+        crate::MoveBody,
+        crate::EngineParams,
+        crate::BatchItem,
+        crate::ValidateResponse,
+        crate::move_cache::MoveCacheStats,
+        crate::telemetry::AggregateStats,
+        crate::telemetry::LevelOutcomes,
+        crate::telemetry::CommonBlunder,
+        crate::LevelInfo,
+        connect4::Analysis,
+        connect4::explain::MoveExplanation,
+        connect4::explain::Reason,
+        connect4::explain::Line,
+        connect4::explain::MovePreview,
+        connect4::tt::TableStats,
+        crate::sessions::PonderStats,
+        connect4::tt::Replacement,
+        crate::sessions::CreateGameRequest,
+        crate::sessions::PlayRequest,
+        crate::sessions::GameView,
+        crate::sessions::HintView,
+        connect4::game::Ending,
+        crate::sessions::GameLookup,
+        connect4::adaptive::AdaptiveStatus,
+        connect4::Level,
+        crate::selfplay::EngineSpec,
+        crate::selfplay::EloRequest,
+        connect4::strength::EloEstimate,
+        connect4::strength::ReferenceResult,
+        crate::jobs::JobStatus,
+        crate::jobs::JobRequest,
+        crate::jobs::Job,
+        crate::jobs::JobLimits,
+        crate::selfplay::SelfPlayRequest,
+        crate::storage::GameResult,
+        crate::storage::GameRecord,
+        crate::storage::GamePage,
+        crate::archive::ArchivedGame,
+        crate::archive::ReviewRequest,
+        crate::rooms::CreateRoomRequest,
+        crate::rooms::RoomTicket,
+        crate::rooms::ServerMessage,
+        crate::rooms::ClientMessage,
+        crate::clock::Clock,
+        crate::clock::TimeControl,
+        connect4::CellChange,
+        connect4::EngineInfo,
+        crate::live::SpectatorMessage,
+        crate::live::LiveKind,
+        crate::live::LiveGame,
+        crate::arena::ArenaMessage,
+        crate::arena::BotMessage,
+        crate::arena::Finish,
+        crate::arena::Rating,
+        crate::arena::HeadToHead,
+        connect4::stats::Score,
+        connect4::stats::SprtStatus,
+        connect4::stats::Decision,
+        crate::users::CreateUserRequest,
+        crate::users::UserTicket,
+        crate::users::StatsView,
+        crate::users::LevelStats,
+        crate::users::PuzzleAttempt,
+        crate::admin::AdminStats,
+        crate::admin::TableReport,
+        crate::admin::CacheKind,
+        crate::admin::FlushResult,
+        crate::book::BookInfo,
+        crate::admin::BookEdit,
+        connect4::book::BookEntry,
+        crate::metrics::LevelLatency,
+        crate::search_pool::SearchPoolStats,
+        crate::jobs::JobQueueStats,
+    )),
+    tags(
+        (name = "engine", description = "Stateless search and board endpoints"),
+        (name = "sessions", description = "Games against the engine kept on the server"),
+        (name = "archive", description = "Finished games"),
+        (name = "rooms", description = "Human-vs-human rooms"),
+        (name = "spectators", description = "Watching games in progress"),
+        (name = "arena", description = "Rated games between bots"),
+        (name = "users", description = "Player accounts and statistics"),
+        (name = "render", description = "Board images for embedding"),
+        (name = "admin", description = "Operator statistics and cache control"),
+    )
+)]
+pub struct ApiDoc;
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/openapi.json", get(spec))
+        .route("/docs", get(swagger_ui))
+}
+
+async fn spec() -> Json<utoipa::openapi::OpenApi> {
+    Json(ApiDoc::openapi())
+}
+
+/// The UI assets come from a CDN so the server binary stays small.
+async fn swagger_ui() -> Html<&'static str> {
+    Html(
+        r##"<!doctype html>
+<html lang="en">
+<head>
+  <meta charset="utf-8">
+  <title>Connect 4 API</title>
+  <link rel="stylesheet" href="https://unpkg.com/swagger-ui-dist@5/swagger-ui.css">
+</head>
+<body>
+  <div id="swagger-ui"></div>
+  <script src="https://unpkg.com/swagger-ui-dist@5/swagger-ui-bundle.js"></script>
+  <script>
+    SwaggerUIBundle({ url: "/api/v1/openapi.json", dom_id: "#swagger-ui" });
+  </script>
+</body>
+</html>
+"##,
+    )
+}
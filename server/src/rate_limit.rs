@@ -0,0 +1,319 @@
+//! Per-client token-bucket rate limiting for the expensive endpoints.
+//!
+//! Each client IP owns a bucket holding up to `burst` tokens that refills at
+//! `per_second`. A request spends one token; an empty bucket means `429` with
+//! a `Retry-After` telling the client when the next token will exist. Buckets
+//! live in memory, so limits are per server instance.
+//!
+//! Handlers whose requests cost more than one search, such as batches, find
+//! the caller's bucket as a [`Tokens`] extension and spend the rest there.
+//! WebSockets that search on request, such as room hints, get the extension
+//! from [`attach_tokens`] instead and pay per search.
+//!
+//! One limiter serves every throttled route, so a client cannot double its
+//! budget by spreading searches over several endpoints.
+//!
+//! Requests carrying an API key are bucketed per key, with the key's own
+//! limits, wherever they come from; anonymous requests use the anonymous
+//! tier's limits per IP.
+//!
+//! Unknown API keys are refused before this layer runs, so [`AuthFailures`]
+//! gives each IP a small bucket of its own for them: enough for a typo, far
+//! too few to guess keys.
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::Instant,
+};
+
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::{header, HeaderValue, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use futures_util::future::{BoxFuture, FutureExt};
+use serde::Deserialize;
+use tower::{Layer, Service};
+
+use crate::{auth::Tier, ApiError, AppState};
+
+/// Idle buckets are pruned once the map grows past this many clients.
+const PRUNE_THRESHOLD: usize = 10_000;
+/// Unknown API keys an IP may present back-to-back, and how fast it earns
+/// more attempts.
+const AUTH_FAILURE_BURST: u32 = 10;
+const AUTH_FAILURES_PER_SECOND: f64 = 0.1;
+
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimitConfig {
+    /// Requests a client may fire back-to-back.
+    pub burst: u32,
+    /// Sustained requests per second once the burst is spent.
+    pub per_second: f64,
+    /// Key clients by the first `X-Forwarded-For` entry instead of the socket
+    /// address. Only safe behind a proxy that overwrites the header.
+    pub trust_forwarded_for: bool,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            burst: 20,
+            per_second: 2.0,
+            trust_forwarded_for: false,
+        }
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+    /// Limits differ per tier, so each bucket remembers its own.
+    config: RateLimitConfig,
+}
+
+impl Bucket {
+    fn tokens_at(&self, now: Instant) -> f64 {
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        (self.tokens + elapsed * self.config.per_second).min(f64::from(self.config.burst))
+    }
+}
+
+struct Limiter {
+    /// Used when no tier is attached, and for `trust_forwarded_for`.
+    config: RateLimitConfig,
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+impl Limiter {
+    /// Spend a token for `client`, or return how many seconds until one exists.
+    fn acquire(&self, client: &str, config: RateLimitConfig) -> Result<(), u64> {
+        self.spend(client, config, 1.0, true)
+    }
+
+    /// Whether `client` has a token, without spending it.
+    fn check(&self, client: &str, config: RateLimitConfig) -> Result<(), u64> {
+        self.spend(client, config, 1.0, false)
+    }
+
+    /// Take `cost` tokens from `client`'s bucket if it holds them (and
+    /// `commit` is set), or return how many seconds until it will.
+    fn spend(
+        &self,
+        client: &str,
+        config: RateLimitConfig,
+        cost: f64,
+        commit: bool,
+    ) -> Result<(), u64> {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() > PRUNE_THRESHOLD {
+            // A bucket that would be full again is indistinguishable from a new one.
+            buckets.retain(|_, b| b.tokens_at(now) < f64::from(b.config.burst));
+        }
+        let bucket = buckets.entry(client.to_string()).or_insert(Bucket {
+            tokens: f64::from(config.burst),
+            updated: now,
+            config,
+        });
+        bucket.config = config;
+        bucket.tokens = bucket.tokens_at(now);
+        bucket.updated = now;
+        if bucket.tokens >= cost {
+            if commit {
+                bucket.tokens -= cost;
+            }
+            Ok(())
+        } else if config.per_second > 0.0 && cost <= f64::from(config.burst) {
+            Err(((cost - bucket.tokens) / config.per_second).ceil() as u64)
+        } else {
+            Err(u64::MAX)
+        }
+    }
+
+    /// Bucket name and limits for a request.
+    fn client(&self, req: &Request) -> (String, RateLimitConfig) {
+        match req.extensions().get::<Tier>() {
+            Some(Tier {
+                key: Some(name),
+                rate_limit,
+                ..
+            }) => (format!("key:{name}"), *rate_limit),
+            Some(tier) => (self.client_ip(req), tier.rate_limit),
+            None => (self.client_ip(req), self.config),
+        }
+    }
+
+    fn client_ip(&self, req: &Request) -> String {
+        if self.config.trust_forwarded_for {
+            let forwarded = req
+                .headers()
+                .get("x-forwarded-for")
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.split(',').next())
+                .map(str::trim)
+                .filter(|v| !v.is_empty());
+            if let Some(ip) = forwarded {
+                return ip.to_string();
+            }
+        }
+        req.extensions()
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip().to_string())
+            .unwrap_or_else(|| "unknown".to_string())
+    }
+}
+
+#[derive(Clone)]
+pub struct RateLimitLayer {
+    limiter: Arc<Limiter>,
+}
+
+impl RateLimitLayer {
+    pub fn new(config: RateLimitConfig) -> Self {
+        Self {
+            limiter: Arc::new(Limiter {
+                config,
+                buckets: Mutex::default(),
+            }),
+        }
+    }
+
+    /// The bucket `req` would be charged to, without charging it.
+    fn tokens(&self, req: &Request) -> Tokens {
+        let (client, config) = self.limiter.client(req);
+        Tokens {
+            limiter: self.limiter.clone(),
+            client,
+            config,
+        }
+    }
+}
+
+/// Middleware giving handlers the caller's [`Tokens`] without spending any.
+pub async fn attach_tokens(State(app): State<AppState>, mut req: Request, next: Next) -> Response {
+    let tokens = app.rate_limit.tokens(&req);
+    req.extensions_mut().insert(tokens);
+    next.run(req).await
+}
+
+impl<S> Layer<S> for RateLimitLayer {
+    type Service = RateLimit<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RateLimit {
+            inner,
+            limiter: self.limiter.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct RateLimit<S> {
+    inner: S,
+    limiter: Arc<Limiter>,
+}
+
+impl<S> Service<Request> for RateLimit<S>
+where
+    S: Service<Request, Response = Response> + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Response, S::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request) -> Self::Future {
+        let (client, config) = self.limiter.client(&req);
+        match self.limiter.acquire(&client, config) {
+            Ok(()) => {
+                req.extensions_mut().insert(Tokens {
+                    limiter: self.limiter.clone(),
+                    client,
+                    config,
+                });
+                self.inner.call(req).boxed()
+            }
+            Err(retry_after) => {
+                let response = too_many_requests(retry_after);
+                async move { Ok(response) }.boxed()
+            }
+        }
+    }
+}
+
+/// The bucket a request is charged to.
+#[derive(Clone)]
+pub struct Tokens {
+    limiter: Arc<Limiter>,
+    client: String,
+    config: RateLimitConfig,
+}
+
+impl Tokens {
+    /// Spend `cost` tokens, or return how many seconds until the bucket holds
+    /// them. Nothing is spent when it falls short.
+    pub fn spend(&self, cost: usize) -> Result<(), u64> {
+        if cost == 0 {
+            return Ok(());
+        }
+        self.limiter
+            .spend(&self.client, self.config, cost as f64, true)
+    }
+}
+
+/// `429` telling the client to come back in `retry_after` seconds.
+pub fn too_many_requests(retry_after: u64) -> Response {
+    let mut response = ApiError::new(
+        StatusCode::TOO_MANY_REQUESTS,
+        "rate limit exceeded, slow down",
+    )
+    .into_response();
+    response
+        .headers_mut()
+        .insert(header::RETRY_AFTER, HeaderValue::from(retry_after));
+    response
+}
+
+/// Per-IP budget for presenting unknown API keys.
+#[derive(Clone)]
+pub struct AuthFailures {
+    limiter: Arc<Limiter>,
+}
+
+impl AuthFailures {
+    pub fn new(trust_forwarded_for: bool) -> Self {
+        Self {
+            limiter: Arc::new(Limiter {
+                config: RateLimitConfig {
+                    burst: AUTH_FAILURE_BURST,
+                    per_second: AUTH_FAILURES_PER_SECOND,
+                    trust_forwarded_for,
+                },
+                buckets: Mutex::default(),
+            }),
+        }
+    }
+
+    /// The `429` for a client that has used up its failed attempts, before
+    /// its key is even looked up.
+    pub fn refuse(&self, req: &Request) -> Option<Response> {
+        let client = self.limiter.client_ip(req);
+        let retry_after = self.limiter.check(&client, self.limiter.config).err()?;
+        Some(too_many_requests(retry_after))
+    }
+
+    /// Charge a failed attempt to `req`'s client.
+    pub fn charge(&self, req: &Request) {
+        let client = self.limiter.client_ip(req);
+        let _ = self.limiter.acquire(&client, self.limiter.config);
+    }
+}
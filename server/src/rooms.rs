@@ -0,0 +1,629 @@
+//! Human-vs-human rooms.
+//!
+//! The server only referees: seats are claimed with a secret token, the
+//! library decides legality, and every accepted move is broadcast to all
+//! sockets in the room. Rooms reuse the session TTL map, keyed by a short code
+//! that players can read out to each other.
+//!
+//! A takeback needs both players: one sends `takeback`, everyone hears the
+//! offer, and the opponent's own `takeback` rewinds the last two moves. Any
+//! move in between withdraws the offer.
+use axum::{
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        Path, State,
+    },
+    http::StatusCode,
+    response::Response,
+    routing::{get, post},
+    Extension, Json, Router,
+};
+use connect4::{
+    best_move, format_history, BoardModel, GameState, GameStatus, MoveRequest, Player, TypedMove,
+};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{
+    auth::{new_secret, Tier},
+    clock::{Clock, GameClock, TimeControl},
+    error::{ApiError, ApiQuery},
+    live::{self, Broadcast, LiveGame, LiveKind},
+    rate_limit::{self, Tokens},
+    sessions::{new_id, TtlMap},
+    storage::{unix_now, GameRecord, GameResult},
+    AppState,
+};
+
+pub type RoomStore = TtlMap<Room>;
+
+/// Unambiguous characters only, since codes are typed in by hand.
+const CODE_ALPHABET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+const CODE_LEN: usize = 6;
+
+/// Hints asked over a room's socket spend a rate-limit token each.
+pub fn routes(app: &AppState) -> Router<AppState> {
+    let tokens = axum::middleware::from_fn_with_state(app.clone(), rate_limit::attach_tokens);
+    Router::new()
+        .route("/rooms", post(create_room))
+        .route("/rooms/match", post(match_room))
+        .route("/rooms/:code", get(get_room))
+        .route("/rooms/:code/join", post(join_room))
+        .route("/rooms/:code/ws", get(room_socket).route_layer(tokens))
+        .route("/rooms/:code/watch", get(watch_room))
+}
+
+pub struct Room {
+    state: GameState,
+    moves: Vec<TypedMove>,
+    /// Secret seat tokens, indexed Red then Blue.
+    seats: [Option<String>; 2],
+    kibitzer: bool,
+    public: bool,
+    events: broadcast::Sender<ServerMessage>,
+    /// Starts once both seats are taken.
+    clock: GameClock,
+    /// Present when the room accepts spectators.
+    spectators: Option<Broadcast>,
+    started_at: u64,
+    /// The player asking for a takeback, until the opponent agrees or
+    /// someone moves.
+    takeback_offer: Option<Player>,
+    takebacks: u32,
+}
+
+impl Room {
+    fn new(req: &CreateRoomRequest) -> Self {
+        Self {
+            state: GameState::empty(Player::Red),
+            moves: Vec::new(),
+            seats: [None, None],
+            kibitzer: req.kibitzer,
+            public: req.public,
+            events: broadcast::channel(32).0,
+            clock: GameClock::new(req.time_control),
+            spectators: req.spectators.then(Broadcast::default),
+            started_at: unix_now(),
+            takeback_offer: None,
+            takebacks: 0,
+        }
+    }
+
+    fn seat_of(&self, token: &str) -> Option<Player> {
+        [Player::Red, Player::Blue]
+            .into_iter()
+            .zip(&self.seats)
+            .find(|(_, seat)| seat.as_deref() == Some(token))
+            .map(|(player, _)| player)
+    }
+
+    fn is_waiting(&self) -> bool {
+        self.seats.iter().any(Option::is_none)
+    }
+
+    /// Hand out the first free seat.
+    fn take_seat(&mut self) -> Option<(Player, String)> {
+        let (player, seat) = [Player::Red, Player::Blue]
+            .into_iter()
+            .zip(self.seats.iter_mut())
+            .find(|(_, seat)| seat.is_none())?;
+        let token = new_secret();
+        *seat = Some(token.clone());
+        Some((player, token))
+    }
+
+    fn snapshot(&self) -> ServerMessage {
+        ServerMessage::State {
+            history: format_history(&self.moves),
+            board: self.state.board_model(),
+            waiting_for_opponent: self.is_waiting(),
+            clock: self.clock.reading(),
+        }
+    }
+
+    /// `None` while the game is still running.
+    fn result(&self) -> Option<GameResult> {
+        match self.clock.flagged() {
+            Some(Player::Red) => Some(GameResult::BlueWin),
+            Some(Player::Blue) => Some(GameResult::RedWin),
+            None => GameResult::of(&self.state),
+        }
+    }
+
+    fn record(&self, result: GameResult) -> GameRecord {
+        GameRecord {
+            // Codes are short and get reused once a room expires, so the
+            // archive gets its own ID.
+            id: new_id(),
+            history: format_history(&self.moves),
+            result,
+            red_level: None,
+            blue_level: None,
+            started_at: self.started_at,
+            finished_at: unix_now(),
+            engine: None,
+            hints: None,
+            player: None,
+            clock: Some(self.clock.reading()),
+        }
+    }
+
+    /// Flag the side to move if its time is up, returning the archive record
+    /// when this call ended the game.
+    pub fn check_clock(&mut self) -> Option<GameRecord> {
+        if !self.clock.check() {
+            return None;
+        }
+        self.broadcast();
+        Some(self.record(self.result()?))
+    }
+
+    /// Referee one move. Errors are reported to the mover only; success is
+    /// broadcast to everyone in the room. Returns the archive record when this
+    /// move ended the game, which a move arriving after the flag fell does
+    /// on time instead of being played.
+    fn play(&mut self, token: &str, column: usize) -> Result<Option<GameRecord>, String> {
+        let player = self.seat_of(token).ok_or("not seated in this room")?;
+        if let Some(record) = self.check_clock() {
+            return Ok(Some(record));
+        }
+        if self.is_waiting() {
+            return Err("waiting for an opponent".to_string());
+        }
+        if self.result().is_some() {
+            return Err("game is already over".to_string());
+        }
+        if self.state.to_move() != player {
+            return Err("it is not your turn".to_string());
+        }
+        self.state.play(column).map_err(|err| err.to_string())?;
+        self.moves.push(TypedMove { player, column });
+        self.takeback_offer = None;
+        if self.state.status() == GameStatus::InProgress {
+            self.clock.moved(player);
+        } else {
+            self.clock.stop();
+        }
+        self.broadcast();
+        Ok(self.result().map(|result| self.record(result)))
+    }
+
+    /// Ask for, or agree to, rewinding the last two moves. Errors go to the
+    /// sender only; an offer or the rewound position is broadcast.
+    fn take_back(&mut self, token: &str, limit: u32) -> Result<(), String> {
+        let player = self.seat_of(token).ok_or("not seated in this room")?;
+        if self.is_waiting() || self.result().is_some() {
+            return Err("no game is running".to_string());
+        }
+        if self.takebacks >= limit {
+            return Err(format!("no takebacks left (the limit is {limit})"));
+        }
+        if self.moves.len() < 2 {
+            return Err("there is no move pair to take back".to_string());
+        }
+        match self.takeback_offer {
+            Some(by) if by != player => {
+                self.moves.truncate(self.moves.len() - 2);
+                self.state =
+                    GameState::from_history(&self.moves).expect("a prefix of a legal game");
+                self.takeback_offer = None;
+                self.takebacks += 1;
+                self.broadcast();
+            }
+            Some(_) => return Err("waiting for your opponent to agree".to_string()),
+            None => {
+                self.takeback_offer = Some(player);
+                let _ = self
+                    .events
+                    .send(ServerMessage::TakebackOffered { by: player });
+            }
+        }
+        Ok(())
+    }
+
+    fn broadcast(&self) {
+        // No receivers simply means nobody is connected right now.
+        let _ = self.events.send(self.snapshot());
+        if let Some(spectators) = &self.spectators {
+            spectators.send(
+                format_history(&self.moves),
+                self.state.board_model(),
+                self.clock.reading(),
+            );
+        }
+    }
+
+    /// Listing entry for `/games/live`, keyed by start time: only rooms
+    /// that accept spectators and have both players seated.
+    pub fn live_game(&self, code: &str) -> Option<(u64, LiveGame)> {
+        let spectators = self.spectators.as_ref()?;
+        let board = self.state.board_model();
+        if self.is_waiting() || self.result().is_some() {
+            return None;
+        }
+        let game = LiveGame {
+            kind: LiveKind::Room,
+            id: code.to_string(),
+            level: None,
+            history: format_history(&self.moves),
+            clock: self.clock.reading(),
+            board,
+            spectators: spectators.spectators(),
+            watch: format!("/api/v1/rooms/{code}/watch"),
+        };
+        Some((self.started_at, game))
+    }
+}
+
+fn new_code() -> String {
+    let seed = u128::from_str_radix(&new_id(), 16).expect("ids are hex");
+    (0..CODE_LEN)
+        .map(|i| CODE_ALPHABET[(seed >> (i * 5)) as usize % CODE_ALPHABET.len()] as char)
+        .collect()
+}
+
+#[derive(Debug, Default, Deserialize, ToSchema)]
+pub struct CreateRoomRequest {
+    /// Allow seated players to ask the engine for a hint.
+    #[serde(default)]
+    pub kibitzer: bool,
+    /// Listed for matchmaking; private rooms are only reachable by code.
+    #[serde(default)]
+    pub public: bool,
+    /// List the room under `/games/live` and let anyone watch it.
+    #[serde(default)]
+    pub spectators: bool,
+    /// Clock budget for both players; untimed when omitted.
+    pub time_control: Option<TimeControl>,
+}
+
+/// Returned to a player who claimed a seat. The token is the only credential
+/// for moving in that seat, so it is never broadcast.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct RoomTicket {
+    pub code: String,
+    pub token: String,
+    pub color: Player,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerMessage {
+    State {
+        history: String,
+        board: BoardModel,
+        waiting_for_opponent: bool,
+        clock: Clock,
+    },
+    Hint {
+        column: usize,
+        level: u8,
+    },
+    /// `by` asks to take back the last two moves; the opponent agrees by
+    /// sending `takeback` too.
+    TakebackOffered {
+        by: Player,
+    },
+    Error {
+        message: String,
+    },
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClientMessage {
+    Move {
+        column: usize,
+    },
+    Hint {
+        level: u8,
+    },
+    /// Ask for a takeback, or agree to the opponent's.
+    Takeback,
+}
+
+fn open_room(app: &AppState, req: CreateRoomRequest) -> RoomTicket {
+    let mut room = Room::new(&req);
+    let (color, token) = room.take_seat().expect("new rooms are empty");
+    let mut code = new_code();
+    while app.rooms.contains(&code) {
+        code = new_code();
+    }
+    app.rooms.insert(code.clone(), room);
+    RoomTicket { code, token, color }
+}
+
+fn join(app: &AppState, code: String) -> Result<RoomTicket, ApiError> {
+    app.rooms.with(&code, |room| {
+        let (color, token) = room
+            .take_seat()
+            .ok_or_else(|| ApiError::new(StatusCode::CONFLICT, "room is full"))?;
+        if !room.is_waiting() {
+            room.clock.start(Player::Red);
+        }
+        room.broadcast();
+        Ok(RoomTicket {
+            code: code.clone(),
+            token,
+            color,
+        })
+    })
+}
+
+/// Open a room and take the Red seat.
+#[utoipa::path(
+    post,
+    path = "/api/v1/rooms",
+    tag = "rooms",
+    request_body(content = Option<CreateRoomRequest>),
+    responses(
+        (status = 201, body = RoomTicket),
+        (status = 400, description = "Time control without initial time", body = ErrorBody),
+    ),
+)]
+pub(crate) async fn create_room(
+    State(app): State<AppState>,
+    body: Option<Json<CreateRoomRequest>>,
+) -> Result<(StatusCode, Json<RoomTicket>), ApiError> {
+    let req = body.map(|Json(req)| req).unwrap_or_default();
+    TimeControl::validate(req.time_control)?;
+    Ok((StatusCode::CREATED, Json(open_room(&app, req))))
+}
+
+/// Take the free seat in a room.
+#[utoipa::path(
+    post,
+    path = "/api/v1/rooms/{code}/join",
+    tag = "rooms",
+    params(("code" = String, Path, description = "Six-character room code")),
+    responses(
+        (status = 200, body = RoomTicket),
+        (status = 404, description = "Unknown or expired room", body = ErrorBody),
+        (status = 409, description = "Room is full", body = ErrorBody),
+    ),
+)]
+pub(crate) async fn join_room(
+    State(app): State<AppState>,
+    Path(code): Path<String>,
+) -> Result<Json<RoomTicket>, ApiError> {
+    join(&app, code).map(Json)
+}
+
+/// Quick match: sit down in a public room that is waiting for an opponent, or
+/// open a new public room and wait there.
+#[utoipa::path(
+    post,
+    path = "/api/v1/rooms/match",
+    tag = "rooms",
+    responses((status = 200, body = RoomTicket)),
+)]
+pub(crate) async fn match_room(State(app): State<AppState>) -> Result<Json<RoomTicket>, ApiError> {
+    if let Some(code) = app.rooms.find(|room| room.public && room.is_waiting()) {
+        // Someone may have taken the seat since `find`; fall through if so.
+        if let Ok(ticket) = join(&app, code) {
+            return Ok(Json(ticket));
+        }
+    }
+    let req = CreateRoomRequest {
+        kibitzer: false,
+        public: true,
+        spectators: false,
+        time_control: None,
+    };
+    Ok(Json(open_room(&app, req)))
+}
+
+/// Current snapshot of a room (a `state` message).
+#[utoipa::path(
+    get,
+    path = "/api/v1/rooms/{code}",
+    tag = "rooms",
+    params(("code" = String, Path, description = "Six-character room code")),
+    responses(
+        (status = 200, body = ServerMessage),
+        (status = 404, description = "Unknown or expired room", body = ErrorBody),
+    ),
+)]
+pub(crate) async fn get_room(
+    State(app): State<AppState>,
+    Path(code): Path<String>,
+) -> Result<Json<ServerMessage>, ApiError> {
+    app.rooms.with(&code, |room| Ok(Json(room.snapshot())))
+}
+
+/// Watch a room as a spectator: a WebSocket of `SpectatorMessage`s.
+#[utoipa::path(
+    get,
+    path = "/api/v1/rooms/{code}/watch",
+    tag = "spectators",
+    params(("code" = String, Path, description = "Six-character room code")),
+    responses(
+        (status = 101, description = "Switching to the WebSocket protocol"),
+        (status = 403, description = "The room does not accept spectators", body = ErrorBody),
+        (status = 404, description = "Unknown or expired room", body = ErrorBody),
+    ),
+)]
+pub(crate) async fn watch_room(
+    State(app): State<AppState>,
+    Path(code): Path<String>,
+    ws: WebSocketUpgrade,
+) -> Result<Response, ApiError> {
+    let (events, snapshot) = app.rooms.with(&code, |room| {
+        let spectators = room.spectators.as_ref().ok_or_else(|| {
+            ApiError::new(StatusCode::FORBIDDEN, "room does not accept spectators")
+        })?;
+        let snapshot = live::SpectatorMessage::State {
+            history: format_history(&room.moves),
+            board: room.state.board_model(),
+            clock: room.clock.reading(),
+        };
+        Ok((spectators.subscribe(), snapshot))
+    })?;
+    Ok(ws.on_upgrade(move |socket| live::watch(socket, events, snapshot)))
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(crate) struct SocketQuery {
+    token: String,
+}
+
+/// Upgrade to a WebSocket carrying `ClientMessage`s in and `ServerMessage`s
+/// out.
+#[utoipa::path(
+    get,
+    path = "/api/v1/rooms/{code}/ws",
+    tag = "rooms",
+    params(
+        ("code" = String, Path, description = "Six-character room code"),
+        SocketQuery,
+    ),
+    responses(
+        (status = 101, description = "Switching to the WebSocket protocol"),
+        (status = 403, description = "Token is not seated in this room", body = ErrorBody),
+    ),
+)]
+pub(crate) async fn room_socket(
+    State(app): State<AppState>,
+    Extension(tier): Extension<Tier>,
+    Extension(tokens): Extension<Tokens>,
+    Path(code): Path<String>,
+    ApiQuery(query): ApiQuery<SocketQuery>,
+    ws: WebSocketUpgrade,
+) -> Result<Response, ApiError> {
+    let (events, snapshot) = app.rooms.with(&code, |room| {
+        room.seat_of(&query.token)
+            .ok_or_else(|| ApiError::new(StatusCode::FORBIDDEN, "not seated in this room"))?;
+        Ok((room.events.subscribe(), room.snapshot()))
+    })?;
+    Ok(ws.on_upgrade(move |socket| {
+        let caller = Caller { tier, tokens };
+        run_socket(app, caller, code, query.token, socket, events, snapshot)
+    }))
+}
+
+/// Who is on the other end of a socket: their limits and rate-limit bucket.
+struct Caller {
+    tier: Tier,
+    tokens: Tokens,
+}
+
+async fn run_socket(
+    app: AppState,
+    caller: Caller,
+    code: String,
+    token: String,
+    mut socket: WebSocket,
+    mut events: broadcast::Receiver<ServerMessage>,
+    snapshot: ServerMessage,
+) {
+    if send(&mut socket, &snapshot).await.is_err() {
+        return;
+    }
+    loop {
+        tokio::select! {
+            incoming = socket.recv() => {
+                let Some(Ok(message)) = incoming else { break };
+                let reply = match message {
+                    Message::Text(text) => handle_client(&app, &caller, &code, &token, &text).await,
+                    Message::Close(_) => break,
+                    _ => continue,
+                };
+                if let Some(reply) = reply {
+                    if send(&mut socket, &reply).await.is_err() {
+                        break;
+                    }
+                }
+            }
+            event = events.recv() => {
+                let event = match event {
+                    Ok(event) => event,
+                    // A slow client missed updates; the next snapshot is complete anyway.
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => break,
+                };
+                if send(&mut socket, &event).await.is_err() {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// Returns the message meant for this client only; moves answer through the
+/// broadcast instead.
+async fn handle_client(
+    app: &AppState,
+    caller: &Caller,
+    code: &str,
+    token: &str,
+    text: &str,
+) -> Option<ServerMessage> {
+    let error = |message: String| Some(ServerMessage::Error { message });
+    let message = match serde_json::from_str::<ClientMessage>(text) {
+        Ok(message) => message,
+        Err(err) => return error(format!("invalid message: {err}")),
+    };
+    match message {
+        ClientMessage::Move { column } => {
+            match app.rooms.with(code, |room| Ok(room.play(token, column))) {
+                Ok(Ok(record)) => {
+                    if let Some(record) = record {
+                        app.record_game(&record);
+                    }
+                    None
+                }
+                Ok(Err(message)) => error(message),
+                Err(err) => error(err.to_string()),
+            }
+        }
+        ClientMessage::Takeback => {
+            let limit = app.config.max_takebacks;
+            match app
+                .rooms
+                .with(code, |room| Ok(room.take_back(token, limit)))
+            {
+                Ok(Ok(())) => None,
+                Ok(Err(message)) => error(message),
+                Err(err) => error(err.to_string()),
+            }
+        }
+        ClientMessage::Hint { level } => {
+            let position = app.rooms.with(code, |room| {
+                let allowed = room.kibitzer && room.seat_of(token).is_some();
+                Ok(allowed.then(|| format_history(&room.moves)))
+            });
+            let position = match position {
+                Ok(Some(position)) => position,
+                Ok(None) => return error("hints are disabled in this room".to_string()),
+                Err(err) => return error(err.to_string()),
+            };
+            if let Err(err) = app.check_level(&caller.tier, level) {
+                return error(err.to_string());
+            }
+            if let Err(retry_after) = caller.tokens.spend(1) {
+                return error(format!("rate limit exceeded, ask again in {retry_after}s"));
+            }
+            let search = app.search_pool.run(level, move || {
+                Ok(best_move(MoveRequest {
+                    position,
+                    level,
+                    first_player: None,
+                })?)
+            });
+            match search.await {
+                Ok(mv) => Some(ServerMessage::Hint {
+                    column: mv.column,
+                    level,
+                }),
+                Err(err) => error(err.to_string()),
+            }
+        }
+    }
+}
+
+async fn send(socket: &mut WebSocket, message: &ServerMessage) -> Result<(), axum::Error> {
+    let text = serde_json::to_string(message).expect("server messages serialize");
+    socket.send(Message::Text(text)).await
+}
@@ -0,0 +1,401 @@
+//! JSON-RPC 2.0 instead of HTTP, for editor plugins (`--rpc stdio`) and
+//! lightweight embedding over a local socket (`--rpc 127.0.0.1:7000`).
+//!
+//! Messages are one JSON value per line in each direction, batches included.
+//! The methods take the same parameters as the matching HTTP endpoints and
+//! answer with the same bodies:
+//!
+//! - `move`: `MoveRequest` plus the engine parameters of `POST /move`;
+//!   returns a `MoveResponse`.
+//! - `analyze`: `{position, level, explain?}`, as `GET /analyze`; returns
+//!   `connect4::Analysis`.
+//! - `validate`: `{position}`; returns the body of `GET /validate`.
+//! - `solve`: `{position, max_ms?}`; the solver's `MoveResponse`, whose
+//!   score is ±1000000 for a proven result.
+//!
+//! Engine failures use error code -32000 with the HTTP error body (stable
+//! `code`, `message`, `position`, `reason`) as `data`. There are no API keys: callers
+//! get the server-wide limits from the config, without rate limiting.
+use std::{fmt, net::SocketAddr, str::FromStr};
+
+use anyhow::Context;
+use connect4::{Algorithm, MoveRequest};
+use futures_util::future::join_all;
+use serde::{de::DeserializeOwned, Deserialize, Deserializer, Serialize};
+use serde_json::Value;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader},
+    net::TcpListener,
+};
+use tracing::{info, warn};
+
+use crate::{
+    auth::Tier, config::Config, error::ErrorBody, open_storage, validate, AnalyzeQuery, AppState,
+    EngineParams, MoveBody, StateQuery,
+};
+
+const PARSE_ERROR: i32 = -32700;
+const INVALID_REQUEST: i32 = -32600;
+const METHOD_NOT_FOUND: i32 = -32601;
+const INVALID_PARAMS: i32 = -32602;
+/// Start of the range JSON-RPC leaves to applications.
+const ENGINE_ERROR: i32 = -32000;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Transport {
+    Stdio,
+    Tcp(SocketAddr),
+}
+
+impl FromStr for Transport {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "stdio" => Ok(Self::Stdio),
+            addr => addr
+                .parse()
+                .map(Self::Tcp)
+                .map_err(|_| format!("expected `stdio` or a socket address, got `{addr}`")),
+        }
+    }
+}
+
+impl fmt::Display for Transport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Stdio => f.write_str("stdio"),
+            Self::Tcp(addr) => addr.fmt(f),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct Request {
+    jsonrpc: String,
+    method: String,
+    #[serde(default)]
+    params: Option<Value>,
+    /// Absent for notifications, which get no response; `null` is an id.
+    #[serde(default, deserialize_with = "present")]
+    id: Option<Value>,
+}
+
+fn present<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Value>, D::Error> {
+    Value::deserialize(deserializer).map(Some)
+}
+
+#[derive(Debug, Serialize)]
+struct Response {
+    jsonrpc: &'static str,
+    #[serde(flatten)]
+    outcome: Outcome,
+    id: Value,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Outcome {
+    Result(Value),
+    Error(RpcError),
+}
+
+#[derive(Debug, Serialize)]
+struct RpcError {
+    code: i32,
+    message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    data: Option<Box<ErrorBody>>,
+}
+
+impl RpcError {
+    fn new(code: i32, message: impl fmt::Display) -> Self {
+        Self {
+            code,
+            message: message.to_string(),
+            data: None,
+        }
+    }
+}
+
+impl From<crate::error::ApiError> for RpcError {
+    fn from(err: crate::error::ApiError) -> Self {
+        let body = err.body();
+        Self {
+            code: ENGINE_ERROR,
+            message: body.message.clone(),
+            data: Some(Box::new(body)),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct SolveParams {
+    #[serde(default)]
+    position: String,
+    max_ms: Option<u64>,
+}
+
+/// Serve JSON-RPC on `transport` until stdin closes or, for TCP, forever.
+pub async fn serve(config: Config, transport: Transport) -> anyhow::Result<()> {
+    let storage = open_storage(config.database.as_deref())?;
+    let app = AppState::new(config, storage);
+    app.book.load()?;
+    app.book.spawn_watcher();
+    let tier = Tier::local(&app.config);
+    match transport {
+        Transport::Stdio => {
+            Ok(connection(&app, &tier, tokio::io::stdin(), tokio::io::stdout()).await?)
+        }
+        Transport::Tcp(addr) => {
+            let listener = TcpListener::bind(addr)
+                .await
+                .with_context(|| format!("cannot listen on {addr}"))?;
+            info!("JSON-RPC on {}", listener.local_addr()?);
+            loop {
+                let (stream, peer) = listener.accept().await?;
+                let (app, tier) = (app.clone(), tier.clone());
+                tokio::spawn(async move {
+                    let (reader, writer) = stream.into_split();
+                    if let Err(err) = connection(&app, &tier, reader, writer).await {
+                        warn!(%peer, "JSON-RPC connection failed: {err}");
+                    }
+                });
+            }
+        }
+    }
+}
+
+/// Answer each line of `reader` in turn; a batch's calls run concurrently.
+async fn connection(
+    app: &AppState,
+    tier: &Tier,
+    reader: impl AsyncRead + Unpin,
+    mut writer: impl AsyncWrite + Unpin,
+) -> std::io::Result<()> {
+    let mut lines = BufReader::new(reader).lines();
+    while let Some(line) = lines.next_line().await? {
+        if line.trim().is_empty() {
+            continue;
+        }
+        if let Some(reply) = message(app, tier, &line).await {
+            writer.write_all(reply.as_bytes()).await?;
+            writer.write_all(b"\n").await?;
+            writer.flush().await?;
+        }
+    }
+    Ok(())
+}
+
+/// The reply to one line, or `None` when it held only notifications.
+async fn message(app: &AppState, tier: &Tier, line: &str) -> Option<String> {
+    let reply = match serde_json::from_str::<Value>(line) {
+        Err(err) => serde_json::to_value(error(Value::Null, RpcError::new(PARSE_ERROR, err))),
+        Ok(Value::Array(calls)) if !calls.is_empty() => {
+            let replies: Vec<Response> =
+                join_all(calls.into_iter().map(|call| handle(app, tier, call)))
+                    .await
+                    .into_iter()
+                    .flatten()
+                    .collect();
+            if replies.is_empty() {
+                return None;
+            }
+            serde_json::to_value(replies)
+        }
+        Ok(call) => serde_json::to_value(handle(app, tier, call).await?),
+    };
+    Some(reply.expect("responses serialize").to_string())
+}
+
+fn error(id: Value, error: RpcError) -> Response {
+    Response {
+        jsonrpc: "2.0",
+        outcome: Outcome::Error(error),
+        id,
+    }
+}
+
+async fn handle(app: &AppState, tier: &Tier, call: Value) -> Option<Response> {
+    let id = call.get("id").cloned().unwrap_or(Value::Null);
+    let request = match serde_json::from_value::<Request>(call) {
+        Ok(request) if request.jsonrpc == "2.0" => request,
+        Ok(_) => {
+            return Some(error(
+                id,
+                RpcError::new(INVALID_REQUEST, "jsonrpc must be \"2.0\""),
+            ))
+        }
+        Err(err) => return Some(error(id, RpcError::new(INVALID_REQUEST, err))),
+    };
+    let outcome = match dispatch(app, tier, &request.method, request.params).await {
+        Ok(result) => Outcome::Result(result),
+        Err(err) => Outcome::Error(err),
+    };
+    Some(Response {
+        jsonrpc: "2.0",
+        outcome,
+        id: request.id?,
+    })
+}
+
+fn params<T: DeserializeOwned>(params: Option<Value>) -> Result<T, RpcError> {
+    serde_json::from_value(params.unwrap_or(Value::Null))
+        .map_err(|err| RpcError::new(INVALID_PARAMS, err))
+}
+
+fn result(value: impl Serialize) -> Result<Value, RpcError> {
+    Ok(serde_json::to_value(value).expect("results serialize"))
+}
+
+async fn dispatch(
+    app: &AppState,
+    tier: &Tier,
+    method: &str,
+    raw: Option<Value>,
+) -> Result<Value, RpcError> {
+    match method {
+        "move" => {
+            let body: MoveBody = params(raw)?;
+            result(app.search(tier, body.request, body.params).await?)
+        }
+        "analyze" => {
+            let query: AnalyzeQuery = params(raw)?;
+            result(app.analyze(tier, query).await?)
+        }
+        "validate" => {
+            let query: StateQuery = params(raw)?;
+            result(validate(&query.position))
+        }
+        "solve" => {
+            let solve: SolveParams = params(raw)?;
+            let request = MoveRequest {
+                position: solve.position,
+                level: 15,
+                first_player: None,
+            };
+            let params = EngineParams {
+                algorithm: Some(Algorithm::Solver),
+                max_ms: solve.max_ms,
+                ..EngineParams::default()
+            };
+            result(app.search(tier, request, params).await?)
+        }
+        _ => Err(RpcError::new(
+            METHOD_NOT_FOUND,
+            format!("unknown method `{method}`"),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn call(line: &str) -> Option<Value> {
+        let app = AppState::default();
+        let tier = Tier::local(&app.config);
+        message(&app, &tier, line)
+            .await
+            .map(|reply| serde_json::from_str(&reply).unwrap())
+    }
+
+    #[tokio::test]
+    async fn methods_answer_like_the_http_endpoints() {
+        let reply = call(r#"{"jsonrpc":"2.0","id":1,"method":"move","params":{"position":"R0B1R0B1R0B1","level":4}}"#)
+            .await
+            .unwrap();
+        assert_eq!(reply["id"], 1);
+        assert_eq!(reply["result"]["column"], 0);
+
+        let reply = call(r#"{"jsonrpc":"2.0","id":"a","method":"analyze","params":{"position":"R3B3","level":3}}"#)
+            .await
+            .unwrap();
+        assert_eq!(reply["result"]["scores"].as_array().unwrap().len(), 7);
+
+        let reply =
+            call(r#"{"jsonrpc":"2.0","id":2,"method":"validate","params":{"position":"R3R3"}}"#)
+                .await
+                .unwrap();
+        assert_eq!(reply["result"]["valid"], false);
+
+        let reply = call(
+            r#"{"jsonrpc":"2.0","id":3,"method":"solve","params":{"position":"R0B1R0B1R0B1"}}"#,
+        )
+        .await
+        .unwrap();
+        assert_eq!(reply["result"]["column"], 0);
+        assert_eq!(reply["result"]["score"], 1_000_000);
+    }
+
+    #[tokio::test]
+    async fn errors_follow_the_spec() {
+        let reply = call("{").await.unwrap();
+        assert_eq!(
+            (reply["error"]["code"].clone(), reply["id"].clone()),
+            (PARSE_ERROR.into(), Value::Null)
+        );
+        let reply = call(r#"{"jsonrpc":"2.0","id":1,"method":"resign"}"#)
+            .await
+            .unwrap();
+        assert_eq!(reply["error"]["code"], METHOD_NOT_FOUND);
+        let reply = call(r#"{"jsonrpc":"2.0","id":1,"method":"move","params":{"level":3}}"#)
+            .await
+            .unwrap();
+        assert_eq!(reply["error"]["code"], INVALID_PARAMS);
+        let reply = call(r#"{"jsonrpc":"1.0","id":1,"method":"move"}"#)
+            .await
+            .unwrap();
+        assert_eq!(reply["error"]["code"], INVALID_REQUEST);
+
+        let reply = call(
+            r#"{"jsonrpc":"2.0","id":1,"method":"move","params":{"position":"R3X","level":3}}"#,
+        )
+        .await
+        .unwrap();
+        assert_eq!(reply["error"]["code"], ENGINE_ERROR);
+        assert_eq!(reply["error"]["data"]["code"], "invalid_position");
+        assert_eq!(reply["error"]["data"]["position"], 2);
+    }
+
+    #[tokio::test]
+    async fn batches_skip_notifications() {
+        let reply = call(
+            r#"[{"jsonrpc":"2.0","method":"validate","params":{}},
+                {"jsonrpc":"2.0","id":7,"method":"validate","params":{"position":"R3"}}]"#
+                .replace('\n', "")
+                .as_str(),
+        )
+        .await
+        .unwrap();
+        let replies = reply.as_array().unwrap();
+        assert_eq!(replies.len(), 1);
+        assert_eq!(replies[0]["id"], 7);
+        assert_eq!(replies[0]["result"]["moves"], 1);
+        assert_eq!(call(r#"{"jsonrpc":"2.0","method":"validate"}"#).await, None);
+    }
+
+    #[tokio::test]
+    async fn connections_answer_line_by_line() {
+        let app = AppState::default();
+        let tier = Tier::local(&app.config);
+        let input = concat!(
+            r#"{"jsonrpc":"2.0","id":1,"method":"validate","params":{"position":""}}"#,
+            "\n\n",
+            r#"{"jsonrpc":"2.0","id":2,"method":"validate","params":{"position":"R3B3"}}"#,
+            "\n",
+        );
+        let mut output = Vec::new();
+        connection(&app, &tier, input.as_bytes(), &mut output)
+            .await
+            .unwrap();
+        let ids: Vec<Value> = String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str::<Value>(line).unwrap()["id"].clone())
+            .collect();
+        assert_eq!(ids, [1, 2]);
+        assert_eq!("stdio".parse(), Ok(Transport::Stdio));
+        assert!("localhost".parse::<Transport>().is_err());
+    }
+}
@@ -0,0 +1,274 @@
+//! Bounded execution of engine searches.
+//!
+//! Searches are pure CPU work, so they never run on the async runtime threads.
+//! At most `workers` run at once on the blocking pool; up to `queue` more may
+//! wait for a worker. Anything beyond that is turned away with `503` straight
+//! away, because a client is better off retrying than waiting behind a backlog
+//! that only grows.
+//!
+//! Deep searches get a tighter cap of their own: a level-14 search costs
+//! thousands of times more than a level-6 one, so a burst of them would
+//! otherwise occupy every worker. They wait for a deep slot before taking a
+//! worker, which keeps cheap requests flowing meanwhile.
+use std::{sync::Arc, thread};
+
+use axum::http::StatusCode;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::ApiError;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SearchPoolConfig {
+    /// Searches running at the same time.
+    pub workers: usize,
+    /// Searches allowed to wait for a free worker.
+    pub queue: usize,
+    /// Levels at or above this count as deep.
+    pub deep_level: u8,
+    /// Deep searches running at the same time.
+    pub deep_workers: usize,
+}
+
+impl Default for SearchPoolConfig {
+    fn default() -> Self {
+        Self {
+            workers: thread::available_parallelism().map_or(1, usize::from),
+            queue: 32,
+            deep_level: 10,
+            deep_workers: 1,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct SearchPool {
+    /// Running plus queued; a failed `try_acquire` here means "queue full".
+    admitted: Arc<Semaphore>,
+    running: Arc<Semaphore>,
+    deep: Arc<Semaphore>,
+    deep_level: u8,
+    workers: usize,
+    queue: usize,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct SearchPoolStats {
+    pub workers: usize,
+    pub running: usize,
+    /// Admitted searches waiting for a worker.
+    pub queued: usize,
+    pub queue_capacity: usize,
+}
+
+impl Default for SearchPool {
+    fn default() -> Self {
+        Self::new(SearchPoolConfig::default())
+    }
+}
+
+impl SearchPool {
+    pub fn new(config: SearchPoolConfig) -> Self {
+        let workers = config.workers.max(1);
+        Self {
+            admitted: Arc::new(Semaphore::new(workers + config.queue)),
+            running: Arc::new(Semaphore::new(workers)),
+            deep: Arc::new(Semaphore::new(config.deep_workers.clamp(1, workers))),
+            deep_level: config.deep_level,
+            workers,
+            queue: config.queue,
+        }
+    }
+
+    pub fn stats(&self) -> SearchPoolStats {
+        let admitted = self.workers + self.queue - self.admitted.available_permits();
+        let running = self.workers - self.running.available_permits();
+        SearchPoolStats {
+            workers: self.workers,
+            running,
+            queued: admitted.saturating_sub(running),
+            queue_capacity: self.queue,
+        }
+    }
+
+    /// Claim a place in the queue, or fail with `503` if it is already full.
+    /// Handlers that must answer before the search starts (streams) admit
+    /// first and run later.
+    pub fn admit(&self, level: u8) -> Result<Admission, ApiError> {
+        let admitted = self.admitted.clone().try_acquire_owned().map_err(|_| {
+            ApiError::new(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "engine is busy, try again shortly",
+            )
+        })?;
+        Ok(Admission {
+            admitted,
+            running: self.running.clone(),
+            deep: (level >= self.deep_level).then(|| self.deep.clone()),
+        })
+    }
+
+    pub async fn run<T, F>(&self, level: u8, search: F) -> Result<T, ApiError>
+    where
+        F: FnOnce() -> Result<T, ApiError> + Send + 'static,
+        T: Send + 'static,
+    {
+        self.admit(level)?.run(search).await
+    }
+
+    /// Start `search` in the background only if a worker (and for deep
+    /// levels a deep slot) is free right now; `false` without running it
+    /// otherwise. For speculative work such as pondering, which should use
+    /// spare capacity but never wait for it. It holds its slot until it
+    /// returns, so it must bound its own running time.
+    pub fn try_run_idle<F>(&self, level: u8, search: F) -> bool
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let permits = (|| {
+            let admitted = self.admitted.clone().try_acquire_owned().ok()?;
+            let deep = match level >= self.deep_level {
+                true => Some(self.deep.clone().try_acquire_owned().ok()?),
+                false => None,
+            };
+            let running = self.running.clone().try_acquire_owned().ok()?;
+            Some((admitted, deep, running))
+        })();
+        let Some(permits) = permits else {
+            return false;
+        };
+        tokio::task::spawn_blocking(move || {
+            let _permits = permits;
+            search()
+        });
+        true
+    }
+}
+
+/// A queued search that has not started yet.
+pub struct Admission {
+    admitted: OwnedSemaphorePermit,
+    running: Arc<Semaphore>,
+    /// Set for deep searches, which also need one of the scarcer deep slots.
+    deep: Option<Arc<Semaphore>>,
+}
+
+impl Admission {
+    /// Wait for a free worker and run `search` on it.
+    pub async fn run<T, F>(self, search: F) -> Result<T, ApiError>
+    where
+        F: FnOnce() -> Result<T, ApiError> + Send + 'static,
+        T: Send + 'static,
+    {
+        const OPEN: &str = "search pool semaphores are never closed";
+        let deep = match self.deep {
+            Some(deep) => Some(deep.acquire_owned().await.expect(OPEN)),
+            None => None,
+        };
+        let running = self.running.acquire_owned().await.expect(OPEN);
+        // The permits move into the task so the slot stays taken until the
+        // search really ends, even if the client hangs up first.
+        let admitted = self.admitted;
+        tokio::task::spawn_blocking(move || {
+            let _permits = (admitted, deep, running);
+            search()
+        })
+        .await
+        .map_err(|err| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, err))?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    #[tokio::test]
+    async fn full_queue_is_rejected() {
+        let pool = SearchPool::new(SearchPoolConfig {
+            workers: 1,
+            queue: 0,
+            ..SearchPoolConfig::default()
+        });
+        let (release, wait) = mpsc::channel::<()>();
+        let busy = tokio::spawn({
+            let pool = pool.clone();
+            async move {
+                pool.run(1, move || {
+                    wait.recv().unwrap();
+                    Ok(1)
+                })
+                .await
+            }
+        });
+        while pool.admitted.available_permits() > 0 {
+            tokio::task::yield_now().await;
+        }
+        let err = pool.run(1, || Ok(2)).await.unwrap_err();
+        assert_eq!(err.status, StatusCode::SERVICE_UNAVAILABLE);
+
+        release.send(()).unwrap();
+        assert_eq!(busy.await.unwrap().unwrap(), 1);
+        assert_eq!(pool.run(1, || Ok(3)).await.unwrap(), 3);
+    }
+
+    #[tokio::test]
+    async fn deep_searches_queue_behind_each_other() {
+        let pool = SearchPool::new(SearchPoolConfig {
+            workers: 2,
+            queue: 2,
+            deep_level: 10,
+            deep_workers: 1,
+        });
+        let (release, wait) = mpsc::channel::<()>();
+        let deep = tokio::spawn({
+            let pool = pool.clone();
+            async move {
+                pool.run(12, move || {
+                    wait.recv().unwrap();
+                    Ok(12)
+                })
+                .await
+            }
+        });
+        while pool.deep.available_permits() > 0 {
+            tokio::task::yield_now().await;
+        }
+        // The second deep search waits without holding a worker, so a
+        // shallow search still gets through.
+        let second = tokio::spawn({
+            let pool = pool.clone();
+            async move { pool.run(11, || Ok(11)).await }
+        });
+        assert_eq!(pool.run(4, || Ok(4)).await.unwrap(), 4);
+        assert!(!second.is_finished());
+
+        release.send(()).unwrap();
+        assert_eq!(deep.await.unwrap().unwrap(), 12);
+        assert_eq!(second.await.unwrap().unwrap(), 11);
+    }
+
+    #[tokio::test]
+    async fn idle_work_never_waits_for_a_worker() {
+        let pool = SearchPool::new(SearchPoolConfig {
+            workers: 1,
+            queue: 4,
+            ..SearchPoolConfig::default()
+        });
+        let (release, wait) = mpsc::channel::<()>();
+        assert!(pool.try_run_idle(5, move || wait.recv().unwrap()));
+        assert_eq!(pool.stats().running, 1);
+        assert!(!pool.try_run_idle(5, || unreachable!("the only worker is busy")));
+
+        release.send(()).unwrap();
+        while pool.stats().running > 0 {
+            tokio::task::yield_now().await;
+        }
+        let (done, finished) = mpsc::channel();
+        assert!(pool.try_run_idle(5, move || done.send(()).unwrap()));
+        tokio::task::spawn_blocking(move || finished.recv().unwrap())
+            .await
+            .unwrap();
+    }
+}
@@ -0,0 +1,218 @@
+//! Engine-versus-engine matches for admins comparing configurations.
+//!
+//! A match can run for a long time, so it is limited to admin keys and takes
+//! a single search-pool slot at the deeper side's level for its whole run.
+//! Every engine move gets at most the tier's search time. The games are
+//! returned, not archived: they would crowd the human games out of `/games`.
+//! `POST /selfplay/elo` plays a configuration against the calibrated levels
+//! instead and rates it on their scale.
+use std::time::Duration;
+
+use axum::{extract::State, http::StatusCode, routing::post, Extension, Json, Router};
+use connect4::{
+    engine_info,
+    strength::{estimate_elo, EloEstimate, GauntletConfig},
+    Algorithm, Level, MoveRequest, Player, SearchOptions, SelfPlayConfig,
+};
+use serde::Deserialize;
+use utoipa::ToSchema;
+
+use crate::{
+    auth::Tier,
+    error::{ApiError, ApiJson},
+    sessions::new_id,
+    storage::{unix_now, GameRecord, GameResult},
+    AppState, EngineParams,
+};
+
+/// Games per request; larger matches can be split over several calls.
+const MAX_GAMES: usize = 100;
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/selfplay", post(run_selfplay))
+        .route("/selfplay/elo", post(rate_engine))
+}
+
+/// One side of a self-play match.
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct EngineSpec {
+    /// Search depth, 1 to 15.
+    pub level: u8,
+    pub algorithm: Option<Algorithm>,
+    /// 0 to 1; see `GET /api/move`.
+    pub randomness: Option<f64>,
+    /// Above 0, samples moves by score; see `GET /api/move`.
+    pub temperature: Option<f64>,
+}
+
+impl EngineSpec {
+    fn options(&self, app: &AppState, tier: &Tier) -> Result<SearchOptions, ApiError> {
+        let params = EngineParams {
+            algorithm: self.algorithm,
+            randomness: self.randomness,
+            temperature: self.temperature,
+            ..EngineParams::default()
+        };
+        let req = MoveRequest {
+            position: String::new(),
+            level: self.level,
+            first_player: None,
+        };
+        app.search_options(tier, &req, &params)
+    }
+}
+
+fn check_games(games: usize) -> Result<(), ApiError> {
+    if !(1..=MAX_GAMES).contains(&games) {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            format!("games must be between 1 and {MAX_GAMES}"),
+        ));
+    }
+    Ok(())
+}
+
+/// Time per engine move: what was asked for, at most the tier's search time.
+fn move_time(max_ms: Option<u64>, tier: &Tier) -> Duration {
+    max_ms
+        .map(Duration::from_millis)
+        .map_or(tier.max_search, |wanted| wanted.min(tier.max_search))
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct SelfPlayRequest {
+    /// 1 to 100.
+    pub games: usize,
+    pub red: EngineSpec,
+    pub blue: EngineSpec,
+    /// Random opening moves before the engines take over; defaults to 2.
+    pub opening_plies: Option<usize>,
+    /// Time budget per engine move in milliseconds, clamped to the server's maximum.
+    pub max_ms: Option<u64>,
+    /// Makes the whole match repeatable.
+    pub seed: Option<u64>,
+}
+
+/// Play a match between two engine configurations (admin keys only).
+#[utoipa::path(
+    post,
+    path = "/api/v1/selfplay",
+    tag = "engine",
+    request_body = SelfPlayRequest,
+    responses(
+        (status = 200, body = [GameRecord]),
+        (status = 400, description = "Game count or level out of range", body = ErrorBody),
+        (status = 401, description = "Unknown API key", body = ErrorBody),
+        (status = 403, description = "Not an admin key", body = ErrorBody),
+        (status = 503, description = "Search queue is full", body = ErrorBody),
+    ),
+)]
+pub async fn run_selfplay(
+    State(app): State<AppState>,
+    Extension(tier): Extension<Tier>,
+    ApiJson(req): ApiJson<SelfPlayRequest>,
+) -> Result<Json<Vec<GameRecord>>, ApiError> {
+    tier.require_admin()?;
+    check_games(req.games)?;
+    let config = SelfPlayConfig {
+        games: req.games,
+        opening_plies: req.opening_plies.unwrap_or(2),
+        move_time: Some(move_time(req.max_ms, &tier)),
+        seed: req.seed,
+        ..SelfPlayConfig::new(
+            req.red.options(&app, &tier)?,
+            req.blue.options(&app, &tier)?,
+        )
+    };
+    let level = config.red.level.max(config.blue.level);
+    let (red_level, blue_level) = (config.red.level, config.blue.level);
+    let started_at = unix_now();
+    let games = app
+        .search_pool
+        .run(level, move || Ok(connect4::selfplay(&config)?))
+        .await?;
+    let finished_at = unix_now();
+    let engine = engine_info().to_string();
+    let records = games
+        .into_iter()
+        .map(|game| GameRecord {
+            id: new_id(),
+            history: game.history,
+            result: match game.winner {
+                Some(Player::Red) => GameResult::RedWin,
+                Some(Player::Blue) => GameResult::BlueWin,
+                None => GameResult::Draw,
+            },
+            red_level: Some(red_level),
+            blue_level: Some(blue_level),
+            started_at,
+            finished_at,
+            engine: Some(engine.clone()),
+            hints: None,
+            player: None,
+            clock: None,
+        })
+        .collect();
+    Ok(Json(records))
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct EloRequest {
+    pub engine: EngineSpec,
+    /// Levels to play; every level when omitted.
+    #[serde(default)]
+    pub levels: Vec<Level>,
+    /// Games against each level, 1 to 100.
+    pub games: usize,
+    /// Random opening moves before the engines take over; defaults to 2.
+    pub opening_plies: Option<usize>,
+    /// Time budget per engine move on both sides in milliseconds, clamped to
+    /// the server's maximum.
+    pub max_ms: Option<u64>,
+    /// Makes the whole gauntlet repeatable.
+    pub seed: Option<u64>,
+}
+
+/// Rate an engine configuration in Elo by a gauntlet against the calibrated
+/// levels (admin keys only).
+#[utoipa::path(
+    post,
+    path = "/api/v1/selfplay/elo",
+    tag = "engine",
+    request_body = EloRequest,
+    responses(
+        (status = 200, body = EloEstimate),
+        (status = 400, description = "Game count or level out of range", body = ErrorBody),
+        (status = 401, description = "Unknown API key", body = ErrorBody),
+        (status = 403, description = "Not an admin key", body = ErrorBody),
+        (status = 503, description = "Search queue is full", body = ErrorBody),
+    ),
+)]
+pub async fn rate_engine(
+    State(app): State<AppState>,
+    Extension(tier): Extension<Tier>,
+    ApiJson(req): ApiJson<EloRequest>,
+) -> Result<Json<EloEstimate>, ApiError> {
+    tier.require_admin()?;
+    check_games(req.games)?;
+    let config = GauntletConfig {
+        opening_plies: req.opening_plies.unwrap_or(2),
+        move_time: Some(move_time(req.max_ms, &tier)),
+        seed: req.seed,
+        ..GauntletConfig::new(req.engine.options(&app, &tier)?)
+    };
+    let deepest = req
+        .levels
+        .iter()
+        .max()
+        .map_or(Level::MAX, |level| level.get());
+    let level = config.options.level.max(deepest);
+    let estimate = app
+        .search_pool
+        .run(level, move || {
+            Ok(estimate_elo(&config, &req.levels, req.games)?)
+        })
+        .await?;
+    Ok(Json(estimate))
+}
@@ -0,0 +1,1260 @@
+//! Server-side game sessions.
+//!
+//! The move API stays stateless; sessions are an opt-in layer on top for
+//! clients that prefer to hold an ID instead of a growing history string. Each
+//! session is small (a replayed `GameState` plus its history) and is dropped
+//! after a period of inactivity so abandoned games cannot accumulate. Each
+//! session has its own lock, which is released while the engine searches on
+//! the search pool, so a long engine reply never blocks lookups of its game
+//! or another.
+//!
+//! While the human thinks, the engine ponders: on an idle search worker it
+//! prepares its answers to the human's likeliest replies, so a predicted
+//! move is answered at once.
+//!
+//! The engine resigns a lost game instead of playing it out, and offers a
+//! draw once it has proved one, as the `[resign]` policy says. The offer
+//! stands until the human moves.
+//!
+//! `POST /games/{id}/takeback` rewinds the human's last move and the
+//! engine's reply, up to `max_takebacks` times a game. Prepared answers and
+//! the expected line belong to the abandoned position, so both are dropped.
+//!
+//! `POST /games/{id}/hint` spends one of the game's `max_hints`: the engine
+//! suggests a move at the session's level, with the teaching-mode reasons
+//! for it. The count goes into the archived record.
+//!
+//! An adaptive session is one game of a series: once it is over,
+//! `POST /games/{id}/next` starts the next game as a new session, with the
+//! level and weakening `connect4::adaptive` picks from the results and
+//! blunders so far.
+use std::{
+    cmp::Reverse,
+    collections::{hash_map::RandomState, HashMap},
+    hash::{BuildHasher, Hasher},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, OnceLock,
+    },
+    time::{Duration, Instant},
+};
+
+use axum::{
+    extract::{Path, State, WebSocketUpgrade},
+    http::{HeaderMap, StatusCode},
+    response::Response,
+    routing::{get, post},
+    Extension, Json, Router,
+};
+use connect4::{
+    adaptive::{Adaptive, AdaptiveStatus},
+    best_move_within, engine_info,
+    explain::{self, Reason},
+    format_history,
+    game::{Ending, Game},
+    notation::Outcome,
+    resign::{ResignPolicy, ResignTracker},
+    review::{review_game, DEFAULT_REVIEW_DEPTH},
+    score_columns, search, BoardModel, CancelToken, EvalPreset, GameState, GameStatus, Level,
+    MoveRequest, MoveResponse, Player, SearchHint, SearchInfo, SearchLimits, SearchOptions,
+    TypedMove,
+};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::{
+    archive::{self, ArchivedGame},
+    auth::Tier,
+    clock::{Clock, GameClock, TimeControl},
+    error::{ApiError, ApiJson},
+    live::{self, Broadcast},
+    search_pool::Admission,
+    storage::{unix_now, GameRecord, GameResult},
+    AppState,
+};
+
+const DEFAULT_TTL: Duration = Duration::from_secs(30 * 60);
+/// Depth of the quick search that ranks the human's likely replies.
+const PONDER_RANKING_DEPTH: usize = 4;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PonderConfig {
+    pub enabled: bool,
+    /// Human replies to prepare an answer to, likeliest first.
+    pub replies: usize,
+    /// Search time one wait for the human may use, over all its replies.
+    pub budget_ms: u64,
+}
+
+impl Default for PonderConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            replies: 3,
+            budget_ms: 2_000,
+        }
+    }
+}
+
+/// Server-wide pondering counts, for the admin stats.
+#[derive(Clone, Default)]
+pub struct PonderCounters(Arc<[AtomicU64; 3]>);
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct PonderStats {
+    /// Waits for the human that got an idle worker to ponder on.
+    pub started: u64,
+    /// Answers searched to the full level.
+    pub prepared: u64,
+    /// Human moves answered from a prepared answer.
+    pub hits: u64,
+}
+
+const STARTED: usize = 0;
+const PREPARED: usize = 1;
+const HITS: usize = 2;
+
+impl PonderCounters {
+    fn add(&self, counter: usize) {
+        self.0[counter].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn stats(&self) -> PonderStats {
+        let [started, prepared, hits] = self.0.each_ref().map(|c| c.load(Ordering::Relaxed));
+        PonderStats {
+            started,
+            prepared,
+            hits,
+        }
+    }
+}
+
+/// The engine's answers being prepared while the human thinks.
+#[derive(Clone, Debug, Default)]
+struct Ponder {
+    cancel: CancelToken,
+    /// By human column: the engine's full-depth answer, as its last
+    /// iteration reported it.
+    replies: Arc<Mutex<HashMap<usize, SearchInfo>>>,
+}
+
+/// The hint for the engine's search after the human played `human`: its
+/// move and score on the line it expected, if the human followed it.
+fn hint(expected: &Option<(Vec<usize>, i32)>, human: usize) -> Option<SearchHint> {
+    let (line, score) = expected.as_ref()?;
+    let column = *line.get(2).filter(|_| line.get(1) == Some(&human))?;
+    Some(SearchHint {
+        column,
+        score: *score,
+    })
+}
+
+/// The human's likeliest replies, best first: the one the engine expected,
+/// then the rest by a quick search from the human's side.
+fn likely_replies(state: &GameState, expected: Option<usize>, count: usize) -> Vec<usize> {
+    let mut scores = score_columns(state, PONDER_RANKING_DEPTH);
+    scores.sort_by_key(|&(column, score)| (Some(column) != expected, Reverse(score)));
+    scores
+        .into_iter()
+        .map(|(column, _)| column)
+        .take(count)
+        .collect()
+}
+
+/// Routes that may run a search spend a rate-limit token, like `/move`.
+pub fn routes(app: &AppState) -> Router<AppState> {
+    let limit = app.rate_limit.clone();
+    Router::new()
+        .route(
+            "/games",
+            post(create_game)
+                .route_layer(limit.clone())
+                .get(archive::list_games),
+        )
+        .route("/games/:id", get(get_game))
+        .route(
+            "/games/:id/move",
+            post(play_move).route_layer(limit.clone()),
+        )
+        .route("/games/:id/draw", post(accept_draw))
+        .route("/games/:id/takeback", post(take_back))
+        .route(
+            "/games/:id/hint",
+            post(give_hint).route_layer(limit.clone()),
+        )
+        .route("/games/:id/next", post(next_game).route_layer(limit))
+        .route("/games/:id/watch", get(watch_game))
+        .route("/games/:id/export", get(archive::export_game))
+}
+
+#[derive(Clone, Debug)]
+pub struct Session {
+    pub id: String,
+    pub level: u8,
+    /// How the engine plays at that level.
+    pub style: EvalPreset,
+    pub human: Player,
+    pub game: Game,
+    pub started_at: u64,
+    pub clock: GameClock,
+    time_control: Option<TimeControl>,
+    /// Limits of the caller who started the game, which bound every engine
+    /// search in it.
+    tier: Tier,
+    /// Present when the game accepts spectators.
+    pub broadcast: Option<Broadcast>,
+    /// The series this game belongs to, as it stood when the game began.
+    adaptive: Option<Adaptive>,
+    /// The series's next game, once started.
+    next: Option<String>,
+    /// The line and score of the engine's last search, from the position it
+    /// moved in, to start the next search from.
+    expected: Option<(Vec<usize>, i32)>,
+    ponder: Option<Ponder>,
+    resign: ResignTracker,
+    takebacks: u32,
+    hints: u32,
+    /// Token of the signed-in human, whose statistics the game counts for.
+    player: Option<String>,
+}
+
+impl Session {
+    fn new(
+        id: String,
+        level: u8,
+        first: Player,
+        human: Player,
+        time_control: Option<TimeControl>,
+        tier: Tier,
+        resign: ResignPolicy,
+    ) -> Self {
+        let mut clock = GameClock::new(time_control);
+        clock.start(first);
+        Self {
+            id,
+            level,
+            style: EvalPreset::Balanced,
+            human,
+            game: Game::new(first),
+            started_at: unix_now(),
+            clock,
+            time_control,
+            tier,
+            broadcast: None,
+            adaptive: None,
+            next: None,
+            expected: None,
+            ponder: None,
+            resign: ResignTracker::new(resign),
+            takebacks: 0,
+            hints: 0,
+            player: None,
+        }
+    }
+
+    /// The engine's weakening: only adaptive series use any.
+    fn randomness(&self) -> f64 {
+        self.adaptive.as_ref().map_or(0.0, Adaptive::randomness)
+    }
+
+    /// `None` while the game is still running.
+    fn result(&self) -> Option<GameResult> {
+        GameResult::of_outcome(self.game.outcome())
+    }
+
+    /// Archive entry for a finished game, `None` while it is still running.
+    pub fn record(&self) -> Option<GameRecord> {
+        let engine = |player: Player| (player != self.human).then_some(self.level);
+        Some(GameRecord {
+            id: self.id.clone(),
+            history: self.history(),
+            result: self.result()?,
+            red_level: engine(Player::Red),
+            blue_level: engine(Player::Blue),
+            started_at: self.started_at,
+            finished_at: unix_now(),
+            engine: Some(engine_info().to_string()),
+            hints: Some(self.hints),
+            player: self.player.clone(),
+            clock: Some(self.clock.reading()),
+        })
+    }
+
+    pub fn history(&self) -> String {
+        self.game.history()
+    }
+
+    fn play(&mut self, column: usize) -> Result<(), ApiError> {
+        if self.game.is_over() {
+            return Err(ApiError::new(StatusCode::CONFLICT, "game is already over"));
+        }
+        let played = self.game.play(column)?;
+        if self.game.is_over() {
+            self.clock.stop();
+        } else {
+            self.clock.moved(played.player);
+        }
+        self.publish();
+        Ok(())
+    }
+
+    /// Rewind one full move pair: the engine's reply and the human move
+    /// before it, leaving the human to move again.
+    fn take_back(&mut self, limit: u32) -> Result<(), ApiError> {
+        if self.game.is_over() {
+            return Err(ApiError::new(StatusCode::CONFLICT, "game is already over"));
+        }
+        if self.takebacks >= limit {
+            return Err(ApiError::new(
+                StatusCode::CONFLICT,
+                format!("no takebacks left (the limit is {limit})"),
+            ));
+        }
+        let moves = self.game.moves();
+        let pair = moves.len() >= 2
+            && moves[moves.len() - 1].player != self.human
+            && moves[moves.len() - 2].player == self.human;
+        if !pair {
+            return Err(ApiError::new(
+                StatusCode::CONFLICT,
+                "you have no move to take back",
+            ));
+        }
+        if let Some(ponder) = self.ponder.take() {
+            ponder.cancel.cancel();
+        }
+        self.game.undo();
+        self.game.undo();
+        self.expected = None;
+        self.takebacks += 1;
+        self.publish();
+        Ok(())
+    }
+
+    /// The search for a hint at the session's level, if one of `budget`
+    /// hints is left.
+    fn hint_request(&self, budget: u32) -> Result<MoveRequest, ApiError> {
+        if self.game.is_over() {
+            return Err(ApiError::new(StatusCode::CONFLICT, "game is already over"));
+        }
+        if self.hints >= budget {
+            return Err(ApiError::new(
+                StatusCode::CONFLICT,
+                format!("no hints left (the limit is {budget})"),
+            ));
+        }
+        Ok(MoveRequest {
+            position: self.history(),
+            level: self.level,
+            first_player: None,
+        })
+    }
+
+    /// Spend one of `budget` hints on the engine's move `column`, which it
+    /// scored `score`.
+    fn hint(&mut self, column: usize, score: i32, budget: u32) -> Result<HintView, ApiError> {
+        let reasons = explain::reasons(self.game.state(), column, Some(score))?;
+        self.hints += 1;
+        Ok(HintView {
+            column,
+            score,
+            text: explain::sentence(&reasons),
+            reasons,
+            hints_left: budget - self.hints,
+        })
+    }
+
+    /// Flag the side to move if its time is up; `true` when this call ended
+    /// the game, which then needs archiving.
+    pub fn check_clock(&mut self) -> bool {
+        let flagged = self.clock.check();
+        if let Some(player) = self.clock.flagged().filter(|_| flagged) {
+            self.game.time_out(player);
+            self.publish();
+        }
+        flagged
+    }
+
+    fn publish(&self) {
+        if let Some(broadcast) = &self.broadcast {
+            broadcast.send(
+                self.history(),
+                self.game.state().board_model(),
+                self.clock.reading(),
+            );
+        }
+    }
+
+    /// What the engine needs to answer, if it is its turn and the game is
+    /// still open: an answer prepared while the human thought, or a search
+    /// to run with the session unlocked. Under a time control it searches
+    /// within its share of the clock.
+    fn engine_turn(&mut self, app: &AppState) -> Option<EngineTurn> {
+        if self.game.is_over() || self.game.state().to_move() == self.human {
+            return None;
+        }
+        let human = self.game.moves().last().map(|m| m.column);
+        let pondered = self.ponder.take().and_then(|ponder| {
+            ponder.cancel.cancel();
+            // A full-depth answer: what an untimed search finds, and at least
+            // as deep as anything the clock would allow.
+            human.and_then(|c| ponder.replies.lock().unwrap().remove(&c))
+        });
+        if let Some(info) = pondered {
+            app.ponder.add(HITS);
+            return Some(EngineTurn::Pondered(info));
+        }
+        // The tier's budget bounds every search; under a time control the
+        // clock's share may end it sooner.
+        let mut limits = app.search_limits(&self.tier, None);
+        let manager = app.config.engine.time_manager();
+        if let Some(clock) = self
+            .clock
+            .engine_limits(self.game.state().to_move(), &manager)
+        {
+            limits.deadline = limits.deadline.into_iter().chain(clock.deadline).min();
+            limits.soft_deadline = clock.soft_deadline;
+        }
+        // When the human played the reply the engine expected, the engine's
+        // next move and score on that line are where this search starts.
+        limits.hint = human.and_then(|human| hint(&self.expected, human));
+        let randomness = self.randomness();
+        let options = (randomness > 0.0 || self.style != EvalPreset::Balanced).then(|| {
+            Box::new(SearchOptions {
+                randomness,
+                weights: self.style.weights(),
+                ..SearchOptions::new(self.level)
+            })
+        });
+        Some(EngineTurn::Search {
+            state: self.game.state().clone(),
+            request: MoveRequest {
+                position: self.history(),
+                level: self.level,
+                first_player: Some(self.game.state().first_player()),
+            },
+            options,
+            limits,
+        })
+    }
+
+    /// Play the engine's answer to `engine_turn`, resigning or offering a
+    /// draw if it says to. The column it played, if it did not resign.
+    fn engine_move(
+        &mut self,
+        (mut reply, last): (MoveResponse, SearchInfo),
+    ) -> Result<Option<usize>, ApiError> {
+        self.resign.observe(self.game.state(), &last, &mut reply);
+        // A weakened engine may not play the line it expected.
+        self.expected = (last.pv.first() == Some(&reply.column)).then_some((last.pv, last.score));
+        let engine = self.human.opponent();
+        if reply.should_resign {
+            self.game.resign(engine);
+            self.clock.stop();
+            self.publish();
+            return Ok(None);
+        }
+        self.play(reply.column)?;
+        if reply.offers_draw {
+            self.game.offer_draw(engine);
+        }
+        Ok(Some(reply.column))
+    }
+
+    /// `engine_turn` through to `engine_move` for a session no other request
+    /// can reach yet, so it need not be unlocked for the search.
+    async fn engine_reply(&mut self, app: &AppState) -> Result<Option<usize>, ApiError> {
+        let Some(turn) = self.engine_turn(app) else {
+            return Ok(None);
+        };
+        let answer = turn.answer(app.search_pool.admit(self.level)?).await?;
+        self.engine_move(answer)
+    }
+
+    /// While the human thinks, prepare the engine's answers to their
+    /// likeliest replies on an idle search worker, within the configured
+    /// budget. Nothing happens when every worker is busy.
+    fn ponder(&mut self, app: &AppState) {
+        let config = app.config.ponder;
+        // Prepared answers are the balanced engine's best moves, which a
+        // weakened or styled engine would not always play.
+        if !config.enabled
+            || self.game.is_over()
+            || self.game.state().to_move() != self.human
+            || self.randomness() > 0.0
+            || self.style != EvalPreset::Balanced
+        {
+            return;
+        }
+        let ponder = Ponder::default();
+        let deadline = Instant::now() + Duration::from_millis(config.budget_ms);
+        let limits = SearchLimits {
+            deadline: Some(deadline),
+            cancel: Some(ponder.cancel.clone()),
+            ..app.with_table(SearchLimits::default())
+        };
+        let (state, moves, level) = (
+            self.game.state().clone(),
+            self.game.moves().to_vec(),
+            self.level,
+        );
+        let (expected, cancel, replies, counters) = (
+            self.expected.clone(),
+            ponder.cancel.clone(),
+            ponder.replies.clone(),
+            app.ponder.clone(),
+        );
+        let started = app.search_pool.try_run_idle(level, move || {
+            let predicted = expected.as_ref().and_then(|(line, _)| line.get(1).copied());
+            for column in likely_replies(&state, predicted, config.replies) {
+                if cancel.is_cancelled() || Instant::now() >= deadline {
+                    return;
+                }
+                let mut moves = moves.clone();
+                moves.push(TypedMove {
+                    player: state.to_move(),
+                    column,
+                });
+                let request = MoveRequest {
+                    position: format_history(&moves),
+                    level,
+                    first_player: Some(state.first_player()),
+                };
+                let limits = SearchLimits {
+                    hint: hint(&expected, column),
+                    ..limits.clone()
+                };
+                let mut last = None;
+                // Replies that end the game have nothing to answer.
+                let Ok(reply) =
+                    best_move_within(request, &limits, |info| last = Some(info.clone()))
+                else {
+                    continue;
+                };
+                if let Some(info) = last.filter(|_| reply.depth == Some(usize::from(level))) {
+                    replies.lock().unwrap().insert(column, info);
+                    counters.add(PREPARED);
+                }
+            }
+        });
+        if started {
+            app.ponder.add(STARTED);
+            self.ponder = Some(ponder);
+        }
+    }
+}
+
+/// The engine's next move in a session, as `Session::engine_turn` left it.
+enum EngineTurn {
+    Pondered(SearchInfo),
+    /// A styled or weakened engine searches with `options`; otherwise the
+    /// plain engine answers `request`.
+    Search {
+        state: GameState,
+        request: MoveRequest,
+        options: Option<Box<SearchOptions>>,
+        limits: SearchLimits,
+    },
+}
+
+impl EngineTurn {
+    /// The engine's move and its last completed iteration. Searches run on
+    /// the search pool once `admission` gets a worker.
+    async fn answer(self, admission: Admission) -> Result<(MoveResponse, SearchInfo), ApiError> {
+        let (state, request, options, limits) = match self {
+            Self::Pondered(info) => return Ok((MoveResponse::new(info.column, info.depth), info)),
+            Self::Search {
+                state,
+                request,
+                options,
+                limits,
+            } => (state, request, options, limits),
+        };
+        admission
+            .run(move || {
+                let mut last = None;
+                let on_iteration = |info: &SearchInfo| last = Some(info.clone());
+                let reply = match options {
+                    Some(options) => {
+                        let outcome = search(&state, &options, &limits, on_iteration)?;
+                        MoveResponse::new(outcome.column, outcome.depth)
+                    }
+                    None => best_move_within(request, &limits, on_iteration)?,
+                };
+                let last = last.expect("every returned move completed an iteration");
+                Ok((reply, last))
+            })
+            .await
+    }
+}
+
+pub type SessionStore = TtlMap<Session>;
+
+type Shared<T> = Arc<Mutex<T>>;
+
+struct Entry<T> {
+    value: T,
+    last_active: Instant,
+}
+
+/// Shared, cheaply cloneable map of live sessions (or rooms) that forgets
+/// entries nobody has touched for `ttl`.
+pub struct TtlMap<T> {
+    inner: Shared<HashMap<String, Shared<Entry<T>>>>,
+    ttl: Duration,
+}
+
+impl<T> Clone for TtlMap<T> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            ttl: self.ttl,
+        }
+    }
+}
+
+impl<T: Send + 'static> Default for TtlMap<T> {
+    fn default() -> Self {
+        Self::new(DEFAULT_TTL)
+    }
+}
+
+impl<T: Send + 'static> TtlMap<T> {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            inner: Arc::default(),
+            ttl,
+        }
+    }
+
+    pub fn insert(&self, id: String, value: T) -> String {
+        let entry = Entry {
+            value,
+            last_active: Instant::now(),
+        };
+        self.inner
+            .lock()
+            .unwrap()
+            .insert(id.clone(), Arc::new(Mutex::new(entry)));
+        id
+    }
+
+    pub fn contains(&self, id: &str) -> bool {
+        self.inner.lock().unwrap().contains_key(id)
+    }
+
+    /// Map every live entry with `f`, keeping the `Some` results. Entries busy
+    /// in another request are skipped rather than waited for.
+    pub fn filter_map<R>(&self, f: impl Fn(&str, &T) -> Option<R>) -> Vec<R> {
+        self.update(|id, value| f(id, value))
+    }
+
+    /// `filter_map` with mutable access, for background upkeep: it does not
+    /// refresh the TTL, so it never keeps an abandoned entry alive.
+    pub fn update<R>(&self, mut f: impl FnMut(&str, &mut T) -> Option<R>) -> Vec<R> {
+        let entries = self.inner.lock().unwrap();
+        entries
+            .iter()
+            .filter_map(|(id, entry)| {
+                let mut entry = entry.try_lock().ok()?;
+                if entry.last_active.elapsed() >= self.ttl {
+                    return None;
+                }
+                f(id, &mut entry.value)
+            })
+            .collect()
+    }
+
+    /// Run `f` on a live entry, refreshing its TTL. Expired entries are treated
+    /// as missing even if the sweeper has not removed them yet.
+    pub fn with<R>(
+        &self,
+        id: &str,
+        f: impl FnOnce(&mut T) -> Result<R, ApiError>,
+    ) -> Result<R, ApiError> {
+        let not_found =
+            || ApiError::new(StatusCode::NOT_FOUND, format!("{id} not found or expired"));
+        let entry = self.inner.lock().unwrap().get(id).cloned();
+        let entry = entry.ok_or_else(not_found)?;
+        let mut entry = entry.lock().unwrap();
+        if entry.last_active.elapsed() >= self.ttl {
+            return Err(not_found());
+        }
+        entry.last_active = Instant::now();
+        f(&mut entry.value)
+    }
+
+    /// ID of some live entry matching `pred`. Entries busy in another request
+    /// are skipped rather than waited for.
+    pub fn find(&self, pred: impl Fn(&T) -> bool) -> Option<String> {
+        let entries = self.inner.lock().unwrap();
+        entries.iter().find_map(|(id, entry)| {
+            let entry = entry.try_lock().ok()?;
+            (entry.last_active.elapsed() < self.ttl && pred(&entry.value)).then(|| id.clone())
+        })
+    }
+
+    /// Entries not yet expired; busy ones count as live.
+    pub fn live(&self) -> usize {
+        let entries = self.inner.lock().unwrap();
+        entries
+            .values()
+            .filter(|e| {
+                e.try_lock()
+                    .map_or(true, |e| e.last_active.elapsed() < self.ttl)
+            })
+            .count()
+    }
+
+    pub fn evict_expired(&self) -> usize {
+        let mut entries = self.inner.lock().unwrap();
+        let before = entries.len();
+        // An entry that is locked is in use right now, so it is not idle.
+        entries.retain(|_, e| {
+            e.try_lock()
+                .map_or(true, |e| e.last_active.elapsed() < self.ttl)
+        });
+        before - entries.len()
+    }
+
+    /// Periodically drop idle entries; lookups already ignore them, this only
+    /// reclaims memory.
+    pub fn spawn_sweeper(&self) -> tokio::task::JoinHandle<()> {
+        let store = self.clone();
+        let period = (self.ttl / 4).max(Duration::from_secs(1));
+        tokio::spawn(async move {
+            let mut tick = tokio::time::interval(period);
+            loop {
+                tick.tick().await;
+                store.evict_expired();
+            }
+        })
+    }
+}
+
+/// Unguessable enough for casual play: a per-process random hasher mixed with a
+/// counter, so IDs never repeat within a run. For IDs only; secrets come from
+/// `auth::new_secret`.
+pub(crate) fn new_id() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    static SEED: OnceLock<RandomState> = OnceLock::new();
+    let mut hasher = SEED.get_or_init(RandomState::new).build_hasher();
+    hasher.write_u64(COUNTER.fetch_add(1, Ordering::Relaxed));
+    let high = hasher.finish();
+    hasher.write_u64(high);
+    format!("{high:016x}{:016x}", hasher.finish())
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct CreateGameRequest {
+    pub level: u8,
+    /// When set the engine opens and the human plays the other colour.
+    #[serde(default)]
+    pub engine_first: bool,
+    /// Clock budget for both sides; untimed when omitted.
+    pub time_control: Option<TimeControl>,
+    /// List the game under `/games/live` and let anyone watch it.
+    #[serde(default)]
+    pub spectators: bool,
+    /// Start a series whose next games (`POST /games/{id}/next`) adjust the
+    /// level and weakening to hold the human near an even score; `level` is
+    /// where it starts.
+    #[serde(default)]
+    pub adaptive: bool,
+    /// `aggressive`, `balanced` (default) or `defensive`: the engine's
+    /// style, at the same level.
+    #[serde(default)]
+    pub style: EvalPreset,
+    /// Who opens the game; Red when omitted.
+    pub first_player: Option<Player>,
+    /// The human's colour. Without it the human opens, or with
+    /// `engine_first` answers the engine's opening move.
+    pub human_color: Option<Player>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct PlayRequest {
+    pub column: usize,
+}
+
+/// The engine's suggestion for the human's move.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct HintView {
+    pub column: usize,
+    /// From the human's side; ±1000000 is a forced result.
+    pub score: i32,
+    /// Why the move is good, as `connect4::explain` sees it.
+    pub reasons: Vec<Reason>,
+    pub text: String,
+    pub hints_left: u32,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct GameView {
+    pub id: String,
+    pub level: u8,
+    #[serde(default)]
+    pub style: EvalPreset,
+    pub human: Player,
+    /// Who opened, which an empty `history` does not show.
+    pub first_player: Player,
+    pub history: String,
+    pub board: BoardModel,
+    /// Column the engine answered with during this request, if any.
+    pub engine_move: Option<usize>,
+    pub clock: Clock,
+    /// The engine offers a draw; accept with `POST /games/{id}/draw`, or
+    /// decline by moving.
+    pub draw_offered: bool,
+    /// Set when the game ended off the board: by resignation, agreement or
+    /// on time.
+    pub ending: Option<Ending>,
+    /// Takebacks still allowed in this game.
+    pub takebacks_left: u32,
+    /// Hints still allowed in this game.
+    pub hints_left: u32,
+    /// For adaptive series: the standing before this game, and the engine's
+    /// weakening in it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub adaptive: Option<AdaptiveStatus>,
+}
+
+impl GameView {
+    /// Over on the board or on time.
+    pub fn is_over(&self) -> bool {
+        self.board.status != GameStatus::InProgress
+            || self.clock.flagged.is_some()
+            || self.ending.is_some()
+    }
+}
+
+impl GameView {
+    fn new(app: &AppState, id: &str, session: &Session, engine_move: Option<usize>) -> Self {
+        Self {
+            id: id.to_string(),
+            level: session.level,
+            style: session.style,
+            human: session.human,
+            first_player: session.game.state().first_player(),
+            history: session.history(),
+            board: session.game.state().board_model(),
+            engine_move,
+            clock: session.clock.reading(),
+            draw_offered: session.game.draw_offer().is_some(),
+            ending: session
+                .game
+                .ending()
+                .filter(|&ending| ending != Ending::Board),
+            takebacks_left: app.config.max_takebacks.saturating_sub(session.takebacks),
+            hints_left: app.config.max_hints.saturating_sub(session.hints),
+            adaptive: session.adaptive.as_ref().map(Adaptive::status),
+        }
+    }
+}
+
+/// Start a game against the engine.
+#[utoipa::path(
+    post,
+    path = "/api/v1/games",
+    tag = "sessions",
+    params((
+        "X-User-Token" = Option<String>,
+        Header,
+        description = "Count the game towards this player's statistics",
+    )),
+    request_body = CreateGameRequest,
+    responses(
+        (status = 201, body = GameView),
+        (status = 400, description = "Level out of range", body = ErrorBody),
+        (status = 401, description = "Unknown user token", body = ErrorBody),
+    ),
+)]
+pub(crate) async fn create_game(
+    State(app): State<AppState>,
+    Extension(tier): Extension<Tier>,
+    headers: HeaderMap,
+    ApiJson(req): ApiJson<CreateGameRequest>,
+) -> Result<(StatusCode, Json<GameView>), ApiError> {
+    let player = app.user(&headers)?.map(|user| user.token);
+    if !(1..=15).contains(&req.level) {
+        return Err(connect4::GameError::DepthOutOfRange(req.level).into());
+    }
+    app.check_level(&tier, req.level)?;
+    let first = req.first_player.unwrap_or(Player::Red);
+    let human = match (req.human_color, req.engine_first) {
+        (Some(human), true) if human == first => {
+            return Err(ApiError::new(
+                StatusCode::BAD_REQUEST,
+                "engine_first contradicts human_color",
+            ))
+        }
+        (Some(human), _) => human,
+        (None, true) => first.opponent(),
+        (None, false) => first,
+    };
+    let id = new_id();
+    TimeControl::validate(req.time_control)?;
+    let mut session = Session::new(
+        id.clone(),
+        req.level,
+        first,
+        human,
+        req.time_control,
+        tier,
+        app.config.resign,
+    );
+    session.broadcast = req.spectators.then(Broadcast::default);
+    session.player = player;
+    session.style = req.style;
+    if req.adaptive {
+        session.adaptive = Some(Adaptive::new(Level::new(req.level)?));
+    }
+    let engine_move = session.engine_reply(&app).await?;
+    session.ponder(&app);
+    let view = GameView::new(&app, &id, &session, engine_move);
+    app.sessions.insert(id, session);
+    Ok((StatusCode::CREATED, Json(view)))
+}
+
+/// Either shape `GET /games/{id}` can answer with.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+#[serde(untagged)]
+pub enum GameLookup {
+    Archived(ArchivedGame),
+    Live(GameView),
+}
+
+/// Live games are served from the session while they are being played;
+/// once finished (or expired) the archived record and its review take over.
+#[utoipa::path(
+    get,
+    path = "/api/v1/games/{id}",
+    tag = "sessions",
+    params(("id" = String, Path, description = "Session or archive ID")),
+    responses(
+        (status = 200, body = GameLookup),
+        (status = 404, description = "Unknown or expired ID", body = ErrorBody),
+    ),
+)]
+pub(crate) async fn get_game(
+    State(app): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<Json<GameLookup>, ApiError> {
+    let live = app
+        .sessions
+        .with(&id, |session| {
+            if let Some(record) = session.check_clock().then(|| session.record()).flatten() {
+                app.record_game(&record);
+            }
+            Ok(GameView::new(&app, &id, session, None))
+        })
+        .ok();
+    match live {
+        Some(view) if !view.is_over() => return Ok(Json(GameLookup::Live(view))),
+        _ => {}
+    }
+    if let Some(game) = archive::archived_game(&app, &id).await? {
+        return Ok(Json(GameLookup::Archived(game)));
+    }
+    // Finished but not archived (storage failed): the live view is all we have.
+    live.map(|view| Json(GameLookup::Live(view)))
+        .ok_or_else(|| ApiError::new(StatusCode::NOT_FOUND, format!("{id} not found or expired")))
+}
+
+/// Play the human move; the engine answers in the same response.
+#[utoipa::path(
+    post,
+    path = "/api/v1/games/{id}/move",
+    tag = "sessions",
+    params(("id" = String, Path, description = "Session ID")),
+    request_body = PlayRequest,
+    responses(
+        (status = 200, body = GameView),
+        (status = 422, description = "Column out of bounds", body = ErrorBody),
+        (status = 404, description = "Unknown or expired ID", body = ErrorBody),
+        (
+            status = 409,
+            description = "Column full, not your turn, or the game is over",
+            body = ErrorBody,
+        ),
+    ),
+)]
+pub(crate) async fn play_move(
+    State(app): State<AppState>,
+    Path(id): Path<String>,
+    ApiJson(req): ApiJson<PlayRequest>,
+) -> Result<Json<GameView>, ApiError> {
+    let (history, turn, admission) = app.sessions.with(&id, |session| {
+        if let Some(record) = session.check_clock().then(|| session.record()).flatten() {
+            app.record_game(&record);
+            return Err(ApiError::new(StatusCode::CONFLICT, "your time is up"));
+        }
+        if session.game.state().to_move() != session.human {
+            return Err(ApiError::new(StatusCode::CONFLICT, "it is not your turn"));
+        }
+        // Claimed before the human move is played: a busy pool must not
+        // leave the game waiting for an answer that never comes.
+        let admission = app.search_pool.admit(session.level)?;
+        session.play(req.column)?;
+        Ok((session.history(), session.engine_turn(&app), admission))
+    })?;
+    // The session stays unlocked while the engine searches; the human
+    // cannot move meanwhile, as it is not their turn.
+    let answer = match turn {
+        Some(turn) => Some(turn.answer(admission).await?),
+        None => None,
+    };
+    app.sessions.with(&id, |session| {
+        if session.history() != history {
+            return Err(ApiError::new(
+                StatusCode::CONFLICT,
+                "the game changed during the engine's search",
+            ));
+        }
+        let engine_move = match answer {
+            Some(answer) => session.engine_move(answer)?,
+            None => None,
+        };
+        session.ponder(&app);
+        if let Some(record) = session.record() {
+            app.record_game(&record);
+        }
+        Ok(Json(GameView::new(&app, &id, session, engine_move)))
+    })
+}
+
+/// Accept the engine's draw offer, ending the game.
+#[utoipa::path(
+    post,
+    path = "/api/v1/games/{id}/draw",
+    tag = "sessions",
+    params(("id" = String, Path, description = "Session ID")),
+    responses(
+        (status = 200, body = GameView),
+        (status = 404, description = "Unknown or expired ID", body = ErrorBody),
+        (status = 409, description = "No draw on offer", body = ErrorBody),
+    ),
+)]
+pub(crate) async fn accept_draw(
+    State(app): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<Json<GameView>, ApiError> {
+    app.sessions.with(&id, |session| {
+        if let Some(record) = session.check_clock().then(|| session.record()).flatten() {
+            app.record_game(&record);
+            return Err(ApiError::new(StatusCode::CONFLICT, "your time is up"));
+        }
+        if !session.game.accept_draw(session.human) {
+            return Err(ApiError::new(StatusCode::CONFLICT, "no draw is on offer"));
+        }
+        if let Some(ponder) = session.ponder.take() {
+            ponder.cancel.cancel();
+        }
+        session.clock.stop();
+        session.publish();
+        if let Some(record) = session.record() {
+            app.record_game(&record);
+        }
+        Ok(Json(GameView::new(&app, &id, session, None)))
+    })
+}
+
+/// Ask the engine for the human's best move, spending one of the game's
+/// hints.
+#[utoipa::path(
+    post,
+    path = "/api/v1/games/{id}/hint",
+    tag = "sessions",
+    params(("id" = String, Path, description = "Session ID")),
+    responses(
+        (status = 200, body = HintView),
+        (status = 404, description = "Unknown or expired ID", body = ErrorBody),
+        (status = 409, description = "The game is over or no hints are left", body = ErrorBody),
+    ),
+)]
+pub(crate) async fn give_hint(
+    State(app): State<AppState>,
+    Extension(tier): Extension<Tier>,
+    Path(id): Path<String>,
+) -> Result<Json<HintView>, ApiError> {
+    let budget = app.config.max_hints;
+    let (history, request) = app.sessions.with(&id, |session| {
+        if let Some(record) = session.check_clock().then(|| session.record()).flatten() {
+            app.record_game(&record);
+            return Err(ApiError::new(StatusCode::CONFLICT, "your time is up"));
+        }
+        Ok((session.history(), session.hint_request(budget)?))
+    })?;
+    let limits = app.search_limits(&tier, None);
+    let (column, score) = app
+        .search_pool
+        .run(request.level, move || {
+            let mut last = None;
+            let reply = best_move_within(request, &limits, |info| last = Some(info.clone()))?;
+            let last = last.expect("every returned move completed an iteration");
+            Ok((reply.column, last.score))
+        })
+        .await?;
+    app.sessions.with(&id, |session| {
+        if session.history() != history {
+            return Err(ApiError::new(
+                StatusCode::CONFLICT,
+                "the game changed during the engine's search",
+            ));
+        }
+        Ok(Json(session.hint(column, score, budget)?))
+    })
+}
+
+/// Take back the human's last move and the engine's reply to it.
+#[utoipa::path(
+    post,
+    path = "/api/v1/games/{id}/takeback",
+    tag = "sessions",
+    params(("id" = String, Path, description = "Session ID")),
+    responses(
+        (status = 200, body = GameView),
+        (status = 404, description = "Unknown or expired ID", body = ErrorBody),
+        (
+            status = 409,
+            description = "The game is over, no move to take back, or no takebacks left",
+            body = ErrorBody,
+        ),
+    ),
+)]
+pub(crate) async fn take_back(
+    State(app): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<Json<GameView>, ApiError> {
+    app.sessions.with(&id, |session| {
+        if let Some(record) = session.check_clock().then(|| session.record()).flatten() {
+            app.record_game(&record);
+            return Err(ApiError::new(StatusCode::CONFLICT, "your time is up"));
+        }
+        session.take_back(app.config.max_takebacks)?;
+        session.ponder(&app);
+        Ok(Json(GameView::new(&app, &id, session, None)))
+    })
+}
+
+/// Start the next game of an adaptive series once this one is over. The
+/// finished game is reviewed, the series updated, and the new game, with
+/// colours swapped, gets a session of its own.
+#[utoipa::path(
+    post,
+    path = "/api/v1/games/{id}/next",
+    tag = "sessions",
+    params(("id" = String, Path, description = "Session ID of the finished game")),
+    responses(
+        (status = 201, body = GameView),
+        (status = 404, description = "Unknown or expired ID", body = ErrorBody),
+        (
+            status = 409,
+            description = "Not an adaptive session, or the game is still running",
+            body = ErrorBody,
+        ),
+    ),
+)]
+pub(crate) async fn next_game(
+    State(app): State<AppState>,
+    Extension(tier): Extension<Tier>,
+    Path(id): Path<String>,
+) -> Result<(StatusCode, Json<GameView>), ApiError> {
+    let next = new_id();
+    let (mut adaptive, moves, outcome, first, human, time_control, spectators, player, style) =
+        app.sessions.with(&id, |session| {
+            if let Some(record) = session.check_clock().then(|| session.record()).flatten() {
+                app.record_game(&record);
+            }
+            let Some(adaptive) = session.adaptive.clone() else {
+                return Err(ApiError::new(
+                    StatusCode::CONFLICT,
+                    "not an adaptive session",
+                ));
+            };
+            if !session.game.is_over() {
+                return Err(ApiError::new(
+                    StatusCode::CONFLICT,
+                    "the game is still running",
+                ));
+            }
+            // One series, not a tree of them.
+            if let Some(next) = &session.next {
+                return Err(ApiError::new(
+                    StatusCode::CONFLICT,
+                    format!("the next game is already {next}"),
+                ));
+            }
+            session.next = Some(next.clone());
+            Ok((
+                adaptive,
+                session.game.moves().to_vec(),
+                session.game.outcome(),
+                session.game.state().first_player(),
+                session.human,
+                session.time_control,
+                session.broadcast.is_some(),
+                session.player.clone(),
+                session.style,
+            ))
+        })?;
+    let review = review_game(&moves, DEFAULT_REVIEW_DEPTH)?;
+    let winner = match outcome {
+        Outcome::RedWin => Some(Player::Red),
+        Outcome::BlueWin => Some(Player::Blue),
+        _ => None,
+    };
+    adaptive.record(human, winner, &review);
+    let level = adaptive.level().get().min(tier.max_level);
+    let id = next;
+    let mut session = Session::new(
+        id.clone(),
+        level,
+        first,
+        human.opponent(),
+        time_control,
+        tier,
+        app.config.resign,
+    );
+    session.broadcast = spectators.then(Broadcast::default);
+    session.adaptive = Some(adaptive);
+    session.player = player;
+    session.style = style;
+    let engine_move = session.engine_reply(&app).await?;
+    session.ponder(&app);
+    let view = GameView::new(&app, &id, &session, engine_move);
+    app.sessions.insert(id, session);
+    Ok((StatusCode::CREATED, Json(view)))
+}
+
+/// Watch a game as a spectator: a WebSocket of `SpectatorMessage`s.
+#[utoipa::path(
+    get,
+    path = "/api/v1/games/{id}/watch",
+    tag = "spectators",
+    params(("id" = String, Path, description = "Session ID")),
+    responses(
+        (status = 101, description = "Switching to the WebSocket protocol"),
+        (status = 403, description = "The game does not accept spectators", body = ErrorBody),
+        (status = 404, description = "Unknown or expired ID", body = ErrorBody),
+    ),
+)]
+pub(crate) async fn watch_game(
+    State(app): State<AppState>,
+    Path(id): Path<String>,
+    ws: WebSocketUpgrade,
+) -> Result<Response, ApiError> {
+    let (events, snapshot) = app.sessions.with(&id, |session| {
+        let broadcast = session.broadcast.as_ref().ok_or_else(|| {
+            ApiError::new(StatusCode::FORBIDDEN, "game does not accept spectators")
+        })?;
+        let snapshot = live::SpectatorMessage::State {
+            history: session.history(),
+            board: session.game.state().board_model(),
+            clock: session.clock.reading(),
+        };
+        Ok((broadcast.subscribe(), snapshot))
+    })?;
+    Ok(ws.on_upgrade(move |socket| live::watch(socket, events, snapshot)))
+}
@@ -0,0 +1,426 @@
+//! Searched moves shared between server replicas through Redis (`redis`
+//! feature).
+//!
+//! Sits behind the in-process and position caches: a replica that misses
+//! both asks Redis before searching, and stores every full-depth answer there
+//! so the other replicas can skip the search. Keys are
+//! `<prefix>move:<version>:<canonical key>:<level>` with the column in
+//! canonical orientation, as in [`crate::move_cache`]. The engine version in
+//! the key keeps replicas of different versions apart during a rolling
+//! deploy, as it retires ETags. Expiry times are spread by
+//! `ttl_jitter` so entries written together do not all expire together.
+//!
+//! Redis is an optimization, never a dependency: every call has a short
+//! timeout, and after `breaker_failures` failures in a row the cache stops
+//! asking for `breaker_cooldown_secs`, then lets one request through to see
+//! whether Redis is back. The client speaks just enough RESP for `GET` and
+//! `SET ... PX` over a plain TCP connection.
+use serde::Deserialize;
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RedisConfig {
+    /// `host:port` of the Redis server.
+    pub addr: String,
+    /// Prepended to every key, so deployments can share one Redis.
+    pub prefix: String,
+    pub ttl_secs: u64,
+    /// Each entry's TTL is moved by up to this fraction either way.
+    pub ttl_jitter: f64,
+    /// Connect, read and write timeout of each call.
+    pub timeout_ms: u64,
+    /// Consecutive failures after which Redis is skipped for a while.
+    pub breaker_failures: u32,
+    pub breaker_cooldown_secs: u64,
+}
+
+impl Default for RedisConfig {
+    fn default() -> Self {
+        Self {
+            addr: "127.0.0.1:6379".to_string(),
+            prefix: "connect4:".to_string(),
+            ttl_secs: 24 * 60 * 60,
+            ttl_jitter: 0.1,
+            timeout_ms: 50,
+            breaker_failures: 5,
+            breaker_cooldown_secs: 30,
+        }
+    }
+}
+
+#[cfg(feature = "redis")]
+pub use enabled::SharedCache;
+
+#[cfg(feature = "redis")]
+mod enabled {
+    use std::{
+        io::{self, BufRead, BufReader, Write},
+        net::{TcpStream, ToSocketAddrs},
+        sync::{Arc, Mutex},
+        time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+    };
+
+    use connect4::GameState;
+    use tracing::{info, warn};
+
+    use super::*;
+
+    /// Rightmost column index; mirroring maps column `c` to `LAST_COLUMN - c`.
+    const LAST_COLUMN: usize = connect4::WIDTH - 1;
+
+    impl RedisConfig {
+        fn timeout(&self) -> Duration {
+            Duration::from_millis(self.timeout_ms)
+        }
+    }
+
+    /// `ttl` moved by up to `jitter` of itself either way; `seed` picks where.
+    fn jittered_ttl(ttl: Duration, jitter: f64, seed: u64) -> Duration {
+        // splitmix64 finalizer, so neighbouring seeds land far apart.
+        let mut z = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^= z >> 31;
+        let unit = (z >> 11) as f64 / (1u64 << 53) as f64;
+        ttl.mul_f64(1.0 + jitter * (2.0 * unit - 1.0))
+            .max(Duration::from_millis(1))
+    }
+
+    /// `None` when no Redis is configured; every call is then a no-op.
+    #[derive(Clone, Default)]
+    pub struct SharedCache(Option<Arc<Remote>>);
+
+    struct Remote {
+        config: RedisConfig,
+        /// One connection, reopened after any error.
+        conn: Mutex<Option<BufReader<TcpStream>>>,
+        breaker: Mutex<Breaker>,
+    }
+
+    #[derive(Default)]
+    struct Breaker {
+        failures: u32,
+        /// Set while open; calls are skipped until then.
+        open_until: Option<Instant>,
+    }
+
+    impl SharedCache {
+        pub fn new(config: Option<&RedisConfig>) -> Self {
+            Self(config.map(|config| {
+                Arc::new(Remote {
+                    config: config.clone(),
+                    conn: Mutex::new(None),
+                    breaker: Mutex::default(),
+                })
+            }))
+        }
+
+        pub fn get(&self, state: &GameState, level: u8) -> Option<usize> {
+            let remote = self.0.as_ref()?;
+            let (key, mirrored) = state.canonical_key();
+            let reply = remote.call(&["GET", &remote.key(key, level)])?;
+            let column = reply?.parse::<usize>().ok()?;
+            (column <= LAST_COLUMN).then(|| {
+                if mirrored {
+                    LAST_COLUMN - column
+                } else {
+                    column
+                }
+            })
+        }
+
+        pub fn insert(&self, state: &GameState, level: u8, column: usize) {
+            let Some(remote) = &self.0 else {
+                return;
+            };
+            let (key, mirrored) = state.canonical_key();
+            let column = if mirrored {
+                LAST_COLUMN - column
+            } else {
+                column
+            };
+            let nanos = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |now| now.subsec_nanos());
+            let ttl = jittered_ttl(
+                Duration::from_secs(remote.config.ttl_secs),
+                remote.config.ttl_jitter,
+                key ^ u64::from(nanos),
+            );
+            let key = remote.key(key, level);
+            let (column, ttl) = (column.to_string(), ttl.as_millis().to_string());
+            remote.call(&["SET", &key, &column, "PX", &ttl]);
+        }
+    }
+
+    impl Remote {
+        fn key(&self, key: u64, level: u8) -> String {
+            format!(
+                "{}move:{}:{key:016x}:{level}",
+                self.config.prefix,
+                env!("CARGO_PKG_VERSION")
+            )
+        }
+
+        /// The reply to one command, or `None` when Redis was skipped or
+        /// failed. A nil reply is `Some(None)`.
+        fn call(&self, args: &[&str]) -> Option<Option<String>> {
+            if !self.breaker.lock().unwrap().allows(Instant::now()) {
+                return None;
+            }
+            let mut conn = self.conn.lock().unwrap();
+            let reply = match conn.as_mut() {
+                Some(stream) => command(stream, args),
+                None => self.connect().and_then(|stream| {
+                    let stream = conn.insert(stream);
+                    command(stream, args)
+                }),
+            };
+            let mut breaker = self.breaker.lock().unwrap();
+            match reply {
+                Ok(reply) => {
+                    breaker.succeeded();
+                    Some(reply)
+                }
+                Err(err) => {
+                    *conn = None;
+                    let cooldown = Duration::from_secs(self.config.breaker_cooldown_secs);
+                    if breaker.failed(self.config.breaker_failures, cooldown, Instant::now()) {
+                        warn!(
+                            addr = %self.config.addr,
+                            "redis unavailable, skipping it for {}s: {err}",
+                            cooldown.as_secs()
+                        );
+                    }
+                    None
+                }
+            }
+        }
+
+        fn connect(&self) -> io::Result<BufReader<TcpStream>> {
+            let timeout = self.config.timeout();
+            let mut last = None;
+            for addr in self.config.addr.to_socket_addrs()? {
+                match TcpStream::connect_timeout(&addr, timeout) {
+                    Ok(stream) => {
+                        stream.set_read_timeout(Some(timeout))?;
+                        stream.set_write_timeout(Some(timeout))?;
+                        stream.set_nodelay(true)?;
+                        info!(addr = %self.config.addr, "connected to redis");
+                        return Ok(BufReader::new(stream));
+                    }
+                    Err(err) => last = Some(err),
+                }
+            }
+            Err(last.unwrap_or_else(|| {
+                io::Error::new(io::ErrorKind::NotFound, "address resolves to nothing")
+            }))
+        }
+    }
+
+    impl Breaker {
+        /// Closed, or open with the cooldown over: one call may try.
+        fn allows(&self, now: Instant) -> bool {
+            !matches!(self.open_until, Some(until) if now < until)
+        }
+
+        fn succeeded(&mut self) {
+            *self = Self::default();
+        }
+
+        /// Count a failure; true when it opens the breaker, including a
+        /// failed trial after the cooldown.
+        fn failed(&mut self, threshold: u32, cooldown: Duration, now: Instant) -> bool {
+            self.failures += 1;
+            if self.failures < threshold {
+                return false;
+            }
+            self.open_until = Some(now + cooldown);
+            true
+        }
+    }
+
+    /// Send one command and read its reply: a bulk or simple string, an
+    /// integer as text, or `None` for nil.
+    fn command(stream: &mut BufReader<TcpStream>, args: &[&str]) -> io::Result<Option<String>> {
+        let mut request = format!("*{}\r\n", args.len());
+        for arg in args {
+            request.push_str(&format!("${}\r\n{arg}\r\n", arg.len()));
+        }
+        stream.get_mut().write_all(request.as_bytes())?;
+        let line = read_line(stream)?;
+        let (kind, rest) = line.split_at(1.min(line.len()));
+        match kind {
+            "+" | ":" => Ok(Some(rest.to_string())),
+            "-" => Err(io::Error::other(rest.to_string())),
+            "$" => {
+                let Ok(len) = usize::try_from(rest.parse::<i64>().map_err(invalid)?) else {
+                    return Ok(None);
+                };
+                let mut body = vec![0; len + 2];
+                io::Read::read_exact(stream, &mut body)?;
+                body.truncate(len);
+                String::from_utf8(body).map(Some).map_err(invalid)
+            }
+            _ => Err(invalid(format!("unexpected reply {line:?}"))),
+        }
+    }
+
+    fn read_line(stream: &mut BufReader<TcpStream>) -> io::Result<String> {
+        let mut line = String::new();
+        if stream.read_line(&mut line)? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        Ok(line.trim_end_matches("\r\n").to_string())
+    }
+
+    fn invalid(err: impl std::fmt::Display) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, err.to_string())
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use std::{collections::HashMap, net::TcpListener, sync::mpsc, thread};
+
+        use connect4::parse_history;
+
+        use super::*;
+
+        /// A Redis stand-in for `GET` and `SET`, reporting each `SET`'s TTL.
+        fn fake_redis() -> (String, mpsc::Receiver<u64>) {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let addr = listener.local_addr().unwrap().to_string();
+            let (ttls, received) = mpsc::channel();
+            let store = Arc::new(Mutex::new(HashMap::<String, String>::new()));
+            thread::spawn(move || {
+                for stream in listener.incoming() {
+                    let mut stream = BufReader::new(stream.unwrap());
+                    let (store, ttls) = (store.clone(), ttls.clone());
+                    thread::spawn(move || {
+                        while let Ok(line) = read_line(&mut stream) {
+                            let count: usize = line[1..].parse().unwrap();
+                            let mut args = Vec::new();
+                            for _ in 0..count {
+                                read_line(&mut stream).unwrap();
+                                args.push(read_line(&mut stream).unwrap());
+                            }
+                            let mut store = store.lock().unwrap();
+                            let reply = match args[0].as_str() {
+                                "GET" => match store.get(&args[1]) {
+                                    Some(value) => format!("${}\r\n{value}\r\n", value.len()),
+                                    None => "$-1\r\n".to_string(),
+                                },
+                                _ => {
+                                    store.insert(args[1].clone(), args[2].clone());
+                                    ttls.send(args[4].parse().unwrap()).unwrap();
+                                    "+OK\r\n".to_string()
+                                }
+                            };
+                            stream.get_mut().write_all(reply.as_bytes()).unwrap();
+                        }
+                    });
+                }
+            });
+            (addr, received)
+        }
+
+        fn state(history: &str) -> GameState {
+            GameState::from_history(&parse_history(history).unwrap()).unwrap()
+        }
+
+        #[test]
+        fn replicas_share_moves_through_redis() {
+            let (addr, ttls) = fake_redis();
+            let config = RedisConfig {
+                addr,
+                ..RedisConfig::default()
+            };
+            let (writer, reader) = (
+                SharedCache::new(Some(&config)),
+                SharedCache::new(Some(&config)),
+            );
+            let position = state("R0B1");
+            assert_eq!(reader.get(&position, 6), None);
+            writer.insert(&position, 6, 2);
+            assert_eq!(reader.get(&position, 6), Some(2));
+            assert_eq!(reader.get(&position, 7), None);
+            // The mirror image shares the entry, flipped back.
+            assert_eq!(reader.get(&state("R6B5"), 6), Some(4));
+
+            let day = 24 * 60 * 60 * 1000;
+            let ttl = ttls.recv().unwrap();
+            assert!((day * 9 / 10..=day * 11 / 10).contains(&ttl), "{ttl}");
+            assert!(SharedCache::default().get(&position, 6).is_none());
+        }
+
+        #[test]
+        fn keys_carry_the_engine_version() {
+            let cache = SharedCache::new(Some(&RedisConfig::default()));
+            let key = cache.0.unwrap().key(0xab, 6);
+            let version = env!("CARGO_PKG_VERSION");
+            assert_eq!(key, format!("connect4:move:{version}:00000000000000ab:6"));
+        }
+
+        #[test]
+        fn jitter_stays_within_bounds_and_spreads() {
+            let ttl = Duration::from_secs(1000);
+            let ttls: Vec<Duration> = (0..100).map(|seed| jittered_ttl(ttl, 0.1, seed)).collect();
+            assert!(ttls
+                .iter()
+                .all(|t| (Duration::from_secs(900)..=Duration::from_secs(1100)).contains(t)));
+            assert!(ttls.iter().any(|t| *t < ttl) && ttls.iter().any(|t| *t > ttl));
+            assert_eq!(jittered_ttl(ttl, 0.0, 7), ttl);
+        }
+
+        #[test]
+        fn breaker_opens_after_repeated_failures_and_retries_after_cooldown() {
+            let mut breaker = Breaker::default();
+            let (cooldown, now) = (Duration::from_secs(30), Instant::now());
+            assert!(!breaker.failed(2, cooldown, now));
+            assert!(breaker.allows(now));
+            assert!(breaker.failed(2, cooldown, now));
+            assert!(!breaker.allows(now + Duration::from_secs(29)));
+            assert!(breaker.allows(now + cooldown));
+            // A failed trial opens it again at once.
+            assert!(breaker.failed(2, cooldown, now + cooldown));
+            breaker.succeeded();
+            assert!(breaker.allows(now + cooldown));
+
+            // Nothing listens here, so calls fail, then stop being tried.
+            let closed = TcpListener::bind("127.0.0.1:0").unwrap();
+            let addr = closed.local_addr().unwrap().to_string();
+            drop(closed);
+            let cache = SharedCache::new(Some(&RedisConfig {
+                addr,
+                breaker_failures: 2,
+                ..RedisConfig::default()
+            }));
+            let position = state("R3");
+            assert_eq!(cache.get(&position, 4), None);
+            assert_eq!(cache.get(&position, 4), None);
+            let remote = cache.0.as_ref().unwrap();
+            assert!(!remote.breaker.lock().unwrap().allows(Instant::now()));
+            assert_eq!(cache.get(&position, 4), None);
+            assert_eq!(remote.breaker.lock().unwrap().failures, 2);
+        }
+    }
+}
+
+/// Stand-in when built without the `redis` feature; configuring Redis is
+/// then refused at startup, so this never holds a connection.
+#[cfg(not(feature = "redis"))]
+#[derive(Clone, Default)]
+pub struct SharedCache;
+
+#[cfg(not(feature = "redis"))]
+impl SharedCache {
+    pub fn new(_config: Option<&RedisConfig>) -> Self {
+        Self
+    }
+
+    pub fn get(&self, _state: &connect4::GameState, _level: u8) -> Option<usize> {
+        None
+    }
+
+    pub fn insert(&self, _state: &connect4::GameState, _level: u8, _column: usize) {}
+}
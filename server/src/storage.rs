@@ -0,0 +1,897 @@
+//! Persistence of finished games and searched positions.
+//!
+//! Positions the solver has finished are kept apart from the position cache
+//! as a tablebase: their scores are exact, so they are never flushed, and they
+//! are keyed by canonical position so a mirror image shares its entry.
+//!
+//! Handlers only see the `Storage` trait. The default backend keeps everything
+//! in memory, which is enough for tests and single-run deployments; building
+//! with the `sqlite` feature adds a backend that survives restarts. Storage
+//! failures are logged by callers rather than failing the request: losing a
+//! record is better than losing the game.
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use connect4::{notation::Outcome, GameState, Player};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{auth::ApiKey, clock::Clock, jobs::Job, users::User};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum GameResult {
+    RedWin,
+    BlueWin,
+    Draw,
+}
+
+impl GameResult {
+    pub fn won_by(player: Player) -> Self {
+        match player {
+            Player::Red => Self::RedWin,
+            Player::Blue => Self::BlueWin,
+        }
+    }
+
+    /// `None` while the game is still being played.
+    pub fn of(state: &GameState) -> Option<Self> {
+        Self::of_outcome(Outcome::of(state))
+    }
+
+    /// `None` for `Outcome::Unfinished`.
+    pub fn of_outcome(outcome: Outcome) -> Option<Self> {
+        match outcome {
+            Outcome::RedWin => Some(Self::RedWin),
+            Outcome::BlueWin => Some(Self::BlueWin),
+            Outcome::Draw => Some(Self::Draw),
+            Outcome::Unfinished => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct GameRecord {
+    pub id: String,
+    pub history: String,
+    pub result: GameResult,
+    /// Engine level playing each colour; `None` means a human sat there.
+    pub red_level: Option<u8>,
+    pub blue_level: Option<u8>,
+    /// Unix timestamps in seconds.
+    pub started_at: u64,
+    pub finished_at: u64,
+    /// Engine that played, as `EngineInfo` displays it; `None` for games
+    /// between humans and games archived before versions were recorded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub engine: Option<String>,
+    /// Hints the human took in a session game; `None` for other games.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hints: Option<u32>,
+    /// Token of the signed-in human, for their statistics. Never serialized
+    /// nor archived.
+    #[serde(skip)]
+    pub player: Option<String>,
+    /// Final clock reading, for the audit log; not archived either.
+    #[serde(skip)]
+    pub clock: Option<Clock>,
+}
+
+/// Archive filters; every field is optional and they combine with AND.
+#[derive(Clone, Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct GameQuery {
+    pub result: Option<GameResult>,
+    /// Matches games where either side was the engine at this level.
+    pub level: Option<u8>,
+    /// Finished at or after this Unix timestamp.
+    pub since: Option<u64>,
+    /// Finished strictly before this Unix timestamp.
+    pub until: Option<u64>,
+    #[serde(default)]
+    pub offset: usize,
+    pub limit: Option<usize>,
+}
+
+impl GameQuery {
+    pub const DEFAULT_LIMIT: usize = 20;
+    pub const MAX_LIMIT: usize = 100;
+
+    pub fn limit(&self) -> usize {
+        self.limit
+            .unwrap_or(Self::DEFAULT_LIMIT)
+            .clamp(1, Self::MAX_LIMIT)
+    }
+
+    fn matches(&self, game: &GameRecord) -> bool {
+        // `Option::iter().all` is "unset or matching".
+        self.result.iter().all(|&r| game.result == r)
+            && self
+                .level
+                .iter()
+                .all(|&l| game.red_level == Some(l) || game.blue_level == Some(l))
+            && self.since.iter().all(|&t| game.finished_at >= t)
+            && self.until.iter().all(|&t| game.finished_at < t)
+    }
+}
+
+/// One page of archived games, newest first, plus the total match count so
+/// clients can render pagination.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct GamePage {
+    pub games: Vec<GameRecord>,
+    pub total: usize,
+    pub offset: usize,
+    pub limit: usize,
+}
+
+/// A solver result, column in the canonical orientation of its key.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Solved {
+    pub column: usize,
+    /// Exact, from the side to move.
+    pub score: i32,
+    /// Plies the proof reached.
+    pub depth: usize,
+}
+
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+pub trait Storage: Send + Sync {
+    fn record_game(&self, game: &GameRecord) -> anyhow::Result<()>;
+    fn game(&self, id: &str) -> anyhow::Result<Option<GameRecord>>;
+    fn list_games(&self, query: &GameQuery) -> anyhow::Result<GamePage>;
+    /// Column previously chosen for this position key at this level.
+    fn cached_move(&self, key: u64, level: u8) -> anyhow::Result<Option<usize>>;
+    fn cache_move(&self, key: u64, level: u8, column: usize) -> anyhow::Result<()>;
+    /// Empty the position cache, returning the number of entries removed.
+    fn clear_cached_moves(&self) -> anyhow::Result<usize>;
+    /// The tablebase entry for a canonical position key.
+    fn solved(&self, key: u64) -> anyhow::Result<Option<Solved>>;
+    fn put_solved(&self, key: u64, solved: &Solved) -> anyhow::Result<()>;
+    /// Keys issued outside the config file, looked up by the `hash_key`
+    /// digest of their secret, which is all that is stored.
+    fn api_key(&self, key: &str) -> anyhow::Result<Option<ApiKey>>;
+    fn put_api_key(&self, key: &ApiKey) -> anyhow::Result<()>;
+    /// Inserts or replaces the job with the same ID.
+    fn put_job(&self, job: &Job) -> anyhow::Result<()>;
+    fn job(&self, id: &str) -> anyhow::Result<Option<Job>>;
+    /// Jobs still queued or running, oldest first.
+    fn unfinished_jobs(&self) -> anyhow::Result<Vec<Job>>;
+    /// Accounts, looked up by their secret token.
+    fn user(&self, token: &str) -> anyhow::Result<Option<User>>;
+    /// Inserts or replaces the account with the same token.
+    fn put_user(&self, user: &User) -> anyhow::Result<()>;
+}
+
+/// Positions kept by the in-memory backend before it starts over; keeps a
+/// long-running process from growing without bound.
+const MEMORY_POSITION_LIMIT: usize = 100_000;
+/// Jobs kept by the in-memory backend before finished ones are dropped.
+const MEMORY_JOB_LIMIT: usize = 10_000;
+
+#[derive(Default)]
+pub struct MemoryStorage {
+    games: Mutex<Vec<GameRecord>>,
+    positions: Mutex<HashMap<(u64, u8), usize>>,
+    solved: Mutex<HashMap<u64, Solved>>,
+    api_keys: Mutex<HashMap<String, ApiKey>>,
+    jobs: Mutex<HashMap<String, Job>>,
+    users: Mutex<HashMap<String, User>>,
+}
+
+impl Storage for MemoryStorage {
+    fn record_game(&self, game: &GameRecord) -> anyhow::Result<()> {
+        let mut games = self.games.lock().unwrap();
+        games.retain(|g| g.id != game.id);
+        games.push(game.clone());
+        Ok(())
+    }
+
+    fn game(&self, id: &str) -> anyhow::Result<Option<GameRecord>> {
+        let games = self.games.lock().unwrap();
+        Ok(games.iter().find(|g| g.id == id).cloned())
+    }
+
+    fn list_games(&self, query: &GameQuery) -> anyhow::Result<GamePage> {
+        let games = self.games.lock().unwrap();
+        let mut matching: Vec<&GameRecord> = games.iter().filter(|g| query.matches(g)).collect();
+        matching.sort_by(|a, b| b.finished_at.cmp(&a.finished_at).then(b.id.cmp(&a.id)));
+        let limit = query.limit();
+        Ok(GamePage {
+            total: matching.len(),
+            games: matching
+                .into_iter()
+                .skip(query.offset)
+                .take(limit)
+                .cloned()
+                .collect(),
+            offset: query.offset,
+            limit,
+        })
+    }
+
+    fn cached_move(&self, key: u64, level: u8) -> anyhow::Result<Option<usize>> {
+        Ok(self.positions.lock().unwrap().get(&(key, level)).copied())
+    }
+
+    fn cache_move(&self, key: u64, level: u8, column: usize) -> anyhow::Result<()> {
+        let mut positions = self.positions.lock().unwrap();
+        if positions.len() >= MEMORY_POSITION_LIMIT {
+            positions.clear();
+        }
+        positions.insert((key, level), column);
+        Ok(())
+    }
+
+    fn clear_cached_moves(&self) -> anyhow::Result<usize> {
+        let mut positions = self.positions.lock().unwrap();
+        let len = positions.len();
+        positions.clear();
+        Ok(len)
+    }
+
+    fn solved(&self, key: u64) -> anyhow::Result<Option<Solved>> {
+        Ok(self.solved.lock().unwrap().get(&key).copied())
+    }
+
+    fn put_solved(&self, key: u64, solved: &Solved) -> anyhow::Result<()> {
+        let mut table = self.solved.lock().unwrap();
+        if table.len() >= MEMORY_POSITION_LIMIT {
+            table.clear();
+        }
+        table.insert(key, *solved);
+        Ok(())
+    }
+
+    fn api_key(&self, key: &str) -> anyhow::Result<Option<ApiKey>> {
+        Ok(self.api_keys.lock().unwrap().get(key).cloned())
+    }
+
+    fn put_api_key(&self, key: &ApiKey) -> anyhow::Result<()> {
+        let mut keys = self.api_keys.lock().unwrap();
+        keys.insert(key.key.clone(), key.clone());
+        Ok(())
+    }
+
+    fn put_job(&self, job: &Job) -> anyhow::Result<()> {
+        let mut jobs = self.jobs.lock().unwrap();
+        if jobs.len() >= MEMORY_JOB_LIMIT && !jobs.contains_key(&job.id) {
+            jobs.retain(|_, job| !job.is_finished());
+        }
+        jobs.insert(job.id.clone(), job.clone());
+        Ok(())
+    }
+
+    fn job(&self, id: &str) -> anyhow::Result<Option<Job>> {
+        Ok(self.jobs.lock().unwrap().get(id).cloned())
+    }
+
+    fn unfinished_jobs(&self) -> anyhow::Result<Vec<Job>> {
+        let jobs = self.jobs.lock().unwrap();
+        let mut unfinished: Vec<Job> = jobs
+            .values()
+            .filter(|j| !j.is_finished())
+            .cloned()
+            .collect();
+        unfinished.sort_by_key(|job| job.created_at);
+        Ok(unfinished)
+    }
+
+    fn user(&self, token: &str) -> anyhow::Result<Option<User>> {
+        Ok(self.users.lock().unwrap().get(token).cloned())
+    }
+
+    fn put_user(&self, user: &User) -> anyhow::Result<()> {
+        let mut users = self.users.lock().unwrap();
+        users.insert(user.token.clone(), user.clone());
+        Ok(())
+    }
+}
+
+#[cfg(feature = "sqlite")]
+pub use sqlite::SqliteStorage;
+
+#[cfg(feature = "sqlite")]
+mod sqlite {
+    use std::{path::Path, sync::Mutex};
+
+    use anyhow::Context;
+    use rusqlite::{
+        params, params_from_iter,
+        types::{Type, Value},
+        Connection, OptionalExtension,
+    };
+
+    use super::{GamePage, GameQuery, GameRecord, GameResult, Solved, Storage};
+    use crate::{
+        auth::{hash_key, ApiKey, TierLimits},
+        jobs::Job,
+        rate_limit::RateLimitConfig,
+        users::User,
+    };
+
+    const SCHEMA: &str = "
+        CREATE TABLE IF NOT EXISTS games (
+            id TEXT PRIMARY KEY,
+            history TEXT NOT NULL,
+            result TEXT NOT NULL,
+            red_level INTEGER,
+            blue_level INTEGER,
+            started_at INTEGER NOT NULL,
+            finished_at INTEGER NOT NULL,
+            engine TEXT,
+            hints INTEGER
+        );
+        CREATE INDEX IF NOT EXISTS games_finished_at ON games (finished_at);
+        CREATE TABLE IF NOT EXISTS positions (
+            key INTEGER NOT NULL,
+            level INTEGER NOT NULL,
+            column INTEGER NOT NULL,
+            PRIMARY KEY (key, level)
+        );
+        CREATE TABLE IF NOT EXISTS solved (
+            key INTEGER PRIMARY KEY,
+            column INTEGER NOT NULL,
+            score INTEGER NOT NULL,
+            depth INTEGER NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS api_keys (
+            key TEXT PRIMARY KEY,
+            name TEXT NOT NULL,
+            max_level INTEGER,
+            max_search_ms INTEGER,
+            rate_burst INTEGER,
+            rate_per_second REAL,
+            admin INTEGER NOT NULL DEFAULT 0
+        );
+        -- Jobs are only ever read whole, so they are stored as their JSON.
+        CREATE TABLE IF NOT EXISTS jobs (
+            id TEXT PRIMARY KEY,
+            finished INTEGER NOT NULL,
+            created_at INTEGER NOT NULL,
+            data TEXT NOT NULL
+        );
+        -- Likewise accounts, with their statistics.
+        CREATE TABLE IF NOT EXISTS users (
+            token TEXT PRIMARY KEY,
+            data TEXT NOT NULL
+        );
+    ";
+
+    /// One connection behind a mutex: writes are tiny and infrequent compared
+    /// to searches, so a pool would only add moving parts.
+    pub struct SqliteStorage {
+        conn: Mutex<Connection>,
+    }
+
+    impl SqliteStorage {
+        pub fn open(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+            let path = path.as_ref();
+            let conn = Connection::open(path)
+                .with_context(|| format!("opening database {}", path.display()))?;
+            conn.execute_batch(SCHEMA)?;
+            // Databases created before games recorded their engine or hints.
+            for (column, kind) in [("engine", "TEXT"), ("hints", "INTEGER")] {
+                let present: bool = conn.query_row(
+                    "SELECT COUNT(*) > 0 FROM pragma_table_info('games') WHERE name = ?1",
+                    [column],
+                    |row| row.get(0),
+                )?;
+                if !present {
+                    conn.execute_batch(&format!("ALTER TABLE games ADD COLUMN {column} {kind}"))?;
+                }
+            }
+            // Keys stored before only their digests were.
+            let keys: Vec<String> = conn
+                .prepare("SELECT key FROM api_keys")?
+                .query_map([], |row| row.get(0))?
+                .collect::<Result<_, _>>()?;
+            for key in keys.iter().filter(|key| !is_digest(key)) {
+                conn.execute(
+                    "UPDATE api_keys SET key = ?1 WHERE key = ?2",
+                    params![hash_key(key), key],
+                )?;
+            }
+            Ok(Self {
+                conn: Mutex::new(conn),
+            })
+        }
+    }
+
+    /// Whether a stored key is already a `hash_key` digest.
+    fn is_digest(key: &str) -> bool {
+        key.len() == 64 && key.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+    }
+
+    const RESULTS: [(GameResult, &str); 3] = [
+        (GameResult::RedWin, "red_win"),
+        (GameResult::BlueWin, "blue_win"),
+        (GameResult::Draw, "draw"),
+    ];
+
+    fn result_str(result: GameResult) -> &'static str {
+        RESULTS.iter().find(|(r, _)| *r == result).unwrap().1
+    }
+
+    const COLUMNS: &str =
+        "id, history, result, red_level, blue_level, started_at, finished_at, engine, hints";
+
+    fn row_to_record(row: &rusqlite::Row<'_>) -> rusqlite::Result<GameRecord> {
+        let result: String = row.get(2)?;
+        Ok(GameRecord {
+            id: row.get(0)?,
+            history: row.get(1)?,
+            result: RESULTS
+                .iter()
+                .find(|(_, s)| *s == result)
+                .map(|(r, _)| *r)
+                .ok_or_else(|| {
+                    rusqlite::Error::FromSqlConversionFailure(
+                        2,
+                        Type::Text,
+                        format!("unknown game result {result:?}").into(),
+                    )
+                })?,
+            red_level: row.get(3)?,
+            blue_level: row.get(4)?,
+            started_at: row.get::<_, i64>(5)? as u64,
+            finished_at: row.get::<_, i64>(6)? as u64,
+            engine: row.get(7)?,
+            hints: row.get(8)?,
+            player: None,
+            clock: None,
+        })
+    }
+
+    /// WHERE clause and parameters for `query`; values are always bound, never
+    /// interpolated.
+    fn filter(query: &GameQuery) -> (String, Vec<Value>) {
+        let mut clauses = vec!["1 = 1".to_string()];
+        let mut values = Vec::new();
+        if let Some(result) = query.result {
+            values.push(Value::Text(result_str(result).to_string()));
+            clauses.push(format!("result = ?{}", values.len()));
+        }
+        if let Some(level) = query.level {
+            values.push(Value::Integer(level.into()));
+            clauses.push(format!(
+                "(red_level = ?{0} OR blue_level = ?{0})",
+                values.len()
+            ));
+        }
+        if let Some(since) = query.since {
+            values.push(Value::Integer(since as i64));
+            clauses.push(format!("finished_at >= ?{}", values.len()));
+        }
+        if let Some(until) = query.until {
+            values.push(Value::Integer(until as i64));
+            clauses.push(format!("finished_at < ?{}", values.len()));
+        }
+        (clauses.join(" AND "), values)
+    }
+
+    impl Storage for SqliteStorage {
+        fn record_game(&self, game: &GameRecord) -> anyhow::Result<()> {
+            self.conn.lock().unwrap().execute(
+                "INSERT OR REPLACE INTO games
+                 (id, history, result, red_level, blue_level, started_at, finished_at, engine,
+                  hints)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+                params![
+                    game.id,
+                    game.history,
+                    result_str(game.result),
+                    game.red_level,
+                    game.blue_level,
+                    game.started_at as i64,
+                    game.finished_at as i64,
+                    game.engine,
+                    game.hints,
+                ],
+            )?;
+            Ok(())
+        }
+
+        fn game(&self, id: &str) -> anyhow::Result<Option<GameRecord>> {
+            let conn = self.conn.lock().unwrap();
+            let sql = format!("SELECT {COLUMNS} FROM games WHERE id = ?1");
+            Ok(conn.query_row(&sql, [id], row_to_record).optional()?)
+        }
+
+        fn list_games(&self, query: &GameQuery) -> anyhow::Result<GamePage> {
+            let conn = self.conn.lock().unwrap();
+            let (clause, values) = filter(query);
+            let total: i64 = conn.query_row(
+                &format!("SELECT COUNT(*) FROM games WHERE {clause}"),
+                params_from_iter(values.iter()),
+                |row| row.get(0),
+            )?;
+            let limit = query.limit();
+            let sql = format!(
+                "SELECT {COLUMNS} FROM games WHERE {clause}
+                 ORDER BY finished_at DESC, id DESC LIMIT {limit} OFFSET {}",
+                query.offset
+            );
+            let mut stmt = conn.prepare(&sql)?;
+            let games = stmt
+                .query_map(params_from_iter(values.iter()), row_to_record)?
+                .collect::<Result<_, _>>()?;
+            Ok(GamePage {
+                games,
+                total: total as usize,
+                offset: query.offset,
+                limit,
+            })
+        }
+
+        fn cached_move(&self, key: u64, level: u8) -> anyhow::Result<Option<usize>> {
+            let conn = self.conn.lock().unwrap();
+            let column = conn
+                .query_row(
+                    "SELECT column FROM positions WHERE key = ?1 AND level = ?2",
+                    params![key as i64, level],
+                    |row| row.get::<_, i64>(0),
+                )
+                .optional()?;
+            Ok(column.map(|c| c as usize))
+        }
+
+        fn cache_move(&self, key: u64, level: u8, column: usize) -> anyhow::Result<()> {
+            self.conn.lock().unwrap().execute(
+                "INSERT OR REPLACE INTO positions (key, level, column) VALUES (?1, ?2, ?3)",
+                params![key as i64, level, column as i64],
+            )?;
+            Ok(())
+        }
+
+        fn clear_cached_moves(&self) -> anyhow::Result<usize> {
+            Ok(self
+                .conn
+                .lock()
+                .unwrap()
+                .execute("DELETE FROM positions", [])?)
+        }
+
+        fn solved(&self, key: u64) -> anyhow::Result<Option<Solved>> {
+            let conn = self.conn.lock().unwrap();
+            Ok(conn
+                .query_row(
+                    "SELECT column, score, depth FROM solved WHERE key = ?1",
+                    params![key as i64],
+                    |row| {
+                        Ok(Solved {
+                            column: row.get::<_, i64>(0)? as usize,
+                            score: row.get(1)?,
+                            depth: row.get::<_, i64>(2)? as usize,
+                        })
+                    },
+                )
+                .optional()?)
+        }
+
+        fn put_solved(&self, key: u64, solved: &Solved) -> anyhow::Result<()> {
+            self.conn.lock().unwrap().execute(
+                "INSERT OR REPLACE INTO solved (key, column, score, depth) VALUES (?1, ?2, ?3, ?4)",
+                params![
+                    key as i64,
+                    solved.column as i64,
+                    solved.score,
+                    solved.depth as i64
+                ],
+            )?;
+            Ok(())
+        }
+
+        fn api_key(&self, key: &str) -> anyhow::Result<Option<ApiKey>> {
+            let conn = self.conn.lock().unwrap();
+            let key = conn
+                .query_row(
+                    "SELECT key, name, max_level, max_search_ms, rate_burst, rate_per_second, admin
+                     FROM api_keys WHERE key = ?1",
+                    [key],
+                    |row| {
+                        let burst: Option<u32> = row.get(4)?;
+                        let per_second: Option<f64> = row.get(5)?;
+                        // A bucket needs both numbers; a half-set pair means the defaults.
+                        let rate_limit =
+                            burst
+                                .zip(per_second)
+                                .map(|(burst, per_second)| RateLimitConfig {
+                                    burst,
+                                    per_second,
+                                    ..RateLimitConfig::default()
+                                });
+                        Ok(ApiKey {
+                            key: row.get(0)?,
+                            name: row.get(1)?,
+                            limits: TierLimits {
+                                max_level: row.get(2)?,
+                                max_search_ms: row.get::<_, Option<i64>>(3)?.map(|ms| ms as u64),
+                                rate_limit,
+                            },
+                            admin: row.get(6)?,
+                        })
+                    },
+                )
+                .optional()?;
+            Ok(key)
+        }
+
+        fn put_api_key(&self, key: &ApiKey) -> anyhow::Result<()> {
+            let limits = &key.limits;
+            self.conn.lock().unwrap().execute(
+                "INSERT OR REPLACE INTO api_keys
+                 (key, name, max_level, max_search_ms, rate_burst, rate_per_second, admin)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                params![
+                    key.key,
+                    key.name,
+                    limits.max_level,
+                    limits.max_search_ms.map(|ms| ms as i64),
+                    limits.rate_limit.map(|r| r.burst),
+                    limits.rate_limit.map(|r| r.per_second),
+                    key.admin,
+                ],
+            )?;
+            Ok(())
+        }
+
+        fn put_job(&self, job: &Job) -> anyhow::Result<()> {
+            self.conn.lock().unwrap().execute(
+                "INSERT OR REPLACE INTO jobs (id, finished, created_at, data)
+                 VALUES (?1, ?2, ?3, ?4)",
+                params![
+                    job.id,
+                    job.is_finished(),
+                    job.created_at as i64,
+                    serde_json::to_string(job)?,
+                ],
+            )?;
+            Ok(())
+        }
+
+        fn job(&self, id: &str) -> anyhow::Result<Option<Job>> {
+            let conn = self.conn.lock().unwrap();
+            let data: Option<String> = conn
+                .query_row("SELECT data FROM jobs WHERE id = ?1", [id], |row| {
+                    row.get(0)
+                })
+                .optional()?;
+            Ok(data.map(|data| serde_json::from_str(&data)).transpose()?)
+        }
+
+        fn unfinished_jobs(&self) -> anyhow::Result<Vec<Job>> {
+            let conn = self.conn.lock().unwrap();
+            let mut stmt =
+                conn.prepare("SELECT data FROM jobs WHERE finished = 0 ORDER BY created_at")?;
+            let rows = stmt.query_map([], |row| row.get::<_, String>(0))?;
+            let mut jobs = Vec::new();
+            for data in rows {
+                jobs.push(serde_json::from_str(&data?)?);
+            }
+            Ok(jobs)
+        }
+
+        fn user(&self, token: &str) -> anyhow::Result<Option<User>> {
+            let conn = self.conn.lock().unwrap();
+            let data: Option<String> = conn
+                .query_row("SELECT data FROM users WHERE token = ?1", [token], |row| {
+                    row.get(0)
+                })
+                .optional()?;
+            Ok(data.map(|data| serde_json::from_str(&data)).transpose()?)
+        }
+
+        fn put_user(&self, user: &User) -> anyhow::Result<()> {
+            self.conn.lock().unwrap().execute(
+                "INSERT OR REPLACE INTO users (token, data) VALUES (?1, ?2)",
+                params![user.token, serde_json::to_string(user)?],
+            )?;
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use connect4::parse_history;
+
+    fn record(id: &str) -> GameRecord {
+        GameRecord {
+            id: id.to_string(),
+            history: "R0B1R0B1R0B1R0".to_string(),
+            result: GameResult::RedWin,
+            red_level: None,
+            blue_level: Some(4),
+            started_at: 1,
+            finished_at: 2,
+            engine: Some("connect4 test".to_string()),
+            hints: Some(2),
+            player: None,
+            clock: None,
+        }
+    }
+
+    fn exercise(storage: &dyn Storage) {
+        storage.record_game(&record("a")).unwrap();
+        storage.record_game(&record("b")).unwrap();
+        assert_eq!(storage.game("a").unwrap(), Some(record("a")));
+        assert_eq!(storage.game("missing").unwrap(), None);
+
+        let draw = GameRecord {
+            result: GameResult::Draw,
+            finished_at: 10,
+            ..record("c")
+        };
+        storage.record_game(&draw).unwrap();
+        let all = storage.list_games(&GameQuery::default()).unwrap();
+        assert_eq!(all.total, 3);
+        assert_eq!(all.games[0].id, "c");
+        let page = storage
+            .list_games(&GameQuery {
+                level: Some(4),
+                result: Some(GameResult::RedWin),
+                offset: 1,
+                limit: Some(1),
+                ..GameQuery::default()
+            })
+            .unwrap();
+        assert_eq!(page.total, 2);
+        assert_eq!(page.games.iter().map(|g| &g.id).collect::<Vec<_>>(), ["a"]);
+        let recent = GameQuery {
+            since: Some(5),
+            ..GameQuery::default()
+        };
+        assert_eq!(storage.list_games(&recent).unwrap().total, 1);
+
+        assert_eq!(storage.cached_move(42, 8).unwrap(), None);
+        storage.cache_move(42, 8, 3).unwrap();
+        assert_eq!(storage.cached_move(42, 8).unwrap(), Some(3));
+        assert_eq!(storage.cached_move(42, 9).unwrap(), None);
+        assert_eq!(storage.clear_cached_moves().unwrap(), 1);
+        assert_eq!(storage.cached_move(42, 8).unwrap(), None);
+
+        let solved = Solved {
+            column: 2,
+            score: -1_000_000,
+            depth: 17,
+        };
+        assert_eq!(storage.solved(42).unwrap(), None);
+        storage.put_solved(42, &solved).unwrap();
+        storage.cache_move(42, 8, 3).unwrap();
+        storage.clear_cached_moves().unwrap();
+        assert_eq!(storage.solved(42).unwrap(), Some(solved));
+
+        let key = ApiKey {
+            key: "secret".to_string(),
+            name: "partner".to_string(),
+            limits: crate::auth::TierLimits {
+                max_level: Some(14),
+                max_search_ms: None,
+                rate_limit: Some(crate::rate_limit::RateLimitConfig {
+                    burst: 100,
+                    per_second: 10.0,
+                    trust_forwarded_for: false,
+                }),
+            },
+            admin: true,
+        };
+        assert_eq!(storage.api_key("secret").unwrap(), None);
+        storage.put_api_key(&key).unwrap();
+        assert_eq!(storage.api_key("secret").unwrap(), Some(key));
+        assert_eq!(storage.api_key("partner").unwrap(), None);
+
+        let mut user = User {
+            id: "u1".to_string(),
+            name: "Ada".to_string(),
+            token: "user-secret".to_string(),
+            created_at: 5,
+            stats: Default::default(),
+        };
+        assert_eq!(storage.user("user-secret").unwrap(), None);
+        storage.put_user(&user).unwrap();
+        user.stats.blunders = 3;
+        storage.put_user(&user).unwrap();
+        assert_eq!(storage.user("user-secret").unwrap(), Some(user));
+        assert_eq!(storage.user("u1").unwrap(), None);
+
+        let job = |id: &str, created_at| crate::jobs::Job {
+            id: id.to_string(),
+            status: crate::jobs::JobStatus::Queued,
+            request: serde_json::from_str(r#"{"position":"R3","level":9}"#).unwrap(),
+            limits: None,
+            result: None,
+            error: None,
+            created_at,
+            finished_at: None,
+        };
+        storage.put_job(&job("late", 5)).unwrap();
+        storage.put_job(&job("early", 3)).unwrap();
+        let done = crate::jobs::Job {
+            status: crate::jobs::JobStatus::Done,
+            result: Some(connect4::MoveResponse::new(3, 9)),
+            finished_at: Some(4),
+            ..job("done", 1)
+        };
+        storage.put_job(&done).unwrap();
+        let found = storage.job("done").unwrap().unwrap();
+        assert_eq!(found.result, done.result);
+        assert!(storage.job("missing").unwrap().is_none());
+        let ids: Vec<String> = storage
+            .unfinished_jobs()
+            .unwrap()
+            .into_iter()
+            .map(|job| job.id)
+            .collect();
+        assert_eq!(ids, ["early", "late"]);
+    }
+
+    #[test]
+    fn memory_storage_round_trips() {
+        exercise(&MemoryStorage::default());
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn sqlite_storage_round_trips() {
+        exercise(&SqliteStorage::open(":memory:").unwrap());
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn sqlite_rejects_an_unknown_result() {
+        let path = std::env::temp_dir().join(format!("connect4-db-{}.sqlite", std::process::id()));
+        let storage = SqliteStorage::open(&path).unwrap();
+        storage.record_game(&record("a")).unwrap();
+        rusqlite::Connection::open(&path)
+            .unwrap()
+            .execute("UPDATE games SET result = 'red_wins' WHERE id = 'a'", [])
+            .unwrap();
+        assert!(storage.game("a").is_err());
+        assert!(storage.list_games(&GameQuery::default()).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn sqlite_hashes_keys_stored_in_plaintext() {
+        let path =
+            std::env::temp_dir().join(format!("connect4-keys-{}.sqlite", std::process::id()));
+        let plain = ApiKey {
+            key: "secret".to_string(),
+            name: "old".to_string(),
+            limits: Default::default(),
+            admin: false,
+        };
+        SqliteStorage::open(&path)
+            .unwrap()
+            .put_api_key(&plain)
+            .unwrap();
+        let storage = SqliteStorage::open(&path).unwrap();
+        assert_eq!(storage.api_key("secret").unwrap(), None);
+        let digest = crate::auth::hash_key("secret");
+        let stored = storage.api_key(&digest).unwrap().unwrap();
+        assert_eq!((stored.key, stored.name), (digest, plain.name));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn result_follows_the_board() {
+        let state = |h: &str| GameState::from_history(&parse_history(h).unwrap()).unwrap();
+        assert_eq!(
+            GameResult::of(&state("R0B1R0B1R0B1R0")),
+            Some(GameResult::RedWin)
+        );
+        assert_eq!(GameResult::of(&state("R0B1")), None);
+    }
+}
@@ -0,0 +1,210 @@
+//! Outcome aggregates over session games, for deciding which levels need
+//! retuning.
+//!
+//! Only counts are kept: games, the human's results and game lengths per
+//! level, and how often each (position, move) pair was a blunder in a game the
+//! human went on to lose. Nothing identifies a player or a game. The figures
+//! cover the server's uptime; the archive keeps the games themselves.
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, Mutex},
+};
+
+use connect4::{
+    format_history, parse_history,
+    review::{GameReview, MoveClass},
+    stats::Score,
+    Player,
+};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::storage::{GameRecord, GameResult};
+
+/// Distinct blunders tracked; once full, only those already seen are
+/// counted, so memory stays bounded.
+const MAX_BLUNDERS: usize = 10_000;
+/// Blunders listed by `snapshot`.
+const TOP_BLUNDERS: usize = 10;
+
+#[derive(Clone, Default)]
+pub struct Telemetry(Arc<Mutex<Aggregates>>);
+
+#[derive(Default)]
+struct Aggregates {
+    /// The human's results and total plies, by engine level.
+    levels: BTreeMap<u8, (Score, u64)>,
+    /// By position before the move and the column played.
+    blunders: HashMap<(String, usize), u32>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct LevelOutcomes {
+    pub level: u8,
+    pub games: u32,
+    /// From the human's side.
+    pub human_score: Score,
+    pub human_win_rate: f64,
+    /// Plies per game.
+    pub average_length: f64,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct CommonBlunder {
+    /// History before the blunder.
+    pub position: String,
+    pub column: usize,
+    /// Lost games it was played in.
+    pub count: u32,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct AggregateStats {
+    pub games: u32,
+    /// Levels played, weakest first.
+    pub levels: Vec<LevelOutcomes>,
+    /// The blunders humans lose with most often, most common first.
+    pub losing_blunders: Vec<CommonBlunder>,
+}
+
+impl Telemetry {
+    /// Count a finished game between a human and the engine, with its
+    /// review if there is one. Other games are ignored.
+    pub fn record(&self, game: &GameRecord, review: Option<&GameReview>) {
+        let (human, level) = match (game.red_level, game.blue_level) {
+            (None, Some(level)) => (Player::Red, level),
+            (Some(level), None) => (Player::Blue, level),
+            _ => return,
+        };
+        let Ok(moves) = parse_history(&game.history) else {
+            return;
+        };
+        let mut aggregates = self.0.lock().unwrap();
+        let (score, plies) = aggregates.levels.entry(level).or_default();
+        *plies += moves.len() as u64;
+        let lost = match (game.result, human) {
+            (GameResult::Draw, _) => {
+                score.draws += 1;
+                false
+            }
+            (GameResult::RedWin, Player::Red) | (GameResult::BlueWin, Player::Blue) => {
+                score.wins += 1;
+                false
+            }
+            _ => {
+                score.losses += 1;
+                true
+            }
+        };
+        let Some(review) = review.filter(|_| lost) else {
+            return;
+        };
+        let blunders = review
+            .moves
+            .iter()
+            .filter(|m| m.player == human && m.class == MoveClass::Blunder);
+        for blunder in blunders {
+            let key = (format_history(&moves[..blunder.ply]), blunder.played);
+            let full = aggregates.blunders.len() >= MAX_BLUNDERS;
+            match aggregates.blunders.get_mut(&key) {
+                Some(count) => *count += 1,
+                None if !full => {
+                    aggregates.blunders.insert(key, 1);
+                }
+                None => {}
+            }
+        }
+    }
+
+    pub fn snapshot(&self) -> AggregateStats {
+        let aggregates = self.0.lock().unwrap();
+        let levels: Vec<LevelOutcomes> = aggregates
+            .levels
+            .iter()
+            .map(|(&level, &(score, plies))| {
+                let games = score.games();
+                LevelOutcomes {
+                    level,
+                    games,
+                    human_score: score,
+                    human_win_rate: f64::from(score.wins) / f64::from(games),
+                    average_length: plies as f64 / f64::from(games),
+                }
+            })
+            .collect();
+        let mut blunders: Vec<CommonBlunder> = aggregates
+            .blunders
+            .iter()
+            .map(|((position, column), &count)| CommonBlunder {
+                position: position.clone(),
+                column: *column,
+                count,
+            })
+            .collect();
+        blunders.sort_by(|a, b| {
+            (b.count, &a.position, a.column).cmp(&(a.count, &b.position, b.column))
+        });
+        blunders.truncate(TOP_BLUNDERS);
+        AggregateStats {
+            games: levels.iter().map(|level| level.games).sum(),
+            levels,
+            losing_blunders: blunders,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use connect4::review::{review_game, DEFAULT_REVIEW_DEPTH};
+
+    use super::*;
+
+    fn game(history: &str, result: GameResult, red_level: Option<u8>) -> GameRecord {
+        GameRecord {
+            id: "g".to_string(),
+            history: history.to_string(),
+            result,
+            red_level,
+            blue_level: red_level.xor(Some(4)),
+            started_at: 0,
+            finished_at: 0,
+            engine: None,
+            hints: Some(0),
+            player: None,
+            clock: None,
+        }
+    }
+
+    #[test]
+    fn outcomes_and_losing_blunders_are_counted_by_level() {
+        let telemetry = Telemetry::default();
+        // Blue, the human, lets Red build an open three on the bottom row.
+        let lost = game("R1B1R2B2R3B6R0", GameResult::RedWin, Some(4));
+        let review =
+            review_game(&parse_history(&lost.history).unwrap(), DEFAULT_REVIEW_DEPTH).unwrap();
+        telemetry.record(&lost, Some(&review));
+        telemetry.record(&lost, Some(&review));
+        telemetry.record(&game("R3B3", GameResult::Draw, None), None);
+        // Engine against engine is not a human's game.
+        let selfplay = GameRecord {
+            blue_level: Some(2),
+            ..game("R3", GameResult::Draw, Some(2))
+        };
+        telemetry.record(&selfplay, None);
+
+        let stats = telemetry.snapshot();
+        assert_eq!(stats.games, 3);
+        assert_eq!(stats.levels.len(), 1);
+        let level = &stats.levels[0];
+        assert_eq!((level.level, level.games), (4, 3));
+        assert_eq!(level.human_score.losses, 2);
+        assert_eq!(level.human_score.draws, 1);
+        assert_eq!(level.human_win_rate, 0.0);
+        assert_eq!(level.average_length, 16.0 / 3.0);
+        let top = &stats.losing_blunders[0];
+        assert_eq!(
+            (top.position.as_str(), top.column, top.count),
+            ("R1B1R2", 2, 2)
+        );
+    }
+}
@@ -0,0 +1,97 @@
+//! Native HTTPS so the public instance can run without a reverse proxy.
+//!
+//! The listener itself needs the `tls` feature (rustls via `axum-server`).
+//! The plain-HTTP redirect is ordinary axum and always compiled, so its URL
+//! handling is tested in every build.
+use std::path::PathBuf;
+
+use axum::{
+    extract::{Host, OriginalUri},
+    response::Redirect,
+    Router,
+};
+use serde::Deserialize;
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TlsConfig {
+    /// PEM certificate chain.
+    pub cert: PathBuf,
+    /// PEM private key.
+    pub key: PathBuf,
+    /// When set, plain HTTP on this port redirects to HTTPS.
+    #[serde(default)]
+    pub redirect_http_port: Option<u16>,
+}
+
+/// Answers every request with a permanent redirect to the same path on the
+/// HTTPS port.
+pub fn redirect_router(https_port: u16) -> Router {
+    Router::new().fallback(
+        move |Host(host): Host, OriginalUri(uri): OriginalUri| async move {
+            Redirect::permanent(&https_url(&host, https_port, &uri))
+        },
+    )
+}
+
+fn https_url(host: &str, https_port: u16, uri: &axum::http::Uri) -> String {
+    // Drop any port from the Host header; bracketed IPv6 hosts keep their colons.
+    let name = match host.rsplit_once(':') {
+        Some((name, port)) if !port.contains(']') => name,
+        _ => host,
+    };
+    let path = uri.path_and_query().map_or("/", |p| p.as_str());
+    if https_port == 443 {
+        format!("https://{name}{path}")
+    } else {
+        format!("https://{name}:{https_port}{path}")
+    }
+}
+
+#[cfg(feature = "tls")]
+pub async fn serve(
+    listener: std::net::TcpListener,
+    tls: &TlsConfig,
+    app: axum::extract::connect_info::IntoMakeServiceWithConnectInfo<Router, std::net::SocketAddr>,
+) -> anyhow::Result<()> {
+    use anyhow::Context;
+
+    // Several crates may pull in rustls; pick the provider explicitly.
+    let _ = rustls::crypto::ring::default_provider().install_default();
+    let config = axum_server::tls_rustls::RustlsConfig::from_pem_file(&tls.cert, &tls.key)
+        .await
+        .with_context(|| {
+            format!(
+                "cannot load TLS certificate {} / key {}",
+                tls.cert.display(),
+                tls.key.display()
+            )
+        })?;
+    axum_server::from_tcp_rustls(listener, config)
+        .serve(app)
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redirects_keep_path_and_swap_port() {
+        let uri = "/api/move?position=R3&level=4".parse().unwrap();
+        assert_eq!(
+            https_url("example.com:8080", 8443, &uri),
+            "https://example.com:8443/api/move?position=R3&level=4"
+        );
+        assert_eq!(
+            https_url("example.com", 443, &"/".parse().unwrap()),
+            "https://example.com/"
+        );
+        assert_eq!(https_url("[::1]", 443, &uri), format!("https://[::1]{uri}"));
+        assert_eq!(
+            https_url("[::1]:80", 443, &uri),
+            format!("https://[::1]{uri}")
+        );
+    }
+}
@@ -0,0 +1,358 @@
+//! Player accounts and their statistics.
+//!
+//! An account is no more than a name and a secret token, handed out once by
+//! `POST /users`. Clients present it in the `X-User-Token` header, apart from
+//! API keys: a key says what a client may ask of the engine, a token says
+//! whose games these are. Session games started with a token count towards
+//! that player's statistics once they finish, blunders included, graded by
+//! the same review as the archive. Puzzles are solved on the client, which
+//! reports each attempt to keep the streak.
+use std::collections::BTreeMap;
+
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    routing::{get, post},
+    Json, Router,
+};
+use connect4::{
+    review::{GameReview, MoveClass},
+    stats::Score,
+    Player,
+};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+use utoipa::ToSchema;
+
+use crate::{
+    auth::new_secret,
+    error::{ApiError, ApiJson},
+    sessions::new_id,
+    storage::{unix_now, GameRecord, GameResult},
+    AppState,
+};
+
+pub const USER_TOKEN_HEADER: &str = "x-user-token";
+/// Longest accepted account name, in characters.
+const MAX_NAME_LEN: usize = 40;
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/users", post(create_user))
+        .route("/users/me/stats", get(my_stats))
+        .route("/users/me/puzzles", post(record_puzzle))
+}
+
+/// An account as storage keeps it.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct User {
+    pub id: String,
+    pub name: String,
+    pub token: String,
+    pub created_at: u64,
+    pub stats: UserStats,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct UserStats {
+    /// By the engine level played against.
+    pub by_level: BTreeMap<u8, Score>,
+    /// Over every counted game.
+    pub blunders: u32,
+    pub puzzle_streak: u32,
+    pub best_puzzle_streak: u32,
+    pub puzzles_solved: u32,
+    pub puzzles_failed: u32,
+}
+
+impl UserStats {
+    /// Count a finished game against the engine at `level`.
+    fn record_game(&mut self, level: u8, points: Option<bool>, blunders: u32) {
+        let score = self.by_level.entry(level).or_default();
+        match points {
+            Some(true) => score.wins += 1,
+            Some(false) => score.losses += 1,
+            None => score.draws += 1,
+        }
+        self.blunders += blunders;
+    }
+
+    fn record_puzzle(&mut self, solved: bool) {
+        if solved {
+            self.puzzles_solved += 1;
+            self.puzzle_streak += 1;
+            self.best_puzzle_streak = self.best_puzzle_streak.max(self.puzzle_streak);
+        } else {
+            self.puzzles_failed += 1;
+            self.puzzle_streak = 0;
+        }
+    }
+
+    fn view(&self, user: &User) -> StatsView {
+        let score = self
+            .by_level
+            .values()
+            .fold(Score::default(), |total, s| Score {
+                wins: total.wins + s.wins,
+                draws: total.draws + s.draws,
+                losses: total.losses + s.losses,
+            });
+        let games = score.games();
+        StatsView {
+            id: user.id.clone(),
+            name: user.name.clone(),
+            games,
+            score,
+            win_rate: win_rate(&score),
+            by_level: self
+                .by_level
+                .iter()
+                .map(|(&level, score)| LevelStats {
+                    level,
+                    score: *score,
+                    win_rate: win_rate(score),
+                })
+                .collect(),
+            average_blunders: match games {
+                0 => 0.0,
+                games => f64::from(self.blunders) / f64::from(games),
+            },
+            puzzle_streak: self.puzzle_streak,
+            best_puzzle_streak: self.best_puzzle_streak,
+            puzzles_solved: self.puzzles_solved,
+            puzzles_failed: self.puzzles_failed,
+        }
+    }
+}
+
+/// Share of games won, 0 before any game.
+fn win_rate(score: &Score) -> f64 {
+    match score.games() {
+        0 => 0.0,
+        games => f64::from(score.wins) / f64::from(games),
+    }
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct CreateUserRequest {
+    pub name: String,
+}
+
+/// Returned once, when the account is created: the token is the only
+/// credential and cannot be looked up again.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct UserTicket {
+    pub id: String,
+    pub name: String,
+    pub token: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct LevelStats {
+    pub level: u8,
+    pub score: Score,
+    pub win_rate: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct StatsView {
+    pub id: String,
+    pub name: String,
+    pub games: u32,
+    pub score: Score,
+    pub win_rate: f64,
+    /// Levels played, weakest first.
+    pub by_level: Vec<LevelStats>,
+    /// Moves the review called blunders, per game.
+    pub average_blunders: f64,
+    /// Puzzles solved in a row, up to the latest attempt.
+    pub puzzle_streak: u32,
+    pub best_puzzle_streak: u32,
+    pub puzzles_solved: u32,
+    pub puzzles_failed: u32,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct PuzzleAttempt {
+    pub solved: bool,
+}
+
+/// The presented token, if any.
+pub fn user_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(USER_TOKEN_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(str::trim)
+}
+
+impl AppState {
+    /// The account behind the request's token: `Ok(None)` without one,
+    /// `401` for a token nobody holds.
+    pub(crate) fn user(&self, headers: &HeaderMap) -> Result<Option<User>, ApiError> {
+        let Some(token) = user_token(headers) else {
+            return Ok(None);
+        };
+        let user = self
+            .storage
+            .user(token)
+            .map_err(|err| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, err))?;
+        user.map(Some).ok_or_else(|| ApiError {
+            code: "invalid_user_token",
+            ..ApiError::new(StatusCode::UNAUTHORIZED, "unknown user token")
+        })
+    }
+
+    fn require_user(&self, headers: &HeaderMap) -> Result<User, ApiError> {
+        self.user(headers)?.ok_or_else(|| ApiError {
+            code: "missing_user_token",
+            ..ApiError::new(
+                StatusCode::UNAUTHORIZED,
+                "this endpoint needs an X-User-Token header",
+            )
+        })
+    }
+
+    fn save_user(&self, user: &User) -> Result<(), ApiError> {
+        self.storage
+            .put_user(user)
+            .map_err(|err| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, err))
+    }
+}
+
+/// Count a finished session game towards its player's statistics. Games
+/// without a player, or whose player has gone, are skipped.
+pub(crate) fn record_result(app: &AppState, game: &GameRecord, review: Option<&GameReview>) {
+    let Some(token) = &game.player else { return };
+    let (human, level) = match (game.red_level, game.blue_level) {
+        (None, Some(level)) => (Player::Red, level),
+        (Some(level), None) => (Player::Blue, level),
+        _ => return,
+    };
+    let points = match game.result {
+        GameResult::RedWin => Some(human == Player::Red),
+        GameResult::BlueWin => Some(human == Player::Blue),
+        GameResult::Draw => None,
+    };
+    let blunders = review.map_or(0, |review| review.count(human, MoveClass::Blunder) as u32);
+    let updated = app.storage.user(token).and_then(|user| {
+        let Some(mut user) = user else { return Ok(()) };
+        user.stats.record_game(level, points, blunders);
+        app.storage.put_user(&user)
+    });
+    if let Err(err) = updated {
+        warn!(id = %game.id, "failed to update player statistics: {err:#}");
+    }
+}
+
+/// Open an account. Keep the token: it is shown only here.
+#[utoipa::path(
+    post,
+    path = "/api/v1/users",
+    tag = "users",
+    request_body = CreateUserRequest,
+    responses(
+        (status = 201, body = UserTicket),
+        (status = 400, description = "Empty or overlong name", body = ErrorBody),
+    ),
+)]
+pub(crate) async fn create_user(
+    State(app): State<AppState>,
+    ApiJson(req): ApiJson<CreateUserRequest>,
+) -> Result<(StatusCode, Json<UserTicket>), ApiError> {
+    let name = req.name.trim();
+    if name.is_empty() || name.chars().count() > MAX_NAME_LEN {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            format!("name must be 1 to {MAX_NAME_LEN} characters"),
+        ));
+    }
+    let user = User {
+        id: new_id(),
+        name: name.to_string(),
+        token: new_secret(),
+        created_at: unix_now(),
+        stats: UserStats::default(),
+    };
+    app.save_user(&user)?;
+    let ticket = UserTicket {
+        id: user.id,
+        name: user.name,
+        token: user.token,
+    };
+    Ok((StatusCode::CREATED, Json(ticket)))
+}
+
+/// The presenting player's statistics.
+#[utoipa::path(
+    get,
+    path = "/api/v1/users/me/stats",
+    tag = "users",
+    params(("X-User-Token" = String, Header, description = "Token from `POST /users`")),
+    responses(
+        (status = 200, body = StatsView),
+        (status = 401, description = "Missing or unknown token", body = ErrorBody),
+    ),
+)]
+pub(crate) async fn my_stats(
+    State(app): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<StatsView>, ApiError> {
+    let user = app.require_user(&headers)?;
+    Ok(Json(user.stats.view(&user)))
+}
+
+/// Report a puzzle attempt; a failure ends the streak.
+#[utoipa::path(
+    post,
+    path = "/api/v1/users/me/puzzles",
+    tag = "users",
+    params(("X-User-Token" = String, Header, description = "Token from `POST /users`")),
+    request_body = PuzzleAttempt,
+    responses(
+        (status = 200, body = StatsView),
+        (status = 401, description = "Missing or unknown token", body = ErrorBody),
+    ),
+)]
+pub(crate) async fn record_puzzle(
+    State(app): State<AppState>,
+    headers: HeaderMap,
+    ApiJson(attempt): ApiJson<PuzzleAttempt>,
+) -> Result<Json<StatsView>, ApiError> {
+    let mut user = app.require_user(&headers)?;
+    user.stats.record_puzzle(attempt.solved);
+    app.save_user(&user)?;
+    Ok(Json(user.stats.view(&user)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn streaks_and_rates_follow_the_results() {
+        let mut stats = UserStats::default();
+        for solved in [true, true, true, false, true] {
+            stats.record_puzzle(solved);
+        }
+        assert_eq!((stats.puzzle_streak, stats.best_puzzle_streak), (1, 3));
+        assert_eq!((stats.puzzles_solved, stats.puzzles_failed), (4, 1));
+
+        stats.record_game(4, Some(true), 0);
+        stats.record_game(4, Some(false), 2);
+        stats.record_game(6, None, 1);
+        let user = User {
+            id: "id".to_string(),
+            name: "Ada".to_string(),
+            token: "secret".to_string(),
+            created_at: 0,
+            stats: stats.clone(),
+        };
+        let view = stats.view(&user);
+        assert_eq!(view.games, 3);
+        assert_eq!(view.average_blunders, 1.0);
+        assert_eq!(view.by_level.len(), 2);
+        assert_eq!(view.by_level[0].win_rate, 0.5);
+        assert_eq!(view.by_level[1].score.draws, 1);
+        assert!((view.win_rate - 1.0 / 3.0).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,113 @@
+//! The web UI at `/`.
+//!
+//! `static_dir` is served from disk whenever it exists, which keeps the Vite
+//! build loop fast during development. Built with `embed-web`, the binary also
+//! carries `web/dist` as it was at compile time and falls back to that copy,
+//! so a standalone deployment needs no files next to it.
+use std::path::Path;
+
+use axum::Router;
+use tower_http::services::ServeDir;
+
+/// Router for the UI, or `None` when there is nothing to serve.
+pub fn routes(static_dir: &Path) -> Option<Router> {
+    if static_dir.is_dir() {
+        return Some(Router::new().nest_service(
+            "/",
+            ServeDir::new(static_dir).append_index_html_on_directories(true),
+        ));
+    }
+    embedded::routes()
+}
+
+#[cfg(feature = "embed-web")]
+mod embedded {
+    use axum::{
+        http::{header, HeaderMap, StatusCode, Uri},
+        response::{IntoResponse, Response},
+        Router,
+    };
+    use rust_embed::RustEmbed;
+
+    #[derive(RustEmbed)]
+    #[folder = "../web/dist"]
+    #[allow_missing = true]
+    struct Assets;
+
+    /// Vite fingerprints everything under `assets/`, so those never change.
+    const IMMUTABLE: &str = "public, max-age=31536000, immutable";
+
+    pub fn routes() -> Option<Router> {
+        Assets::iter().next()?;
+        Some(Router::new().fallback(serve))
+    }
+
+    async fn serve(uri: Uri, headers: HeaderMap) -> Response {
+        let mut path = uri.path().trim_start_matches('/').to_string();
+        if path.is_empty() || path.ends_with('/') {
+            path.push_str("index.html");
+        }
+        let Some(file) = Assets::get(&path) else {
+            return StatusCode::NOT_FOUND.into_response();
+        };
+        let hash = file.metadata.sha256_hash();
+        let etag = format!("\"{}\"", hex(&hash[..16]));
+        let cache = if path.starts_with("assets/") {
+            IMMUTABLE
+        } else {
+            "no-cache"
+        };
+        let matched = headers
+            .get(header::IF_NONE_MATCH)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| value.split(',').any(|tag| tag.trim() == etag));
+        let common = [
+            (header::ETAG, etag.clone()),
+            (header::CACHE_CONTROL, cache.to_string()),
+        ];
+        if matched {
+            return (StatusCode::NOT_MODIFIED, common).into_response();
+        }
+        let content_type = [(header::CONTENT_TYPE, file.metadata.mimetype().to_string())];
+        (common, content_type, file.data.into_owned()).into_response()
+    }
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{b:02x}")).collect()
+    }
+}
+
+#[cfg(not(feature = "embed-web"))]
+mod embedded {
+    pub fn routes() -> Option<axum::Router> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, http::Request};
+    use tower::ServiceExt;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn static_dir_on_disk_is_served() {
+        let dir = std::env::temp_dir().join(format!("connect4-web-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("index.html"), "<h1>Connect 4</h1>").unwrap();
+        let app = routes(&dir).unwrap();
+        let response = app
+            .oneshot(Request::get("/").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(&body[..], b"<h1>Connect 4</h1>");
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        // Without `web/dist` at build time there is nothing embedded either.
+        assert!(routes(&dir).is_none() || cfg!(feature = "embed-web"));
+    }
+}
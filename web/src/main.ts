@@ -158,12 +158,13 @@ function dropPiece(player: CellState, col: number): number {
 
 async function aiMove() {
   try {
-    const url = `/api/move?position=${encodeURIComponent(
+    const url = `/api/v1/move?position=${encodeURIComponent(
       state.history
     )}&level=${state.level}`;
     const res = await fetch(url, { method: "GET", cache: "no-store" });
     if (!res.ok) {
-      throw new Error(await res.text());
+      const error = (await res.json()) as { code: string; message: string };
+      throw new Error(error.message);
     }
     const body = (await res.json()) as { column: number };
     const row = dropPiece(2, body.column);